@@ -1,18 +1,63 @@
 use anyhow::{bail, Context, Result};
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use std::fs;
+use std::io::IsTerminal;
 use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
-use crate::cmd::run as run_cmd;
+use crate::chroot;
+use crate::cmd::{run as run_cmd, run_with_env as run_cmd_with_env};
+use crate::commands::rollback;
 use crate::crypt::snapshot::create_pre_apply_snapshot;
-use crate::distro::{self, get_distro};
-use crate::manifest::{self, Manifest, ManifestSource};
+use crate::distro;
+use crate::init::InitSystem;
+use crate::keyboard::{self, XkbLayout};
+use crate::manifest::{self, DotfilesConfig, FileConfig, Manifest, ManifestSource, ServiceConfig};
+use crate::pkgmgr::InstalledPackages;
+use crate::privilege;
+use crate::prompt::prompt_yes_no;
+use crate::state::{StateAction, SystemState};
+
+/// Where apply records the packages it installed, under the root
+const APPLIED_PACKAGES_PATH: &str = "var/lib/mkos/applied-packages.json";
+
+/// Arguments of `mkos apply` and `mkos-apply`
+#[derive(Debug, clap::Args)]
+pub struct ApplyArgs {
+    /// Apply to the system tree at this path, running commands via chroot
+    #[arg(long, value_name = "PATH", default_value = "/")]
+    pub root: PathBuf,
+
+    /// Restore the pre-apply snapshot without asking if any step fails
+    #[arg(long)]
+    pub auto_rollback: bool,
+
+    /// Manifest file or tar bundle, comma-separated URLs, or - for stdin
+    pub manifest: String,
+}
 
-pub fn run(source: ManifestSource) -> Result<()> {
+impl ApplyArgs {
+    pub fn source(&self) -> ManifestSource {
+        ManifestSource::from_arg(Some(&self.manifest))
+    }
+}
+
+/// Apply a manifest to the system rooted at `root`
+///
+/// With a root other than `/`, commands run chrooted into it, so the
+/// target must be a bootstrapped tree (e.g. `/mnt` during install).
+///
+/// When a step fails after the pre-apply snapshot was taken, the snapshot
+/// is restored if `auto_rollback` is set or the user agrees to it.
+pub fn run(source: ManifestSource, root: &Path, auto_rollback: bool) -> Result<()> {
     println!("\n=== mkOS Apply ===\n");
-    println!("Applying manifest to existing system...\n");
+    if is_host_root(root) {
+        println!("Applying manifest to existing system...\n");
+    } else {
+        println!("Applying manifest to {}...\n", root.display());
+    }
 
     // Load manifest
     let bundle = match &source {
@@ -23,8 +68,8 @@ pub fn run(source: ManifestSource) -> Result<()> {
             println!("Loading manifest from: {}\n", path.display());
             manifest::load(&source)?
         }
-        ManifestSource::Url(url) => {
-            println!("Loading manifest from: {}\n", url);
+        ManifestSource::Url(urls) => {
+            println!("Loading manifest from: {}\n", urls.join(", "));
             manifest::load(&source)?
         }
         ManifestSource::Stdin => {
@@ -34,237 +79,513 @@ pub fn run(source: ManifestSource) -> Result<()> {
     };
 
     let manifest = bundle.manifest;
+    for warning in manifest.unknown_field_warnings() {
+        println!("Warning: {}", warning);
+    }
     let files_dir = bundle.files_dir;
 
-    // Create snapshot before making changes
-    match create_pre_apply_snapshot() {
-        Ok(Some(name)) => println!("Created snapshot: {}\n", name),
-        Ok(None) => println!("Skipping snapshot (not btrfs)\n"),
-        Err(e) => println!("Warning: Could not create snapshot: {}\n", e),
-    }
+    // Create snapshot before making changes; snapshots cover the running
+    // system, so a separate target tree gets none
+    let snapshot = if is_host_root(root) {
+        match create_pre_apply_snapshot() {
+            Ok(Some(name)) => {
+                println!("Created snapshot: {}\n", name);
+                Some(name)
+            }
+            Ok(None) => {
+                println!("Skipping snapshot (not btrfs)\n");
+                None
+            }
+            Err(e) => {
+                println!("Warning: Could not create snapshot: {}\n", e);
+                None
+            }
+        }
+    } else {
+        println!("Skipping snapshot (target is not the running system)\n");
+        None
+    };
+
+    apply_manifest(&manifest, files_dir.as_deref(), root).map_err(|error| {
+        recover(
+            error,
+            snapshot.as_deref(),
+            auto_rollback,
+            |name| {
+                std::io::stdin().is_terminal()
+                    && prompt_yes_no(&format!("Restore snapshot {}?", name), false).unwrap_or(false)
+            },
+            |name| rollback::restore(name).map(drop),
+        )
+    })?;
 
+    println!("\n=== Apply Complete ===\n");
+    println!("System has been updated to match the manifest.\n");
+
+    Ok(())
+}
+
+/// Every apply step, in order, stopping at the first that fails
+fn apply_manifest(manifest: &Manifest, files_dir: Option<&Path>, root: &Path) -> Result<()> {
     // Detect distro
-    let distro_kind = distro::detect()?;
-    let distro = get_distro(distro_kind);
+    let distro_kind = distro::detect_in(root)?;
+    let init = manifest.init.as_deref().map(str::parse).transpose()?;
+    let distro = distro_kind.create_with_init(init)?;
+
+    // One query of the package database serves every step that checks it
+    let mut installed = InstalledPackages::query(distro.package_manager(), root)?;
+
+    check_dotfiles_git(manifest, root, &installed)?;
 
     // Apply system configuration
-    apply_system_config(&manifest)?;
+    apply_system_config(manifest, root)?;
 
     // Install packages
-    apply_packages(&manifest, distro.as_ref())?;
+    apply_packages(manifest, distro.as_ref(), root, &mut installed)?;
 
     // Apply services
-    apply_services(&manifest, distro.as_ref())?;
+    apply_services(manifest, distro.as_ref(), root)?;
+
+    // Schedule snapshots from cron
+    if let Some(schedule) = &manifest.snapshots.schedule {
+        println!("Scheduling snapshots...");
+        crate::snapshot_schedule::setup_snapshot_schedule(root, schedule, distro.as_ref())?;
+    }
+
+    // Apply sudo or doas
+    apply_security(manifest, root)?;
 
     // Apply users
-    apply_users(&manifest)?;
+    apply_users(manifest, root)?;
 
     // Apply files
-    apply_files(&manifest, files_dir.as_deref())?;
+    apply_files(manifest, files_dir, root)?;
 
     // Run post-apply scripts
-    run_scripts(&manifest.scripts.post_apply)?;
+    run_scripts(&manifest.scripts.post_apply, root)?;
 
-    println!("\n=== Apply Complete ===\n");
-    println!("System has been updated to match the manifest.\n");
+    SystemState::new(StateAction::Apply, manifest).write(root)
+}
 
-    Ok(())
+/// After a failed apply, restore the pre-apply `snapshot` when
+/// `auto_rollback` is set or `confirm` agrees, or say how to restore it
+fn recover(
+    error: anyhow::Error,
+    snapshot: Option<&str>,
+    auto_rollback: bool,
+    confirm: impl FnOnce(&str) -> bool,
+    restore: impl FnOnce(&str) -> Result<()>,
+) -> anyhow::Error {
+    let Some(snapshot) = snapshot else {
+        return error;
+    };
+    eprintln!("\nApply failed: {:#}\n", error);
+
+    if !auto_rollback && !confirm(snapshot) {
+        return error.context(format!(
+            "Apply failed; to undo its changes, run: mkos rollback {}",
+            snapshot
+        ));
+    }
+
+    println!("Restoring snapshot {}...", snapshot);
+    match restore(snapshot) {
+        Ok(()) => error.context(format!(
+            "Apply failed; restored snapshot {}, reboot to boot into it",
+            snapshot
+        )),
+        Err(e) => error.context(format!(
+            "Apply failed, and so did restoring snapshot {} ({:#}); run: mkos rollback {}",
+            snapshot, e, snapshot
+        )),
+    }
+}
+
+/// Whether `root` is the running system rather than a separate tree
+fn is_host_root(root: &Path) -> bool {
+    root == Path::new("/")
+}
+
+/// `path` from the manifest, relocated under `root`
+fn target_path(root: &Path, path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    root.join(path.strip_prefix("/").unwrap_or(path))
 }
 
-fn apply_system_config(manifest: &Manifest) -> Result<()> {
+/// A command that runs `program` on the target: directly for `/`, through
+/// chroot for any other root
+fn command_in(root: &Path, program: &str) -> Command {
+    if is_host_root(root) {
+        Command::new(program)
+    } else {
+        let mut command = Command::new("chroot");
+        command.arg(root).arg(program);
+        command
+    }
+}
+
+/// [`run_cmd`] on the target; see [`command_in`]
+fn run_in<S: AsRef<str>>(root: &Path, program: &str, args: &[S]) -> Result<()> {
+    let args = args.iter().map(|a| a.as_ref());
+    if is_host_root(root) {
+        run_cmd(program, args)
+    } else {
+        let root = root.to_string_lossy();
+        run_cmd("chroot", [root.as_ref(), program].into_iter().chain(args))
+    }
+}
+
+/// [`run_in`] with extra environment variables, which chroot passes on
+fn run_in_with_env<S: AsRef<str>>(
+    root: &Path,
+    program: &str,
+    args: &[S],
+    env: &[(&str, &str)],
+) -> Result<()> {
+    let args = args.iter().map(|a| a.as_ref());
+    if is_host_root(root) {
+        run_cmd_with_env(program, args, env)
+    } else {
+        let root = root.to_string_lossy();
+        run_cmd_with_env(
+            "chroot",
+            [root.as_ref(), program].into_iter().chain(args),
+            env,
+        )
+    }
+}
+
+/// Fail before changing anything when a user's dotfiles need git and
+/// neither the target nor the manifest's packages provide it
+fn check_dotfiles_git(
+    manifest: &Manifest,
+    root: &Path,
+    installed_packages: &InstalledPackages,
+) -> Result<()> {
+    let mut users: Vec<&str> = manifest
+        .users
+        .iter()
+        .filter(|(_, config)| config.dotfiles.is_some())
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if users.is_empty() {
+        return Ok(());
+    }
+    users.sort_unstable();
+
+    let installed = installed_packages.contains("git")
+        || ["usr/bin/git", "bin/git"]
+            .iter()
+            .any(|path| root.join(path).exists());
+    if installed || manifest.all_packages().contains(&"git") {
+        return Ok(());
+    }
+
+    bail!(
+        "Dotfiles for {} need git; add git to packages",
+        users.join(", ")
+    )
+}
+
+fn apply_system_config(manifest: &Manifest, root: &Path) -> Result<()> {
     println!("Applying system configuration...");
 
+    // Checked up front, so a typo doesn't leave the system half configured
+    crate::locale::check_timezone(root, &manifest.system.timezone)?;
+    for locale in manifest.system.all_locales() {
+        crate::locale::check_locale(root, locale)?;
+    }
+
     // Hostname
-    let current_hostname = fs::read_to_string("/etc/hostname")
+    let hostname_path = target_path(root, "/etc/hostname");
+    let current_hostname = fs::read_to_string(&hostname_path)
         .unwrap_or_default()
         .trim()
         .to_string();
 
     if current_hostname != manifest.system.hostname {
         println!("  Setting hostname: {}", manifest.system.hostname);
-        fs::write("/etc/hostname", format!("{}\n", manifest.system.hostname))
+        fs::write(&hostname_path, format!("{}\n", manifest.system.hostname))
             .context("Failed to write /etc/hostname")?;
 
-        run_cmd("hostname", [&manifest.system.hostname])?;
+        // A target tree picks its hostname up from the file when it boots
+        if is_host_root(root) {
+            run_cmd("hostname", [&manifest.system.hostname])?;
+        }
     }
 
-    // Timezone
+    // Timezone; the link target is resolved inside the root it lives in
     let tz_path = format!("/usr/share/zoneinfo/{}", manifest.system.timezone);
-    if Path::new(&tz_path).exists() {
+    if target_path(root, &tz_path).exists() {
         println!("  Setting timezone: {}", manifest.system.timezone);
-        let _ = fs::remove_file("/etc/localtime");
-        std::os::unix::fs::symlink(&tz_path, "/etc/localtime")
-            .context("Failed to symlink timezone")?;
+        let localtime = target_path(root, "/etc/localtime");
+        let _ = fs::remove_file(&localtime);
+        std::os::unix::fs::symlink(&tz_path, &localtime).context("Failed to symlink timezone")?;
     }
 
-    // Locale
-    let locale_gen = Path::new("/etc/locale.gen");
+    // Locales, generated only when one was missing
+    let locale_gen = target_path(root, "/etc/locale.gen");
     if locale_gen.exists() {
-        let content = fs::read_to_string(locale_gen)?;
-        let locale_line = format!("{} UTF-8", manifest.system.locale);
-        if !content.contains(&locale_line) || content.contains(&format!("#{}", locale_line)) {
+        let content = fs::read_to_string(&locale_gen)?;
+        let locales = manifest.system.all_locales();
+        let new_content = crate::locale::enable_locales(&content, &locales);
+        if new_content != content {
+            println!("  Generating locales: {}", locales.join(", "));
+            fs::write(&locale_gen, new_content)?;
+            let _ = run_in::<&str>(root, "locale-gen", &[]);
+        }
+
+        let locale_conf = target_path(root, "/etc/locale.conf");
+        let lang_line = format!("LANG={}\n", manifest.system.locale);
+        if fs::read_to_string(&locale_conf).ok().as_deref() != Some(lang_line.as_str()) {
             println!("  Setting locale: {}", manifest.system.locale);
-            let new_content = content
-                .lines()
-                .map(|line| {
-                    if line.trim().starts_with('#') && line.contains(&manifest.system.locale) {
-                        line.trim_start_matches('#').trim().to_string()
-                    } else {
-                        line.to_string()
-                    }
-                })
-                .collect::<Vec<_>>()
-                .join("\n");
-
-            fs::write(locale_gen, new_content)?;
-            let _ = run_cmd("locale-gen", &[] as &[&str]);
-        }
-    }
-
-    // Keymap
-    let vconsole = "/etc/vconsole.conf";
-    let keymap_line = format!("KEYMAP={}", manifest.system.keymap);
-    if let Ok(content) = fs::read_to_string(vconsole) {
-        if !content.contains(&keymap_line) {
+            fs::write(&locale_conf, lang_line).context("Failed to write /etc/locale.conf")?;
+        }
+    }
+
+    // Keymap and console font
+    let vconsole = target_path(root, "/etc/vconsole.conf");
+    let content = fs::read_to_string(&vconsole).unwrap_or_default();
+    let new_content = vconsole_with(
+        &content,
+        &manifest.system.keymap,
+        manifest.system.console_font.as_deref(),
+    );
+    if new_content != content {
+        if !content.contains(&format!("KEYMAP={}\n", manifest.system.keymap)) {
             println!("  Setting keymap: {}", manifest.system.keymap);
-            let new_content = content
-                .lines()
-                .filter(|l| !l.starts_with("KEYMAP="))
-                .collect::<Vec<_>>()
-                .join("\n")
-                + "\n"
-                + &keymap_line
-                + "\n";
-            fs::write(vconsole, new_content)?;
         }
-    } else {
-        fs::write(vconsole, format!("{}\n", keymap_line))?;
+        if let Some(font) = &manifest.system.console_font {
+            if !content.contains(&format!("FONT={}\n", font)) {
+                println!("  Setting console font: {}", font);
+            }
+        }
+        fs::write(&vconsole, new_content)?;
+    }
+
+    // The same layout in graphical sessions
+    if manifest.desktop.enabled {
+        let layout = XkbLayout::for_keymap(
+            &manifest.system.keymap,
+            manifest.system.keymap_x11.as_deref(),
+        );
+        if keyboard::write_graphical_layout(root, &layout)? {
+            println!("  Setting graphical keyboard layout: {}", layout.layout);
+        }
     }
 
     Ok(())
 }
 
-fn apply_packages(manifest: &Manifest, distro: &dyn distro::Distro) -> Result<()> {
-    let packages: Vec<&str> = manifest
-        .packages
-        .values()
-        .flat_map(|pkgs| pkgs.iter().map(|s| s.as_str()))
-        .collect();
+/// vconsole.conf with `keymap` and, when given, `font` set, keeping the
+/// other settings
+fn vconsole_with(content: &str, keymap: &str, font: Option<&str>) -> String {
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+    let settings = std::iter::once(("KEYMAP", keymap)).chain(font.map(|font| ("FONT", font)));
+    for (key, value) in settings {
+        let line = format!("{}={}", key, value);
+        let prefix = format!("{}=", key);
+        match lines.iter_mut().find(|l| l.starts_with(&prefix)) {
+            Some(existing) => *existing = line,
+            None => lines.push(line),
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+fn apply_packages(
+    manifest: &Manifest,
+    distro: &dyn distro::Distro,
+    root: &Path,
+    installed: &mut InstalledPackages,
+) -> Result<()> {
+    let packages = manifest.all_packages();
+    let mut applied = AppliedPackages::load(root)?;
+
+    // A managed manifest also drops what earlier applies installed and it no
+    // longer lists
+    let unlisted = if manifest.packages.managed {
+        applied.unlisted(&packages)
+    } else {
+        Vec::new()
+    };
+    let mut removed = manifest.removed_packages();
+    for package in &unlisted {
+        if !removed.contains(&package.as_str()) {
+            removed.push(package);
+        }
+    }
 
-    if packages.is_empty() {
+    if packages.is_empty() && removed.is_empty() {
         return Ok(());
     }
 
-    println!("Installing packages ({} total)...", packages.len());
+    if !packages.is_empty() {
+        println!("Installing packages ({} total)...", packages.len());
+        // The database has distro names, the manifest generic ones
+        let to_install: Vec<&str> = packages
+            .iter()
+            .filter(|p| {
+                !distro
+                    .map_package(p)
+                    .is_some_and(|name| installed.contains(&name))
+            })
+            .copied()
+            .collect();
+
+        if to_install.is_empty() {
+            println!("  All packages already installed");
+        } else {
+            println!("  Installing {} new packages...", to_install.len());
+            distro.install_packages(root, &to_install)?;
+            applied.record(&to_install);
+            installed.add(to_install.iter().filter_map(|p| distro.map_package(p)));
+        }
+    }
 
-    // Get currently installed packages (simplified)
-    let installed = get_installed_packages(distro)?;
-    let to_install: Vec<&str> = packages
-        .iter()
-        .filter(|p| !installed.contains(**p))
-        .copied()
+    // Only what is still there, since package managers fail on the rest.
+    // Removing everything in one transaction lets packages that depend on
+    // each other go together, and the package manager takes dependencies
+    // nothing else needs with them.
+    let to_remove: Vec<&str> = removed
+        .into_iter()
+        .filter(|p| {
+            distro
+                .map_package(p)
+                .is_some_and(|name| installed.contains(&name))
+        })
         .collect();
-
-    if to_install.is_empty() {
-        println!("  All packages already installed");
-        return Ok(());
+    if !to_remove.is_empty() {
+        println!("  Removing {} packages...", to_remove.len());
+        distro.remove_packages(root, &to_remove)?;
+        installed.remove(to_remove.iter().filter_map(|p| distro.map_package(p)));
     }
+    // Unlisted packages that are gone either way stop being tracked
+    applied.forget(&manifest.removed_packages());
+    applied.forget(&unlisted.iter().map(String::as_str).collect::<Vec<_>>());
 
-    println!("  Installing {} new packages...", to_install.len());
-    distro.install_packages(Path::new("/"), &to_install)?;
+    applied.save(root)
+}
 
-    Ok(())
+/// Packages earlier applies installed, so a managed manifest only ever
+/// removes what mkOS put there and never what was installed by hand
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct AppliedPackages {
+    packages: BTreeSet<String>,
 }
 
-fn get_installed_packages(distro: &dyn distro::Distro) -> Result<HashSet<String>> {
-    let mut installed = HashSet::new();
-
-    let output = match distro.pkg_manager() {
-        "pacman" => Command::new("pacman").args(["-Qq"]).output(),
-        "xbps-install" => Command::new("xbps-query").args(["-l"]).output(),
-        "apk" => Command::new("apk").args(["list", "--installed"]).output(),
-        "emerge" => Command::new("qlist").args(["-I"]).output(),
-        "apt" => Command::new("dpkg-query")
-            .args(["-W", "-f=${Package}\n"])
-            .output(),
-        "slackpkg" | "slapt-get" => {
-            // List installed packages from /var/log/packages
-            if let Ok(entries) = fs::read_dir("/var/log/packages") {
-                for entry in entries.flatten() {
-                    if let Some(name) = entry.file_name().to_str() {
-                        // Package format: name-version-arch-build
-                        // Extract just the package name
-                        if let Some(pkg) = name.split('-').next() {
-                            installed.insert(pkg.to_string());
-                        }
-                    }
-                }
-            }
-            return Ok(installed);
+impl AppliedPackages {
+    fn load(root: &Path) -> Result<Self> {
+        let path = target_path(root, APPLIED_PACKAGES_PATH);
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
         }
-        _ => return Ok(installed),
-    };
-
-    if let Ok(output) = output {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        for line in stdout.lines() {
-            // Different output formats per package manager:
-            // pacman: "package"
-            // xbps-query: "ii package-1.0_1  description"
-            // apk: "package-version arch {description}"
-            // emerge/qlist: "category/package-version"
-            // dpkg-query: "package"
-            let pkg = line.split_whitespace().next().unwrap_or("");
-
-            // For apk, extract package name before version
-            let pkg = if distro.pkg_manager() == "apk" {
-                pkg.split('-').next().unwrap_or(pkg)
-            } else if distro.pkg_manager() == "emerge" {
-                // For Gentoo, extract package name from category/package-version
-                if let Some(slash_pos) = pkg.find('/') {
-                    let after_slash = &pkg[slash_pos + 1..];
-                    after_slash.split('-').next().unwrap_or(after_slash)
-                } else {
-                    pkg
-                }
-            } else {
-                pkg
-            };
+    }
 
-            if !pkg.is_empty() {
-                installed.insert(pkg.to_string());
-            }
+    fn save(&self, root: &Path) -> Result<()> {
+        let path = target_path(root, APPLIED_PACKAGES_PATH);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
         }
+        fs::write(&path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Recorded packages `listed` no longer has
+    fn unlisted(&self, listed: &[&str]) -> Vec<String> {
+        self.packages
+            .iter()
+            .filter(|p| !listed.contains(&p.as_str()))
+            .cloned()
+            .collect()
     }
 
-    Ok(installed)
+    fn record(&mut self, installed: &[&str]) {
+        self.packages
+            .extend(installed.iter().map(|p| p.to_string()));
+    }
+
+    fn forget(&mut self, removed: &[&str]) {
+        self.packages.retain(|p| !removed.contains(&p.as_str()));
+    }
 }
 
-fn apply_services(manifest: &Manifest, distro: &dyn distro::Distro) -> Result<()> {
-    if manifest.services.enable.is_empty() && manifest.services.disable.is_empty() {
+fn apply_services(manifest: &Manifest, distro: &dyn distro::Distro, root: &Path) -> Result<()> {
+    let services = ServiceConfig {
+        enable: manifest
+            .enabled_services()
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        disable: manifest.services.disable.clone(),
+    };
+    if services.enable.is_empty() && services.disable.is_empty() {
         return Ok(());
     }
 
     println!("Configuring services...");
 
-    let init = distro.init_system();
+    reconcile_services(
+        root,
+        &services,
+        |service| distro.map_service(service),
+        distro.init_system(),
+    )
+}
 
-    for service in &manifest.services.enable {
-        let mapped = distro.map_service(service);
+/// Enable and disable services through the init system, mapping generic
+/// service names to distro-specific ones first
+fn reconcile_services(
+    root: &Path,
+    services: &ServiceConfig,
+    map_service: impl Fn(&str) -> String,
+    init: &dyn InitSystem,
+) -> Result<()> {
+    for service in &services.enable {
+        let mapped = map_service(service);
+        if mapped.is_empty() {
+            println!("  Skipping: {} (not available on this distro)", service);
+            continue;
+        }
         println!("  Enabling: {}", mapped);
-        init.enable_service(Path::new("/"), &mapped)?;
+        init.enable_service(root, &mapped)?;
     }
 
-    for service in &manifest.services.disable {
-        let mapped = distro.map_service(service);
+    for service in &services.disable {
+        let mapped = map_service(service);
+        if mapped.is_empty() {
+            continue;
+        }
         println!("  Disabling: {}", mapped);
-        init.disable_service(Path::new("/"), &mapped)?;
+        init.disable_service(root, &mapped)?;
     }
 
     Ok(())
 }
 
-fn apply_users(manifest: &Manifest) -> Result<()> {
+fn apply_security(manifest: &Manifest, root: &Path) -> Result<()> {
+    let security = &manifest.security;
+    if security.privilege_escalation.is_none() && !security.wheel_nopasswd {
+        return Ok(());
+    }
+
+    println!("Configuring {}...", security.tool());
+    let installed = ["usr/bin", "bin"]
+        .iter()
+        .any(|dir| root.join(dir).join(security.tool()).exists());
+    if !installed {
+        bail!(
+            "security.privilege_escalation: {} is not installed; add it to packages",
+            security.tool()
+        );
+    }
+    privilege::write_privilege_config(root, security)
+}
+
+fn apply_users(manifest: &Manifest, root: &Path) -> Result<()> {
     if manifest.users.is_empty() {
         return Ok(());
     }
@@ -275,7 +596,7 @@ fn apply_users(manifest: &Manifest) -> Result<()> {
         println!("  User: {}", username);
 
         // Check if user exists
-        let user_exists = Command::new("id")
+        let user_exists = command_in(root, "id")
             .arg(username)
             .output()
             .map(|o| o.status.success())
@@ -301,10 +622,7 @@ fn apply_users(manifest: &Manifest) -> Result<()> {
             args.push(username.clone());
 
             println!("    Creating user...");
-            run_cmd(
-                "useradd",
-                args.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
-            )?;
+            run_in(root, "useradd", &args)?;
         } else {
             // Modify existing user
             let mut args = vec![];
@@ -320,41 +638,149 @@ fn apply_users(manifest: &Manifest) -> Result<()> {
             args.push(username.clone());
 
             println!("    Updating user...");
-            run_cmd(
-                "usermod",
-                args.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
-            )?;
+            run_in(root, "usermod", &args)?;
         }
 
+        let home = config
+            .home
+            .clone()
+            .unwrap_or_else(|| format!("/home/{}", username));
+
         // Add SSH keys
         if !config.ssh_keys.is_empty() {
-            let home = config
-                .home
-                .clone()
-                .unwrap_or_else(|| format!("/home/{}", username));
             let ssh_dir = format!("{}/.ssh", home);
-            let auth_keys = format!("{}/authorized_keys", ssh_dir);
+            let host_ssh_dir = target_path(root, &ssh_dir);
+            let auth_keys = host_ssh_dir.join("authorized_keys");
 
-            fs::create_dir_all(&ssh_dir)?;
+            fs::create_dir_all(&host_ssh_dir)?;
             let keys = config.ssh_keys.join("\n") + "\n";
             fs::write(&auth_keys, keys)?;
 
             // Set permissions
-            fs::set_permissions(&ssh_dir, fs::Permissions::from_mode(0o700))?;
+            fs::set_permissions(&host_ssh_dir, fs::Permissions::from_mode(0o700))?;
             fs::set_permissions(&auth_keys, fs::Permissions::from_mode(0o600))?;
 
-            // Set ownership
-            run_cmd(
+            // Set ownership, resolving the user inside the target
+            run_in(
+                root,
                 "chown",
-                ["-R", &format!("{}:{}", username, username), &ssh_dir],
+                &["-R", &format!("{}:{}", username, username), &ssh_dir],
             )?;
         }
+
+        if let Some(dotfiles) = &config.dotfiles {
+            apply_dotfiles(username, &home, dotfiles, root)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Clone `username`'s dotfiles repository into their home, then run its
+/// `post_clone` command as them
+///
+/// A clone that's already there is left alone, so local changes survive
+/// later applies.
+fn apply_dotfiles(
+    username: &str,
+    home: &str,
+    dotfiles: &DotfilesConfig,
+    root: &Path,
+) -> Result<()> {
+    let dest = dotfiles_dest(home, dotfiles);
+    if target_path(root, &dest).join(".git").exists() {
+        println!("    Dotfiles already cloned at {}", dest);
+        return Ok(());
+    }
+
+    println!("    Cloning dotfiles from {}...", dotfiles.repo);
+    // Cloned as the user, so the repository's hooks and config never run
+    // as root and its files are theirs
+    run_in_with_env(
+        root,
+        "runuser",
+        &dotfiles_clone_args(username, dotfiles, &dest)?,
+        &[
+            ("HOME", home),
+            // Fail instead of waiting for credentials nobody will type
+            ("GIT_TERMINAL_PROMPT", "0"),
+            (
+                "GIT_SSH_COMMAND",
+                "ssh -o BatchMode=yes -o StrictHostKeyChecking=accept-new",
+            ),
+        ],
+    )?;
+
+    if let Some(command) = &dotfiles.post_clone {
+        println!("    Running {}...", command);
+        let script = format!("cd {} && {}", shell_quote(&dest), command);
+        run_in(root, "su", &["-", username, "-c", &script])?;
     }
 
     Ok(())
 }
 
-fn apply_files(manifest: &Manifest, files_dir: Option<&Path>) -> Result<()> {
+/// Where `dotfiles` go, resolved against `home`
+fn dotfiles_dest(home: &str, dotfiles: &DotfilesConfig) -> String {
+    let dest = dotfiles.dest.as_deref().unwrap_or(".dotfiles");
+    if dest.starts_with('/') {
+        dest.to_string()
+    } else {
+        format!("{}/{}", home.trim_end_matches('/'), dest)
+    }
+}
+
+/// runuser arguments cloning `dotfiles` to `dest` as `username`
+fn dotfiles_clone_args(
+    username: &str,
+    dotfiles: &DotfilesConfig,
+    dest: &str,
+) -> Result<Vec<String>> {
+    check_repo_url(&dotfiles.repo)?;
+
+    let mut args: Vec<String> = ["-u", username, "--", "git", "clone"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    if let Some(branch) = &dotfiles.branch {
+        args.push("--branch".into());
+        args.push(branch.clone());
+    }
+    args.push("--".into());
+    args.push(dotfiles.repo.clone());
+    args.push(dest.to_string());
+    Ok(args)
+}
+
+/// Accept HTTPS and SSH repository URLs, including scp-style
+/// `git@host:path`; plain http:// and git:// are unauthenticated, and
+/// local paths read from the filesystem
+fn check_repo_url(url: &str) -> Result<()> {
+    if ["https://", "ssh://"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+    {
+        return Ok(());
+    }
+
+    // scp-style: `[user@]host:path`, where the host has no slash; git reads
+    // any other `scheme://` as a URL
+    if let Some((host, path)) = url.split_once(':') {
+        let plain_host = !host.is_empty() && !host.contains('/') && !host.starts_with('-');
+        if plain_host && !path.is_empty() && !path.starts_with("//") {
+            return Ok(());
+        }
+    }
+
+    bail!("Dotfiles repository {} is not an HTTPS or SSH URL", url)
+}
+
+/// `value` single-quoted for sh
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn apply_files(manifest: &Manifest, files_dir: Option<&Path>, root: &Path) -> Result<()> {
     if manifest.files.is_empty() {
         return Ok(());
     }
@@ -362,59 +788,156 @@ fn apply_files(manifest: &Manifest, files_dir: Option<&Path>) -> Result<()> {
     println!("Deploying files...");
 
     for file in &manifest.files {
-        println!("  {}", file.path);
+        let change = deploy_file(file, files_dir, root)?;
 
-        // Create parent directories
-        if let Some(parent) = Path::new(&file.path).parent() {
-            fs::create_dir_all(parent)?;
+        if change.is_unchanged() {
+            println!("  {} (unchanged)", file.path);
+        } else {
+            println!("  {}", file.path);
         }
+    }
 
-        // Get content
-        let content = if let Some(content) = &file.content {
-            content.clone()
-        } else if let Some(source) = &file.source {
-            // Source is relative to files_dir (from tar) or absolute
-            let source_path = if let Some(base) = files_dir {
-                base.join(source)
-            } else {
-                Path::new(source).to_path_buf()
-            };
+    Ok(())
+}
 
-            fs::read_to_string(&source_path)
-                .with_context(|| format!("Failed to read source file: {}", source_path.display()))?
-        } else {
-            bail!("File {} has no content or source", file.path);
-        };
+/// What `deploy_file` had to touch to bring a file in line with the manifest
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct FileChange {
+    content: bool,
+    mode: bool,
+    owner: bool,
+}
 
-        fs::write(&file.path, content)?;
+impl FileChange {
+    fn is_unchanged(&self) -> bool {
+        !self.content && !self.mode && !self.owner
+    }
+}
 
-        // Set mode if specified
-        if let Some(mode) = &file.mode {
-            let mode_int = u32::from_str_radix(mode.trim_start_matches('0'), 8)
-                .context("Invalid file mode")?;
-            fs::set_permissions(&file.path, fs::Permissions::from_mode(mode_int))?;
+/// Deploy a single manifest file under `root`, only writing, chmodding or
+/// chowning when the existing file differs from what the manifest asks for
+fn deploy_file(file: &FileConfig, files_dir: Option<&Path>, root: &Path) -> Result<FileChange> {
+    let path = target_path(root, &file.path);
+    let path = path.as_path();
+    let desired = file_content(file, files_dir)?;
+    let mut change = FileChange::default();
+
+    // Compare content byte-for-byte; a missing file always counts as changed
+    let existing = fs::read(path).ok();
+    if existing.as_deref() != Some(desired.as_slice()) {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
         }
+        fs::write(path, &desired).with_context(|| format!("Failed to write {}", file.path))?;
+        change.content = true;
+    }
 
-        // Set ownership if specified
-        if file.owner.is_some() || file.group.is_some() {
-            let owner = file.owner.as_deref().unwrap_or("");
-            let group = file.group.as_deref().unwrap_or("");
-            let ownership = if !owner.is_empty() && !group.is_empty() {
-                format!("{}:{}", owner, group)
-            } else if !owner.is_empty() {
-                owner.to_string()
-            } else {
-                format!(":{}", group)
+    let metadata = fs::metadata(path)?;
+
+    // Set mode if specified
+    if let Some(mode) = &file.mode {
+        let mode_int = parse_mode(mode)?;
+        if metadata.permissions().mode() & 0o7777 != mode_int {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode_int))?;
+            change.mode = true;
+        }
+    }
+
+    // Set ownership if specified
+    if file.owner.is_some() || file.group.is_some() {
+        let owner = file.owner.as_deref().filter(|o| !o.is_empty());
+        let group = file.group.as_deref().filter(|g| !g.is_empty());
+
+        // Names resolve against the host's user database, so a separate
+        // root is always chowned
+        if !is_host_root(root) || !ownership_matches(&metadata, owner, group) {
+            let ownership = match (owner, group) {
+                (Some(o), Some(g)) => format!("{}:{}", o, g),
+                (Some(o), None) => o.to_string(),
+                (None, Some(g)) => format!(":{}", g),
+                (None, None) => String::new(),
             };
 
-            run_cmd("chown", [&ownership, &file.path])?;
+            if !ownership.is_empty() {
+                run_in(root, "chown", &[&ownership, &file.path])?;
+                change.owner = true;
+            }
         }
     }
 
-    Ok(())
+    Ok(change)
+}
+
+/// Resolve the desired content of a manifest file from inline content, a
+/// source URL or a source file, checking it against `sha256` when given
+fn file_content(file: &FileConfig, files_dir: Option<&Path>) -> Result<Vec<u8>> {
+    let content = read_file_source(file, files_dir)?;
+
+    if let Some(expected) = &file.sha256 {
+        manifest::verify_sha256(content.as_slice(), expected)
+            .with_context(|| format!("Refusing to deploy {}", file.path))?;
+    }
+
+    Ok(content)
 }
 
-fn run_scripts(scripts: &[String]) -> Result<()> {
+fn read_file_source(file: &FileConfig, files_dir: Option<&Path>) -> Result<Vec<u8>> {
+    if let Some(content) = &file.content {
+        Ok(content.as_bytes().to_vec())
+    } else if let Some(source) = file.source.as_deref().filter(|s| manifest::is_url(s)) {
+        manifest::fetch_file(source, manifest::MAX_REMOTE_FILE_BYTES)
+    } else if let Some(source) = &file.source {
+        // Source is relative to files_dir (from tar) or absolute
+        let source_path = if let Some(base) = files_dir {
+            base.join(source)
+        } else {
+            Path::new(source).to_path_buf()
+        };
+
+        fs::read(&source_path)
+            .with_context(|| format!("Failed to read source file: {}", source_path.display()))
+    } else {
+        bail!("File {} has no content or source", file.path);
+    }
+}
+
+/// Parse an octal mode string such as "0644" or "755"
+fn parse_mode(mode: &str) -> Result<u32> {
+    let digits = mode.trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u32::from_str_radix(digits, 8).context("Invalid file mode")
+}
+
+/// Check whether a file is already owned by the requested user and/or group.
+///
+/// Names that can't be resolved are treated as a mismatch so chown gets a
+/// chance to report the problem.
+fn ownership_matches(metadata: &fs::Metadata, owner: Option<&str>, group: Option<&str>) -> bool {
+    use nix::unistd::{Group, User};
+    use std::os::unix::fs::MetadataExt;
+
+    let owner_ok = match owner {
+        Some(name) => matches!(
+            User::from_name(name),
+            Ok(Some(user)) if user.uid.as_raw() == metadata.uid()
+        ),
+        None => true,
+    };
+
+    let group_ok = match group {
+        Some(name) => matches!(
+            Group::from_name(name),
+            Ok(Some(grp)) if grp.gid.as_raw() == metadata.gid()
+        ),
+        None => true,
+    };
+
+    owner_ok && group_ok
+}
+
+fn run_scripts(scripts: &[String], root: &Path) -> Result<()> {
     if scripts.is_empty() {
         return Ok(());
     }
@@ -426,8 +949,486 @@ fn run_scripts(scripts: &[String]) -> Result<()> {
             "  Executing: {}...",
             script.lines().next().unwrap_or("(script)")
         );
-        run_cmd("sh", ["-c", script])?;
+        if is_host_root(root) {
+            run_cmd("sh", ["-c", script])?;
+        } else {
+            chroot::run_script(root, script)?;
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::distro::DistroKind;
+    use crate::init::ServiceSpec;
+    use crate::manifest::UserConfig;
+    use std::sync::Mutex;
+
+    /// Init system that records enable/disable calls instead of touching the filesystem
+    #[derive(Default)]
+    struct FakeInit {
+        enabled: Mutex<Vec<String>>,
+        disabled: Mutex<Vec<String>>,
+    }
+
+    impl InitSystem for FakeInit {
+        fn name(&self) -> &str {
+            "fake"
+        }
+
+        fn enable_service(&self, _root: &Path, service: &str) -> Result<()> {
+            self.enabled.lock().unwrap().push(service.into());
+            Ok(())
+        }
+
+        fn disable_service(&self, _root: &Path, service: &str) -> Result<()> {
+            self.disabled.lock().unwrap().push(service.into());
+            Ok(())
+        }
+
+        fn is_service_enabled(&self, _root: &Path, service: &str) -> bool {
+            self.enabled.lock().unwrap().iter().any(|s| s == service)
+        }
+
+        fn enabled_services(&self, _root: &Path) -> Vec<String> {
+            self.enabled.lock().unwrap().clone()
+        }
+
+        fn create_service(&self, _root: &Path, _spec: &ServiceSpec) -> Result<()> {
+            Ok(())
+        }
+
+        fn user_service_dir(&self) -> &str {
+            ""
+        }
+
+        fn setup_user_services(&self, _root: &Path) -> Result<()> {
+            Ok(())
+        }
+
+        fn create_user_service(&self, _root: &Path, _spec: &ServiceSpec) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn disable_uses_mapped_service_name() {
+        let artix = DistroKind::Artix.create();
+        let init = FakeInit::default();
+        let services = ServiceConfig {
+            enable: vec!["dbus".into()],
+            disable: vec!["seatd".into(), "sshd".into()],
+        };
+
+        reconcile_services(Path::new("/"), &services, |s| artix.map_service(s), &init).unwrap();
+
+        assert_eq!(*init.enabled.lock().unwrap(), vec!["dbus-srv"]);
+        assert_eq!(*init.disabled.lock().unwrap(), vec!["seatd-srv", "sshd"]);
+    }
+
+    #[test]
+    fn services_without_distro_equivalent_are_skipped() {
+        let init = FakeInit::default();
+        let services = ServiceConfig {
+            enable: vec!["dbus".into(), "unavailable".into()],
+            disable: vec!["unavailable".into()],
+        };
+        let map = |s: &str| {
+            if s == "unavailable" {
+                String::new()
+            } else {
+                s.to_string()
+            }
+        };
+
+        reconcile_services(Path::new("/"), &services, map, &init).unwrap();
+
+        assert_eq!(*init.enabled.lock().unwrap(), vec!["dbus"]);
+        assert!(init.disabled.lock().unwrap().is_empty());
+    }
+
+    fn file_config(path: &Path, content: &str) -> FileConfig {
+        FileConfig {
+            path: path.to_string_lossy().into(),
+            content: Some(content.into()),
+            source: None,
+            mode: None,
+            owner: None,
+            group: None,
+            sha256: None,
+        }
+    }
+
+    #[test]
+    fn identical_content_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("motd");
+        fs::write(&path, "Welcome to mkOS").unwrap();
+        let before = fs::metadata(&path).unwrap().modified().unwrap();
+
+        let change =
+            deploy_file(&file_config(&path, "Welcome to mkOS"), None, Path::new("/")).unwrap();
+
+        assert!(change.is_unchanged());
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), before);
+    }
+
+    #[test]
+    fn changed_content_is_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("motd");
+        fs::write(&path, "old").unwrap();
+
+        let change = deploy_file(&file_config(&path, "new"), None, Path::new("/")).unwrap();
+
+        assert!(change.content);
+        assert!(!change.mode);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+    }
+
+    #[test]
+    fn missing_parent_is_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("etc/mkos/motd");
+
+        let change = deploy_file(&file_config(&path, "hello"), None, Path::new("/")).unwrap();
+
+        assert!(change.content);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello");
+    }
+
+    #[test]
+    fn mode_only_change_keeps_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("script.sh");
+        fs::write(&path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let mut file = file_config(&path, "#!/bin/sh\n");
+        file.mode = Some("0755".into());
+        let change = deploy_file(&file, None, Path::new("/")).unwrap();
+
+        assert!(!change.content);
+        assert!(change.mode);
+        let mode = fs::metadata(&path).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode, 0o755);
+
+        // Second run is a no-op
+        assert!(deploy_file(&file, None, Path::new("/"))
+            .unwrap()
+            .is_unchanged());
+    }
+
+    #[test]
+    fn source_file_is_compared_by_content() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("motd.src"), "from source").unwrap();
+        let path = dir.path().join("motd");
+        fs::write(&path, "from source").unwrap();
+
+        let file = FileConfig {
+            content: None,
+            source: Some("motd.src".into()),
+            ..file_config(&path, "")
+        };
+
+        assert!(deploy_file(&file, Some(dir.path()), Path::new("/"))
+            .unwrap()
+            .is_unchanged());
+    }
+
+    #[test]
+    fn checksum_is_verified_before_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("motd");
+
+        let mut file = file_config(&path, "hello");
+        file.sha256 = Some("0".repeat(64));
+        let err = deploy_file(&file, None, Path::new("/")).unwrap_err();
+        assert!(format!("{:#}", err).contains("SHA-256 mismatch"));
+        assert!(!path.exists());
+
+        file.sha256 =
+            Some("2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824".into());
+        assert!(deploy_file(&file, None, Path::new("/")).unwrap().content);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello");
+    }
+
+    #[test]
+    fn target_paths_are_relocated_under_root() {
+        let root = Path::new("/mnt");
+        assert_eq!(
+            target_path(root, "/etc/hostname"),
+            Path::new("/mnt/etc/hostname")
+        );
+        assert_eq!(
+            target_path(root, "etc/vconsole.conf"),
+            Path::new("/mnt/etc/vconsole.conf")
+        );
+        assert_eq!(
+            target_path(Path::new("/"), "/etc/hostname"),
+            Path::new("/etc/hostname")
+        );
+    }
+
+    #[test]
+    fn files_are_deployed_under_target_root() {
+        let root = tempfile::tempdir().unwrap();
+        let file = FileConfig {
+            path: "/etc/motd".into(),
+            ..file_config(Path::new(""), "hello")
+        };
+
+        let change = deploy_file(&file, None, root.path()).unwrap();
+
+        assert!(change.content);
+        assert_eq!(
+            fs::read_to_string(root.path().join("etc/motd")).unwrap(),
+            "hello"
+        );
+    }
+
+    #[derive(clap::Parser)]
+    struct Cli {
+        #[command(flatten)]
+        apply: ApplyArgs,
+    }
+
+    fn parse_args(args: &[&str]) -> Result<ApplyArgs, clap::Error> {
+        use clap::Parser;
+        Cli::try_parse_from(["mkos-apply"].iter().chain(args)).map(|cli| cli.apply)
+    }
+
+    #[test]
+    fn apply_args_take_root_and_manifest() {
+        let args = parse_args(&["--root", "/mnt", "site.yaml"]).unwrap();
+        assert!(matches!(args.source(), ManifestSource::File(p) if p == Path::new("site.yaml")));
+        assert_eq!(args.root, Path::new("/mnt"));
+
+        let args = parse_args(&["site.yaml"]).unwrap();
+        assert_eq!(args.root, Path::new("/"));
+        assert!(matches!(
+            parse_args(&["-"]).unwrap().source(),
+            ManifestSource::Stdin
+        ));
+
+        assert!(parse_args(&[]).is_err());
+        assert!(parse_args(&["--root"]).is_err());
+        assert!(parse_args(&["a.yaml", "b.yaml"]).is_err());
+    }
+
+    #[test]
+    fn parse_mode_handles_leading_zeros() {
+        assert_eq!(parse_mode("0644").unwrap(), 0o644);
+        assert_eq!(parse_mode("755").unwrap(), 0o755);
+        assert_eq!(parse_mode("0000").unwrap(), 0);
+        assert!(parse_mode("0999").is_err());
+    }
+
+    #[test]
+    fn managed_manifest_drops_only_recorded_packages() {
+        let mut applied = AppliedPackages::default();
+        applied.record(&["firefox", "vlc", "git"]);
+
+        // htop was never recorded, so it was installed by hand and stays
+        assert_eq!(applied.unlisted(&["git", "htop"]), ["firefox", "vlc"]);
+        assert!(applied.unlisted(&["firefox", "git", "vlc"]).is_empty());
+
+        applied.forget(&["firefox", "vlc"]);
+        assert_eq!(applied.unlisted(&[]), ["git"]);
+    }
+
+    #[test]
+    fn applied_packages_survive_a_round_trip() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(
+            AppliedPackages::load(root.path()).unwrap(),
+            AppliedPackages::default()
+        );
+
+        let mut applied = AppliedPackages::default();
+        applied.record(&["vlc", "firefox"]);
+        applied.save(root.path()).unwrap();
+
+        let loaded = AppliedPackages::load(root.path()).unwrap();
+        assert_eq!(loaded, applied);
+        let json = fs::read_to_string(root.path().join(APPLIED_PACKAGES_PATH)).unwrap();
+        assert!(json.contains("\"firefox\",\n    \"vlc\""));
+    }
+
+    fn dotfiles(repo: &str) -> DotfilesConfig {
+        DotfilesConfig {
+            repo: repo.into(),
+            branch: None,
+            dest: None,
+            post_clone: None,
+        }
+    }
+
+    #[test]
+    fn dotfiles_are_cloned_as_the_user() {
+        let mut config = dotfiles("https://github.com/alice/dotfiles.git");
+        let dest = dotfiles_dest("/home/alice", &config);
+        assert_eq!(dest, "/home/alice/.dotfiles");
+        assert_eq!(
+            dotfiles_clone_args("alice", &config, &dest).unwrap(),
+            [
+                "-u",
+                "alice",
+                "--",
+                "git",
+                "clone",
+                "--",
+                "https://github.com/alice/dotfiles.git",
+                "/home/alice/.dotfiles"
+            ]
+        );
+
+        config.repo = "git@github.com:alice/dotfiles.git".into();
+        config.branch = Some("laptop".into());
+        config.dest = Some("src/dotfiles".into());
+        let dest = dotfiles_dest("/home/alice/", &config);
+        assert_eq!(
+            dotfiles_clone_args("alice", &config, &dest).unwrap(),
+            [
+                "-u",
+                "alice",
+                "--",
+                "git",
+                "clone",
+                "--branch",
+                "laptop",
+                "--",
+                "git@github.com:alice/dotfiles.git",
+                "/home/alice/src/dotfiles"
+            ]
+        );
+    }
+
+    #[test]
+    fn dotfiles_come_from_https_or_ssh_only() {
+        for repo in [
+            "https://example.com/dotfiles.git",
+            "ssh://git@example.com/dotfiles.git",
+            "git@example.com:dotfiles.git",
+        ] {
+            assert!(check_repo_url(repo).is_ok(), "{}", repo);
+        }
+        for repo in [
+            "/srv/dotfiles",
+            "file:///srv/dotfiles",
+            "http://example.com/dotfiles.git",
+            "git://example.com/dotfiles.git",
+            "./dotfiles",
+            "-uhelp",
+        ] {
+            assert!(check_repo_url(repo).is_err(), "{}", repo);
+        }
+        assert_eq!(shell_quote("/home/o'brien"), "'/home/o'\\''brien'");
+    }
+
+    #[test]
+    fn failed_apply_names_its_snapshot() {
+        let snapshot = "pre-apply-2026-10-16T09:30:00";
+        let err = recover(
+            anyhow::anyhow!("pacman failed"),
+            Some(snapshot),
+            false,
+            |_| false,
+            |_| panic!("restored without asking"),
+        );
+        assert_eq!(
+            err.to_string(),
+            "Apply failed; to undo its changes, run: mkos rollback pre-apply-2026-10-16T09:30:00"
+        );
+        assert_eq!(err.root_cause().to_string(), "pacman failed");
+
+        // Nothing to restore without a snapshot
+        let err = recover(
+            anyhow::anyhow!("pacman failed"),
+            None,
+            true,
+            |_| true,
+            |_| panic!("no snapshot to restore"),
+        );
+        assert_eq!(err.to_string(), "pacman failed");
+    }
+
+    #[test]
+    fn auto_rollback_restores_the_snapshot() {
+        let mut restored = None;
+        let err = recover(
+            anyhow::anyhow!("pacman failed"),
+            Some("pre-apply-1"),
+            true,
+            |_| panic!("asked despite --auto-rollback"),
+            |name| {
+                restored = Some(name.to_string());
+                Ok(())
+            },
+        );
+        assert_eq!(restored.as_deref(), Some("pre-apply-1"));
+        assert!(err.to_string().contains("restored snapshot pre-apply-1"));
+
+        let err = recover(
+            anyhow::anyhow!("pacman failed"),
+            Some("pre-apply-1"),
+            false,
+            |name| name == "pre-apply-1",
+            |_| bail!("@ is busy"),
+        );
+        assert_eq!(
+            err.to_string(),
+            "Apply failed, and so did restoring snapshot pre-apply-1 (@ is busy); run: mkos rollback pre-apply-1"
+        );
+    }
+
+    #[test]
+    fn dotfiles_need_git() {
+        let root = tempfile::tempdir().unwrap();
+        let none = InstalledPackages::default();
+        let mut manifest = Manifest::default();
+        assert!(check_dotfiles_git(&manifest, root.path(), &none).is_ok());
+
+        let user: UserConfig =
+            serde_yaml::from_str("dotfiles:\n  repo: https://example.com/d.git\n").unwrap();
+        manifest.users.insert("alice".into(), user);
+        let err = check_dotfiles_git(&manifest, root.path(), &none).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Dotfiles for alice need git; add git to packages"
+        );
+
+        manifest
+            .packages
+            .groups
+            .insert("base".into(), vec!["git".into()]);
+        assert!(check_dotfiles_git(&manifest, root.path(), &none).is_ok());
+
+        manifest.packages.groups.clear();
+        fs::create_dir_all(root.path().join("usr/bin")).unwrap();
+        fs::write(root.path().join("usr/bin/git"), "").unwrap();
+        assert!(check_dotfiles_git(&manifest, root.path(), &none).is_ok());
+
+        // Or the package database says git is installed
+        let git: InstalledPackages = ["git"].into_iter().collect();
+        let bare = tempfile::tempdir().unwrap();
+        assert!(check_dotfiles_git(&manifest, bare.path(), &git).is_ok());
+    }
+
+    #[test]
+    fn vconsole_sets_keymap_and_font_in_place() {
+        assert_eq!(vconsole_with("", "us", None), "KEYMAP=us\n");
+        assert_eq!(
+            vconsole_with("KEYMAP=us\nXKBLAYOUT=us\n", "de", Some("ter-132n")),
+            "KEYMAP=de\nXKBLAYOUT=us\nFONT=ter-132n\n"
+        );
+        // Without a font in the manifest, one set by hand stays
+        let content = "FONT=lat2-16\nKEYMAP=us\n";
+        assert_eq!(vconsole_with(content, "us", None), content);
+    }
+}
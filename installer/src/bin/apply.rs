@@ -1,22 +1,23 @@
-use anyhow::{bail, Result};
-use std::env;
+use anyhow::Result;
+use clap::Parser;
 use tracing_subscriber::EnvFilter;
 
-use mkos::apply;
-use mkos::manifest::ManifestSource;
+use mkos::apply::{self, ApplyArgs};
+
+/// Apply a manifest to an existing mkOS system (same as `mkos apply`)
+#[derive(Parser)]
+#[command(name = "mkos-apply", version)]
+struct Cli {
+    #[command(flatten)]
+    apply: ApplyArgs,
+}
 
 fn main() -> Result<()> {
+    let cli = Cli::parse();
+
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .init();
 
-    let args: Vec<String> = env::args().collect();
-    let source = ManifestSource::from_arg(args.get(1).map(|s| s.as_str()));
-
-    // Validate that a manifest was provided
-    if matches!(source, ManifestSource::Interactive) {
-        bail!("mkos-apply requires a manifest.\nUsage: mkos apply <manifest> (or mkos-apply <manifest>)");
-    }
-
-    apply::run(source)
+    apply::run(cli.apply.source(), &cli.apply.root, cli.apply.auto_rollback)
 }
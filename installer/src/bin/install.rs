@@ -1,10 +1,41 @@
 use anyhow::Result;
-use tracing_subscriber::EnvFilter;
+use clap::Parser;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+
+use mkos::cli::InstallArgs;
 
 fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
+    let args = InstallArgs::parse();
+
+    // RUST_LOG wins; --verbose is a shortcut for debug output from mkOS itself
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        if args.verbose {
+            EnvFilter::new("mkos=debug")
+        } else {
+            EnvFilter::new("error")
+        }
+    });
+
+    // Closing spans reports how long each install step took. The install
+    // log always gets debug output, whatever the terminal shows.
+    tracing_subscriber::registry()
+        .with(
+            fmt::layer()
+                .with_span_events(FmtSpan::CLOSE)
+                .with_writer(std::io::stderr)
+                .with_filter(filter),
+        )
+        .with(
+            fmt::layer()
+                .with_ansi(false)
+                .with_span_events(FmtSpan::CLOSE)
+                .with_writer(|| mkos::cmd::LogWriter)
+                .with_filter(EnvFilter::new("mkos=debug")),
+        )
         .init();
 
-    mkos::cli::run()
+    mkos::cli::run(args)
 }
@@ -1,69 +1,111 @@
-use anyhow::{bail, Result};
-use std::env;
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
-use mkos::commands::{rollback, snapshot, update};
-use mkos::manifest::ManifestSource;
-
-fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() < 2 {
-        print_usage();
-        return Ok(());
-    }
-
-    match args[1].as_str() {
-        "update" => update::update(),
-        "upgrade" | "up" => update::upgrade(),
-        "rollback" => rollback::rollback(),
-        "snapshot" => snapshot::snapshot_cmd(&args[2..]),
-        "apply" => apply(&args[2..]),
-        "help" | "--help" | "-h" => {
-            print_usage();
-            Ok(())
-        }
-        _ => {
-            eprintln!("Unknown command: {}", args[1]);
-            print_usage();
-            std::process::exit(1);
-        }
-    }
-}
-
-fn print_usage() {
-    println!(
-        r#"mkOS - System management tool
-
-Usage:
-    mkos update           Update package indexes
-    mkos upgrade          Update indexes and upgrade packages (with snapshot)
-    mkos rollback         Restore system to current snapshot (use when booted to fallback)
-    mkos apply <manifest> Apply manifest to system (with snapshot)
-    mkos snapshot list    List all snapshots
-    mkos snapshot delete <name>  Delete a snapshot
-    mkos help             Show this help message
+use mkos::apply::ApplyArgs;
+use mkos::commands::snapshot::SnapshotCommand;
+use mkos::commands::state::StateCommand;
+use mkos::commands::{regen_boot, repair, rollback, snapshot, state, status, update, validate};
 
+const EXAMPLES: &str = "\
 Examples:
     mkos update           # Update package database only
     mkos upgrade          # Update and upgrade all packages (creates snapshot first)
     mkos rollback         # Restore system from fallback snapshot (when main system is broken)
+    mkos rollback install # Restore the system to its freshly installed state
     mkos apply config.yml # Apply configuration from manifest file
+    mkos apply --root /mnt config.yml  # Configure a freshly bootstrapped tree
+    mkos snapshot create before-tweaks  # Checkpoint the running system
     mkos snapshot list    # List all available snapshots
-"#
-    );
+    mkos snapshot diff install --full  # What changed since install
+    mkos snapshot prune --keep 5 --dry-run  # Preview pruning all but the newest 5
+    mkos status --json    # Machine-readable system status
+    mkos state show       # Manifest last installed or applied
+    mkos regen-boot       # Repoint crypttab, fstab and UKIs after cloning the disk
+    mkos repair --device /dev/nvme0n1  # From a live USB: rebuild boot for a system that won't boot
+    mkos validate --strict config.yml  # CI check for a manifest";
+
+/// mkOS - System management tool
+#[derive(Parser)]
+#[command(name = "mkos", version, after_help = EXAMPLES, arg_required_else_help = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 }
 
-fn apply(args: &[String]) -> Result<()> {
+#[derive(Subcommand)]
+enum Command {
+    /// Update package indexes
+    Update,
+    /// Update indexes and upgrade packages (with snapshot)
+    #[command(visible_alias = "up")]
+    Upgrade,
+    /// Restore the system to a snapshot (use when booted to fallback)
+    Rollback {
+        /// Snapshot to restore; the one booted from when omitted
+        name: Option<String>,
+    },
+    /// Create, list, compare, delete, and prune snapshots
+    Snapshot {
+        #[command(subcommand)]
+        command: SnapshotCommand,
+    },
+    /// Show the manifest mkOS last installed or applied
+    State {
+        #[command(subcommand)]
+        command: StateCommand,
+    },
+    /// Show filesystem, snapshot, swap, and kernel hook state
+    Status {
+        /// Print machine-readable JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Apply manifest to system (with snapshot)
+    Apply(ApplyArgs),
+    /// Rewrite crypttab, fstab and the UKIs for the disk's current UUIDs
+    RegenBoot,
+    /// Rebuild the boot images and entries of an installed system from a
+    /// live environment
+    Repair {
+        /// Disk mkOS is installed on
+        #[arg(long)]
+        device: PathBuf,
+        /// Detached LUKS header, for a disk installed with disk.detached_header
+        #[arg(long)]
+        header: Option<PathBuf>,
+    },
+    /// Check a manifest without applying it
+    Validate {
+        /// Also fail on unknown fields
+        #[arg(long)]
+        strict: bool,
+
+        /// Manifest file or tar bundle, comma-separated URLs, or - for stdin
+        manifest: String,
+    },
+}
+
+fn main() -> Result<()> {
+    match Cli::parse().command {
+        Command::Update => update::update(),
+        Command::Upgrade => update::upgrade(),
+        Command::Rollback { name } => rollback::rollback(name.as_deref()),
+        Command::Snapshot { command } => snapshot::snapshot_cmd(command),
+        Command::State { command } => state::state_cmd(command),
+        Command::Status { json } => status::status(json),
+        Command::Apply(args) => apply(args),
+        Command::RegenBoot => regen_boot::regen_boot(),
+        Command::Repair { device, header } => repair::repair(&device, header.as_deref()),
+        Command::Validate { strict, manifest } => validate::validate(&manifest, strict),
+    }
+}
+
+fn apply(args: ApplyArgs) -> Result<()> {
     if !nix::unistd::Uid::effective().is_root() {
         eprintln!("Error: mkos apply must be run as root (use sudo)");
         std::process::exit(1);
     }
 
-    let source = ManifestSource::from_arg(args.first().map(|s| s.as_str()));
-
-    if matches!(source, ManifestSource::Interactive) {
-        bail!("mkos apply requires a manifest. Usage: mkos apply <manifest>");
-    }
-
-    mkos::apply::run(source)
+    mkos::apply::run(args.source(), &args.root, args.auto_rollback)
 }
@@ -0,0 +1,7 @@
+use anyhow::Result;
+
+use mkos::uki::rebuild;
+
+fn main() -> Result<()> {
+    rebuild::run()
+}
@@ -3,6 +3,7 @@ use std::path::PathBuf;
 
 use mkos::prompt;
 use mkos::rescue;
+use mkos::secret::Secret;
 
 fn main() -> Result<()> {
     tracing_subscriber::fmt()
@@ -31,11 +32,12 @@ fn main() -> Result<()> {
         luks_partition.display()
     );
 
-    // Prompt for LUKS passphrase
-    let passphrase = rpassword::prompt_password("Enter LUKS passphrase: ")?;
+    // Prompt for LUKS passphrase, wiped once the volume is open
+    let passphrase = Secret::from(rpassword::prompt_password("Enter LUKS passphrase: ")?);
 
     // Mount and enter chroot
-    rescue::mount_system(&efi_partition, &luks_partition, &passphrase)?;
+    rescue::mount_system(&efi_partition, &luks_partition, passphrase.expose())?;
+    drop(passphrase);
     rescue::enter_chroot()?;
     rescue::cleanup()?;
 
@@ -1,18 +1,34 @@
-use super::{get_kernel_version, BootConfig, BootEntry, BootSystem};
+use super::{
+    entries_for, flavour_image, get_kernel_version, kernel_versions, order_first, release_image,
+    BootConfig, BootEntry, BootMode, BootSystem, Kernel, ResumeConfig,
+};
 use anyhow::{Context, Result};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::cmd;
+use tracing::instrument;
+
+/// EFI stubs ukify builds UKIs with, relative to the target
+const STUB_PATHS: [&str; 3] = [
+    "usr/lib/systemd/boot/efi/linuxx64.efi.stub",
+    "usr/lib/gummiboot/linuxx64.efi.stub",
+    "usr/share/systemd-boot/linuxx64.efi.stub",
+];
 
 /// Dracut + EFISTUB boot system implementation
 ///
 /// Uses dracut to generate initramfs and builds a Unified Kernel Image (UKI)
-/// that boots directly via UEFI without a separate bootloader.
+/// that boots directly via UEFI without a separate bootloader, or in EFISTUB
+/// mode boots the kernel itself with the command line in the NVRAM entry.
 #[derive(Debug, Clone, Default)]
 pub struct DracutEfistub {
     /// Extra kernel command line arguments
     pub extra_cmdline: Vec<String>,
+    /// Kernels to build UKIs for; every installed kernel when empty
+    pub kernels: Vec<Kernel>,
+    /// Whether to build UKIs or boot the kernels directly
+    pub mode: BootMode,
 }
 
 impl DracutEfistub {
@@ -25,101 +41,245 @@ impl DracutEfistub {
         self
     }
 
+    pub fn with_kernels(mut self, kernels: Vec<Kernel>) -> Self {
+        self.kernels = kernels;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: BootMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
     /// Generate UKI filename based on kernel version
     fn uki_filename(kver: &str) -> String {
         format!("mkos-{}.efi", kver)
     }
 
-    /// Build a UKI with a custom cmdline and output filename.
+    /// Label of the boot entry for `kver`; only the default kernel's entry
+    /// is plain "mkOS"
+    fn entry_label(kver: &str, default: bool) -> String {
+        if default {
+            "mkOS".into()
+        } else {
+            format!("mkOS ({})", kver)
+        }
+    }
+
+    /// Initramfs dracut builds for `kver`, relative to the target
+    fn initramfs_path(kver: &str) -> String {
+        format!("boot/initramfs-{}.img", kver)
+    }
+
+    /// Kernel image of `kver`
     ///
-    /// Reuses the existing vmlinuz and initramfs.img from the target's /boot.
-    /// Falls back to copying kernel as EFISTUB if no EFI stub is available.
-    fn build_uki(target: &Path, cmdline: &str, output_name: &str) -> Result<()> {
-        let efi_linux_dir = target.join("boot");
-        let uki_full_path = efi_linux_dir.join(output_name);
+    /// Arch-style packages keep it in the module directory, others install
+    /// it to /boot under the release or the package name; the last is where
+    /// mkOS has always looked.
+    fn kernel_image(target: &Path, kver: &str) -> PathBuf {
+        release_image(target, kver).unwrap_or_else(|| flavour_image(target, kver))
+    }
 
-        let stub_paths = [
-            target.join("usr/lib/systemd/boot/efi/linuxx64.efi.stub"),
-            target.join("usr/lib/gummiboot/linuxx64.efi.stub"),
-            target.join("usr/share/systemd-boot/linuxx64.efi.stub"),
-        ];
+    /// Initrd images in load order: microcode (when present) must come first
+    fn initrd_images(target: &Path, config: &BootConfig, kver: &str) -> Vec<PathBuf> {
+        let boot_dir = target.join("boot");
+        let mut images = Vec::new();
 
-        if stub_paths.iter().any(|p| p.exists()) {
-            let vmlinuz = target.join("boot/vmlinuz-linux");
-            let initramfs = target.join("boot/initramfs.img");
-            let osrel = target.join("etc/os-release");
-
-            cmd::run(
-                "ukify",
-                [
-                    "build",
-                    "--linux",
-                    &vmlinuz.to_string_lossy(),
-                    "--initrd",
-                    &initramfs.to_string_lossy(),
-                    "--cmdline",
-                    cmdline,
-                    "--os-release",
-                    &format!("@{}", osrel.display()),
-                    "--output",
-                    &uki_full_path.to_string_lossy(),
-                ],
-            )?;
-        } else {
-            fs::copy(target.join("boot/vmlinuz-linux"), &uki_full_path)?;
+        if let Some(ref microcode) = config.microcode {
+            let microcode_path = boot_dir.join(microcode);
+            if microcode_path.exists() {
+                images.push(microcode_path);
+            }
         }
 
-        Ok(())
+        images.push(target.join(Self::initramfs_path(kver)));
+        images
     }
 
-    /// Build a rescue UKI that boots with init=/bin/sh
+    /// Whether a vendor microcode image is loaded ahead of the initramfs
+    fn prepends_microcode(target: &Path, config: &BootConfig) -> bool {
+        config
+            .microcode
+            .as_ref()
+            .is_some_and(|image| target.join("boot").join(image).exists())
+    }
+
+    /// Append EFISTUB `initrd=` arguments (ESP-relative, in load order)
+    fn efistub_cmdline(cmdline: &str, initrds: &[PathBuf]) -> String {
+        let mut result = cmdline.to_string();
+        for initrd in initrds {
+            if let Some(name) = initrd.file_name() {
+                result.push_str(&format!(" initrd=\\{}", name.to_string_lossy()));
+            }
+        }
+        result
+    }
+
+    /// Assemble a UKI with ukify, passing each initrd in load order
+    fn run_ukify(
+        target: &Path,
+        kver: &str,
+        initrds: &[PathBuf],
+        cmdline: &str,
+        output: &Path,
+    ) -> Result<()> {
+        let vmlinuz = Self::kernel_image(target, kver);
+        let osrel = target.join("etc/os-release");
+
+        let mut args = vec![
+            "build".to_string(),
+            "--linux".to_string(),
+            vmlinuz.to_string_lossy().to_string(),
+        ];
+        for initrd in initrds {
+            args.push("--initrd".into());
+            args.push(initrd.to_string_lossy().to_string());
+        }
+        args.extend([
+            "--cmdline".to_string(),
+            cmdline.to_string(),
+            "--os-release".to_string(),
+            format!("@{}", osrel.display()),
+            "--output".to_string(),
+            output.to_string_lossy().to_string(),
+        ]);
+
+        cmd::run("ukify", args)
+    }
+
+    /// Whether images are UKIs: in UKI mode when an EFI stub is installed
+    fn builds_uki(&self, target: &Path) -> bool {
+        self.mode == BootMode::Uki && STUB_PATHS.iter().any(|p| target.join(p).exists())
+    }
+
+    /// Command line the boot entry for `kver`'s image passes, with `initrd=`
+    /// arguments in load order; None when the image is a UKI that embeds it
+    pub fn entry_cmdline(
+        &self,
+        target: &Path,
+        config: &BootConfig,
+        kver: &str,
+        cmdline: &str,
+    ) -> Option<String> {
+        (!self.builds_uki(target))
+            .then(|| Self::efistub_cmdline(cmdline, &Self::initrd_images(target, config, kver)))
+    }
+
+    /// Build a bootable image of `kver` with a custom cmdline and output
+    /// filename, returning the command line its boot entry has to pass
     ///
-    /// Uses the same kernel and initramfs as the main UKI but appends
-    /// `init=/bin/sh` to the command line for emergency shell access.
-    pub fn build_rescue_image(&self, target: &Path, config: &BootConfig) -> Result<BootEntry> {
-        let rescue_name = "mkos-rescue.efi";
-        let cmdline = format!("{} init=/bin/sh", self.build_cmdline(config));
+    /// Reuses the existing kernel and initramfs of `kver` from the target.
+    /// In EFISTUB mode, or if no EFI stub is available, the kernel is copied
+    /// as-is and boots with the initramfs alongside it.
+    fn build_image(
+        &self,
+        target: &Path,
+        config: &BootConfig,
+        kver: &str,
+        cmdline: &str,
+        output_name: &str,
+    ) -> Result<Option<String>> {
+        let efi_linux_dir = target.join("boot");
+        fs::create_dir_all(&efi_linux_dir)?;
+        let output = efi_linux_dir.join(output_name);
 
-        println!("  Building rescue UKI...");
-        Self::build_uki(target, &cmdline, rescue_name)?;
-        println!("  Rescue UKI: /boot/{}", rescue_name);
+        let entry_cmdline = self.entry_cmdline(target, config, kver, cmdline);
+        if entry_cmdline.is_none() {
+            // Use ukify to build UKI (proper tool, not objcopy)
+            let initrds = Self::initrd_images(target, config, kver);
+            Self::run_ukify(target, kver, &initrds, cmdline, &output)?;
+        } else {
+            if self.mode == BootMode::Uki {
+                println!("  No EFI stub found, using kernel EFISTUB...");
+            }
+            // The kernel can boot directly as an EFI application
+            fs::copy(Self::kernel_image(target, kver), &output)?;
+        }
 
-        Ok(BootEntry {
-            label: "mkOS (rescue)".into(),
-            loader_path: format!("/{}", rescue_name),
-        })
+        Ok(entry_cmdline)
     }
 
-    /// Build a fallback UKI that boots into a specific subvolume
-    pub fn build_fallback_image(
+    /// Build the main image for `kver`, named after the release
+    fn build_kernel_uki(
         &self,
         target: &Path,
         config: &BootConfig,
-        subvol: &str,
+        kver: &str,
+        label: String,
     ) -> Result<BootEntry> {
-        let fallback_name = "mkos-fallback.efi";
-        let fallback_config = BootConfig {
-            subvol: subvol.into(),
-            ..config.clone()
-        };
-        let cmdline = self.build_cmdline(&fallback_config);
+        let uki_name = Self::uki_filename(kver);
 
-        println!("  Building fallback UKI (subvol={})...", subvol);
-        Self::build_uki(target, &cmdline, fallback_name)?;
-        println!("  Fallback UKI: /boot/{}", fallback_name);
+        println!("Building boot image for kernel {}...", kver);
+        let cmdline = self.build_cmdline(config);
+        let entry_cmdline = self.build_image(target, config, kver, &cmdline, &uki_name)?;
+
+        match &entry_cmdline {
+            None => println!("✓ UKI built: /boot/{}", uki_name),
+            Some(entry_cmdline) => {
+                // For reference; the boot entry passes it
+                fs::write(target.join("boot/cmdline.txt"), entry_cmdline)?;
+                println!(
+                    "✓ EFISTUB kernel: /boot/{} (command line in the boot entry)",
+                    uki_name
+                );
+            }
+        }
 
         Ok(BootEntry {
-            label: "mkOS (fallback)".into(),
-            loader_path: format!("/{}", fallback_name),
+            label,
+            loader_path: format!("/{}", uki_name),
+            cmdline: entry_cmdline,
         })
     }
 
+    /// Command line of the rescue image, which starts a root shell instead
+    /// of init
+    fn rescue_cmdline(&self, config: &BootConfig) -> String {
+        // A splash would hide the shell
+        let rescue_config = BootConfig {
+            splash: false,
+            ..config.clone()
+        };
+        format!("{} init=/bin/sh", self.build_cmdline(&rescue_config))
+    }
+
     /// Build the kernel command line
-    fn build_cmdline(&self, config: &BootConfig) -> String {
-        let mut cmdline = format!(
-            "rd.luks.uuid={} root={} rootflags=subvol={} rw quiet",
-            config.luks_uuid, config.root_device, config.subvol
-        );
+    pub(crate) fn build_cmdline(&self, config: &BootConfig) -> String {
+        // An unencrypted root has nothing for the initramfs to unlock
+        let mut args = Vec::new();
+        if !config.luks_uuid.is_empty() {
+            args.push(format!("rd.luks.uuid={}", config.luks_uuid));
+            for uuid in &config.extra_luks_uuids {
+                args.push(format!("rd.luks.uuid={}", uuid));
+            }
+            args.extend(
+                config
+                    .luks_header
+                    .iter()
+                    .flat_map(|h| h.cmdline_args(&config.luks_uuid)),
+            );
+        }
+        args.push(format!("root={}", config.root_device));
+        let mut cmdline = args.join(" ");
+
+        // Filesystems without subvolumes (ext4, xfs) have no rootflags
+        if !config.subvol.is_empty() {
+            cmdline.push_str(&format!(" rootflags=subvol={}", config.subvol));
+        }
+
+        cmdline.push_str(" rw");
+        // Kernel messages would draw over the splash
+        if config.quiet || config.splash {
+            cmdline.push_str(" quiet");
+        }
+        if config.splash {
+            cmdline.push_str(" splash");
+        }
+
+        for arg in config.resume.iter().flat_map(ResumeConfig::cmdline_args) {
+            cmdline.push(' ');
+            cmdline.push_str(&arg);
+        }
 
         for arg in &self.extra_cmdline {
             cmdline.push(' ');
@@ -128,6 +288,75 @@ impl DracutEfistub {
 
         cmdline
     }
+
+    /// Whether the system at `target` has a LUKS root to unlock
+    fn is_encrypted(target: &Path) -> bool {
+        let crypttab = fs::read_to_string(target.join("etc/crypttab")).unwrap_or_default();
+        crate::uki::rebuild::has_entries(&crypttab)
+    }
+
+    /// Build and check the initramfs of `kver`
+    fn build_kernel_initramfs(&self, target: &Path, kver: &str) -> Result<()> {
+        let target_str = target.to_string_lossy().to_string();
+        let initramfs = Self::initramfs_path(kver);
+
+        println!("  Generating initramfs for kernel {}...", kver);
+
+        // Use --hostonly since live USB runs on target hardware
+        // Force-add modules that return 255 when not detected on running system:
+        //   - dm: device mapper (check() always returns 255)
+        //   - crypt: LUKS encryption (returns 255 if no crypto_LUKS on live USB)
+        //   - btrfs: btrfs filesystem (returns 255 if no btrfs on live USB)
+        // These are always needed for mkOS but may not be on the live USB;
+        // an unencrypted install leaves crypt out
+        let encrypted = Self::is_encrypted(target);
+        let initramfs_arg = format!("/{}", initramfs);
+        let mut args = vec![
+            target_str.as_str(),
+            "dracut",
+            "--force",
+            "--hostonly",
+            "--kver",
+            kver,
+            "--omit",
+            "systemd systemd-initrd systemd-udevd dracut-systemd",
+            "--force-add",
+            "dm",
+            "--force-add",
+            "btrfs",
+            "--add-drivers",
+            "dm_mod",
+        ];
+        if encrypted {
+            args.extend(["--force-add", "crypt", "--add-drivers", "dm_crypt"]);
+        }
+        args.push(&initramfs_arg);
+        cmd::run("chroot", args)?;
+
+        // Verify critical modules are present
+        println!("  Verifying dm modules in initramfs...");
+        let lsinitrd_output = std::process::Command::new("lsinitrd")
+            .arg(target.join(&initramfs))
+            .output()
+            .context("Failed to run lsinitrd")?;
+
+        let output_str = String::from_utf8_lossy(&lsinitrd_output.stdout);
+
+        if !output_str.contains("dm_mod.ko") {
+            anyhow::bail!("dm_mod module not found in initramfs! Boot will fail.");
+        }
+        if !encrypted {
+            println!("  ✓ dm_mod verified in initramfs");
+            return Ok(());
+        }
+        if !output_str.contains("dm_crypt.ko") {
+            anyhow::bail!("dm_crypt module not found in initramfs! Boot will fail.");
+        }
+
+        println!("  ✓ dm_mod and dm_crypt verified in initramfs");
+
+        Ok(())
+    }
 }
 
 impl BootSystem for DracutEfistub {
@@ -135,8 +364,9 @@ impl BootSystem for DracutEfistub {
         "dracut-efistub"
     }
 
-    fn generate_initramfs_config(&self, target: &Path, _config: &BootConfig) -> Result<()> {
-        let dracut_config = r#"# mkOS dracut configuration
+    fn generate_initramfs_config(&self, target: &Path, config: &BootConfig) -> Result<()> {
+        let mut dracut_config = String::from(
+            r#"# mkOS dracut configuration
 # Note: hostonly is controlled by command line in hook script
 
 # Omit all systemd dracut modules - mkOS targets non-systemd distributions
@@ -153,182 +383,171 @@ omit_dracutmodules+=" systemd-veritysetup systemd-emergency systemd-sysusers "
 # Force modules that return 255 when not on running system
 # mkOS always uses these, even if live USB doesn't have them:
 #   - dm: device mapper (check() always returns 255)
-#   - crypt: LUKS encryption (returns 255 if no crypto_LUKS detected)
 #   - btrfs: btrfs filesystem (returns 255 if no btrfs detected)
-force_add_dracutmodules+=" dm crypt btrfs "
+force_add_dracutmodules+=" dm btrfs "
 
 # Additional required modules
 add_dracutmodules+=" rootfs-block "
 
-# CPU microcode - critical for stability on some hardware
-# Install intel-ucode or amd-ucode package
-early_microcode=yes
-
-# Critical drivers - always include for LUKS support
-add_drivers+=" dm_mod dm_crypt "
+# Critical drivers
+add_drivers+=" dm_mod "
 
 # Drivers for VMs and common hardware
 add_drivers+=" virtio virtio_blk virtio_pci virtio_scsi nvme ahci sd_mod "
 
 # Filesystems
-filesystems+=" btrfs ext4 vfat "
+filesystems+=" btrfs ext4 xfs vfat "
 
 # Compression
 compress="zstd"
+"#,
+        );
+
+        if !config.luks_uuid.is_empty() {
+            // crypt returns 255 if no crypto_LUKS is detected on the live USB
+            dracut_config.push_str(
+                "\n# LUKS unlocking, with crypttab for device discovery\n\
+                 force_add_dracutmodules+=\" crypt \"\n\
+                 add_drivers+=\" dm_crypt \"\n\
+                 install_items+=\" /etc/crypttab \"\n",
+            );
+        }
+
+        // CPU microcode is critical for stability on some hardware. Where the
+        // distro ships a vendor image (intel-ucode.img/amd-ucode.img) it's
+        // loaded as the first initrd of the UKI, so dracut mustn't embed a
+        // second copy; everywhere else dracut embeds it
+        let early_microcode = if Self::prepends_microcode(target, config) {
+            "no"
+        } else {
+            "yes"
+        };
+        dracut_config.push_str(&format!(
+            "\n# CPU microcode\nearly_microcode={}\n",
+            early_microcode
+        ));
+
+        if !config.kms_drivers.is_empty() {
+            dracut_config.push_str(&format!(
+                "\n# Early KMS - native resolution before the passphrase prompt\nadd_drivers+=\" {} \"\n",
+                config.kms_drivers.join(" ")
+            ));
+        }
+
+        if config.console_font {
+            dracut_config.push_str(
+                "\n# Console font from vconsole.conf, set before the passphrase prompt\nadd_dracutmodules+=\" i18n \"\ninstall_items+=\" /etc/vconsole.conf \"\n",
+            );
+        }
 
-# Include crypttab for LUKS device discovery
-install_items+=" /etc/crypttab "
-"#;
+        if config.splash {
+            // dracut's crypt module asks for the passphrase through plymouth
+            // when it's in the initramfs
+            dracut_config.push_str(
+                "\n# Boot splash - also themes the LUKS passphrase prompt\nadd_dracutmodules+=\" plymouth \"\n",
+            );
+        }
+
+        if config.resume.is_some() {
+            dracut_config.push_str(
+                "\n# Hibernation - resume from swap after LUKS is unlocked\nadd_dracutmodules+=\" resume \"\n",
+            );
+        }
 
         let dracut_conf_dir = target.join("etc/dracut.conf.d");
         fs::create_dir_all(&dracut_conf_dir)?;
-        fs::write(dracut_conf_dir.join("mkos.conf"), dracut_config)?;
+        fs::write(dracut_conf_dir.join("mkos.conf"), &dracut_config)?;
 
         Ok(())
     }
 
+    #[instrument(skip(self))]
     fn build_initramfs(&self, target: &Path) -> Result<()> {
-        let kver = get_kernel_version(target)?;
-        let target_str = target.to_string_lossy().to_string();
-
-        println!("  Generating initramfs for kernel {}...", kver);
-        // Use --hostonly since live USB runs on target hardware
-        // Force-add modules that return 255 when not detected on running system:
-        //   - dm: device mapper (check() always returns 255)
-        //   - crypt: LUKS encryption (returns 255 if no crypto_LUKS on live USB)
-        //   - btrfs: btrfs filesystem (returns 255 if no btrfs on live USB)
-        // These are always needed for mkOS but may not be on the live USB
-        cmd::run(
-            "chroot",
-            [
-                &target_str,
-                "dracut",
-                "--force",
-                "--hostonly",
-                "--kver",
-                &kver,
-                "--omit",
-                "systemd systemd-initrd systemd-udevd dracut-systemd",
-                "--force-add",
-                "dm",
-                "--force-add",
-                "crypt",
-                "--force-add",
-                "btrfs",
-                "--add-drivers",
-                "dm_mod",
-                "--add-drivers",
-                "dm_crypt",
-                "/boot/initramfs.img",
-            ],
-        )?;
-
-        // Verify critical modules are present
-        println!("  Verifying dm modules in initramfs...");
-        let lsinitrd_output = std::process::Command::new("lsinitrd")
-            .arg(target.join("boot/initramfs.img"))
-            .output()
-            .context("Failed to run lsinitrd")?;
-
-        let output_str = String::from_utf8_lossy(&lsinitrd_output.stdout);
-
-        if !output_str.contains("dm_mod.ko") {
-            anyhow::bail!("dm_mod module not found in initramfs! Boot will fail.");
-        }
-        if !output_str.contains("dm_crypt.ko") {
-            anyhow::bail!("dm_crypt module not found in initramfs! Boot will fail.");
+        for kver in kernel_versions(target, &self.kernels)? {
+            self.build_kernel_initramfs(target, &kver)?;
         }
-
-        println!("  ✓ dm_mod and dm_crypt verified in initramfs");
-
         Ok(())
     }
 
+    #[instrument(skip(self))]
     fn build_boot_image(&self, target: &Path, config: &BootConfig) -> Result<BootEntry> {
-        let kver = get_kernel_version(target)?;
-        let uki_name = Self::uki_filename(&kver);
-
-        println!("Building UKI for kernel {}...", kver);
-
-        // Create the Linux directory
-        let efi_linux_dir = target.join("boot");
-        fs::create_dir_all(&efi_linux_dir)?;
-
-        // Build cmdline
-        let cmdline = self.build_cmdline(config);
-
-        // Write cmdline to a temp file
-        let cmdline_path = target.join("boot/cmdline.txt");
-        fs::write(&cmdline_path, &cmdline)?;
-
-        // Find the EFI stub - check common locations
-        let stub_paths = [
-            target.join("usr/lib/systemd/boot/efi/linuxx64.efi.stub"),
-            target.join("usr/lib/gummiboot/linuxx64.efi.stub"),
-            target.join("usr/share/systemd-boot/linuxx64.efi.stub"),
-        ];
-
-        let stub_path = stub_paths.iter().find(|p| p.exists()).cloned();
-        let uki_full_path = efi_linux_dir.join(&uki_name);
-
-        if let Some(_stub) = stub_path {
-            // Use ukify to build UKI (proper tool, not objcopy)
-            println!("  Assembling UKI with ukify...");
-
-            let vmlinuz = target.join("boot/vmlinuz-linux");
-            let initramfs = target.join("boot/initramfs.img");
-            let osrel = target.join("etc/os-release");
-
-            cmd::run(
-                "ukify",
-                [
-                    "build",
-                    "--linux",
-                    &vmlinuz.to_string_lossy(),
-                    "--initrd",
-                    &initramfs.to_string_lossy(),
-                    "--cmdline",
-                    &cmdline,
-                    "--os-release",
-                    &format!("@{}", osrel.display()),
-                    "--output",
-                    &uki_full_path.to_string_lossy(),
-                ],
-            )?;
-        } else {
-            // No stub available - fall back to copying kernel + initramfs separately
-            // and use EFISTUB boot (kernel can boot directly as EFI application)
-            println!("  No EFI stub found, using kernel EFISTUB...");
+        let kver = get_kernel_version(target, &self.kernels)?;
+        self.build_kernel_uki(target, config, &kver, Self::entry_label(&kver, true))
+    }
 
-            // Copy kernel as the UKI (Linux kernel is EFI-bootable)
-            fs::copy(target.join("boot/vmlinuz-linux"), &uki_full_path)?;
+    /// Build a UKI per installed kernel; the newest is the default "mkOS"
+    /// entry and the others are labelled with their release
+    #[instrument(skip(self))]
+    fn build_boot_images(&self, target: &Path, config: &BootConfig) -> Result<Vec<BootEntry>> {
+        let versions = kernel_versions(target, &self.kernels)?;
+
+        // Oldest first, so the default's UKI is the most recently written
+        // (what mkos-rebuild-uki keeps as fallback) and its cmdline.txt stays
+        let mut entries = Vec::new();
+        for (i, kver) in versions.iter().enumerate().rev() {
+            let label = Self::entry_label(kver, i == 0);
+            entries.push(self.build_kernel_uki(target, config, kver, label)?);
+        }
+        entries.reverse();
+        Ok(entries)
+    }
 
-            // Also copy initramfs next to it
-            fs::copy(
-                target.join("boot/initramfs.img"),
-                efi_linux_dir.join("initramfs.img"),
-            )?;
+    /// Build a rescue UKI that boots with init=/bin/sh
+    ///
+    /// Uses the default kernel and its initramfs like the main UKI but appends
+    /// `init=/bin/sh` to the command line for emergency shell access.
+    #[instrument(skip(self))]
+    fn build_rescue_image(&self, target: &Path, config: &BootConfig) -> Result<BootEntry> {
+        let rescue_name = "mkos-rescue.efi";
+        let kver = get_kernel_version(target, &self.kernels)?;
+        let cmdline = self.rescue_cmdline(config);
 
-            // Write cmdline for the boot entry
-            fs::write(efi_linux_dir.join("cmdline.txt"), &cmdline)?;
+        println!("  Building rescue UKI...");
+        let entry_cmdline = self.build_image(target, config, &kver, &cmdline, rescue_name)?;
+        println!("  Rescue UKI: /boot/{}", rescue_name);
 
-            println!("  Note: Using EFISTUB fallback (kernel + separate initramfs)");
-        }
+        Ok(BootEntry {
+            label: "mkOS (rescue)".into(),
+            loader_path: format!("/{}", rescue_name),
+            cmdline: entry_cmdline,
+        })
+    }
 
-        // Clean up temp files
-        let _ = fs::remove_file(&cmdline_path);
+    /// Build a fallback UKI that boots into a specific subvolume
+    fn build_fallback_image(
+        &self,
+        target: &Path,
+        config: &BootConfig,
+        subvol: &str,
+    ) -> Result<BootEntry> {
+        let fallback_name = "mkos-fallback.efi";
+        let kver = get_kernel_version(target, &self.kernels)?;
+        let fallback_config = BootConfig {
+            subvol: subvol.into(),
+            ..config.clone()
+        };
+        let cmdline = self.build_cmdline(&fallback_config);
 
-        println!("✓ UKI built: /boot/{}", uki_name);
+        println!("  Building fallback UKI (subvol={})...", subvol);
+        let entry_cmdline =
+            self.build_image(target, &fallback_config, &kver, &cmdline, fallback_name)?;
+        println!("  Fallback UKI: /boot/{}", fallback_name);
 
         Ok(BootEntry {
-            label: "mkOS".into(),
-            loader_path: format!("/{}", uki_name),
+            label: "mkOS (fallback)".into(),
+            loader_path: format!("/{}", fallback_name),
+            cmdline: entry_cmdline,
         })
     }
 
     fn create_fallback_scripts(&self, target: &Path, entry: &BootEntry) -> Result<()> {
         // Create a startup.nsh script that some UEFI implementations will auto-execute
-        let loader_escaped = entry.loader_path.replace('/', "\\");
+        let mut loader_escaped = entry.loader_path.replace('/', "\\");
+        // The shell passes the rest of the line to an EFISTUB kernel
+        if let Some(cmdline) = &entry.cmdline {
+            loader_escaped.push(' ');
+            loader_escaped.push_str(cmdline);
+        }
         let startup_script = format!(
             "# mkOS automatic boot script\n\
              # This script is executed automatically by some UEFI implementations\n\
@@ -345,6 +564,7 @@ install_items+=" /etc/crypttab "
         Ok(())
     }
 
+    #[instrument(skip(self))]
     fn create_boot_entry(&self, device: &Path, efi_part_num: u32, entry: &BootEntry) -> Result<()> {
         // Check if system is booted in UEFI mode
         if !Path::new("/sys/firmware/efi").exists() {
@@ -363,8 +583,11 @@ install_items+=" /etc/crypttab "
             );
         }
 
-        let device_str = device.to_string_lossy().to_string();
-        let part_str = efi_part_num.to_string();
+        // Reinstalls and repairs would otherwise stack up identical entries
+        for bootnum in entries_for(&efibootmgr_verbose()?, &entry.label, &entry.loader_path) {
+            println!("Removing old EFI boot entry Boot{}", bootnum);
+            cmd::run("efibootmgr", ["--bootnum", &bootnum, "--delete-bootnum"])?;
+        }
 
         println!(
             "Creating EFI boot entry '{}' for {} partition {}...",
@@ -373,48 +596,78 @@ install_items+=" /etc/crypttab "
             efi_part_num
         );
 
-        // UKI contains cmdline, so we don't pass --unicode
         cmd::run(
             "efibootmgr",
-            [
-                "--create",
-                "--disk",
-                &device_str,
-                "--part",
-                &part_str,
-                "--label",
-                &entry.label,
-                "--loader",
-                &entry.loader_path,
-            ],
+            efibootmgr_create_args(device, efi_part_num, entry),
         )?;
 
         println!("✓ EFI boot entry '{}' created successfully", entry.label);
 
-        // Verify the entry was created
-        if let Ok(output) = std::process::Command::new("efibootmgr").output() {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            if !stdout.contains(&entry.label) {
-                anyhow::bail!(
-                    "Boot entry was not saved to NVRAM. Your UEFI firmware may have issues."
-                );
-            }
-            println!("✓ Boot entry '{}' verified in NVRAM", entry.label);
-        }
+        // Verify the entry was created, and boot it first
+        let output = efibootmgr_verbose()?;
+        let Some(bootnum) = entries_for(&output, &entry.label, &entry.loader_path)
+            .into_iter()
+            .next()
+        else {
+            anyhow::bail!("Boot entry was not saved to NVRAM. Your UEFI firmware may have issues.");
+        };
+        println!("✓ Boot entry '{}' verified in NVRAM", entry.label);
+
+        cmd::run(
+            "efibootmgr",
+            ["--bootorder", &order_first(&output, &bootnum).join(",")],
+        )?;
 
         Ok(())
     }
 }
 
+/// `efibootmgr` arguments creating `entry`
+///
+/// A UKI contains its cmdline, so `--unicode` only passes one to an EFISTUB
+/// kernel.
+fn efibootmgr_create_args(device: &Path, efi_part_num: u32, entry: &BootEntry) -> Vec<String> {
+    let mut args = vec![
+        "--create".to_string(),
+        "--disk".to_string(),
+        device.to_string_lossy().to_string(),
+        "--part".to_string(),
+        efi_part_num.to_string(),
+        "--label".to_string(),
+        entry.label.clone(),
+        "--loader".to_string(),
+        entry.loader_path.clone(),
+    ];
+    if let Some(cmdline) = &entry.cmdline {
+        args.push("--unicode".into());
+        args.push(cmdline.clone());
+    }
+    args
+}
+
+/// NVRAM boot entries with the paths they start
+fn efibootmgr_verbose() -> Result<String> {
+    cmd::run_output("efibootmgr", ["--verbose"])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypt::DetachedHeader;
 
     fn test_config() -> BootConfig {
         BootConfig {
             luks_uuid: "abcd-1234-efgh-5678".into(),
+            extra_luks_uuids: Vec::new(),
             root_device: "/dev/mapper/system".into(),
             subvol: "@".into(),
+            quiet: true,
+            splash: false,
+            kms_drivers: Vec::new(),
+            console_font: false,
+            microcode: None,
+            resume: None,
+            luks_header: None,
         }
     }
 
@@ -427,6 +680,62 @@ mod tests {
         assert_eq!(DracutEfistub::uki_filename("5.15.0"), "mkos-5.15.0.efi");
     }
 
+    #[test]
+    fn test_uki_filename_follows_the_chosen_kernel() {
+        let target = tempfile::tempdir().unwrap();
+        for kver in ["6.10.2-arch1-1", "6.10.2-zen1-1-zen"] {
+            let modules = target.path().join("lib/modules").join(kver);
+            fs::create_dir_all(&modules).unwrap();
+            fs::write(modules.join("vmlinuz"), "kernel").unwrap();
+        }
+
+        let boot = DracutEfistub::new().with_kernels(vec![Kernel::Zen]);
+        let kver = get_kernel_version(target.path(), &boot.kernels).unwrap();
+        assert_eq!(
+            DracutEfistub::uki_filename(&kver),
+            "mkos-6.10.2-zen1-1-zen.efi"
+        );
+    }
+
+    #[test]
+    fn test_each_kernel_gets_its_own_uki_and_initramfs() {
+        for kver in ["6.10.10-arch1-1", "6.6.40-1-lts"] {
+            let uki = DracutEfistub::uki_filename(kver);
+            assert_eq!(uki, format!("mkos-{}.efi", kver));
+            assert_eq!(
+                DracutEfistub::initramfs_path(kver),
+                format!("boot/initramfs-{}.img", kver)
+            );
+        }
+        assert_eq!(DracutEfistub::entry_label("6.10.10-arch1-1", true), "mkOS");
+        assert_eq!(
+            DracutEfistub::entry_label("6.6.40-1-lts", false),
+            "mkOS (6.6.40-1-lts)"
+        );
+    }
+
+    #[test]
+    fn test_kernel_image_per_release() {
+        let target = tempfile::tempdir().unwrap();
+        let modules = target.path().join("lib/modules/6.6.40-1-lts");
+        fs::create_dir_all(&modules).unwrap();
+        fs::write(modules.join("vmlinuz"), b"kernel").unwrap();
+
+        assert_eq!(
+            DracutEfistub::kernel_image(target.path(), "6.6.40-1-lts"),
+            modules.join("vmlinuz")
+        );
+        // Without an image alongside the modules, /boot is named by package
+        assert_eq!(
+            DracutEfistub::kernel_image(target.path(), "6.10.2-zen1-1-zen"),
+            target.path().join("boot/vmlinuz-linux-zen")
+        );
+        assert_eq!(
+            DracutEfistub::kernel_image(target.path(), "6.10.2-arch1-1"),
+            target.path().join("boot/vmlinuz-linux")
+        );
+    }
+
     #[test]
     fn test_build_cmdline_basic() {
         let boot = DracutEfistub::new();
@@ -471,6 +780,146 @@ mod tests {
         assert!(boot.extra_cmdline.is_empty());
     }
 
+    #[test]
+    fn test_build_cmdline_extras_follow_mandatory_args() {
+        let boot = DracutEfistub::new().with_extra_cmdline(vec![
+            "nvidia-drm.modeset=1".into(),
+            "mitigations=off".into(),
+        ]);
+        let cmdline = boot.build_cmdline(&test_config());
+
+        assert!(cmdline.starts_with("rd.luks.uuid=abcd-1234-efgh-5678 root=/dev/mapper/system "));
+        assert!(cmdline.ends_with(" rw quiet nvidia-drm.modeset=1 mitigations=off"));
+    }
+
+    #[test]
+    fn test_initrd_images_microcode_first() {
+        let target = tempfile::tempdir().unwrap();
+        let boot_dir = target.path().join("boot");
+        std::fs::create_dir_all(&boot_dir).unwrap();
+        std::fs::write(boot_dir.join("intel-ucode.img"), b"ucode").unwrap();
+
+        let config = BootConfig {
+            microcode: Some("intel-ucode.img".into()),
+            ..test_config()
+        };
+        let images = DracutEfistub::initrd_images(target.path(), &config, "6.10.2-arch1-1");
+
+        assert_eq!(
+            images,
+            vec![
+                boot_dir.join("intel-ucode.img"),
+                boot_dir.join("initramfs-6.10.2-arch1-1.img")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_efistub_cmdline_initrd_order() {
+        let initrds = vec![
+            PathBuf::from("/mnt/boot/amd-ucode.img"),
+            PathBuf::from("/mnt/boot/initramfs.img"),
+        ];
+        let cmdline = DracutEfistub::efistub_cmdline("root=/dev/mapper/system rw", &initrds);
+
+        assert_eq!(
+            cmdline,
+            "root=/dev/mapper/system rw initrd=\\amd-ucode.img initrd=\\initramfs.img"
+        );
+    }
+
+    #[test]
+    fn test_initrd_images_skip_missing_microcode() {
+        let target = tempfile::tempdir().unwrap();
+        let config = BootConfig {
+            microcode: Some("amd-ucode.img".into()),
+            ..test_config()
+        };
+        let images = DracutEfistub::initrd_images(target.path(), &config, "6.10.2-arch1-1");
+
+        assert_eq!(
+            images,
+            vec![target.path().join("boot/initramfs-6.10.2-arch1-1.img")]
+        );
+    }
+
+    #[test]
+    fn test_build_cmdline_not_quiet() {
+        let boot = DracutEfistub::new();
+        let config = BootConfig {
+            quiet: false,
+            ..test_config()
+        };
+        let cmdline = boot.build_cmdline(&config);
+
+        assert!(!cmdline.contains("quiet"));
+        assert!(cmdline.ends_with("rootflags=subvol=@ rw"));
+    }
+
+    #[test]
+    fn test_build_cmdline_unencrypted_has_no_rd_luks() {
+        let boot = DracutEfistub::new();
+        let config = BootConfig {
+            luks_uuid: String::new(),
+            root_device: "UUID=1111-2222".into(),
+            ..test_config()
+        };
+        let cmdline = boot.build_cmdline(&config);
+
+        assert!(!cmdline.contains("rd.luks"));
+        assert_eq!(cmdline, "root=UUID=1111-2222 rootflags=subvol=@ rw quiet");
+    }
+
+    #[test]
+    fn test_build_cmdline_unlocks_raid_members() {
+        let boot = DracutEfistub::new();
+        let config = BootConfig {
+            extra_luks_uuids: vec!["ef01-2345".into()],
+            ..test_config()
+        };
+
+        assert!(boot.build_cmdline(&config).starts_with(
+            "rd.luks.uuid=abcd-1234-efgh-5678 rd.luks.uuid=ef01-2345 root=/dev/mapper/system "
+        ));
+    }
+
+    #[test]
+    fn test_build_cmdline_detached_header() {
+        let boot = DracutEfistub::new();
+        let config = BootConfig {
+            luks_header: Some(DetachedHeader {
+                data_device: "PARTUUID=0f3a-01".into(),
+                header: "/system.hdr:UUID=ABCD-1234".into(),
+            }),
+            subvol: String::new(),
+            ..test_config()
+        };
+
+        assert_eq!(
+            boot.build_cmdline(&config),
+            "rd.luks.uuid=abcd-1234-efgh-5678 \
+             rd.luks.data=abcd-1234-efgh-5678=PARTUUID=0f3a-01 \
+             rd.luks.options=abcd-1234-efgh-5678=header=/system.hdr:UUID=ABCD-1234,discard \
+             root=/dev/mapper/system rw quiet"
+        );
+    }
+
+    #[test]
+    fn test_build_cmdline_without_subvol() {
+        let boot = DracutEfistub::new();
+        let config = BootConfig {
+            subvol: String::new(),
+            ..test_config()
+        };
+        let cmdline = boot.build_cmdline(&config);
+
+        assert!(!cmdline.contains("rootflags"));
+        assert_eq!(
+            cmdline,
+            "rd.luks.uuid=abcd-1234-efgh-5678 root=/dev/mapper/system rw quiet"
+        );
+    }
+
     #[test]
     fn test_build_cmdline_custom_subvol() {
         let boot = DracutEfistub::new();
@@ -489,8 +938,7 @@ mod tests {
         let boot = DracutEfistub::new();
         let config = test_config();
 
-        // Simulate what build_rescue_image produces (without calling external tools)
-        let cmdline = format!("{} init=/bin/sh", boot.build_cmdline(&config));
+        let cmdline = boot.rescue_cmdline(&config);
 
         assert!(cmdline.contains("init=/bin/sh"));
         assert!(cmdline.contains("rd.luks.uuid=abcd-1234-efgh-5678"));
@@ -500,6 +948,7 @@ mod tests {
         let entry = BootEntry {
             label: "mkOS (rescue)".into(),
             loader_path: "/mkos-rescue.efi".into(),
+            cmdline: None,
         };
         assert_eq!(entry.label, "mkOS (rescue)");
         assert_eq!(entry.loader_path, "/mkos-rescue.efi");
@@ -520,33 +969,110 @@ mod tests {
         let entry = BootEntry {
             label: "mkOS (fallback)".into(),
             loader_path: "/mkos-fallback.efi".into(),
+            cmdline: None,
         };
         assert_eq!(entry.label, "mkOS (fallback)");
         assert_eq!(entry.loader_path, "/mkos-fallback.efi");
     }
 
+    #[test]
+    fn efistub_entry_passes_cmdline_and_initrd() {
+        let target = tempfile::tempdir().unwrap();
+        let boot = DracutEfistub::new().with_mode(BootMode::Efistub);
+        let config = test_config();
+        let cmdline = boot
+            .entry_cmdline(
+                target.path(),
+                &config,
+                "6.6.40-1-lts",
+                &boot.build_cmdline(&config),
+            )
+            .unwrap();
+        let entry = BootEntry {
+            label: "mkOS".into(),
+            loader_path: "/mkos-6.6.40-1-lts.efi".into(),
+            cmdline: Some(cmdline),
+        };
+
+        let args = efibootmgr_create_args(Path::new("/dev/nvme0n1"), 1, &entry);
+        let unicode = args.iter().position(|a| a == "--unicode").unwrap();
+        let passed = &args[unicode + 1];
+        assert!(passed.contains("rd.luks.uuid=abcd-1234-efgh-5678"));
+        assert!(passed.ends_with(" initrd=\\initramfs-6.6.40-1-lts.img"));
+    }
+
+    #[test]
+    fn uki_entry_passes_no_cmdline() {
+        let entry = BootEntry {
+            label: "mkOS".into(),
+            loader_path: "/mkos-6.6.40-1-lts.efi".into(),
+            cmdline: None,
+        };
+        let args = efibootmgr_create_args(Path::new("/dev/sda"), 1, &entry);
+        assert!(!args.contains(&"--unicode".to_string()));
+        assert_eq!(args.last().unwrap(), "/mkos-6.6.40-1-lts.efi");
+    }
+
+    #[test]
+    fn firmware_starts_only_the_boot_images() {
+        assert!(DracutEfistub::new().efi_binaries().is_empty());
+    }
+
     #[test]
     fn test_rescue_cmdline_appends_init() {
         let boot = DracutEfistub::new().with_extra_cmdline(vec!["debug".into()]);
         let config = test_config();
-        let rescue_cmdline = format!("{} init=/bin/sh", boot.build_cmdline(&config));
+        let rescue_cmdline = boot.rescue_cmdline(&config);
 
         assert!(rescue_cmdline.ends_with("debug init=/bin/sh"));
     }
 
+    #[test]
+    fn rescue_cmdline_drops_splash() {
+        let boot = DracutEfistub::new();
+        let config = BootConfig {
+            splash: true,
+            ..test_config()
+        };
+        assert!(boot.build_cmdline(&config).contains(" splash"));
+        assert!(!boot.rescue_cmdline(&config).contains("splash"));
+    }
+
+    #[test]
+    fn efistub_rescue_entry_passes_init_shell() {
+        let target = tempfile::tempdir().unwrap();
+        let modules = target.path().join("lib/modules/6.6.40-1-lts");
+        fs::create_dir_all(&modules).unwrap();
+        fs::write(modules.join("vmlinuz"), "kernel").unwrap();
+
+        let boot = DracutEfistub::new().with_mode(BootMode::Efistub);
+        let entry = boot
+            .build_rescue_image(target.path(), &test_config())
+            .unwrap();
+
+        assert_eq!(entry.loader_path, "/mkos-rescue.efi");
+        let cmdline = entry.cmdline.unwrap();
+        assert!(cmdline.contains(" init=/bin/sh"));
+        assert!(cmdline.ends_with("initrd=\\initramfs-6.6.40-1-lts.img"));
+        assert!(target.path().join("boot/mkos-rescue.efi").exists());
+    }
+
     #[test]
     fn test_all_three_entries_have_distinct_labels() {
         let main = BootEntry {
             label: "mkOS".into(),
             loader_path: "/mkos-6.1.0.efi".into(),
+            cmdline: None,
         };
         let fallback = BootEntry {
             label: "mkOS (fallback)".into(),
             loader_path: "/mkos-fallback.efi".into(),
+            cmdline: None,
         };
         let rescue = BootEntry {
             label: "mkOS (rescue)".into(),
             loader_path: "/mkos-rescue.efi".into(),
+            cmdline: None,
         };
 
         assert_ne!(main.label, fallback.label);
@@ -602,10 +1128,151 @@ mod tests {
         let conf_path = target.path().join("etc/dracut.conf.d/mkos.conf");
         let content = std::fs::read_to_string(&conf_path).unwrap();
 
-        assert!(content.contains("force_add_dracutmodules"));
-        assert!(content.contains("dm crypt btrfs"));
-        assert!(content.contains("dm_mod dm_crypt"));
-        assert!(content.contains("crypttab"));
+        assert!(content.contains("force_add_dracutmodules+=\" dm btrfs \""));
+        assert!(content.contains("force_add_dracutmodules+=\" crypt \""));
+        assert!(content.contains("add_drivers+=\" dm_crypt \""));
+        assert!(content.contains("install_items+=\" /etc/crypttab \""));
+        assert!(content.contains("filesystems+=\" btrfs ext4 xfs vfat \""));
+    }
+
+    #[test]
+    fn test_dracut_config_leaves_out_crypt_without_luks() {
+        let boot = DracutEfistub::new();
+        let target = tempfile::tempdir().unwrap();
+        let config = BootConfig {
+            luks_uuid: String::new(),
+            root_device: "UUID=ffff".into(),
+            ..test_config()
+        };
+
+        boot.generate_initramfs_config(target.path(), &config)
+            .unwrap();
+
+        let content =
+            std::fs::read_to_string(target.path().join("etc/dracut.conf.d/mkos.conf")).unwrap();
+        assert!(content.contains("force_add_dracutmodules+=\" dm btrfs \""));
+        assert!(!content.contains(" crypt "));
+        assert!(!content.contains("dm_crypt"));
+        assert!(!content.contains("/etc/crypttab"));
+    }
+
+    #[test]
+    fn test_build_cmdline_resume_only_when_hibernating() {
+        let boot = DracutEfistub::new();
+        assert!(!boot.build_cmdline(&test_config()).contains("resume"));
+
+        let config = BootConfig {
+            resume: Some(ResumeConfig {
+                device: "/dev/mapper/system".into(),
+                offset: Some(533760),
+            }),
+            ..test_config()
+        };
+        assert!(boot
+            .build_cmdline(&config)
+            .ends_with(" rw quiet resume=/dev/mapper/system resume_offset=533760"));
+    }
+
+    #[test]
+    fn early_microcode_only_off_when_an_image_is_prepended() {
+        let boot = DracutEfistub::new();
+        let target = tempfile::tempdir().unwrap();
+        let conf_path = target.path().join("etc/dracut.conf.d/mkos.conf");
+        let config = BootConfig {
+            microcode: Some("intel-ucode.img".into()),
+            ..test_config()
+        };
+
+        // Distros without the Arch layout have no vendor image in /boot
+        boot.generate_initramfs_config(target.path(), &config)
+            .unwrap();
+        assert!(fs::read_to_string(&conf_path)
+            .unwrap()
+            .contains("early_microcode=yes"));
+
+        fs::create_dir_all(target.path().join("boot")).unwrap();
+        fs::write(target.path().join("boot/intel-ucode.img"), "ucode").unwrap();
+        boot.generate_initramfs_config(target.path(), &config)
+            .unwrap();
+        assert!(fs::read_to_string(&conf_path)
+            .unwrap()
+            .contains("early_microcode=no"));
+    }
+
+    #[test]
+    fn test_splash_adds_plymouth_and_cmdline_args() {
+        let boot = DracutEfistub::new();
+        let target = tempfile::tempdir().unwrap();
+        let conf_path = target.path().join("etc/dracut.conf.d/mkos.conf");
+
+        boot.generate_initramfs_config(target.path(), &test_config())
+            .unwrap();
+        assert!(!std::fs::read_to_string(&conf_path)
+            .unwrap()
+            .contains("plymouth"));
+        assert!(!boot.build_cmdline(&test_config()).contains("splash"));
+
+        let config = BootConfig {
+            quiet: false,
+            splash: true,
+            ..test_config()
+        };
+        boot.generate_initramfs_config(target.path(), &config)
+            .unwrap();
+        let content = std::fs::read_to_string(&conf_path).unwrap();
+        assert!(content.contains("add_dracutmodules+=\" plymouth \""));
+        assert!(boot
+            .build_cmdline(&config)
+            .ends_with("rootflags=subvol=@ rw quiet splash"));
+    }
+
+    #[test]
+    fn test_dracut_config_early_kms_and_console_font() {
+        let boot = DracutEfistub::new();
+        let target = tempfile::tempdir().unwrap();
+        let conf_path = target.path().join("etc/dracut.conf.d/mkos.conf");
+
+        boot.generate_initramfs_config(target.path(), &test_config())
+            .unwrap();
+        let content = std::fs::read_to_string(&conf_path).unwrap();
+        assert!(!content.contains("i915"));
+        assert!(!content.contains("i18n"));
+
+        let config = BootConfig {
+            kms_drivers: vec!["i915".into(), "nouveau".into()],
+            console_font: true,
+            ..test_config()
+        };
+        boot.generate_initramfs_config(target.path(), &config)
+            .unwrap();
+        let content = std::fs::read_to_string(&conf_path).unwrap();
+        assert!(content.contains("add_drivers+=\" i915 nouveau \""));
+        assert!(content.contains("add_dracutmodules+=\" i18n \""));
+        assert!(content.contains("install_items+=\" /etc/vconsole.conf \""));
+    }
+
+    #[test]
+    fn test_dracut_config_resume_module() {
+        let boot = DracutEfistub::new();
+        let target = tempfile::tempdir().unwrap();
+        let conf_path = target.path().join("etc/dracut.conf.d/mkos.conf");
+
+        boot.generate_initramfs_config(target.path(), &test_config())
+            .unwrap();
+        let content = std::fs::read_to_string(&conf_path).unwrap();
+        assert!(!content.contains(" resume "));
+
+        let config = BootConfig {
+            resume: Some(ResumeConfig {
+                device: "/dev/mapper/system".into(),
+                offset: Some(533760),
+            }),
+            ..test_config()
+        };
+        boot.generate_initramfs_config(target.path(), &config)
+            .unwrap();
+        let content = std::fs::read_to_string(&conf_path).unwrap();
+        assert!(content.contains("add_dracutmodules+=\" resume \""));
     }
 
     #[test]
@@ -0,0 +1,101 @@
+use anyhow::{bail, Result};
+use std::str::FromStr;
+
+/// Kernel flavours a manifest can pick with `boot.kernel`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kernel {
+    Linux,
+    Lts,
+    Zen,
+    Hardened,
+}
+
+impl Kernel {
+    pub const ALL: [Kernel; 4] = [Kernel::Linux, Kernel::Lts, Kernel::Zen, Kernel::Hardened];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kernel::Linux => "linux",
+            Kernel::Lts => "linux-lts",
+            Kernel::Zen => "linux-zen",
+            Kernel::Hardened => "linux-hardened",
+        }
+    }
+
+    /// Generic package name in packages.toml
+    pub fn package(self) -> &'static str {
+        match self {
+            Kernel::Linux => "kernel-linux",
+            Kernel::Lts => "kernel-linux-lts",
+            Kernel::Zen => "kernel-linux-zen",
+            Kernel::Hardened => "kernel-linux-hardened",
+        }
+    }
+
+    /// Suffix the flavour puts on its release (`uname -r`), such as
+    /// `6.6.40-1-lts`; mainline releases carry none of these
+    fn release_suffix(self) -> Option<&'static str> {
+        match self {
+            Kernel::Linux => None,
+            Kernel::Lts => Some("-lts"),
+            Kernel::Zen => Some("-zen"),
+            Kernel::Hardened => Some("-hardened"),
+        }
+    }
+
+    /// Flavour the kernel release `kver` belongs to
+    pub fn of_release(kver: &str) -> Kernel {
+        Kernel::ALL
+            .into_iter()
+            .find(|k| k.matches_release(kver))
+            .unwrap_or(Kernel::Linux)
+    }
+
+    /// Whether the kernel release `kver` belongs to this flavour
+    pub fn matches_release(self, kver: &str) -> bool {
+        match self.release_suffix() {
+            Some(suffix) => kver.ends_with(suffix),
+            None => !Kernel::ALL
+                .iter()
+                .filter_map(|k| k.release_suffix())
+                .any(|suffix| kver.ends_with(suffix)),
+        }
+    }
+}
+
+impl FromStr for Kernel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match Kernel::ALL.into_iter().find(|k| k.name() == s) {
+            Some(kernel) => Ok(kernel),
+            None => bail!(
+                "Unknown kernel: {}. Supported: linux, linux-lts, linux-zen, linux-hardened",
+                s
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_names_round_trip() {
+        for kernel in Kernel::ALL {
+            assert_eq!(kernel.name().parse::<Kernel>().unwrap(), kernel);
+        }
+        assert!("linux-rt".parse::<Kernel>().is_err());
+    }
+
+    #[test]
+    fn releases_match_their_flavour() {
+        assert!(Kernel::Lts.matches_release("6.6.40-1-lts"));
+        assert!(Kernel::Zen.matches_release("6.10.2-zen1-1-zen"));
+        assert!(Kernel::Hardened.matches_release("6.10.2-hardened1-1-hardened"));
+        assert!(Kernel::Linux.matches_release("6.10.2-arch1-1"));
+        assert!(!Kernel::Linux.matches_release("6.6.40-1-lts"));
+        assert!(!Kernel::Lts.matches_release("6.10.2-arch1-1"));
+    }
+}
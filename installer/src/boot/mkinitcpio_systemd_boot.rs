@@ -0,0 +1,567 @@
+use super::{BootConfig, BootEntry, BootSystem, Kernel, ResumeConfig};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::cmd;
+use tracing::instrument;
+
+/// Loader entry names under /boot/loader/entries
+const MAIN_ENTRY: &str = "mkos";
+const RESCUE_ENTRY: &str = "mkos-rescue";
+const FALLBACK_ENTRY: &str = "mkos-fallback";
+
+/// mkinitcpio + systemd-boot boot system implementation
+///
+/// Uses mkinitcpio with the `encrypt` hook to build the initramfs and
+/// systemd-boot loader entries that reference the kernel on the ESP.
+#[derive(Debug, Clone, Default)]
+pub struct MkinitcpioSystemdBoot {
+    /// Extra kernel command line arguments
+    pub extra_cmdline: Vec<String>,
+    /// Kernel package the loader entries boot; `linux` when None
+    pub kernel: Option<Kernel>,
+}
+
+impl MkinitcpioSystemdBoot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_extra_cmdline(mut self, args: Vec<String>) -> Self {
+        self.extra_cmdline = args;
+        self
+    }
+
+    pub fn with_kernel(mut self, kernel: Option<Kernel>) -> Self {
+        self.kernel = kernel;
+        self
+    }
+
+    /// Package name mkinitcpio and the kernel install name files after
+    fn kernel_name(&self) -> &'static str {
+        self.kernel.map_or("linux", Kernel::name)
+    }
+
+    /// Kernel the installed main loader entry boots, None without one
+    pub fn installed_kernel(target: &Path) -> Option<Kernel> {
+        let entry = fs::read_to_string(Self::entry_path(target, MAIN_ENTRY)).ok()?;
+        Self::entry_kernel(&entry)
+    }
+
+    /// Kernel named by a loader entry's `linux /vmlinuz-<kernel>` line
+    fn entry_kernel(entry: &str) -> Option<Kernel> {
+        entry
+            .lines()
+            .find_map(|line| line.strip_prefix("linux"))
+            .and_then(|path| path.trim().strip_prefix("/vmlinuz-"))
+            .and_then(|name| name.parse().ok())
+    }
+
+    /// Whether the loader entry `name` has been written
+    pub fn has_entry(target: &Path, name: &str) -> bool {
+        Self::entry_path(target, name).exists()
+    }
+
+    fn entry_path(target: &Path, name: &str) -> std::path::PathBuf {
+        target
+            .join("boot/loader/entries")
+            .join(format!("{}.conf", name))
+    }
+
+    /// Rebuild the initramfs and rewrite the loader entries of an installed
+    /// system, keeping the rescue and fallback entries it was installed with
+    pub fn rebuild_entries(
+        &self,
+        target: &Path,
+        config: &BootConfig,
+        fallback_subvol: &str,
+    ) -> Result<Vec<BootEntry>> {
+        self.build_initramfs(target)?;
+
+        let mut entries = vec![self.write_main_entry(target, config)?];
+        if Self::has_entry(target, RESCUE_ENTRY) {
+            entries.push(self.build_rescue_image(target, config)?);
+        }
+        if Self::has_entry(target, FALLBACK_ENTRY) {
+            entries.push(self.build_fallback_image(target, config, fallback_subvol)?);
+        }
+        Ok(entries)
+    }
+
+    fn write_main_entry(&self, target: &Path, config: &BootConfig) -> Result<BootEntry> {
+        self.write_entry(target, MAIN_ENTRY, "mkOS", &self.build_cmdline(config))
+    }
+
+    /// Build the kernel command line for the `encrypt` hook
+    fn build_cmdline(&self, config: &BootConfig) -> String {
+        let mapper = config
+            .root_device
+            .rsplit('/')
+            .next()
+            .unwrap_or(&config.root_device);
+
+        let mut cmdline = match (&config.luks_header, config.luks_uuid.is_empty()) {
+            (_, true) => format!("root={}", config.root_device),
+            // The encrypt hook reads the header off the media it names
+            (Some(header), false) => format!(
+                "cryptdevice={}:{}:header {} root={}",
+                header.data_device,
+                mapper,
+                header.cryptheader_arg(),
+                config.root_device
+            ),
+            (None, false) => format!(
+                "cryptdevice=UUID={}:{} root={}",
+                config.luks_uuid, mapper, config.root_device
+            ),
+        };
+
+        if !config.subvol.is_empty() {
+            cmdline.push_str(&format!(" rootflags=subvol={}", config.subvol));
+        }
+
+        cmdline.push_str(" rw");
+        // Kernel messages would draw over the splash
+        if config.quiet || config.splash {
+            cmdline.push_str(" quiet");
+        }
+        if config.splash {
+            cmdline.push_str(" splash");
+        }
+
+        for arg in config.resume.iter().flat_map(ResumeConfig::cmdline_args) {
+            cmdline.push(' ');
+            cmdline.push_str(&arg);
+        }
+
+        for arg in &self.extra_cmdline {
+            cmdline.push(' ');
+            cmdline.push_str(arg);
+        }
+
+        cmdline
+    }
+
+    /// Render a systemd-boot loader entry
+    fn loader_entry(&self, title: &str, options: &str) -> String {
+        format!(
+            "title   {}\n\
+             linux   /vmlinuz-{kernel}\n\
+             initrd  /initramfs-{kernel}.img\n\
+             options {}\n",
+            title,
+            options,
+            kernel = self.kernel_name()
+        )
+    }
+
+    /// Write a loader entry to /boot/loader/entries/<name>.conf
+    fn write_entry(
+        &self,
+        target: &Path,
+        name: &str,
+        title: &str,
+        options: &str,
+    ) -> Result<BootEntry> {
+        let entry_path = Self::entry_path(target, name);
+        if let Some(entries_dir) = entry_path.parent() {
+            fs::create_dir_all(entries_dir)?;
+        }
+
+        fs::write(&entry_path, self.loader_entry(title, options))
+            .with_context(|| format!("Failed to write loader entry {}", entry_path.display()))?;
+
+        println!("  Loader entry: /boot/loader/entries/{}.conf", name);
+
+        Ok(BootEntry {
+            label: title.into(),
+            loader_path: format!("/vmlinuz-{}", self.kernel_name()),
+            // The loader entry holds the command line
+            cmdline: None,
+        })
+    }
+}
+
+impl BootSystem for MkinitcpioSystemdBoot {
+    fn name(&self) -> &str {
+        "mkinitcpio-systemd-boot"
+    }
+
+    fn generate_initramfs_config(&self, target: &Path, config: &BootConfig) -> Result<()> {
+        // resume must run after encrypt unlocks the swap and before root is mounted
+        let resume = if config.resume.is_some() {
+            " resume"
+        } else {
+            ""
+        };
+        let kms: String = config
+            .kms_drivers
+            .iter()
+            .map(|driver| format!(" {}", driver))
+            .collect();
+        // plymouth must start before encrypt to ask for the passphrase
+        let splash = if config.splash { " plymouth" } else { "" };
+
+        let mkinitcpio_config = format!(
+            r#"# mkOS mkinitcpio configuration
+
+# Critical drivers - always include for LUKS support - plus the GPU's DRM
+# driver for early KMS
+MODULES=(dm_mod dm_crypt{})
+
+BINARIES=()
+
+FILES=(/etc/crypttab)
+
+# encrypt must come before filesystems; btrfs adds multi-device support
+HOOKS=(base udev autodetect microcode modconf kms keyboard keymap consolefont block{} encrypt{} btrfs filesystems fsck)
+
+COMPRESSION="zstd"
+"#,
+            kms, splash, resume
+        );
+
+        let etc_dir = target.join("etc");
+        fs::create_dir_all(&etc_dir)?;
+        fs::write(etc_dir.join("mkinitcpio.conf"), mkinitcpio_config)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    fn build_initramfs(&self, target: &Path) -> Result<()> {
+        let target_str = target.to_string_lossy().to_string();
+
+        println!("  Generating initramfs with mkinitcpio...");
+        cmd::run("chroot", [&target_str, "mkinitcpio", "-P"])
+    }
+
+    #[instrument(skip(self))]
+    fn build_boot_image(&self, target: &Path, config: &BootConfig) -> Result<BootEntry> {
+        let target_str = target.to_string_lossy().to_string();
+
+        println!("Installing systemd-boot...");
+        cmd::run(
+            "chroot",
+            [&target_str, "bootctl", "install", "--esp-path=/boot"],
+        )?;
+
+        let loader_dir = target.join("boot/loader");
+        fs::create_dir_all(&loader_dir)?;
+        fs::write(
+            loader_dir.join("loader.conf"),
+            "default mkos.conf\ntimeout 3\n",
+        )?;
+
+        let entry = self.write_main_entry(target, config)?;
+
+        println!("✓ systemd-boot installed");
+
+        Ok(entry)
+    }
+
+    #[instrument(skip(self))]
+    fn build_rescue_image(&self, target: &Path, config: &BootConfig) -> Result<BootEntry> {
+        // A splash would hide the shell
+        let rescue_config = BootConfig {
+            splash: false,
+            ..config.clone()
+        };
+        let cmdline = format!("{} init=/bin/sh", self.build_cmdline(&rescue_config));
+        self.write_entry(target, RESCUE_ENTRY, "mkOS (rescue)", &cmdline)
+    }
+
+    fn build_fallback_image(
+        &self,
+        target: &Path,
+        config: &BootConfig,
+        subvol: &str,
+    ) -> Result<BootEntry> {
+        let fallback_config = BootConfig {
+            subvol: subvol.into(),
+            ..config.clone()
+        };
+        let cmdline = self.build_cmdline(&fallback_config);
+        self.write_entry(target, FALLBACK_ENTRY, "mkOS (fallback)", &cmdline)
+    }
+
+    fn efi_binaries(&self) -> &'static [&'static str] {
+        // bootctl install places the loader and its removable-media copy
+        &["EFI/systemd/systemd-bootx64.efi", "EFI/BOOT/BOOTX64.EFI"]
+    }
+
+    fn create_fallback_scripts(&self, _target: &Path, _entry: &BootEntry) -> Result<()> {
+        // bootctl install already places the removable-media fallback loader
+        // at /EFI/BOOT/BOOTX64.EFI
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    fn create_boot_entry(
+        &self,
+        _device: &Path,
+        _efi_part_num: u32,
+        entry: &BootEntry,
+    ) -> Result<()> {
+        // systemd-boot owns the single NVRAM entry (registered by bootctl
+        // install); individual entries live in /boot/loader/entries
+        println!(
+            "✓ Boot entry '{}' available in systemd-boot menu",
+            entry.label
+        );
+        Ok(())
+    }
+
+    fn nvram_label(&self, _entry: &BootEntry) -> String {
+        "Linux Boot Manager".into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypt::DetachedHeader;
+
+    fn test_config() -> BootConfig {
+        BootConfig {
+            luks_uuid: "abcd-1234-efgh-5678".into(),
+            extra_luks_uuids: Vec::new(),
+            root_device: "/dev/mapper/system".into(),
+            subvol: "@".into(),
+            quiet: true,
+            splash: false,
+            kms_drivers: Vec::new(),
+            console_font: false,
+            microcode: None,
+            resume: None,
+            luks_header: None,
+        }
+    }
+
+    #[test]
+    fn test_boot_system_name() {
+        let boot = MkinitcpioSystemdBoot::new();
+        assert_eq!(boot.name(), "mkinitcpio-systemd-boot");
+    }
+
+    #[test]
+    fn test_build_cmdline_uses_cryptdevice() {
+        let boot = MkinitcpioSystemdBoot::new();
+        let cmdline = boot.build_cmdline(&test_config());
+
+        assert_eq!(
+            cmdline,
+            "cryptdevice=UUID=abcd-1234-efgh-5678:system root=/dev/mapper/system \
+             rootflags=subvol=@ rw quiet"
+        );
+    }
+
+    #[test]
+    fn test_build_cmdline_detached_header() {
+        let boot = MkinitcpioSystemdBoot::new();
+        let config = BootConfig {
+            luks_header: Some(DetachedHeader {
+                data_device: "PARTUUID=0f3a-01".into(),
+                header: "/system.hdr:UUID=ABCD-1234".into(),
+            }),
+            subvol: String::new(),
+            ..test_config()
+        };
+
+        assert_eq!(
+            boot.build_cmdline(&config),
+            "cryptdevice=PARTUUID=0f3a-01:system:header \
+             cryptheader=UUID=ABCD-1234:auto:/system.hdr root=/dev/mapper/system rw quiet"
+        );
+    }
+
+    #[test]
+    fn test_build_cmdline_unencrypted_has_no_cryptdevice() {
+        let boot = MkinitcpioSystemdBoot::new();
+        let config = BootConfig {
+            luks_uuid: String::new(),
+            root_device: "UUID=1111-2222".into(),
+            ..test_config()
+        };
+
+        assert_eq!(
+            boot.build_cmdline(&config),
+            "root=UUID=1111-2222 rootflags=subvol=@ rw quiet"
+        );
+    }
+
+    #[test]
+    fn test_build_cmdline_without_subvol_with_extra_args() {
+        let boot = MkinitcpioSystemdBoot::new().with_extra_cmdline(vec!["debug".into()]);
+        let config = BootConfig {
+            subvol: String::new(),
+            ..test_config()
+        };
+        let cmdline = boot.build_cmdline(&config);
+
+        assert!(!cmdline.contains("rootflags"));
+        assert!(cmdline.ends_with("rw quiet debug"));
+    }
+
+    #[test]
+    fn test_loader_entry_format() {
+        let entry = MkinitcpioSystemdBoot::new().loader_entry("mkOS", "root=/dev/mapper/system rw");
+
+        assert!(entry.starts_with("title   mkOS\n"));
+        assert!(entry.contains("linux   /vmlinuz-linux\n"));
+        assert!(entry.contains("initrd  /initramfs-linux.img\n"));
+        assert!(entry.ends_with("options root=/dev/mapper/system rw\n"));
+    }
+
+    #[test]
+    fn test_loader_entry_boots_the_chosen_kernel() {
+        let boot = MkinitcpioSystemdBoot::new().with_kernel(Some(Kernel::Lts));
+        let entry = boot.loader_entry("mkOS", "rw");
+
+        assert!(entry.contains("linux   /vmlinuz-linux-lts\n"));
+        assert!(entry.contains("initrd  /initramfs-linux-lts.img\n"));
+        assert_eq!(
+            MkinitcpioSystemdBoot::entry_kernel(&entry),
+            Some(Kernel::Lts)
+        );
+    }
+
+    #[test]
+    fn installed_kernel_is_read_from_the_main_entry() {
+        let target = tempfile::tempdir().unwrap();
+        assert_eq!(MkinitcpioSystemdBoot::installed_kernel(target.path()), None);
+
+        let boot = MkinitcpioSystemdBoot::new().with_kernel(Some(Kernel::Zen));
+        boot.write_main_entry(target.path(), &test_config())
+            .unwrap();
+        assert_eq!(
+            MkinitcpioSystemdBoot::installed_kernel(target.path()),
+            Some(Kernel::Zen)
+        );
+        assert!(MkinitcpioSystemdBoot::has_entry(target.path(), MAIN_ENTRY));
+        assert!(!MkinitcpioSystemdBoot::has_entry(
+            target.path(),
+            RESCUE_ENTRY
+        ));
+    }
+
+    #[test]
+    fn systemd_boot_binaries_are_signed_too() {
+        let boot = MkinitcpioSystemdBoot::new();
+        assert_eq!(
+            boot.efi_binaries(),
+            ["EFI/systemd/systemd-bootx64.efi", "EFI/BOOT/BOOTX64.EFI"]
+        );
+    }
+
+    #[test]
+    fn test_rescue_and_fallback_entries_written() {
+        let boot = MkinitcpioSystemdBoot::new();
+        let target = tempfile::tempdir().unwrap();
+        let config = test_config();
+
+        let rescue = boot.build_rescue_image(target.path(), &config).unwrap();
+        let fallback = boot
+            .build_fallback_image(target.path(), &config, "@snapshots/install")
+            .unwrap();
+
+        assert_eq!(rescue.label, "mkOS (rescue)");
+        assert_eq!(fallback.label, "mkOS (fallback)");
+
+        let entries = target.path().join("boot/loader/entries");
+        let rescue_conf = fs::read_to_string(entries.join("mkos-rescue.conf")).unwrap();
+        let fallback_conf = fs::read_to_string(entries.join("mkos-fallback.conf")).unwrap();
+
+        assert!(rescue_conf.contains("init=/bin/sh"));
+        assert!(fallback_conf.contains("rootflags=subvol=@snapshots/install"));
+    }
+
+    #[test]
+    fn test_mkinitcpio_config_hooks() {
+        let boot = MkinitcpioSystemdBoot::new();
+        let target = tempfile::tempdir().unwrap();
+
+        boot.generate_initramfs_config(target.path(), &test_config())
+            .unwrap();
+
+        let content = fs::read_to_string(target.path().join("etc/mkinitcpio.conf")).unwrap();
+        let hooks = content.lines().find(|l| l.starts_with("HOOKS=")).unwrap();
+
+        let encrypt = hooks.find(" encrypt ").unwrap();
+        let filesystems = hooks.find(" filesystems ").unwrap();
+        assert!(encrypt < filesystems);
+        assert!(hooks.contains(" btrfs "));
+        assert!(content.contains("MODULES=(dm_mod dm_crypt)"));
+    }
+
+    #[test]
+    fn test_resume_partition_has_no_offset() {
+        let boot = MkinitcpioSystemdBoot::new();
+        let config = BootConfig {
+            resume: Some(ResumeConfig {
+                device: "UUID=1111-2222".into(),
+                offset: None,
+            }),
+            ..test_config()
+        };
+
+        let cmdline = boot.build_cmdline(&config);
+        assert!(cmdline.ends_with(" rw quiet resume=UUID=1111-2222"));
+        assert!(!cmdline.contains("resume_offset"));
+    }
+
+    #[test]
+    fn test_mkinitcpio_resume_hook_after_encrypt() {
+        let boot = MkinitcpioSystemdBoot::new();
+        let target = tempfile::tempdir().unwrap();
+        let config = BootConfig {
+            resume: Some(ResumeConfig {
+                device: "/dev/mapper/system".into(),
+                offset: Some(533760),
+            }),
+            ..test_config()
+        };
+
+        boot.generate_initramfs_config(target.path(), &config)
+            .unwrap();
+
+        let content = fs::read_to_string(target.path().join("etc/mkinitcpio.conf")).unwrap();
+        let hooks = content.lines().find(|l| l.starts_with("HOOKS=")).unwrap();
+        assert!(hooks.contains(" encrypt resume btrfs filesystems "));
+    }
+
+    #[test]
+    fn test_mkinitcpio_splash_starts_plymouth_before_encrypt() {
+        let boot = MkinitcpioSystemdBoot::new();
+        let target = tempfile::tempdir().unwrap();
+        let config = BootConfig {
+            splash: true,
+            ..test_config()
+        };
+
+        boot.generate_initramfs_config(target.path(), &config)
+            .unwrap();
+
+        let content = fs::read_to_string(target.path().join("etc/mkinitcpio.conf")).unwrap();
+        let hooks = content.lines().find(|l| l.starts_with("HOOKS=")).unwrap();
+        assert!(hooks.contains(" block plymouth encrypt "));
+        assert!(boot.build_cmdline(&config).ends_with(" rw quiet splash"));
+    }
+
+    #[test]
+    fn test_mkinitcpio_loads_kms_drivers_early() {
+        let boot = MkinitcpioSystemdBoot::new();
+        let target = tempfile::tempdir().unwrap();
+        let config = BootConfig {
+            kms_drivers: vec!["amdgpu".into()],
+            ..test_config()
+        };
+
+        boot.generate_initramfs_config(target.path(), &config)
+            .unwrap();
+
+        let content = fs::read_to_string(target.path().join("etc/mkinitcpio.conf")).unwrap();
+        assert!(content.contains("MODULES=(dm_mod dm_crypt amdgpu)\n"));
+    }
+}
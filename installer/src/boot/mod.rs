@@ -1,19 +1,176 @@
 mod dracut_efistub;
+mod kernel;
+mod mkinitcpio_systemd_boot;
+mod nvram;
 
 pub use dracut_efistub::DracutEfistub;
+pub use kernel::Kernel;
+pub use mkinitcpio_systemd_boot::MkinitcpioSystemdBoot;
+pub use nvram::{entries_for, nvram_has_entry, order_first, parse_efibootmgr, NvramEntry};
 
-use anyhow::Result;
-use std::path::Path;
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::crypt::DetachedHeader;
+use crate::distro::MKINITCPIO_BOOT_PACKAGES;
+
+/// Where an install records its boot system, for mkos-rebuild-uki
+pub const BOOT_SYSTEM_PATH: &str = "etc/mkos/boot-system";
+
+/// Available boot system backends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BootKind {
+    #[default]
+    DracutEfistub,
+    MkinitcpioSystemdBoot,
+}
+
+impl BootKind {
+    pub fn create(self) -> Box<dyn BootSystem> {
+        match self {
+            BootKind::DracutEfistub => Box::new(DracutEfistub::new()),
+            BootKind::MkinitcpioSystemdBoot => Box::new(MkinitcpioSystemdBoot::new()),
+        }
+    }
+
+    /// Create the backend with extra kernel command line arguments, booting
+    /// the installed releases of `kernels` (every installed kernel when empty)
+    pub fn create_with_cmdline(
+        self,
+        extra_cmdline: Vec<String>,
+        kernels: Vec<Kernel>,
+        mode: BootMode,
+    ) -> Box<dyn BootSystem> {
+        match self {
+            BootKind::DracutEfistub => Box::new(
+                DracutEfistub::new()
+                    .with_extra_cmdline(extra_cmdline)
+                    .with_kernels(kernels)
+                    .with_mode(mode),
+            ),
+            BootKind::MkinitcpioSystemdBoot => Box::new(
+                MkinitcpioSystemdBoot::new()
+                    .with_extra_cmdline(extra_cmdline)
+                    .with_kernel(kernels.first().copied()),
+            ),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BootKind::DracutEfistub => "dracut-efistub",
+            BootKind::MkinitcpioSystemdBoot => "mkinitcpio-systemd-boot",
+        }
+    }
+
+    /// Generic packages the backend needs besides dracut and efibootmgr,
+    /// which every distro's bootstrap installs
+    pub fn packages(self) -> &'static [&'static str] {
+        match self {
+            BootKind::DracutEfistub => &[],
+            BootKind::MkinitcpioSystemdBoot => MKINITCPIO_BOOT_PACKAGES,
+        }
+    }
+}
+
+impl FromStr for BootKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dracut-efistub" | "dracut" => Ok(BootKind::DracutEfistub),
+            "mkinitcpio-systemd-boot" | "systemd-boot" => Ok(BootKind::MkinitcpioSystemdBoot),
+            other => bail!(
+                "Unknown boot system: {}. Supported: dracut-efistub, mkinitcpio-systemd-boot",
+                other
+            ),
+        }
+    }
+}
+
+/// Where an install records its boot mode, for mkos-rebuild-uki
+pub const BOOT_MODE_PATH: &str = "etc/mkos/boot-mode";
+
+/// How dracut-efistub makes a kernel bootable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BootMode {
+    /// A UKI embedding the kernel, initramfs and command line
+    #[default]
+    Uki,
+    /// The kernel on its own, with the command line and `initrd=` arguments
+    /// in the NVRAM entry
+    Efistub,
+}
+
+impl BootMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            BootMode::Uki => "uki",
+            BootMode::Efistub => "efistub",
+        }
+    }
+}
+
+impl FromStr for BootMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "uki" => Ok(BootMode::Uki),
+            "efistub" => Ok(BootMode::Efistub),
+            other => bail!("Unknown boot mode: {}. Supported: uki, efistub", other),
+        }
+    }
+}
 
 /// Boot configuration parameters
 #[derive(Debug, Clone)]
 pub struct BootConfig {
-    /// LUKS UUID for the encrypted partition
+    /// LUKS UUID for the encrypted partition (empty for an unencrypted root)
     pub luks_uuid: String,
+    /// LUKS UUIDs of the other disks a RAID root spans, unlocked alongside
+    /// `luks_uuid`
+    pub extra_luks_uuids: Vec<String>,
     /// Root device path (e.g., /dev/mapper/system)
     pub root_device: String,
-    /// Root subvolume (for btrfs)
+    /// Root subvolume (for btrfs; empty for filesystems without subvolumes)
     pub subvol: String,
+    /// Pass `quiet` on the kernel command line
+    pub quiet: bool,
+    /// Show the plymouth splash, which also asks for the LUKS passphrase
+    pub splash: bool,
+    /// DRM drivers loaded by the initramfs, so the console is at native
+    /// resolution by the passphrase prompt
+    pub kms_drivers: Vec<String>,
+    /// Set the console font from /etc/vconsole.conf in the initramfs
+    pub console_font: bool,
+    /// Microcode image in /boot to load before the initramfs (e.g., intel-ucode.img)
+    pub microcode: Option<String>,
+    /// Hibernation image location, None when hibernation is disabled
+    pub resume: Option<ResumeConfig>,
+    /// Header location when it isn't on the LUKS partition
+    pub luks_header: Option<DetachedHeader>,
+}
+
+/// Where the kernel looks for a hibernation image
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResumeConfig {
+    /// Device holding the swap space (e.g., /dev/mapper/system)
+    pub device: String,
+    /// Page offset of a swapfile within the device; None for a swap partition
+    pub offset: Option<u64>,
+}
+
+impl ResumeConfig {
+    /// `resume=` and `resume_offset=` kernel arguments
+    pub fn cmdline_args(&self) -> Vec<String> {
+        let mut args = vec![format!("resume={}", self.device)];
+        if let Some(offset) = self.offset {
+            args.push(format!("resume_offset={}", offset));
+        }
+        args
+    }
 }
 
 /// Boot entry information
@@ -23,6 +180,8 @@ pub struct BootEntry {
     pub label: String,
     /// Path to the bootable image relative to ESP
     pub loader_path: String,
+    /// Command line the firmware passes to an image that doesn't embed one
+    pub cmdline: Option<String>,
 }
 
 /// Trait for boot system implementations (dracut+EFISTUB, mkinitcpio+systemd-boot, etc.)
@@ -40,12 +199,41 @@ pub trait BootSystem: Send + Sync {
     /// Returns the boot entry information
     fn build_boot_image(&self, target: &Path, config: &BootConfig) -> Result<BootEntry>;
 
+    /// Build a boot image for each installed kernel, the default one first
+    ///
+    /// Backends that boot a single kernel build just the main image.
+    fn build_boot_images(&self, target: &Path, config: &BootConfig) -> Result<Vec<BootEntry>> {
+        Ok(vec![self.build_boot_image(target, config)?])
+    }
+
+    /// Build a rescue boot image that boots with init=/bin/sh
+    fn build_rescue_image(&self, target: &Path, config: &BootConfig) -> Result<BootEntry>;
+
+    /// Build a fallback boot image that boots into a specific subvolume
+    fn build_fallback_image(
+        &self,
+        target: &Path,
+        config: &BootConfig,
+        subvol: &str,
+    ) -> Result<BootEntry>;
+
     /// Create fallback boot scripts (e.g., startup.nsh)
     fn create_fallback_scripts(&self, target: &Path, entry: &BootEntry) -> Result<()>;
 
+    /// EFI binaries the firmware starts besides the boot images, relative
+    /// to the ESP; Secure Boot refuses them unless they're signed too
+    fn efi_binaries(&self) -> &'static [&'static str] {
+        &[]
+    }
+
     /// Create EFI boot entry in NVRAM
     fn create_boot_entry(&self, device: &Path, efi_part_num: u32, entry: &BootEntry) -> Result<()>;
 
+    /// Label of the NVRAM entry the firmware starts `entry` through
+    fn nvram_label(&self, entry: &BootEntry) -> String {
+        entry.label.clone()
+    }
+
     /// Full boot setup: config -> build -> create entry
     fn setup_boot(
         &self,
@@ -63,15 +251,234 @@ pub trait BootSystem: Send + Sync {
     }
 }
 
-/// Get kernel version from /lib/modules
-pub fn get_kernel_version(target: &Path) -> Result<String> {
-    use anyhow::Context;
+/// Validate extra kernel command line arguments.
+///
+/// Each entry must be a single argument; whitespace would silently split it.
+pub fn validate_cmdline(args: &[String]) -> Result<()> {
+    for arg in args {
+        if arg.is_empty() {
+            bail!("Empty kernel command line argument");
+        }
+        if arg.chars().any(char::is_whitespace) {
+            bail!(
+                "Kernel command line argument contains whitespace: {:?} (use one entry per argument)",
+                arg
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Get the newest kernel version from /lib/modules, only looking at the
+/// releases of `kernels` when any are given
+pub fn get_kernel_version(target: &Path, kernels: &[Kernel]) -> Result<String> {
+    Ok(kernel_versions(target, kernels)?.remove(0))
+}
 
+/// Kernel versions in /lib/modules, newest first, only looking at the
+/// releases of `kernels` when any are given
+pub fn kernel_versions(target: &Path, kernels: &[Kernel]) -> Result<Vec<String>> {
     let modules_dir = target.join("lib/modules");
-    std::fs::read_dir(&modules_dir)?
+    let mut versions: Vec<String> = std::fs::read_dir(&modules_dir)
+        .with_context(|| format!("Failed to read {}", modules_dir.display()))?
         .filter_map(|e| e.ok())
         .filter(|e| e.path().is_dir())
         .map(|e| e.file_name().to_string_lossy().to_string())
-        .next()
-        .context("No kernel found in /lib/modules")
+        .filter(|kver| kernels.is_empty() || kernels.iter().any(|k| k.matches_release(kver)))
+        .collect();
+    versions.sort_by_key(|kver| std::cmp::Reverse(version_key(kver)));
+
+    // Module directories left behind by removed kernels have no image; the
+    // image /boot names after a flavour is its newest release's
+    let mut seen = Vec::new();
+    versions.retain(|kver| {
+        let flavour = Kernel::of_release(kver);
+        let newest = !seen.contains(&flavour);
+        seen.push(flavour);
+        release_image(target, kver).is_some() || (newest && flavour_image(target, kver).exists())
+    });
+
+    if versions.is_empty() {
+        match kernels {
+            [] => bail!("No kernel found in /lib/modules"),
+            _ => bail!(
+                "No {} kernel found in /lib/modules",
+                kernels
+                    .iter()
+                    .map(|k| k.name())
+                    .collect::<Vec<_>>()
+                    .join(" or ")
+            ),
+        }
+    }
+
+    Ok(versions)
+}
+
+/// Kernel image installed under the release `kver`: alongside its modules
+/// (Arch-style packages) or in /boot
+pub(crate) fn release_image(target: &Path, kver: &str) -> Option<PathBuf> {
+    [
+        target.join("lib/modules").join(kver).join("vmlinuz"),
+        target.join(format!("boot/vmlinuz-{}", kver)),
+    ]
+    .into_iter()
+    .find(|p| p.exists())
+}
+
+/// Kernel image /boot names after `kver`'s flavour package, where mkOS has
+/// always looked
+pub(crate) fn flavour_image(target: &Path, kver: &str) -> PathBuf {
+    target.join(format!("boot/vmlinuz-{}", Kernel::of_release(kver).name()))
+}
+
+/// Numeric components of a kernel version for ordering (like `sort -V`)
+fn version_key(kver: &str) -> Vec<u64> {
+    kver.split(|c: char| !c.is_ascii_digit())
+        .filter_map(|part| part.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_kind_from_str() {
+        assert_eq!(
+            "dracut-efistub".parse::<BootKind>().unwrap(),
+            BootKind::DracutEfistub
+        );
+        assert_eq!(
+            "systemd-boot".parse::<BootKind>().unwrap(),
+            BootKind::MkinitcpioSystemdBoot
+        );
+        assert!("grub".parse::<BootKind>().is_err());
+    }
+
+    #[test]
+    fn validate_cmdline_accepts_single_args() {
+        let args = vec!["nvidia-drm.modeset=1".into(), "mitigations=off".into()];
+        assert!(validate_cmdline(&args).is_ok());
+    }
+
+    #[test]
+    fn validate_cmdline_rejects_whitespace() {
+        assert!(validate_cmdline(&["quiet splash".into()]).is_err());
+        assert!(validate_cmdline(&["".into()]).is_err());
+    }
+
+    /// Module directories with their kernel image beside them
+    fn install_kernels(target: &Path, kvers: &[&str]) {
+        for kver in kvers {
+            let modules = target.join("lib/modules").join(kver);
+            std::fs::create_dir_all(&modules).unwrap();
+            std::fs::write(modules.join("vmlinuz"), "kernel").unwrap();
+        }
+    }
+
+    #[test]
+    fn leftover_module_directories_are_skipped() {
+        let target = tempfile::tempdir().unwrap();
+        install_kernels(target.path(), &["6.10.2-arch1-1"]);
+        std::fs::create_dir_all(target.path().join("lib/modules/6.9.1-arch1-1")).unwrap();
+        std::fs::create_dir_all(target.path().join("lib/modules/6.6.40-1-lts")).unwrap();
+        assert_eq!(
+            kernel_versions(target.path(), &[]).unwrap(),
+            ["6.10.2-arch1-1"]
+        );
+
+        // /boot names the image after the flavour, for its newest release
+        let modules = target.path().join("lib/modules");
+        std::fs::remove_file(modules.join("6.10.2-arch1-1/vmlinuz")).unwrap();
+        std::fs::create_dir_all(target.path().join("boot")).unwrap();
+        std::fs::write(target.path().join("boot/vmlinuz-linux-lts"), "kernel").unwrap();
+        assert_eq!(
+            kernel_versions(target.path(), &[]).unwrap(),
+            ["6.6.40-1-lts"]
+        );
+        std::fs::write(target.path().join("boot/vmlinuz-linux"), "kernel").unwrap();
+        assert_eq!(
+            kernel_versions(target.path(), &[]).unwrap(),
+            ["6.10.2-arch1-1", "6.6.40-1-lts"]
+        );
+    }
+
+    #[test]
+    fn kernel_version_picks_newest() {
+        let target = tempfile::tempdir().unwrap();
+        let kvers = ["6.9.1-arch1-1", "6.10.2-arch1-1", "6.10.10-arch1-1"];
+        install_kernels(target.path(), &kvers);
+
+        assert_eq!(
+            get_kernel_version(target.path(), &[]).unwrap(),
+            "6.10.10-arch1-1"
+        );
+    }
+
+    #[test]
+    fn kernel_version_picks_the_chosen_kernel() {
+        let target = tempfile::tempdir().unwrap();
+        let kvers = ["6.10.2-arch1-1", "6.6.40-1-lts"];
+        install_kernels(target.path(), &kvers);
+
+        assert_eq!(
+            get_kernel_version(target.path(), &[Kernel::Lts]).unwrap(),
+            "6.6.40-1-lts"
+        );
+        assert_eq!(
+            get_kernel_version(target.path(), &[Kernel::Linux]).unwrap(),
+            "6.10.2-arch1-1"
+        );
+        let err = get_kernel_version(target.path(), &[Kernel::Zen]).unwrap_err();
+        assert_eq!(err.to_string(), "No linux-zen kernel found in /lib/modules");
+    }
+
+    #[test]
+    fn kernel_versions_lists_each_chosen_kernel_newest_first() {
+        let target = tempfile::tempdir().unwrap();
+        let kvers = [
+            "6.6.40-1-lts",
+            "6.10.2-arch1-1",
+            "6.10.10-arch1-1",
+            "6.10.2-zen1-1-zen",
+        ];
+        install_kernels(target.path(), &kvers);
+
+        assert_eq!(
+            kernel_versions(target.path(), &[Kernel::Linux, Kernel::Lts]).unwrap(),
+            ["6.10.10-arch1-1", "6.10.2-arch1-1", "6.6.40-1-lts"]
+        );
+        assert_eq!(kernel_versions(target.path(), &[]).unwrap().len(), 4);
+        let err = get_kernel_version(target.path(), &[Kernel::Hardened]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No linux-hardened kernel found in /lib/modules"
+        );
+    }
+
+    #[test]
+    fn boot_mode_from_str() {
+        assert_eq!("uki".parse::<BootMode>().unwrap(), BootMode::Uki);
+        assert_eq!("efistub".parse::<BootMode>().unwrap(), BootMode::Efistub);
+        assert!("grub".parse::<BootMode>().is_err());
+        assert_eq!(BootMode::default(), BootMode::Uki);
+    }
+
+    #[test]
+    fn only_systemd_boot_needs_more_packages() {
+        assert!(BootKind::DracutEfistub.packages().is_empty());
+        assert_eq!(
+            BootKind::MkinitcpioSystemdBoot.packages(),
+            ["mkinitcpio", "systemd-boot"]
+        );
+    }
+
+    #[test]
+    fn boot_kind_creates_matching_backend() {
+        for kind in [BootKind::DracutEfistub, BootKind::MkinitcpioSystemdBoot] {
+            assert_eq!(kind.create().name(), kind.name());
+        }
+        assert_eq!(BootKind::default(), BootKind::DracutEfistub);
+    }
 }
@@ -0,0 +1,138 @@
+//! Reading the firmware's boot entries from `efibootmgr` output
+
+/// A `BootXXXX` line from `efibootmgr`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvramEntry {
+    /// Four hex digits, as `efibootmgr -b` takes them
+    pub bootnum: String,
+    pub label: String,
+    /// Image path on the ESP (e.g., `\mkos-6.1.0.efi`), when the output
+    /// shows it
+    pub loader: Option<String>,
+}
+
+/// Parse `efibootmgr` or `efibootmgr -v` output into its boot entries
+pub fn parse_efibootmgr(output: &str) -> Vec<NvramEntry> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let rest = line.strip_prefix("Boot")?;
+            let bootnum: String = rest.chars().take_while(|c| c.is_ascii_hexdigit()).collect();
+            if bootnum.len() != 4 {
+                return None;
+            }
+            let rest = rest[4..].trim_start_matches('*').trim();
+            let (label, path) = rest.split_once('\t').unwrap_or((rest, ""));
+            Some(NvramEntry {
+                bootnum,
+                label: label.trim().to_string(),
+                loader: loader_in(path),
+            })
+        })
+        .collect()
+}
+
+/// The file in a device path: `HD(...)/File(\x.efi)` from `efibootmgr -v`,
+/// or `HD(...)/\x.efi` from newer efibootmgr
+fn loader_in(path: &str) -> Option<String> {
+    if let Some((_, file)) = path.split_once("File(") {
+        return file.split(')').next().map(String::from);
+    }
+    path.split_once(")/")
+        .map(|(_, file)| file.split_whitespace().next().unwrap_or(file))
+        .filter(|file| file.starts_with('\\'))
+        .map(String::from)
+}
+
+/// Whether `efibootmgr` output lists a boot entry named `label`
+pub fn nvram_has_entry(efibootmgr: &str, label: &str) -> bool {
+    parse_efibootmgr(efibootmgr)
+        .iter()
+        .any(|entry| entry.label == label)
+}
+
+/// Boot numbers of entries named `label` that start `loader_path` (relative
+/// to the ESP, with `/` separators), or whose output doesn't say what they
+/// start
+pub fn entries_for(efibootmgr: &str, label: &str, loader_path: &str) -> Vec<String> {
+    let loader = loader_path.replace('/', "\\");
+    parse_efibootmgr(efibootmgr)
+        .into_iter()
+        .filter(|entry| entry.label == label)
+        // FAT paths are case-insensitive, and firmware may upcase them
+        .filter(|entry| {
+            entry
+                .loader
+                .as_ref()
+                .is_none_or(|l| l.eq_ignore_ascii_case(&loader))
+        })
+        .map(|entry| entry.bootnum)
+        .collect()
+}
+
+/// BootOrder with `bootnum` moved to the front
+pub fn order_first(efibootmgr: &str, bootnum: &str) -> Vec<String> {
+    let current = efibootmgr
+        .lines()
+        .find_map(|line| line.strip_prefix("BootOrder:"))
+        .unwrap_or("");
+    std::iter::once(bootnum.to_string())
+        .chain(
+            current
+                .trim()
+                .split(',')
+                .filter(|n| !n.is_empty() && !n.eq_ignore_ascii_case(bootnum))
+                .map(String::from),
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERBOSE: &str = "BootCurrent: 0001\n\
+        Timeout: 1 seconds\n\
+        BootOrder: 0003,0000,0001\n\
+        Boot0000* Windows Boot Manager\tHD(1,GPT,aa,0x800,0x32000)/File(\\EFI\\Microsoft\\Boot\\bootmgfw.efi)\n\
+        Boot0001* mkOS\tHD(1,GPT,bb,0x800,0x100000)/File(\\mkos-6.1.0.efi)\n\
+        Boot0002* mkOS (rescue)\tHD(1,GPT,bb,0x800,0x100000)/File(\\mkos-rescue.efi)\n\
+        Boot0003* mkOS\tHD(1,GPT,bb,0x800,0x100000)/File(\\MKOS-6.1.0.EFI)\n\
+        Boot0004* mkOS\tHD(1,GPT,bb,0x800,0x100000)/File(\\mkos-6.0.9.efi)\n";
+
+    #[test]
+    fn entries_for_label_and_loader() {
+        assert_eq!(
+            entries_for(VERBOSE, "mkOS", "/mkos-6.1.0.efi"),
+            ["0001", "0003"]
+        );
+        assert_eq!(
+            entries_for(VERBOSE, "mkOS (rescue)", "/mkos-rescue.efi"),
+            ["0002"]
+        );
+        assert!(entries_for(VERBOSE, "mkOS (fallback)", "/mkos-fallback.efi").is_empty());
+    }
+
+    #[test]
+    fn entries_without_a_path_match_by_label() {
+        let output = "Boot0001* mkOS\nBoot0002* mkOS (rescue)\n";
+        assert_eq!(entries_for(output, "mkOS", "/mkos-6.1.0.efi"), ["0001"]);
+
+        let newer = "Boot0001* mkOS\tHD(1,GPT,bb,0x800,0x100000)/\\mkos-6.0.9.efi\n";
+        assert!(entries_for(newer, "mkOS", "/mkos-6.1.0.efi").is_empty());
+    }
+
+    #[test]
+    fn entry_moves_to_front_of_boot_order() {
+        assert_eq!(order_first(VERBOSE, "0001"), ["0001", "0003", "0000"]);
+        assert_eq!(order_first(VERBOSE, "0003"), ["0003", "0000", "0001"]);
+        assert_eq!(order_first("", "0005"), ["0005"]);
+    }
+
+    #[test]
+    fn labels_match_whole() {
+        assert!(nvram_has_entry(VERBOSE, "mkOS (rescue)"));
+        assert!(!nvram_has_entry(VERBOSE, "mkOS (fallback)"));
+        assert!(!nvram_has_entry("BootCurrent: 0001\n", "mkOS"));
+    }
+}
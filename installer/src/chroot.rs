@@ -1,9 +1,10 @@
 use anyhow::{Context, Result};
 use std::fs;
-use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
+use zeroize::Zeroizing;
 
 use crate::cmd;
+use crate::crypt::DetachedHeader;
 use crate::paths;
 
 /// Mount special filesystems for chroot operations
@@ -55,12 +56,30 @@ pub fn teardown_chroot(target: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Run a manifest script inside the target with /bin/sh
+pub fn run_script(target: &Path, script: &str) -> Result<()> {
+    cmd::run("chroot", script_args(target, script))
+}
+
+fn script_args(target: &Path, script: &str) -> Vec<String> {
+    vec![
+        target.to_string_lossy().to_string(),
+        "/bin/sh".into(),
+        "-c".into(),
+        script.into(),
+    ]
+}
+
 #[derive(Debug, Clone)]
 pub struct SystemConfig {
     pub hostname: String,
     pub timezone: String,
     pub locale: String,
+    /// Generated besides `locale`, which stays the default
+    pub locales: Vec<String>,
     pub keymap: String,
+    /// Console font; the kernel's built-in font when None
+    pub console_font: Option<String>,
 }
 
 impl Default for SystemConfig {
@@ -69,16 +88,18 @@ impl Default for SystemConfig {
             hostname: "mkos".into(),
             timezone: "UTC".into(),
             locale: "en_US.UTF-8".into(),
+            locales: Vec::new(),
             keymap: "us".into(),
+            console_font: None,
         }
     }
 }
 
 pub fn configure_system(target: &Path, config: &SystemConfig) -> Result<()> {
     configure_timezone(target, &config.timezone)?;
-    configure_locale(target, &config.locale)?;
+    configure_locale(target, &config.locale, &config.locales)?;
     configure_hostname(target, &config.hostname)?;
-    configure_keymap(target, &config.keymap)?;
+    configure_vconsole(target, &config.keymap, config.console_font.as_deref())?;
     Ok(())
 }
 
@@ -104,9 +125,17 @@ fn configure_timezone(target: &Path, timezone: &str) -> Result<()> {
     Ok(())
 }
 
-fn configure_locale(target: &Path, locale: &str) -> Result<()> {
+fn configure_locale(target: &Path, locale: &str, extra: &[String]) -> Result<()> {
+    let mut locales = vec![locale];
+    locales.extend(extra.iter().map(String::as_str));
+
     let locale_gen = target.join("etc/locale.gen");
-    fs::write(&locale_gen, format!("{} UTF-8\n", locale)).context("Failed to write locale.gen")?;
+    let content = fs::read_to_string(&locale_gen).unwrap_or_default();
+    fs::write(
+        &locale_gen,
+        crate::locale::enable_locales(&content, &locales),
+    )
+    .context("Failed to write locale.gen")?;
 
     cmd::run("chroot", [&target.to_string_lossy(), "locale-gen"])?;
 
@@ -130,10 +159,14 @@ fn configure_hostname(target: &Path, hostname: &str) -> Result<()> {
     Ok(())
 }
 
-fn configure_keymap(target: &Path, keymap: &str) -> Result<()> {
+fn configure_vconsole(target: &Path, keymap: &str, font: Option<&str>) -> Result<()> {
+    let mut content = format!("KEYMAP={}\n", keymap);
+    if let Some(font) = font {
+        content.push_str(&format!("FONT={}\n", font));
+    }
+
     let vconsole = target.join("etc/vconsole.conf");
-    fs::write(&vconsole, format!("KEYMAP={}\n", keymap))
-        .context("Failed to write vconsole.conf")?;
+    fs::write(&vconsole, content).context("Failed to write vconsole.conf")?;
 
     Ok(())
 }
@@ -142,7 +175,7 @@ pub fn set_root_password(target: &Path, password: &str) -> Result<()> {
     cmd::run_with_stdin(
         "chroot",
         [&target.to_string_lossy(), "chpasswd"],
-        format!("root:{}\n", password).as_bytes(),
+        Zeroizing::new(format!("root:{}\n", password)).as_bytes(),
     )
 }
 
@@ -153,19 +186,73 @@ pub fn generate_fstab(target: &Path, fstab_content: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn generate_crypttab(target: &Path, luks_uuid: &str) -> Result<()> {
-    let crypttab_content = format!(
-        "# <target name> <source device> <key file> <options>\n{} UUID={} none luks,discard\n",
-        paths::LUKS_MAPPER_NAME,
-        luks_uuid
-    );
-
+/// Write crypttab for the root and the LUKS UUIDs of any other disks it
+/// spans
+pub fn generate_crypttab(
+    target: &Path,
+    luks_uuid: &str,
+    header: Option<&DetachedHeader>,
+    members: &[String],
+) -> Result<()> {
     let crypttab_path = target.join("etc/crypttab");
-    fs::write(&crypttab_path, &crypttab_content).context("Failed to write crypttab")?;
+    let content = crypttab(luks_uuid, header) + &crypttab_members(members);
+    fs::write(&crypttab_path, content).context("Failed to write crypttab")?;
 
     Ok(())
 }
 
+/// crypttab unlocking the root; a detached header names the partition by
+/// PARTUUID, since the UUID lives in the header
+pub fn crypttab(luks_uuid: &str, header: Option<&DetachedHeader>) -> String {
+    let (device, options) = match header {
+        Some(header) => (
+            header.data_device.clone(),
+            format!("luks,discard,{}", header.crypttab_option()),
+        ),
+        None => (format!("UUID={}", luks_uuid), "luks,discard".into()),
+    };
+    format!(
+        "# <target name> <source device> <key file> <options>\n{} {} none {}\n",
+        paths::LUKS_MAPPER_NAME,
+        device,
+        options
+    )
+}
+
+/// crypttab lines for the other disks of a RAID root, named after the root
+/// with their position appended (system1, system2, ...)
+pub fn crypttab_members(members: &[String]) -> String {
+    members
+        .iter()
+        .enumerate()
+        .map(|(i, uuid)| {
+            format!(
+                "{} UUID={} none luks,discard\n",
+                crate::disk::member_name(paths::LUKS_MAPPER_NAME, i + 1),
+                uuid
+            )
+        })
+        .collect()
+}
+
+/// fstab with every btrfs mount naming all of `devices`, so a filesystem
+/// spanning several disks mounts once they're all unlocked
+pub fn with_btrfs_devices(fstab: &str, devices: &[String]) -> String {
+    let options: Vec<String> = devices.iter().map(|d| format!("device={}", d)).collect();
+    fstab
+        .lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if line.trim_start().starts_with('#') || fields.len() < 4 || fields[2] != "btrfs" {
+                return format!("{}\n", line);
+            }
+            let mut fields: Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+            fields[3] = format!("{},{}", fields[3], options.join(","));
+            format!("{}\n", fields.join("\t"))
+        })
+        .collect()
+}
+
 /// Create a user account with the specified groups
 pub fn create_user(target: &Path, username: &str, password: &str, groups: &[&str]) -> Result<()> {
     let target_str = target.to_string_lossy().to_string();
@@ -222,10 +309,51 @@ pub fn determine_user_groups(
     groups
 }
 
+/// Configure NSSwitch for name resolution
+///
+/// When mDNS is enabled, configures the hosts line to use mdns_minimal
+/// for .local domain resolution via Avahi.
+pub fn configure_nsswitch(target: &Path, mdns_enabled: bool) -> Result<()> {
+    let hosts_line = if mdns_enabled {
+        "hosts:      files mdns_minimal [NOTFOUND=return] resolve [!UNAVAIL=return] dns"
+    } else {
+        "hosts:      files resolve [!UNAVAIL=return] dns"
+    };
+
+    let content = format!(
+        "# /etc/nsswitch.conf - Name Service Switch configuration\n\
+         # Generated by mkOS installer\n\n\
+         passwd:     files\n\
+         group:      files\n\
+         shadow:     files\n\n\
+         {}\n\n\
+         networks:   files\n\
+         protocols:  files\n\
+         services:   files\n\
+         ethers:     files\n\
+         rpc:        files\n",
+        hosts_line
+    );
+
+    fs::write(target.join("etc/nsswitch.conf"), content)
+        .context("Failed to write nsswitch.conf")?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn script_runs_with_sh_in_target() {
+        let script = "echo hello > /etc/motd\nexit 1";
+        assert_eq!(
+            script_args(Path::new("/mnt"), script),
+            vec!["/mnt", "/bin/sh", "-c", script]
+        );
+    }
+
     #[test]
     fn groups_always_include_wheel() {
         let groups = determine_user_groups(false, None, false);
@@ -294,61 +422,44 @@ mod tests {
 
     #[test]
     fn crypttab_content_format() {
-        let uuid = "abcd-1234";
-        let content = format!(
-            "# <target name> <source device> <key file> <options>\n{} UUID={} none luks,discard\n",
-            paths::LUKS_MAPPER_NAME,
-            uuid
-        );
-        assert!(content.contains("system UUID=abcd-1234 none luks,discard"));
+        let content = crypttab("abcd-1234", None);
+        assert!(content.contains("system UUID=abcd-1234 none luks,discard\n"));
         assert!(content.starts_with('#'));
     }
-}
-
-/// Configure sudoers to allow wheel group sudo access
-pub fn configure_sudoers(target: &Path) -> Result<()> {
-    let sudoers_d = target.join("etc/sudoers.d");
-    fs::create_dir_all(&sudoers_d)?;
-
-    let wheel_file = sudoers_d.join("wheel");
-    fs::write(&wheel_file, "%wheel ALL=(ALL:ALL) ALL\n")
-        .context("Failed to write sudoers.d/wheel")?;
-
-    // Set restrictive permissions (required by sudo)
-    fs::set_permissions(&wheel_file, fs::Permissions::from_mode(0o440))
-        .context("Failed to set permissions on sudoers.d/wheel")?;
-
-    Ok(())
-}
-
-/// Configure NSSwitch for name resolution
-///
-/// When mDNS is enabled, configures the hosts line to use mdns_minimal
-/// for .local domain resolution via Avahi.
-pub fn configure_nsswitch(target: &Path, mdns_enabled: bool) -> Result<()> {
-    let hosts_line = if mdns_enabled {
-        "hosts:      files mdns_minimal [NOTFOUND=return] resolve [!UNAVAIL=return] dns"
-    } else {
-        "hosts:      files resolve [!UNAVAIL=return] dns"
-    };
 
-    let content = format!(
-        "# /etc/nsswitch.conf - Name Service Switch configuration\n\
-         # Generated by mkOS installer\n\n\
-         passwd:     files\n\
-         group:      files\n\
-         shadow:     files\n\n\
-         {}\n\n\
-         networks:   files\n\
-         protocols:  files\n\
-         services:   files\n\
-         ethers:     files\n\
-         rpc:        files\n",
-        hosts_line
-    );
+    #[test]
+    fn crypttab_lists_raid_members() {
+        let content = crypttab("abcd-1234", None)
+            + &crypttab_members(&["ef01-2345".into(), "6789-abcd".into()]);
+        assert!(content.ends_with(
+            "system UUID=abcd-1234 none luks,discard\n\
+             system1 UUID=ef01-2345 none luks,discard\n\
+             system2 UUID=6789-abcd none luks,discard\n"
+        ));
+    }
 
-    fs::write(target.join("etc/nsswitch.conf"), content)
-        .context("Failed to write nsswitch.conf")?;
+    #[test]
+    fn btrfs_mounts_name_every_device() {
+        let fstab = "# /dev/mapper/system\n\
+                     UUID=1111 / btrfs rw,noatime,subvol=/@ 0 0\n\
+                     UUID=2222 /boot vfat rw 0 2\n";
+        let devices = ["/dev/mapper/system".into(), "/dev/mapper/system1".into()];
+        assert_eq!(
+            with_btrfs_devices(fstab, &devices),
+            "# /dev/mapper/system\n\
+             UUID=1111\t/\tbtrfs\trw,noatime,subvol=/@,device=/dev/mapper/system,device=/dev/mapper/system1\t0\t0\n\
+             UUID=2222 /boot vfat rw 0 2\n"
+        );
+    }
 
-    Ok(())
+    #[test]
+    fn crypttab_points_at_detached_header() {
+        let header = DetachedHeader {
+            data_device: "PARTUUID=0f3a-01".into(),
+            header: "/system.hdr:UUID=ABCD-1234".into(),
+        };
+        assert!(crypttab("abcd-1234", Some(&header)).ends_with(
+            "system PARTUUID=0f3a-01 none luks,discard,header=/system.hdr:UUID=ABCD-1234\n"
+        ));
+    }
 }
@@ -1,20 +1,36 @@
 use anyhow::{bail, Result};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use crate::boot::{validate_cmdline, BootKind, BootMode, Kernel};
+use crate::crypt::{validate_compression, BtrfsLayout, FilesystemKind, Subvolume};
 use crate::disk;
 use crate::distro::DistroKind;
-use crate::install::{DesktopConfig, InstallConfig, SwapConfig};
-use crate::manifest::Manifest;
+use crate::init::InitKind;
+use crate::install::{DesktopConfig, InstallConfig, SecureBootConfig, SwapConfig};
+use crate::keyboard;
+use crate::locale;
+use crate::manifest::{DiskConfig, Manifest, MirrorConfig, NetworkConfig};
+use crate::secret::Secret;
+use crate::swap;
 
-use super::gpu::{detect_gpus, get_nvidia_packages, GpuVendor};
+use super::gpu::{
+    detect_gpus, get_amd_packages, get_intel_packages, get_lib32_packages, get_nvidia_packages,
+    get_prime_packages, is_hybrid, GpuVendor,
+};
 use super::prompts::{
-    prompt_default, prompt_display_manager, prompt_greeter, prompt_passphrase,
-    prompt_password_confirm, prompt_seat_manager, prompt_yes_no, select_device,
+    prompt_default, prompt_display_manager, prompt_firewall_config, prompt_greeter,
+    prompt_network_config, prompt_passphrase, prompt_password_confirm, prompt_preset,
+    prompt_search, prompt_seat_manager, prompt_wifi, prompt_yes_no, select_device,
 };
 
 pub fn build_config(manifest: &Manifest) -> Result<InstallConfig> {
     // Get device - from manifest or prompt
-    let device = match &manifest.disk.device {
+    let device = match manifest
+        .disk
+        .device
+        .as_ref()
+        .or(manifest.disk.devices.first())
+    {
         Some(dev) => {
             println!("Using device from manifest: {}", dev);
             PathBuf::from(dev)
@@ -24,13 +40,37 @@ pub fn build_config(manifest: &Manifest) -> Result<InstallConfig> {
             if devices.is_empty() {
                 bail!("No block devices found");
             }
+            let boot_disk = disk::current_boot_disk();
             println!("Available disks:");
             for (i, dev) in devices.iter().enumerate() {
-                let size_gb = dev.size_bytes / 1_000_000_000;
                 let model = dev.model.as_deref().unwrap_or("Unknown");
-                println!("  [{}] {} - {} GB - {}", i + 1, dev.path, size_gb, model);
+                let boot = if boot_disk.as_ref() == Some(&dev.path) {
+                    " [boot medium]"
+                } else {
+                    ""
+                };
+                println!(
+                    "  [{}] {} - {} - {}{}",
+                    i + 1,
+                    dev.path,
+                    dev.size_label(),
+                    model,
+                    boot
+                );
             }
-            let selected = select_device(&devices)?;
+            let selected = loop {
+                let selected = select_device(&devices)?;
+                if boot_disk.as_ref() != Some(&selected.path) {
+                    break selected;
+                }
+                println!(
+                    "\nWARNING: {} is the disk the running system booted from.",
+                    selected.path
+                );
+                if prompt_yes_no("Erase it anyway?", false)? {
+                    break selected;
+                }
+            };
             println!("\nSelected: {}\n", selected.path);
             PathBuf::from(&selected.path)
         }
@@ -38,9 +78,10 @@ pub fn build_config(manifest: &Manifest) -> Result<InstallConfig> {
 
     // Always prompt for passphrase (never in manifest for security)
     let passphrase = if manifest.disk.encryption {
-        prompt_passphrase()?
+        crate::crypt::validate_passphrase_policy(&manifest.disk.passphrase_policy)?;
+        prompt_passphrase(&manifest.disk.passphrase_policy)?
     } else {
-        String::new()
+        Secret::default()
     };
 
     // Always prompt for root password (never in manifest for security)
@@ -60,17 +101,37 @@ pub fn build_config(manifest: &Manifest) -> Result<InstallConfig> {
         println!("Using timezone from manifest: {}", manifest.system.timezone);
         manifest.system.timezone.clone()
     } else {
-        prompt_default("Timezone", "UTC")?
+        prompt_search("Timezone", locale::timezones(Path::new("/")), "UTC")?
+    };
+
+    // Get locale - from manifest or prompt
+    let locale = if manifest.system.locale != "en_US.UTF-8" {
+        println!("Using locale from manifest: {}", manifest.system.locale);
+        manifest.system.locale.clone()
+    } else {
+        prompt_search("Locale", locale::locales(Path::new("/")), "en_US.UTF-8")?
     };
 
-    // Get locale from manifest
-    let locale = manifest.system.locale.clone();
+    // Get keymap - from manifest or prompt
+    let keymap = if manifest.system.keymap != "us" {
+        println!("Using keymap from manifest: {}", manifest.system.keymap);
+        manifest.system.keymap.clone()
+    } else {
+        prompt_search("Keymap", keyboard::console_keymaps(Path::new("/")), "us")?
+    };
+
+    // Timezone and locale must exist; the live system ships the same lists
+    locale::check_timezone(Path::new("/"), &timezone)?;
+    locale::check_locale(Path::new("/"), &locale)?;
+    for extra in &manifest.system.locales {
+        locale::check_locale(Path::new("/"), extra)?;
+    }
 
-    // Get keymap from manifest
-    let keymap = manifest.system.keymap.clone();
+    // Extra locales come from the manifest only
+    let locales = manifest.system.locales.clone();
 
     // Get distro - from manifest, auto-detect, or prompt
-    let distro = if manifest.distro == "artix" {
+    let distro = if manifest.distro == DistroKind::default().as_str() {
         // Default value - try to auto-detect, prompt if detection fails
         match crate::distro::detect() {
             Ok(detected) => {
@@ -85,51 +146,108 @@ pub fn build_config(manifest: &Manifest) -> Result<InstallConfig> {
     } else {
         // Non-default value from manifest, use it
         println!("Using distro from manifest: {}", manifest.distro);
-        match manifest.distro.as_str() {
-            "artix" => DistroKind::Artix,
-            "void" => DistroKind::Void,
-            "slackware" => DistroKind::Slackware,
-            "alpine" => DistroKind::Alpine,
-            "gentoo" => DistroKind::Gentoo,
-            "devuan" => DistroKind::Devuan,
-            other => bail!(
-                "Unknown distro: {}. Supported: artix, void, slackware, alpine, gentoo, devuan",
-                other
-            ),
-        }
+        manifest.distro.parse::<DistroKind>()?
+    };
+
+    // Init system from manifest, checked against what the distro ships
+    let init = parse_init(manifest.init.as_deref(), distro)?;
+
+    // Filesystem from manifest
+    let filesystem = parse_filesystem(&manifest.disk)?;
+
+    // Btrfs layout (subvolumes and compression) from manifest
+    let btrfs_layout = build_btrfs_layout(&manifest.disk)?;
+
+    // EFI partition size, label and reuse from manifest
+    let partition_layout = build_partition_layout(&manifest.disk)?;
+
+    // Boot system from manifest
+    let boot = parse_boot_system(&manifest.boot.system, distro)?;
+    validate_cmdline(&manifest.boot.cmdline)?;
+
+    // Secure Boot from manifest
+    let secureboot = SecureBootConfig {
+        enabled: manifest.secureboot.enabled,
+        keys_path: manifest.secureboot.keys_path.as_ref().map(PathBuf::from),
+        enroll: manifest.secureboot.enroll,
     };
 
     // Enable networking - check if any networking services are requested
     let enable_networking =
-        manifest.services.enable.iter().any(|s| {
+        manifest.enabled_services().into_iter().any(|s| {
             s == "dhcpcd" || s == "networkmanager" || s == "connman" || s.contains("network")
         }) || prompt_yes_no("Enable networking (DHCP)", true)?;
 
-    // Detect GPUs and offer proprietary drivers
-    let mut extra_packages = Vec::new();
+    // A manifest without packages gets a preset offered instead
+    let with_preset;
+    let manifest = if manifest.preset.is_none() && manifest.packages.groups.is_empty() {
+        with_preset = Manifest {
+            preset: prompt_preset()?,
+            ..manifest.clone()
+        };
+        &with_preset
+    } else {
+        manifest
+    };
+
+    // Manifest packages, then drivers for detected GPUs: open ones default
+    // to yes, proprietary to no
+    let mut extra_packages: Vec<String> = manifest
+        .all_packages()
+        .into_iter()
+        .map(String::from)
+        .collect();
     let gpus = detect_gpus();
+    // Vendors whose drivers were accepted, for their 32-bit drivers
+    let mut with_drivers = Vec::new();
+
+    if gpus.contains(&GpuVendor::Intel) {
+        println!("\nIntel GPU detected.");
+        if prompt_yes_no("Install Intel Vulkan and video acceleration drivers", true)? {
+            add_gpu_packages(&mut extra_packages, get_intel_packages());
+            with_drivers.push(GpuVendor::Intel);
+        }
+    }
+
+    if gpus.contains(&GpuVendor::AmdDiscrete) {
+        println!("\nAMD GPU detected.");
+        if prompt_yes_no("Install AMD Vulkan drivers", true)? {
+            add_gpu_packages(&mut extra_packages, get_amd_packages());
+            with_drivers.push(GpuVendor::AmdDiscrete);
+        }
+    }
 
     if gpus.contains(&GpuVendor::Nvidia) {
         println!("\nNVIDIA GPU detected.");
         if prompt_yes_no("Install proprietary NVIDIA drivers", false)? {
-            extra_packages.extend(get_nvidia_packages());
-            println!("  Will install: nvidia, nvidia-utils, nvidia-prime, lib32-nvidia-utils");
+            add_gpu_packages(&mut extra_packages, get_nvidia_packages());
+            with_drivers.push(GpuVendor::Nvidia);
+
+            if is_hybrid(&gpus) {
+                println!("\nThe NVIDIA GPU shares the display with an integrated one.");
+                if prompt_yes_no("Set up PRIME render offload (prime-run)", true)? {
+                    add_gpu_packages(&mut extra_packages, get_prime_packages());
+                }
+            }
         }
     }
 
+    // 32-bit drivers come from multilib or i386, which only gets enabled
+    // when asked for
+    let multilib = !with_drivers.is_empty()
+        && distro.has_multilib()
+        && prompt_yes_no(
+            "Enable 32-bit packages and install 32-bit GPU drivers (for Steam and Wine)",
+            false,
+        )?;
+    if multilib {
+        add_gpu_packages(&mut extra_packages, get_lib32_packages(&with_drivers));
+    }
+
     // Desktop environment setup - from manifest or prompt
     let desktop = if manifest.desktop.enabled {
         println!("Using desktop config from manifest");
-        DesktopConfig {
-            enabled: true,
-            seat_manager: manifest.desktop.seat_manager.clone(),
-            display_manager: manifest.desktop.display_manager.clone(),
-            greeter: manifest.desktop.greeter.clone(),
-            user_services: manifest.desktop.user_services,
-            portals: manifest.desktop.portals,
-            portal_backends: manifest.desktop.portal_backends.clone(),
-            greetd_config: manifest.desktop.greetd.clone(),
-        }
+        manifest_desktop(manifest)
     } else {
         prompt_desktop_config()?
     };
@@ -137,16 +255,19 @@ pub fn build_config(manifest: &Manifest) -> Result<InstallConfig> {
     // Swap configuration - from manifest or prompt
     let swap = if manifest.swap.zram || manifest.swap.swapfile {
         println!("Using swap config from manifest");
-        SwapConfig {
-            zram_enabled: manifest.swap.zram,
-            zram_size_gb: manifest.swap.zram_size,
-            swapfile_enabled: manifest.swap.swapfile,
-            swapfile_size_gb: manifest.swap.swapfile_size,
-            swappiness: manifest.swap.swappiness,
-        }
+        manifest_swap(manifest)
     } else {
         prompt_swap_config()?
     };
+    swap::validate_hibernation(&swap)?;
+    let ram_gb = get_system_ram_gb();
+    if swap::hibernation_swap_too_small(&swap, ram_gb) {
+        println!(
+            "Warning: {}GB swapfile is smaller than {}GB of RAM; hibernation may fail",
+            swap.swapfile_size_gb.unwrap_or(8),
+            ram_gb
+        );
+    }
 
     // Audio configuration - from manifest or prompt
     let audio = if manifest.audio.enabled {
@@ -168,36 +289,446 @@ pub fn build_config(manifest: &Manifest) -> Result<InstallConfig> {
         crate::manifest::AudioConfig::default()
     };
 
-    // Network config from manifest (no interactive prompts yet)
-    let network = manifest.network.clone();
+    // Network services - from manifest or prompt
+    crate::network::validate_interfaces(&manifest.network.interfaces)?;
+    let services_set = manifest.network.mdns
+        || manifest.network.ssh.is_some()
+        || manifest.network.eternalterminal.is_some();
+    let mut network = if services_set {
+        println!("Using network services from manifest");
+        manifest.network.clone()
+    } else if enable_networking {
+        NetworkConfig {
+            interfaces: manifest.network.interfaces.clone(),
+            wifi: manifest.network.wifi.clone(),
+            wireguard: manifest.network.wireguard.clone(),
+            tailscale: manifest.network.tailscale.clone(),
+            ..prompt_network_config()?
+        }
+    } else {
+        manifest.network.clone()
+    };
+
+    // WiFi from manifest, or prompt when there's no wired link to fall back on
+    if let Some(wifi) = &network.wifi {
+        crate::network::validate_wifi(wifi)?;
+    } else if enable_networking
+        && !crate::network::has_wired_link()
+        && prompt_yes_no("\nNo wired connection detected. Configure WiFi", true)?
+    {
+        network.wifi = Some(prompt_wifi()?);
+    }
+
+    if let Some(ssh) = &network.ssh {
+        crate::network::validate_ssh(ssh)?;
+    }
+    crate::network::validate_vpn(&network)?;
+    crate::network::settle_ssh_auth(&mut network, manifest.has_ssh_keys());
+
+    // Firewall - from manifest or prompt
+    crate::firewall::validate_firewall(&manifest.firewall)?;
+    crate::privilege::validate_security(&manifest.security)?;
+    crate::privilege::check_packaged(&manifest.security, distro)?;
+    let firewall = if manifest.firewall.enabled {
+        println!("Using firewall config from manifest");
+        manifest.firewall.clone()
+    } else {
+        prompt_firewall_config(&network)?
+    };
 
-    // Firewall config from manifest (no interactive prompts yet)
-    let firewall = manifest.firewall.clone();
+    crate::mirror::validate_mirror(&manifest.mirror)?;
+    check_repo(&manifest.mirror, distro)?;
 
     // Microcode - detect CPU and prompt user
     let microcode = prompt_microcode()?;
 
+    let luks_header = detached_header(&manifest.disk, &device)?;
+    let raid_devices = raid_devices(&manifest.disk, boot, swap.swapfile_enabled)?;
+
     Ok(InstallConfig {
         device,
+        encryption: manifest.disk.encryption,
+        raid_devices,
         passphrase,
+        luks_header,
         root_password,
         hostname,
         timezone,
         locale,
+        locales,
         keymap,
+        keymap_x11: manifest.system.keymap_x11.clone(),
+        console_font: manifest.system.console_font.clone(),
         distro,
+        init,
+        filesystem,
+        btrfs_layout,
+        partition_layout,
+        boot,
+        boot_mode: parse_boot_mode(&manifest.boot.mode, boot, manifest.secureboot.enabled)?,
+        kernel_cmdline: manifest.boot.cmdline.clone(),
+        kernel: parse_kernel(manifest.boot.kernel.as_deref(), distro)?,
+        extra_kernels: parse_extra_kernels(&manifest.boot.extra_kernels, distro, boot)?,
+        quiet_boot: manifest.boot.quiet,
+        splash_boot: parse_splash(manifest.boot.splash, distro)?,
+        recovery_entry: manifest.boot.recovery_entry,
         enable_networking,
         extra_packages,
+        multilib,
         desktop,
         swap,
         audio,
         network,
         firewall,
-        secureboot: crate::install::SecureBootConfig::default(),
+        snapshots: manifest.snapshots.clone(),
+        security: manifest.security.clone(),
+        secureboot,
         microcode,
+        scripts: manifest.scripts.clone(),
+        mirror: manifest.mirror.clone(),
+        selected_mirror: None,
+        image: None,
+    })
+}
+
+/// Config for an install with nobody to answer prompts: everything comes
+/// from the manifest, and whatever it leaves out gets the default an
+/// interactive install would offer
+///
+/// The secrets are passed in since manifests never carry them.
+pub fn headless_config(
+    manifest: &Manifest,
+    passphrase: Secret,
+    root_password: Secret,
+) -> Result<InstallConfig> {
+    let Some(device) = manifest
+        .disk
+        .device
+        .as_ref()
+        .or(manifest.disk.devices.first())
+    else {
+        bail!("disk.device or disk.devices must be set when there is nobody to pick a disk");
+    };
+    if manifest.disk.encryption {
+        if passphrase.is_empty() {
+            bail!("A passphrase is required for an encrypted disk");
+        }
+        crate::crypt::validate_passphrase_policy(&manifest.disk.passphrase_policy)?;
+        crate::crypt::validate_passphrase(passphrase.expose(), &manifest.disk.passphrase_policy)?;
+    }
+    if root_password.is_empty() {
+        bail!("A root password is required");
+    }
+
+    Ok(InstallConfig {
+        passphrase,
+        root_password,
+        ..manifest_config(manifest, device)?
+    })
+}
+
+/// Everything the manifest settles about an install onto `device`, without
+/// prompting and without secrets; `device` is empty when none is chosen yet
+pub fn manifest_config(manifest: &Manifest, device: &str) -> Result<InstallConfig> {
+    locale::check_timezone(Path::new("/"), &manifest.system.timezone)?;
+    for locale in manifest.system.all_locales() {
+        locale::check_locale(Path::new("/"), locale)?;
+    }
+
+    let distro: DistroKind = manifest.distro.parse()?;
+    let init = parse_init(manifest.init.as_deref(), distro)?;
+    let filesystem = parse_filesystem(&manifest.disk)?;
+    let btrfs_layout = build_btrfs_layout(&manifest.disk)?;
+    let partition_layout = build_partition_layout(&manifest.disk)?;
+    let boot = parse_boot_system(&manifest.boot.system, distro)?;
+    validate_cmdline(&manifest.boot.cmdline)?;
+
+    let desktop = manifest_desktop(manifest);
+    let swap = manifest_swap(manifest);
+    swap::validate_hibernation(&swap)?;
+
+    let audio = if manifest.audio.enabled || !desktop.enabled {
+        manifest.audio.clone()
+    } else {
+        crate::manifest::AudioConfig {
+            enabled: true,
+            ..Default::default()
+        }
+    };
+
+    crate::network::validate_interfaces(&manifest.network.interfaces)?;
+    if let Some(wifi) = &manifest.network.wifi {
+        crate::network::validate_wifi(wifi)?;
+    }
+    if let Some(ssh) = &manifest.network.ssh {
+        crate::network::validate_ssh(ssh)?;
+    }
+    crate::network::validate_vpn(&manifest.network)?;
+    let mut network = manifest.network.clone();
+    crate::network::settle_ssh_auth(&mut network, manifest.has_ssh_keys());
+    crate::firewall::validate_firewall(&manifest.firewall)?;
+    crate::privilege::validate_security(&manifest.security)?;
+    crate::privilege::check_packaged(&manifest.security, distro)?;
+    crate::mirror::validate_mirror(&manifest.mirror)?;
+    check_repo(&manifest.mirror, distro)?;
+    let luks_header = detached_header(&manifest.disk, Path::new(device))?;
+    let raid_devices = raid_devices(&manifest.disk, boot, swap.swapfile_enabled)?;
+
+    Ok(InstallConfig {
+        device: PathBuf::from(device),
+        encryption: manifest.disk.encryption,
+        raid_devices,
+        passphrase: Secret::default(),
+        luks_header,
+        root_password: Secret::default(),
+        hostname: manifest.system.hostname.clone(),
+        timezone: manifest.system.timezone.clone(),
+        locale: manifest.system.locale.clone(),
+        locales: manifest.system.locales.clone(),
+        keymap: manifest.system.keymap.clone(),
+        keymap_x11: manifest.system.keymap_x11.clone(),
+        console_font: manifest.system.console_font.clone(),
+        distro,
+        init,
+        filesystem,
+        btrfs_layout,
+        partition_layout,
+        boot,
+        boot_mode: parse_boot_mode(&manifest.boot.mode, boot, manifest.secureboot.enabled)?,
+        kernel_cmdline: manifest.boot.cmdline.clone(),
+        kernel: parse_kernel(manifest.boot.kernel.as_deref(), distro)?,
+        extra_kernels: parse_extra_kernels(&manifest.boot.extra_kernels, distro, boot)?,
+        quiet_boot: manifest.boot.quiet,
+        splash_boot: parse_splash(manifest.boot.splash, distro)?,
+        recovery_entry: manifest.boot.recovery_entry,
+        enable_networking: true,
+        extra_packages: manifest
+            .all_packages()
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        multilib: false,
+        desktop,
+        swap,
+        audio,
+        network,
+        firewall: manifest.firewall.clone(),
+        snapshots: manifest.snapshots.clone(),
+        security: manifest.security.clone(),
+        secureboot: SecureBootConfig {
+            enabled: manifest.secureboot.enabled,
+            keys_path: manifest.secureboot.keys_path.as_ref().map(PathBuf::from),
+            enroll: manifest.secureboot.enroll,
+        },
+        microcode: false,
+        scripts: manifest.scripts.clone(),
+        mirror: manifest.mirror.clone(),
+        selected_mirror: None,
+        image: None,
     })
 }
 
+fn manifest_desktop(manifest: &Manifest) -> DesktopConfig {
+    DesktopConfig {
+        enabled: manifest.desktop.enabled,
+        seat_manager: manifest.desktop.seat_manager.clone(),
+        display_manager: manifest.desktop.display_manager.clone(),
+        greeter: manifest.desktop.greeter.clone(),
+        user_services: manifest.desktop.user_services,
+        portals: manifest.desktop.portals,
+        portal_backends: manifest.desktop.portal_backends.clone(),
+        greetd_config: manifest.desktop.greetd.clone(),
+    }
+}
+
+fn manifest_swap(manifest: &Manifest) -> SwapConfig {
+    SwapConfig {
+        zram_enabled: manifest.swap.zram,
+        zram_size_gb: manifest.swap.zram_size,
+        swapfile_enabled: manifest.swap.swapfile,
+        swapfile_size_gb: manifest.swap.swapfile_size,
+        swappiness: manifest.swap.swappiness,
+        hibernation: manifest.swap.hibernation,
+    }
+}
+
+/// Queue driver packages, skipping ones another GPU already asked for
+///
+/// Names are generic and mapped through the distro's package map at install.
+fn add_gpu_packages(extra_packages: &mut Vec<String>, packages: Vec<String>) {
+    let new: Vec<String> = packages
+        .into_iter()
+        .filter(|p| !extra_packages.contains(p))
+        .collect();
+    if !new.is_empty() {
+        println!("  Will install: {}", new.join(", "));
+        extra_packages.extend(new);
+    }
+}
+
+/// Parse the manifest init system, rejecting ones the distro doesn't offer
+fn parse_init(init: Option<&str>, distro: DistroKind) -> Result<Option<InitKind>> {
+    let Some(init) = init else {
+        return Ok(None);
+    };
+
+    let init: InitKind = init.parse()?;
+    distro.check_init(init)?;
+    Ok(Some(init))
+}
+
+/// The manifest's kernel, checked against what the distro packages
+fn parse_kernel(kernel: Option<&str>, distro: DistroKind) -> Result<Option<Kernel>> {
+    let Some(kernel) = kernel else {
+        return Ok(None);
+    };
+
+    let kernel: Kernel = kernel.parse()?;
+    distro.check_kernel(kernel)?;
+    Ok(Some(kernel))
+}
+
+/// Whether to show a boot splash, which needs plymouth from the distro
+fn parse_splash(splash: bool, distro: DistroKind) -> Result<bool> {
+    if splash {
+        if let Err(e) = distro.check_splash() {
+            bail!("boot.splash: {}", e);
+        }
+    }
+    Ok(splash)
+}
+
+/// Fail if the manifest names a repository the distro can't install from
+fn check_repo(mirror: &MirrorConfig, distro: DistroKind) -> Result<()> {
+    if mirror.url.is_some() || mirror.local_path.is_some() {
+        if let Err(e) = distro.check_repo() {
+            bail!("mirror: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// The manifest's boot system, checked against what the distro packages
+fn parse_boot_system(system: &str, distro: DistroKind) -> Result<BootKind> {
+    let boot: BootKind = system.parse()?;
+    if let Err(e) = distro.check_boot(boot) {
+        bail!("boot.system: {}", e);
+    }
+    Ok(boot)
+}
+
+/// The manifest's boot mode, which only dracut-efistub has a choice of
+fn parse_boot_mode(mode: &str, boot: BootKind, secureboot: bool) -> Result<BootMode> {
+    let mode: BootMode = mode.parse()?;
+    if mode != BootMode::Uki && boot != BootKind::DracutEfistub {
+        bail!(
+            "boot.mode {} needs the dracut-efistub boot system",
+            mode.name()
+        );
+    }
+    // Only the kernel is signed; its separate initramfs would go unverified
+    if mode == BootMode::Efistub && secureboot {
+        bail!("boot.mode efistub can't be used with Secure Boot");
+    }
+    Ok(mode)
+}
+
+/// The manifest's extra kernels, which need a UKI apiece
+fn parse_extra_kernels(
+    kernels: &[String],
+    distro: DistroKind,
+    boot: BootKind,
+) -> Result<Vec<Kernel>> {
+    if !kernels.is_empty() && boot != BootKind::DracutEfistub {
+        bail!("boot.extra_kernels needs the dracut-efistub boot system");
+    }
+    kernels
+        .iter()
+        .map(|kernel| Ok(parse_kernel(Some(kernel), distro)?.unwrap_or(Kernel::Linux)))
+        .collect()
+}
+
+/// The detached LUKS header path, checked against the disk it unlocks
+fn detached_header(disk: &DiskConfig, device: &Path) -> Result<Option<PathBuf>> {
+    let Some(header) = &disk.detached_header else {
+        return Ok(None);
+    };
+    if !disk.encryption {
+        bail!("disk.detached_header is set but disk.encryption is off");
+    }
+
+    let header = PathBuf::from(header);
+    crate::crypt::check_detached_header(&header, device)?;
+    Ok(Some(header))
+}
+
+/// The disks besides the first that `disk.devices` mirrors the root
+/// across, each checked to be a whole disk
+fn raid_devices(disk: &DiskConfig, boot: BootKind, swapfile: bool) -> Result<Vec<PathBuf>> {
+    disk::check_raid_devices(disk, boot, swapfile)?;
+    for device in &disk.devices {
+        disk::check_raid_member(Path::new(device))?;
+    }
+    Ok(disk.devices.iter().skip(1).map(PathBuf::from).collect())
+}
+
+/// Parse the manifest filesystem, rejecting subvolumes it can't provide
+fn parse_filesystem(disk: &DiskConfig) -> Result<FilesystemKind> {
+    let filesystem: FilesystemKind = disk.filesystem.parse()?;
+
+    if !disk.subvolumes.is_empty() && !filesystem.create().supports_subvolumes() {
+        bail!(
+            "disk.subvolumes is set but {} does not support subvolumes",
+            filesystem.name()
+        );
+    }
+
+    Ok(filesystem)
+}
+
+/// Build the btrfs layout from manifest subvolumes, falling back to the default
+fn build_btrfs_layout(disk: &DiskConfig) -> Result<BtrfsLayout> {
+    let mut layout = if disk.subvolumes.is_empty() {
+        BtrfsLayout::default()
+    } else {
+        let subvolumes = disk
+            .subvolumes
+            .iter()
+            .map(|s| Subvolume {
+                name: s.name.clone(),
+                mountpoint: s.mountpoint.clone(),
+            })
+            .collect();
+        BtrfsLayout::from_subvolumes(subvolumes)?
+    };
+
+    if let Some(ref compression) = disk.compression {
+        validate_compression(compression)?;
+        layout.compress = compression.clone();
+    }
+    layout.quota = disk.btrfs_quota;
+    layout.snapshots_limit = crate::crypt::check_quota(disk)?;
+
+    Ok(layout)
+}
+
+/// Build the partition layout from the manifest's EFI settings
+fn build_partition_layout(disk: &DiskConfig) -> Result<disk::PartitionLayout> {
+    let mut layout = disk::PartitionLayout {
+        reuse_esp: disk.reuse_esp,
+        secure_erase: disk.secure_erase,
+        ..Default::default()
+    };
+    if let Some(size) = disk.efi_size_mb {
+        layout.efi_size_mb = size;
+    }
+    if let Some(ref label) = disk.efi_label {
+        layout.efi_label = label.clone();
+    }
+    layout.validate()?;
+    Ok(layout)
+}
+
 fn prompt_desktop_config() -> Result<DesktopConfig> {
     println!("\n=== Desktop Environment ===");
 
@@ -278,6 +809,9 @@ fn prompt_swap_config() -> Result<SwapConfig> {
         None
     };
 
+    let hibernation =
+        swapfile_enabled && prompt_yes_no("  Enable hibernation (resume from swapfile)", false)?;
+
     // swappiness (only ask if any swap is enabled)
     let swappiness = if zram_enabled || swapfile_enabled {
         let swap_str = prompt_default("  Swappiness (0-100, lower = prefer RAM)", "20")?;
@@ -292,6 +826,7 @@ fn prompt_swap_config() -> Result<SwapConfig> {
         swapfile_enabled,
         swapfile_size_gb,
         swappiness,
+        hibernation,
     })
 }
 
@@ -330,3 +865,248 @@ fn prompt_microcode() -> Result<bool> {
         CpuVendor::Unknown => Ok(false),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::SubvolumeConfig;
+
+    fn disk(filesystem: &str, subvolumes: Vec<SubvolumeConfig>) -> DiskConfig {
+        DiskConfig {
+            filesystem: filesystem.into(),
+            subvolumes,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn custom_repo_needs_a_bootstrap_that_uses_it() {
+        let mirror = MirrorConfig {
+            url: Some("https://mirror.example/devuan".into()),
+            ..Default::default()
+        };
+        assert!(check_repo(&MirrorConfig::default(), DistroKind::Devuan).is_ok());
+        assert!(check_repo(&mirror, DistroKind::Gentoo).is_ok());
+        let err = check_repo(&mirror, DistroKind::Devuan).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "mirror: Devuan GNU+Linux can't install from a custom repository yet"
+        );
+    }
+
+    #[test]
+    fn splash_needs_plymouth() {
+        assert!(parse_splash(true, DistroKind::Artix).unwrap());
+        assert!(!parse_splash(false, DistroKind::Slackware).unwrap());
+        let err = parse_splash(true, DistroKind::Slackware).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "boot.splash: Slackware Linux does not package plymouth"
+        );
+    }
+
+    #[test]
+    fn detached_header_needs_encryption_and_an_absolute_path() {
+        let device = Path::new("/dev/sda");
+        let mut disk = disk("btrfs", Vec::new());
+        assert_eq!(detached_header(&disk, device).unwrap(), None);
+
+        disk.detached_header = Some("system.hdr".into());
+        let err = detached_header(&disk, device).unwrap_err();
+        assert!(err.to_string().contains("absolute path"));
+
+        disk.encryption = false;
+        let err = detached_header(&disk, device).unwrap_err();
+        assert!(err.to_string().contains("disk.encryption is off"));
+    }
+
+    #[test]
+    fn parse_init_checks_distro_support() {
+        assert_eq!(parse_init(None, DistroKind::Artix).unwrap(), None);
+        assert_eq!(
+            parse_init(Some("openrc"), DistroKind::Artix).unwrap(),
+            Some(InitKind::OpenRC)
+        );
+        assert_eq!(
+            parse_init(Some("runit"), DistroKind::Void).unwrap(),
+            Some(InitKind::Runit)
+        );
+
+        let err = parse_init(Some("openrc"), DistroKind::Void).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Void Linux does not support the openrc"));
+        assert!(parse_init(Some("systemd"), DistroKind::Artix).is_err());
+    }
+
+    #[test]
+    fn parse_kernel_checks_distro_packages() {
+        assert_eq!(parse_kernel(None, DistroKind::Alpine).unwrap(), None);
+        assert_eq!(
+            parse_kernel(Some("linux-zen"), DistroKind::Artix).unwrap(),
+            Some(Kernel::Zen)
+        );
+        assert_eq!(
+            parse_kernel(Some("linux-lts"), DistroKind::Alpine).unwrap(),
+            Some(Kernel::Lts)
+        );
+
+        let err = parse_kernel(Some("linux-hardened"), DistroKind::Void).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Void Linux does not package the linux-hardened kernel. Supported: linux"
+        );
+        assert!(parse_kernel(Some("linux-rt"), DistroKind::Artix).is_err());
+    }
+
+    #[test]
+    fn parse_boot_system_checks_distro_packages() {
+        assert_eq!(
+            parse_boot_system("systemd-boot", DistroKind::Artix).unwrap(),
+            BootKind::MkinitcpioSystemdBoot
+        );
+        assert_eq!(
+            parse_boot_system("dracut-efistub", DistroKind::Devuan).unwrap(),
+            BootKind::DracutEfistub
+        );
+        let err = parse_boot_system("systemd-boot", DistroKind::Devuan).unwrap_err();
+        assert!(err.to_string().starts_with("boot.system: Devuan"));
+    }
+
+    #[test]
+    fn parse_boot_mode_needs_dracut_for_efistub() {
+        assert_eq!(
+            parse_boot_mode("efistub", BootKind::DracutEfistub, false).unwrap(),
+            BootMode::Efistub
+        );
+        assert_eq!(
+            parse_boot_mode("uki", BootKind::MkinitcpioSystemdBoot, true).unwrap(),
+            BootMode::Uki
+        );
+        let err = parse_boot_mode("efistub", BootKind::MkinitcpioSystemdBoot, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "boot.mode efistub needs the dracut-efistub boot system"
+        );
+        assert!(parse_boot_mode("grub", BootKind::DracutEfistub, false).is_err());
+    }
+
+    #[test]
+    fn parse_boot_mode_refuses_efistub_with_secureboot() {
+        let err = parse_boot_mode("efistub", BootKind::DracutEfistub, true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "boot.mode efistub can't be used with Secure Boot"
+        );
+    }
+
+    #[test]
+    fn parse_extra_kernels_needs_dracut() {
+        let kernels = vec!["linux-lts".to_string()];
+        assert_eq!(
+            parse_extra_kernels(&kernels, DistroKind::Artix, BootKind::DracutEfistub).unwrap(),
+            [Kernel::Lts]
+        );
+        assert!(parse_extra_kernels(&kernels, DistroKind::Void, BootKind::DracutEfistub).is_err());
+
+        let err = parse_extra_kernels(&kernels, DistroKind::Artix, BootKind::MkinitcpioSystemdBoot)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "boot.extra_kernels needs the dracut-efistub boot system"
+        );
+        assert!(
+            parse_extra_kernels(&[], DistroKind::Artix, BootKind::MkinitcpioSystemdBoot)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn parse_filesystem_xfs_without_subvolumes() {
+        let kind = parse_filesystem(&disk("xfs", vec![])).unwrap();
+        assert_eq!(kind, FilesystemKind::Xfs);
+    }
+
+    #[test]
+    fn parse_filesystem_rejects_subvolumes_on_xfs() {
+        let subvolumes = vec![SubvolumeConfig {
+            name: "@".into(),
+            mountpoint: "/".into(),
+        }];
+        let err = parse_filesystem(&disk("xfs", subvolumes)).unwrap_err();
+        assert!(err.to_string().contains("does not support subvolumes"));
+    }
+
+    #[test]
+    fn build_btrfs_layout_uses_manifest_subvolumes() {
+        let subvolumes = vec![
+            SubvolumeConfig {
+                name: "@var".into(),
+                mountpoint: "/var".into(),
+            },
+            SubvolumeConfig {
+                name: "@".into(),
+                mountpoint: "/".into(),
+            },
+            SubvolumeConfig {
+                name: "@snapshots".into(),
+                mountpoint: "/.snapshots".into(),
+            },
+        ];
+        let disk = DiskConfig {
+            compression: Some("zstd:5".into()),
+            ..disk("btrfs", subvolumes)
+        };
+        let layout = build_btrfs_layout(&disk).unwrap();
+
+        assert_eq!(layout.subvolumes[0].name, "@");
+        assert_eq!(layout.subvolumes[1].name, "@var");
+        assert_eq!(layout.subvolumes[1].mountpoint, "/var");
+        assert_eq!(layout.compress, "zstd:5");
+    }
+
+    #[test]
+    fn build_partition_layout_takes_efi_settings() {
+        let disk = DiskConfig {
+            efi_size_mb: Some(2048),
+            efi_label: Some("ESP".into()),
+            reuse_esp: true,
+            ..DiskConfig::default()
+        };
+        let layout = build_partition_layout(&disk).unwrap();
+        assert_eq!(layout.efi_size_mb, 2048);
+        assert_eq!(layout.efi_label, "ESP");
+        assert!(layout.reuse_esp);
+
+        let layout = build_partition_layout(&DiskConfig::default()).unwrap();
+        assert_eq!(layout.efi_size_mb, 1024);
+        assert_eq!(layout.efi_label, "MKOS_EFI");
+        assert!(!layout.reuse_esp);
+    }
+
+    #[test]
+    fn build_partition_layout_rejects_small_esp() {
+        let disk = DiskConfig {
+            efi_size_mb: Some(256),
+            ..DiskConfig::default()
+        };
+        let err = build_partition_layout(&disk).unwrap_err();
+        assert!(err.to_string().contains("at least 512"));
+    }
+
+    #[test]
+    fn build_btrfs_layout_rejects_bad_compression() {
+        let disk = DiskConfig {
+            compression: Some("lz4".into()),
+            ..DiskConfig::default()
+        };
+        assert!(build_btrfs_layout(&disk).is_err());
+    }
+
+    #[test]
+    fn parse_filesystem_btrfs_keeps_default_subvolumes() {
+        let kind = parse_filesystem(&DiskConfig::default()).unwrap();
+        assert_eq!(kind, FilesystemKind::Btrfs);
+    }
+}
@@ -7,6 +7,35 @@ pub enum GpuVendor {
     Intel,
 }
 
+impl GpuVendor {
+    /// Open DRM driver the initramfs loads for early KMS
+    pub fn kms_driver(&self) -> &'static str {
+        match self {
+            GpuVendor::Nvidia => "nouveau",
+            GpuVendor::AmdDiscrete => "amdgpu",
+            GpuVendor::Intel => "i915",
+        }
+    }
+}
+
+/// Early KMS drivers for `gpus`, each once
+///
+/// The proprietary NVIDIA driver blacklists nouveau, so that's left out when
+/// it's installed.
+pub fn kms_drivers(gpus: &[GpuVendor], proprietary_nvidia: bool) -> Vec<String> {
+    let mut drivers: Vec<String> = Vec::new();
+    for gpu in gpus {
+        if *gpu == GpuVendor::Nvidia && proprietary_nvidia {
+            continue;
+        }
+        let driver = gpu.kms_driver();
+        if !drivers.iter().any(|d| d == driver) {
+            drivers.push(driver.into());
+        }
+    }
+    drivers
+}
+
 pub fn detect_gpus() -> Vec<GpuVendor> {
     let output = match Command::new("lspci").output() {
         Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout).to_string(),
@@ -20,34 +49,179 @@ pub fn detect_gpus() -> Vec<GpuVendor> {
         }
     };
 
+    parse_lspci(&output)
+}
+
+/// GPUs in `lspci` output, one entry per display device
+fn parse_lspci(output: &str) -> Vec<GpuVendor> {
     let mut gpus = Vec::new();
 
     for line in output.lines() {
-        let line_lower = line.to_lowercase();
-        if line_lower.contains("vga") || line_lower.contains("3d controller") {
-            if line_lower.contains("nvidia") {
-                gpus.push(GpuVendor::Nvidia);
-            } else if line_lower.contains("amd") || line_lower.contains("ati") {
-                if line_lower.contains("radeon")
-                    || line_lower.contains("navi")
-                    || line_lower.contains("vega")
-                {
-                    gpus.push(GpuVendor::AmdDiscrete);
-                }
-            } else if line_lower.contains("intel") {
-                gpus.push(GpuVendor::Intel);
-            }
+        // "00:02.0 VGA compatible controller: Intel Corporation ..."
+        let Some((class, device)) = line.split_once(": ") else {
+            continue;
+        };
+        let class = class.to_lowercase();
+        if !(class.contains("vga")
+            || class.contains("3d controller")
+            || class.contains("display controller"))
+        {
+            continue;
+        }
+
+        // Vendor names are matched whole: "ati" is also in "Corporation"
+        let device = device.to_lowercase();
+        if device.contains("nvidia") {
+            gpus.push(GpuVendor::Nvidia);
+        } else if device.contains("intel") {
+            gpus.push(GpuVendor::Intel);
+        } else if (device.contains("[amd/ati]") || device.contains("advanced micro devices"))
+            && (device.contains("radeon") || device.contains("navi") || device.contains("vega"))
+        {
+            gpus.push(GpuVendor::AmdDiscrete);
         }
     }
 
     gpus
 }
 
+/// An NVIDIA GPU next to another one, as in most laptops
+pub fn is_hybrid(gpus: &[GpuVendor]) -> bool {
+    gpus.contains(&GpuVendor::Nvidia) && gpus.iter().any(|g| *g != GpuVendor::Nvidia)
+}
+
 pub fn get_nvidia_packages() -> Vec<String> {
+    vec!["nvidia".into(), "nvidia-utils".into()]
+}
+
+/// PRIME render offload, so applications can opt into the NVIDIA GPU
+pub fn get_prime_packages() -> Vec<String> {
+    vec!["nvidia-prime".into()]
+}
+
+pub fn get_amd_packages() -> Vec<String> {
+    vec!["mesa".into(), "vulkan-radeon".into()]
+}
+
+pub fn get_intel_packages() -> Vec<String> {
     vec![
-        "nvidia".into(),
-        "nvidia-utils".into(),
-        "nvidia-prime".into(),
-        "lib32-nvidia-utils".into(),
+        "mesa".into(),
+        "vulkan-intel".into(),
+        "intel-media-driver".into(),
     ]
 }
+
+/// 32-bit drivers for the `vendors` whose drivers are installed, for Steam
+/// and Wine; they need the distro's multilib or i386 packages enabled
+pub fn get_lib32_packages(vendors: &[GpuVendor]) -> Vec<String> {
+    let mut packages: Vec<String> = Vec::new();
+    for vendor in vendors {
+        let names: &[&str] = match vendor {
+            GpuVendor::Nvidia => &["lib32-nvidia-utils"],
+            GpuVendor::AmdDiscrete => &["lib32-mesa", "lib32-vulkan-radeon"],
+            GpuVendor::Intel => &["lib32-mesa", "lib32-vulkan-intel"],
+        };
+        for name in names {
+            if !packages.iter().any(|p| p == name) {
+                packages.push(name.to_string());
+            }
+        }
+    }
+    packages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_gpus() {
+        assert_eq!(
+            parse_lspci(
+                "00:02.0 VGA compatible controller: Intel Corporation Alder Lake-P GT2 [Iris Xe Graphics] (rev 0c)"
+            ),
+            [GpuVendor::Intel]
+        );
+        assert_eq!(
+            parse_lspci(
+                "03:00.0 VGA compatible controller: Advanced Micro Devices, Inc. [AMD/ATI] Navi 23 [Radeon RX 6600] (rev c7)"
+            ),
+            [GpuVendor::AmdDiscrete]
+        );
+        assert_eq!(
+            parse_lspci(
+                "01:00.0 VGA compatible controller: NVIDIA Corporation AD104 [GeForce RTX 4070] (rev a1)"
+            ),
+            [GpuVendor::Nvidia]
+        );
+    }
+
+    #[test]
+    fn hybrid_laptop() {
+        let output = "\
+00:00.0 Host bridge: Intel Corporation 12th Gen Core Processor Host Bridge/DRAM Registers (rev 02)
+00:02.0 VGA compatible controller: Intel Corporation Alder Lake-P GT2 [Iris Xe Graphics] (rev 0c)
+00:1f.3 Audio device: Intel Corporation Alder Lake PCH-P High Definition Audio Controller (rev 01)
+01:00.0 3D controller: NVIDIA Corporation GA107M [GeForce RTX 3050 Mobile] (rev a1)
+02:00.0 Non-Volatile memory controller: Samsung Electronics Co Ltd NVMe SSD Controller PM9A1/PM9A3/980PRO
+";
+        let gpus = parse_lspci(output);
+        assert_eq!(gpus, [GpuVendor::Intel, GpuVendor::Nvidia]);
+        assert!(is_hybrid(&gpus));
+    }
+
+    #[test]
+    fn amd_display_controller_with_nvidia() {
+        let output = "\
+01:00.0 VGA compatible controller: NVIDIA Corporation TU117M [GeForce GTX 1650 Mobile / Max-Q] (rev a1)
+05:00.0 Display controller: Advanced Micro Devices, Inc. [AMD/ATI] Renoir [Radeon Vega Series / Radeon Vega Mobile Series] (rev c6)
+";
+        let gpus = parse_lspci(output);
+        assert_eq!(gpus, [GpuVendor::Nvidia, GpuVendor::AmdDiscrete]);
+        assert!(is_hybrid(&gpus));
+    }
+
+    #[test]
+    fn non_gpu_devices_are_ignored() {
+        let output = "\
+00:14.0 USB controller: Intel Corporation Alder Lake PCH USB 3.2 xHCI Host Controller (rev 01)
+00:1f.3 Audio device: Advanced Micro Devices, Inc. [AMD/ATI] Navi 21/23 HDMI/DP Audio Controller
+";
+        assert!(parse_lspci(output).is_empty());
+        assert!(!is_hybrid(&[GpuVendor::Nvidia]));
+        assert!(!is_hybrid(&[GpuVendor::Intel]));
+    }
+
+    #[test]
+    fn lib32_drivers_are_kept_apart() {
+        for packages in [
+            get_nvidia_packages(),
+            get_amd_packages(),
+            get_intel_packages(),
+        ] {
+            assert!(!packages.iter().any(|p| p.starts_with("lib32-")));
+        }
+
+        assert_eq!(
+            get_lib32_packages(&[GpuVendor::Intel, GpuVendor::AmdDiscrete]),
+            ["lib32-mesa", "lib32-vulkan-intel", "lib32-vulkan-radeon"]
+        );
+        assert_eq!(
+            get_lib32_packages(&[GpuVendor::Nvidia]),
+            ["lib32-nvidia-utils"]
+        );
+        assert!(get_lib32_packages(&[]).is_empty());
+    }
+
+    #[test]
+    fn each_vendor_gets_its_kms_driver() {
+        assert_eq!(GpuVendor::Intel.kms_driver(), "i915");
+        assert_eq!(GpuVendor::AmdDiscrete.kms_driver(), "amdgpu");
+        assert_eq!(GpuVendor::Nvidia.kms_driver(), "nouveau");
+
+        let gpus = [GpuVendor::Intel, GpuVendor::Nvidia, GpuVendor::Intel];
+        assert_eq!(kms_drivers(&gpus, false), ["i915", "nouveau"]);
+        assert_eq!(kms_drivers(&gpus, true), ["i915"]);
+        assert!(kms_drivers(&[], false).is_empty());
+    }
+}
@@ -1,27 +1,99 @@
+//! The `mkos-install` front end: `src/bin/install.rs` parses
+//! [`InstallArgs`] and hands them to [`run`], which installs from a
+//! manifest, prompts for whatever it leaves out, or serves headless installs
+//!
+//! `mkos` and `mkos-apply` manage installed systems through
+//! [`crate::commands`] and [`crate::apply`] instead.
+
 mod config;
-mod gpu;
+pub(crate) mod gpu;
 mod prompts;
+mod serve;
 
 use anyhow::Result;
-use std::env;
+use std::path::{Path, PathBuf};
 
-use crate::install::{InstallConfig, Installer};
+use crate::crypt::FilesystemKind;
+use crate::disk;
+use crate::distro::DistroKind;
+use crate::install::{self, InstallConfig, Installer, LiveSystem};
 use crate::manifest::{self, Manifest, ManifestBundle, ManifestSource};
 use crate::mirror;
 
-use config::build_config;
+use config::{build_config, manifest_config};
 use prompts::prompt_raw;
 
-pub fn run() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
-    let source = ManifestSource::from_arg(args.get(1).map(|s| s.as_str()));
+/// Install mkOS, interactively or from a manifest
+#[derive(Debug, clap::Parser)]
+#[command(name = "mkos-install", version)]
+pub struct InstallArgs {
+    /// Log each command and step timing to stderr (same as RUST_LOG=debug)
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Merge this profile from the manifest's `profiles` over the rest of it
+    #[arg(long, value_name = "NAME", requires = "manifest")]
+    pub profile: Option<String>,
+
+    /// Also write the JSON install report here, or to stdout with -
+    #[arg(long, value_name = "PATH")]
+    pub report: Option<PathBuf>,
+
+    /// Keep the install's log here rather than in /var/log/mkos-install.log
+    /// on the target, so it survives an install that fails before mounting
+    #[arg(long, value_name = "PATH", conflicts_with = "serve")]
+    pub log_file: Option<PathBuf>,
+
+    /// Wait on 127.0.0.1 for a manifest POSTed to /install, and stream the
+    /// install's progress back as JSON lines
+    #[arg(
+        long,
+        value_name = "PORT",
+        num_args = 0..=1,
+        default_missing_value = "7433",
+        conflicts_with_all = ["manifest", "profile"]
+    )]
+    pub serve: Option<u16>,
+
+    /// Install to a new sparse disk image at this path instead of a disk,
+    /// through a loop device
+    #[arg(long, value_name = "PATH", requires = "size", conflicts_with = "serve")]
+    pub image: Option<PathBuf>,
+
+    /// Check the manifest and this machine without the network or touching
+    /// a disk, report whether the install can go ahead, and exit
+    #[arg(long, conflicts_with_all = ["serve", "image"])]
+    pub preflight: bool,
+
+    /// Size of the --image file (e.g., 40G)
+    #[arg(long, value_name = "SIZE", requires = "image", value_parser = disk::parse_size)]
+    pub size: Option<u64>,
+
+    /// Manifest file or tar bundle, comma-separated URLs, or - for stdin;
+    /// prompts for everything when omitted
+    pub manifest: Option<String>,
+}
+
+pub fn run(args: InstallArgs) -> Result<()> {
+    if let Some(port) = args.serve {
+        let report = serve::serve(port)?;
+        if let Some(path) = &args.report {
+            report.write(path)?;
+        }
+        return Ok(());
+    }
+
+    let source = ManifestSource::from_arg(args.manifest.as_deref());
+    let profile = args.profile.clone();
 
     println!("\n=== mkOS Installer ===\n");
-    println!("This will install mkOS with:");
-    println!("  - LUKS2 encryption (Argon2id)");
-    println!("  - btrfs with subvolumes");
-    println!("  - Your choice of distribution");
-    println!("  - EFISTUB boot\n");
+    if !args.preflight {
+        println!("This will install mkOS with:");
+        println!("  - LUKS2 encryption (Argon2id)");
+        println!("  - btrfs with subvolumes");
+        println!("  - Your choice of distribution");
+        println!("  - EFISTUB boot\n");
+    }
 
     // Load manifest
     let bundle = match &source {
@@ -36,8 +108,8 @@ pub fn run() -> Result<()> {
             println!("Loading manifest from: {}\n", path.display());
             manifest::load(&source)?
         }
-        ManifestSource::Url(url) => {
-            println!("Loading manifest from: {}\n", url);
+        ManifestSource::Url(urls) => {
+            println!("Loading manifest from: {}\n", urls.join(", "));
             manifest::load(&source)?
         }
         ManifestSource::Stdin => {
@@ -46,10 +118,92 @@ pub fn run() -> Result<()> {
         }
     };
 
-    let manifest = bundle.manifest;
+    let manifest = match &profile {
+        Some(name) => {
+            println!("Using profile: {}\n", name);
+            bundle.manifest.with_profile(name)?
+        }
+        None => bundle.manifest,
+    };
+    for warning in manifest.unknown_field_warnings() {
+        println!("Warning: {}", warning);
+    }
+
+    if args.preflight {
+        return preflight(&manifest);
+    }
+
+    let (Some(image), Some(size)) = (&args.image, args.size) else {
+        return install(&source, manifest, &args, None);
+    };
+
+    disk::create_image(image, size)?;
+    disk::with_loop_device(&disk::Losetup, image, |device| {
+        println!(
+            "Installing to {} through {}\n",
+            image.display(),
+            device.display()
+        );
+        let mut manifest = manifest;
+        manifest.disk.device = Some(device.to_string_lossy().into_owned());
+        install(&source, manifest, &args, Some(image))
+    })
+}
+
+/// Check `manifest` and this machine with everything that needs no network,
+/// so a machine can be vetted before it's online or its disk is wiped
+fn preflight(manifest: &Manifest) -> Result<()> {
+    println!("=== Preflight (offline) ===\n");
+
+    let device = manifest
+        .disk
+        .device
+        .as_ref()
+        .or(manifest.disk.devices.first())
+        .map_or("", String::as_str);
+
+    // The config can't be built from a manifest that doesn't validate
+    let mut failures = manifest.validate();
+    if failures.is_empty() {
+        match manifest_config(manifest, device) {
+            Ok(config) => failures.extend(install::preflight_offline(&config, &install::Host)),
+            Err(e) => failures.push(format!("{:#}", e)),
+        }
+    }
+    install::check_all(failures)?;
+
+    if device.is_empty() {
+        println!("The manifest names no disk, so no disk was checked.");
+    }
+    println!("Ready to install; the package repository is checked once online.");
+    Ok(())
+}
 
+/// Configure and run the install onto `manifest`'s disk, or the one picked
+/// interactively
+fn install(
+    source: &ManifestSource,
+    manifest: Manifest,
+    args: &InstallArgs,
+    image: Option<&Path>,
+) -> Result<()> {
     // Collect missing configuration interactively
-    let config = build_config(&manifest)?;
+    let mut config = build_config(&manifest)?;
+    config.image = image.map(Path::to_path_buf);
+
+    // Only pacman has a mirrorlist; other distros take mirror.url or
+    // mirror.local_path as their repository
+    if config.distro == DistroKind::Artix {
+        println!("\n=== Mirror Selection ===");
+        config.selected_mirror = match mirror::choose_mirror(&config.mirror) {
+            Err(e) if mirror::is_configured(&config.mirror) => return Err(e),
+            Err(e) => {
+                println!("Warning: Could not choose a mirror: {}", e);
+                None
+            }
+            Ok(mirror) => mirror,
+        };
+    }
 
     // Show summary and confirm
     print_summary(&config);
@@ -60,21 +214,59 @@ pub fn run() -> Result<()> {
         return Ok(());
     }
 
-    // Select mirror
-    println!("\n=== Mirror Selection ===");
-    if let Err(e) = mirror::setup_mirror() {
-        println!("Warning: Could not configure mirror: {}", e);
-        println!("Continuing with default mirror...");
+    if let Some(mirror) = &config.selected_mirror {
+        match mirror::setup_mirror(mirror) {
+            // Falling back to the default mirror would defeat an offline install
+            Err(e) if mirror::is_configured(&config.mirror) => return Err(e),
+            Err(e) => {
+                println!("Warning: Could not configure mirror: {}", e);
+                println!("Continuing with default mirror...");
+            }
+            Ok(()) => {}
+        }
+    }
+    if let Some(downloads) = config.pacman_parallel_downloads() {
+        mirror::setup_parallel_downloads(downloads)?;
     }
 
     // Run install
     println!("\n=== Installing ===\n");
-    let installer = Installer::new(config);
-    installer.run()?;
+    let mut installer = Installer::new(config);
+    if !matches!(source, ManifestSource::Interactive) {
+        installer = installer.with_manifest(manifest);
+    }
+    if let Some(path) = &args.log_file {
+        installer = installer.with_log_file(path.clone());
+    }
+    let result = installer.run();
+
+    // The loop device can't detach while the target is mounted
+    if image.is_some() {
+        let released = installer.release();
+        if result.is_ok() {
+            released?;
+        }
+    }
+    let report = result?;
 
     println!("\n=== Installation Complete ===\n");
-    println!("You can now reboot into your new system.");
-    println!("Remember to remove the installation media.\n");
+    match image {
+        Some(image) => {
+            println!(
+                "Boot {} in a VM with UEFI firmware (e.g., OVMF).",
+                image.display()
+            );
+            println!("It has no boot entries; the EFI shell runs /startup.nsh from the ESP.\n");
+        }
+        None => {
+            println!("You can now reboot into your new system.");
+            println!("Remember to remove the installation media.\n");
+        }
+    }
+
+    if let Some(path) = &args.report {
+        report.write(path)?;
+    }
 
     Ok(())
 }
@@ -82,11 +274,70 @@ pub fn run() -> Result<()> {
 fn print_summary(config: &InstallConfig) {
     println!("\n=== Summary ===");
     println!("  Device:     {}", config.device.display());
+    for device in &config.raid_devices {
+        println!("  RAID1 with: {}", device.display());
+    }
+    if config.partition_layout.reuse_esp {
+        println!("  Partitions: kept; mkOS takes partition 2, sharing partition 1's ESP");
+    } else {
+        for device in config.disks() {
+            let Ok(contents) = disk::probe(&device.to_string_lossy()) else {
+                continue;
+            };
+            if let Some(warning) = contents.warning() {
+                println!("  WARNING:    {} - it will be erased", warning);
+            }
+        }
+    }
+    let required = install::required_bytes(config);
+    match install::Host.disk_size(&config.device) {
+        Ok(available) if config.image.is_none() => {
+            println!(
+                "  Space:      {}",
+                install::space_summary(required, available)
+            );
+        }
+        _ => println!("  Space:      ~{} required", install::format_gb(required)),
+    }
     println!("  Hostname:   {}", config.hostname);
     println!("  Timezone:   {}", config.timezone);
     println!("  Locale:     {}", config.locale);
     println!("  Keymap:     {}", config.keymap);
-    println!("  Distro:     {:?}", config.distro);
+    println!("  Distro:     {}", config.distro.name());
+    if let Some(init) = config.init {
+        println!("  Init:       {}", init.name());
+    }
+    if let Some(mirror) = &config.selected_mirror {
+        println!("  Mirror:     {} ({})", mirror.name, mirror.url);
+    } else if let Ok(Some(repo)) = mirror::Repo::from_config(&config.mirror) {
+        println!("  Mirror:     {}", repo.location());
+    }
+    println!("  Filesystem: {}", config.filesystem.name());
+    if !config.encryption {
+        println!("  Encryption: off");
+    }
+    println!("  Boot: {}", config.boot.name());
+    if !config.kernel_cmdline.is_empty() {
+        println!("  Kernel cmdline: {}", config.kernel_cmdline.join(" "));
+    }
+    if config.secureboot.enabled {
+        let enroll = if config.secureboot.enroll {
+            " (enroll in Setup Mode)"
+        } else {
+            ""
+        };
+        println!("  Secure Boot: enabled{}", enroll);
+    }
+    if config.filesystem == FilesystemKind::Btrfs {
+        let subvolumes: Vec<_> = config
+            .btrfs_layout
+            .subvolumes
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect();
+        println!("  Subvolumes: {}", subvolumes.join(", "));
+        println!("  Compression: {}", config.btrfs_layout.compress);
+    }
     println!(
         "  Networking: {}",
         if config.enable_networking {
@@ -95,6 +346,9 @@ fn print_summary(config: &InstallConfig) {
             "disabled"
         }
     );
+    if let Some(wifi) = &config.network.wifi {
+        println!("  WiFi:       {}", wifi.ssid);
+    }
     if config.desktop.enabled {
         let seat_mgr = config.desktop.seat_manager.as_deref().unwrap_or("seatd");
         println!("  Desktop:    enabled ({}, polkit)", seat_mgr);
@@ -133,6 +387,9 @@ fn print_summary(config: &InstallConfig) {
             swap_parts.join(" + "),
             config.swap.swappiness
         );
+        if config.swap.hibernation {
+            println!("  Hibernate:  enabled (resume from swapfile)");
+        }
     } else {
         println!("  Swap:       disabled");
     }
@@ -165,3 +422,78 @@ fn print_summary(config: &InstallConfig) {
         config.device.display()
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn run_takes_the_parsed_install_args() {
+        // The mkos-install binary calls this; it must keep building against
+        // the current config types
+        let _: fn(InstallArgs) -> Result<()> = run;
+    }
+
+    #[test]
+    fn install_args_keep_the_positional_manifest() {
+        let args = InstallArgs::try_parse_from(["mkos-install"]).unwrap();
+        assert!(args.manifest.is_none());
+
+        let args =
+            InstallArgs::try_parse_from(["mkos-install", "-v", "--profile", "laptop", "site.yaml"])
+                .unwrap();
+        assert!(args.verbose);
+        assert_eq!(args.profile.as_deref(), Some("laptop"));
+        assert_eq!(args.manifest.as_deref(), Some("site.yaml"));
+
+        assert!(InstallArgs::try_parse_from(["mkos-install", "-"]).is_ok());
+        assert!(InstallArgs::try_parse_from(["mkos-install", "--profile", "laptop"]).is_err());
+    }
+
+    #[test]
+    fn preflight_is_its_own_mode() {
+        let args =
+            InstallArgs::try_parse_from(["mkos-install", "--preflight", "site.yaml"]).unwrap();
+        assert!(args.preflight);
+        assert!(InstallArgs::try_parse_from(["mkos-install", "--preflight", "--serve"]).is_err());
+    }
+
+    #[test]
+    fn image_needs_a_size() {
+        let args =
+            InstallArgs::try_parse_from(["mkos-install", "--image", "out.img", "--size", "40G"])
+                .unwrap();
+        assert_eq!(args.image.as_deref(), Some(Path::new("out.img")));
+        assert_eq!(args.size, Some(40 << 30));
+
+        assert!(InstallArgs::try_parse_from(["mkos-install", "--image", "out.img"]).is_err());
+        assert!(InstallArgs::try_parse_from(["mkos-install", "--size", "40G"]).is_err());
+        assert!(InstallArgs::try_parse_from([
+            "mkos-install",
+            "--image",
+            "out.img",
+            "--size",
+            "40Q"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn serve_takes_an_optional_port() {
+        let args = InstallArgs::try_parse_from(["mkos-install", "--serve"]).unwrap();
+        assert_eq!(args.serve, Some(7433));
+
+        let args = InstallArgs::try_parse_from(["mkos-install", "--serve", "8000"]).unwrap();
+        assert_eq!(args.serve, Some(8000));
+
+        assert!(InstallArgs::try_parse_from(["mkos-install", "--serve=80", "site.yaml"]).is_err());
+        assert!(InstallArgs::try_parse_from([
+            "mkos-install",
+            "--serve",
+            "--log-file",
+            "install.log"
+        ])
+        .is_err());
+    }
+}
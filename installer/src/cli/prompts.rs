@@ -3,7 +3,11 @@ use std::io::{self, Write};
 
 use crate::disk::BlockDevice;
 use crate::distro::DistroKind;
-use crate::prompt::{self, FieldSpec, FieldValue};
+use crate::manifest::{
+    FirewallConfig, NetworkConfig, PassphrasePolicy, Preset, SshConfig, WifiConfig,
+};
+use crate::prompt::{self, FieldSpec, FieldValue, SelectOption};
+use crate::secret::Secret;
 
 pub fn prompt_seat_manager() -> Result<Option<String>> {
     println!("\nSeat manager options:");
@@ -61,6 +65,19 @@ pub fn prompt_greeter(dm: Option<&str>) -> Result<Option<String>> {
     }
 }
 
+/// A package preset, or None to install only the base system
+pub fn prompt_preset() -> Result<Option<Preset>> {
+    let options = Preset::ALL
+        .iter()
+        .map(|p| SelectOption::new(p.name(), p.name()).with_description(p.description()))
+        .collect();
+    let spec = FieldSpec::optional_select("preset", "Package preset", options);
+    match prompt::prompt_field(&spec)? {
+        FieldValue::Text(name) => Ok(Some(name.parse()?)),
+        _ => Ok(None),
+    }
+}
+
 pub fn select_device(devices: &[BlockDevice]) -> Result<&BlockDevice> {
     loop {
         let input = prompt_raw(&format!("Select disk [1-{}]: ", devices.len()))?;
@@ -94,24 +111,35 @@ pub fn prompt_default(name: &str, default: &str) -> Result<String> {
     }
 }
 
+/// Pick one of `options`, narrowing the list as the user types
+pub fn prompt_search(name: &str, options: Vec<String>, default: &str) -> Result<String> {
+    let spec = FieldSpec::search("_inline", name, options, default);
+    match prompt::prompt_field(&spec)? {
+        FieldValue::Text(s) => Ok(s),
+        _ => Ok(default.to_string()),
+    }
+}
+
 pub fn prompt_yes_no(name: &str, default: bool) -> Result<bool> {
     prompt::prompt_yes_no(name, default)
 }
 
-pub fn prompt_passphrase() -> Result<String> {
+pub fn prompt_passphrase(policy: &PassphrasePolicy) -> Result<Secret> {
     loop {
         let pass1 = rpassword::prompt_password("Encryption passphrase: ")
+            .map(Secret::from)
             .map_err(|e| anyhow::anyhow!("Failed to read passphrase: {}", e))?;
 
-        if pass1.len() < 8 {
-            println!("Passphrase must be at least 8 characters");
+        if let Err(e) = crate::crypt::validate_passphrase(pass1.expose(), policy) {
+            println!("{}", e);
             continue;
         }
 
         let pass2 = rpassword::prompt_password("Confirm passphrase: ")
+            .map(Secret::from)
             .map_err(|e| anyhow::anyhow!("Failed to read passphrase: {}", e))?;
 
-        if pass1 != pass2 {
+        if pass1.expose() != pass2.expose() {
             println!("Passphrases do not match");
             continue;
         }
@@ -120,10 +148,59 @@ pub fn prompt_passphrase() -> Result<String> {
     }
 }
 
-pub fn prompt_password_confirm(name: &str) -> Result<String> {
+pub fn prompt_wifi() -> Result<WifiConfig> {
+    loop {
+        let ssid = prompt_raw("WiFi SSID: ")?;
+        let psk = rpassword::prompt_password("WiFi passphrase: ")
+            .map_err(|e| anyhow::anyhow!("Failed to read passphrase: {}", e))?;
+
+        let wifi = WifiConfig { ssid, psk };
+        match crate::network::validate_wifi(&wifi) {
+            Ok(()) => return Ok(wifi),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Ask which network services to run; interfaces and WiFi are left unset
+pub fn prompt_network_config() -> Result<NetworkConfig> {
+    println!("\n=== Network Services ===");
+
+    let ssh = prompt_yes_no("Enable SSH server", false)?;
+    let mdns = prompt_yes_no("Enable mDNS (.local hostnames)", true)?;
+
+    Ok(NetworkConfig {
+        mdns,
+        ssh: ssh.then(SshConfig::default),
+        ..Default::default()
+    })
+}
+
+/// Ask whether to run the firewall, opening the ports `network` serves on
+pub fn prompt_firewall_config(network: &NetworkConfig) -> Result<FirewallConfig> {
+    if !prompt_yes_no("Enable firewall (drop incoming connections)", true)? {
+        return Ok(FirewallConfig::default());
+    }
+
+    let mut firewall = FirewallConfig {
+        enabled: true,
+        ..Default::default()
+    };
+    if let Some(ssh) = network.ssh.as_ref().filter(|ssh| ssh.enabled) {
+        println!("  Allowing SSH (tcp {})", ssh.port);
+        crate::firewall::allow_ssh(&mut firewall, ssh.port);
+    }
+    if network.mdns {
+        println!("  Allowing mDNS (udp {})", crate::firewall::MDNS_PORT);
+        crate::firewall::allow_mdns(&mut firewall);
+    }
+    Ok(firewall)
+}
+
+pub fn prompt_password_confirm(name: &str) -> Result<Secret> {
     let spec = FieldSpec::password_confirm("_inline", name);
     match prompt::prompt_field(&spec)? {
-        FieldValue::Text(s) => Ok(s),
+        FieldValue::Text(s) => Ok(s.into()),
         _ => bail!("Password is required"),
     }
 }
@@ -136,9 +213,10 @@ pub fn prompt_distro() -> Result<DistroKind> {
     println!("  [4] Alpine Linux (lightweight, musl, OpenRC)");
     println!("  [5] Slackware Linux (oldest active distro, SysVinit)");
     println!("  [6] Devuan GNU+Linux (systemd-free Debian, SysVinit)");
+    println!("  [7] openSUSE Tumbleweed (rolling RPM, zypper, runit)");
 
     loop {
-        let input = prompt_raw("Select distribution [1-6]: ")?;
+        let input = prompt_raw("Select distribution [1-7]: ")?;
         match input.as_str() {
             "1" => return Ok(DistroKind::Artix),
             "2" => return Ok(DistroKind::Void),
@@ -146,7 +224,8 @@ pub fn prompt_distro() -> Result<DistroKind> {
             "4" => return Ok(DistroKind::Alpine),
             "5" => return Ok(DistroKind::Slackware),
             "6" => return Ok(DistroKind::Devuan),
-            _ => println!("Invalid selection. Please enter 1-6."),
+            "7" => return Ok(DistroKind::OpenSuse),
+            _ => println!("Invalid selection. Please enter 1-7."),
         }
     }
 }
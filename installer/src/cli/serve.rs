@@ -0,0 +1,485 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+use crate::distro::DistroKind;
+use crate::install::{InstallConfig, InstallObserver, InstallReport, InstallStep, Installer};
+use crate::manifest::{self, Manifest};
+use crate::mirror;
+use crate::secret::Secret;
+
+use super::config::headless_config;
+
+/// Largest request body accepted; manifests are a few kilobytes
+const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
+/// Header lines read before giving up on a request
+const MAX_HEADERS: usize = 64;
+
+/// How long a client may stall a read or write before it's dropped, so one
+/// that never sends its request can't hold up the others
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What a provisioning system POSTs to `/install`
+///
+/// The manifest is the text of a manifest in any supported format. The
+/// secrets travel beside it since manifests never carry them.
+#[derive(Debug, Deserialize)]
+struct InstallRequest {
+    manifest: String,
+    #[serde(default)]
+    profile: Option<String>,
+    #[serde(default)]
+    passphrase: Secret,
+    root_password: Secret,
+}
+
+/// A line of the response stream
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    StepStart {
+        step: usize,
+        description: &'a str,
+    },
+    Log {
+        line: &'a str,
+    },
+    StepEnd {
+        step: usize,
+        ok: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    Complete {
+        report: &'a InstallReport,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// Writes install progress to the client as newline-delimited JSON
+struct NdjsonObserver<W>(Arc<Mutex<W>>);
+
+impl<W: Write> NdjsonObserver<W> {
+    /// Send one event; a client that hung up doesn't stop the install
+    fn send(&self, event: &Event) {
+        let Ok(mut line) = serde_json::to_vec(event) else {
+            return;
+        };
+        line.push(b'\n');
+        if let Ok(mut writer) = self.0.lock() {
+            let _ = writer.write_all(&line).and_then(|()| writer.flush());
+        }
+    }
+}
+
+impl<W: Write + Send> InstallObserver for NdjsonObserver<W> {
+    fn on_step_start(&self, step: InstallStep) {
+        self.send(&Event::StepStart {
+            step: step.number(),
+            description: step.description(),
+        });
+    }
+
+    fn on_log(&self, line: &str) {
+        let line = line.trim_matches('\n');
+        if !line.trim().is_empty() {
+            self.send(&Event::Log { line });
+        }
+    }
+
+    fn on_step_end(&self, step: InstallStep, result: &Result<()>) {
+        self.send(&Event::StepEnd {
+            step: step.number(),
+            ok: result.is_ok(),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+        });
+    }
+}
+
+/// Wait on localhost `port` for one install request, run it, and stream its
+/// progress back
+///
+/// Requests that can't be decoded are answered with 400 and the server keeps
+/// waiting, as it does when a client's connection fails; once an install
+/// starts, its outcome ends the server.
+pub fn serve(port: u16) -> Result<InstallReport> {
+    if !nix::unistd::Uid::effective().is_root() {
+        bail!("mkos-install --serve must be run as root");
+    }
+
+    // Never reachable from the network: whoever can connect can wipe a disk
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .with_context(|| format!("Failed to listen on 127.0.0.1:{}", port))?;
+    println!(
+        "Waiting for an install request on http://127.0.0.1:{}/install",
+        port
+    );
+
+    for stream in listener.incoming() {
+        // One client's broken connection doesn't stop the others
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let timeouts = stream
+            .set_read_timeout(Some(CLIENT_TIMEOUT))
+            .and_then(|()| stream.set_write_timeout(Some(CLIENT_TIMEOUT)));
+        if let Err(e) = timeouts {
+            eprintln!("Failed to set connection timeouts: {}", e);
+            continue;
+        }
+        let reader = match stream.try_clone() {
+            Ok(stream) => BufReader::new(stream),
+            Err(e) => {
+                eprintln!("Failed to read from connection: {}", e);
+                continue;
+            }
+        };
+        let writer = Arc::new(Mutex::new(stream));
+
+        match handle(reader, writer, install)? {
+            Some(report) => return Ok(report),
+            None => println!("Rejected a request; still waiting"),
+        }
+    }
+    bail!("Stopped listening before an install request arrived")
+}
+
+/// Decode one request from `reader` and hand it to `install`, streaming
+/// progress to `writer`
+///
+/// `None` when the request was rejected, or the client couldn't be answered,
+/// without touching the disk.
+fn handle<R, W, F>(reader: R, writer: Arc<Mutex<W>>, install: F) -> Result<Option<InstallReport>>
+where
+    R: BufRead,
+    W: Write + Send + 'static,
+    F: FnOnce(InstallConfig, Manifest, Box<dyn InstallObserver>) -> Result<InstallReport>,
+{
+    let decoded = read_request(reader).and_then(|request| decode(&request));
+    let (config, manifest) = match decoded {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            let message = format!("{:#}\n", e);
+            let response = format!(
+                "HTTP/1.1 400 Bad Request\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                message.len(),
+                message
+            );
+            if let Err(e) = respond(&writer, response.as_bytes()) {
+                eprintln!("Failed to answer a bad request: {:#}", e);
+            }
+            return Ok(None);
+        }
+    };
+
+    // No Content-Length: the stream runs until the install ends
+    if let Err(e) = respond(
+        &writer,
+        b"HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nConnection: close\r\n\r\n",
+    ) {
+        eprintln!("Failed to answer an install request: {:#}", e);
+        return Ok(None);
+    }
+
+    let events = NdjsonObserver(writer.clone());
+    for warning in manifest.unknown_field_warnings() {
+        events.on_log(&format!("Warning: {}", warning));
+    }
+
+    let result = install(config, manifest, Box::new(NdjsonObserver(writer)));
+    match &result {
+        Ok(report) => events.send(&Event::Complete { report }),
+        Err(e) => events.send(&Event::Failed {
+            error: format!("{:#}", e),
+        }),
+    }
+    result.map(Some)
+}
+
+/// Write `response` to the client
+fn respond<W: Write>(writer: &Mutex<W>, response: &[u8]) -> Result<()> {
+    let mut writer = writer
+        .lock()
+        .map_err(|_| anyhow!("Response writer was poisoned"))?;
+    writer.write_all(response)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read a `POST /install` request and its JSON body
+fn read_request(mut reader: impl BufRead) -> Result<InstallRequest> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    if (parts.next(), parts.next()) != (Some("POST"), Some("/install")) {
+        bail!("Only POST /install is served");
+    }
+
+    let mut length = None;
+    for _ in 0..MAX_HEADERS {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            let length: usize = length.context("Content-Length is required")?;
+            if length > MAX_REQUEST_BYTES {
+                bail!("Request body is over {} bytes", MAX_REQUEST_BYTES);
+            }
+
+            // The body carries the passphrase and root password
+            let mut body = Zeroizing::new(vec![0; length]);
+            reader
+                .read_exact(&mut body)
+                .context("Request body is shorter than its Content-Length")?;
+            return serde_json::from_slice(&body).context("Invalid install request");
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = Some(value.trim().parse().context("Invalid Content-Length")?);
+            }
+        }
+    }
+    bail!("Request has more than {} headers", MAX_HEADERS)
+}
+
+/// The install config a request asks for, and the manifest it came from
+fn decode(request: &InstallRequest) -> Result<(InstallConfig, Manifest)> {
+    let mut manifest = manifest::parse(&request.manifest)?;
+    if let Some(profile) = &request.profile {
+        manifest = manifest.with_profile(profile)?;
+    }
+
+    let config = headless_config(
+        &manifest,
+        request.passphrase.clone(),
+        request.root_password.clone(),
+    )?;
+    Ok((config, manifest))
+}
+
+/// Run the install on this machine
+fn install(
+    mut config: InstallConfig,
+    manifest: Manifest,
+    observer: Box<dyn InstallObserver>,
+) -> Result<InstallReport> {
+    // Mirror selection only runs unprompted when the manifest configures it
+    if config.distro == DistroKind::Artix && mirror::is_configured(&config.mirror) {
+        config.selected_mirror = mirror::choose_mirror(&config.mirror)?;
+        if let Some(mirror) = &config.selected_mirror {
+            mirror::setup_mirror(mirror)?;
+        }
+    }
+    if let Some(downloads) = config.pacman_parallel_downloads() {
+        mirror::setup_parallel_downloads(downloads)?;
+    }
+
+    Installer::new(config)
+        .with_observer(observer)
+        .with_manifest(manifest)
+        .run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const MANIFEST: &str =
+        "distro: void\ndisk:\n  device: /dev/vda\nsystem:\n  hostname: node1\nsytem: {}\n";
+
+    fn post(body: &str) -> Cursor<Vec<u8>> {
+        Cursor::new(
+            format!(
+                "POST /install HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .into_bytes(),
+        )
+    }
+
+    fn request(manifest: &str) -> String {
+        serde_json::json!({
+            "manifest": manifest,
+            "passphrase": "correct horse",
+            "root_password": "toor",
+        })
+        .to_string()
+    }
+
+    /// Response body lines, parsed
+    fn events(response: &[u8]) -> Vec<serde_json::Value> {
+        let response = String::from_utf8_lossy(response);
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        body.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn manifest_is_decoded_and_installed() {
+        let writer = Arc::new(Mutex::new(Vec::new()));
+        let report = handle(
+            post(&request(MANIFEST)),
+            writer.clone(),
+            |config, _, observer| {
+                assert_eq!(config.device.to_str(), Some("/dev/vda"));
+                assert_eq!(config.distro, DistroKind::Void);
+                assert_eq!(config.hostname, "node1");
+                assert_eq!(config.passphrase.expose(), "correct horse");
+                assert_eq!(config.root_password.expose(), "toor");
+
+                observer.on_step_start(InstallStep::Partition);
+                observer.on_log("  Wiping /dev/vda");
+                observer.on_step_end(InstallStep::Partition, &Ok(()));
+                Ok(InstallReport {
+                    hostname: config.hostname,
+                    ..Default::default()
+                })
+            },
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(report.hostname, "node1");
+
+        let events = events(&writer.lock().unwrap());
+        let kinds: Vec<&str> = events
+            .iter()
+            .map(|e| e["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds, ["log", "step_start", "log", "step_end", "complete"]);
+        assert_eq!(
+            events[0]["line"],
+            "Warning: Unknown manifest field sytem was ignored"
+        );
+        assert_eq!(events[1]["step"], 1);
+        assert_eq!(events[1]["description"], "Partitioning disk");
+        assert_eq!(events[3]["ok"], true);
+        assert_eq!(events[4]["report"]["hostname"], "node1");
+    }
+
+    #[test]
+    fn failed_install_is_streamed() {
+        let writer = Arc::new(Mutex::new(Vec::new()));
+        let result = handle(
+            post(&request(MANIFEST)),
+            writer.clone(),
+            |_, _, observer| {
+                let err = anyhow::anyhow!("sgdisk failed");
+                observer.on_step_start(InstallStep::Partition);
+                observer.on_step_end(
+                    InstallStep::Partition,
+                    &Err(anyhow::anyhow!("sgdisk failed")),
+                );
+                Err(err)
+            },
+        );
+        assert!(result.is_err());
+
+        let events = events(&writer.lock().unwrap());
+        let last = &events[events.len() - 2..];
+        assert_eq!(last[0]["ok"], false);
+        assert_eq!(last[0]["error"], "sgdisk failed");
+        assert_eq!(last[1]["event"], "failed");
+    }
+
+    #[test]
+    fn bad_requests_are_rejected_before_installing() {
+        let no_device = request("distro: void\n");
+        let bad_profile = serde_json::json!({
+            "manifest": MANIFEST,
+            "profile": "laptop",
+            "root_password": "toor",
+        })
+        .to_string();
+
+        for input in [
+            post(&no_device),
+            post(&bad_profile),
+            post("{\"manifest\": \"distro: void\"}"),
+            Cursor::new(b"GET /install HTTP/1.1\r\n\r\n".to_vec()),
+            Cursor::new(b"POST /install HTTP/1.1\r\n\r\n".to_vec()),
+        ] {
+            let writer = Arc::new(Mutex::new(Vec::new()));
+            let result = handle(input, writer.clone(), |_, _, _| panic!("installed"));
+            assert!(result.unwrap().is_none());
+
+            let response = writer.lock().unwrap();
+            assert!(response.starts_with(b"HTTP/1.1 400 Bad Request"));
+        }
+    }
+
+    /// A client that hung up
+    struct HungUp;
+
+    impl Write for HungUp {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn client_that_hung_up_is_not_installed_for() {
+        for input in [post(&request(MANIFEST)), post("{}")] {
+            let result = handle(input, Arc::new(Mutex::new(HungUp)), |_, _, _| {
+                panic!("installed")
+            });
+            assert!(result.unwrap().is_none());
+        }
+    }
+
+    /// A client that stopped sending, as a socket read timeout reports it
+    struct Stalled;
+
+    impl std::io::Read for Stalled {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    #[test]
+    fn stalled_client_is_rejected() {
+        use std::io::Read;
+
+        let input = Cursor::new(b"POST /install HTTP/1.1\r\nHost: localhost\r\n".to_vec());
+        let writer = Arc::new(Mutex::new(Vec::new()));
+        let result = handle(
+            BufReader::new(input.chain(Stalled)),
+            writer.clone(),
+            |_, _, _| panic!("installed"),
+        );
+        assert!(result.unwrap().is_none());
+        assert!(writer
+            .lock()
+            .unwrap()
+            .starts_with(b"HTTP/1.1 400 Bad Request"));
+    }
+
+    #[test]
+    fn oversized_bodies_are_refused() {
+        let input = format!(
+            "POST /install HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_REQUEST_BYTES + 1
+        );
+        let err = read_request(Cursor::new(input.into_bytes())).unwrap_err();
+        assert!(err.to_string().contains("over"));
+    }
+}
@@ -1,9 +1,96 @@
 use anyhow::{Context, Result};
 use std::ffi::OsStr;
-use std::process::{Command, Stdio};
+use std::fmt;
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::debug;
 
-const CYAN: &str = "\x1b[36m";
-const RESET: &str = "\x1b[0m";
+/// Lines of stderr kept in a [`CmdError`]
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Upper bound on captured stderr; older output is dropped first
+const STDERR_CAPTURE_BYTES: usize = 64 * 1024;
+
+/// Where command output is copied besides the terminal, if anywhere
+static LOG: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+
+/// Copy the output of every command run from now on, and tracing events
+/// through [`LogWriter`], to `log`; None stops copying
+pub fn set_log(log: Option<Box<dyn Write + Send>>) {
+    *LOG.lock().unwrap_or_else(|e| e.into_inner()) = log;
+}
+
+/// Whether [`set_log`] has somewhere to copy to
+fn logging() -> bool {
+    LOG.lock().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+fn write_log(bytes: &[u8]) {
+    if let Some(log) = LOG.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        let _ = log.write_all(bytes);
+    }
+}
+
+/// Writes to the log set with [`set_log`], for a tracing subscriber
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        write_log(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A command that ran but exited unsuccessfully
+#[derive(Debug, Clone)]
+pub struct CmdError {
+    pub program: String,
+    pub args: Vec<String>,
+    /// None when the process was killed by a signal
+    pub code: Option<i32>,
+    /// Last lines the command wrote to stderr
+    pub stderr_tail: Vec<String>,
+}
+
+impl CmdError {
+    fn new<S: AsRef<OsStr>>(program: &str, args: &[S], code: Option<i32>, stderr: &[u8]) -> Self {
+        Self {
+            program: program.to_string(),
+            args: args
+                .iter()
+                .map(|a| a.as_ref().to_string_lossy().to_string())
+                .collect(),
+            code,
+            stderr_tail: stderr_tail(stderr, STDERR_TAIL_LINES),
+        }
+    }
+}
+
+impl fmt::Display for CmdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let command = describe(&self.program, &self.args);
+        match self.code {
+            Some(code) => write!(f, "`{}` failed with exit code {}", command, code)?,
+            None => write!(f, "`{}` was terminated by a signal", command)?,
+        }
+
+        for line in &self.stderr_tail {
+            write!(f, "\n  {}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for CmdError {}
 
 pub fn run<I, S>(program: &str, args: I) -> Result<()>
 where
@@ -11,20 +98,50 @@ where
     S: AsRef<OsStr>,
 {
     let args: Vec<_> = args.into_iter().collect();
-    let args_str: Vec<_> = args.iter().map(|s| s.as_ref().to_string_lossy()).collect();
+    let mut command = Command::new(program);
+    command.args(&args);
+    run_teed(program, &args, command, None, None, false).map(drop)
+}
 
-    println!("{}> {} {}{}", CYAN, program, args_str.join(" "), RESET);
+/// Like [`run`], killing the command if it's still running after `timeout`
+///
+/// For network-bound commands that can hang on a dead mirror.
+pub fn run_with_timeout<I, S>(program: &str, args: I, timeout: Duration) -> Result<()>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let args: Vec<_> = args.into_iter().collect();
+    let mut command = Command::new(program);
+    command.args(&args);
+    run_teed(program, &args, command, None, Some(timeout), false).map(drop)
+}
 
-    let status = Command::new(program)
-        .args(&args)
-        .status()
-        .with_context(|| format!("Failed to run {}", program))?;
+/// Like [`run_output`], killing the command if it's still running after `timeout`
+pub fn run_output_with_timeout<I, S>(program: &str, args: I, timeout: Duration) -> Result<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let args: Vec<_> = args.into_iter().collect();
+    let mut command = Command::new(program);
+    command.args(&args);
+    let stdout = run_teed(program, &args, command, None, Some(timeout), true)?;
 
-    if !status.success() {
-        anyhow::bail!("{} failed with exit code {:?}", program, status.code());
-    }
+    Ok(String::from_utf8_lossy(&stdout).trim().to_string())
+}
 
-    Ok(())
+/// Like [`run`], with extra environment variables for the child
+pub fn run_with_env<I, S>(program: &str, args: I, env: &[(&str, &str)]) -> Result<()>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let args: Vec<_> = args.into_iter().collect();
+    debug!(?env, "environment");
+    let mut command = Command::new(program);
+    command.args(&args).envs(env.iter().copied());
+    run_teed(program, &args, command, None, None, false).map(drop)
 }
 
 pub fn run_with_stdin<I, S>(program: &str, args: I, input: &[u8]) -> Result<()>
@@ -32,53 +149,276 @@ where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
 {
-    use std::io::Write;
-
     let args: Vec<_> = args.into_iter().collect();
-    let args_str: Vec<_> = args.iter().map(|s| s.as_ref().to_string_lossy()).collect();
+    let mut command = Command::new(program);
+    command.args(&args);
+    // stdin usually carries a passphrase, so it's never logged
+    run_teed(program, &args, command, Some(input), None, false).map(drop)
+}
 
-    println!("{}> {} {}{}", CYAN, program, args_str.join(" "), RESET);
+pub fn run_output<I, S>(program: &str, args: I) -> Result<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let args: Vec<_> = args.into_iter().collect();
+    let command = describe(program, &args);
+    debug!(%command, "running");
 
-    let mut child = Command::new(program)
+    let start = Instant::now();
+    let output = Command::new(program)
         .args(&args)
-        .stdin(Stdio::piped())
+        .output()
+        .with_context(|| format!("Failed to run {}", program))?;
+
+    finish(program, &args, output.status, &output.stderr, start)?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Run with stderr passed through to the terminal as it's written, keeping a
+/// copy so failures can report what the command said
+///
+/// Returns stdout when `capture_stdout` is set. Otherwise it goes to the
+/// terminal, through a pipe when there's a log to copy it to.
+fn run_teed<S: AsRef<OsStr>>(
+    program: &str,
+    args: &[S],
+    mut command: Command,
+    input: Option<&[u8]>,
+    timeout: Option<Duration>,
+    capture_stdout: bool,
+) -> Result<Vec<u8>> {
+    let description = describe(program, args);
+    debug!(command = %description, ?timeout, "running");
+
+    if input.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    if timeout.is_some() {
+        // Own process group, so helpers the command spawned die with it
+        command.process_group(0);
+    }
+
+    let tee_stdout = !capture_stdout && logging();
+    if capture_stdout || tee_stdout {
+        command.stdout(Stdio::piped());
+    }
+
+    write_log(format!("$ {}\n", description).as_bytes());
+    let start = Instant::now();
+    let mut child = command
+        .stderr(Stdio::piped())
         .spawn()
         .with_context(|| format!("Failed to run {}", program))?;
 
-    if let Some(mut stdin) = child.stdin.take() {
+    let stderr = tee(child.stderr.take(), std::io::stderr(), true);
+    let stdout = match child.stdout.take() {
+        Some(pipe) if tee_stdout => Some(tee(Some(pipe), std::io::stdout(), false)),
+        Some(mut pipe) => Some(thread::spawn(move || {
+            let mut out = Vec::new();
+            let _ = pipe.read_to_end(&mut out);
+            write_log(&out);
+            out
+        })),
+        None => None,
+    };
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
         stdin.write_all(input)?;
     }
 
-    let status = child.wait()?;
+    let status = match timeout {
+        Some(timeout) => wait_with_timeout(&mut child, timeout),
+        None => child.wait().map(Some),
+    }?;
+
+    let captured = stderr.join().unwrap_or_default();
+    let stdout = stdout
+        .map(|h| h.join().unwrap_or_default())
+        .unwrap_or_default();
+
+    let Some(status) = status else {
+        debug!(command = %description, ?timeout, "timed out");
+        anyhow::bail!(
+            "`{}` timed out after {}s and was killed",
+            description,
+            timeout.unwrap_or_default().as_secs()
+        );
+    };
+
+    finish(program, args, status, &captured, start)?;
+    Ok(stdout)
+}
+
+/// Wait for `child`, killing its process group once `timeout` passes.
+/// Returns None if it had to be killed.
+fn wait_with_timeout(child: &mut Child, timeout: Duration) -> std::io::Result<Option<ExitStatus>> {
+    use nix::sys::signal::{killpg, Signal};
+    use nix::unistd::Pid;
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            let _ = killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Pass what a child writes to `pipe` on to `terminal` and the log as it
+/// comes, returning the last of it when `keep` is set
+fn tee(
+    pipe: Option<impl Read + Send + 'static>,
+    mut terminal: impl Write + Send + 'static,
+    keep: bool,
+) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut captured = Vec::new();
+        let Some(mut pipe) = pipe else {
+            return captured;
+        };
+
+        // Chunks rather than lines, so prompts without a newline still show
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = pipe.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let _ = terminal.write_all(&buf[..n]);
+            let _ = terminal.flush();
+            write_log(&buf[..n]);
+
+            if keep {
+                captured.extend_from_slice(&buf[..n]);
+                if captured.len() > STDERR_CAPTURE_BYTES {
+                    captured.drain(..captured.len() - STDERR_CAPTURE_BYTES);
+                }
+            }
+        }
+
+        captured
+    })
+}
+
+fn describe<S: AsRef<OsStr>>(program: &str, args: &[S]) -> String {
+    let args: Vec<_> = args.iter().map(|s| s.as_ref().to_string_lossy()).collect();
+    format!("{} {}", program, args.join(" "))
+        .trim_end()
+        .to_string()
+}
+
+/// The last `n` non-empty lines of `stderr`
+fn stderr_tail(stderr: &[u8], n: usize) -> Vec<String> {
+    let text = String::from_utf8_lossy(stderr);
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim_end)
+        .filter(|l| !l.is_empty())
+        .collect();
+
+    lines[lines.len().saturating_sub(n)..]
+        .iter()
+        .map(|l| l.to_string())
+        .collect()
+}
+
+fn finish<S: AsRef<OsStr>>(
+    program: &str,
+    args: &[S],
+    status: ExitStatus,
+    stderr: &[u8],
+    start: Instant,
+) -> Result<()> {
+    let command = describe(program, args);
+    let elapsed_ms = start.elapsed().as_millis() as u64;
+
     if !status.success() {
-        anyhow::bail!("{} failed with exit code {:?}", program, status.code());
+        debug!(%command, elapsed_ms, code = ?status.code(), "failed");
+        return Err(CmdError::new(program, args, status.code(), stderr).into());
     }
 
+    debug!(%command, elapsed_ms, "finished");
     Ok(())
 }
 
-pub fn run_output<I, S>(program: &str, args: I) -> Result<String>
-where
-    I: IntoIterator<Item = S>,
-    S: AsRef<OsStr>,
-{
-    let args: Vec<_> = args.into_iter().collect();
-    let args_str: Vec<_> = args.iter().map(|s| s.as_ref().to_string_lossy()).collect();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    println!("{}> {} {}{}", CYAN, program, args_str.join(" "), RESET);
+    #[test]
+    fn describe_joins_program_and_args() {
+        assert_eq!(
+            describe("cryptsetup", &["open", "/dev/sda2", "system"]),
+            "cryptsetup open /dev/sda2 system"
+        );
+        assert_eq!(describe::<&str>("sync", &[]), "sync");
+    }
 
-    let output = Command::new(program)
-        .args(&args)
-        .output()
-        .with_context(|| format!("Failed to run {}", program))?;
+    #[test]
+    fn failure_reports_code_and_stderr() {
+        let err = run("sh", ["-c", "echo oops >&2; exit 3"]).unwrap_err();
+        let cmd_err = err.downcast_ref::<CmdError>().unwrap();
+        assert_eq!(cmd_err.code, Some(3));
+        assert_eq!(cmd_err.stderr_tail, ["oops"]);
 
-    if !output.status.success() {
-        anyhow::bail!(
-            "{} failed with exit code {:?}",
-            program,
-            output.status.code()
-        );
+        let message = err.to_string();
+        assert!(message.contains("oops"));
+        assert!(message.contains("exit code 3"));
     }
 
-    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    #[test]
+    fn run_output_failure_reports_stderr() {
+        let err = run_output("sh", ["-c", "echo partial; echo oops >&2; exit 3"]).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("`sh -c echo partial; echo oops >&2; exit 3`"));
+        assert!(message.contains("oops"));
+        assert!(message.contains('3'));
+    }
+
+    #[test]
+    fn false_has_no_stderr() {
+        let err = run::<[&str; 0], &str>("false", []).unwrap_err();
+        assert_eq!(err.to_string(), "`false` failed with exit code 1");
+    }
+
+    #[test]
+    fn stdin_is_passed_through() {
+        run_with_stdin(
+            "sh",
+            ["-c", "read line; [ \"$line\" = secret ]"],
+            b"secret\n",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn timeout_kills_slow_command() {
+        let start = Instant::now();
+        let err = run_with_timeout("sleep", ["5"], Duration::from_secs(1)).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(4));
+        assert!(err.to_string().contains("timed out after 1s"));
+    }
+
+    #[test]
+    fn timeout_not_hit_returns_output() {
+        let out =
+            run_output_with_timeout("sh", ["-c", "echo ready"], Duration::from_secs(5)).unwrap();
+        assert_eq!(out, "ready");
+
+        let err = run_with_timeout("sh", ["-c", "exit 2"], Duration::from_secs(5)).unwrap_err();
+        assert!(err.to_string().contains("exit code 2"));
+    }
+
+    #[test]
+    fn tail_keeps_last_lines() {
+        let stderr = b"one\ntwo\n\nthree\nfour\n";
+        assert_eq!(stderr_tail(stderr, 2), ["three", "four"]);
+        assert_eq!(stderr_tail(stderr, 10), ["one", "two", "three", "four"]);
+    }
 }
@@ -1,3 +1,8 @@
+pub mod regen_boot;
+pub mod repair;
 pub mod rollback;
 pub mod snapshot;
+pub mod state;
+pub mod status;
 pub mod update;
+pub mod validate;
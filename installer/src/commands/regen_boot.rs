@@ -0,0 +1,177 @@
+//! Repoint crypttab, fstab and the UKIs at the disk the system runs from,
+//! after cloning it or otherwise changing its UUIDs
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chroot;
+use crate::cmd;
+use crate::crypt::{get_uuid, DetachedHeader};
+use crate::distro;
+use crate::install;
+use crate::paths::LUKS_MAPPER_NAME;
+use crate::prompt::prompt_yes_no;
+use crate::uki::rebuild;
+
+pub fn regen_boot() -> Result<()> {
+    if !nix::unistd::Uid::effective().is_root() {
+        eprintln!("Error: mkos regen-boot must be run as root (use sudo)");
+        std::process::exit(1);
+    }
+
+    println!("=== mkOS Regenerate Boot Configuration ===\n");
+
+    let root = Path::new("/");
+    // An unencrypted install has no crypttab entries to repoint
+    let current = fs::read_to_string(root.join("etc/crypttab")).unwrap_or_default();
+    let (crypttab, devices) = if rebuild::has_entries(&current) {
+        let (crypttab, devices) = repointed_crypttab(&current)?;
+        (Some(crypttab), devices)
+    } else {
+        (None, Vec::new())
+    };
+
+    let distro = distro::detect()?.create_with_init(install::recorded_init(root)?)?;
+    let mut fstab = distro.generate_fstab(root)?;
+    if devices.len() > 1 {
+        fstab = chroot::with_btrfs_devices(&fstab, &devices);
+    }
+
+    if let Some(crypttab) = &crypttab {
+        println!("\n/etc/crypttab will be:\n{}", crypttab);
+    }
+    println!("/etc/fstab will be:\n{}", fstab);
+    if crypttab.is_some() {
+        println!("The current files are kept as /etc/crypttab.bak and /etc/fstab.bak,");
+        println!("and the UKIs are rebuilt with the new LUKS UUID.\n");
+    } else {
+        println!("The current file is kept as /etc/fstab.bak, and the UKIs are rebuilt.\n");
+    }
+
+    if !prompt_yes_no("Write these and rebuild the UKIs?", false)? {
+        println!("Nothing changed.");
+        return Ok(());
+    }
+
+    if let Some(crypttab) = crypttab {
+        backup(&root.join("etc/crypttab"))?;
+        fs::write(root.join("etc/crypttab"), crypttab).context("Failed to write crypttab")?;
+        println!("✓ Wrote /etc/crypttab");
+    }
+    backup(&root.join("etc/fstab"))?;
+    chroot::generate_fstab(root, &fstab)?;
+    println!("✓ Wrote /etc/fstab\n");
+
+    // Reads the new crypttab back for rd.luks.uuid
+    rebuild::run()
+}
+
+/// crypttab naming the partitions this boot unlocked, and the mapper
+/// devices of its entries
+fn repointed_crypttab(current: &str) -> Result<(String, Vec<String>)> {
+    let (name, _, header) = rebuild::parse_crypttab(current)?;
+    if name != LUKS_MAPPER_NAME {
+        bail!(
+            "/etc/crypttab unlocks {}, not {}; it wasn't written by mkOS",
+            name,
+            LUKS_MAPPER_NAME
+        );
+    }
+
+    let partition = unlocked_partition(&name)?;
+    println!("Encrypted partition: {}", partition.display());
+
+    // The other disks of a RAID root are repointed the same way
+    let members = rebuild::crypttab_members(current);
+    let mut member_uuids = Vec::new();
+    for (member, _) in &members {
+        let partition = unlocked_partition(member)?;
+        println!("Encrypted partition: {}", partition.display());
+        member_uuids.push(get_uuid(&partition, None)?);
+    }
+
+    let mut crypttab = match header {
+        // The UUID lives in the header, so the partition is named by PARTUUID
+        Some(header) => {
+            let partuuid = cmd::run_output(
+                "blkid",
+                [
+                    "-s",
+                    "PARTUUID",
+                    "-o",
+                    "value",
+                    &partition.to_string_lossy(),
+                ],
+            )?;
+            let header = DetachedHeader {
+                data_device: format!("PARTUUID={}", partuuid.trim()),
+                ..header
+            };
+            chroot::crypttab(&rebuild::mapped_luks_uuid(&name)?, Some(&header))
+        }
+        None => chroot::crypttab(&get_uuid(&partition, None)?, None),
+    };
+    crypttab.push_str(&chroot::crypttab_members(&member_uuids));
+
+    let devices = std::iter::once(&name)
+        .chain(members.iter().map(|(member, _)| member))
+        .map(|name| format!("/dev/mapper/{}", name))
+        .collect();
+    Ok((crypttab, devices))
+}
+
+/// The partition under the open mapping `name`: the one this boot
+/// unlocked, whatever crypttab says
+fn unlocked_partition(name: &str) -> Result<PathBuf> {
+    let status = cmd::run_output("cryptsetup", ["status", name])
+        .with_context(|| format!("{} is not open", name))?;
+    backing_device(&status).with_context(|| format!("Could not find the partition under {}", name))
+}
+
+/// Copy `path` to `path.bak`
+fn backup(path: &Path) -> Result<()> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    fs::copy(path, &backup).with_context(|| format!("Failed to back up {}", path.display()))?;
+    Ok(())
+}
+
+/// The `device:` line of `cryptsetup status`
+fn backing_device(status: &str) -> Option<PathBuf> {
+    status
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("device:"))
+        .map(|device| PathBuf::from(device.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backing_device_from_cryptsetup_status() {
+        let status = "/dev/mapper/system is active and is in use.\n\
+                      \x20 type:    LUKS2\n\
+                      \x20 cipher:  aes-xts-plain64\n\
+                      \x20 device:  /dev/nvme1n1p2\n\
+                      \x20 sector size:  512\n";
+        assert_eq!(
+            backing_device(status),
+            Some(PathBuf::from("/dev/nvme1n1p2"))
+        );
+        assert_eq!(backing_device("/dev/mapper/system is inactive.\n"), None);
+    }
+
+    #[test]
+    fn backup_keeps_original_beside_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crypttab");
+        fs::write(&path, "system UUID=old none luks\n").unwrap();
+        backup(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("crypttab.bak")).unwrap(),
+            "system UUID=old none luks\n"
+        );
+    }
+}
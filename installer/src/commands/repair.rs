@@ -0,0 +1,578 @@
+//! Rebuild the boot images and NVRAM entries of an installed system that
+//! no longer boots, from a live or rescue environment
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::boot::{kernel_versions, BootConfig, BootEntry, BootSystem, DracutEfistub};
+use crate::chroot;
+use crate::cmd;
+use crate::crypt::snapshot::INSTALL_SNAPSHOT;
+use crate::crypt::{close_luks, get_uuid, open_luks};
+use crate::disk;
+use crate::paths::{self, LUKS_MAPPER_NAME};
+use crate::secret::Secret;
+use crate::uki::rebuild;
+
+/// The installed system's root filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RootLayout {
+    /// Device the filesystem is on: the opened LUKS container, or the
+    /// partition itself
+    device: PathBuf,
+    /// Empty when the root isn't encrypted
+    luks_uuid: String,
+    /// Filesystem type, as blkid reports it
+    fstype: String,
+    fs_uuid: String,
+}
+
+/// A mount under the installed system's root
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mount {
+    source: PathBuf,
+    /// Where the installed system mounts it, e.g. `/home`
+    mountpoint: String,
+    options: Option<String>,
+}
+
+pub fn repair(device: &Path, header: Option<&Path>) -> Result<()> {
+    if !nix::unistd::Uid::effective().is_root() {
+        eprintln!("Error: mkos repair must be run as root (use sudo)");
+        std::process::exit(1);
+    }
+    if !Path::new("/sys/firmware/efi").exists() {
+        bail!("mkos repair needs the live system booted in UEFI mode to write boot entries");
+    }
+
+    println!("=== mkOS Repair ===\n");
+
+    let parts = disk::detect_partitions(device)?;
+    for partition in [&parts.efi, &parts.luks] {
+        if !partition.exists() {
+            bail!(
+                "{} not found; is {} an mkOS disk?",
+                partition.display(),
+                device.display()
+            );
+        }
+    }
+
+    // Without its header the partition is indistinguishable from random data
+    let encrypted = header.is_some() || is_luks(&parts.luks);
+    println!(
+        "EFI partition: {}\nRoot partition: {}{}\n",
+        parts.efi.display(),
+        parts.luks.display(),
+        if encrypted { " (LUKS)" } else { "" }
+    );
+
+    if encrypted {
+        // Wiped once the volume is open
+        let passphrase = Secret::from(rpassword::prompt_password("Enter LUKS passphrase: ")?);
+        println!("Opening LUKS partition...");
+        open_luks(&parts.luks, LUKS_MAPPER_NAME, passphrase.expose(), header)?;
+    }
+
+    // Close the container whatever happens, so the disk isn't left open
+    let target = Path::new(paths::MOUNT_TARGET);
+    let result = root_layout(&parts.luks, encrypted, header)
+        .and_then(|layout| repair_mounted(target, device, &parts.efi, &layout));
+    if encrypted {
+        if let Err(e) = close_luks(LUKS_MAPPER_NAME) {
+            println!("Warning: failed to close LUKS: {}", e);
+        }
+    }
+    result?;
+
+    println!("\n✓ Boot images and entries rebuilt. Reboot to try the repaired system.");
+    Ok(())
+}
+
+/// Whether `partition` is a LUKS container
+fn is_luks(partition: &Path) -> bool {
+    cmd::run_output("cryptsetup", ["isLuks", &*partition.to_string_lossy()]).is_ok()
+}
+
+/// Find the root filesystem on `partition`, inside its opened LUKS
+/// container when `encrypted`
+fn root_layout(partition: &Path, encrypted: bool, header: Option<&Path>) -> Result<RootLayout> {
+    let (device, luks_uuid) = if encrypted {
+        let device = PathBuf::from(format!("/dev/mapper/{}", LUKS_MAPPER_NAME));
+        (device, get_uuid(partition, header)?)
+    } else {
+        (partition.to_path_buf(), String::new())
+    };
+    let fstype = disk::filesystem_type(&device)
+        .with_context(|| format!("No filesystem found on {}", device.display()))?;
+    let fs_uuid = disk::filesystem_uuid(&device)?;
+
+    Ok(RootLayout {
+        device,
+        luks_uuid,
+        fstype,
+        fs_uuid,
+    })
+}
+
+/// Mount the system at `target`, rebuild its boot, and unmount it again
+/// whatever happens
+fn repair_mounted(target: &Path, device: &Path, efi: &Path, layout: &RootLayout) -> Result<()> {
+    println!("Mounting root filesystem...");
+    let subvol = mount_root(target, layout)?;
+
+    let fstab = fs::read_to_string(target.join("etc/fstab")).unwrap_or_default();
+    let mounts = fstab_mounts(&fstab, &layout.device, efi);
+    let mut mounted = Vec::new();
+    let result = mount_all(target, &mounts, &mut mounted)
+        .and_then(|()| {
+            println!("Setting up chroot environment...");
+            chroot::setup_chroot(target)
+        })
+        .and_then(|()| rebuild_boot(target, device, layout, subvol));
+
+    println!("\nCleaning up...");
+    unmount(target, &mounted);
+    result
+}
+
+/// Mount the root filesystem at `target`, returning the subvolume the
+/// system boots from on btrfs
+fn mount_root(target: &Path, layout: &RootLayout) -> Result<String> {
+    fs::create_dir_all(target)?;
+    let device = layout.device.to_string_lossy();
+    let target_str = target.to_string_lossy();
+    if layout.fstype != "btrfs" {
+        cmd::run("mount", [&*device, &*target_str])?;
+        return Ok(String::new());
+    }
+
+    // The root subvolume is the one whose own fstab mounts it at /
+    cmd::run("mount", ["-o", "subvolid=5", &*device, &*target_str])?;
+    let subvol = root_subvolume(target);
+    cmd::run("umount", [&*target_str])?;
+    let subvol =
+        subvol.context("No subvolume on the root filesystem is mounted at / by its fstab")?;
+
+    let options = format!("subvol={}", subvol);
+    cmd::run("mount", ["-o", &options, &*device, &*target_str])?;
+    Ok(subvol)
+}
+
+/// Subvolume at the top level of the btrfs filesystem mounted at `top`
+/// whose fstab mounts it at /
+fn root_subvolume(top: &Path) -> Option<String> {
+    let mut names: Vec<String> = fs::read_dir(top)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names.into_iter().find(|name| {
+        let fstab = fs::read_to_string(top.join(name).join("etc/fstab")).unwrap_or_default();
+        let root = fstab_entries(&fstab).find(|(mountpoint, _, _)| *mountpoint == "/");
+        root.and_then(|(_, _, options)| subvol_option(options)) == Some(name.as_str())
+    })
+}
+
+/// (mountpoint, type, options) of each fstab entry
+fn fstab_entries(fstab: &str) -> impl Iterator<Item = (&str, &str, &str)> {
+    fstab
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            (fields.len() >= 4).then(|| (fields[1], fields[2], fields[3]))
+        })
+}
+
+/// The `subvol=` mount option, without its leading slash
+fn subvol_option(options: &str) -> Option<&str> {
+    options
+        .split(',')
+        .find_map(|option| option.strip_prefix("subvol="))
+        .map(|subvol| subvol.trim_start_matches('/'))
+}
+
+/// What to mount under the root, parents first: the other btrfs
+/// subvolumes the installed fstab lists on `device`, and the ESP at /boot
+fn fstab_mounts(fstab: &str, device: &Path, efi: &Path) -> Vec<Mount> {
+    let mut mounts: Vec<Mount> = fstab_entries(fstab)
+        .filter(|(mountpoint, fstype, _)| *mountpoint != "/" && *fstype == "btrfs")
+        .filter_map(|(mountpoint, _, options)| {
+            Some(Mount {
+                source: device.to_path_buf(),
+                mountpoint: mountpoint.to_string(),
+                options: Some(format!("subvol={}", subvol_option(options)?)),
+            })
+        })
+        .collect();
+    // dracut and the images need /boot even when fstab doesn't list it
+    mounts.push(Mount {
+        source: efi.to_path_buf(),
+        mountpoint: "/boot".into(),
+        options: None,
+    });
+    mounts.sort_by_key(|mount| Path::new(&mount.mountpoint).components().count());
+    mounts
+}
+
+/// Mount `mounts` under `target`, recording each in `mounted`
+fn mount_all(target: &Path, mounts: &[Mount], mounted: &mut Vec<Mount>) -> Result<()> {
+    for mount in mounts {
+        let path = target.join(mount.mountpoint.trim_start_matches('/'));
+        fs::create_dir_all(&path)?;
+        cmd::run("mount", mount_args(mount, &path))
+            .with_context(|| format!("Failed to mount {}", mount.mountpoint))?;
+        mounted.push(mount.clone());
+    }
+    Ok(())
+}
+
+fn mount_args(mount: &Mount, path: &Path) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(options) = &mount.options {
+        args.push("-o".into());
+        args.push(options.clone());
+    }
+    args.push(mount.source.to_string_lossy().into_owned());
+    args.push(path.to_string_lossy().into_owned());
+    args
+}
+
+/// Unmount the chroot filesystems, `mounted` and the root
+fn unmount(target: &Path, mounted: &[Mount]) {
+    if let Err(e) = chroot::teardown_chroot(target) {
+        tracing::debug!("Failed to tear down the chroot: {}", e);
+    }
+    // Already unmounted unless the rebuild failed before dracut
+    if let Err(e) = chroot::unmount_run(target) {
+        tracing::debug!("Failed to unmount /run: {}", e);
+    }
+    for path in unmount_order(target, mounted) {
+        if let Err(e) = cmd::run("umount", [&path]) {
+            println!("Warning: failed to unmount {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Paths to unmount, children before their parents and the root last
+fn unmount_order(target: &Path, mounted: &[Mount]) -> Vec<PathBuf> {
+    mounted
+        .iter()
+        .rev()
+        .map(|mount| target.join(mount.mountpoint.trim_start_matches('/')))
+        .chain(std::iter::once(target.to_path_buf()))
+        .collect()
+}
+
+/// Rebuild the initramfs and boot images of the system mounted at
+/// `target`, and recreate its NVRAM entries on `device`'s EFI partition
+fn rebuild_boot(target: &Path, device: &Path, layout: &RootLayout, subvol: String) -> Result<()> {
+    check_dracut_efistub(target)?;
+
+    println!("==> Detecting kernel versions...");
+    for kver in kernel_versions(target, &[])? {
+        println!("  Kernel: {}", kver);
+    }
+
+    let (boot_system, config) = boot_setup(target, layout, subvol)?;
+
+    // dracut --hostonly would pick up the live system's runtime state
+    chroot::unmount_run(target)?;
+    boot_system.build_initramfs(target)?;
+
+    let kernel_entries = boot_system.build_boot_images(target, &config)?;
+    // Only installs with boot.recovery_entry have a rescue UKI
+    let rescue_entry = if target.join("boot/mkos-rescue.efi").exists() {
+        Some(boot_system.build_rescue_image(target, &config)?)
+    } else {
+        None
+    };
+    let fallback_entry = match fallback_subvolume(target) {
+        Some(subvol) => Some(boot_system.build_fallback_image(target, &config, &subvol)?),
+        None => None,
+    };
+
+    let entries: Vec<&BootEntry> = kernel_entries
+        .iter()
+        .chain(fallback_entry.as_ref())
+        .chain(rescue_entry.as_ref())
+        .collect();
+    sign_images(target, &entries)?;
+
+    boot_system.create_fallback_scripts(target, &kernel_entries[0])?;
+    rebuild::recreate_boot_entries(
+        &boot_system,
+        device,
+        1,
+        &kernel_entries,
+        fallback_entry.as_ref(),
+        rescue_entry.as_ref(),
+    )
+}
+
+/// mkinitcpio-systemd-boot installs boot through systemd-boot, which this
+/// doesn't rebuild
+fn check_dracut_efistub(target: &Path) -> Result<()> {
+    if target.join("boot/loader/entries").is_dir() {
+        bail!(
+            "This system boots with systemd-boot; mkos repair only rebuilds dracut-efistub installs.\n\
+             Use mkos-rescue to chroot in and run mkinitcpio -P and bootctl install."
+        );
+    }
+    Ok(())
+}
+
+/// Boot configuration of the system mounted at `target`, whose root
+/// filesystem is `layout` and boots from `subvol` on btrfs
+///
+/// The kernel command line extras and boot mode are the ones the installer
+/// recorded.
+fn boot_setup(
+    target: &Path,
+    layout: &RootLayout,
+    subvol: String,
+) -> Result<(DracutEfistub, BootConfig)> {
+    let crypttab = fs::read_to_string(target.join("etc/crypttab")).unwrap_or_default();
+    let (quiet, splash, extra_cmdline) = rebuild::recorded_cmdline(target);
+
+    // The initramfs opens the container under the name crypttab gives it,
+    // finding a detached header where crypttab says
+    let (root_device, luks_header) = if layout.luks_uuid.is_empty() {
+        (format!("UUID={}", layout.fs_uuid), None)
+    } else {
+        let (name, header) = rebuild::parse_crypttab(&crypttab)
+            .map(|(name, _, header)| (name, header))
+            .unwrap_or_else(|_| (LUKS_MAPPER_NAME.into(), None));
+        (format!("/dev/mapper/{}", name), header)
+    };
+
+    let config = BootConfig {
+        luks_uuid: layout.luks_uuid.clone(),
+        extra_luks_uuids: rebuild::crypttab_members(&crypttab)
+            .into_iter()
+            .map(|(_, uuid)| uuid)
+            .collect(),
+        root_device,
+        subvol,
+        quiet,
+        splash,
+        // The initramfs config written at install already has the KMS
+        // drivers and console font
+        kms_drivers: Vec::new(),
+        console_font: false,
+        microcode: rebuild::microcode(&target.join("boot")),
+        // resume= is recorded with the extras
+        resume: None,
+        luks_header,
+    };
+    let boot_system = DracutEfistub::new()
+        .with_extra_cmdline(extra_cmdline)
+        .with_mode(rebuild::recorded_boot_mode(target)?);
+
+    Ok((boot_system, config))
+}
+
+/// Subvolume the fallback entry boots: the install snapshot, when the
+/// system has one
+fn fallback_subvolume(target: &Path) -> Option<String> {
+    target
+        .join(paths::SNAPSHOTS_DIR)
+        .join(INSTALL_SNAPSHOT)
+        .is_dir()
+        .then(|| format!("@snapshots/{}", INSTALL_SNAPSHOT))
+}
+
+/// Sign the rebuilt images with the installed system's sbctl keys
+fn sign_images(target: &Path, entries: &[&BootEntry]) -> Result<()> {
+    if target.join("usr/share/secureboot").is_dir() {
+        println!("==> Signing for Secure Boot...");
+        let target_str = target.to_string_lossy();
+        for entry in entries {
+            let path = PathBuf::from("/boot").join(entry.loader_path.trim_start_matches('/'));
+            cmd::run(
+                "chroot",
+                [
+                    &*target_str,
+                    "sbctl",
+                    "sign",
+                    "--save",
+                    &path.to_string_lossy(),
+                ],
+            )
+            .with_context(|| format!("Failed to sign {}", entry.loader_path))?;
+            println!("  Signed: {}", entry.loader_path);
+        }
+    } else if target.join("root/.secureboot-keys").is_dir() {
+        println!("WARNING: The images are unsigned. Turn Secure Boot off to boot, then");
+        println!("         run mkos-rebuild-uki to sign them with your keys.");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boot::BOOT_MODE_PATH;
+
+    fn encrypted_btrfs() -> RootLayout {
+        RootLayout {
+            device: "/dev/mapper/system".into(),
+            luks_uuid: "cccc".into(),
+            fstype: "btrfs".into(),
+            fs_uuid: "ffff".into(),
+        }
+    }
+
+    #[test]
+    fn boot_setup_reads_the_mounted_system() {
+        let target = tempfile::tempdir().unwrap();
+        let root = target.path();
+        fs::create_dir_all(root.join("etc/mkos")).unwrap();
+        fs::create_dir_all(root.join("boot")).unwrap();
+        fs::write(
+            root.join("etc/crypttab"),
+            "system UUID=aaaa none luks\nsystem1 UUID=bbbb none luks\n",
+        )
+        .unwrap();
+        fs::write(root.join("etc/mkos/cmdline"), "quiet splash debug\n").unwrap();
+        fs::write(root.join(BOOT_MODE_PATH), "efistub\n").unwrap();
+        fs::write(root.join("boot/amd-ucode.img"), "").unwrap();
+
+        let (boot, config) = boot_setup(root, &encrypted_btrfs(), "@".into()).unwrap();
+
+        assert_eq!(config.luks_uuid, "cccc");
+        assert_eq!(config.extra_luks_uuids, ["bbbb"]);
+        assert_eq!(config.root_device, "/dev/mapper/system");
+        assert_eq!(config.subvol, "@");
+        assert!(config.quiet && config.splash);
+        assert_eq!(config.microcode.as_deref(), Some("amd-ucode.img"));
+        let cmdline = boot.build_cmdline(&config);
+        assert!(cmdline.contains("rd.luks.uuid=cccc"));
+        assert!(cmdline.ends_with(" debug"));
+    }
+
+    #[test]
+    fn boot_setup_defaults_without_recorded_state() {
+        let target = tempfile::tempdir().unwrap();
+        let (boot, config) = boot_setup(target.path(), &encrypted_btrfs(), "@".into()).unwrap();
+
+        assert_eq!(config.root_device, "/dev/mapper/system");
+        assert!(config.quiet && !config.splash);
+        assert!(config.extra_luks_uuids.is_empty());
+        assert_eq!(config.microcode, None);
+        // Without an EFI stub in the target, UKI mode boots the kernel's own
+        assert!(boot
+            .entry_cmdline(target.path(), &config, "6.6.40", "")
+            .is_some());
+    }
+
+    #[test]
+    fn boot_setup_boots_an_unencrypted_root_by_uuid() {
+        let target = tempfile::tempdir().unwrap();
+        let layout = RootLayout {
+            device: "/dev/sda2".into(),
+            luks_uuid: String::new(),
+            fstype: "ext4".into(),
+            fs_uuid: "ffff".into(),
+        };
+        let (boot, config) = boot_setup(target.path(), &layout, String::new()).unwrap();
+
+        assert_eq!(config.root_device, "UUID=ffff");
+        assert_eq!(config.subvol, "");
+        let cmdline = boot.build_cmdline(&config);
+        assert!(!cmdline.contains("rd.luks"));
+        assert!(!cmdline.contains("rootflags"));
+    }
+
+    #[test]
+    fn root_subvolume_is_the_one_its_fstab_mounts_at_root() {
+        let top = tempfile::tempdir().unwrap();
+        for (subvol, root) in [("@home", "@"), ("@root", "@root"), ("@snapshots", "@")] {
+            fs::create_dir_all(top.path().join(subvol).join("etc")).unwrap();
+            fs::write(
+                top.path().join(subvol).join("etc/fstab"),
+                format!(
+                    "# <file system>\nUUID=ffff\t/\tbtrfs\trw,subvol=/{}\t0 0\n",
+                    root
+                ),
+            )
+            .unwrap();
+        }
+        assert_eq!(root_subvolume(top.path()).as_deref(), Some("@root"));
+
+        let empty = tempfile::tempdir().unwrap();
+        assert_eq!(root_subvolume(empty.path()), None);
+    }
+
+    #[test]
+    fn mounts_follow_the_installed_fstab() {
+        let fstab = "# /etc/fstab\n\
+                     UUID=ffff\t/\tbtrfs\trw,compress=zstd:1,subvol=/@\t0 0\n\
+                     UUID=ffff\t/home\tbtrfs\trw,compress=zstd:1,subvol=/@home\t0 0\n\
+                     UUID=ffff\t/.snapshots\tbtrfs\trw,subvol=/@snapshots\t0 0\n\
+                     UUID=1234-ABCD\t/boot\tvfat\trw\t0 2\n\
+                     tmpfs\t/tmp\ttmpfs\tnosuid\t0 0\n";
+        let device = Path::new("/dev/mapper/system");
+        let mounts = fstab_mounts(fstab, device, Path::new("/dev/sda1"));
+
+        let points: Vec<&str> = mounts.iter().map(|m| m.mountpoint.as_str()).collect();
+        assert_eq!(points, ["/home", "/.snapshots", "/boot"]);
+        assert_eq!(
+            mount_args(&mounts[0], Path::new("/mnt/home")),
+            ["-o", "subvol=@home", "/dev/mapper/system", "/mnt/home"]
+        );
+        assert_eq!(
+            mount_args(&mounts[2], Path::new("/mnt/boot")),
+            ["/dev/sda1", "/mnt/boot"]
+        );
+
+        // ext4 and xfs roots have only the ESP under them
+        let mounts = fstab_mounts("UUID=ffff / ext4 rw 0 1\n", device, Path::new("/dev/sda1"));
+        assert_eq!(mounts.len(), 1);
+        assert_eq!(mounts[0].mountpoint, "/boot");
+    }
+
+    #[test]
+    fn unmounts_children_first_and_root_last() {
+        let target = Path::new("/mnt");
+        let mounts = fstab_mounts(
+            "UUID=ffff /home btrfs subvol=@home 0 0\n",
+            Path::new("/dev/sda2"),
+            Path::new("/dev/sda1"),
+        );
+        assert_eq!(
+            unmount_order(target, &mounts),
+            [
+                PathBuf::from("/mnt/boot"),
+                PathBuf::from("/mnt/home"),
+                PathBuf::from("/mnt")
+            ]
+        );
+        assert_eq!(unmount_order(target, &[]), [PathBuf::from("/mnt")]);
+    }
+
+    #[test]
+    fn fallback_boots_the_install_snapshot() {
+        let target = tempfile::tempdir().unwrap();
+        assert_eq!(fallback_subvolume(target.path()), None);
+
+        fs::create_dir_all(target.path().join(".snapshots/install")).unwrap();
+        assert_eq!(
+            fallback_subvolume(target.path()).as_deref(),
+            Some("@snapshots/install")
+        );
+    }
+
+    #[test]
+    fn systemd_boot_installs_are_refused() {
+        let target = tempfile::tempdir().unwrap();
+        assert!(check_dracut_efistub(target.path()).is_ok());
+
+        fs::create_dir_all(target.path().join("boot/loader/entries")).unwrap();
+        let err = check_dracut_efistub(target.path()).unwrap_err();
+        assert!(err.to_string().contains("systemd-boot"));
+    }
+}
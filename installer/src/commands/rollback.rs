@@ -1,9 +1,11 @@
 use anyhow::{Context, Result};
-use std::fs;
-use std::path::PathBuf;
+use std::path::Path;
 use std::process::Command;
 
-pub fn rollback() -> Result<()> {
+use crate::crypt::snapshot;
+
+/// Restore the snapshot `name`, or the one the system is booted from
+pub fn rollback(name: Option<&str>) -> Result<()> {
     if !nix::unistd::Uid::effective().is_root() {
         eprintln!("Error: mkos rollback must be run as root (use sudo)");
         std::process::exit(1);
@@ -12,54 +14,39 @@ pub fn rollback() -> Result<()> {
     println!("=== mkOS Rollback ===\n");
 
     // Detect current root subvolume
-    let findmnt_output = Command::new("findmnt")
-        .args(["-n", "-o", "SOURCE", "/"])
-        .output()
-        .context("Failed to find root device")?;
-
-    let root_source = String::from_utf8_lossy(&findmnt_output.stdout)
-        .trim()
-        .to_string();
-
-    // Extract subvolume from source (e.g., /dev/mapper/system[@snapshots/pre-upgrade-2026-01-12T...])
-    let current_subvol = if let Some(bracket_pos) = root_source.find('[') {
-        let end_bracket = root_source.find(']').unwrap_or(root_source.len());
-        root_source[bracket_pos + 1..end_bracket].to_string()
-    } else {
-        // No subvolume in source, check options
-        let findmnt_opts = Command::new("findmnt")
-            .args(["-n", "-o", "OPTIONS", "/"])
-            .output()
-            .context("Failed to get mount options")?;
-        let opts = String::from_utf8_lossy(&findmnt_opts.stdout);
-
-        // Look for subvol= in mount options
-        if let Some(subvol_start) = opts.find("subvol=") {
-            let after = &opts[subvol_start + 7..];
-            let end = after.find(',').unwrap_or(after.trim().len());
-            after[..end].trim().to_string()
-        } else {
-            "@".to_string()
-        }
-    };
-
+    // (e.g., /dev/mapper/system[@snapshots/pre-upgrade-2026-01-12T...])
+    let current_subvol = snapshot::current_root_subvolume()?;
     println!("Current root subvolume: {}\n", current_subvol);
 
-    // Check if we're booted from a snapshot
-    if !current_subvol.starts_with("@snapshots/") {
-        eprintln!("Error: You are not booted from a snapshot!");
-        eprintln!("Current subvolume: {}", current_subvol);
-        eprintln!("\nRollback is only available when booted to a fallback snapshot.");
-        eprintln!(
-            "If your main system is broken, reboot and select 'mkOS (fallback)' from boot menu."
-        );
-        std::process::exit(1);
-    }
+    let snapshot_name = match name {
+        Some(name) => {
+            validate_snapshot_name(name)?;
+
+            let exists = snapshot::with_toplevel_mount(|root| {
+                Ok(root.join("@snapshots").join(name).is_dir())
+            })?;
+            if !exists {
+                anyhow::bail!("Snapshot not found: {}", name);
+            }
+
+            println!("Rolling back to snapshot: {}", name);
+            name.to_string()
+        }
+        None => {
+            // Without a name, restore the snapshot we're booted from
+            let Some(name) = current_subvol.strip_prefix("@snapshots/") else {
+                eprintln!("Error: You are not booted from a snapshot!");
+                eprintln!("Current subvolume: {}", current_subvol);
+                eprintln!("\nSpecify a snapshot (see 'mkos snapshot list'), or reboot and");
+                eprintln!("select 'mkOS (fallback)' from boot menu if your main system is broken.");
+                std::process::exit(1);
+            };
+
+            println!("You are booted from snapshot: {}", name);
+            name.to_string()
+        }
+    };
 
-    let snapshot_name = current_subvol
-        .strip_prefix("@snapshots/")
-        .context("Failed to extract snapshot name from subvolume path")?;
-    println!("You are booted from snapshot: {}", snapshot_name);
     println!("This will replace the main @ subvolume with this snapshot.\n");
 
     // Confirm with user
@@ -82,56 +69,56 @@ pub fn rollback() -> Result<()> {
 
     println!("\nProceeding with rollback...\n");
 
-    // Get the root device (strip subvolume notation)
-    let mut root_device = root_source.clone();
-    if let Some(bracket_pos) = root_device.find('[') {
-        root_device = root_device[..bracket_pos].to_string();
-    }
+    let broken_name = restore(&snapshot_name)?;
 
-    // Create temporary mount point
-    let temp_mount = PathBuf::from("/tmp/mkos-btrfs-root");
-    fs::create_dir_all(&temp_mount)?;
+    println!("\n✓ Rollback complete!\n");
+    println!("Changes made:");
+    println!("  - Old @ moved to: /{}", broken_name);
+    println!("  - New @ created from snapshot: {}", snapshot_name);
+    println!("\nREBOOT NOW to boot into the restored system.");
+    println!("Select 'mkOS' (not fallback) from boot menu.");
 
-    // Mount btrfs root
-    println!("Mounting btrfs root...");
-    let mount_status = Command::new("mount")
-        .args([
-            "-o",
-            "subvolid=5",
-            &root_device,
-            &temp_mount.to_string_lossy(),
-        ])
-        .status()
-        .context("Failed to mount btrfs root")?;
+    Ok(())
+}
 
-    if !mount_status.success() {
-        let _ = fs::remove_dir(&temp_mount);
-        anyhow::bail!("Failed to mount btrfs root");
-    }
+/// Replace @ with a copy of the snapshot `name`, without asking, returning
+/// what the old @ was renamed to
+pub fn restore(name: &str) -> Result<String> {
+    validate_snapshot_name(name)?;
 
-    // Rename current @ to @broken
     let timestamp = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S");
     let broken_name = format!("@broken-{}", timestamp);
 
+    snapshot::with_toplevel_mount(|root| restore_snapshot(root, name, &broken_name))?;
+    Ok(broken_name)
+}
+
+/// Reject names that would escape the snapshots directory
+pub(crate) fn validate_snapshot_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        anyhow::bail!("Invalid snapshot name: {}", name);
+    }
+    Ok(())
+}
+
+/// Move @ aside and replace it with a writable copy of the snapshot
+fn restore_snapshot(root: &Path, snapshot_name: &str, broken_name: &str) -> Result<()> {
     println!("Renaming @ to {}...", broken_name);
     let rename_status = Command::new("mv")
         .args([
-            &*temp_mount.join("@").to_string_lossy(),
-            &*temp_mount.join(&broken_name).to_string_lossy(),
+            &*root.join("@").to_string_lossy(),
+            &*root.join(broken_name).to_string_lossy(),
         ])
         .status()
         .context("Failed to rename @ subvolume")?;
 
     if !rename_status.success() {
-        let _ = Command::new("umount").arg(&temp_mount).status();
-        let _ = fs::remove_dir(&temp_mount);
         anyhow::bail!("Failed to rename @ subvolume");
     }
 
-    // Create writable copy of snapshot as new @
     println!("Creating new @ from snapshot {}...", snapshot_name);
-    let snapshot_path = temp_mount.join("@snapshots").join(snapshot_name);
-    let new_at = temp_mount.join("@");
+    let snapshot_path = root.join("@snapshots").join(snapshot_name);
+    let new_at = root.join("@");
 
     let snapshot_status = Command::new("btrfs")
         .args([
@@ -143,20 +130,30 @@ pub fn rollback() -> Result<()> {
         .status()
         .context("Failed to create new @ subvolume")?;
 
-    // Unmount
-    let _ = Command::new("umount").arg(&temp_mount).status();
-    let _ = fs::remove_dir(&temp_mount);
-
     if !snapshot_status.success() {
+        // Put the original @ back so the system still boots
+        let _ = Command::new("mv")
+            .args([
+                &*root.join(broken_name).to_string_lossy(),
+                &*new_at.to_string_lossy(),
+            ])
+            .status();
         anyhow::bail!("Failed to create new @ subvolume from snapshot");
     }
 
-    println!("\n✓ Rollback complete!\n");
-    println!("Changes made:");
-    println!("  - Old @ moved to: /{}", broken_name);
-    println!("  - New @ created from snapshot: {}", snapshot_name);
-    println!("\nREBOOT NOW to boot into the restored system.");
-    println!("Select 'mkOS' (not fallback) from boot menu.");
-
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_name_validation() {
+        assert!(validate_snapshot_name("pre-upgrade-2026-01-12T10:00:00").is_ok());
+        assert!(validate_snapshot_name("install").is_ok());
+        assert!(validate_snapshot_name("").is_err());
+        assert!(validate_snapshot_name("..").is_err());
+        assert!(validate_snapshot_name("../@").is_err());
+    }
+}
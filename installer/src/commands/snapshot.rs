@@ -1,30 +1,44 @@
 use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::path::Path;
 use std::process::Command;
 
+use super::rollback::validate_snapshot_name;
 use crate::crypt::snapshot;
+use crate::snapshot_schedule::Scheduled;
+use crate::tree_diff::{self, DiffSummary};
 
-pub fn snapshot_cmd(args: &[String]) -> Result<()> {
-    if args.is_empty() {
-        eprintln!("Error: snapshot subcommand required");
-        eprintln!("Usage: mkos snapshot <list|delete>");
-        std::process::exit(1);
-    }
+#[derive(Debug, clap::Subcommand)]
+pub enum SnapshotCommand {
+    /// List all snapshots
+    #[command(visible_alias = "ls")]
+    List {
+        /// Print machine-readable JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Take a read-only snapshot of the running system
+    #[command(visible_alias = "new")]
+    Create(CreateOptions),
+    /// Show which files changed between two snapshots
+    Diff(DiffOptions),
+    /// Delete a snapshot
+    #[command(visible_aliases = ["del", "rm"])]
+    Delete { name: String },
+    /// Delete old timestamped snapshots taken by mkOS
+    Prune(PruneOptions),
+}
 
-    match args[0].as_str() {
-        "list" | "ls" => list_snapshots(),
-        "delete" | "del" | "rm" => {
-            if args.len() < 2 {
-                eprintln!("Error: snapshot name required");
-                eprintln!("Usage: mkos snapshot delete <name>");
-                std::process::exit(1);
-            }
-            delete_snapshot(&args[1])
-        }
-        _ => {
-            eprintln!("Unknown snapshot subcommand: {}", args[0]);
-            std::process::exit(1);
-        }
+pub fn snapshot_cmd(command: SnapshotCommand) -> Result<()> {
+    match command {
+        SnapshotCommand::List { json: false } => list_snapshots(),
+        SnapshotCommand::List { json: true } => list_snapshots_json(),
+        SnapshotCommand::Create(options) => create_snapshot(options),
+        SnapshotCommand::Diff(options) => diff_snapshots(options),
+        SnapshotCommand::Delete { name } => delete_snapshot(&name),
+        SnapshotCommand::Prune(options) => prune_snapshots(options),
     }
 }
 
@@ -48,26 +62,301 @@ fn list_snapshots() -> Result<()> {
     if snapshots.is_empty() {
         println!("  (no snapshots)");
     } else {
+        let sizes = SnapshotSizes::read(snapshots_dir);
         for entry in snapshots {
             let name = entry.file_name();
             let name_str = name.to_string_lossy();
 
             // Get metadata if available
-            if let Ok(metadata) = entry.metadata() {
-                if let Ok(modified) = metadata.modified() {
+            let mut details: Vec<String> = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .map(|modified| {
                     let datetime: chrono::DateTime<chrono::Local> = modified.into();
-                    println!("  {} ({})", name_str, datetime.format("%Y-%m-%d %H:%M:%S"));
-                    continue;
-                }
+                    datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+                })
+                .into_iter()
+                .collect();
+            if let Some(bytes) = sizes.exclusive(&entry.path()) {
+                details.push(format!("{} exclusive", format_bytes(bytes)));
+            }
+
+            if details.is_empty() {
+                println!("  {}", name_str);
+            } else {
+                println!("  {} ({})", name_str, details.join(", "));
             }
+        }
+    }
+
+    Ok(())
+}
+
+/// Snapshot entry for `mkos snapshot list --json`
+#[derive(Debug, Serialize)]
+struct SnapshotInfo {
+    name: String,
+    /// Modification time (RFC 3339), if available
+    created: Option<String>,
+    readonly: bool,
+    path: String,
+    /// Bytes only this snapshot holds, when btrfs quotas are enabled
+    exclusive_bytes: Option<u64>,
+}
+
+/// Exclusive snapshot sizes from btrfs quotas, empty when they're off
+struct SnapshotSizes(HashMap<u64, u64>);
+
+impl SnapshotSizes {
+    fn read(snapshots_dir: &Path) -> Self {
+        Self(
+            snapshot::qgroup_show(snapshots_dir)
+                .map(|qgroups| snapshot::exclusive_sizes(&qgroups))
+                .unwrap_or_default(),
+        )
+    }
+
+    fn exclusive(&self, path: &Path) -> Option<u64> {
+        if self.0.is_empty() {
+            return None;
+        }
+        self.0.get(&snapshot::subvolume_id(path)?).copied()
+    }
+}
+
+/// Bytes in binary units, as btrfs prints them
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn list_snapshots_json() -> Result<()> {
+    let snapshots_dir = Path::new("/.snapshots");
+    let mut snapshots = Vec::new();
+
+    if snapshots_dir.exists() {
+        let sizes = SnapshotSizes::read(snapshots_dir);
+        let mut entries: Vec<_> = std::fs::read_dir(snapshots_dir)?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .collect();
+        entries.sort_by_key(|e| e.file_name());
+
+        for entry in entries {
+            let path = entry.path();
+            let created = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .map(|modified| chrono::DateTime::<chrono::Local>::from(modified).to_rfc3339());
 
-            println!("  {}", name_str);
+            snapshots.push(SnapshotInfo {
+                name: entry.file_name().to_string_lossy().to_string(),
+                created,
+                readonly: is_readonly(&path),
+                exclusive_bytes: sizes.exclusive(&path),
+                path: path.to_string_lossy().to_string(),
+            });
         }
     }
 
+    println!("{}", serde_json::to_string_pretty(&snapshots)?);
+
     Ok(())
 }
 
+/// Read the btrfs read-only property of a subvolume
+fn is_readonly(path: &Path) -> bool {
+    Command::new("btrfs")
+        .args(["property", "get", "-ts"])
+        .arg(path)
+        .arg("ro")
+        .output()
+        .map(|o| parse_ro_property(&String::from_utf8_lossy(&o.stdout)))
+        .unwrap_or(false)
+}
+
+/// Parse `btrfs property get ... ro` output ("ro=true")
+fn parse_ro_property(output: &str) -> bool {
+    output.trim() == "ro=true"
+}
+
+#[derive(Debug, Default, clap::Args)]
+pub struct CreateOptions {
+    /// Appended to the snapshot's timestamped name
+    #[arg(conflicts_with = "auto")]
+    label: Option<String>,
+
+    /// Take a scheduled snapshot of this kind if one is due
+    #[arg(long, value_name = "KIND")]
+    auto: Option<Scheduled>,
+
+    /// Then delete all but the newest N snapshots of that kind
+    // clap waives `requires` when the label is given, as auto conflicts with it
+    #[arg(long, value_name = "N", requires = "auto", conflicts_with = "label")]
+    keep: Option<usize>,
+}
+
+fn create_snapshot(options: CreateOptions) -> Result<()> {
+    if !nix::unistd::Uid::effective().is_root() {
+        eprintln!("Error: Creating snapshots requires root privileges (use sudo)");
+        std::process::exit(1);
+    }
+
+    if !snapshot::is_btrfs_root() {
+        anyhow::bail!("Root filesystem is not btrfs");
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let Some(kind) = options.auto else {
+        let name = manual_name(now, options.label.as_deref())?;
+        if Path::new("/.snapshots").join(&name).exists() {
+            anyhow::bail!("Snapshot already exists: {}", name);
+        }
+        create_btrfs_snapshot(&name)?;
+        println!("✓ Created snapshot: {}", name);
+        return Ok(());
+    };
+
+    let snapshots_dir = Path::new("/.snapshots");
+    let mut names = snapshot_names(snapshots_dir)?;
+
+    if kind.is_due(newest_scheduled(kind, &names), now) {
+        let name = format!("{}{}", kind.prefix(), now.format(TIMESTAMP_FORMAT));
+        create_btrfs_snapshot(&name)?;
+        println!("✓ Created snapshot: {}", name);
+        names.push(name);
+    } else {
+        println!("No {} snapshot due yet", kind.name());
+    }
+
+    let Some(keep) = options.keep else {
+        return Ok(());
+    };
+    let current = snapshot::current_root_subvolume()?;
+    let booted = current.strip_prefix("@snapshots/");
+    let scheduled = scheduled_names(kind, &names);
+    for name in select_for_pruning(&scheduled, Some(keep), None, booted) {
+        println!("Deleting snapshot: {}", name);
+        snapshot::delete_snapshot(&snapshots_dir.join(&name))?;
+    }
+
+    Ok(())
+}
+
+/// Name of a snapshot taken by hand at `now`: the timestamp, then the label
+fn manual_name(now: NaiveDateTime, label: Option<&str>) -> Result<String> {
+    let timestamp = now.format(TIMESTAMP_FORMAT).to_string();
+    let Some(label) = label else {
+        return Ok(timestamp);
+    };
+    if label.is_empty() || label.contains('/') {
+        anyhow::bail!("Invalid snapshot label: {:?}", label);
+    }
+    let name = format!("{}-{}", timestamp, label);
+    validate_snapshot_name(&name)?;
+    Ok(name)
+}
+
+/// Names of the snapshots in `dir`, none when it doesn't exist
+fn snapshot_names(dir: &Path) -> Result<Vec<String>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect())
+}
+
+/// The snapshots among `names` that were scheduled as `kind`
+fn scheduled_names(kind: Scheduled, names: &[String]) -> Vec<String> {
+    names
+        .iter()
+        .filter(|name| name.starts_with(kind.prefix()))
+        .cloned()
+        .collect()
+}
+
+/// When the newest snapshot scheduled as `kind` was taken
+fn newest_scheduled(kind: Scheduled, names: &[String]) -> Option<NaiveDateTime> {
+    scheduled_names(kind, names)
+        .iter()
+        .filter_map(|name| managed_timestamp(name))
+        .max()
+}
+
+#[derive(Debug, clap::Args)]
+pub struct DiffOptions {
+    /// Snapshot to compare from
+    old: String,
+
+    /// Snapshot to compare to; the running system when omitted
+    new: Option<String>,
+
+    /// List every changed path, not just the counts
+    #[arg(long)]
+    full: bool,
+}
+
+fn diff_snapshots(options: DiffOptions) -> Result<()> {
+    if !nix::unistd::Uid::effective().is_root() {
+        eprintln!("Error: Comparing snapshots requires root privileges (use sudo)");
+        std::process::exit(1);
+    }
+
+    let old = snapshot_path(&options.old)?;
+    let new = match &options.new {
+        Some(name) => snapshot_path(name)?,
+        None => Path::new("/").to_path_buf(),
+    };
+
+    let mut summary = DiffSummary::default();
+    tree_diff::diff_trees(&old, &new, |change| {
+        summary.record(&change);
+        if options.full {
+            let slash = if change.is_dir { "/" } else { "" };
+            println!(
+                "{} /{}{}",
+                change.kind.marker(),
+                change.path.display(),
+                slash
+            );
+        }
+    })?;
+
+    if options.full {
+        println!();
+    }
+    println!(
+        "{} added, {} removed, {} modified",
+        summary.added, summary.removed, summary.modified
+    );
+    Ok(())
+}
+
+/// Path of an existing snapshot
+fn snapshot_path(name: &str) -> Result<std::path::PathBuf> {
+    validate_snapshot_name(name)?;
+    let path = Path::new("/.snapshots").join(name);
+    if !path.is_dir() {
+        anyhow::bail!("Snapshot not found: {}", name);
+    }
+    Ok(path)
+}
+
 fn delete_snapshot(name: &str) -> Result<()> {
     if !nix::unistd::Uid::effective().is_root() {
         eprintln!("Error: Deleting snapshots requires root privileges (use sudo)");
@@ -87,96 +376,384 @@ fn delete_snapshot(name: &str) -> Result<()> {
     Ok(())
 }
 
+/// Name prefixes of timestamped snapshots created by mkOS
+const MANAGED_PREFIXES: &[&str] = &["pre-upgrade-", "pre-apply-", "hourly-", "daily-"];
+
+/// Timestamp format used in managed snapshot names
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+#[derive(Debug, Default, clap::Args)]
+pub struct PruneOptions {
+    /// Keep the newest N managed snapshots
+    #[arg(long, value_name = "N", required_unless_present = "older_than_days")]
+    keep: Option<usize>,
+
+    /// Only delete snapshots older than DAYS
+    #[arg(
+        long = "older-than",
+        value_name = "DAYS",
+        value_parser = clap::value_parser!(i64).range(1..)
+    )]
+    older_than_days: Option<i64>,
+
+    /// Show what would be deleted without deleting it
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+}
+
+/// Parse the timestamp out of a managed snapshot name
+fn managed_timestamp(name: &str) -> Option<NaiveDateTime> {
+    MANAGED_PREFIXES
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .and_then(|ts| NaiveDateTime::parse_from_str(ts, TIMESTAMP_FORMAT).ok())
+}
+
+/// Select which snapshots to prune.
+///
+/// Only timestamped mkOS snapshots are considered, so `install` (the fallback
+/// boot target) and user-made snapshots are never touched. The newest `keep`
+/// snapshots are retained; with `cutoff`, only snapshots older than it go.
+fn select_for_pruning(
+    names: &[String],
+    keep: Option<usize>,
+    cutoff: Option<NaiveDateTime>,
+    booted: Option<&str>,
+) -> Vec<String> {
+    let mut managed: Vec<(NaiveDateTime, &String)> = names
+        .iter()
+        .filter_map(|name| managed_timestamp(name).map(|ts| (ts, name)))
+        .collect();
+
+    // Newest first
+    managed.sort_by_key(|(ts, _)| std::cmp::Reverse(*ts));
+
+    managed
+        .into_iter()
+        .skip(keep.unwrap_or(0))
+        .filter(|(ts, _)| cutoff.is_none_or(|cutoff| *ts < cutoff))
+        .filter(|(_, name)| Some(name.as_str()) != booted)
+        .map(|(_, name)| name.clone())
+        .collect()
+}
+
+fn prune_snapshots(options: PruneOptions) -> Result<()> {
+    if !options.dry_run && !nix::unistd::Uid::effective().is_root() {
+        eprintln!("Error: Pruning snapshots requires root privileges (use sudo)");
+        std::process::exit(1);
+    }
+
+    let snapshots_dir = Path::new("/.snapshots");
+    if !snapshots_dir.exists() {
+        println!("No snapshots directory found.");
+        return Ok(());
+    }
+
+    let names = snapshot_names(snapshots_dir)?;
+
+    let current = snapshot::current_root_subvolume()?;
+    let booted = current.strip_prefix("@snapshots/");
+
+    let cutoff = options
+        .older_than_days
+        .map(|days| (chrono::Utc::now() - chrono::Duration::days(days)).naive_utc());
+
+    let selected = select_for_pruning(&names, options.keep, cutoff, booted);
+
+    if selected.is_empty() {
+        println!("No snapshots to prune.");
+        return Ok(());
+    }
+
+    for name in &selected {
+        if options.dry_run {
+            println!("Would delete: {}", name);
+        } else {
+            println!("Deleting snapshot: {}", name);
+            snapshot::delete_snapshot(&snapshots_dir.join(name))?;
+        }
+    }
+
+    if options.dry_run {
+        println!(
+            "\n{} snapshot(s) would be deleted (dry run)",
+            selected.len()
+        );
+    } else {
+        println!("\n✓ Pruned {} snapshot(s)", selected.len());
+    }
+
+    Ok(())
+}
+
 pub fn create_btrfs_snapshot(name: &str) -> Result<()> {
-    use std::fs;
-    use std::path::PathBuf;
+    // btrfs can't snapshot subvolumes with active swapfiles
+    snapshot::with_swapfile_disabled(|| {
+        // Snapshot @ to @snapshots/name from the btrfs top-level
+        snapshot::with_toplevel_mount(|root| {
+            let source = root.join("@");
+            let dest = root.join("@snapshots").join(name);
 
-    // Check if there's a swapfile (btrfs can't snapshot subvolumes with active swapfiles)
-    let swapfile_active = Command::new("swapon")
-        .args(["--show", "--noheadings"])
-        .output()
-        .ok()
-        .map(|o| {
-            let output = String::from_utf8_lossy(&o.stdout);
-            output.contains("/swapfile")
+            let snapshot_status = Command::new("btrfs")
+                .args(["subvolume", "snapshot", "-r"])
+                .args(snapshot::qgroup_args(root))
+                .arg(&source)
+                .arg(&dest)
+                .status()
+                .context("Failed to create snapshot")?;
+
+            if !snapshot_status.success() {
+                anyhow::bail!("btrfs snapshot command failed");
+            }
+
+            Ok(())
         })
-        .unwrap_or(false);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_list_json_shape() {
+        let snapshots = vec![
+            SnapshotInfo {
+                name: "install".into(),
+                created: Some("2024-01-01T00:00:00+00:00".into()),
+                readonly: true,
+                path: "/.snapshots/install".into(),
+                exclusive_bytes: Some(12_582_912),
+            },
+            SnapshotInfo {
+                name: "manual".into(),
+                created: None,
+                readonly: false,
+                path: "/.snapshots/manual".into(),
+                exclusive_bytes: None,
+            },
+        ];
 
-    // Disable swap if needed
-    if swapfile_active {
-        println!("  Temporarily disabling swap...");
-        Command::new("swapoff")
-            .arg("/swapfile")
-            .status()
-            .context("Failed to disable swap")?;
+        let json = serde_json::to_value(&snapshots).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {
+                    "name": "install",
+                    "created": "2024-01-01T00:00:00+00:00",
+                    "readonly": true,
+                    "path": "/.snapshots/install",
+                    "exclusive_bytes": 12582912
+                },
+                {
+                    "name": "manual",
+                    "created": null,
+                    "readonly": false,
+                    "path": "/.snapshots/manual",
+                    "exclusive_bytes": null
+                }
+            ])
+        );
     }
 
-    // Get the root device
-    let findmnt_output = Command::new("findmnt")
-        .args(["-n", "-o", "SOURCE", "/"])
-        .output()
-        .context("Failed to find root device")?;
+    #[test]
+    fn bytes_use_binary_units() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(12_582_912), "12.0 MiB");
+        assert_eq!(format_bytes(5 << 30), "5.0 GiB");
+    }
 
-    let mut root_device = String::from_utf8_lossy(&findmnt_output.stdout)
-        .trim()
-        .to_string();
+    #[test]
+    fn ro_property_parsing() {
+        assert!(parse_ro_property("ro=true\n"));
+        assert!(!parse_ro_property("ro=false\n"));
+        assert!(!parse_ro_property(""));
+    }
 
-    // Strip subvolume notation if present
-    if let Some(bracket_pos) = root_device.find('[') {
-        root_device = root_device[..bracket_pos].to_string();
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
     }
 
-    // Create temporary mount point for btrfs root
-    let temp_mount = PathBuf::from("/tmp/mkos-btrfs-root");
-    fs::create_dir_all(&temp_mount)?;
+    fn ts(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, TIMESTAMP_FORMAT).unwrap()
+    }
 
-    // Mount btrfs root (subvolid=5)
-    let mount_status = Command::new("mount")
-        .args([
-            "-o",
-            "subvolid=5",
-            &root_device,
-            &temp_mount.to_string_lossy(),
+    fn snapshots() -> Vec<String> {
+        names(&[
+            "install",
+            "pre-upgrade-2026-01-01T10:00:00",
+            "pre-apply-2026-01-03T10:00:00",
+            "pre-upgrade-2026-01-05T10:00:00",
+            "pre-upgrade-2026-01-02T10:00:00",
+            "my-backup",
+            "pre-upgrade-garbage",
         ])
-        .status()
-        .context("Failed to mount btrfs root")?;
+    }
 
-    if !mount_status.success() {
-        let _ = fs::remove_dir(&temp_mount);
-        if swapfile_active {
-            let _ = Command::new("swapon").arg("/swapfile").status();
-        }
-        anyhow::bail!("Failed to mount btrfs root");
+    #[test]
+    fn prune_keeps_newest() {
+        let selected = select_for_pruning(&snapshots(), Some(2), None, None);
+        assert_eq!(
+            selected,
+            names(&[
+                "pre-upgrade-2026-01-02T10:00:00",
+                "pre-upgrade-2026-01-01T10:00:00",
+            ])
+        );
     }
 
-    // Snapshot @ to @snapshots/name
-    let source = temp_mount.join("@");
-    let dest = temp_mount.join("@snapshots").join(name);
+    #[test]
+    fn prune_older_than_cutoff() {
+        let cutoff = ts("2026-01-03T00:00:00");
+        let selected = select_for_pruning(&snapshots(), None, Some(cutoff), None);
+        assert_eq!(
+            selected,
+            names(&[
+                "pre-upgrade-2026-01-02T10:00:00",
+                "pre-upgrade-2026-01-01T10:00:00",
+            ])
+        );
+    }
 
-    let snapshot_status = Command::new("btrfs")
-        .args([
-            "subvolume",
-            "snapshot",
-            "-r",
-            &source.to_string_lossy(),
-            &dest.to_string_lossy(),
-        ])
-        .status()
-        .context("Failed to create snapshot")?;
+    #[test]
+    fn prune_keep_and_cutoff_combined() {
+        let cutoff = ts("2026-01-04T00:00:00");
+        let selected = select_for_pruning(&snapshots(), Some(3), Some(cutoff), None);
+        assert_eq!(selected, names(&["pre-upgrade-2026-01-01T10:00:00"]));
+    }
 
-    // Unmount temporary mount
-    let _ = Command::new("umount").arg(&temp_mount).status();
-    let _ = fs::remove_dir(&temp_mount);
+    #[test]
+    fn prune_never_selects_unmanaged_or_booted() {
+        let booted = "pre-upgrade-2026-01-01T10:00:00";
+        let selected = select_for_pruning(&snapshots(), Some(0), None, Some(booted));
+        assert!(!selected.contains(&booted.to_string()));
+        assert!(!selected.contains(&"install".to_string()));
+        assert!(!selected.contains(&"my-backup".to_string()));
+        assert!(!selected.contains(&"pre-upgrade-garbage".to_string()));
+        assert_eq!(selected.len(), 3);
+    }
 
-    // Re-enable swap if it was active
-    if swapfile_active {
-        println!("  Re-enabling swap...");
-        let _ = Command::new("swapon").arg("/swapfile").status();
+    #[derive(clap::Parser)]
+    struct Cli {
+        #[command(subcommand)]
+        command: SnapshotCommand,
     }
 
-    if !snapshot_status.success() {
-        anyhow::bail!("btrfs snapshot command failed");
+    fn parse_args(args: &[&str]) -> Result<SnapshotCommand, clap::Error> {
+        use clap::Parser;
+        Cli::try_parse_from(["snapshot"].iter().chain(args)).map(|cli| cli.command)
     }
 
-    Ok(())
+    #[test]
+    fn prune_args_require_a_policy() {
+        assert!(parse_args(&["prune", "--dry-run"]).is_err());
+        let Ok(SnapshotCommand::Prune(options)) = parse_args(&["prune", "--keep", "5", "-n"])
+        else {
+            panic!("expected prune");
+        };
+        assert_eq!(options.keep, Some(5));
+        assert!(options.dry_run);
+        assert!(parse_args(&["prune", "--keep", "x"]).is_err());
+
+        let Ok(SnapshotCommand::Prune(options)) = parse_args(&["prune", "--older-than", "30"])
+        else {
+            panic!("expected prune");
+        };
+        assert_eq!(options.older_than_days, Some(30));
+        assert!(parse_args(&["prune", "--older-than", "0"]).is_err());
+        assert!(parse_args(&["prune", "--older-than", "-7"]).is_err());
+    }
+
+    #[test]
+    fn create_args_take_a_name_or_a_kind() {
+        let Ok(SnapshotCommand::Create(options)) = parse_args(&["create", "before-tinkering"])
+        else {
+            panic!("expected create");
+        };
+        assert_eq!(options.label.as_deref(), Some("before-tinkering"));
+        assert!(matches!(
+            parse_args(&["create"]).unwrap(),
+            SnapshotCommand::Create(CreateOptions {
+                label: None,
+                auto: None,
+                ..
+            })
+        ));
+
+        let Ok(SnapshotCommand::Create(options)) =
+            parse_args(&["create", "--auto", "hourly", "--keep", "24"])
+        else {
+            panic!("expected create");
+        };
+        assert_eq!(options.auto, Some(Scheduled::Hourly));
+        assert_eq!(options.keep, Some(24));
+
+        assert!(parse_args(&["create", "x", "--auto", "daily"]).is_err());
+        assert!(parse_args(&["create", "x", "--keep", "3"]).is_err());
+        assert!(parse_args(&["create", "--keep", "3"]).is_err());
+        assert!(parse_args(&["create", "--auto", "weekly"]).is_err());
+    }
+
+    #[test]
+    fn manual_names_start_with_the_timestamp() {
+        let now = ts("2026-10-16T09:30:00");
+        assert_eq!(manual_name(now, None).unwrap(), "2026-10-16T09:30:00");
+        assert_eq!(
+            manual_name(now, Some("before-tinkering")).unwrap(),
+            "2026-10-16T09:30:00-before-tinkering"
+        );
+        assert!(manual_name(now, Some("a/b")).is_err());
+        assert!(manual_name(now, Some("")).is_err());
+    }
+
+    #[test]
+    fn scheduled_snapshots_are_tracked_per_kind() {
+        let names = names(&[
+            "install",
+            "hourly-2026-01-05T09:00:00",
+            "hourly-2026-01-05T10:00:00",
+            "daily-2026-01-04T00:30:00",
+            "pre-upgrade-2026-01-05T11:00:00",
+        ]);
+        assert_eq!(
+            newest_scheduled(Scheduled::Hourly, &names),
+            Some(ts("2026-01-05T10:00:00"))
+        );
+        assert_eq!(
+            newest_scheduled(Scheduled::Daily, &names),
+            Some(ts("2026-01-04T00:30:00"))
+        );
+
+        // Pruning one kind leaves the others alone
+        let hourly = scheduled_names(Scheduled::Hourly, &names);
+        assert_eq!(
+            select_for_pruning(&hourly, Some(1), None, None),
+            ["hourly-2026-01-05T09:00:00"]
+        );
+        assert!(select_for_pruning(&names, None, None, None).contains(&names[3]));
+    }
+
+    #[test]
+    fn diff_args_default_to_the_running_system() {
+        let Ok(SnapshotCommand::Diff(options)) = parse_args(&["diff", "install", "--full"]) else {
+            panic!("expected diff");
+        };
+        assert_eq!(options.old, "install");
+        assert_eq!(options.new, None);
+        assert!(options.full);
+        assert!(parse_args(&["diff"]).is_err());
+    }
+
+    #[test]
+    fn snapshot_subcommands_keep_their_aliases() {
+        assert!(matches!(
+            parse_args(&["ls", "--json"]).unwrap(),
+            SnapshotCommand::List { json: true }
+        ));
+        assert!(matches!(
+            parse_args(&["rm", "install"]).unwrap(),
+            SnapshotCommand::Delete { name } if name == "install"
+        ));
+        assert!(parse_args(&["delete"]).is_err());
+    }
 }
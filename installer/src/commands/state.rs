@@ -0,0 +1,33 @@
+use anyhow::{bail, Result};
+use std::path::Path;
+
+use crate::state::{state_path, SystemState};
+
+#[derive(Debug, clap::Subcommand)]
+pub enum StateCommand {
+    /// Print the manifest last installed or applied, with when and by which
+    /// mkOS version
+    Show,
+    /// Print where the state file lives
+    Path,
+}
+
+pub fn state_cmd(command: StateCommand) -> Result<()> {
+    let root = Path::new("/");
+    match command {
+        StateCommand::Show => match SystemState::read(root)? {
+            Some(state) => {
+                println!("{}", serde_json::to_string_pretty(&state)?);
+                Ok(())
+            }
+            None => bail!(
+                "No state recorded at {}; this system was not installed or applied with mkOS yet",
+                state_path(root).display()
+            ),
+        },
+        StateCommand::Path => {
+            println!("{}", state_path(root).display());
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::crypt::snapshot;
+use crate::distro;
+
+/// Kernel hooks installed by the distro backends, relative to /
+const KERNEL_HOOK_PATHS: &[&str] = &[
+    "etc/pacman.d/hooks/90-mkos-uki.hook",
+    "etc/kernel.d/post-install/50-mkos-uki",
+    "etc/kernel/postinst.d/50-mkos-uki",
+    "etc/kernel/postinst.d/zz-mkos-uki",
+    "etc/apk/triggers/mkos-uki.trigger",
+];
+
+const REBUILD_UKI_PATH: &str = "usr/local/bin/mkos-rebuild-uki";
+
+/// Where swap lives on this system
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SwapLayout {
+    /// /swap/swapfile on the @swap subvolume (excluded from snapshots)
+    Subvolume,
+    /// /swapfile inside the root subvolume (pre-@swap installs)
+    Legacy,
+    None,
+}
+
+impl SwapLayout {
+    fn as_str(self) -> &'static str {
+        match self {
+            SwapLayout::Subvolume => "subvolume",
+            SwapLayout::Legacy => "legacy",
+            SwapLayout::None => "none",
+        }
+    }
+}
+
+/// Snapshot of mkOS-managed system state
+#[derive(Debug, Serialize)]
+pub struct SystemStatus {
+    pub distro: Option<String>,
+    pub root_filesystem: String,
+    pub btrfs: bool,
+    pub snapshots: Option<usize>,
+    pub swap: SwapLayout,
+    pub kernel_hook: bool,
+    pub rebuild_uki: bool,
+}
+
+pub fn status(json: bool) -> Result<()> {
+    let status = collect(Path::new("/"));
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+    } else {
+        print!("{}", format_status(&status));
+    }
+
+    Ok(())
+}
+
+fn collect(root: &Path) -> SystemStatus {
+    let btrfs = snapshot::is_btrfs_root();
+
+    let snapshots = if btrfs {
+        snapshot::list_snapshots(&root.join(crate::paths::SNAPSHOTS_DIR))
+            .ok()
+            .map(|s| s.len())
+    } else {
+        None
+    };
+
+    let fstab = fs::read_to_string(root.join("etc/fstab")).unwrap_or_default();
+
+    SystemStatus {
+        distro: distro::detect().ok().map(|kind| kind.name().to_string()),
+        root_filesystem: root_fstype().unwrap_or_else(|| "unknown".into()),
+        btrfs,
+        snapshots,
+        swap: swap_layout(&fstab, root.join("swapfile").exists()),
+        kernel_hook: KERNEL_HOOK_PATHS.iter().any(|p| root.join(p).exists()),
+        rebuild_uki: root.join(REBUILD_UKI_PATH).exists(),
+    }
+}
+
+fn root_fstype() -> Option<String> {
+    let output = Command::new("findmnt")
+        .args(["-n", "-o", "FSTYPE", "/"])
+        .output()
+        .ok()?;
+    let fstype = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!fstype.is_empty()).then_some(fstype)
+}
+
+/// Classify swap from fstab, falling back to a stray /swapfile on disk
+fn swap_layout(fstab: &str, legacy_swapfile_exists: bool) -> SwapLayout {
+    let swap_entries: Vec<&str> = fstab
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.starts_with('#'))
+        .filter_map(|l| {
+            let fields: Vec<&str> = l.split_whitespace().collect();
+            (fields.get(2) == Some(&"swap")).then(|| fields[0])
+        })
+        .collect();
+
+    if swap_entries.contains(&"/swap/swapfile") {
+        SwapLayout::Subvolume
+    } else if swap_entries.contains(&"/swapfile") || legacy_swapfile_exists {
+        SwapLayout::Legacy
+    } else {
+        SwapLayout::None
+    }
+}
+
+/// Compact `key: value` output, one per line
+fn format_status(status: &SystemStatus) -> String {
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+    let mut out = String::new();
+    out.push_str(&format!(
+        "distro: {}\n",
+        status.distro.as_deref().unwrap_or("unknown")
+    ));
+    out.push_str(&format!("root-filesystem: {}\n", status.root_filesystem));
+    out.push_str(&format!("btrfs: {}\n", yes_no(status.btrfs)));
+    out.push_str(&format!(
+        "snapshots: {}\n",
+        status
+            .snapshots
+            .map(|n| n.to_string())
+            .unwrap_or_else(|| "n/a".into())
+    ));
+    out.push_str(&format!("swap: {}\n", status.swap.as_str()));
+    out.push_str(&format!("kernel-hook: {}\n", yes_no(status.kernel_hook)));
+    out.push_str(&format!("rebuild-uki: {}\n", yes_no(status.rebuild_uki)));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swap_layout_subvolume() {
+        let fstab = "UUID=abc / btrfs subvol=@ 0 0\n\
+                     /swap/swapfile none swap defaults,pri=10 0 0\n";
+        assert_eq!(swap_layout(fstab, false), SwapLayout::Subvolume);
+    }
+
+    #[test]
+    fn swap_layout_legacy() {
+        let fstab = "/swapfile none swap defaults 0 0\n";
+        assert_eq!(swap_layout(fstab, false), SwapLayout::Legacy);
+        assert_eq!(swap_layout("", true), SwapLayout::Legacy);
+    }
+
+    #[test]
+    fn swap_layout_ignores_comments() {
+        let fstab = "# /swap/swapfile none swap defaults 0 0\n";
+        assert_eq!(swap_layout(fstab, false), SwapLayout::None);
+    }
+
+    #[test]
+    fn status_text_and_json() {
+        let status = SystemStatus {
+            distro: Some("Artix Linux".into()),
+            root_filesystem: "btrfs".into(),
+            btrfs: true,
+            snapshots: Some(3),
+            swap: SwapLayout::Legacy,
+            kernel_hook: true,
+            rebuild_uki: false,
+        };
+
+        assert_eq!(
+            format_status(&status),
+            "distro: Artix Linux\n\
+             root-filesystem: btrfs\n\
+             btrfs: yes\n\
+             snapshots: 3\n\
+             swap: legacy\n\
+             kernel-hook: yes\n\
+             rebuild-uki: no\n"
+        );
+
+        let json: serde_json::Value = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["swap"], "legacy");
+        assert_eq!(json["snapshots"], 3);
+        assert_eq!(json["kernel_hook"], true);
+    }
+
+    #[test]
+    fn collect_detects_hooks_in_root() {
+        let root = tempfile::tempdir().unwrap();
+        let hook = root.path().join("etc/kernel/postinst.d/zz-mkos-uki");
+        fs::create_dir_all(hook.parent().unwrap()).unwrap();
+        fs::write(&hook, "").unwrap();
+
+        let status = collect(root.path());
+        assert!(status.kernel_hook);
+        assert!(!status.rebuild_uki);
+        assert_eq!(status.swap, SwapLayout::None);
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::{bail, Result};
+use std::path::Path;
+
+use crate::locale;
+use crate::manifest::{self, Manifest, ManifestBundle, ManifestSource};
+
+/// Services whose packages come with the base system or a manifest section
+/// other than `packages`
+const IMPLIED_SERVICES: &[&str] = &["dbus", "dhcpcd", "udevd", "nftables", "iwd"];
+
+/// Services named differently from the package that ships them
+const SERVICE_PACKAGES: &[(&str, &str)] = &[("sshd", "openssh"), ("crond", "cronie")];
+
+/// Check `manifest` (a file, URLs, or `-` for stdin), failing on errors and
+/// with `strict` on unknown fields too
+pub fn validate(manifest: &str, strict: bool) -> Result<()> {
+    let source = ManifestSource::from_arg(Some(manifest));
+    let bundle = manifest::load(&source)?;
+
+    let mut errors = bundle.manifest.validate();
+    errors.extend(missing_sources(&bundle));
+    errors.extend(unavailable_settings(&bundle.manifest, Path::new("/")));
+    let mut warnings = unprovided_services(&bundle.manifest);
+
+    let unknown = bundle.manifest.unknown_field_warnings();
+    if strict {
+        errors.extend(unknown);
+    } else {
+        warnings.extend(unknown);
+    }
+
+    for error in &errors {
+        println!("error: {}", error);
+    }
+    for warning in &warnings {
+        println!("warning: {}", warning);
+    }
+
+    if !errors.is_empty() {
+        bail!(
+            "Manifest has {} error(s) and {} warning(s)",
+            errors.len(),
+            warnings.len()
+        );
+    }
+
+    println!("Manifest is valid ({} warning(s))", warnings.len());
+    Ok(())
+}
+
+/// Local file sources that aren't where deployment would look for them
+fn missing_sources(bundle: &ManifestBundle) -> Vec<String> {
+    bundle
+        .manifest
+        .files
+        .iter()
+        .enumerate()
+        .filter_map(|(i, file)| {
+            let source = file.source.as_deref().filter(|s| !manifest::is_url(s))?;
+            let path = match &bundle.files_dir {
+                Some(base) => base.join(source),
+                None => Path::new(source).to_path_buf(),
+            };
+            (!path.exists()).then(|| format!("files[{}]: source {} not found", i, path.display()))
+        })
+        .collect()
+}
+
+/// Timezone and locale settings the system at `root` doesn't have
+fn unavailable_settings(manifest: &Manifest, root: &Path) -> Vec<String> {
+    let mut checks = vec![
+        (
+            "system.timezone",
+            locale::check_timezone(root, &manifest.system.timezone),
+        ),
+        (
+            "system.locale",
+            locale::check_locale(root, &manifest.system.locale),
+        ),
+    ];
+    for extra in &manifest.system.locales {
+        checks.push(("system.locales", locale::check_locale(root, extra)));
+    }
+    checks
+        .into_iter()
+        .filter_map(|(field, result)| result.err().map(|e| format!("{}: {}", field, e)))
+        .collect()
+}
+
+/// Enabled services no manifest package looks like it provides
+///
+/// Best effort: package names are compared before distro mapping.
+fn unprovided_services(manifest: &Manifest) -> Vec<String> {
+    let mut packages = manifest.all_packages();
+    packages.extend(manifest.desktop.seat_manager.as_deref());
+    packages.extend(manifest.desktop.display_manager.as_deref());
+
+    manifest
+        .enabled_services()
+        .into_iter()
+        .filter(|service| !IMPLIED_SERVICES.contains(service))
+        .filter(|service| !packages.iter().any(|pkg| provides(pkg, service)))
+        .map(|service| {
+            format!(
+                "services.enable: no package in the manifest provides {}",
+                service
+            )
+        })
+        .collect()
+}
+
+/// `avahi` provides `avahi-daemon`, `openssh` provides `sshd`
+fn provides(package: &str, service: &str) -> bool {
+    package == service
+        || service.starts_with(&format!("{}-", package))
+        || SERVICE_PACKAGES.contains(&(service, package))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::FileConfig;
+
+    fn file(source: &str) -> FileConfig {
+        FileConfig {
+            path: "/etc/motd".into(),
+            content: None,
+            source: Some(source.into()),
+            mode: None,
+            owner: None,
+            group: None,
+            sha256: None,
+        }
+    }
+
+    #[test]
+    fn sources_are_looked_up_in_the_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("motd"), "hi").unwrap();
+
+        let manifest = Manifest {
+            files: vec![
+                file("motd"),
+                file("issue"),
+                file("https://example.org/motd"),
+            ],
+            ..Default::default()
+        };
+        let bundle = ManifestBundle {
+            manifest,
+            files_dir: Some(dir.path().to_path_buf()),
+        };
+
+        assert_eq!(
+            missing_sources(&bundle),
+            [format!(
+                "files[1]: source {} not found",
+                dir.path().join("issue").display()
+            )]
+        );
+    }
+
+    #[test]
+    fn settings_are_checked_against_the_system() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("usr/share/zoneinfo/Europe")).unwrap();
+        std::fs::write(root.path().join("usr/share/zoneinfo/Europe/Berlin"), "").unwrap();
+
+        let mut manifest = Manifest::default();
+        manifest.system.timezone = "Europe/Berlin".into();
+        assert!(unavailable_settings(&manifest, root.path()).is_empty());
+
+        manifest.system.timezone = "Europe/Berln".into();
+        assert_eq!(
+            unavailable_settings(&manifest, root.path()),
+            ["system.timezone: Unknown timezone Europe/Berln; did you mean Europe/Berlin?"]
+        );
+    }
+
+    #[test]
+    fn services_need_a_package() {
+        let mut manifest = Manifest::default();
+        manifest
+            .packages
+            .groups
+            .insert("base".into(), vec!["openssh".into(), "avahi".into()]);
+        manifest.desktop.display_manager = Some("greetd".into());
+        manifest.services.enable = ["sshd", "avahi-daemon", "greetd", "dbus", "docker"]
+            .map(String::from)
+            .to_vec();
+
+        assert_eq!(
+            unprovided_services(&manifest),
+            ["services.enable: no package in the manifest provides docker"]
+        );
+    }
+}
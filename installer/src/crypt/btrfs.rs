@@ -1,10 +1,14 @@
-use super::{Filesystem, MountOptions};
-use anyhow::Result;
-use std::path::Path;
+use super::{Filesystem, FilesystemKind, MountOptions};
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
 
 use crate::cmd;
+use crate::manifest::DiskConfig;
 use crate::paths;
 
+/// Parent qgroup every snapshot joins, so one limit covers them all
+pub const SNAPSHOTS_QGROUP: &str = "1/0";
+
 /// Btrfs subvolume definition
 #[derive(Debug, Clone)]
 pub struct Subvolume {
@@ -17,6 +21,10 @@ pub struct Subvolume {
 pub struct BtrfsLayout {
     pub subvolumes: Vec<Subvolume>,
     pub compress: String,
+    /// Enable quotas once the filesystem is created
+    pub quota: bool,
+    /// Bytes all snapshots may take together, through [`SNAPSHOTS_QGROUP`]
+    pub snapshots_limit: Option<u64>,
 }
 
 impl Default for BtrfsLayout {
@@ -41,8 +49,128 @@ impl Default for BtrfsLayout {
                 },
             ],
             compress: "zstd:1".into(),
+            quota: false,
+            snapshots_limit: None,
+        }
+    }
+}
+
+impl BtrfsLayout {
+    /// Build a layout from a custom subvolume list.
+    ///
+    /// Subvolumes are ordered so parents mount before children (root first),
+    /// and `@swap` is added when missing since the swapfile lives there.
+    pub fn from_subvolumes(mut subvolumes: Vec<Subvolume>) -> Result<Self> {
+        // The boot entries, rescue and rollback all find the system by these
+        if !subvolumes
+            .iter()
+            .any(|s| s.name == "@" && s.mountpoint == "/")
+        {
+            bail!("disk.subvolumes must include @ mounted at /");
+        }
+        let snapshots = format!("/{}", paths::SNAPSHOTS_DIR);
+        if !subvolumes
+            .iter()
+            .any(|s| s.name == "@snapshots" && s.mountpoint == snapshots)
+        {
+            bail!(
+                "disk.subvolumes must include @snapshots mounted at {}",
+                snapshots
+            );
+        }
+
+        if !subvolumes.iter().any(|s| s.name == "@swap") {
+            subvolumes.push(Subvolume {
+                name: "@swap".into(),
+                mountpoint: "/swap".into(),
+            });
+        }
+
+        subvolumes.sort_by_key(|s| Path::new(&s.mountpoint).components().count());
+
+        Ok(Self {
+            subvolumes,
+            ..Default::default()
+        })
+    }
+}
+
+/// Check `disk.btrfs_quota` and `disk.snapshots_limit`, returning the limit
+/// in bytes
+pub fn check_quota(disk: &DiskConfig) -> Result<Option<u64>> {
+    if disk.btrfs_quota && disk.filesystem.parse::<FilesystemKind>()? != FilesystemKind::Btrfs {
+        bail!("disk.btrfs_quota needs btrfs");
+    }
+    let Some(limit) = &disk.snapshots_limit else {
+        return Ok(None);
+    };
+    if !disk.btrfs_quota {
+        bail!("disk.snapshots_limit needs disk.btrfs_quota");
+    }
+    crate::disk::parse_size(limit).map(Some)
+}
+
+/// Enable quotas on the btrfs on `device`, and cap the space snapshots take
+/// together at `snapshots_limit` bytes
+pub fn enable_quota(device: &Path, snapshots_limit: Option<u64>) -> Result<()> {
+    let mount_point = "/mnt/btrfs_setup";
+
+    std::fs::create_dir_all(mount_point)?;
+    cmd::run("mount", [&device.to_string_lossy(), mount_point])?;
+
+    let result = (|| {
+        cmd::run("btrfs", ["quota", "enable", mount_point])?;
+        if let Some(limit) = snapshots_limit {
+            cmd::run("btrfs", ["qgroup", "create", SNAPSHOTS_QGROUP, mount_point])?;
+            cmd::run(
+                "btrfs",
+                [
+                    "qgroup",
+                    "limit",
+                    &limit.to_string(),
+                    SNAPSHOTS_QGROUP,
+                    mount_point,
+                ],
+            )?;
         }
+        Ok(())
+    })();
+
+    cmd::run("umount", [mount_point])?;
+    result
+}
+
+/// Check a btrfs compression setting against the algorithms mkOS supports
+pub fn validate_compression(value: &str) -> Result<()> {
+    let valid = match value {
+        "zstd" | "lzo" | "zlib" | "none" => true,
+        _ => value
+            .strip_prefix("zstd:")
+            .and_then(|level| level.parse::<u8>().ok())
+            .is_some_and(|level| (1..=15).contains(&level)),
+    };
+
+    if !valid {
+        bail!(
+            "Unknown compression: {}. Supported: zstd, zstd:1-zstd:15, lzo, zlib, none",
+            value
+        );
     }
+
+    Ok(())
+}
+
+/// Profile for both data and metadata when a filesystem spans several disks
+const RAID_PROFILE: &str = "raid1";
+
+/// mkfs.btrfs arguments for `devices`, mirrored when there's more than one
+fn mkfs_args(devices: &[PathBuf], label: &str) -> Vec<String> {
+    let mut args = vec!["-L".to_string(), label.to_string(), "-f".to_string()];
+    if devices.len() > 1 {
+        args.extend(["-d", RAID_PROFILE, "-m", RAID_PROFILE].map(String::from));
+    }
+    args.extend(devices.iter().map(|d| d.to_string_lossy().into_owned()));
+    args
 }
 
 /// Btrfs filesystem implementation
@@ -74,7 +202,16 @@ impl Filesystem for Btrfs {
     }
 
     fn format(&self, device: &Path, label: &str) -> Result<()> {
-        cmd::run("mkfs.btrfs", ["-L", label, "-f", &device.to_string_lossy()])
+        self.format_devices(&[device.to_path_buf()], label)
+    }
+
+    fn format_devices(&self, devices: &[PathBuf], label: &str) -> Result<()> {
+        cmd::run("mkfs.btrfs", mkfs_args(devices, label))?;
+        // The kernel needs every member before the first one will mount
+        if devices.len() > 1 {
+            cmd::run("btrfs", ["device", "scan"])?;
+        }
+        Ok(())
     }
 
     fn mount(&self, device: &Path, target: &Path, options: &MountOptions) -> Result<()> {
@@ -128,12 +265,11 @@ impl Filesystem for Btrfs {
     ) -> Result<()> {
         let device_str = device.to_string_lossy();
 
-        // Build base options
+        // Build base options ("none" disables compression entirely)
         let mut base_opts = Vec::new();
-        if let Some(ref comp) = options.compress {
-            base_opts.push(format!("compress={}", comp));
-        } else if !self.compress.is_empty() {
-            base_opts.push(format!("compress={}", self.compress));
+        let compress = options.compress.as_deref().unwrap_or(&self.compress);
+        if !compress.is_empty() && compress != "none" {
+            base_opts.push(format!("compress={}", compress));
         }
         base_opts.extend(self.mount_options.clone());
         base_opts.extend(options.extra.clone());
@@ -143,6 +279,10 @@ impl Filesystem for Btrfs {
             std::fs::create_dir_all(&mount_path)?;
 
             let mut opts = base_opts.clone();
+            // Swapfiles can't be compressed; keep compress= off the @swap fstab line
+            if subvol.name == "@swap" {
+                opts.retain(|o| !o.starts_with("compress="));
+            }
             opts.push(format!("subvol={}", subvol.name));
             let opts_str = opts.join(",");
 
@@ -202,3 +342,60 @@ pub fn mount_subvolumes(device: &Path, layout: &BtrfsLayout, target: &Path) -> R
     };
     btrfs.mount_subvolumes(device, &layout.subvolumes, target, &options)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quota_settings_are_checked() {
+        let mut disk = DiskConfig {
+            snapshots_limit: Some("50G".into()),
+            ..Default::default()
+        };
+        assert!(check_quota(&disk)
+            .unwrap_err()
+            .to_string()
+            .contains("btrfs_quota"));
+
+        disk.btrfs_quota = true;
+        assert_eq!(check_quota(&disk).unwrap(), Some(50 << 30));
+
+        disk.snapshots_limit = Some("lots".into());
+        assert!(check_quota(&disk).is_err());
+
+        disk.snapshots_limit = None;
+        disk.filesystem = "ext4".into();
+        assert!(check_quota(&disk).is_err());
+    }
+
+    #[test]
+    fn one_device_gets_default_profiles() {
+        assert_eq!(
+            mkfs_args(&[PathBuf::from("/dev/mapper/system")], "mkos"),
+            ["-L", "mkos", "-f", "/dev/mapper/system"]
+        );
+    }
+
+    #[test]
+    fn several_devices_are_mirrored() {
+        let devices = [
+            PathBuf::from("/dev/mapper/system"),
+            PathBuf::from("/dev/mapper/system1"),
+        ];
+        assert_eq!(
+            mkfs_args(&devices, "mkos"),
+            [
+                "-L",
+                "mkos",
+                "-f",
+                "-d",
+                "raid1",
+                "-m",
+                "raid1",
+                "/dev/mapper/system",
+                "/dev/mapper/system1"
+            ]
+        );
+    }
+}
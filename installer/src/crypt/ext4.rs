@@ -0,0 +1,63 @@
+use super::{Filesystem, MountOptions, Subvolume};
+use anyhow::Result;
+use std::path::Path;
+
+use crate::cmd;
+
+/// ext4 filesystem implementation (no subvolumes, no snapshots)
+#[derive(Debug, Clone, Default)]
+pub struct Ext4 {
+    /// Additional mount options
+    pub mount_options: Vec<String>,
+}
+
+impl Ext4 {
+    pub fn new() -> Self {
+        Self {
+            mount_options: vec!["noatime".into()],
+        }
+    }
+}
+
+impl Filesystem for Ext4 {
+    fn name(&self) -> &str {
+        "ext4"
+    }
+
+    fn format(&self, device: &Path, label: &str) -> Result<()> {
+        cmd::run("mkfs.ext4", ["-L", label, "-F", &device.to_string_lossy()])
+    }
+
+    fn mount(&self, device: &Path, target: &Path, options: &MountOptions) -> Result<()> {
+        std::fs::create_dir_all(target)?;
+
+        let mut opts = self.mount_options.clone();
+        opts.extend(options.extra.clone());
+        let opts = opts.join(",");
+
+        let device_str = device.to_string_lossy().to_string();
+        let target_str = target.to_string_lossy().to_string();
+
+        if opts.is_empty() {
+            cmd::run("mount", [&device_str, &target_str])
+        } else {
+            cmd::run("mount", ["-o", &opts, &device_str, &target_str])
+        }
+    }
+
+    fn unmount(&self, target: &Path) -> Result<()> {
+        let target_str = target.to_string_lossy().to_string();
+        cmd::run("umount", [&target_str])
+    }
+
+    /// ext4 has no subvolumes, so the whole device is mounted at the target root
+    fn mount_subvolumes(
+        &self,
+        device: &Path,
+        _subvolumes: &[Subvolume],
+        target: &Path,
+        options: &MountOptions,
+    ) -> Result<()> {
+        self.mount(device, target, options)
+    }
+}
@@ -0,0 +1,268 @@
+//! LUKS headers kept on separate media, away from the disk they unlock
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::cmd;
+use crate::disk::split_partition;
+
+/// Where the initramfs finds a detached header and the ciphertext it unlocks
+///
+/// Both are in crypttab notation, so the same strings serve `/etc/crypttab`
+/// and the `rd.luks.*` arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetachedHeader {
+    /// Encrypted partition (e.g., PARTUUID=...), which has no LUKS UUID of
+    /// its own without the header
+    pub data_device: String,
+    /// Header file on the filesystem holding it (e.g., /system.hdr:UUID=...)
+    pub header: String,
+}
+
+impl DetachedHeader {
+    /// Name `header` and the encrypted `partition` by IDs that survive
+    /// device renumbering
+    pub fn locate(header: &Path, partition: &Path) -> Result<Self> {
+        let partuuid = cmd::run_output(
+            "blkid",
+            [
+                "-s",
+                "PARTUUID",
+                "-o",
+                "value",
+                &partition.to_string_lossy(),
+            ],
+        )?;
+        let mount = cmd::run_output(
+            "findmnt",
+            [
+                "-n",
+                "-r",
+                "-o",
+                "UUID,TARGET",
+                "--target",
+                &header.to_string_lossy(),
+            ],
+        )
+        .with_context(|| format!("Failed to find the filesystem holding {}", header.display()))?;
+
+        let mut fields = mount.split_whitespace();
+        let (Some(fs_uuid), Some(mountpoint)) = (fields.next(), fields.next()) else {
+            bail!("{} is not on a filesystem with a UUID", header.display());
+        };
+        Self::new(partuuid.trim(), header, fs_uuid, &unescape(mountpoint))
+    }
+
+    fn new(partuuid: &str, header: &Path, fs_uuid: &str, mountpoint: &Path) -> Result<Self> {
+        let relative = header.strip_prefix(mountpoint).with_context(|| {
+            format!(
+                "{} is not under its mountpoint {}",
+                header.display(),
+                mountpoint.display()
+            )
+        })?;
+        Ok(Self {
+            data_device: format!("PARTUUID={}", partuuid),
+            header: format!(
+                "{}:UUID={}",
+                Path::new("/").join(relative).display(),
+                fs_uuid
+            ),
+        })
+    }
+
+    /// Read back the crypttab entry written for a detached header
+    pub fn from_crypttab(device: &str, options: &str) -> Option<Self> {
+        let header = options.split(',').find_map(|o| o.strip_prefix("header="))?;
+        Some(Self {
+            data_device: device.into(),
+            header: header.into(),
+        })
+    }
+
+    /// crypttab option pointing at the header
+    pub fn crypttab_option(&self) -> String {
+        format!("header={}", self.header)
+    }
+
+    /// Kernel arguments telling dracut where the data and header for
+    /// `luks_uuid` are
+    pub fn cmdline_args(&self, luks_uuid: &str) -> Vec<String> {
+        vec![
+            format!("rd.luks.data={}={}", luks_uuid, self.data_device),
+            format!(
+                "rd.luks.options={}={},discard",
+                luks_uuid,
+                self.crypttab_option()
+            ),
+        ]
+    }
+
+    /// mkinitcpio encrypt hook argument naming the filesystem holding the
+    /// header, and the header's path on it
+    pub fn cryptheader_arg(&self) -> String {
+        match self.header.rsplit_once(':') {
+            Some((path, device)) => format!("cryptheader={}:auto:{}", device, path),
+            None => format!("cryptheader=rootfs:{}", self.header),
+        }
+    }
+}
+
+/// Check a detached header at `header` would survive the reboot and not sit
+/// on `device`, the disk being encrypted
+pub fn check_detached_header(header: &Path, device: &Path) -> Result<()> {
+    if !header.is_absolute() {
+        bail!(
+            "disk.detached_header must be an absolute path, not {}",
+            header.display()
+        );
+    }
+    let dir = header
+        .parent()
+        .filter(|dir| dir.is_dir())
+        .with_context(|| format!("No directory to write {} into", header.display()))?;
+
+    let source = cmd::run_output(
+        "findmnt",
+        [
+            "-n",
+            "-r",
+            "-o",
+            "SOURCE",
+            "--target",
+            &dir.to_string_lossy(),
+        ],
+    )
+    .with_context(|| format!("Failed to find the filesystem holding {}", dir.display()))?;
+    let source = source.trim();
+
+    if !source.starts_with("/dev/") {
+        bail!(
+            "{} is on {}, which won't survive the reboot; put the header on removable media",
+            header.display(),
+            source
+        );
+    }
+    if on_device(source, &device.to_string_lossy()) {
+        bail!(
+            "The detached header must be on a different device than {}",
+            device.display()
+        );
+    }
+    Ok(())
+}
+
+/// Whether block device `source` is `device` or one of its partitions
+fn on_device(source: &str, device: &str) -> bool {
+    source == device || split_partition(source).is_some_and(|(disk, _)| disk == device)
+}
+
+/// Undo findmnt's raw-mode `\xNN` escaping of spaces and other bytes
+fn unescape(field: &str) -> PathBuf {
+    let mut out = String::new();
+    let mut rest = field;
+    while let Some(i) = rest.find("\\x") {
+        out.push_str(&rest[..i]);
+        match rest
+            .get(i + 2..i + 4)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            Some(byte) => {
+                out.push(byte as char);
+                rest = &rest[i + 4..];
+            }
+            None => {
+                out.push_str("\\x");
+                rest = &rest[i + 2..];
+            }
+        }
+    }
+    out.push_str(rest);
+    PathBuf::from(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> DetachedHeader {
+        DetachedHeader::new(
+            "0f3a-01",
+            Path::new("/run/media/usb/keys/system.hdr"),
+            "ABCD-1234",
+            Path::new("/run/media/usb"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn header_is_named_relative_to_its_filesystem() {
+        assert_eq!(
+            header(),
+            DetachedHeader {
+                data_device: "PARTUUID=0f3a-01".into(),
+                header: "/keys/system.hdr:UUID=ABCD-1234".into(),
+            }
+        );
+        assert_eq!(
+            header().crypttab_option(),
+            "header=/keys/system.hdr:UUID=ABCD-1234"
+        );
+        assert_eq!(
+            DetachedHeader::from_crypttab(
+                "PARTUUID=0f3a-01",
+                "luks,discard,header=/keys/system.hdr:UUID=ABCD-1234"
+            ),
+            Some(header())
+        );
+        assert_eq!(
+            DetachedHeader::from_crypttab("UUID=abcd", "luks,discard"),
+            None
+        );
+    }
+
+    #[test]
+    fn cmdline_points_systemd_at_data_and_header() {
+        assert_eq!(
+            header().cmdline_args("luks-uuid"),
+            [
+                "rd.luks.data=luks-uuid=PARTUUID=0f3a-01",
+                "rd.luks.options=luks-uuid=header=/keys/system.hdr:UUID=ABCD-1234,discard",
+            ]
+        );
+    }
+
+    #[test]
+    fn cryptheader_names_the_header_filesystem() {
+        assert_eq!(
+            header().cryptheader_arg(),
+            "cryptheader=UUID=ABCD-1234:auto:/keys/system.hdr"
+        );
+    }
+
+    #[test]
+    fn partitions_belong_to_their_disk() {
+        assert!(on_device("/dev/sda", "/dev/sda"));
+        assert!(on_device("/dev/sda2", "/dev/sda"));
+        assert!(on_device("/dev/nvme0n1p1", "/dev/nvme0n1"));
+        assert!(!on_device("/dev/sdb1", "/dev/sda"));
+        assert!(!on_device("/dev/nvme0n10p1", "/dev/nvme0n1"));
+        assert!(!on_device("/dev/mapper/system", "/dev/sda"));
+    }
+
+    #[test]
+    fn findmnt_escapes_are_undone() {
+        assert_eq!(
+            unescape("/run/media/My\\x20Stick"),
+            Path::new("/run/media/My Stick")
+        );
+        assert_eq!(unescape("/mnt/usb"), Path::new("/mnt/usb"));
+    }
+
+    #[test]
+    fn header_must_be_absolute() {
+        let err =
+            check_detached_header(Path::new("system.hdr"), Path::new("/dev/sda")).unwrap_err();
+        assert!(err.to_string().contains("absolute path"));
+    }
+}
@@ -1,9 +1,11 @@
 use super::DiskEncryption;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use std::path::{Path, PathBuf};
 
 use crate::cmd;
+use crate::manifest::PassphrasePolicy;
 use crate::paths;
+use tracing::instrument;
 
 /// LUKS encryption configuration
 #[derive(Debug, Clone)]
@@ -13,6 +15,8 @@ pub struct LuksConfig {
     pub hash: String,
     pub iter_time: u32,
     pub label: String,
+    /// Keep the header in this file instead of at the start of the partition
+    pub header: Option<PathBuf>,
 }
 
 impl Default for LuksConfig {
@@ -23,10 +27,56 @@ impl Default for LuksConfig {
             hash: "sha512".into(),
             iter_time: 5000,
             label: paths::LUKS_MAPPER_NAME.into(),
+            header: None,
         }
     }
 }
 
+/// Check `passphrase` meets `policy`, naming the first requirement it misses
+pub fn validate_passphrase(passphrase: &str, policy: &PassphrasePolicy) -> Result<()> {
+    let length = passphrase.chars().count();
+    if length < policy.min_length {
+        bail!(
+            "Passphrase must be at least {} characters ({} more needed)",
+            policy.min_length,
+            policy.min_length - length
+        );
+    }
+
+    let has = |class: fn(char) -> bool| passphrase.chars().any(class);
+    let missing = [
+        (
+            policy.require_uppercase && !has(char::is_uppercase),
+            "an uppercase letter",
+        ),
+        (
+            policy.require_lowercase && !has(char::is_lowercase),
+            "a lowercase letter",
+        ),
+        (
+            policy.require_digit && !has(|c| c.is_ascii_digit()),
+            "a digit",
+        ),
+        (
+            policy.require_symbol && !has(|c| !c.is_alphanumeric()),
+            "a symbol",
+        ),
+    ];
+    if let Some((_, class)) = missing.iter().find(|(missing, _)| *missing) {
+        bail!("Passphrase must contain {}", class);
+    }
+
+    Ok(())
+}
+
+/// Check a policy can be met at all
+pub fn validate_passphrase_policy(policy: &PassphrasePolicy) -> Result<()> {
+    if policy.min_length == 0 {
+        bail!("min_length must be at least 1");
+    }
+    Ok(())
+}
+
 /// LUKS2 disk encryption implementation with Argon2id
 #[derive(Debug, Clone, Default)]
 pub struct Luks2 {
@@ -46,6 +96,60 @@ impl Luks2 {
         self.config.label = label.into();
         self
     }
+
+    pub fn with_header(mut self, header: impl Into<PathBuf>) -> Self {
+        self.config.header = Some(header.into());
+        self
+    }
+
+    /// `--header <file>` when the header is detached
+    fn header_args(&self) -> Vec<String> {
+        match &self.config.header {
+            Some(header) => vec!["--header".into(), header.to_string_lossy().into_owned()],
+            None => Vec::new(),
+        }
+    }
+
+    /// cryptsetup arguments formatting `partition`, passphrase on stdin
+    pub fn format_args(&self, partition: &Path) -> Vec<String> {
+        let mut args: Vec<String> = [
+            "luksFormat",
+            "--type",
+            "luks2",
+            "--cipher",
+            &self.config.cipher,
+            "--key-size",
+            &self.config.key_size.to_string(),
+            "--hash",
+            &self.config.hash,
+            "--iter-time",
+            &self.config.iter_time.to_string(),
+            "--label",
+            &self.config.label,
+            "--pbkdf",
+            "argon2id",
+            "--batch-mode",
+            "--key-file=-",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        args.extend(self.header_args());
+        args.push(partition.to_string_lossy().into_owned());
+        args
+    }
+
+    /// cryptsetup arguments opening `partition` as `name`, passphrase on stdin
+    pub fn open_args(&self, partition: &Path, name: &str) -> Vec<String> {
+        let mut args: Vec<String> = ["open", "--type", "luks2", "--key-file=-"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        args.extend(self.header_args());
+        args.push(partition.to_string_lossy().into_owned());
+        args.push(name.into());
+        args
+    }
 }
 
 impl DiskEncryption for Luks2 {
@@ -54,47 +158,17 @@ impl DiskEncryption for Luks2 {
     }
 
     fn format(&self, partition: &Path, passphrase: &str) -> Result<()> {
-        let partition_str = partition.to_string_lossy();
-
         cmd::run_with_stdin(
             "cryptsetup",
-            [
-                "luksFormat",
-                "--type",
-                "luks2",
-                "--cipher",
-                &self.config.cipher,
-                "--key-size",
-                &self.config.key_size.to_string(),
-                "--hash",
-                &self.config.hash,
-                "--iter-time",
-                &self.config.iter_time.to_string(),
-                "--label",
-                &self.config.label,
-                "--pbkdf",
-                "argon2id",
-                "--batch-mode",
-                "--key-file=-",
-                &partition_str,
-            ],
+            self.format_args(partition),
             passphrase.as_bytes(),
         )
     }
 
     fn open(&self, partition: &Path, name: &str, passphrase: &str) -> Result<PathBuf> {
-        let partition_str = partition.to_string_lossy();
-
         cmd::run_with_stdin(
             "cryptsetup",
-            [
-                "open",
-                "--type",
-                "luks2",
-                "--key-file=-",
-                &partition_str,
-                name,
-            ],
+            self.open_args(partition, name),
             passphrase.as_bytes(),
         )?;
 
@@ -106,23 +180,39 @@ impl DiskEncryption for Luks2 {
     }
 
     fn get_uuid(&self, partition: &Path) -> Result<String> {
-        cmd::run_output("cryptsetup", ["luksUUID", &partition.to_string_lossy()])
+        // A detached header carries the UUID; the partition is bare ciphertext
+        let source = self.config.header.as_deref().unwrap_or(partition);
+        cmd::run_output("cryptsetup", ["luksUUID", &source.to_string_lossy()])
     }
 }
 
 // Legacy function wrappers for backwards compatibility during migration
+#[instrument(skip(passphrase))]
 pub fn format_luks(partition: &Path, passphrase: &str, config: &LuksConfig) -> Result<()> {
     Luks2::with_config(config.clone()).format(partition, passphrase)
 }
 
-pub fn open_luks(partition: &Path, name: &str, passphrase: &str) -> Result<PathBuf> {
-    Luks2::new().open(partition, name, passphrase)
+#[instrument(skip(passphrase))]
+pub fn open_luks(
+    partition: &Path,
+    name: &str,
+    passphrase: &str,
+    header: Option<&Path>,
+) -> Result<PathBuf> {
+    with_header(header).open(partition, name, passphrase)
 }
 
 pub fn close_luks(name: &str) -> Result<()> {
     Luks2::new().close(name)
 }
 
-pub fn get_uuid(partition: &Path) -> Result<String> {
-    Luks2::new().get_uuid(partition)
+pub fn get_uuid(partition: &Path, header: Option<&Path>) -> Result<String> {
+    with_header(header).get_uuid(partition)
+}
+
+fn with_header(header: Option<&Path>) -> Luks2 {
+    match header {
+        Some(header) => Luks2::new().with_header(header),
+        None => Luks2::new(),
+    }
 }
@@ -1,14 +1,24 @@
 mod btrfs;
+mod ext4;
+mod header;
 mod luks;
 pub mod snapshot;
+mod xfs;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use std::fmt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 // Re-export implementations
-pub use btrfs::{Btrfs, BtrfsLayout, Subvolume};
-pub use luks::{Luks2, LuksConfig};
+pub use btrfs::{
+    check_quota, enable_quota, validate_compression, Btrfs, BtrfsLayout, Subvolume,
+    SNAPSHOTS_QGROUP,
+};
+pub use ext4::Ext4;
+pub use header::{check_detached_header, DetachedHeader};
+pub use luks::{validate_passphrase, validate_passphrase_policy, Luks2, LuksConfig};
+pub use xfs::Xfs;
 
 // Re-export legacy functions for backwards compatibility
 pub use btrfs::{create_subvolumes, format_btrfs, mount_subvolumes};
@@ -22,6 +32,17 @@ pub struct MountOptions {
     pub extra: Vec<String>,
 }
 
+impl MountOptions {
+    /// Copy of these options without btrfs-only `compress`/`subvol` entries
+    pub fn without_btrfs_options(&self) -> Self {
+        Self {
+            compress: None,
+            subvolume: None,
+            extra: self.extra.clone(),
+        }
+    }
+}
+
 impl fmt::Display for MountOptions {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut opts = Vec::new();
@@ -38,6 +59,126 @@ impl fmt::Display for MountOptions {
     }
 }
 
+/// Available filesystem backends for the root filesystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilesystemKind {
+    #[default]
+    Btrfs,
+    Ext4,
+    Xfs,
+}
+
+impl FilesystemKind {
+    pub fn create(self) -> Box<dyn Filesystem> {
+        match self {
+            FilesystemKind::Btrfs => Box::new(Btrfs::new()),
+            FilesystemKind::Ext4 => Box::new(Ext4::new()),
+            FilesystemKind::Xfs => Box::new(Xfs::new()),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FilesystemKind::Btrfs => "btrfs",
+            FilesystemKind::Ext4 => "ext4",
+            FilesystemKind::Xfs => "xfs",
+        }
+    }
+
+    /// Generic packages the target needs to fsck and manage the root
+    /// filesystem
+    pub fn packages(self) -> &'static [&'static str] {
+        match self {
+            FilesystemKind::Btrfs => &["btrfs-progs"],
+            FilesystemKind::Ext4 => &["e2fsprogs"],
+            FilesystemKind::Xfs => &["xfsprogs"],
+        }
+    }
+}
+
+impl FromStr for FilesystemKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "btrfs" => Ok(FilesystemKind::Btrfs),
+            "ext4" => Ok(FilesystemKind::Ext4),
+            "xfs" => Ok(FilesystemKind::Xfs),
+            other => bail!("Unknown filesystem: {}. Supported: btrfs, ext4, xfs", other),
+        }
+    }
+}
+
+/// Trait for filesystem implementations
+pub trait Filesystem: Send + Sync {
+    /// Filesystem name (e.g., "btrfs", "ext4", "xfs")
+    fn name(&self) -> &str;
+
+    /// Format a device with this filesystem
+    fn format(&self, device: &Path, label: &str) -> Result<()>;
+
+    /// Format one filesystem spanning `devices` (for btrfs RAID)
+    fn format_devices(&self, devices: &[PathBuf], label: &str) -> Result<()> {
+        match devices {
+            [device] => self.format(device, label),
+            _ => anyhow::bail!("{} can't span several disks", self.name()),
+        }
+    }
+
+    /// Mount the filesystem
+    fn mount(&self, device: &Path, target: &Path, options: &MountOptions) -> Result<()>;
+
+    /// Unmount the filesystem
+    fn unmount(&self, target: &Path) -> Result<()>;
+
+    /// Check if this filesystem supports subvolumes/datasets
+    fn supports_subvolumes(&self) -> bool {
+        false
+    }
+
+    /// Create subvolumes/datasets (for btrfs/zfs)
+    fn create_subvolumes(&self, _device: &Path, _subvolumes: &[Subvolume]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Mount with subvolume layout (for btrfs/zfs)
+    fn mount_subvolumes(
+        &self,
+        device: &Path,
+        subvolumes: &[Subvolume],
+        target: &Path,
+        options: &MountOptions,
+    ) -> Result<()>;
+
+    /// Create a snapshot (for btrfs/zfs)
+    fn snapshot(&self, _source: &Path, _dest: &Path, _readonly: bool) -> Result<()> {
+        anyhow::bail!("Snapshots not supported by this filesystem")
+    }
+
+    /// Check if this filesystem supports snapshots
+    fn supports_snapshots(&self) -> bool {
+        false
+    }
+}
+
+/// Trait for disk encryption implementations
+pub trait DiskEncryption: Send + Sync {
+    /// Encryption type name (e.g., "luks2", "luks1")
+    fn name(&self) -> &str;
+
+    /// Format/encrypt a partition
+    fn format(&self, partition: &Path, passphrase: &str) -> Result<()>;
+
+    /// Open/unlock an encrypted partition
+    fn open(&self, partition: &Path, name: &str, passphrase: &str) -> Result<PathBuf>;
+
+    /// Close/lock an encrypted partition
+    fn close(&self, name: &str) -> Result<()>;
+
+    /// Get the UUID of an encrypted partition
+    fn get_uuid(&self, partition: &Path) -> Result<String>;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -57,6 +198,29 @@ mod tests {
         assert_eq!(opts.to_string(), "compress=zstd:1");
     }
 
+    #[test]
+    fn compress_with_level() {
+        let opts = MountOptions {
+            compress: Some("zstd:5".into()),
+            ..Default::default()
+        };
+        assert_eq!(opts.to_string(), "compress=zstd:5");
+    }
+
+    #[test]
+    fn validate_compression_accepts_known_values() {
+        for value in ["zstd", "zstd:1", "zstd:15", "lzo", "zlib", "none"] {
+            assert!(validate_compression(value).is_ok(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn validate_compression_rejects_unknown_values() {
+        for value in ["zstd:0", "zstd:16", "zstd:", "lz4", "gzip", ""] {
+            assert!(validate_compression(value).is_err(), "{}", value);
+        }
+    }
+
     #[test]
     fn subvolume_only() {
         let opts = MountOptions {
@@ -85,6 +249,116 @@ mod tests {
         assert_eq!(opts.to_string(), "compress=zstd:1,subvol=@,ssd");
     }
 
+    #[test]
+    fn btrfs_layout_from_custom_subvolumes() {
+        let subvol = |name: &str, mountpoint: &str| Subvolume {
+            name: name.into(),
+            mountpoint: mountpoint.into(),
+        };
+        let layout = BtrfsLayout::from_subvolumes(vec![
+            subvol("@log", "/var/log"),
+            subvol("@home", "/home"),
+            subvol("@", "/"),
+            subvol("@var", "/var"),
+            subvol("@snapshots", "/.snapshots"),
+        ])
+        .unwrap();
+
+        let names: Vec<_> = layout.subvolumes.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["@", "@home", "@var", "@snapshots", "@swap", "@log"]);
+        assert_eq!(layout.subvolumes[4].mountpoint, "/swap");
+        assert_eq!(layout.compress, "zstd:1");
+    }
+
+    #[test]
+    fn btrfs_layout_requires_root_subvolume() {
+        let result = BtrfsLayout::from_subvolumes(vec![Subvolume {
+            name: "@home".into(),
+            mountpoint: "/home".into(),
+        }]);
+        assert!(result.is_err());
+
+        // The root must be @, whatever else is mounted at /
+        let result = BtrfsLayout::from_subvolumes(vec![
+            Subvolume {
+                name: "@root".into(),
+                mountpoint: "/".into(),
+            },
+            Subvolume {
+                name: "@snapshots".into(),
+                mountpoint: "/.snapshots".into(),
+            },
+        ]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "disk.subvolumes must include @ mounted at /"
+        );
+    }
+
+    #[test]
+    fn btrfs_layout_requires_snapshots_subvolume() {
+        let result = BtrfsLayout::from_subvolumes(vec![Subvolume {
+            name: "@".into(),
+            mountpoint: "/".into(),
+        }]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "disk.subvolumes must include @snapshots mounted at /.snapshots"
+        );
+    }
+
+    #[test]
+    fn passphrase_length_boundary() {
+        let policy = crate::manifest::PassphrasePolicy::default();
+        assert!(validate_passphrase("12345678", &policy).is_ok());
+        assert_eq!(
+            validate_passphrase("1234567", &policy)
+                .unwrap_err()
+                .to_string(),
+            "Passphrase must be at least 8 characters (1 more needed)"
+        );
+        // Characters, not bytes
+        assert!(validate_passphrase("ééééééé", &policy).is_err());
+        assert!(validate_passphrase("éééééééé", &policy).is_ok());
+
+        let relaxed = crate::manifest::PassphrasePolicy {
+            min_length: 1,
+            ..Default::default()
+        };
+        assert!(validate_passphrase("x", &relaxed).is_ok());
+        assert!(validate_passphrase("", &relaxed).is_err());
+        assert!(validate_passphrase_policy(&relaxed).is_ok());
+        assert!(
+            validate_passphrase_policy(&crate::manifest::PassphrasePolicy {
+                min_length: 0,
+                ..Default::default()
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn passphrase_complexity_names_the_missing_class() {
+        let policy = crate::manifest::PassphrasePolicy {
+            min_length: 4,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_symbol: true,
+        };
+        let error = |pass| validate_passphrase(pass, &policy).unwrap_err().to_string();
+
+        assert_eq!(
+            error("abc1!"),
+            "Passphrase must contain an uppercase letter"
+        );
+        assert_eq!(error("ABC1!"), "Passphrase must contain a lowercase letter");
+        assert_eq!(error("Abcd!"), "Passphrase must contain a digit");
+        assert_eq!(error("Abcd1"), "Passphrase must contain a symbol");
+        assert!(validate_passphrase("Abc1 ", &policy).is_ok());
+        assert!(validate_passphrase("Ab1!", &policy).is_ok());
+    }
+
     #[test]
     fn luks_config_defaults() {
         let config = LuksConfig::default();
@@ -95,6 +369,44 @@ mod tests {
         assert_eq!(config.label, "system");
     }
 
+    #[test]
+    fn luks_args_name_a_detached_header() {
+        let partition = Path::new("/dev/sda2");
+        let attached = Luks2::new();
+        assert!(!attached.format_args(partition).contains(&"--header".into()));
+        assert_eq!(
+            attached.open_args(partition, "system"),
+            [
+                "open",
+                "--type",
+                "luks2",
+                "--key-file=-",
+                "/dev/sda2",
+                "system"
+            ]
+        );
+
+        let detached = Luks2::new().with_header("/mnt/usb/system.hdr");
+        let format = detached.format_args(partition);
+        assert_eq!(
+            format[format.len() - 3..],
+            ["--header", "/mnt/usb/system.hdr", "/dev/sda2"]
+        );
+        assert_eq!(
+            detached.open_args(partition, "system"),
+            [
+                "open",
+                "--type",
+                "luks2",
+                "--key-file=-",
+                "--header",
+                "/mnt/usb/system.hdr",
+                "/dev/sda2",
+                "system"
+            ]
+        );
+    }
+
     #[test]
     fn luks2_with_label() {
         let luks = Luks2::new().with_label("myroot");
@@ -137,66 +449,69 @@ mod tests {
         let btrfs = Btrfs::new().with_compress("lzo");
         assert_eq!(btrfs.compress, "lzo");
     }
-}
 
-/// Trait for filesystem implementations
-pub trait Filesystem: Send + Sync {
-    /// Filesystem name (e.g., "btrfs", "ext4", "xfs")
-    fn name(&self) -> &str;
-
-    /// Format a device with this filesystem
-    fn format(&self, device: &Path, label: &str) -> Result<()>;
-
-    /// Mount the filesystem
-    fn mount(&self, device: &Path, target: &Path, options: &MountOptions) -> Result<()>;
-
-    /// Unmount the filesystem
-    fn unmount(&self, target: &Path) -> Result<()>;
-
-    /// Check if this filesystem supports subvolumes/datasets
-    fn supports_subvolumes(&self) -> bool {
-        false
+    #[test]
+    fn ext4_new_defaults() {
+        let ext4 = Ext4::new();
+        assert_eq!(ext4.name(), "ext4");
+        assert_eq!(ext4.mount_options, vec!["noatime"]);
+        assert!(!ext4.supports_subvolumes());
+        assert!(!ext4.supports_snapshots());
     }
 
-    /// Create subvolumes/datasets (for btrfs/zfs)
-    fn create_subvolumes(&self, _device: &Path, _subvolumes: &[Subvolume]) -> Result<()> {
-        Ok(())
+    #[test]
+    fn ext4_snapshot_is_unsupported() {
+        let ext4 = Ext4::new();
+        assert!(ext4
+            .snapshot(Path::new("/"), Path::new("/.snapshots/x"), true)
+            .is_err());
     }
 
-    /// Mount with subvolume layout (for btrfs/zfs)
-    fn mount_subvolumes(
-        &self,
-        device: &Path,
-        subvolumes: &[Subvolume],
-        target: &Path,
-        options: &MountOptions,
-    ) -> Result<()>;
-
-    /// Create a snapshot (for btrfs/zfs)
-    fn snapshot(&self, _source: &Path, _dest: &Path, _readonly: bool) -> Result<()> {
-        anyhow::bail!("Snapshots not supported by this filesystem")
+    #[test]
+    fn filesystem_kind_from_str() {
+        assert_eq!(
+            "btrfs".parse::<FilesystemKind>().unwrap(),
+            FilesystemKind::Btrfs
+        );
+        assert_eq!(
+            "ext4".parse::<FilesystemKind>().unwrap(),
+            FilesystemKind::Ext4
+        );
+        assert_eq!(
+            "xfs".parse::<FilesystemKind>().unwrap(),
+            FilesystemKind::Xfs
+        );
+        assert!("zfs".parse::<FilesystemKind>().is_err());
     }
 
-    /// Check if this filesystem supports snapshots
-    fn supports_snapshots(&self) -> bool {
-        false
+    #[test]
+    fn targets_get_their_filesystem_tools() {
+        assert_eq!(FilesystemKind::Ext4.packages(), &["e2fsprogs"]);
+        assert_eq!(FilesystemKind::Btrfs.packages(), &["btrfs-progs"]);
+        assert_eq!(FilesystemKind::Xfs.packages(), &["xfsprogs"]);
     }
-}
 
-/// Trait for disk encryption implementations
-pub trait DiskEncryption: Send + Sync {
-    /// Encryption type name (e.g., "luks2", "luks1")
-    fn name(&self) -> &str;
-
-    /// Format/encrypt a partition
-    fn format(&self, partition: &Path, passphrase: &str) -> Result<()>;
-
-    /// Open/unlock an encrypted partition
-    fn open(&self, partition: &Path, name: &str, passphrase: &str) -> Result<PathBuf>;
-
-    /// Close/lock an encrypted partition
-    fn close(&self, name: &str) -> Result<()>;
+    #[test]
+    fn filesystem_kind_creates_matching_backend() {
+        for kind in [
+            FilesystemKind::Btrfs,
+            FilesystemKind::Ext4,
+            FilesystemKind::Xfs,
+        ] {
+            assert_eq!(kind.create().name(), kind.name());
+        }
+        assert!(FilesystemKind::Btrfs.create().supports_snapshots());
+        assert!(!FilesystemKind::Ext4.create().supports_snapshots());
+        assert!(!FilesystemKind::Xfs.create().supports_subvolumes());
+    }
 
-    /// Get the UUID of an encrypted partition
-    fn get_uuid(&self, partition: &Path) -> Result<String>;
+    #[test]
+    fn without_btrfs_options_keeps_extra() {
+        let opts = MountOptions {
+            compress: Some("zstd:1".into()),
+            subvolume: Some("@home".into()),
+            extra: vec!["noatime".into()],
+        };
+        assert_eq!(opts.without_btrfs_options().to_string(), "noatime");
+    }
 }
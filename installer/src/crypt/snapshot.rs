@@ -1,8 +1,11 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
 use std::path::Path;
+use std::process::Command;
 
 use crate::cmd;
 use crate::paths;
+use crate::swap::SWAPFILE;
 
 #[derive(Debug, Clone)]
 pub struct Snapshot {
@@ -25,12 +28,16 @@ pub fn create_snapshot(
     if read_only {
         args.push("-r");
     }
+    args.extend(qgroup_args(snapshots_dir));
     args.push(&source_str);
     args.push(&snapshot_str);
 
     cmd::run("btrfs", args)
 }
 
+/// Name of the read-only snapshot taken at the end of an install
+pub const INSTALL_SNAPSHOT: &str = "install";
+
 pub fn create_install_snapshot(target_root: &Path) -> Result<()> {
     let snapshots_dir = target_root.join(paths::SNAPSHOTS_DIR);
     std::fs::create_dir_all(&snapshots_dir)?;
@@ -39,7 +46,7 @@ pub fn create_install_snapshot(target_root: &Path) -> Result<()> {
     create_snapshot(
         &snapshots_dir,
         target_root,
-        "install",
+        INSTALL_SNAPSHOT,
         true, // read-only
     )?;
 
@@ -63,6 +70,63 @@ pub fn list_snapshots(snapshots_dir: &Path) -> Result<Vec<String>> {
     Ok(snapshots)
 }
 
+/// `btrfs qgroup show --raw` for the filesystem holding `path`, None when
+/// quotas are off
+pub fn qgroup_show(path: &Path) -> Option<String> {
+    let output = Command::new("btrfs")
+        .args(["qgroup", "show", "--raw"])
+        .arg(path)
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Exclusive bytes of each subvolume in `btrfs qgroup show --raw` output,
+/// by subvolume ID: the space deleting it would free
+pub fn exclusive_sizes(qgroups: &str) -> HashMap<u64, u64> {
+    qgroups
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let id = fields.next()?.strip_prefix("0/")?.parse().ok()?;
+            let _referenced: u64 = fields.next()?.parse().ok()?;
+            let exclusive = fields.next()?.parse().ok()?;
+            Some((id, exclusive))
+        })
+        .collect()
+}
+
+/// Arguments adding a new snapshot to the snapshots' qgroup, when quotas
+/// set one up on the filesystem holding `path`, so its limit counts it
+pub fn qgroup_args(path: &Path) -> Vec<&'static str> {
+    let qgroups = qgroup_show(path).unwrap_or_default();
+    if has_qgroup(&qgroups, super::SNAPSHOTS_QGROUP) {
+        vec!["-i", super::SNAPSHOTS_QGROUP]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Whether `btrfs qgroup show` output lists the qgroup `id`
+fn has_qgroup(qgroups: &str, id: &str) -> bool {
+    qgroups
+        .lines()
+        .any(|line| line.split_whitespace().next() == Some(id))
+}
+
+/// Subvolume ID of the subvolume at `path`
+pub fn subvolume_id(path: &Path) -> Option<u64> {
+    let output = Command::new("btrfs")
+        .args(["inspect-internal", "rootid"])
+        .arg(path)
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
 pub fn delete_snapshot(snapshot_path: &Path) -> Result<()> {
     cmd::run(
         "btrfs",
@@ -79,6 +143,120 @@ pub fn is_btrfs_root() -> bool {
         .unwrap_or(false)
 }
 
+/// Extract the root subvolume from findmnt SOURCE and OPTIONS output
+///
+/// SOURCE looks like `/dev/mapper/system[/@snapshots/install]`; when it has no
+/// bracket the `subvol=` mount option is used, falling back to `@`.
+pub fn parse_root_subvolume(source: &str, options: &str) -> String {
+    if let Some(bracket_pos) = source.find('[') {
+        let end_bracket = source.find(']').unwrap_or(source.len());
+        return source[bracket_pos + 1..end_bracket]
+            .trim_start_matches('/')
+            .to_string();
+    }
+
+    options
+        .split(',')
+        .find_map(|opt| opt.trim().strip_prefix("subvol="))
+        .map(|subvol| subvol.trim_start_matches('/').to_string())
+        .unwrap_or_else(|| "@".to_string())
+}
+
+/// Detect the subvolume currently mounted at /
+pub fn current_root_subvolume() -> Result<String> {
+    let findmnt = |column: &str| -> Result<String> {
+        let output = std::process::Command::new("findmnt")
+            .args(["-n", "-o", column, "/"])
+            .output()
+            .context("Failed to run findmnt")?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+
+    Ok(parse_root_subvolume(
+        &findmnt("SOURCE")?,
+        &findmnt("OPTIONS")?,
+    ))
+}
+
+/// Block device backing / (with any `[subvol]` suffix stripped)
+pub fn root_device() -> Result<String> {
+    let output = std::process::Command::new("findmnt")
+        .args(["-n", "-o", "SOURCE", "/"])
+        .output()
+        .context("Failed to find root device")?;
+
+    let source = String::from_utf8_lossy(&output.stdout);
+    Ok(strip_subvolume(source.trim()).to_string())
+}
+
+/// Strip the `[subvol]` suffix findmnt appends to btrfs sources
+pub fn strip_subvolume(source: &str) -> &str {
+    match source.find('[') {
+        Some(bracket_pos) => &source[..bracket_pos],
+        None => source,
+    }
+}
+
+/// Run `f` with the swapfile swapped off if it is active, re-enabling it after
+pub fn with_swapfile_disabled<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let swapfile_active = std::process::Command::new("swapon")
+        .args(["--show", "--noheadings"])
+        .output()
+        .map(|o| swapfile_listed(&String::from_utf8_lossy(&o.stdout)))
+        .unwrap_or(false);
+
+    if swapfile_active {
+        println!("  Temporarily disabling swap...");
+        std::process::Command::new("swapoff")
+            .arg(SWAPFILE)
+            .status()
+            .context("Failed to disable swap")?;
+    }
+
+    let result = f();
+
+    if swapfile_active {
+        println!("  Re-enabling swap...");
+        let _ = std::process::Command::new("swapon").arg(SWAPFILE).status();
+    }
+
+    result
+}
+
+/// Whether `swapon --show` output lists the mkOS swapfile
+fn swapfile_listed(swapon: &str) -> bool {
+    swapon
+        .lines()
+        .any(|line| line.split_whitespace().next() == Some(SWAPFILE))
+}
+
+/// Mount the btrfs top-level (subvolid=5) of the root device and run `f`
+/// with its mount point. The top-level is always unmounted afterwards.
+pub fn with_toplevel_mount<T>(f: impl FnOnce(&Path) -> Result<T>) -> Result<T> {
+    let device = root_device()?;
+    let temp_mount = Path::new(paths::TEMP_BTRFS_MOUNT);
+    std::fs::create_dir_all(temp_mount)?;
+
+    let mount_status = std::process::Command::new("mount")
+        .args(["-o", "subvolid=5", &device, &temp_mount.to_string_lossy()])
+        .status()
+        .context("Failed to mount btrfs root")?;
+
+    if !mount_status.success() {
+        let _ = std::fs::remove_dir(temp_mount);
+        anyhow::bail!("Failed to mount btrfs root");
+    }
+
+    let result = f(temp_mount);
+
+    let _ = std::process::Command::new("umount")
+        .arg(temp_mount)
+        .status();
+    let _ = std::fs::remove_dir(temp_mount);
+
+    result
+}
+
 /// Create a pre-apply snapshot with timestamp
 pub fn create_pre_apply_snapshot() -> Result<Option<String>> {
     if !is_btrfs_root() {
@@ -97,3 +275,88 @@ pub fn create_pre_apply_snapshot() -> Result<Option<String>> {
 
     Ok(Some(name))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swapfile_is_found_in_swapon_output() {
+        assert!(swapfile_listed(
+            "/dev/zram0 partition   7.7G   0B  100\n/swap/swapfile file 8G 0B 10\n"
+        ));
+        assert!(!swapfile_listed("/swapfile file 8G 0B -2\n"));
+        assert!(!swapfile_listed(""));
+    }
+
+    const QGROUP_SHOW: &str = "\
+Qgroupid    Referenced    Exclusive   Path
+--------    ----------    ---------   ----
+0/5              16384        16384   <toplevel>
+0/256       4521984000    104857600   @
+0/258           131072       131072   @snapshots
+0/261       4400000000     12582912   @snapshots/install
+0/262       4480000000    335544320   @snapshots/pre-upgrade-2026-10-01T09:00:00
+1/0         4812345344    348258304   <0 member qgroups>
+";
+
+    #[test]
+    fn qgroup_show_maps_subvolumes_to_exclusive_size() {
+        let sizes = exclusive_sizes(QGROUP_SHOW);
+        assert_eq!(sizes.len(), 5);
+        assert_eq!(sizes[&261], 12_582_912);
+        assert_eq!(sizes[&262], 335_544_320);
+        assert!(!sizes.contains_key(&0));
+
+        // Older btrfs-progs print no Path column
+        let sizes =
+            exclusive_sizes("qgroupid rfer excl\n-------- ---- ----\n0/261 4400000000 12582912\n");
+        assert_eq!(sizes[&261], 12_582_912);
+        assert!(exclusive_sizes("").is_empty());
+    }
+
+    #[test]
+    fn snapshots_qgroup_is_found() {
+        assert!(has_qgroup(QGROUP_SHOW, "1/0"));
+        assert!(!has_qgroup(QGROUP_SHOW, "1/1"));
+        assert!(!has_qgroup("", "1/0"));
+    }
+
+    #[test]
+    fn root_subvolume_from_source_bracket() {
+        assert_eq!(
+            parse_root_subvolume("/dev/mapper/system[/@snapshots/install]", ""),
+            "@snapshots/install"
+        );
+        assert_eq!(parse_root_subvolume("/dev/mapper/system[@]", ""), "@");
+    }
+
+    #[test]
+    fn strip_subvolume_bracket() {
+        assert_eq!(
+            strip_subvolume("/dev/mapper/system[/@snapshots/install]"),
+            "/dev/mapper/system"
+        );
+        assert_eq!(
+            strip_subvolume("/dev/mapper/system[/@]"),
+            "/dev/mapper/system"
+        );
+        assert_eq!(strip_subvolume("/dev/sda2"), "/dev/sda2");
+    }
+
+    #[test]
+    fn root_subvolume_from_options() {
+        assert_eq!(
+            parse_root_subvolume("/dev/mapper/system", "rw,noatime,subvol=/@,compress=zstd:1"),
+            "@"
+        );
+    }
+
+    #[test]
+    fn root_subvolume_defaults_to_at() {
+        assert_eq!(
+            parse_root_subvolume("/dev/mapper/system", "rw,noatime"),
+            "@"
+        );
+    }
+}
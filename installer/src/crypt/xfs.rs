@@ -0,0 +1,92 @@
+use super::{Filesystem, MountOptions, Subvolume};
+use anyhow::Result;
+use std::path::Path;
+
+use crate::cmd;
+
+/// XFS filesystem implementation (no subvolumes, no snapshots)
+#[derive(Debug, Clone, Default)]
+pub struct Xfs {
+    /// Additional mount options
+    pub mount_options: Vec<String>,
+}
+
+impl Xfs {
+    pub fn new() -> Self {
+        Self {
+            mount_options: vec!["noatime".into()],
+        }
+    }
+
+    /// Build the mount option string, dropping btrfs-only options
+    fn mount_opts(&self, options: &MountOptions) -> String {
+        let mut opts = MountOptions {
+            extra: self.mount_options.clone(),
+            ..Default::default()
+        };
+        opts.extra.extend(options.without_btrfs_options().extra);
+        opts.to_string()
+    }
+}
+
+impl Filesystem for Xfs {
+    fn name(&self) -> &str {
+        "xfs"
+    }
+
+    fn format(&self, device: &Path, label: &str) -> Result<()> {
+        cmd::run("mkfs.xfs", ["-L", label, "-f", &device.to_string_lossy()])
+    }
+
+    fn mount(&self, device: &Path, target: &Path, options: &MountOptions) -> Result<()> {
+        std::fs::create_dir_all(target)?;
+
+        let opts = self.mount_opts(options);
+        let device_str = device.to_string_lossy().to_string();
+        let target_str = target.to_string_lossy().to_string();
+
+        if opts.is_empty() {
+            cmd::run("mount", [&device_str, &target_str])
+        } else {
+            cmd::run("mount", ["-o", &opts, &device_str, &target_str])
+        }
+    }
+
+    fn unmount(&self, target: &Path) -> Result<()> {
+        let target_str = target.to_string_lossy().to_string();
+        cmd::run("umount", [&target_str])
+    }
+
+    /// XFS has no subvolumes, so the whole device is mounted at the target root
+    fn mount_subvolumes(
+        &self,
+        device: &Path,
+        _subvolumes: &[Subvolume],
+        target: &Path,
+        options: &MountOptions,
+    ) -> Result<()> {
+        self.mount(device, target, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mount_opts_drop_btrfs_options() {
+        let xfs = Xfs::new();
+        let options = MountOptions {
+            compress: Some("zstd:1".into()),
+            subvolume: Some("@".into()),
+            extra: vec!["inode64".into()],
+        };
+        assert_eq!(xfs.mount_opts(&options), "noatime,inode64");
+    }
+
+    #[test]
+    fn mount_opts_defaults() {
+        let xfs = Xfs::new();
+        assert_eq!(xfs.mount_opts(&MountOptions::default()), "noatime");
+    }
+}
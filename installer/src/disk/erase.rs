@@ -0,0 +1,163 @@
+//! Erasing a whole disk before partitioning, beyond clearing signatures
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::cmd;
+
+/// Mapping the random fill writes through
+const FILL_MAPPER_NAME: &str = "mkos_erase";
+
+/// How `disk.secure_erase` clears a disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EraseMethod {
+    /// Discard every block, which is fast and leaves an SSD reading zeros
+    Discard,
+    /// Write the whole disk through a throwaway plain dm-crypt mapping, so
+    /// it reads as random data and used space can't be told from free
+    RandomFill,
+}
+
+impl EraseMethod {
+    /// Discard for SSDs, which remap writes and make overwriting
+    /// unreliable, and random fill for spinning disks
+    pub fn for_device(device: &Path) -> Self {
+        let queue = device
+            .file_name()
+            .map(|name| Path::new("/sys/class/block").join(name).join("queue"))
+            .unwrap_or_default();
+        let read = |file: &str| fs::read_to_string(queue.join(file)).unwrap_or_default();
+        Self::choose(
+            read("rotational").trim() == "1",
+            read("discard_max_bytes").trim(),
+        )
+    }
+
+    fn choose(rotational: bool, discard_max_bytes: &str) -> Self {
+        let discards = discard_max_bytes.parse::<u64>().is_ok_and(|max| max > 0);
+        if !rotational && discards {
+            Self::Discard
+        } else {
+            Self::RandomFill
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Discard => "discarding all blocks",
+            Self::RandomFill => "filling with random data",
+        }
+    }
+
+    /// Commands erasing `device`, `size` bytes long, in order
+    fn commands(self, device: &str, size: u64) -> Vec<(&'static str, Vec<String>)> {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect();
+        match self {
+            Self::Discard => vec![("blkdiscard", args(&["--force", device]))],
+            Self::RandomFill => {
+                let mapped = format!("/dev/mapper/{}", FILL_MAPPER_NAME);
+                vec![
+                    (
+                        "cryptsetup",
+                        args(&[
+                            "open",
+                            "--type",
+                            "plain",
+                            "--key-file",
+                            "/dev/urandom",
+                            device,
+                            FILL_MAPPER_NAME,
+                        ]),
+                    ),
+                    // Zeros encrypted with a key nobody keeps; the byte count
+                    // stops dd at the end instead of failing with ENOSPC
+                    (
+                        "dd",
+                        args(&[
+                            "if=/dev/zero",
+                            &format!("of={}", mapped),
+                            "bs=4M",
+                            &format!("count={}", size),
+                            "iflag=count_bytes",
+                            "oflag=direct",
+                            "status=progress",
+                        ]),
+                    ),
+                    ("cryptsetup", args(&["close", FILL_MAPPER_NAME])),
+                ]
+            }
+        }
+    }
+}
+
+/// Erase all of `device` with `method`; the commands report their progress
+/// on stderr as they go
+pub fn erase_device(device: &Path, method: EraseMethod) -> Result<()> {
+    super::validate_device(device)?;
+    let device_str = device.to_string_lossy();
+    let size: u64 = cmd::run_output("blockdev", ["--getsize64", &device_str])?
+        .parse()
+        .with_context(|| format!("Failed to read the size of {}", device.display()))?;
+
+    for (program, args) in method.commands(&device_str, size) {
+        if let Err(e) = cmd::run(program, &args) {
+            // Don't leave the fill mapping holding the disk open
+            if method == EraseMethod::RandomFill {
+                let _ = cmd::run("cryptsetup", ["close", FILL_MAPPER_NAME]);
+            }
+            return Err(e.context(format!("Failed to erase {}", device.display())));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssds_are_discarded_and_disks_filled() {
+        assert_eq!(
+            EraseMethod::choose(false, "2199023255040"),
+            EraseMethod::Discard
+        );
+        assert_eq!(EraseMethod::choose(true, "0"), EraseMethod::RandomFill);
+        // An SSD behind a controller that doesn't pass discards through
+        assert_eq!(EraseMethod::choose(false, "0"), EraseMethod::RandomFill);
+        assert_eq!(EraseMethod::choose(false, ""), EraseMethod::RandomFill);
+    }
+
+    #[test]
+    fn discard_runs_blkdiscard() {
+        let commands = EraseMethod::Discard.commands("/dev/nvme0n1", 1 << 40);
+        assert_eq!(
+            commands,
+            [("blkdiscard", vec!["--force".into(), "/dev/nvme0n1".into()])]
+        );
+    }
+
+    #[test]
+    fn random_fill_writes_through_plain_dm_crypt() {
+        let commands = EraseMethod::RandomFill.commands("/dev/sda", 1 << 30);
+        let programs: Vec<&str> = commands.iter().map(|(program, _)| *program).collect();
+        assert_eq!(programs, ["cryptsetup", "dd", "cryptsetup"]);
+
+        assert_eq!(
+            commands[0].1,
+            [
+                "open",
+                "--type",
+                "plain",
+                "--key-file",
+                "/dev/urandom",
+                "/dev/sda",
+                "mkos_erase"
+            ]
+        );
+        assert!(commands[1].1.contains(&"of=/dev/mapper/mkos_erase".into()));
+        assert!(commands[1].1.contains(&"count=1073741824".into()));
+        assert!(commands[1].1.contains(&"status=progress".into()));
+        assert_eq!(commands[2].1, ["close", "mkos_erase"]);
+    }
+}
@@ -0,0 +1,164 @@
+//! Raw disk image files, installed to through a loop device
+
+use anyhow::{bail, Context, Result};
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+
+use crate::cmd;
+
+/// Attaches image files as block devices
+pub trait LoopControl {
+    /// Attach `image` with its partitions scanned, returning the device
+    fn attach(&self, image: &Path) -> Result<PathBuf>;
+
+    fn detach(&self, device: &Path) -> Result<()>;
+}
+
+/// Loop devices through util-linux `losetup`
+pub struct Losetup;
+
+impl LoopControl for Losetup {
+    fn attach(&self, image: &Path) -> Result<PathBuf> {
+        let device = cmd::run_output(
+            "losetup",
+            ["--find", "--partscan", "--show", &image.to_string_lossy()],
+        )
+        .with_context(|| format!("Failed to attach {}", image.display()))?;
+        Ok(PathBuf::from(device.trim()))
+    }
+
+    fn detach(&self, device: &Path) -> Result<()> {
+        cmd::run("losetup", ["--detach", &device.to_string_lossy()])
+    }
+}
+
+/// Create `path` as a sparse file of `size` bytes, refusing to overwrite
+pub fn create_image(path: &Path, size: u64) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    file.set_len(size)
+        .with_context(|| format!("Failed to size {}", path.display()))
+}
+
+/// Run `install` against `image` attached as a loop device, detaching it
+/// afterwards whether or not `install` succeeded
+pub fn with_loop_device<T>(
+    control: &dyn LoopControl,
+    image: &Path,
+    install: impl FnOnce(&Path) -> Result<T>,
+) -> Result<T> {
+    let device = control.attach(image)?;
+    let result = install(&device);
+    let detached = control.detach(&device);
+    let value = result?;
+    detached?;
+    Ok(value)
+}
+
+/// Bytes in a size like `40G`, `512M` or `1T` (powers of 1024); bare
+/// numbers are bytes
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let (number, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => size.split_at(i),
+        None => (size, ""),
+    };
+    let shift = match unit
+        .to_ascii_uppercase()
+        .trim_end_matches("IB")
+        .trim_end_matches('B')
+    {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => bail!("Unknown size unit in {}; use K, M, G or T", size),
+    };
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("Invalid size: {}", size))?;
+    number
+        .checked_mul(1 << shift)
+        .filter(|&bytes| bytes > 0)
+        .with_context(|| format!("Invalid size: {}", size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Records attach and detach calls instead of running losetup
+    #[derive(Default)]
+    struct FakeLoop(Mutex<Vec<String>>);
+
+    impl LoopControl for FakeLoop {
+        fn attach(&self, image: &Path) -> Result<PathBuf> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("attach {}", image.display()));
+            Ok(PathBuf::from("/dev/loop7"))
+        }
+
+        fn detach(&self, device: &Path) -> Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("detach {}", device.display()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn loop_device_is_detached_after_install() {
+        let control = FakeLoop::default();
+        let device = with_loop_device(&control, Path::new("out.img"), |device| {
+            Ok(device.to_path_buf())
+        })
+        .unwrap();
+
+        assert_eq!(device, Path::new("/dev/loop7"));
+        assert_eq!(
+            *control.0.lock().unwrap(),
+            ["attach out.img", "detach /dev/loop7"]
+        );
+    }
+
+    #[test]
+    fn loop_device_is_detached_when_install_fails() {
+        let control = FakeLoop::default();
+        let result: Result<()> =
+            with_loop_device(&control, Path::new("out.img"), |_| bail!("pacstrap failed"));
+
+        assert_eq!(result.unwrap_err().to_string(), "pacstrap failed");
+        assert_eq!(
+            control.0.lock().unwrap().last().unwrap(),
+            "detach /dev/loop7"
+        );
+    }
+
+    #[test]
+    fn image_is_sparse_and_never_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("out.img");
+        create_image(&image, 40 << 30).unwrap();
+        assert_eq!(std::fs::metadata(&image).unwrap().len(), 40 << 30);
+        assert!(create_image(&image, 1 << 30).is_err());
+    }
+
+    #[test]
+    fn sizes_take_binary_units() {
+        assert_eq!(parse_size("40G").unwrap(), 40 << 30);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("1TiB").unwrap(), 1 << 40);
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert!(parse_size("40X").is_err());
+        assert!(parse_size("0G").is_err());
+        assert!(parse_size("G").is_err());
+    }
+}
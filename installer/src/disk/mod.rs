@@ -1,12 +1,19 @@
+mod erase;
+mod image;
 mod partition;
+mod raid;
 
+pub use erase::{erase_device, EraseMethod};
+pub use image::{create_image, parse_size, with_loop_device, LoopControl, Losetup};
 pub use partition::*;
+pub use raid::{check_raid_devices, check_raid_member, member_name};
 
 use crate::cmd;
 use anyhow::Result;
 use std::os::unix::fs::FileTypeExt;
 use std::path::Path;
 use std::process::Command;
+use tracing::instrument;
 
 #[derive(Debug, Clone)]
 pub struct BlockDevice {
@@ -16,9 +23,62 @@ pub struct BlockDevice {
     pub removable: bool,
 }
 
+impl BlockDevice {
+    /// Size in decimal gigabytes, as disk vendors label them
+    pub fn size_label(&self) -> String {
+        format!("{} GB", self.size_bytes / 1_000_000_000)
+    }
+}
+
+/// What's already on a disk, as seen by lsblk
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiskContents {
+    /// Partition table type (gpt, dos)
+    pub partition_table: Option<String>,
+    /// Filesystem and LUKS signatures on the disk or its partitions
+    pub signatures: Vec<String>,
+}
+
+impl DiskContents {
+    pub fn is_empty(&self) -> bool {
+        self.partition_table.is_none() && self.signatures.is_empty()
+    }
+
+    /// One-line description for confirmation screens, None for a blank disk
+    pub fn warning(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let table = self
+            .partition_table
+            .as_deref()
+            .map(|t| format!("a {} partition table", t));
+        let signatures = (!self.signatures.is_empty())
+            .then(|| format!("existing {} data", self.signatures.join(", ")));
+
+        let found: Vec<String> = [table, signatures].into_iter().flatten().collect();
+        Some(format!("Disk contains {}", found.join(" and ")))
+    }
+}
+
+/// Smallest EFI partition that holds the main, fallback and rescue UKIs
+pub const MIN_EFI_SIZE_MB: u64 = 512;
+
+/// Characters FAT doesn't allow in a volume label
+const FAT_LABEL_FORBIDDEN: &str = "\"*+,./:;<=>?[\\]|";
+
 #[derive(Debug, Clone)]
 pub struct PartitionLayout {
     pub efi_size_mb: u64,
+    /// FAT volume label of the EFI partition
+    pub efi_label: String,
+    /// Keep the disk's partitions and the EFI partition's contents, for
+    /// dual-booting; mkOS takes partition 2
+    pub reuse_esp: bool,
+    /// Discard or random-fill the whole disk before partitioning, rather
+    /// than only clearing its signatures
+    pub secure_erase: bool,
     pub root_size_mb: Option<u64>, // None = use remaining space
     pub home_size_mb: Option<u64>, // None = use remaining after root
 }
@@ -26,13 +86,186 @@ pub struct PartitionLayout {
 impl Default for PartitionLayout {
     fn default() -> Self {
         Self {
-            efi_size_mb: 1024,  // 1GB for UKI
+            efi_size_mb: 1024, // 1GB for UKI
+            efi_label: "MKOS_EFI".into(),
+            reuse_esp: false,
+            secure_erase: false,
             root_size_mb: None, // Will be calculated
             home_size_mb: None, // Rest goes to home
         }
     }
 }
 
+impl PartitionLayout {
+    /// Check the EFI partition fits the UKIs and its label fits FAT
+    pub fn validate(&self) -> Result<()> {
+        if self.efi_size_mb < MIN_EFI_SIZE_MB {
+            anyhow::bail!(
+                "disk.efi_size_mb is {}, but the UKIs need at least {}",
+                self.efi_size_mb,
+                MIN_EFI_SIZE_MB
+            );
+        }
+        if self.efi_label.is_empty()
+            || self.efi_label.len() > 11
+            || !self
+                .efi_label
+                .chars()
+                .all(|c| (c.is_ascii_graphic() && !FAT_LABEL_FORBIDDEN.contains(c)) || c == ' ')
+        {
+            anyhow::bail!(
+                "disk.efi_label must be 1 to 11 letters, digits or symbols FAT allows, not {:?}",
+                self.efi_label
+            );
+        }
+        if self.reuse_esp && self.secure_erase {
+            anyhow::bail!("disk.secure_erase would erase the EFI partition disk.reuse_esp keeps");
+        }
+        Ok(())
+    }
+}
+
+/// What's already on partitions 1 and 2 of a disk whose EFI partition
+/// mkOS would keep
+#[derive(Debug, Clone, Default)]
+pub struct ExistingPartitions {
+    /// Filesystem on partition 1
+    pub efi_fstype: Option<String>,
+    /// Size and free space of partition 1's filesystem, in MB
+    pub efi_size_mb: u64,
+    pub efi_free_mb: u64,
+    pub mkos_exists: bool,
+    /// GPT partition type GUID of partition 2
+    pub mkos_parttype: Option<String>,
+    /// Filesystem on partition 2
+    pub mkos_fstype: Option<String>,
+}
+
+/// Read what's on partitions 1 and 2 of `device`
+///
+/// A FAT partition 1 is mounted read-only for a moment to measure its free
+/// space.
+pub fn probe_existing_partitions(device: &Path) -> Result<ExistingPartitions> {
+    let parts = detect_partitions(device)?;
+    let efi_fstype = filesystem_type(&parts.efi);
+    let (efi_size_mb, efi_free_mb) = if efi_fstype.as_deref() == Some("vfat") {
+        filesystem_space_mb(&parts.efi)?
+    } else {
+        (0, 0)
+    };
+
+    Ok(ExistingPartitions {
+        efi_fstype,
+        efi_size_mb,
+        efi_free_mb,
+        mkos_exists: parts.luks.exists(),
+        mkos_parttype: partition_type(&parts.luks),
+        mkos_fstype: filesystem_type(&parts.luks),
+    })
+}
+
+/// GPT partition type GUID of `partition`, from its partition table entry
+fn partition_type(partition: &Path) -> Option<String> {
+    let output = Command::new("blkid")
+        .args(["-p", "-s", "PART_ENTRY_TYPE", "-o", "value"])
+        .arg(partition)
+        .output()
+        .ok()?;
+    let parttype = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!parttype.is_empty()).then_some(parttype)
+}
+
+/// Size and free space in MB of the filesystem on `partition`
+fn filesystem_space_mb(partition: &Path) -> Result<(u64, u64)> {
+    let mountpoint = tempfile::tempdir()?;
+    let mount_str = mountpoint.path().to_string_lossy();
+    cmd::run(
+        "mount",
+        ["-o", "ro", &partition.to_string_lossy(), &*mount_str],
+    )?;
+    let output = Command::new("df")
+        .args(["-B1M", "--output=size,avail", &*mount_str])
+        .output();
+    cmd::run("umount", [&*mount_str])?;
+
+    let output = output?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    parse_df_space(&stdout).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Can't read the free space on {}: {}",
+            partition.display(),
+            stdout.trim()
+        )
+    })
+}
+
+/// Parse `df --output=size,avail` output: a header line, then the sizes
+fn parse_df_space(output: &str) -> Option<(u64, u64)> {
+    let mut fields = output.lines().nth(1)?.split_whitespace();
+    let size = fields.next()?.parse().ok()?;
+    let avail = fields.next()?.parse().ok()?;
+    Some((size, avail))
+}
+
+/// Name of a GPT partition type other systems commonly put at partition 2
+fn partition_type_name(guid: &str) -> Option<&'static str> {
+    match guid.to_ascii_uppercase().as_str() {
+        "E3C9E316-0B5C-4DB8-817D-F92DF00215AE" => Some("Microsoft reserved"),
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7" => Some("Microsoft basic data"),
+        "DE94BBA4-06D1-4D40-A16A-BFD50179D6AC" => Some("Windows recovery"),
+        _ => None,
+    }
+}
+
+/// Check a disk can be installed to without repartitioning: partition 1 is
+/// a FAT EFI partition to keep with room for the UKIs, and partition 2 is
+/// an empty Linux filesystem partition for mkOS, so no other system's data
+/// or reserved space gets encrypted over
+pub fn check_reusable_esp(existing: &ExistingPartitions) -> Result<()> {
+    match existing.efi_fstype.as_deref() {
+        Some("vfat") => {}
+        Some(other) => anyhow::bail!(
+            "disk.reuse_esp is set, but partition 1 is {}, not a FAT EFI partition",
+            other
+        ),
+        None => anyhow::bail!("disk.reuse_esp is set, but partition 1 has no filesystem"),
+    }
+    if existing.efi_size_mb < MIN_EFI_SIZE_MB {
+        anyhow::bail!(
+            "disk.reuse_esp is set, but the EFI partition is {} MB; the UKIs need at least {} MB",
+            existing.efi_size_mb,
+            MIN_EFI_SIZE_MB
+        );
+    }
+    if existing.efi_free_mb < MIN_EFI_SIZE_MB {
+        anyhow::bail!(
+            "disk.reuse_esp is set, but the EFI partition has {} MB free; the UKIs need at least {} MB",
+            existing.efi_free_mb,
+            MIN_EFI_SIZE_MB
+        );
+    }
+    if !existing.mkos_exists {
+        anyhow::bail!("disk.reuse_esp is set, so create an empty partition 2 for mkOS first");
+    }
+    match existing.mkos_parttype.as_deref() {
+        Some(guid) if guid.eq_ignore_ascii_case(LINUX_FILESYSTEM_GUID) => {}
+        Some(guid) => anyhow::bail!(
+            "disk.reuse_esp is set, but partition 2 is a {} partition, not Linux filesystem; \
+             change its type to {} if mkOS should take it",
+            partition_type_name(guid).unwrap_or(guid),
+            LINUX_FILESYSTEM_GUID
+        ),
+        None => anyhow::bail!("disk.reuse_esp is set, but partition 2 isn't on a GPT disk"),
+    }
+    if let Some(fstype) = existing.mkos_fstype.as_deref() {
+        anyhow::bail!(
+            "disk.reuse_esp is set, but partition 2 holds {}; wipe it first if mkOS should take it",
+            fstype
+        );
+    }
+    Ok(())
+}
+
 pub fn list_block_devices() -> Result<Vec<BlockDevice>> {
     let output = Command::new("lsblk")
         .args(["-b", "-d", "-n", "-o", "PATH,SIZE,MODEL,RM"])
@@ -71,6 +304,147 @@ pub fn list_block_devices() -> Result<Vec<BlockDevice>> {
     Ok(devices)
 }
 
+/// Detect partition tables and filesystem signatures on `device`
+pub fn probe(device: &str) -> Result<DiskContents> {
+    let output = Command::new("lsblk")
+        .args(["-n", "-P", "-p", "-o", "NAME,PTTYPE,FSTYPE", device])
+        .output()?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "lsblk failed for {}: {}",
+            device,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(parse_probe(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_probe(output: &str) -> DiskContents {
+    let mut contents = DiskContents::default();
+
+    for line in output.lines() {
+        let pairs = parse_lsblk_pairs(line);
+        let field = |key: &str| {
+            pairs
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+                .filter(|v| !v.is_empty())
+        };
+
+        // Partitions repeat their parent's table type; the first line is the disk
+        if contents.partition_table.is_none() {
+            contents.partition_table = field("PTTYPE").map(String::from);
+        }
+
+        if let Some(fstype) = field("FSTYPE") {
+            let name = match fstype {
+                "crypto_LUKS" => "LUKS",
+                other => other,
+            };
+            if !contents.signatures.iter().any(|s| s == name) {
+                contents.signatures.push(name.to_string());
+            }
+        }
+    }
+
+    contents
+}
+
+/// Split lsblk `-P` output (`KEY="value" ...`) into pairs
+fn parse_lsblk_pairs(line: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut rest = line.trim();
+
+    while let Some(eq) = rest.find("=\"") {
+        let key = rest[..eq].trim().to_string();
+        let after = &rest[eq + 2..];
+        let Some(end) = after.find('"') else {
+            break;
+        };
+        pairs.push((key, after[..end].to_string()));
+        rest = &after[end + 1..];
+    }
+
+    pairs
+}
+
+/// Mount points that give away the disk the running system booted from: the
+/// live root and ESP, plus where each live ISO mounts its boot medium
+const BOOT_MOUNTPOINTS: &[&str] = &[
+    "/",
+    "/boot",
+    "/boot/efi",
+    "/efi",
+    "/run/archiso/bootmnt",
+    "/run/initramfs/live",
+    "/run/live/medium",
+    "/lib/live/mount/medium",
+];
+
+/// The whole disk hosting the running system, if it can be resolved
+///
+/// Live ISOs run from an overlay, so the root alone isn't enough; the first
+/// mount point backed by a real device wins.
+pub fn current_boot_disk() -> Option<String> {
+    BOOT_MOUNTPOINTS.iter().find_map(|mountpoint| {
+        let source = mount_source(mountpoint)?;
+        parent_disk(&source)
+    })
+}
+
+fn mount_source(mountpoint: &str) -> Option<String> {
+    let output = Command::new("findmnt")
+        .args(["-n", "-o", "SOURCE", "--mountpoint", mountpoint])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    parse_mount_source(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// First device-backed source from findmnt, without the btrfs `[/subvol]` suffix
+fn parse_mount_source(output: &str) -> Option<String> {
+    let source = output.lines().next()?.trim();
+    let source = source.split('[').next().unwrap_or(source);
+    source.starts_with("/dev/").then(|| source.to_string())
+}
+
+fn parent_disk(device: &str) -> Option<String> {
+    // -s walks up through dm-crypt/LVM holders to the disk itself
+    let output = Command::new("lsblk")
+        .args(["-n", "-r", "-s", "-p", "-o", "NAME,TYPE", device])
+        .output()
+        .ok()?;
+
+    parse_parent_disk(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse_parent_disk(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let name = fields.next()?;
+        (fields.next() == Some("disk")).then(|| name.to_string())
+    })
+}
+
+/// Whether the kernel lists `device` (or what it links to) as a partition
+fn is_partition(device: &Path) -> bool {
+    let device = device
+        .canonicalize()
+        .unwrap_or_else(|_| device.to_path_buf());
+    device.file_name().is_some_and(|name| {
+        Path::new("/sys/class/block")
+            .join(name)
+            .join("partition")
+            .exists()
+    })
+}
+
 /// Validate that a path is a valid block device suitable for installation
 pub fn validate_device(device: &Path) -> Result<()> {
     // Check device exists
@@ -87,40 +461,30 @@ pub fn validate_device(device: &Path) -> Result<()> {
         );
     }
 
-    // Check it's a whole disk, not a partition (path shouldn't end with a number)
-    let path_str = device.to_string_lossy();
-    if path_str
-        .chars()
-        .last()
-        .map(|c| c.is_ascii_digit())
-        .unwrap_or(false)
-    {
-        // Could be a partition like /dev/sda1 or /dev/nvme0n1p1
-        // But /dev/nvme0n1 is valid (ends in digit but is a whole disk)
-        // Check if it looks like a partition suffix
-        if path_str.contains('p')
-            && path_str
-                .rfind('p')
-                .map(|i| path_str[i + 1..].chars().all(|c| c.is_ascii_digit()))
-                .unwrap_or(false)
-        {
-            anyhow::bail!(
-                "{} appears to be a partition, not a whole disk",
-                device.display()
-            );
-        }
-        // For traditional devices like /dev/sda1
-        if !path_str.contains("nvme") && !path_str.contains("mmcblk") {
-            anyhow::bail!(
-                "{} appears to be a partition, not a whole disk",
-                device.display()
-            );
-        }
+    // Check it's a whole disk, not a partition, by asking the kernel rather
+    // than guessing from the name
+    if is_partition(device) {
+        anyhow::bail!(
+            "{} appears to be a partition, not a whole disk",
+            device.display()
+        );
     }
 
     Ok(())
 }
 
+/// Filesystem type on `partition`, None when it doesn't exist or has none
+pub fn filesystem_type(partition: &Path) -> Option<String> {
+    let output = Command::new("blkid")
+        .args(["-s", "TYPE", "-o", "value"])
+        .arg(partition)
+        .output()
+        .ok()?;
+    let fstype = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!fstype.is_empty()).then_some(fstype)
+}
+
+#[instrument]
 pub fn wipe_device(device: &Path) -> Result<()> {
     validate_device(device)?;
     cmd::run("wipefs", ["--all", "--force", &device.to_string_lossy()])
@@ -132,3 +496,194 @@ pub fn create_gpt(device: &Path) -> Result<()> {
         ["-s", &device.to_string_lossy(), "mklabel", "gpt"],
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn efi_partition_must_fit_the_ukis() {
+        let layout = PartitionLayout {
+            efi_size_mb: 511,
+            ..Default::default()
+        };
+        assert!(layout.validate().is_err());
+        let layout = PartitionLayout {
+            efi_size_mb: MIN_EFI_SIZE_MB,
+            ..Default::default()
+        };
+        assert!(layout.validate().is_ok());
+    }
+
+    #[test]
+    fn secure_erase_is_off_by_default() {
+        assert!(!PartitionLayout::default().secure_erase);
+
+        let layout = PartitionLayout {
+            reuse_esp: true,
+            secure_erase: true,
+            ..Default::default()
+        };
+        assert!(layout.validate().is_err());
+    }
+
+    #[test]
+    fn efi_label_must_fit_fat() {
+        let label = |efi_label: &str| {
+            PartitionLayout {
+                efi_label: efi_label.into(),
+                ..Default::default()
+            }
+            .validate()
+        };
+        assert!(label("MKOS_EFI").is_ok());
+        assert!(label("EFI SYSTEM").is_ok());
+        assert!(label("").is_err());
+        assert!(label("TWELVE_CHARS").is_err());
+        assert!(label("EFI/BOOT").is_err());
+        assert!(label("ÉFI").is_err());
+    }
+
+    fn reusable() -> ExistingPartitions {
+        ExistingPartitions {
+            efi_fstype: Some("vfat".into()),
+            efi_size_mb: 1024,
+            efi_free_mb: 900,
+            mkos_exists: true,
+            mkos_parttype: Some(LINUX_FILESYSTEM_GUID.to_ascii_lowercase()),
+            mkos_fstype: None,
+        }
+    }
+
+    fn reuse_error(existing: ExistingPartitions) -> String {
+        check_reusable_esp(&existing).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn reused_esp_must_be_fat_beside_an_empty_partition() {
+        assert!(check_reusable_esp(&reusable()).is_ok());
+        assert!(reuse_error(ExistingPartitions {
+            efi_fstype: Some("ntfs".into()),
+            ..reusable()
+        })
+        .contains("partition 1 is ntfs"));
+        assert!(check_reusable_esp(&ExistingPartitions {
+            efi_fstype: None,
+            ..reusable()
+        })
+        .is_err());
+        assert!(reuse_error(ExistingPartitions {
+            mkos_exists: false,
+            ..reusable()
+        })
+        .contains("create an empty partition 2"));
+        assert!(reuse_error(ExistingPartitions {
+            mkos_fstype: Some("ntfs".into()),
+            ..reusable()
+        })
+        .contains("partition 2 holds ntfs"));
+    }
+
+    #[test]
+    fn reused_esp_needs_room_for_the_ukis() {
+        // Windows' default 100 MB ESP
+        assert!(reuse_error(ExistingPartitions {
+            efi_size_mb: 100,
+            efi_free_mb: 70,
+            ..reusable()
+        })
+        .contains("is 100 MB"));
+        assert!(reuse_error(ExistingPartitions {
+            efi_free_mb: 300,
+            ..reusable()
+        })
+        .contains("has 300 MB free"));
+    }
+
+    #[test]
+    fn windows_reserved_partition_is_not_taken() {
+        let err = reuse_error(ExistingPartitions {
+            mkos_parttype: Some("e3c9e316-0b5c-4db8-817d-f92df00215ae".into()),
+            ..reusable()
+        });
+        assert!(err.contains("Microsoft reserved partition"));
+        assert!(reuse_error(ExistingPartitions {
+            mkos_parttype: None,
+            ..reusable()
+        })
+        .contains("isn't on a GPT disk"));
+    }
+
+    #[test]
+    fn df_space_is_parsed() {
+        let out = " 1M-blocks Avail\n      1022   913\n";
+        assert_eq!(parse_df_space(out), Some((1022, 913)));
+        assert_eq!(parse_df_space("1M-blocks Avail\n"), None);
+    }
+
+    #[test]
+    fn probe_blank_disk() {
+        let out = "NAME=\"/dev/sdb\" PTTYPE=\"\" FSTYPE=\"\"\n";
+        let contents = parse_probe(out);
+        assert!(contents.is_empty());
+        assert_eq!(contents.warning(), None);
+    }
+
+    #[test]
+    fn probe_populated_disk() {
+        let out = "NAME=\"/dev/sda\" PTTYPE=\"gpt\" FSTYPE=\"\"\n\
+                   NAME=\"/dev/sda1\" PTTYPE=\"gpt\" FSTYPE=\"vfat\"\n\
+                   NAME=\"/dev/sda2\" PTTYPE=\"gpt\" FSTYPE=\"crypto_LUKS\"\n\
+                   NAME=\"/dev/mapper/root\" PTTYPE=\"\" FSTYPE=\"btrfs\"\n\
+                   NAME=\"/dev/sda3\" PTTYPE=\"gpt\" FSTYPE=\"vfat\"\n";
+        let contents = parse_probe(out);
+        assert_eq!(contents.partition_table.as_deref(), Some("gpt"));
+        assert_eq!(contents.signatures, vec!["vfat", "LUKS", "btrfs"]);
+        assert_eq!(
+            contents.warning().unwrap(),
+            "Disk contains a gpt partition table and existing vfat, LUKS, btrfs data"
+        );
+    }
+
+    #[test]
+    fn probe_unpartitioned_filesystem() {
+        let out = "NAME=\"/dev/sdc\" PTTYPE=\"\" FSTYPE=\"ext4\"\n";
+        assert_eq!(
+            parse_probe(out).warning().unwrap(),
+            "Disk contains existing ext4 data"
+        );
+    }
+
+    #[test]
+    fn mount_source_strips_subvolume() {
+        assert_eq!(
+            parse_mount_source("/dev/mapper/root[/@]\n"),
+            Some("/dev/mapper/root".into())
+        );
+        assert_eq!(parse_mount_source("/dev/sdb1\n"), Some("/dev/sdb1".into()));
+    }
+
+    #[test]
+    fn mount_source_ignores_virtual_roots() {
+        assert_eq!(parse_mount_source("airootfs\n"), None);
+        assert_eq!(parse_mount_source("overlay\n"), None);
+        assert_eq!(parse_mount_source(""), None);
+    }
+
+    #[test]
+    fn parent_disk_of_partition() {
+        let out = "/dev/nvme0n1p2 part\n/dev/nvme0n1 disk\n";
+        assert_eq!(parse_parent_disk(out), Some("/dev/nvme0n1".into()));
+    }
+
+    #[test]
+    fn parent_disk_through_crypt() {
+        let out = "/dev/mapper/root crypt\n/dev/sda2 part\n/dev/sda disk\n";
+        assert_eq!(parse_parent_disk(out), Some("/dev/sda".into()));
+    }
+
+    #[test]
+    fn parent_disk_of_loop_is_none() {
+        assert_eq!(parse_parent_disk("/dev/loop0 loop\n"), None);
+    }
+}
@@ -3,6 +3,10 @@ use std::path::{Path, PathBuf};
 
 use super::PartitionLayout;
 use crate::cmd;
+use tracing::instrument;
+
+/// GPT type of the partition mkOS installs to
+pub const LINUX_FILESYSTEM_GUID: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
 
 #[derive(Debug, Clone)]
 pub struct CreatedPartitions {
@@ -10,6 +14,7 @@ pub struct CreatedPartitions {
     pub luks: PathBuf,
 }
 
+#[instrument]
 pub fn create_partitions(device: &Path, layout: &PartitionLayout) -> Result<CreatedPartitions> {
     let device_str = device.to_string_lossy();
 
@@ -18,8 +23,8 @@ pub fn create_partitions(device: &Path, layout: &PartitionLayout) -> Result<Crea
     // EFI System Partition: C12A7328-F81F-11D2-BA4B-00A0C93EC93B
     // Linux filesystem: 0FC63DAF-8483-4772-8E79-3D69D8477DE4
     let script = format!(
-        "label: gpt\n,{}M,C12A7328-F81F-11D2-BA4B-00A0C93EC93B,*\n,,0FC63DAF-8483-4772-8E79-3D69D8477DE4\n",
-        layout.efi_size_mb
+        "label: gpt\n,{}M,C12A7328-F81F-11D2-BA4B-00A0C93EC93B,*\n,,{}\n",
+        layout.efi_size_mb, LINUX_FILESYSTEM_GUID
     );
 
     cmd::run_with_stdin("sfdisk", [&*device_str], script.as_bytes())?;
@@ -36,29 +41,102 @@ fn detect_partition_names(device: &Path) -> Result<CreatedPartitions> {
     detect_partitions(device)
 }
 
+#[instrument]
 pub fn detect_partitions(device: &Path) -> Result<CreatedPartitions> {
-    let device_str = device.to_string_lossy();
+    Ok(CreatedPartitions {
+        efi: partition_path(device, 1),
+        luks: partition_path(device, 2),
+    })
+}
 
-    // Handle nvme vs sata naming (nvme0n1p1 vs sda1)
-    // Also handle virtio (vda1)
-    let (efi, luks) = if device_str.contains("nvme") || device_str.contains("mmcblk") {
-        (
-            PathBuf::from(format!("{}p1", device_str)),
-            PathBuf::from(format!("{}p2", device_str)),
-        )
+/// Path of partition `num` on `device`
+///
+/// The kernel separates the number with `p` when the disk's name ends in a
+/// digit, so it stays readable: sda1 and vda1, but nvme0n1p1, mmcblk0p1 and
+/// loop0p1.
+pub fn partition_path(device: &Path, num: u32) -> PathBuf {
+    let device = device.to_string_lossy();
+    let separator = if device.ends_with(|c: char| c.is_ascii_digit()) {
+        "p"
     } else {
-        (
-            PathBuf::from(format!("{}1", device_str)),
-            PathBuf::from(format!("{}2", device_str)),
-        )
+        ""
     };
+    PathBuf::from(format!("{}{}{}", device, separator, num))
+}
 
-    Ok(CreatedPartitions { efi, luks })
+/// Split a partition path into its disk and partition number, undoing
+/// [`partition_path`] (/dev/nvme0n1p1 -> /dev/nvme0n1, 1)
+pub fn split_partition(partition: &str) -> Option<(String, u32)> {
+    let disk_end = partition
+        .trim_end_matches(|c: char| c.is_ascii_digit())
+        .len();
+    let num = partition[disk_end..].parse().ok()?;
+    let mut disk = &partition[..disk_end];
+
+    if let Some(stripped) = disk.strip_suffix('p') {
+        if stripped.ends_with(|c: char| c.is_ascii_digit()) {
+            disk = stripped;
+        }
+    }
+
+    if disk.is_empty() {
+        return None;
+    }
+    Some((disk.to_string(), num))
 }
 
-pub fn format_efi(partition: &Path) -> Result<()> {
+#[instrument]
+/// UUID of the filesystem on a partition
+pub fn filesystem_uuid(partition: &Path) -> Result<String> {
+    let output = cmd::run_output(
+        "blkid",
+        ["-s", "UUID", "-o", "value", &partition.to_string_lossy()],
+    )?;
+    Ok(output.trim().to_string())
+}
+
+pub fn format_efi(partition: &Path, label: &str) -> Result<()> {
     cmd::run(
         "mkfs.fat",
-        ["-F", "32", "-n", "MKOS_EFI", &partition.to_string_lossy()],
+        ["-F", "32", "-n", label, &partition.to_string_lossy()],
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partitions_are_named_like_the_kernel_does() {
+        for (device, num, partition) in [
+            ("/dev/sda", 1, "/dev/sda1"),
+            ("/dev/sdp", 2, "/dev/sdp2"),
+            ("/dev/vda", 2, "/dev/vda2"),
+            ("/dev/nvme0n1", 1, "/dev/nvme0n1p1"),
+            ("/dev/nvme10n2", 12, "/dev/nvme10n2p12"),
+            ("/dev/mmcblk0", 2, "/dev/mmcblk0p2"),
+            ("/dev/loop0", 1, "/dev/loop0p1"),
+            ("/dev/loop12", 2, "/dev/loop12p2"),
+        ] {
+            assert_eq!(partition_path(Path::new(device), num), Path::new(partition));
+            assert_eq!(
+                split_partition(partition),
+                Some((device.to_string(), num)),
+                "{}",
+                partition
+            );
+        }
+        assert_eq!(split_partition("/dev/sda"), None);
+    }
+
+    #[test]
+    fn detected_partitions_follow_the_naming() {
+        let parts = detect_partitions(Path::new("/dev/nvme0n1")).unwrap();
+        assert_eq!(parts.efi, Path::new("/dev/nvme0n1p1"));
+        assert_eq!(parts.luks, Path::new("/dev/nvme0n1p2"));
+
+        let parts = detect_partitions(Path::new("/dev/sda")).unwrap();
+        assert_eq!(parts.efi, Path::new("/dev/sda1"));
+        assert_eq!(parts.luks, Path::new("/dev/sda2"));
+    }
+}
@@ -0,0 +1,175 @@
+//! Btrfs roots mirrored across several disks, each encrypted on its own
+
+use anyhow::{bail, Result};
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::boot::BootKind;
+use crate::cmd;
+use crate::crypt::FilesystemKind;
+use crate::manifest::DiskConfig;
+
+/// Check `disk.devices` describes a RAID1 root mkOS can build and boot
+pub fn check_raid_devices(disk: &DiskConfig, boot: BootKind, swapfile: bool) -> Result<()> {
+    if disk.devices.is_empty() {
+        return Ok(());
+    }
+    if disk.device.is_some() {
+        bail!("disk.device and disk.devices can't both be set");
+    }
+    if disk.devices.len() < 2 {
+        bail!("disk.devices needs at least two disks to mirror; use disk.device for one");
+    }
+
+    let mut seen = HashSet::new();
+    for device in &disk.devices {
+        if !device.starts_with("/dev/") {
+            bail!("disk.devices must be disks under /dev, not {}", device);
+        }
+        if !seen.insert(device) {
+            bail!("{} is listed in disk.devices more than once", device);
+        }
+    }
+
+    if disk.filesystem.parse::<FilesystemKind>()? != FilesystemKind::Btrfs {
+        bail!("disk.devices needs btrfs, the only filesystem mkOS can span disks with");
+    }
+    if !disk.encryption {
+        bail!("disk.devices is set but disk.encryption is off");
+    }
+    if disk.reuse_esp {
+        bail!(
+            "disk.reuse_esp keeps a single disk's partitions; it can't be used with disk.devices"
+        );
+    }
+    if disk.detached_header.is_some() {
+        bail!(
+            "disk.detached_header holds a single disk's header; it can't be used with disk.devices"
+        );
+    }
+    if swapfile {
+        bail!("btrfs only keeps swapfiles on a single disk; use swap.zram with disk.devices");
+    }
+    // The encrypt hook only unlocks one device
+    if boot != BootKind::DracutEfistub {
+        bail!("disk.devices needs the dracut-efistub boot system");
+    }
+
+    Ok(())
+}
+
+/// Check RAID member `device` is a whole disk the kernel can see, not a
+/// partition, loop or device-mapper node that can't take a partition table
+pub fn check_raid_member(device: &Path) -> Result<()> {
+    super::validate_device(device)?;
+    let kind = cmd::run_output(
+        "lsblk",
+        ["-d", "-n", "-o", "TYPE", &device.to_string_lossy()],
+    )?;
+    check_disk_type(device, &kind)
+}
+
+/// Check lsblk's TYPE for `device` is a disk
+fn check_disk_type(device: &Path, kind: &str) -> Result<()> {
+    match kind.trim() {
+        "disk" => Ok(()),
+        kind => bail!(
+            "disk.devices must be whole disks, but {} is a {}",
+            device.display(),
+            kind
+        ),
+    }
+}
+
+/// Device-mapper name of RAID member `index`: the first keeps `name`, so
+/// the root is mounted from the same device as on a single disk
+pub fn member_name(name: &str, index: usize) -> String {
+    match index {
+        0 => name.to_string(),
+        _ => format!("{}{}", name, index),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raid(devices: &[&str]) -> DiskConfig {
+        DiskConfig {
+            device: None,
+            devices: devices.iter().map(|d| d.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn error(disk: &DiskConfig, boot: BootKind) -> String {
+        check_raid_devices(disk, boot, false)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn two_disks_mirror() {
+        let disk = raid(&["/dev/sda", "/dev/nvme0n1"]);
+        assert!(check_raid_devices(&disk, BootKind::DracutEfistub, false).is_ok());
+        assert!(check_raid_devices(&disk, BootKind::DracutEfistub, true)
+            .unwrap_err()
+            .to_string()
+            .contains("swapfile"));
+        assert!(check_raid_devices(
+            &DiskConfig::default(),
+            BootKind::MkinitcpioSystemdBoot,
+            true
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn devices_must_be_distinct_disks() {
+        let boot = BootKind::DracutEfistub;
+        assert!(error(&raid(&["/dev/sda"]), boot).contains("at least two"));
+        assert!(error(&raid(&["/dev/sda", "/dev/sda"]), boot).contains("more than once"));
+        assert!(error(&raid(&["/dev/sda", "disk.img"]), boot).contains("under /dev"));
+
+        let mut disk = raid(&["/dev/sda", "/dev/sdb"]);
+        disk.device = Some("/dev/sda".into());
+        assert!(error(&disk, boot).contains("both be set"));
+    }
+
+    #[test]
+    fn members_must_be_whole_disks() {
+        assert!(check_disk_type(Path::new("/dev/sda"), "disk\n").is_ok());
+        for (device, kind) in [
+            ("/dev/sda1", "part"),
+            ("/dev/mapper/foo", "crypt"),
+            ("/dev/loop0", "loop"),
+        ] {
+            let err = check_disk_type(Path::new(device), kind).unwrap_err();
+            assert!(err.to_string().contains("whole disks"), "{}", device);
+        }
+    }
+
+    #[test]
+    fn raid_needs_btrfs_encryption_and_dracut() {
+        let mut disk = raid(&["/dev/sda", "/dev/sdb"]);
+        assert!(error(&disk, BootKind::MkinitcpioSystemdBoot).contains("dracut-efistub"));
+
+        disk.filesystem = "ext4".into();
+        assert!(error(&disk, BootKind::DracutEfistub).contains("btrfs"));
+
+        let mut disk = raid(&["/dev/sda", "/dev/sdb"]);
+        disk.encryption = false;
+        assert!(error(&disk, BootKind::DracutEfistub).contains("encryption"));
+
+        let mut disk = raid(&["/dev/sda", "/dev/sdb"]);
+        disk.reuse_esp = true;
+        assert!(error(&disk, BootKind::DracutEfistub).contains("reuse_esp"));
+    }
+
+    #[test]
+    fn first_member_keeps_the_name() {
+        assert_eq!(member_name("system", 0), "system");
+        assert_eq!(member_name("system", 1), "system1");
+        assert_eq!(member_name("system", 2), "system2");
+    }
+}
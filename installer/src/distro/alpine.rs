@@ -1,13 +1,17 @@
-use super::Distro;
+use super::{kernel_package, Distro, DistroKind};
+use crate::boot::Kernel;
 use crate::cmd;
 use crate::distro::packages::PackageDatabase;
 use crate::init::{InitSystem, OpenRC};
+use crate::mirror::Repo;
 use crate::pkgmgr::{Apk, PackageManager};
 use anyhow::Result;
 use std::path::Path;
 
 pub struct Alpine {
     repo: String,
+    /// Install from `repo` alone rather than the host's /etc/apk/repositories
+    pin_repo: bool,
     init_system: OpenRC,
     pkg_manager: Apk,
 }
@@ -16,6 +20,7 @@ impl Default for Alpine {
     fn default() -> Self {
         Self {
             repo: "https://dl-cdn.alpinelinux.org/alpine/edge/main".into(),
+            pin_repo: false,
             init_system: OpenRC::alpine(),
             pkg_manager: Apk::new(),
         }
@@ -35,13 +40,21 @@ impl Distro for Alpine {
         &self.repo
     }
 
+    fn set_repo(&mut self, repo: &Repo) {
+        self.repo = repo.location();
+        self.pin_repo = true;
+    }
+
     fn map_package(&self, generic: &str) -> Option<String> {
-        PackageDatabase::global().map_for_distro(generic, "alpine")
+        PackageDatabase::global().map_for_distro(generic, DistroKind::Alpine)
     }
 
     fn map_service(&self, generic: &str) -> String {
-        // Alpine uses simple service names
-        generic.to_string()
+        // Alpine uses simple service names, cronie's for cron
+        match generic {
+            "crond" => "cronie".into(),
+            _ => generic.to_string(),
+        }
     }
 
     fn init_system(&self) -> &dyn InitSystem {
@@ -60,6 +73,14 @@ impl Distro for Alpine {
 
         let root_str = root.to_string_lossy();
         let mut args = vec!["add", "--root", &root_str, "--no-cache"];
+        if self.pin_repo {
+            args.extend([
+                "--repositories-file",
+                "/dev/null",
+                "--repository",
+                &self.repo,
+            ]);
+        }
         let mapped_refs: Vec<&str> = mapped.iter().map(|s| s.as_str()).collect();
         args.extend(mapped_refs);
 
@@ -71,8 +92,13 @@ impl Distro for Alpine {
         cmd::run("apk", ["upgrade"])
     }
 
-    fn bootstrap(&self, root: &Path, enable_networking: bool) -> Result<()> {
-        let mut packages = vec!["alpine-base", "openrc"];
+    fn bootstrap(
+        &self,
+        root: &Path,
+        kernel: Option<Kernel>,
+        enable_networking: bool,
+    ) -> Result<()> {
+        let mut packages = vec!["alpine-base", "openrc", kernel_package(kernel)];
 
         if enable_networking {
             packages.push("dhcpcd");
@@ -175,8 +201,8 @@ impl Distro for Alpine {
             fs::set_permissions(&script_path, perms)?;
         }
 
-        // Install the rebuild script
-        crate::hooks::install_uki_rebuild_script(target)?;
+        // Install the rebuild command
+        crate::hooks::install_uki_rebuild_binary(target)?;
 
         println!("✓ Installed kernel hook for Alpine Linux (apk trigger)");
 
@@ -235,6 +261,11 @@ mod tests {
         assert_eq!(alpine().map_service("seatd"), "seatd");
     }
 
+    #[test]
+    fn map_service_crond_is_cronie() {
+        assert_eq!(alpine().map_service("crond"), "cronie");
+    }
+
     #[test]
     fn distro_trait_name() {
         let a = alpine();
@@ -1,40 +1,102 @@
-use super::Distro;
+use super::{Distro, DistroKind};
+use crate::boot::Kernel;
 use crate::cmd;
 use crate::distro::packages::PackageDatabase;
-use crate::init::{InitSystem, S6};
+use crate::init::{Dinit, InitKind, InitSystem, OpenRC, Runit, S6};
+use crate::mirror::Repo;
 use crate::pkgmgr::{PackageManager, Pacman};
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use std::collections::HashMap;
 use std::path::Path;
 
 pub struct Artix {
     repo: String,
     service_map: HashMap<String, String>,
-    init_system: S6,
+    init: InitKind,
+    init_system: Box<dyn InitSystem>,
     pkg_manager: Pacman,
 }
 
 impl Default for Artix {
     fn default() -> Self {
+        Self::new(InitKind::S6, Box::new(S6::artix()))
+    }
+}
+
+impl Artix {
+    /// Artix with one of its init variants: s6, runit, openrc or dinit
+    pub fn with_init(init: InitKind) -> Result<Self> {
+        let init_system: Box<dyn InitSystem> = match init {
+            InitKind::S6 => Box::new(S6::artix()),
+            InitKind::Runit => Box::new(Runit::artix()),
+            InitKind::OpenRC => Box::new(OpenRC::artix()),
+            InitKind::Dinit => Box::new(Dinit::artix()),
+            other => bail!("Artix has no {} variant", other.name()),
+        };
+        Ok(Self::new(init, init_system))
+    }
+
+    fn new(init: InitKind, init_system: Box<dyn InitSystem>) -> Self {
         let mut service_map = HashMap::new();
-        service_map.insert("dbus".into(), "dbus-srv".into());
-        service_map.insert("seatd".into(), "seatd-srv".into());
-        service_map.insert("elogind".into(), "elogind-srv".into());
+        // s6-rc bundles are named <service>-srv; the other inits use the plain name
+        if init == InitKind::S6 {
+            service_map.insert("dbus".into(), "dbus-srv".into());
+            service_map.insert("seatd".into(), "seatd-srv".into());
+            service_map.insert("elogind".into(), "elogind-srv".into());
+        }
         service_map.insert("avahi".into(), "avahi".into());
         service_map.insert("sshd".into(), "sshd".into());
         service_map.insert("etserver".into(), "etserver".into());
         service_map.insert("nftables".into(), "nftables".into());
+        service_map.insert("crond".into(), "cronie".into());
 
         Self {
             repo: "https://mirrors.dotsrc.org/artix-linux/repos".into(),
             service_map,
-            init_system: S6::artix(),
+            init,
+            init_system,
             pkg_manager: Pacman::new(),
         }
     }
-}
 
-impl Artix {
+    /// The init system's own base package
+    fn init_base_package(&self) -> &'static str {
+        match self.init {
+            InitKind::S6 => "s6-base",
+            other => other.name(),
+        }
+    }
+
+    /// Artix ships service scripts for each init as `<package>-<init>`
+    fn service_package(&self, package: &str) -> String {
+        format!("{}-{}", package, self.init.name())
+    }
+
+    /// Packages basestrap installs into a fresh root
+    fn bootstrap_packages(&self, kernel: Option<Kernel>, enable_networking: bool) -> Vec<String> {
+        let mut packages: Vec<String> = vec![
+            "base".into(),
+            self.init_base_package().into(),
+            self.service_package("elogind"),
+            // Artix packages each kernel under its own name
+            kernel.map_or("linux", Kernel::name).into(),
+            "linux-firmware".into(),
+            "cryptsetup".into(),
+            "btrfs-progs".into(),
+            "efibootmgr".into(),
+            "dracut".into(),
+            "dbus".into(),
+            self.service_package("dbus"),
+        ];
+
+        if enable_networking {
+            packages.push("dhcpcd".into());
+            packages.push(self.service_package("dhcpcd"));
+        }
+
+        packages
+    }
+
     fn configure_pam_rundir(&self, root: &Path, dm: &str) -> Result<()> {
         super::configure_pam_rundir(root, dm)
     }
@@ -53,8 +115,13 @@ impl Distro for Artix {
         &self.repo
     }
 
+    fn set_repo(&mut self, repo: &Repo) {
+        // basestrap and pacman read the mirrorlist, which setup_mirror points here
+        self.repo = repo.url();
+    }
+
     fn map_package(&self, generic: &str) -> Option<String> {
-        PackageDatabase::global().map_for_distro(generic, "artix")
+        PackageDatabase::global().map_for_distro(generic, DistroKind::Artix)
     }
 
     fn map_service(&self, generic: &str) -> String {
@@ -65,7 +132,7 @@ impl Distro for Artix {
     }
 
     fn init_system(&self) -> &dyn InitSystem {
-        &self.init_system
+        self.init_system.as_ref()
     }
 
     fn install_packages(&self, root: &Path, packages: &[&str]) -> Result<()> {
@@ -86,33 +153,35 @@ impl Distro for Artix {
         cmd::run("pacman", args)
     }
 
+    fn install_service_scripts(&self, root: &Path, package: &str) -> Result<()> {
+        let root_str = root.to_string_lossy();
+        cmd::run(
+            "pacman",
+            [
+                "-S",
+                "--noconfirm",
+                "-r",
+                &root_str,
+                &self.service_package(package),
+            ],
+        )
+    }
+
     fn update_system(&self) -> Result<()> {
         cmd::run("pacman", ["-Syu", "--noconfirm"])
     }
 
-    fn bootstrap(&self, root: &Path, enable_networking: bool) -> Result<()> {
-        let mut packages = vec![
-            "base",
-            "s6-base",
-            "elogind-s6",
-            "linux",
-            "linux-firmware",
-            "cryptsetup",
-            "btrfs-progs",
-            "efibootmgr",
-            "dracut",
-            "dbus",
-            "dbus-s6",
-        ];
-
-        if enable_networking {
-            packages.push("dhcpcd");
-            packages.push("dhcpcd-s6");
-        }
+    fn bootstrap(
+        &self,
+        root: &Path,
+        kernel: Option<Kernel>,
+        enable_networking: bool,
+    ) -> Result<()> {
+        let packages = self.bootstrap_packages(kernel, enable_networking);
 
         let root_str = root.to_string_lossy().to_string();
         let mut args = vec![root_str.as_str()];
-        args.extend(packages);
+        args.extend(packages.iter().map(|p| p.as_str()));
 
         cmd::run("basestrap", args)?;
 
@@ -129,17 +198,27 @@ impl Distro for Artix {
     }
 
     fn install_desktop_base(&self, root: &Path, seat_manager: &str) -> Result<()> {
-        let (seat_packages, service_name): (Vec<&str>, &str) = match seat_manager {
-            "elogind" => (vec!["elogind", "elogind-s6"], "elogind"),
-            _ => (vec!["seatd", "seatd-s6", "pam_rundir"], "seatd"),
+        let (seat_packages, service_name): (Vec<String>, &str) = match seat_manager {
+            "elogind" => (
+                vec!["elogind".into(), self.service_package("elogind")],
+                "elogind",
+            ),
+            _ => (
+                vec![
+                    "seatd".into(),
+                    self.service_package("seatd"),
+                    "pam_rundir".into(),
+                ],
+                "seatd",
+            ),
         };
 
         let mut packages = seat_packages;
-        packages.extend(["polkit", "xdg-utils"]);
+        packages.extend(["polkit".into(), "xdg-utils".into()]);
 
         let root_str = root.to_string_lossy().to_string();
         let mut args: Vec<&str> = vec!["-S", "--noconfirm", "-r", &root_str];
-        args.extend(packages);
+        args.extend(packages.iter().map(|p| p.as_str()));
 
         cmd::run("pacman", args)?;
 
@@ -156,23 +235,23 @@ impl Distro for Artix {
     ) -> Result<()> {
         let root_str = root.to_string_lossy().to_string();
 
-        let dm_packages: Vec<&str> = match dm {
+        let dm_packages: Vec<String> = match dm {
             "greetd" => {
-                let mut pkgs = vec!["greetd", "greetd-s6"];
+                let mut pkgs = vec!["greetd".to_string(), self.service_package("greetd")];
                 if let Some(g) = greeter {
                     match g {
-                        "regreet" => pkgs.push("greetd-regreet"),
-                        "tuigreet" => pkgs.push("greetd-tuigreet"),
-                        "gtkgreet" => pkgs.push("greetd-gtkgreet"),
+                        "regreet" => pkgs.push("greetd-regreet".into()),
+                        "tuigreet" => pkgs.push("greetd-tuigreet".into()),
+                        "gtkgreet" => pkgs.push("greetd-gtkgreet".into()),
                         _ => {}
                     }
                 }
                 if greeter == Some("regreet") {
-                    pkgs.push("cage");
+                    pkgs.push("cage".into());
                 }
                 pkgs
             }
-            "ly" => vec!["ly", "ly-s6"],
+            "ly" => vec!["ly".into(), self.service_package("ly")],
             _ => return Ok(()),
         };
 
@@ -181,7 +260,7 @@ impl Distro for Artix {
         }
 
         let mut args: Vec<&str> = vec!["-S", "--noconfirm", "-r", &root_str];
-        args.extend(dm_packages);
+        args.extend(dm_packages.iter().map(|p| p.as_str()));
 
         cmd::run("pacman", args)?;
 
@@ -224,10 +303,15 @@ impl Distro for Artix {
         &self.pkg_manager
     }
 
+    fn enable_multilib(&self, root: &Path) -> Result<()> {
+        crate::mirror::enable_lib32(root)?;
+        cmd::run("pacman", ["-Sy", "-r", &root.to_string_lossy()])
+    }
+
     fn install_kernel_hook(&self, target: &Path) -> Result<()> {
-        // Install pacman hook and rebuild script
+        // Install pacman hook and rebuild command
         crate::hooks::install_pacman_hooks(target)?;
-        crate::hooks::install_uki_rebuild_script(target)?;
+        crate::hooks::install_uki_rebuild_binary(target)?;
         Ok(())
     }
 }
@@ -288,6 +372,62 @@ mod tests {
         assert_eq!(artix().map_service("unknown"), "unknown");
     }
 
+    #[test]
+    fn map_service_crond_is_cronie() {
+        assert_eq!(artix().map_service("crond"), "cronie");
+        let runit = Artix::with_init(InitKind::Runit).unwrap();
+        assert_eq!(runit.map_service("crond"), "cronie");
+        assert_eq!(runit.service_package("cronie"), "cronie-runit");
+    }
+
+    #[test]
+    fn openrc_variant_uses_openrc_packages_and_plain_services() {
+        let a = Artix::with_init(InitKind::OpenRC).unwrap();
+        let packages = a.bootstrap_packages(None, true);
+
+        assert!(packages.contains(&"openrc".to_string()));
+        assert!(packages.contains(&"elogind-openrc".to_string()));
+        assert!(packages.contains(&"dhcpcd-openrc".to_string()));
+        assert!(!packages.iter().any(|p| p.contains("s6")));
+        assert_eq!(a.map_service("dbus"), "dbus");
+        assert_eq!(a.init_system().name(), "OpenRC");
+    }
+
+    #[test]
+    fn s6_variant_keeps_s6_base() {
+        let packages = artix().bootstrap_packages(None, false);
+        assert!(packages.contains(&"s6-base".to_string()));
+        assert!(packages.contains(&"dbus-s6".to_string()));
+        assert!(!packages.contains(&"dhcpcd".to_string()));
+    }
+
+    #[test]
+    fn dinit_variant_uses_dinit_packages() {
+        let a = Artix::with_init(InitKind::Dinit).unwrap();
+        let packages = a.bootstrap_packages(None, true);
+
+        assert!(packages.contains(&"dinit".to_string()));
+        assert!(packages.contains(&"dbus-dinit".to_string()));
+        assert_eq!(a.map_service("elogind"), "elogind");
+        assert_eq!(a.init_system().name(), "dinit");
+    }
+
+    #[test]
+    fn bootstrap_installs_the_chosen_kernel() {
+        assert!(artix()
+            .bootstrap_packages(None, false)
+            .contains(&"linux".to_string()));
+
+        let packages = artix().bootstrap_packages(Some(Kernel::Hardened), false);
+        assert!(packages.contains(&"linux-hardened".to_string()));
+        assert!(!packages.contains(&"linux".to_string()));
+    }
+
+    #[test]
+    fn sysvinit_is_not_an_artix_variant() {
+        assert!(Artix::with_init(InitKind::SysVinit).is_err());
+    }
+
     #[test]
     fn distro_trait_name() {
         let a = artix();
@@ -1,7 +1,9 @@
-use super::Distro;
+use super::{kernel_package, Distro, DistroKind};
+use crate::boot::Kernel;
 use crate::cmd;
 use crate::distro::packages::PackageDatabase;
 use crate::init::{InitSystem, SysVinit};
+use crate::mirror::Repo;
 use crate::pkgmgr::{Apt, PackageManager};
 use anyhow::Result;
 use std::path::Path;
@@ -35,13 +37,20 @@ impl Distro for Devuan {
         &self.repo
     }
 
+    fn set_repo(&mut self, repo: &Repo) {
+        self.repo = repo.url();
+    }
+
     fn map_package(&self, generic: &str) -> Option<String> {
-        PackageDatabase::global().map_for_distro(generic, "devuan")
+        PackageDatabase::global().map_for_distro(generic, DistroKind::Devuan)
     }
 
     fn map_service(&self, generic: &str) -> String {
-        // Devuan uses simple service names
-        generic.to_string()
+        // Devuan uses simple service names, Debian's for cron
+        match generic {
+            "crond" => "cron".into(),
+            _ => generic.to_string(),
+        }
     }
 
     fn init_system(&self) -> &dyn InitSystem {
@@ -73,9 +82,14 @@ impl Distro for Devuan {
         cmd::run("apt-get", ["upgrade", "-y"])
     }
 
-    fn bootstrap(&self, root: &Path, enable_networking: bool) -> Result<()> {
+    fn bootstrap(
+        &self,
+        root: &Path,
+        kernel: Option<Kernel>,
+        enable_networking: bool,
+    ) -> Result<()> {
         // Devuan bootstrap typically done via debootstrap
-        let mut packages = vec!["systemd-shim", "linux-image-amd64"];
+        let mut packages = vec!["systemd-shim", kernel_package(kernel)];
 
         if enable_networking {
             packages.push("dhcpcd5");
@@ -147,6 +161,12 @@ iface eth0 inet dhcp
         &self.pkg_manager
     }
 
+    fn enable_multilib(&self, root: &Path) -> Result<()> {
+        let root_str = root.to_string_lossy();
+        cmd::run("chroot", [&*root_str, "dpkg", "--add-architecture", "i386"])?;
+        cmd::run("chroot", [&*root_str, "apt-get", "update"])
+    }
+
     fn install_kernel_hook(&self, target: &Path) -> Result<()> {
         use std::fs;
 
@@ -187,8 +207,8 @@ exit 0
             fs::set_permissions(hook_dir.join("zz-mkos-uki"), perms)?;
         }
 
-        // Install the rebuild script
-        crate::hooks::install_uki_rebuild_script(target)?;
+        // Install the rebuild command
+        crate::hooks::install_uki_rebuild_binary(target)?;
 
         println!("✓ Installed kernel hook for Devuan (kernel postinst.d)");
 
@@ -254,6 +274,11 @@ mod tests {
         assert_eq!(devuan().map_service("seatd"), "seatd");
     }
 
+    #[test]
+    fn map_service_crond_is_cron() {
+        assert_eq!(devuan().map_service("crond"), "cron");
+    }
+
     #[test]
     fn distro_trait_name() {
         let d = devuan();
@@ -1,10 +1,19 @@
-use super::Distro;
+use super::{kernel_package, Distro, DistroKind};
+use crate::boot::Kernel;
 use crate::cmd;
 use crate::distro::packages::PackageDatabase;
 use crate::init::{InitSystem, OpenRC};
+use crate::mirror::Repo;
 use crate::pkgmgr::{Emerge, PackageManager};
 use anyhow::{Context, Result};
 use std::path::Path;
+use std::time::Duration;
+
+/// The latest-stage3 pointer is a few hundred bytes
+const STAGE3_INFO_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Generous enough for a ~300MB tarball on a slow link, short of hanging forever
+const STAGE3_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60 * 60);
 
 pub struct Gentoo {
     repo: String,
@@ -35,13 +44,20 @@ impl Distro for Gentoo {
         &self.repo
     }
 
+    fn set_repo(&mut self, repo: &Repo) {
+        self.repo = repo.url();
+    }
+
     fn map_package(&self, generic: &str) -> Option<String> {
-        PackageDatabase::global().map_for_distro(generic, "gentoo")
+        PackageDatabase::global().map_for_distro(generic, DistroKind::Gentoo)
     }
 
     fn map_service(&self, generic: &str) -> String {
-        // Gentoo uses simple service names
-        generic.to_string()
+        // Gentoo uses simple service names, cronie's for cron
+        match generic {
+            "crond" => "cronie".into(),
+            _ => generic.to_string(),
+        }
     }
 
     fn init_system(&self) -> &dyn InitSystem {
@@ -73,20 +89,25 @@ impl Distro for Gentoo {
         cmd::run("emerge", ["--update", "--deep", "--newuse", "@world"])
     }
 
-    fn bootstrap(&self, root: &Path, enable_networking: bool) -> Result<()> {
+    fn bootstrap(
+        &self,
+        root: &Path,
+        kernel: Option<Kernel>,
+        enable_networking: bool,
+    ) -> Result<()> {
         println!("\n=== Gentoo Bootstrap ===");
 
         // Check if stage3 is already extracted
         if !is_stage3_extracted(root)? {
             println!("Stage3 not found. Downloading and extracting...\n");
-            download_and_extract_stage3(root)?;
+            download_and_extract_stage3(root, &self.repo)?;
         } else {
             println!("Stage3 already extracted, skipping download.\n");
         }
 
         // Install kernel and essential packages
         println!("Installing kernel and essential packages...");
-        let mut packages = vec!["sys-kernel/gentoo-kernel-bin"];
+        let mut packages = vec![kernel_package(kernel)];
 
         if enable_networking {
             packages.push("net-misc/dhcpcd");
@@ -193,7 +214,7 @@ if [ -z "$KERNEL_VERSION" ]; then
     fi
 fi
 
-# Run the UKI rebuild script
+# Rebuild the UKIs
 exec /usr/local/bin/mkos-rebuild-uki
 "#;
 
@@ -207,8 +228,8 @@ exec /usr/local/bin/mkos-rebuild-uki
             fs::set_permissions(hook_dir.join("50-mkos-uki"), perms)?;
         }
 
-        // Install the rebuild script
-        crate::hooks::install_uki_rebuild_script(target)?;
+        // Install the rebuild command
+        crate::hooks::install_uki_rebuild_binary(target)?;
 
         println!("✓ Installed kernel hook for Gentoo (kernel postinst.d)");
 
@@ -230,7 +251,16 @@ fn is_stage3_extracted(root: &Path) -> Result<bool> {
     Ok(markers.iter().any(|p| p.exists()))
 }
 
-fn download_and_extract_stage3(root: &Path) -> Result<()> {
+/// Directory of the Gentoo mirror `repo` with the stage3 builds for `arch`
+fn autobuilds_url(repo: &str, arch: &str) -> String {
+    format!(
+        "{}/releases/{}/autobuilds",
+        repo.trim_end_matches('/'),
+        arch
+    )
+}
+
+fn download_and_extract_stage3(root: &Path, repo: &str) -> Result<()> {
     use std::process::Command;
 
     // Detect architecture
@@ -241,25 +271,16 @@ fn download_and_extract_stage3(root: &Path) -> Result<()> {
     let variant = prompt_stage3_variant()?;
     println!("Selected variant: {}\n", variant);
 
-    // Construct download URL
-    let mirror = "https://distfiles.gentoo.org/releases";
-    let autobuilds = format!("{}/{}/autobuilds", mirror, arch);
+    let autobuilds = autobuilds_url(repo, arch);
 
     // Get latest stage3 filename
     println!("Fetching latest stage3 information...");
     let latest_file = format!("latest-stage3-{}-{}.txt", arch, variant);
     let latest_url = format!("{}/{}", autobuilds, latest_file);
 
-    let output = Command::new("curl")
-        .args(["-sL", &latest_url])
-        .output()
-        .context("Failed to fetch latest stage3 info. Is curl installed?")?;
-
-    if !output.status.success() {
-        anyhow::bail!("Failed to fetch latest stage3 info from Gentoo mirrors");
-    }
-
-    let latest_content = String::from_utf8_lossy(&output.stdout);
+    let latest_content =
+        cmd::run_output_with_timeout("curl", ["-fsSL", &latest_url], STAGE3_INFO_TIMEOUT)
+            .context("Failed to fetch latest stage3 info from Gentoo mirrors")?;
 
     // Parse the latest file - format is:
     // # comment lines
@@ -282,20 +303,18 @@ fn download_and_extract_stage3(root: &Path) -> Result<()> {
     // Download to /tmp
     let tmp_path = format!("/tmp/{}", filename);
 
-    let status = Command::new("curl")
-        .args([
-            "-L",             // Follow redirects
+    cmd::run_with_timeout(
+        "curl",
+        [
+            "-fL",            // Follow redirects, fail on HTTP errors
             "--progress-bar", // Show progress
             "-o",
             &tmp_path, // Output file
             &stage3_url,
-        ])
-        .status()
-        .context("Failed to download stage3 tarball")?;
-
-    if !status.success() {
-        anyhow::bail!("Failed to download stage3 tarball");
-    }
+        ],
+        STAGE3_DOWNLOAD_TIMEOUT,
+    )
+    .context("Failed to download stage3 tarball")?;
 
     println!("\n✓ Download complete");
 
@@ -347,6 +366,30 @@ fn detect_architecture() -> Result<&'static str> {
     }
 }
 
+fn prompt_stage3_variant() -> Result<&'static str> {
+    use std::io::{self, Write};
+
+    println!("Select Gentoo stage3 variant:");
+    println!("  [1] openrc - Standard OpenRC init (recommended)");
+    println!("  [2] openrc-hardened - Hardened OpenRC with security features");
+    println!("  [3] musl-openrc - OpenRC with musl libc (lightweight)");
+
+    loop {
+        print!("Select variant [1-3]: ");
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+
+        match input.trim() {
+            "1" | "" => return Ok("openrc"),
+            "2" => return Ok("openrc-hardened"),
+            "3" => return Ok("musl-openrc"),
+            _ => println!("Invalid selection. Please enter 1-3."),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -355,6 +398,20 @@ mod tests {
         Gentoo::default()
     }
 
+    #[test]
+    fn stage3_comes_from_the_repo() {
+        assert_eq!(
+            autobuilds_url(gentoo().repo_url(), "amd64"),
+            "https://gentoo.osuosl.org/releases/amd64/autobuilds"
+        );
+        let mut distro = gentoo();
+        distro.set_repo(&Repo::Local("/srv/gentoo".into()));
+        assert_eq!(
+            autobuilds_url(distro.repo_url(), "amd64"),
+            "file:///srv/gentoo/releases/amd64/autobuilds"
+        );
+    }
+
     #[test]
     fn map_package_base_system() {
         assert_eq!(gentoo().map_package("base-system"), Some("@system".into()));
@@ -403,33 +460,14 @@ mod tests {
         assert_eq!(gentoo().map_service("seatd"), "seatd");
     }
 
+    #[test]
+    fn map_service_crond_is_cronie() {
+        assert_eq!(gentoo().map_service("crond"), "cronie");
+    }
+
     #[test]
     fn distro_trait_name() {
         let g = gentoo();
         assert_eq!(Distro::name(&g), "Gentoo Linux");
     }
 }
-
-fn prompt_stage3_variant() -> Result<&'static str> {
-    use std::io::{self, Write};
-
-    println!("Select Gentoo stage3 variant:");
-    println!("  [1] openrc - Standard OpenRC init (recommended)");
-    println!("  [2] openrc-hardened - Hardened OpenRC with security features");
-    println!("  [3] musl-openrc - OpenRC with musl libc (lightweight)");
-
-    loop {
-        print!("Select variant [1-3]: ");
-        io::stdout().flush()?;
-
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-
-        match input.trim() {
-            "1" | "" => return Ok("openrc"),
-            "2" => return Ok("openrc-hardened"),
-            "3" => return Ok("musl-openrc"),
-            _ => println!("Invalid selection. Please enter 1-3."),
-        }
-    }
-}
@@ -2,17 +2,23 @@ mod alpine;
 mod artix;
 mod devuan;
 mod gentoo;
+mod opensuse;
 pub mod packages;
 mod slackware;
 mod void;
 
 pub use packages::*;
 
-use crate::init::InitSystem;
+use crate::boot::{BootKind, Kernel};
+use crate::init::{InitKind, InitSystem};
+use crate::mirror::Repo;
 use crate::pkgmgr::PackageManager;
 use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::fmt;
 use std::fs;
 use std::path::Path;
+use std::str::FromStr;
 
 /// Distro backend trait - implement this for each supported distro
 pub trait Distro: Send + Sync {
@@ -25,11 +31,29 @@ pub trait Distro: Send + Sync {
     /// Install packages to a target root
     fn install_packages(&self, root: &Path, packages: &[&str]) -> Result<()>;
 
+    /// Install the init scripts for `package`'s service, where the distro
+    /// packages them apart from it
+    fn install_service_scripts(&self, _root: &Path, _package: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Remove packages, by generic name, from a target root
+    fn remove_packages(&self, root: &Path, packages: &[&str]) -> Result<()> {
+        let mapped: Vec<String> = packages
+            .iter()
+            .filter_map(|p| self.map_package(p))
+            .collect();
+        let mapped: Vec<&str> = mapped.iter().map(String::as_str).collect();
+        self.package_manager().remove(root, &mapped)
+    }
+
     /// Update system
     fn update_system(&self) -> Result<()>;
 
-    /// Bootstrap a minimal system to target root
-    fn bootstrap(&self, root: &Path, enable_networking: bool) -> Result<()>;
+    /// Bootstrap a minimal system to target root, with `kernel` or the
+    /// distro's default kernel
+    fn bootstrap(&self, root: &Path, kernel: Option<Kernel>, enable_networking: bool)
+        -> Result<()>;
 
     /// Install desktop session prerequisites (seat manager, polkit, etc.)
     fn install_desktop_base(&self, root: &Path, seat_manager: &str) -> Result<()>;
@@ -53,6 +77,7 @@ pub trait Distro: Send + Sync {
     fn package_manager(&self) -> &dyn PackageManager;
 
     /// Map generic service name to distro-specific name
+    /// (empty when the distro has no equivalent and the service should be skipped)
     fn map_service(&self, generic: &str) -> String;
 
     /// Map generic package name to distro-specific name
@@ -61,12 +86,25 @@ pub trait Distro: Send + Sync {
     /// Get the repo URL
     fn repo_url(&self) -> &str;
 
+    /// Install from `repo` instead of the distro's default repository
+    fn set_repo(&mut self, repo: &Repo);
+
     /// Generate fstab content for the target root
     fn generate_fstab(&self, root: &Path) -> Result<String>;
 
     /// Install kernel rebuild hook for this distro
     /// This hook should rebuild the boot image (UKI or initramfs) when the kernel is upgraded
     fn install_kernel_hook(&self, target: &Path) -> Result<()>;
+
+    /// Let the target install 32-bit packages: multilib or i386
+    fn enable_multilib(&self, _root: &Path) -> Result<()> {
+        bail!("{} has no 32-bit packages", self.name())
+    }
+}
+
+/// Generic package name of `kernel`, or of the distro's default kernel
+pub fn kernel_package(kernel: Option<Kernel>) -> &'static str {
+    kernel.map_or("linux-kernel", Kernel::package)
 }
 
 /// Available distro backends
@@ -79,6 +117,7 @@ pub enum DistroKind {
     Alpine,
     Gentoo,
     Devuan,
+    OpenSuse,
 }
 
 const DISTRO_DETECTION_TABLE: &[(&str, DistroKind)] = &[
@@ -90,16 +129,31 @@ const DISTRO_DETECTION_TABLE: &[(&str, DistroKind)] = &[
     ("/etc/devuan_version", DistroKind::Devuan),
 ];
 
-const OS_RELEASE_TABLE: &[(&str, DistroKind)] = &[
-    ("artix", DistroKind::Artix),
-    ("void", DistroKind::Void),
-    ("slackware", DistroKind::Slackware),
-    ("alpine", DistroKind::Alpine),
-    ("gentoo", DistroKind::Gentoo),
-    ("devuan", DistroKind::Devuan),
-];
-
 impl DistroKind {
+    /// Every backend
+    pub const ALL: [DistroKind; 7] = [
+        DistroKind::Artix,
+        DistroKind::Void,
+        DistroKind::Slackware,
+        DistroKind::Alpine,
+        DistroKind::Gentoo,
+        DistroKind::Devuan,
+        DistroKind::OpenSuse,
+    ];
+
+    /// Manifest name, which is also the distro's os-release ID
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DistroKind::Artix => "artix",
+            DistroKind::Void => "void",
+            DistroKind::Slackware => "slackware",
+            DistroKind::Alpine => "alpine",
+            DistroKind::Gentoo => "gentoo",
+            DistroKind::Devuan => "devuan",
+            DistroKind::OpenSuse => "opensuse",
+        }
+    }
+
     pub fn create(self) -> Box<dyn Distro> {
         match self {
             DistroKind::Artix => Box::new(artix::Artix::default()),
@@ -108,7 +162,131 @@ impl DistroKind {
             DistroKind::Alpine => Box::new(alpine::Alpine::default()),
             DistroKind::Gentoo => Box::new(gentoo::Gentoo::default()),
             DistroKind::Devuan => Box::new(devuan::Devuan::default()),
+            DistroKind::OpenSuse => Box::new(opensuse::OpenSuse::default()),
+        }
+    }
+
+    /// Create the backend with a chosen init system, or its default for None
+    pub fn create_with_init(self, init: Option<InitKind>) -> Result<Box<dyn Distro>> {
+        let Some(init) = init else {
+            return Ok(self.create());
+        };
+        self.check_init(init)?;
+
+        Ok(match self {
+            DistroKind::Artix => Box::new(artix::Artix::with_init(init)?),
+            DistroKind::Void => Box::new(void::Void::with_init(init)?),
+            // Only the default init is supported, and check_init passed
+            _ => self.create(),
+        })
+    }
+
+    /// Init systems the distro can be installed with, default first
+    pub fn supported_inits(self) -> &'static [InitKind] {
+        match self {
+            DistroKind::Artix => &[
+                InitKind::S6,
+                InitKind::Runit,
+                InitKind::OpenRC,
+                InitKind::Dinit,
+            ],
+            DistroKind::Void => &[InitKind::S6, InitKind::Runit],
+            DistroKind::Slackware | DistroKind::Devuan => &[InitKind::SysVinit],
+            DistroKind::Alpine | DistroKind::Gentoo => &[InitKind::OpenRC],
+            DistroKind::OpenSuse => &[InitKind::Runit],
+        }
+    }
+
+    /// Fail unless the distro packages `kernel`
+    pub fn check_kernel(self, kernel: Kernel) -> Result<()> {
+        let db = PackageDatabase::global();
+        if db.map_for_distro(kernel.package(), self).is_none() {
+            let names: Vec<&str> = Kernel::ALL
+                .into_iter()
+                .filter(|k| db.map_for_distro(k.package(), self).is_some())
+                .map(Kernel::name)
+                .collect();
+            bail!(
+                "{} does not package the {} kernel. Supported: {}",
+                self.name(),
+                kernel.name(),
+                names.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Fail unless the distro's bootstrap can install from the manifest's
+    /// repository
+    pub fn check_repo(self) -> Result<()> {
+        if matches!(self, DistroKind::Devuan | DistroKind::Slackware) {
+            bail!("{} can't install from a custom repository yet", self.name());
+        }
+        Ok(())
+    }
+
+    /// Flavour of the kernel the distro installs when `boot.kernel` is unset
+    pub fn default_kernel(self) -> Kernel {
+        let db = PackageDatabase::global();
+        let default = db.map_for_distro("linux-kernel", self);
+        Kernel::ALL
+            .into_iter()
+            .find(|k| default.is_some() && db.map_for_distro(k.package(), self) == default)
+            .unwrap_or(Kernel::Linux)
+    }
+
+    /// Whether [`Distro::enable_multilib`] can offer 32-bit packages
+    pub fn has_multilib(self) -> bool {
+        matches!(
+            self,
+            DistroKind::Artix | DistroKind::Devuan | DistroKind::OpenSuse
+        )
+    }
+
+    /// Fail unless the distro packages plymouth for a boot splash
+    pub fn check_splash(self) -> Result<()> {
+        if PackageDatabase::global()
+            .map_for_distro("plymouth", self)
+            .is_none()
+        {
+            bail!("{} does not package plymouth", self.name());
         }
+        Ok(())
+    }
+
+    /// Fail unless the distro packages everything the `boot` backend runs
+    pub fn check_boot(self, boot: BootKind) -> Result<()> {
+        let db = PackageDatabase::global();
+        let missing: Vec<&str> = boot
+            .packages()
+            .iter()
+            .copied()
+            .filter(|p| db.map_for_distro(p, self).is_none())
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "{} does not package {}, which the {} boot system needs",
+                self.name(),
+                missing.join(" or "),
+                boot.name()
+            );
+        }
+        Ok(())
+    }
+
+    /// Fail unless the distro can be installed with `init`
+    pub fn check_init(self, init: InitKind) -> Result<()> {
+        let supported = self.supported_inits();
+        if !supported.contains(&init) {
+            let names: Vec<&str> = supported.iter().map(|i| i.name()).collect();
+            bail!(
+                "{} does not support the {} init system. Supported: {}",
+                self.name(),
+                init.name(),
+                names.join(", ")
+            );
+        }
+        Ok(())
     }
 
     pub fn name(self) -> &'static str {
@@ -119,28 +297,92 @@ impl DistroKind {
             DistroKind::Alpine => "Alpine Linux",
             DistroKind::Gentoo => "Gentoo Linux",
             DistroKind::Devuan => "Devuan GNU+Linux",
+            DistroKind::OpenSuse => "openSUSE Tumbleweed",
+        }
+    }
+}
+
+impl FromStr for DistroKind {
+    type Err = anyhow::Error;
+
+    /// Parse a manifest distro name (the os-release ID)
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(kind) = DistroKind::ALL.into_iter().find(|k| k.as_str() == s) {
+            return Ok(kind);
         }
+
+        bail!("Unknown distro: {}. Supported: {}", s, supported_names())
+    }
+}
+
+impl fmt::Display for DistroKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
 /// Detect the running distribution from release files and os-release
 pub fn detect() -> Result<DistroKind> {
+    detect_in(Path::new("/"))
+}
+
+/// Detect the distro installed under `root`
+pub fn detect_in(root: &Path) -> Result<DistroKind> {
+    let in_root = |path: &str| root.join(path.trim_start_matches('/'));
+    let os_release = fs::read_to_string(in_root("/etc/os-release")).ok();
+    detect_from(|path| in_root(path).exists(), os_release.as_deref())
+}
+
+/// Detection against an arbitrary filesystem view: distro-specific release
+/// files win, then the os-release ID, then ID_LIKE
+fn detect_from(
+    release_exists: impl Fn(&str) -> bool,
+    os_release: Option<&str>,
+) -> Result<DistroKind> {
     for (path, kind) in DISTRO_DETECTION_TABLE {
-        if Path::new(path).exists() {
+        if release_exists(path) {
             return Ok(*kind);
         }
     }
 
-    if let Ok(content) = fs::read_to_string("/etc/os-release") {
-        let content_lower = content.to_lowercase();
-        for (name, kind) in OS_RELEASE_TABLE {
-            if content_lower.contains(name) {
-                return Ok(*kind);
-            }
-        }
+    if let Some(kind) = os_release.and_then(parse_os_release) {
+        return Ok(kind);
     }
 
-    bail!("Could not detect distro. Supported: Artix, Void, Slackware, Alpine, Gentoo, Devuan")
+    bail!(
+        "Could not detect distro. Supported os-release IDs: {}",
+        supported_names()
+    )
+}
+
+/// Every distro's manifest name, for error messages
+fn supported_names() -> String {
+    let names: Vec<&str> = DistroKind::ALL.iter().map(|k| k.as_str()).collect();
+    names.join(", ")
+}
+
+/// Match the os-release ID (or an ID_LIKE entry) against supported distros
+fn parse_os_release(content: &str) -> Option<DistroKind> {
+    let field = |key: &str| {
+        content.lines().find_map(|line| {
+            let value = line.trim().strip_prefix(key)?.strip_prefix('=')?;
+            Some(value.trim().trim_matches(|c| c == '"' || c == '\''))
+        })
+    };
+
+    // Variants like "opensuse-tumbleweed" match their base ID
+    let lookup = |id: &str| {
+        DistroKind::ALL.into_iter().find(|kind| {
+            id == kind.as_str()
+                || id
+                    .strip_prefix(kind.as_str())
+                    .is_some_and(|rest| rest.starts_with('-'))
+        })
+    };
+
+    field("ID")
+        .and_then(lookup)
+        .or_else(|| field("ID_LIKE")?.split_whitespace().find_map(lookup))
 }
 
 pub fn get_distro(kind: DistroKind) -> Box<dyn Distro> {
@@ -152,7 +394,7 @@ pub fn generate_fstab_from_findmnt(root: &Path) -> Result<String> {
     use std::process::Command;
 
     let output = Command::new("findmnt")
-        .args(["-R", "-n", "-o", "SOURCE,TARGET,FSTYPE,OPTIONS"])
+        .args(["-R", "-n", "-P", "-o", "SOURCE,UUID,TARGET,FSTYPE,OPTIONS"])
         .arg(root)
         .output()
         .context("Failed to run findmnt")?;
@@ -161,19 +403,52 @@ pub fn generate_fstab_from_findmnt(root: &Path) -> Result<String> {
         bail!("findmnt failed");
     }
 
+    Ok(fstab_from_findmnt(
+        &String::from_utf8_lossy(&output.stdout),
+        root,
+    ))
+}
+
+/// fstab lines for the block filesystems in `findmnt -P` output, by UUID
+/// and relative to `root`; proc, sysfs, tmpfs and the like are left out
+fn fstab_from_findmnt(output: &str, root: &Path) -> String {
     let mut fstab = String::from("# /etc/fstab\n# Generated by mkOS installer\n\n");
 
-    for line in String::from_utf8_lossy(&output.stdout).lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() >= 4 {
-            fstab.push_str(&format!(
-                "{}\t{}\t{}\t{}\t0 0\n",
-                parts[0], parts[1], parts[2], parts[3]
-            ));
+    for line in output.lines() {
+        let fields = findmnt_pairs(line);
+        let field = |key| fields.get(key).copied().unwrap_or_default();
+        let (source, uuid) = (field("SOURCE"), field("UUID"));
+        if !source.starts_with("/dev/") || uuid.is_empty() {
+            continue;
         }
+        let Ok(target) = Path::new(field("TARGET")).strip_prefix(root) else {
+            continue;
+        };
+
+        fstab.push_str(&format!(
+            "UUID={}\t{}\t{}\t{}\t0 0\n",
+            uuid,
+            Path::new("/").join(target).display(),
+            field("FSTYPE"),
+            field("OPTIONS")
+        ));
     }
 
-    Ok(fstab)
+    fstab
+}
+
+/// The KEY="value" pairs of a `findmnt -P` line
+fn findmnt_pairs(line: &str) -> HashMap<&str, &str> {
+    let mut pairs = HashMap::new();
+    let mut rest = line;
+    while let Some((key, tail)) = rest.split_once("=\"") {
+        let Some((value, tail)) = tail.split_once('"') else {
+            break;
+        };
+        pairs.insert(key.trim(), value);
+        rest = tail;
+    }
+    pairs
 }
 
 /// Configure pam_rundir in a display manager's PAM file for XDG_RUNTIME_DIR
@@ -201,11 +476,57 @@ pub fn configure_pam_rundir(root: &Path, dm: &str) -> Result<()> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn fstab_keeps_block_filesystems_by_uuid() {
+        let output = concat!(
+            "SOURCE=\"/dev/mapper/system[/@]\" UUID=\"aaaa\" TARGET=\"/mnt\" FSTYPE=\"btrfs\" OPTIONS=\"rw,subvol=/@\"\n",
+            "SOURCE=\"proc\" UUID=\"\" TARGET=\"/mnt/proc\" FSTYPE=\"proc\" OPTIONS=\"rw\"\n",
+            "SOURCE=\"tmpfs\" UUID=\"\" TARGET=\"/mnt/tmp\" FSTYPE=\"tmpfs\" OPTIONS=\"rw\"\n",
+            "SOURCE=\"/dev/nvme0n1p1\" UUID=\"1234-ABCD\" TARGET=\"/mnt/boot\" FSTYPE=\"vfat\" OPTIONS=\"rw,fmask=0077\"\n",
+        );
+
+        let fstab = fstab_from_findmnt(output, Path::new("/mnt"));
+
+        let entries: Vec<&str> = fstab.lines().filter(|l| l.starts_with("UUID=")).collect();
+        assert_eq!(
+            entries,
+            [
+                "UUID=aaaa\t/\tbtrfs\trw,subvol=/@\t0 0",
+                "UUID=1234-ABCD\t/boot\tvfat\trw,fmask=0077\t0 0",
+            ]
+        );
+        assert!(!fstab.contains("/dev/"));
+    }
+
     #[test]
     fn distrokind_default_is_artix() {
         assert_eq!(DistroKind::default(), DistroKind::Artix);
     }
 
+    #[test]
+    fn distrokind_round_trips_through_its_name() {
+        for kind in DistroKind::ALL {
+            assert_eq!(kind.as_str().parse::<DistroKind>().unwrap(), kind);
+            assert_eq!(kind.to_string(), kind.as_str());
+        }
+
+        let err = "arch".parse::<DistroKind>().unwrap_err().to_string();
+        assert!(err.contains("artix, void, slackware, alpine, gentoo, devuan, opensuse"));
+    }
+
+    #[test]
+    fn detect_in_reads_the_target_tree() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("etc")).unwrap();
+        fs::write(
+            root.path().join("etc/os-release"),
+            "NAME=\"Void\"\nID=\"void\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(detect_in(root.path()).unwrap(), DistroKind::Void);
+    }
+
     #[test]
     fn create_artix() {
         let distro = DistroKind::Artix.create();
@@ -248,6 +569,70 @@ mod tests {
         assert_eq!(distro.pkg_manager(), "apt");
     }
 
+    #[test]
+    fn create_opensuse() {
+        let distro = DistroKind::OpenSuse.create();
+        assert_eq!(distro.name(), "openSUSE Tumbleweed");
+        assert_eq!(distro.pkg_manager(), "zypper");
+    }
+
+    fn detect_os_release(content: &str) -> Result<DistroKind> {
+        detect_from(|_| false, Some(content))
+    }
+
+    #[test]
+    fn detect_os_release_all_distros() {
+        let cases = [
+            ("NAME=\"Artix Linux\"\nID=artix\nID_LIKE=arch\n", DistroKind::Artix),
+            ("NAME=\"Void\"\nID=\"void\"\n", DistroKind::Void),
+            ("NAME=Slackware\nID=slackware\n", DistroKind::Slackware),
+            ("NAME=\"Alpine Linux\"\nID=alpine\n", DistroKind::Alpine),
+            ("NAME=Gentoo\nID=gentoo\n", DistroKind::Gentoo),
+            (
+                "NAME=\"Devuan GNU/Linux\"\nID=devuan\nID_LIKE=debian\n",
+                DistroKind::Devuan,
+            ),
+            (
+                "NAME=\"openSUSE Tumbleweed\"\nID=\"opensuse-tumbleweed\"\nID_LIKE=\"opensuse suse\"\n",
+                DistroKind::OpenSuse,
+            ),
+        ];
+
+        for (content, expected) in cases {
+            assert_eq!(detect_os_release(content).unwrap(), expected, "{}", content);
+        }
+    }
+
+    #[test]
+    fn detect_os_release_id_like_fallback() {
+        let content = "NAME=\"Artix derivative\"\nID=mydistro\nID_LIKE=\"artix arch\"\n";
+        assert_eq!(detect_os_release(content).unwrap(), DistroKind::Artix);
+    }
+
+    #[test]
+    fn detect_os_release_ignores_substrings() {
+        // Mentions of a supported distro outside ID/ID_LIKE don't count
+        let content = "NAME=Debian\nID=debian\nHOME_URL=\"https://void.example\"\n";
+        let err = detect_os_release(content).unwrap_err().to_string();
+        assert!(err.contains("artix, void, slackware, alpine, gentoo, devuan, opensuse"));
+    }
+
+    #[test]
+    fn distrokind_from_str() {
+        assert_eq!("void".parse::<DistroKind>().unwrap(), DistroKind::Void);
+        assert_eq!(
+            "opensuse".parse::<DistroKind>().unwrap(),
+            DistroKind::OpenSuse
+        );
+        assert!("debian".parse::<DistroKind>().is_err());
+    }
+
+    #[test]
+    fn detect_prefers_release_files() {
+        let kind = detect_from(|path| path == "/etc/devuan_version", Some("ID=debian\n")).unwrap();
+        assert_eq!(kind, DistroKind::Devuan);
+    }
+
     #[test]
     fn get_distro_returns_correct_type() {
         let distro = get_distro(DistroKind::Void);
@@ -263,6 +648,7 @@ mod tests {
             DistroKind::Gentoo,
             DistroKind::Slackware,
             DistroKind::Devuan,
+            DistroKind::OpenSuse,
         ];
         for kind in kinds {
             let distro = kind.create();
@@ -279,6 +665,7 @@ mod tests {
             DistroKind::Gentoo,
             DistroKind::Slackware,
             DistroKind::Devuan,
+            DistroKind::OpenSuse,
         ];
 
         for kind in kinds {
@@ -297,6 +684,54 @@ mod tests {
         }
     }
 
+    #[test]
+    fn default_kernel_is_the_distros_own() {
+        assert_eq!(DistroKind::Artix.default_kernel(), Kernel::Linux);
+        assert_eq!(DistroKind::Void.default_kernel(), Kernel::Linux);
+        // Alpine installs linux-lts, whose releases end in -lts
+        assert_eq!(DistroKind::Alpine.default_kernel(), Kernel::Lts);
+    }
+
+    #[test]
+    fn systemd_boot_needs_mkinitcpio_and_bootctl() {
+        for distro in DistroKind::ALL {
+            assert!(distro.check_boot(BootKind::DracutEfistub).is_ok());
+        }
+        assert!(DistroKind::Artix
+            .check_boot(BootKind::MkinitcpioSystemdBoot)
+            .is_ok());
+        let err = DistroKind::OpenSuse
+            .check_boot(BootKind::MkinitcpioSystemdBoot)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "openSUSE Tumbleweed does not package mkinitcpio, which the \
+             mkinitcpio-systemd-boot boot system needs"
+        );
+        assert!(DistroKind::Void
+            .check_boot(BootKind::MkinitcpioSystemdBoot)
+            .is_err());
+    }
+
+    #[test]
+    fn create_with_init_validates_choice() {
+        let artix = DistroKind::Artix
+            .create_with_init(Some(InitKind::Runit))
+            .unwrap();
+        assert_eq!(artix.init_system().name(), "runit");
+        assert_eq!(artix.map_service("dbus"), "dbus");
+
+        let void = DistroKind::Void.create_with_init(None).unwrap();
+        assert_eq!(void.init_system().name(), "s6");
+
+        assert!(DistroKind::Alpine
+            .create_with_init(Some(InitKind::OpenRC))
+            .is_ok());
+        assert!(DistroKind::Alpine
+            .create_with_init(Some(InitKind::S6))
+            .is_err());
+    }
+
     #[test]
     fn artix_service_mapping() {
         let distro = DistroKind::Artix.create();
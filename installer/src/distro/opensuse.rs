@@ -0,0 +1,474 @@
+use super::{kernel_package, Distro, DistroKind};
+use crate::boot::Kernel;
+use crate::cmd;
+use crate::distro::packages::PackageDatabase;
+use crate::init::{InitSystem, Runit, ServiceSpec};
+use crate::mirror::Repo;
+use crate::pkgmgr::{PackageManager, Zypper};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// runit stage 1: one-time system setup before services start
+const RUNIT_STAGE1: &str = r#"#!/bin/sh
+# mkOS runit stage 1 for openSUSE
+PATH=/usr/bin:/usr/sbin
+
+mountpoint -q /proc || mount -t proc proc /proc
+mountpoint -q /sys || mount -t sysfs sys /sys
+mountpoint -q /dev || mount -t devtmpfs dev /dev
+mountpoint -q /run || mount -t tmpfs -o mode=0755,nosuid,nodev run /run
+# Terminals for logins and sshd, and POSIX shared memory
+mkdir -p /dev/pts /dev/shm
+mountpoint -q /dev/pts || mount -t devpts -o mode=0620,gid=5,nosuid,noexec devpts /dev/pts
+mountpoint -q /dev/shm || mount -t tmpfs -o mode=1777,nosuid,nodev shm /dev/shm
+
+/usr/lib/systemd/systemd-udevd --daemon
+udevadm trigger --action=add --type=subsystems
+udevadm trigger --action=add --type=devices
+udevadm settle
+
+mount -o remount,rw /
+mount -a
+swapon -a
+hostname -F /etc/hostname 2>/dev/null
+
+touch /etc/runit/stopit /etc/runit/reboot
+chmod 0 /etc/runit/stopit /etc/runit/reboot
+"#;
+
+/// runit stage 2: supervise the enabled services until shutdown
+const RUNIT_STAGE2: &str = r#"#!/bin/sh
+# mkOS runit stage 2 for openSUSE
+PATH=/usr/bin:/usr/sbin
+
+exec runsvdir -P /var/service 'log: ...........................................'
+"#;
+
+/// runit stage 3: stop services and unmount before halt or reboot
+const RUNIT_STAGE3: &str = r#"#!/bin/sh
+# mkOS runit stage 3 for openSUSE
+PATH=/usr/bin:/usr/sbin
+
+sv -w 30 force-stop /var/service/*
+sv exit /var/service/*
+
+swapoff -a
+umount -a -r
+sync
+"#;
+
+/// Virtual consoles that get a login prompt
+const GETTY_TTYS: &[&str] = &["tty1", "tty2", "tty3", "tty4", "tty5", "tty6"];
+
+pub struct OpenSuse {
+    repo: String,
+    service_map: HashMap<String, String>,
+    init_system: Runit,
+    pkg_manager: Zypper,
+}
+
+impl Default for OpenSuse {
+    fn default() -> Self {
+        let mut service_map = HashMap::new();
+        service_map.insert("avahi".into(), "avahi-daemon".into());
+        service_map.insert("crond".into(), "cron".into());
+        // openSUSE doesn't package elogind
+        service_map.insert("elogind".into(), String::new());
+
+        Self {
+            repo: "https://download.opensuse.org/tumbleweed/repo/oss".into(),
+            service_map,
+            init_system: Runit::opensuse(),
+            pkg_manager: Zypper::new(),
+        }
+    }
+}
+
+impl OpenSuse {
+    fn zypper(&self, root: &Path, args: &[&str]) -> Result<()> {
+        let root_str = root.to_string_lossy();
+        let mut full_args = vec!["--root", &root_str, "--gpg-auto-import-keys"];
+        full_args.extend(args);
+        cmd::run("zypper", full_args)
+    }
+
+    /// Make runit PID 1: openSUSE's `base` pattern boots systemd through
+    /// systemd-sysvinit's /sbin/init, and its runit ships no stage scripts
+    fn make_runit_init(&self, root: &Path) -> Result<()> {
+        self.zypper(root, &["remove", "-y", "systemd-sysvinit"])?;
+        // Otherwise a dist-upgrade reinstalls it and systemd is init again
+        self.zypper(root, &["addlock", "systemd-sysvinit"])?;
+        write_runit_stages(root)
+    }
+
+    /// openSUSE packages only ship systemd units, so runit services are
+    /// created from a spec before they're enabled
+    fn create_and_enable(&self, root: &Path, service: &str, command: &str) -> Result<()> {
+        let service = self.map_service(service);
+        if service.is_empty() {
+            return Ok(());
+        }
+
+        self.init_system
+            .create_service(root, &ServiceSpec::longrun(&service, command))?;
+        self.init_system.enable_service(root, &service)
+    }
+
+    /// A login prompt on each virtual console, which systemd's getty units
+    /// gave before
+    fn enable_gettys(&self, root: &Path) -> Result<()> {
+        for tty in GETTY_TTYS {
+            self.create_and_enable(
+                root,
+                &format!("agetty-{}", tty),
+                &format!("agetty --noclear {} 38400 linux", tty),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Write runit's stage scripts and point /sbin/init at runit-init
+fn write_runit_stages(root: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let runit_dir = root.join("etc/runit");
+    fs::create_dir_all(&runit_dir)?;
+    for (stage, script) in [
+        ("1", RUNIT_STAGE1),
+        ("2", RUNIT_STAGE2),
+        ("3", RUNIT_STAGE3),
+    ] {
+        let path = runit_dir.join(stage);
+        fs::write(&path, script)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    }
+
+    // /sbin is a link to /usr/sbin on Tumbleweed
+    let sbin = root.join("usr/sbin");
+    fs::create_dir_all(&sbin)?;
+    let init = sbin.join("init");
+    if init.symlink_metadata().is_ok() {
+        fs::remove_file(&init)?;
+    }
+    std::os::unix::fs::symlink("runit-init", &init)?;
+
+    Ok(())
+}
+
+impl Distro for OpenSuse {
+    fn name(&self) -> &str {
+        "openSUSE Tumbleweed"
+    }
+
+    fn pkg_manager(&self) -> &str {
+        "zypper"
+    }
+
+    fn repo_url(&self) -> &str {
+        &self.repo
+    }
+
+    fn set_repo(&mut self, repo: &Repo) {
+        self.repo = repo.url();
+    }
+
+    fn map_package(&self, generic: &str) -> Option<String> {
+        PackageDatabase::global().map_for_distro(generic, DistroKind::OpenSuse)
+    }
+
+    fn map_service(&self, generic: &str) -> String {
+        self.service_map
+            .get(generic)
+            .cloned()
+            .unwrap_or_else(|| generic.to_string())
+    }
+
+    fn init_system(&self) -> &dyn InitSystem {
+        &self.init_system
+    }
+
+    fn install_packages(&self, root: &Path, packages: &[&str]) -> Result<()> {
+        let mapped: Vec<String> = packages
+            .iter()
+            .filter_map(|p| self.map_package(p))
+            .collect();
+
+        if mapped.is_empty() {
+            return Ok(());
+        }
+
+        let mut args = vec!["install", "-y"];
+        args.extend(mapped.iter().map(|s| s.as_str()));
+        self.zypper(root, &args)
+    }
+
+    fn update_system(&self) -> Result<()> {
+        cmd::run("zypper", ["--gpg-auto-import-keys", "refresh"])?;
+        cmd::run("zypper", ["dist-upgrade", "-y"])
+    }
+
+    fn bootstrap(
+        &self,
+        root: &Path,
+        kernel: Option<Kernel>,
+        enable_networking: bool,
+    ) -> Result<()> {
+        // A fresh root has no repositories configured
+        self.zypper(root, &["addrepo", "--refresh", &self.repo, "repo-oss"])?;
+        self.zypper(root, &["refresh"])?;
+
+        self.zypper(root, &["install", "-y", "-t", "pattern", "base"])?;
+
+        let mut packages = vec!["runit", kernel_package(kernel)];
+
+        if enable_networking {
+            packages.push("dhcpcd");
+        }
+
+        self.install_packages(root, &packages)?;
+        self.make_runit_init(root)?;
+        self.enable_gettys(root)?;
+
+        if enable_networking {
+            self.create_and_enable(root, "dhcpcd", "dhcpcd -B")?;
+        }
+
+        Ok(())
+    }
+
+    fn install_desktop_base(&self, root: &Path, seat_manager: &str) -> Result<()> {
+        // elogind isn't packaged, so seatd is always used
+        if seat_manager == "elogind" {
+            println!("Note: elogind is not available on openSUSE, using seatd");
+        }
+
+        self.install_packages(root, &["seatd", "polkit", "xdg-utils"])?;
+        self.create_and_enable(root, "seatd", "seatd -g video")
+    }
+
+    fn install_display_manager(
+        &self,
+        root: &Path,
+        dm: &str,
+        greeter: Option<&str>,
+        configure_pam_rundir: bool,
+    ) -> Result<()> {
+        let dm_packages: Vec<&str> = match dm {
+            "greetd" => {
+                let mut pkgs = vec!["greetd"];
+                match greeter {
+                    Some("tuigreet") => pkgs.push("greetd-tuigreet"),
+                    Some("gtkgreet") => pkgs.push("greetd-gtkgreet"),
+                    _ => {}
+                }
+                pkgs
+            }
+            _ => return Ok(()),
+        };
+
+        self.install_packages(root, &dm_packages)?;
+
+        if configure_pam_rundir {
+            super::configure_pam_rundir(root, dm)?;
+        }
+
+        self.create_and_enable(root, "greetd", "greetd")
+    }
+
+    fn install_portals(&self, root: &Path, backends: &[&str]) -> Result<()> {
+        let mut packages = vec!["xdg-desktop-portal"];
+
+        for backend in backends {
+            match *backend {
+                "wlr" => packages.push("xdg-desktop-portal-wlr"),
+                "gtk" => packages.push("xdg-desktop-portal-gtk"),
+                "kde" => packages.push("xdg-desktop-portal-kde"),
+                _ => {}
+            }
+        }
+
+        self.install_packages(root, &packages)
+    }
+
+    fn generate_fstab(&self, root: &Path) -> Result<String> {
+        super::generate_fstab_from_findmnt(root)
+    }
+
+    fn package_manager(&self) -> &dyn PackageManager {
+        &self.pkg_manager
+    }
+
+    fn enable_multilib(&self, _root: &Path) -> Result<()> {
+        // The -32bit packages are in the main repository
+        Ok(())
+    }
+
+    fn install_kernel_hook(&self, target: &Path) -> Result<()> {
+        use std::fs;
+
+        // openSUSE kernel scriptlets run the hooks in /etc/kernel/postinst.d/
+        // after a kernel package is installed
+        let hook_dir = target.join("etc/kernel/postinst.d");
+        fs::create_dir_all(&hook_dir)?;
+
+        let hook_content = r#"#!/bin/sh
+# mkOS kernel hook for openSUSE
+# Called by the kernel package scriptlets
+
+set -e
+
+VERSION="$1"
+
+if [ -z "$VERSION" ]; then
+    echo "ERROR: Kernel version not provided"
+    exit 1
+fi
+
+if [ -d "/lib/modules/$VERSION" ]; then
+    echo "mkOS: Rebuilding UKI for kernel $VERSION..."
+    /usr/local/bin/mkos-rebuild-uki
+fi
+
+exit 0
+"#;
+
+        fs::write(hook_dir.join("zz-mkos-uki"), hook_content)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mut perms = fs::metadata(hook_dir.join("zz-mkos-uki"))?.permissions();
+            perms.set_mode(0o755);
+            fs::set_permissions(hook_dir.join("zz-mkos-uki"), perms)?;
+        }
+
+        // Install the rebuild command
+        crate::hooks::install_uki_rebuild_binary(target)?;
+
+        println!("✓ Installed kernel hook for openSUSE (kernel postinst.d)");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opensuse() -> OpenSuse {
+        OpenSuse::default()
+    }
+
+    #[test]
+    fn map_package_linux_kernel() {
+        assert_eq!(
+            opensuse().map_package("linux-kernel"),
+            Some("kernel-default".into())
+        );
+    }
+
+    #[test]
+    fn map_package_base_system() {
+        assert_eq!(
+            opensuse().map_package("base-system"),
+            Some("patterns-base-base".into())
+        );
+    }
+
+    #[test]
+    fn map_package_btrfs_progs() {
+        assert_eq!(
+            opensuse().map_package("btrfs-progs"),
+            Some("btrfsprogs".into())
+        );
+    }
+
+    #[test]
+    fn map_package_cryptsetup() {
+        assert_eq!(
+            opensuse().map_package("cryptsetup"),
+            Some("cryptsetup".into())
+        );
+    }
+
+    #[test]
+    fn map_package_ucode() {
+        assert_eq!(
+            opensuse().map_package("intel-ucode"),
+            Some("ucode-intel".into())
+        );
+        assert_eq!(
+            opensuse().map_package("amd-ucode"),
+            Some("ucode-amd".into())
+        );
+    }
+
+    #[test]
+    fn map_package_unavailable_returns_none() {
+        assert_eq!(opensuse().map_package("elogind"), None);
+        assert_eq!(opensuse().map_package("nonexistent"), None);
+    }
+
+    #[test]
+    fn map_service_names() {
+        assert_eq!(opensuse().map_service("avahi"), "avahi-daemon");
+        assert_eq!(opensuse().map_service("elogind"), "");
+        assert_eq!(opensuse().map_service("sshd"), "sshd");
+        assert_eq!(opensuse().map_service("crond"), "cron");
+    }
+
+    #[test]
+    fn runit_replaces_systemd_as_init() {
+        let root = tempfile::tempdir().unwrap();
+        let sbin = root.path().join("usr/sbin");
+        fs::create_dir_all(&sbin).unwrap();
+        std::os::unix::fs::symlink("../lib/systemd/systemd", sbin.join("init")).unwrap();
+
+        write_runit_stages(root.path()).unwrap();
+
+        assert_eq!(
+            fs::read_link(sbin.join("init")).unwrap(),
+            Path::new("runit-init")
+        );
+        let stage1 = fs::read_to_string(root.path().join("etc/runit/1")).unwrap();
+        assert!(stage1.contains("mount -t devpts"));
+        assert!(stage1.contains("mount -t tmpfs -o mode=1777,nosuid,nodev shm /dev/shm"));
+        let stage2 = fs::read_to_string(root.path().join("etc/runit/2")).unwrap();
+        assert!(stage2.contains("runsvdir -P /var/service"));
+        let stage3 = fs::read_to_string(root.path().join("etc/runit/3")).unwrap();
+        assert!(stage3.contains("umount -a -r"));
+        for stage in ["1", "2", "3"] {
+            assert!(root.path().join("etc/runit").join(stage).exists());
+        }
+    }
+
+    #[test]
+    fn every_console_gets_a_getty() {
+        let root = tempfile::tempdir().unwrap();
+        opensuse().enable_gettys(root.path()).unwrap();
+
+        let run = fs::read_to_string(root.path().join("etc/sv/agetty-tty1/run")).unwrap();
+        assert!(run.ends_with("exec agetty --noclear tty1 38400 linux\n"));
+        for tty in GETTY_TTYS {
+            assert_eq!(
+                fs::read_link(
+                    root.path()
+                        .join("var/service")
+                        .join(format!("agetty-{}", tty))
+                )
+                .unwrap(),
+                Path::new("/etc/sv").join(format!("agetty-{}", tty))
+            );
+        }
+    }
+
+    #[test]
+    fn distro_trait_name() {
+        let o = opensuse();
+        assert_eq!(Distro::name(&o), "openSUSE Tumbleweed");
+        assert_eq!(o.pkg_manager(), "zypper");
+    }
+}
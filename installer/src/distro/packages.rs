@@ -1,6 +1,7 @@
 //! Generic package names that get mapped to distro-specific names.
 //! This lets us write package lists once and have them work across distros.
 
+use super::DistroKind;
 use once_cell::sync::Lazy;
 use serde::Deserialize;
 use std::collections::HashMap;
@@ -16,6 +17,8 @@ pub const CORE_PACKAGES: &[&str] = &[
 
 pub const BOOT_PACKAGES: &[&str] = &["dracut", "efibootmgr", "sbsigntools"];
 
+pub const MKINITCPIO_BOOT_PACKAGES: &[&str] = &["mkinitcpio", "systemd-boot"];
+
 pub const CRYPT_PACKAGES: &[&str] = &["cryptsetup", "btrfs-progs"];
 
 pub const NETWORK_PACKAGES: &[&str] = &["dhcpcd", "iwd"];
@@ -67,6 +70,8 @@ pub struct PackageMapping {
     pub devuan: String,
     #[serde(default)]
     pub slackware: String,
+    #[serde(default)]
+    pub opensuse: String,
 }
 
 /// Global package database, parsed once at compile time (embedded) and first access (parsed)
@@ -87,16 +92,16 @@ impl PackageDatabase {
     }
 
     /// Map a generic package name to a distro-specific name
-    pub fn map_for_distro(&self, generic: &str, distro: &str) -> Option<String> {
+    pub fn map_for_distro(&self, generic: &str, distro: DistroKind) -> Option<String> {
         let mapping = self.get(generic)?;
         let pkg_name = match distro {
-            "artix" => &mapping.artix,
-            "void" => &mapping.void,
-            "alpine" => &mapping.alpine,
-            "gentoo" => &mapping.gentoo,
-            "devuan" => &mapping.devuan,
-            "slackware" => &mapping.slackware,
-            _ => return None,
+            DistroKind::Artix => &mapping.artix,
+            DistroKind::Void => &mapping.void,
+            DistroKind::Alpine => &mapping.alpine,
+            DistroKind::Gentoo => &mapping.gentoo,
+            DistroKind::Devuan => &mapping.devuan,
+            DistroKind::Slackware => &mapping.slackware,
+            DistroKind::OpenSuse => &mapping.opensuse,
         };
 
         if pkg_name.is_empty() {
@@ -127,42 +132,67 @@ mod tests {
         let db = PackageDatabase::global();
 
         assert_eq!(
-            db.map_for_distro("linux-kernel", "artix"),
+            db.map_for_distro("linux-kernel", DistroKind::Artix),
             Some("linux".into())
         );
         assert_eq!(
-            db.map_for_distro("linux-kernel", "void"),
+            db.map_for_distro("linux-kernel", DistroKind::Void),
             Some("linux".into())
         );
         assert_eq!(
-            db.map_for_distro("linux-kernel", "alpine"),
+            db.map_for_distro("linux-kernel", DistroKind::Alpine),
             Some("linux-lts".into())
         );
         assert_eq!(
-            db.map_for_distro("linux-kernel", "gentoo"),
+            db.map_for_distro("linux-kernel", DistroKind::Gentoo),
             Some("sys-kernel/gentoo-kernel-bin".into())
         );
         assert_eq!(
-            db.map_for_distro("linux-kernel", "devuan"),
+            db.map_for_distro("linux-kernel", DistroKind::Devuan),
             Some("linux-image-amd64".into())
         );
     }
 
+    #[test]
+    fn test_map_chosen_kernels() {
+        use crate::boot::Kernel;
+
+        let db = PackageDatabase::global();
+        let map = |kernel: Kernel, distro| db.map_for_distro(kernel.package(), distro);
+
+        for kernel in Kernel::ALL {
+            assert_eq!(map(kernel, DistroKind::Artix), Some(kernel.name().into()));
+        }
+        assert_eq!(
+            map(Kernel::Linux, DistroKind::Alpine),
+            Some("linux-stable".into())
+        );
+        assert_eq!(
+            map(Kernel::Lts, DistroKind::Alpine),
+            Some("linux-lts".into())
+        );
+        assert_eq!(
+            map(Kernel::Linux, DistroKind::OpenSuse),
+            Some("kernel-default".into())
+        );
+        assert_eq!(map(Kernel::Zen, DistroKind::Void), None);
+    }
+
     #[test]
     fn test_map_nss_mdns() {
         let db = PackageDatabase::global();
 
         // This is an important test - Alpine uses a different name
         assert_eq!(
-            db.map_for_distro("nss-mdns", "artix"),
+            db.map_for_distro("nss-mdns", DistroKind::Artix),
             Some("nss-mdns".into())
         );
         assert_eq!(
-            db.map_for_distro("nss-mdns", "alpine"),
+            db.map_for_distro("nss-mdns", DistroKind::Alpine),
             Some("avahi-nss-mdns".into())
         );
         assert_eq!(
-            db.map_for_distro("nss-mdns", "devuan"),
+            db.map_for_distro("nss-mdns", DistroKind::Devuan),
             Some("libnss-mdns".into())
         );
     }
@@ -172,7 +202,10 @@ mod tests {
         let db = PackageDatabase::global();
 
         // eternalterminal not available on Alpine
-        assert_eq!(db.map_for_distro("eternalterminal", "alpine"), None);
+        assert_eq!(
+            db.map_for_distro("eternalterminal", DistroKind::Alpine),
+            None
+        );
     }
 
     #[test]
@@ -181,9 +214,9 @@ mod tests {
 
         // Void bundles pipewire-pulse into pipewire
         assert_eq!(
-            db.map_for_distro("pipewire-pulse", "artix"),
+            db.map_for_distro("pipewire-pulse", DistroKind::Artix),
             Some("pipewire-pulse".into())
         );
-        assert_eq!(db.map_for_distro("pipewire-pulse", "void"), None);
+        assert_eq!(db.map_for_distro("pipewire-pulse", DistroKind::Void), None);
     }
 }
@@ -1,7 +1,9 @@
-use super::Distro;
+use super::{kernel_package, Distro, DistroKind};
+use crate::boot::Kernel;
 use crate::cmd;
 use crate::distro::packages::PackageDatabase;
 use crate::init::{InitSystem, SysVinit};
+use crate::mirror::Repo;
 use crate::pkgmgr::{PackageManager, SlaptGet};
 use anyhow::Result;
 use std::path::Path;
@@ -52,8 +54,12 @@ impl Distro for Slackware {
         &self.repo
     }
 
+    fn set_repo(&mut self, repo: &Repo) {
+        self.repo = repo.url();
+    }
+
     fn map_package(&self, generic: &str) -> Option<String> {
-        PackageDatabase::global().map_for_distro(generic, "slackware")
+        PackageDatabase::global().map_for_distro(generic, DistroKind::Slackware)
     }
 
     fn map_service(&self, generic: &str) -> String {
@@ -85,9 +91,19 @@ impl Distro for Slackware {
         cmd::run("slapt-get", ["--upgrade", "--yes"])
     }
 
-    fn bootstrap(&self, root: &Path, enable_networking: bool) -> Result<()> {
+    fn bootstrap(
+        &self,
+        root: &Path,
+        kernel: Option<Kernel>,
+        enable_networking: bool,
+    ) -> Result<()> {
         // Slackware bootstrap is more manual - typically done via installpkg
-        let mut packages = vec!["aaa_base", "kernel-generic", "cryptsetup", "btrfs-progs"];
+        let mut packages = vec![
+            "aaa_base",
+            kernel_package(kernel),
+            "cryptsetup",
+            "btrfs-progs",
+        ];
 
         if enable_networking {
             packages.push("dhcpcd");
@@ -202,7 +218,7 @@ if [ -z "$KERNEL_VERSION" ]; then
     exit 1
 fi
 
-# Run the UKI rebuild script
+# Rebuild the UKIs
 exec /usr/local/bin/mkos-rebuild-uki
 "#;
 
@@ -216,8 +232,8 @@ exec /usr/local/bin/mkos-rebuild-uki
             fs::set_permissions(hook_dir.join("50-mkos-uki"), perms)?;
         }
 
-        // Install the rebuild script
-        crate::hooks::install_uki_rebuild_script(target)?;
+        // Install the rebuild command
+        crate::hooks::install_uki_rebuild_binary(target)?;
 
         println!("✓ Installed kernel hook for Slackware");
         println!("  Note: You'll need to manually run this after kernel upgrades");
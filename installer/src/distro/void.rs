@@ -1,30 +1,65 @@
-use super::Distro;
+use super::{Distro, DistroKind};
+use crate::boot::Kernel;
 use crate::cmd;
 use crate::distro::packages::PackageDatabase;
-use crate::init::{InitSystem, S6};
+use crate::init::{InitKind, InitSystem, Runit, S6};
+use crate::mirror::Repo;
 use crate::pkgmgr::{PackageManager, Xbps};
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
 use std::path::Path;
 use std::process::Command;
 
 pub struct Void {
     repo: String,
-    init_system: S6,
+    service_map: HashMap<String, String>,
+    init_system: Box<dyn InitSystem>,
     pkg_manager: Xbps,
 }
 
 impl Default for Void {
     fn default() -> Self {
+        Self::new(Box::new(S6::void()))
+    }
+}
+
+impl Void {
+    /// Void with s6, or runit as Void ships it
+    pub fn with_init(init: InitKind) -> Result<Self> {
+        let init_system: Box<dyn InitSystem> = match init {
+            InitKind::S6 => Box::new(S6::void()),
+            InitKind::Runit => Box::new(Runit::void()),
+            other => bail!("Void does not support {}", other.name()),
+        };
+        Ok(Self::new(init_system))
+    }
+
+    fn new(init_system: Box<dyn InitSystem>) -> Self {
         let repo = "https://repo-default.voidlinux.org/current".to_string();
+
+        // Void service directory names for the generic names used across the
+        // installer. An empty name means Void has no equivalent and the
+        // service is skipped.
+        let mut service_map = HashMap::new();
+        service_map.insert("dbus".into(), "dbus".into());
+        service_map.insert("seatd".into(), "seatd".into());
+        service_map.insert("elogind".into(), "elogind".into());
+        service_map.insert("dhcpcd".into(), "dhcpcd".into());
+        service_map.insert("iwd".into(), "iwd".into());
+        service_map.insert("sshd".into(), "sshd".into());
+        service_map.insert("avahi".into(), "avahi-daemon".into());
+        service_map.insert("nftables".into(), "nftables".into());
+        service_map.insert("etserver".into(), "etserver".into());
+        service_map.insert("crond".into(), "cronie".into());
+
         Self {
             pkg_manager: Xbps::new(&repo),
             repo,
-            init_system: S6::void(),
+            service_map,
+            init_system,
         }
     }
-}
 
-impl Void {
     fn xbps_install(&self, root: &Path, packages: &[&str]) -> Result<()> {
         let root_str = root.to_string_lossy();
         let mut args = vec!["-Sy", "-R", &self.repo, "-r", &root_str];
@@ -50,17 +85,25 @@ impl Distro for Void {
         &self.repo
     }
 
+    fn set_repo(&mut self, repo: &Repo) {
+        // xbps takes a local repository as a plain directory path
+        self.repo = repo.location();
+        self.pkg_manager = Xbps::new(&self.repo);
+    }
+
     fn map_package(&self, generic: &str) -> Option<String> {
-        PackageDatabase::global().map_for_distro(generic, "void")
+        PackageDatabase::global().map_for_distro(generic, DistroKind::Void)
     }
 
     fn map_service(&self, generic: &str) -> String {
-        // Void uses generic service names (no mapping needed)
-        generic.to_string()
+        self.service_map
+            .get(generic)
+            .cloned()
+            .unwrap_or_else(|| generic.to_string())
     }
 
     fn init_system(&self) -> &dyn InitSystem {
-        &self.init_system
+        self.init_system.as_ref()
     }
 
     fn install_packages(&self, root: &Path, packages: &[&str]) -> Result<()> {
@@ -81,7 +124,13 @@ impl Distro for Void {
         cmd::run("xbps-install", ["-Syu"])
     }
 
-    fn bootstrap(&self, root: &Path, enable_networking: bool) -> Result<()> {
+    fn bootstrap(
+        &self,
+        root: &Path,
+        _kernel: Option<Kernel>,
+        enable_networking: bool,
+    ) -> Result<()> {
+        // base-system depends on linux, the only kernel offered for Void
         let mut packages = vec!["base-system"];
 
         if enable_networking {
@@ -204,7 +253,7 @@ if [ -z "$KERNEL_VERSION" ]; then
     exit 1
 fi
 
-# Run the UKI rebuild script
+# Rebuild the UKIs
 exec /usr/local/bin/mkos-rebuild-uki
 "#;
 
@@ -219,8 +268,8 @@ exec /usr/local/bin/mkos-rebuild-uki
             fs::set_permissions(trigger_dir.join("50-mkos-uki"), perms)?;
         }
 
-        // Install the rebuild script (same as Artix)
-        crate::hooks::install_uki_rebuild_script(target)?;
+        // Install the rebuild command (same as Artix)
+        crate::hooks::install_uki_rebuild_binary(target)?;
 
         println!("✓ Installed kernel hook for Void Linux");
         Ok(())
@@ -270,12 +319,41 @@ mod tests {
     }
 
     #[test]
-    fn map_service_passes_through() {
-        assert_eq!(void().map_service("dbus"), "dbus");
-        assert_eq!(void().map_service("seatd"), "seatd");
+    fn map_service_runit_names() {
+        let v = void();
+        assert_eq!(v.map_service("dbus"), "dbus");
+        assert_eq!(v.map_service("seatd"), "seatd");
+        assert_eq!(v.map_service("elogind"), "elogind");
+        assert_eq!(v.map_service("dhcpcd"), "dhcpcd");
+        assert_eq!(v.map_service("iwd"), "iwd");
+        assert_eq!(v.map_service("sshd"), "sshd");
+        assert_eq!(v.map_service("nftables"), "nftables");
+    }
+
+    #[test]
+    fn map_service_crond_is_cronie() {
+        assert_eq!(void().map_service("crond"), "cronie");
+    }
+
+    #[test]
+    fn map_service_avahi_daemon() {
+        assert_eq!(void().map_service("avahi"), "avahi-daemon");
+    }
+
+    #[test]
+    fn map_service_unknown_passes_through() {
         assert_eq!(void().map_service("anything"), "anything");
     }
 
+    #[test]
+    fn map_package_network_services() {
+        let v = void();
+        assert_eq!(v.map_package("dhcpcd"), Some("dhcpcd".into()));
+        assert_eq!(v.map_package("avahi"), Some("avahi".into()));
+        assert_eq!(v.map_package("openssh"), Some("openssh".into()));
+        assert_eq!(v.map_package("nftables"), Some("nftables".into()));
+    }
+
     #[test]
     fn distro_trait_name() {
         let v = void();
@@ -1,12 +1,14 @@
 //! Firewall setup using nftables
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use std::fs;
 use std::path::Path;
 
+use crate::cmd;
 use crate::distro::Distro;
 use crate::init::ServiceSpec;
 use crate::manifest::{FirewallConfig, FirewallRule};
+use crate::network::parse_cidr;
 
 /// Set up nftables firewall based on configuration
 pub fn setup_firewall(root: &Path, config: &FirewallConfig, distro: &dyn Distro) -> Result<()> {
@@ -14,13 +16,25 @@ pub fn setup_firewall(root: &Path, config: &FirewallConfig, distro: &dyn Distro)
         return Ok(());
     }
 
+    validate_firewall(config)?;
+
     // Install nftables package
     distro.install_packages(root, &["nftables"])?;
 
-    // Generate and write nftables configuration
+    // Generate the ruleset next to its final path and check it with nft
+    // before moving it into place, so a bad ruleset never gets loaded at boot
     let nft_config = generate_nftables_config(config);
-    fs::write(root.join("etc/nftables.conf"), &nft_config)
-        .context("Failed to write /etc/nftables.conf")?;
+    let conf_path = root.join("etc/nftables.conf");
+    let staged_path = root.join("etc/nftables.conf.new");
+    fs::write(&staged_path, &nft_config).context("Failed to write /etc/nftables.conf")?;
+
+    let staged_str = staged_path.to_string_lossy().to_string();
+    if let Err(e) = cmd::run("nft", ["-c", "-f", &staged_str]) {
+        let _ = fs::remove_file(&staged_path);
+        return Err(e.context("Generated nftables ruleset failed validation"));
+    }
+
+    fs::rename(&staged_path, &conf_path).context("Failed to write /etc/nftables.conf")?;
 
     // Create oneshot service to load rules at boot
     let nftables_service = ServiceSpec::oneshot("nftables", "nft -f /etc/nftables.conf");
@@ -32,6 +46,109 @@ pub fn setup_firewall(root: &Path, config: &FirewallConfig, distro: &dyn Distro)
     Ok(())
 }
 
+/// Port mDNS queries and answers use
+pub const MDNS_PORT: u16 = 5353;
+
+/// Let SSH in on `port`, unless a rule already allows it
+pub fn allow_ssh(config: &mut FirewallConfig, port: u16) {
+    allow_port(config, "SSH", port, "tcp");
+}
+
+/// Let mDNS in, unless a rule already allows its port
+pub fn allow_mdns(config: &mut FirewallConfig) {
+    allow_port(config, "mDNS", MDNS_PORT, "udp");
+}
+
+fn allow_port(config: &mut FirewallConfig, name: &str, port: u16, protocol: &str) {
+    let allowed = config.rules.iter().any(|rule| {
+        rule.protocol == protocol
+            && (rule.port == Some(port) || rule.ports.as_ref().is_some_and(|p| p.contains(&port)))
+    });
+    if !allowed {
+        config.rules.push(FirewallRule {
+            name: name.into(),
+            port: Some(port),
+            ports: None,
+            protocol: protocol.into(),
+            source: None,
+            family: None,
+        });
+    }
+}
+
+/// ICMPv6 types needed for address resolution and autoconfiguration
+const ICMPV6_NEIGHBOR_DISCOVERY: &[&str] = &[
+    "nd-router-solicit",
+    "nd-router-advert",
+    "nd-neighbor-solicit",
+    "nd-neighbor-advert",
+];
+
+/// Validate firewall policies and rules before generating a ruleset
+pub fn validate_firewall(config: &FirewallConfig) -> Result<()> {
+    for (chain, policy) in [
+        ("input", &config.defaults.input),
+        ("forward", &config.defaults.forward),
+        ("output", &config.defaults.output),
+    ] {
+        if policy != "accept" && policy != "drop" {
+            bail!(
+                "Invalid {} policy: {}. Supported: accept, drop",
+                chain,
+                policy
+            );
+        }
+    }
+
+    for rule in &config.rules {
+        match (&rule.port, &rule.ports) {
+            (Some(_), Some(_)) => {
+                bail!("Firewall rule '{}' sets both port and ports", rule.name)
+            }
+            (None, None) => bail!("Firewall rule '{}' needs a port or ports", rule.name),
+            (None, Some(ports)) if ports.is_empty() => {
+                bail!("Firewall rule '{}' has an empty ports list", rule.name)
+            }
+            _ => {}
+        }
+
+        if rule.protocol != "tcp" && rule.protocol != "udp" {
+            bail!(
+                "Firewall rule '{}' has unknown protocol: {}. Supported: tcp, udp",
+                rule.name,
+                rule.protocol
+            );
+        }
+
+        if let Some(family) = &rule.family {
+            if family != "ip" && family != "ip6" {
+                bail!(
+                    "Firewall rule '{}' has unknown family: {}. Supported: ip, ip6",
+                    rule.name,
+                    family
+                );
+            }
+        }
+
+        if let Some(source) = &rule.source {
+            let (addr, _) = parse_cidr(source)
+                .with_context(|| format!("Invalid source for firewall rule '{}'", rule.name))?;
+
+            let source_family = if addr.is_ipv4() { "ip" } else { "ip6" };
+            if rule.family.as_deref().is_some_and(|f| f != source_family) {
+                bail!(
+                    "Firewall rule '{}' source {} doesn't match family {}",
+                    rule.name,
+                    source,
+                    rule.family.as_deref().unwrap_or_default()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
 /// Generate nftables configuration file content
 fn generate_nftables_config(config: &FirewallConfig) -> String {
     let mut rules = String::new();
@@ -50,12 +167,17 @@ fn generate_nftables_config(config: &FirewallConfig) -> String {
     ));
     rules.push_str("        # Accept loopback traffic\n");
     rules.push_str("        iif lo accept\n\n");
+    rules.push_str("        # Accept IPv6 neighbor discovery (IPv6 breaks without it)\n");
+    rules.push_str(&format!(
+        "        icmpv6 type {{ {} }} accept\n\n",
+        ICMPV6_NEIGHBOR_DISCOVERY.join(", ")
+    ));
     rules.push_str("        # Accept established and related connections\n");
     rules.push_str("        ct state established,related accept\n");
     rules.push_str("        ct state invalid drop\n\n");
     rules.push_str("        # Accept ICMP (ping)\n");
-    rules.push_str("        ip protocol icmp accept\n");
-    rules.push_str("        ip6 nexthdr icmpv6 accept\n\n");
+    rules.push_str("        meta l4proto icmp accept\n");
+    rules.push_str("        meta l4proto ipv6-icmp accept\n\n");
 
     // Add custom rules
     for rule in &config.rules {
@@ -88,9 +210,14 @@ fn generate_nftables_config(config: &FirewallConfig) -> String {
 fn format_rule(rule: &FirewallRule) -> String {
     let mut line = format!("        # {}\n        ", rule.name);
 
-    // Source IP restriction
+    // Source IP restriction (implies the address family), otherwise an
+    // explicit family restriction; the inet table matches both by default
     if let Some(src) = &rule.source {
-        line.push_str(&format!("ip saddr {} ", src));
+        let family = if src.contains(':') { "ip6" } else { "ip" };
+        line.push_str(&format!("{} saddr {} ", family, src));
+    } else if let Some(family) = &rule.family {
+        let nfproto = if family == "ip6" { "ipv6" } else { "ipv4" };
+        line.push_str(&format!("meta nfproto {} ", nfproto));
     }
 
     // Protocol and port(s)
@@ -107,3 +234,156 @@ fn format_rule(rule: &FirewallRule) -> String {
 
     line
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(port: Option<u16>, ports: Option<Vec<u16>>, source: Option<&str>) -> FirewallRule {
+        FirewallRule {
+            name: "SSH".into(),
+            port,
+            ports,
+            protocol: "tcp".into(),
+            source: source.map(String::from),
+            family: None,
+        }
+    }
+
+    fn config(rules: Vec<FirewallRule>) -> FirewallConfig {
+        FirewallConfig {
+            enabled: true,
+            rules,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn allow_ssh_opens_port_22_once() {
+        let mut firewall = config(Vec::new());
+        allow_ssh(&mut firewall, 22);
+        allow_ssh(&mut firewall, 22);
+        assert_eq!(firewall.rules.len(), 1);
+        assert_eq!(firewall.rules[0].port, Some(22));
+        assert_eq!(firewall.rules[0].protocol, "tcp");
+        assert!(validate_firewall(&firewall).is_ok());
+    }
+
+    #[test]
+    fn allow_ssh_keeps_an_existing_rule() {
+        // A manifest's source-restricted rule must not be widened
+        let mut firewall = config(vec![rule(Some(22), None, Some("192.168.1.0/24"))]);
+        allow_ssh(&mut firewall, 22);
+        assert_eq!(firewall.rules.len(), 1);
+
+        let mut firewall = config(vec![rule(None, Some(vec![80, 22]), None)]);
+        allow_ssh(&mut firewall, 22);
+        assert_eq!(firewall.rules.len(), 1);
+
+        // Port 22 over udp isn't SSH
+        let mut udp = rule(Some(22), None, None);
+        udp.protocol = "udp".into();
+        let mut firewall = config(vec![udp]);
+        allow_ssh(&mut firewall, 22);
+        assert_eq!(firewall.rules.len(), 2);
+    }
+
+    #[test]
+    fn allow_mdns_opens_udp_5353() {
+        let mut firewall = config(Vec::new());
+        allow_mdns(&mut firewall);
+        assert_eq!(firewall.rules[0].port, Some(MDNS_PORT));
+        assert_eq!(firewall.rules[0].protocol, "udp");
+    }
+
+    #[test]
+    fn source_restricted_rule() {
+        let line = format_rule(&rule(Some(22), None, Some("192.168.1.0/24")));
+        assert_eq!(
+            line,
+            "        # SSH\n        ip saddr 192.168.1.0/24 tcp dport 22 accept\n\n"
+        );
+    }
+
+    #[test]
+    fn ipv6_source_uses_ip6() {
+        let line = format_rule(&rule(Some(22), None, Some("fd00::/64")));
+        assert!(line.contains("ip6 saddr fd00::/64 tcp dport 22 accept"));
+    }
+
+    #[test]
+    fn family_restricts_stack() {
+        let mut v6_only = rule(Some(22), None, None);
+        v6_only.family = Some("ip6".into());
+        assert!(format_rule(&v6_only).contains("meta nfproto ipv6 tcp dport 22 accept"));
+
+        let both = format_rule(&rule(Some(22), None, None));
+        assert!(both.contains("        tcp dport 22 accept"));
+    }
+
+    #[test]
+    fn rejects_mismatched_family() {
+        let mut mismatch = rule(Some(22), None, Some("fd00::/64"));
+        mismatch.family = Some("ip".into());
+        assert!(validate_firewall(&config(vec![mismatch])).is_err());
+
+        let mut unknown = rule(Some(22), None, None);
+        unknown.family = Some("inet".into());
+        assert!(validate_firewall(&config(vec![unknown])).is_err());
+    }
+
+    #[test]
+    fn ruleset_uses_inet_table_and_allows_neighbor_discovery() {
+        let ruleset = generate_nftables_config(&config(vec![]));
+        assert!(ruleset.contains("table inet filter {"));
+        assert!(ruleset.contains(
+            "icmpv6 type { nd-router-solicit, nd-router-advert, \
+             nd-neighbor-solicit, nd-neighbor-advert } accept"
+        ));
+
+        // Neighbor discovery must be accepted before invalid packets are dropped
+        let nd = ruleset.find("icmpv6 type").unwrap();
+        let invalid = ruleset.find("ct state invalid drop").unwrap();
+        assert!(nd < invalid);
+    }
+
+    #[test]
+    fn multiple_ports_render_as_set() {
+        let line = format_rule(&rule(None, Some(vec![80, 443]), None));
+        assert!(line.contains("tcp dport { 80, 443 } accept"));
+    }
+
+    #[test]
+    fn rejects_port_and_ports() {
+        let config = config(vec![rule(Some(22), Some(vec![80]), None)]);
+        assert!(validate_firewall(&config).is_err());
+    }
+
+    #[test]
+    fn rejects_rule_without_ports() {
+        assert!(validate_firewall(&config(vec![rule(None, None, None)])).is_err());
+        assert!(validate_firewall(&config(vec![rule(None, Some(vec![]), None)])).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_source_protocol_and_policy() {
+        assert!(validate_firewall(&config(vec![rule(Some(22), None, Some("10.0.0.0"))])).is_err());
+
+        let mut bad_proto = rule(Some(22), None, None);
+        bad_proto.protocol = "icmp".into();
+        assert!(validate_firewall(&config(vec![bad_proto])).is_err());
+
+        let mut bad_policy = config(vec![]);
+        bad_policy.defaults.input = "reject".into();
+        assert!(validate_firewall(&bad_policy).is_err());
+    }
+
+    #[test]
+    fn valid_config_passes() {
+        let config = config(vec![
+            rule(Some(22), None, Some("192.168.1.0/24")),
+            rule(None, Some(vec![80, 443]), None),
+        ]);
+        assert!(validate_firewall(&config).is_ok());
+    }
+}
@@ -0,0 +1,60 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Rebuilds the UKIs from the kernel upgrade hooks
+pub const REBUILD_UKI: &str = "mkos-rebuild-uki";
+
+/// System management tool, run by scheduled snapshots
+pub const MKOS: &str = "mkos";
+
+/// A binary shipped alongside the running installer
+pub fn companion_binary(name: &str) -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    let binary = exe.parent()?.join(name);
+    binary.is_file().then_some(binary)
+}
+
+/// Copy the companion binary `name` to /usr/local/bin in the target
+pub fn install_binary(root: &Path, name: &str) -> Result<()> {
+    let binary = companion_binary(name)
+        .with_context(|| format!("{} isn't next to the installer; it ships with it", name))?;
+
+    let bin_dir = root.join("usr/local/bin");
+    fs::create_dir_all(&bin_dir)?;
+
+    let dest = bin_dir.join(name);
+    fs::copy(&binary, &dest).with_context(|| format!("Failed to copy {}", name))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&dest, fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Failed to make {} executable", name))?;
+    }
+
+    println!("✓ Installed {} at /usr/local/bin/{}", name, name);
+
+    Ok(())
+}
+
+/// Install the UKI rebuild command the kernel hooks run
+pub fn install_uki_rebuild_binary(root: &Path) -> Result<()> {
+    install_binary(root, REBUILD_UKI)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_companion_is_an_error() {
+        let root = tempfile::tempdir().unwrap();
+        let err = install_binary(root.path(), "mkos-no-such-binary").unwrap_err();
+        assert!(err.to_string().contains("isn't next to the installer"));
+        assert!(!root
+            .path()
+            .join("usr/local/bin/mkos-no-such-binary")
+            .exists());
+    }
+}
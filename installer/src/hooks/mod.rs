@@ -1,6 +1,9 @@
+mod binaries;
 mod pacman;
 mod void;
 
+pub use binaries::{
+    companion_binary, install_binary, install_uki_rebuild_binary, MKOS, REBUILD_UKI,
+};
 pub use pacman::install_pacman_hooks;
-pub use pacman::install_uki_rebuild_script;
 pub use void::install_void_kernel_hooks;
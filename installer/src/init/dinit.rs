@@ -0,0 +1,266 @@
+use super::{InitSystem, ServiceSpec, ServiceType};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+pub struct Dinit {
+    service_dir: &'static str,
+    /// Services linked here are started by the `boot` service
+    boot_dir: &'static str,
+    user_service_dir: &'static str,
+}
+
+impl Dinit {
+    /// Create dinit configuration for Artix dinit
+    pub fn artix() -> Self {
+        Self {
+            service_dir: "etc/dinit.d",
+            boot_dir: "etc/dinit.d/boot.d",
+            user_service_dir: ".config/dinit.d",
+        }
+    }
+
+    /// Generate a dinit service description
+    fn generate_service_file(&self, spec: &ServiceSpec) -> String {
+        let description = format!("# mkOS service for {}\n", spec.name);
+
+        let service_type = match spec.service_type {
+            ServiceType::Longrun => "process",
+            ServiceType::Oneshot => "scripted",
+        };
+
+        // dinit execs the command directly, so waiting and environment
+        // variables need a shell in front of it
+        let command = if spec.wait_for.is_some() || !spec.environment.is_empty() {
+            let mut script = String::new();
+            for (key, value) in &spec.environment {
+                script.push_str(&format!("export {}=\"{}\"; ", key, value));
+            }
+            if let Some(wait_path) = &spec.wait_for {
+                script.push_str(&format!(
+                    "while [ ! -e \"{}\" ]; do sleep 0.1; done; ",
+                    wait_path
+                ));
+            }
+            script.push_str(&format!("exec {}", spec.command));
+            format!("/bin/sh -c {}", quote(&script))
+        } else {
+            spec.command.clone()
+        };
+
+        let mut content = format!(
+            "{}type = {}\ncommand = {}\n",
+            description, service_type, command
+        );
+
+        match spec.service_type {
+            ServiceType::Longrun => content.push_str("restart = true\n"),
+            ServiceType::Oneshot => content.push_str("restart = false\n"),
+        }
+
+        for dep in &spec.depends {
+            content.push_str(&format!("depends-on = {}\n", dep));
+        }
+
+        content
+    }
+}
+
+/// `arg` as one argument of a dinit `command`: dinit splits on whitespace
+/// outside double quotes and takes the character after a backslash as is
+fn quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', r"\\").replace('"', r#"\""#))
+}
+
+impl InitSystem for Dinit {
+    fn name(&self) -> &str {
+        "dinit"
+    }
+
+    fn enable_service(&self, root: &Path, service: &str) -> Result<()> {
+        let service_path = root.join(self.service_dir).join(service);
+        let boot_dir = root.join(self.boot_dir);
+
+        if !service_path.exists() {
+            anyhow::bail!(
+                "Service {} not found in {}",
+                service,
+                service_path.display()
+            );
+        }
+
+        fs::create_dir_all(&boot_dir)?;
+
+        // What `dinitctl enable` does on a running system. The link points at
+        // the target's path, so it dangles until the system boots
+        let link = boot_dir.join(service);
+        if link.symlink_metadata().is_err() {
+            let target = Path::new("/").join(self.service_dir).join(service);
+            std::os::unix::fs::symlink(&target, &link)
+                .with_context(|| format!("Failed to enable service {}", service))?;
+        }
+
+        Ok(())
+    }
+
+    fn disable_service(&self, root: &Path, service: &str) -> Result<()> {
+        let link = root.join(self.boot_dir).join(service);
+
+        if link.symlink_metadata().is_ok() {
+            fs::remove_file(&link)
+                .with_context(|| format!("Failed to disable service {}", service))?;
+        }
+
+        Ok(())
+    }
+
+    fn is_service_enabled(&self, root: &Path, service: &str) -> bool {
+        root.join(self.boot_dir)
+            .join(service)
+            .symlink_metadata()
+            .is_ok()
+    }
+
+    fn enabled_services(&self, root: &Path) -> Vec<String> {
+        super::entry_names(&root.join(self.boot_dir))
+    }
+
+    fn create_service(&self, root: &Path, spec: &ServiceSpec) -> Result<()> {
+        let service_dir = root.join(self.service_dir);
+        fs::create_dir_all(&service_dir)?;
+
+        fs::write(
+            service_dir.join(&spec.name),
+            self.generate_service_file(spec),
+        )
+        .with_context(|| format!("Failed to create service {}", spec.name))
+    }
+
+    fn user_service_dir(&self) -> &str {
+        self.user_service_dir
+    }
+
+    fn setup_user_services(&self, root: &Path) -> Result<()> {
+        let skel_service = root.join("etc/skel").join(self.user_service_dir);
+        fs::create_dir_all(skel_service.join("boot.d"))
+            .context("Failed to create user service skeleton directory")?;
+
+        // The user-mode boot service pulls in whatever is linked into boot.d
+        let boot = "# User services started at login\n\
+            type = internal\n\
+            waits-for.d = boot.d\n";
+        fs::write(skel_service.join("boot"), boot)?;
+
+        let readme = "# User Services (dinit)\n\n\
+            This directory contains your personal dinit services.\n\n\
+            ## Running User Services\n\n\
+            Add this to your shell profile (~/.profile or ~/.bash_profile):\n\n\
+            ```sh\n\
+            if ! dinitctl --user list >/dev/null 2>&1; then\n    \
+            dinit --user &\n\
+            fi\n\
+            ```\n\n\
+            Create services here and enable them with `dinitctl --user enable <name>`.\n";
+
+        fs::write(skel_service.join("README.md"), readme)?;
+
+        Ok(())
+    }
+
+    fn create_user_service(&self, root: &Path, spec: &ServiceSpec) -> Result<()> {
+        let skel_service = root.join("etc/skel").join(self.user_service_dir);
+        fs::create_dir_all(&skel_service)?;
+
+        fs::write(
+            skel_service.join(&spec.name),
+            self.generate_service_file(spec),
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longrun_service_file() {
+        let spec = ServiceSpec::longrun("sshd", "/usr/bin/sshd -D");
+        assert_eq!(
+            Dinit::artix().generate_service_file(&spec),
+            "# mkOS service for sshd\n\
+             type = process\n\
+             command = /usr/bin/sshd -D\n\
+             restart = true\n"
+        );
+    }
+
+    #[test]
+    fn oneshot_with_wait_and_env_runs_through_shell() {
+        let spec = ServiceSpec::oneshot("mount-data", "/usr/local/bin/mount-data")
+            .wait_for("/dev/mapper/data")
+            .env("MODE", "ro");
+        let file = Dinit::artix().generate_service_file(&spec);
+
+        assert!(file.contains("type = scripted\n"));
+        assert!(file.contains("restart = false\n"));
+        assert!(file.contains(
+            r#"command = /bin/sh -c "export MODE=\"ro\"; while [ ! -e \"/dev/mapper/data\" ]; do sleep 0.1; done; exec /usr/local/bin/mount-data""#
+        ));
+    }
+
+    #[test]
+    fn shell_command_with_spaces_and_quotes_stays_one_argument() {
+        let spec = ServiceSpec::longrun(
+            "kiosk",
+            r#"/usr/bin/cage -- firefox --kiosk "https://example.com/a b""#,
+        )
+        .env("GREETING", "it's");
+        let file = Dinit::artix().generate_service_file(&spec);
+
+        assert!(file.contains(
+            r#"command = /bin/sh -c "export GREETING=\"it's\"; exec /usr/bin/cage -- firefox --kiosk \"https://example.com/a b\"""#
+        ));
+        assert_eq!(quote(r"C:\dir"), r#""C:\\dir""#);
+    }
+
+    #[test]
+    fn dependencies_use_depends_on() {
+        let spec = ServiceSpec::longrun("greetd", "/usr/bin/greetd").depends_on("dbus");
+        assert!(Dinit::artix()
+            .generate_service_file(&spec)
+            .ends_with("depends-on = dbus\n"));
+    }
+
+    #[test]
+    fn enable_links_into_boot_d() {
+        let root = tempfile::tempdir().unwrap();
+        let dinit = Dinit::artix();
+        dinit
+            .create_service(
+                root.path(),
+                &ServiceSpec::longrun("sshd", "/usr/bin/sshd -D"),
+            )
+            .unwrap();
+
+        dinit.enable_service(root.path(), "sshd").unwrap();
+
+        let link = root.path().join("etc/dinit.d/boot.d/sshd");
+        assert_eq!(
+            fs::read_link(&link).unwrap(),
+            Path::new("/etc/dinit.d/sshd")
+        );
+        assert!(dinit.is_service_enabled(root.path(), "sshd"));
+        assert_eq!(dinit.enabled_services(root.path()), ["sshd"]);
+
+        dinit.disable_service(root.path(), "sshd").unwrap();
+        assert!(!dinit.is_service_enabled(root.path(), "sshd"));
+    }
+
+    #[test]
+    fn enabling_a_missing_service_fails() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(Dinit::artix().enable_service(root.path(), "nope").is_err());
+    }
+}
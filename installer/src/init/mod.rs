@@ -1,15 +1,59 @@
+mod dinit;
 mod openrc;
 mod runit;
 mod s6;
 mod sysvinit;
 
+pub use dinit::Dinit;
 pub use openrc::OpenRC;
 pub use runit::Runit;
 pub use s6::S6;
 pub use sysvinit::SysVinit;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use std::fs;
 use std::path::Path;
+use std::str::FromStr;
+
+/// Init systems a distro can be installed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitKind {
+    S6,
+    Runit,
+    OpenRC,
+    SysVinit,
+    Dinit,
+}
+
+impl InitKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            InitKind::S6 => "s6",
+            InitKind::Runit => "runit",
+            InitKind::OpenRC => "openrc",
+            InitKind::SysVinit => "sysvinit",
+            InitKind::Dinit => "dinit",
+        }
+    }
+}
+
+impl FromStr for InitKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "s6" => Ok(InitKind::S6),
+            "runit" => Ok(InitKind::Runit),
+            "openrc" => Ok(InitKind::OpenRC),
+            "sysvinit" => Ok(InitKind::SysVinit),
+            "dinit" => Ok(InitKind::Dinit),
+            other => bail!(
+                "Unknown init system: {}. Supported: s6, runit, openrc, sysvinit, dinit",
+                other
+            ),
+        }
+    }
+}
 
 /// Type of service execution
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
@@ -32,6 +76,9 @@ pub struct ServiceSpec {
     pub service_type: ServiceType,
     /// File/socket to wait for before starting (optional)
     pub wait_for: Option<String>,
+    /// Services to start first, as the init system names them
+    /// (see `Distro::map_service`)
+    pub depends: Vec<String>,
     /// Environment variables to set
     pub environment: Vec<(String, String)>,
 }
@@ -44,6 +91,7 @@ impl ServiceSpec {
             command: command.into(),
             service_type: ServiceType::Longrun,
             wait_for: None,
+            depends: Vec::new(),
             environment: Vec::new(),
         }
     }
@@ -55,6 +103,7 @@ impl ServiceSpec {
             command: command.into(),
             service_type: ServiceType::Oneshot,
             wait_for: None,
+            depends: Vec::new(),
             environment: Vec::new(),
         }
     }
@@ -65,6 +114,12 @@ impl ServiceSpec {
         self
     }
 
+    /// Add a service that must be started first
+    pub fn depends_on(mut self, service: impl Into<String>) -> Self {
+        self.depends.push(service.into());
+        self
+    }
+
     /// Add an environment variable
     pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
         self.environment.push((key.into(), value.into()));
@@ -72,6 +127,18 @@ impl ServiceSpec {
     }
 }
 
+/// Sorted names of the entries in `dir`, none when it doesn't exist
+fn entry_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    names
+}
+
 /// Trait for init system implementations (s6, runit, dinit, etc.)
 pub trait InitSystem: Send + Sync {
     /// Name of the init system
@@ -88,6 +155,9 @@ pub trait InitSystem: Send + Sync {
     /// Check if a system service is enabled
     fn is_service_enabled(&self, root: &Path, service: &str) -> bool;
 
+    /// Names of the system services enabled at boot, sorted
+    fn enabled_services(&self, root: &Path) -> Vec<String>;
+
     /// Create a new system service from spec
     fn create_service(&self, root: &Path, spec: &ServiceSpec) -> Result<()>;
 
@@ -36,11 +36,10 @@ impl OpenRC {
 
     /// Generate an OpenRC service script
     fn generate_service_script(&self, spec: &ServiceSpec) -> String {
-        let depends = if let Some(wait_path) = &spec.wait_for {
-            format!(
-                "depend() {{\n    need localmount\n    after {}\n}}\n\n",
-                wait_path
-            )
+        let depends = if spec.wait_for.is_some() || !spec.depends.is_empty() {
+            let mut need = vec!["localmount"];
+            need.extend(spec.depends.iter().map(|d| d.as_str()));
+            format!("depend() {{\n    need {}\n}}\n\n", need.join(" "))
         } else {
             String::new()
         };
@@ -158,6 +157,10 @@ impl InitSystem for OpenRC {
         root.join(self.runlevel_dir).join(service).exists()
     }
 
+    fn enabled_services(&self, root: &Path) -> Vec<String> {
+        super::entry_names(&root.join(self.runlevel_dir))
+    }
+
     fn create_service(&self, root: &Path, spec: &ServiceSpec) -> Result<()> {
         let service_dir = root.join(self.service_dir);
         fs::create_dir_all(&service_dir)?;
@@ -196,3 +199,23 @@ impl InitSystem for OpenRC {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dependencies_are_needed() {
+        let spec = ServiceSpec::longrun("greetd", "/usr/bin/greetd").depends_on("dbus");
+        let script = OpenRC::artix().generate_service_script(&spec);
+        assert!(script.contains("depend() {\n    need localmount dbus\n}\n"));
+    }
+
+    #[test]
+    fn no_depend_block_without_dependencies() {
+        let spec = ServiceSpec::oneshot("motd", "/usr/local/bin/motd");
+        assert!(!OpenRC::alpine()
+            .generate_service_script(&spec)
+            .contains("depend()"));
+    }
+}
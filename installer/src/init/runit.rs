@@ -20,6 +20,15 @@ impl Runit {
         }
     }
 
+    /// Create Runit configuration for openSUSE (runit's default layout)
+    pub fn opensuse() -> Self {
+        Self {
+            service_dir: "etc/sv",
+            enablement_dir: "var/service",
+            user_service_dir: "service",
+        }
+    }
+
     /// Create Runit configuration for Artix runit
     pub fn artix() -> Self {
         Self {
@@ -33,6 +42,14 @@ impl Runit {
     fn generate_run_script(&self, spec: &ServiceSpec) -> String {
         let mut script = String::from("#!/bin/sh\n");
 
+        // runit has no dependencies; exiting makes runsv retry until they're up
+        for dep in &spec.depends {
+            script.push_str(&format!("sv check {} >/dev/null || exit 1\n", dep));
+        }
+        if !spec.depends.is_empty() {
+            script.push('\n');
+        }
+
         if let Some(wait_path) = &spec.wait_for {
             script.push_str(&format!(
                 "# Wait for {}\nwhile [ ! -e \"{}\" ]; do sleep 0.1; done\n\n",
@@ -97,6 +114,10 @@ impl InitSystem for Runit {
         root.join(self.enablement_dir).join(service).exists()
     }
 
+    fn enabled_services(&self, root: &Path) -> Vec<String> {
+        super::entry_names(&root.join(self.enablement_dir))
+    }
+
     fn create_service(&self, root: &Path, spec: &ServiceSpec) -> Result<()> {
         let service_path = root.join(self.service_dir).join(&spec.name);
         fs::create_dir_all(&service_path)?;
@@ -185,3 +206,17 @@ impl InitSystem for Runit {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dependencies_are_checked_before_exec() {
+        let spec = ServiceSpec::longrun("greetd", "/usr/bin/greetd").depends_on("dbus");
+        assert_eq!(
+            Runit::void().generate_run_script(&spec),
+            "#!/bin/sh\nsv check dbus >/dev/null || exit 1\n\nexec /usr/bin/greetd\n"
+        );
+    }
+}
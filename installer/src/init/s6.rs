@@ -62,6 +62,54 @@ impl S6 {
         script
     }
 
+    /// Run script for a service under the per-user s6-svscan
+    ///
+    /// There's no s6-rc in the user tree, so a oneshot marks itself "run
+    /// once" instead of relying on s6-rc's `type`/`up` files.
+    fn generate_user_run_script(&self, spec: &ServiceSpec) -> String {
+        let mut preamble = String::from("#!/bin/sh\n");
+        if spec.service_type == ServiceType::Oneshot {
+            preamble.push_str("s6-svc -O .\n");
+        }
+        // Dependencies are siblings in the same scan directory
+        for dep in &spec.depends {
+            preamble.push_str(&format!("s6-svwait -u ../{}\n", dep));
+        }
+
+        self.generate_run_script(spec)
+            .replacen("#!/bin/sh\n", &preamble, 1)
+    }
+
+    /// profile.d hook that starts the user's s6-svscan at login
+    ///
+    /// The scan directory lives in XDG_RUNTIME_DIR, so supervise state is
+    /// per-session and cleaned up at logout; it links to the services in the
+    /// user's service directory.
+    fn login_hook(&self) -> String {
+        format!(
+            r#"#!/bin/sh
+# Start user-level s6 supervisor if not already running
+# Only run in sessions with XDG_RUNTIME_DIR set
+
+if [ -n "$XDG_RUNTIME_DIR" ] && [ -d "$HOME/{sv}" ]; then
+    S6_SCANDIR="$XDG_RUNTIME_DIR/s6/service"
+    S6_PIDFILE="$XDG_RUNTIME_DIR/s6/s6-svscan.pid"
+
+    if ! {{ [ -f "$S6_PIDFILE" ] && kill -0 "$(cat "$S6_PIDFILE")" 2>/dev/null; }}; then
+        mkdir -p "$S6_SCANDIR"
+        for sv in "$HOME/{sv}"/*; do
+            [ -d "$sv" ] && ln -sfn "$sv" "$S6_SCANDIR/${{sv##*/}}"
+        done
+
+        s6-svscan "$S6_SCANDIR" >/dev/null 2>&1 &
+        echo $! > "$S6_PIDFILE"
+    fi
+fi
+"#,
+            sv = self.user_service_dir
+        )
+    }
+
     /// Write a service to a directory
     fn write_service(&self, service_dir: &Path, spec: &ServiceSpec) -> Result<()> {
         std::fs::create_dir_all(service_dir)?;
@@ -78,6 +126,15 @@ impl S6 {
             std::fs::write(service_dir.join("up"), "run\n")?;
         }
 
+        // s6-rc reads dependencies from empty files named after each service
+        if !spec.depends.is_empty() {
+            let deps_dir = service_dir.join("dependencies.d");
+            std::fs::create_dir_all(&deps_dir)?;
+            for dep in &spec.depends {
+                std::fs::write(deps_dir.join(dep), "")?;
+            }
+        }
+
         Ok(())
     }
 }
@@ -127,6 +184,10 @@ impl InitSystem for S6 {
         service_dst.exists() || service_dst.is_symlink()
     }
 
+    fn enabled_services(&self, root: &Path) -> Vec<String> {
+        super::entry_names(&root.join(self.enablement_dir))
+    }
+
     fn create_service(&self, root: &Path, spec: &ServiceSpec) -> Result<()> {
         let service_dir = root.join(self.service_dir).join(&spec.name);
         self.write_service(&service_dir, spec)
@@ -138,48 +199,111 @@ impl InitSystem for S6 {
     }
 
     fn setup_user_services(&self, root: &Path) -> Result<()> {
-        let skel_s6 = root.join("etc/skel/.config/s6");
-
-        // Create service directory
-        std::fs::create_dir_all(skel_s6.join("sv"))?;
+        // New users get an empty service directory from /etc/skel
+        std::fs::create_dir_all(root.join("etc/skel").join(self.user_service_dir))?;
 
-        // Create log directory for s6-svscan
-        std::fs::create_dir_all(skel_s6.join("log"))?;
-
-        // Create profile.d script to start user s6-svscan on login
+        // profile.d script to start user s6-svscan on login
         let profile_d = root.join("etc/profile.d");
         std::fs::create_dir_all(&profile_d)?;
 
-        let script = r#"#!/bin/sh
-# Start user-level s6 supervisor if not already running
-# Only run in interactive shells with XDG_RUNTIME_DIR set
-
-if [ -n "$XDG_RUNTIME_DIR" ] && [ -d "$HOME/.config/s6/sv" ]; then
-    S6_SCANDIR="$HOME/.config/s6/sv"
-    S6_PIDFILE="$XDG_RUNTIME_DIR/s6-svscan.pid"
-
-    # Check if already running
-    if [ -f "$S6_PIDFILE" ] && kill -0 "$(cat "$S6_PIDFILE")" 2>/dev/null; then
-        return 0
-    fi
-
-    # Start s6-svscan in background
-    s6-svscan "$S6_SCANDIR" &
-    echo $! > "$S6_PIDFILE"
-fi
-"#;
-
         let script_path = profile_d.join("50-s6-user.sh");
-        std::fs::write(&script_path, script)?;
+        std::fs::write(&script_path, self.login_hook())?;
         std::fs::set_permissions(&script_path, std::fs::Permissions::from_mode(0o755))?;
 
         Ok(())
     }
 
     fn create_user_service(&self, root: &Path, spec: &ServiceSpec) -> Result<()> {
-        let skel_sv = root.join("etc/skel").join(self.user_service_dir);
-        let service_dir = skel_sv.join(&spec.name);
-        self.write_service(&service_dir, spec)
+        let service_dir = root
+            .join("etc/skel")
+            .join(self.user_service_dir)
+            .join(&spec.name);
+        std::fs::create_dir_all(&service_dir)?;
+
+        let run_path = service_dir.join("run");
+        std::fs::write(&run_path, self.generate_user_run_script(spec))
+            .and_then(|_| {
+                std::fs::set_permissions(&run_path, std::fs::Permissions::from_mode(0o755))
+            })
             .context(format!("Failed to create user service '{}'", spec.name))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_script_waits_and_exports_before_exec() {
+        let spec = ServiceSpec::longrun("pipewire-pulse", "pipewire-pulse")
+            .wait_for("$XDG_RUNTIME_DIR/pipewire-0")
+            .env("PIPEWIRE_DEBUG", "2");
+
+        assert_eq!(
+            S6::artix().generate_run_script(&spec),
+            "#!/bin/sh\n\
+             export PIPEWIRE_DEBUG=\"2\"\n\
+             # Wait for $XDG_RUNTIME_DIR/pipewire-0\n\
+             while [ ! -e \"$XDG_RUNTIME_DIR/pipewire-0\" ]; do\n    \
+             sleep 0.1\n\
+             done\n\
+             exec pipewire-pulse\n"
+        );
+    }
+
+    #[test]
+    fn dependencies_are_written_for_s6_rc() {
+        let root = tempfile::tempdir().unwrap();
+        let spec = ServiceSpec::longrun("greetd", "/usr/bin/greetd").depends_on("dbus-srv");
+
+        S6::artix().create_service(root.path(), &spec).unwrap();
+
+        assert!(root
+            .path()
+            .join("etc/s6/sv/greetd/dependencies.d/dbus-srv")
+            .exists());
+    }
+
+    #[test]
+    fn user_dependencies_wait_on_siblings() {
+        let spec = ServiceSpec::longrun("pipewire-pulse", "pipewire-pulse").depends_on("pipewire");
+        assert_eq!(
+            S6::artix().generate_user_run_script(&spec),
+            "#!/bin/sh\ns6-svwait -u ../pipewire\nexec pipewire-pulse\n"
+        );
+    }
+
+    #[test]
+    fn user_oneshot_runs_once() {
+        let spec = ServiceSpec::oneshot("wallpaper", "swaybg -i ~/bg.png");
+        let script = S6::artix().generate_user_run_script(&spec);
+        assert!(script.starts_with("#!/bin/sh\ns6-svc -O .\n"));
+        assert!(script.ends_with("swaybg -i ~/bg.png\n"));
+    }
+
+    #[test]
+    fn user_services_are_seeded_in_skel() {
+        let root = tempfile::tempdir().unwrap();
+        let s6 = S6::artix();
+
+        s6.setup_user_services(root.path()).unwrap();
+        s6.create_user_service(root.path(), &ServiceSpec::longrun("pipewire", "pipewire"))
+            .unwrap();
+
+        let run = root.path().join("etc/skel/.config/s6/sv/pipewire/run");
+        assert_eq!(
+            std::fs::read_to_string(&run).unwrap(),
+            "#!/bin/sh\nexec pipewire\n"
+        );
+        let mode = std::fs::metadata(&run).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+        // No s6-rc metadata in the user tree
+        assert!(!run.with_file_name("type").exists());
+
+        let hook =
+            std::fs::read_to_string(root.path().join("etc/profile.d/50-s6-user.sh")).unwrap();
+        assert!(hook.contains(r#"S6_SCANDIR="$XDG_RUNTIME_DIR/s6/service""#));
+        assert!(hook.contains(r#"for sv in "$HOME/.config/s6/sv"/*; do"#));
+        assert!(hook.contains(r#"ln -sfn "$sv" "$S6_SCANDIR/${sv##*/}""#));
+    }
+}
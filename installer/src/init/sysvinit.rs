@@ -37,6 +37,7 @@ impl SysVinit {
     /// Generate a SysVinit service script
     fn generate_service_script(&self, spec: &ServiceSpec) -> String {
         let description = format!("mkOS service for {}", spec.name);
+        let depends: String = spec.depends.iter().map(|d| format!(" {}", d)).collect();
 
         let env_vars = if !spec.environment.is_empty() {
             spec.environment
@@ -65,7 +66,7 @@ impl SysVinit {
                     "#!/bin/sh\n\
                      ### BEGIN INIT INFO\n\
                      # Provides:          {name}\n\
-                     # Required-Start:    $local_fs $remote_fs $network\n\
+                     # Required-Start:    $local_fs $remote_fs $network{deps}\n\
                      # Required-Stop:     $local_fs $remote_fs $network\n\
                      # Default-Start:     2 3 4 5\n\
                      # Default-Stop:      0 1 6\n\
@@ -107,6 +108,7 @@ impl SysVinit {
                      exit 0\n",
                     name = spec.name,
                     desc = description,
+                    deps = depends,
                     env = env_vars,
                     wait = wait_code,
                     cmd = spec.command
@@ -117,7 +119,7 @@ impl SysVinit {
                     "#!/bin/sh\n\
                      ### BEGIN INIT INFO\n\
                      # Provides:          {name}\n\
-                     # Required-Start:    $local_fs $remote_fs\n\
+                     # Required-Start:    $local_fs $remote_fs{deps}\n\
                      # Required-Stop:\n\
                      # Default-Start:     2 3 4 5\n\
                      # Default-Stop:\n\
@@ -142,6 +144,7 @@ impl SysVinit {
                      exit 0\n",
                     name = spec.name,
                     desc = description,
+                    deps = depends,
                     env = env_vars,
                     wait = wait_code,
                     cmd = spec.command
@@ -256,6 +259,24 @@ impl InitSystem for SysVinit {
         false
     }
 
+    fn enabled_services(&self, root: &Path) -> Vec<String> {
+        // Start links are S<priority><service>
+        let mut services: Vec<String> = self
+            .runlevel_dirs
+            .iter()
+            .flat_map(|dir| super::entry_names(&root.join(dir)))
+            .filter_map(|name| {
+                let service = name
+                    .strip_prefix('S')?
+                    .trim_start_matches(|c: char| c.is_ascii_digit());
+                (!service.is_empty()).then(|| service.to_string())
+            })
+            .collect();
+        services.sort();
+        services.dedup();
+        services
+    }
+
     fn create_service(&self, root: &Path, spec: &ServiceSpec) -> Result<()> {
         let service_dir = root.join(self.service_dir);
         fs::create_dir_all(&service_dir)?;
@@ -294,3 +315,34 @@ impl InitSystem for SysVinit {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dependencies_extend_required_start() {
+        let longrun = ServiceSpec::longrun("greetd", "/usr/bin/greetd").depends_on("dbus");
+        assert!(SysVinit::devuan()
+            .generate_service_script(&longrun)
+            .contains("# Required-Start:    $local_fs $remote_fs $network dbus\n"));
+
+        let oneshot = ServiceSpec::oneshot("motd", "/usr/local/bin/motd").depends_on("dbus");
+        assert!(SysVinit::devuan()
+            .generate_service_script(&oneshot)
+            .contains("# Required-Start:    $local_fs $remote_fs dbus\n"));
+    }
+
+    #[test]
+    fn enabled_services_come_from_start_links() {
+        let root = tempfile::tempdir().unwrap();
+        let init = SysVinit::devuan();
+        init.create_service(root.path(), &ServiceSpec::longrun("sshd", "/usr/sbin/sshd"))
+            .unwrap();
+        init.enable_service(root.path(), "sshd").unwrap();
+        fs::write(root.path().join("etc/rc2.d/K01dbus"), "").unwrap();
+        fs::write(root.path().join("etc/rc3.d/S01dbus"), "").unwrap();
+
+        assert_eq!(init.enabled_services(root.path()), ["dbus", "sshd"]);
+    }
+}
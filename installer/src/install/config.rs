@@ -1,7 +1,17 @@
-use std::path::PathBuf;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
 
-use crate::distro::DistroKind;
-use crate::manifest::{AudioConfig, FirewallConfig, GreetdConfig, NetworkConfig};
+use crate::boot::{BootKind, BootMode, Kernel};
+use crate::crypt::{BtrfsLayout, FilesystemKind};
+use crate::disk::PartitionLayout;
+use crate::distro::{Distro, DistroKind};
+use crate::init::InitKind;
+use crate::manifest::{
+    AudioConfig, FirewallConfig, GreetdConfig, MirrorConfig, NetworkConfig, ScriptConfig,
+    SecurityConfig, SnapshotsConfig,
+};
+use crate::mirror::{Mirror, Repo};
+use crate::secret::Secret;
 
 /// Desktop/graphical session configuration
 #[derive(Debug, Clone, Default)]
@@ -37,6 +47,8 @@ pub struct SwapConfig {
     pub swapfile_size_gb: Option<u32>,
     /// Swappiness value (0-100, default 20)
     pub swappiness: u8,
+    /// Resume from the swapfile after hibernating
+    pub hibernation: bool,
 }
 
 /// Secure Boot configuration
@@ -46,6 +58,8 @@ pub struct SecureBootConfig {
     pub enabled: bool,
     /// Path to existing secure boot keys directory (if None, will generate new keys)
     pub keys_path: Option<PathBuf>,
+    /// Enroll keys into efivars when the firmware is in Setup Mode
+    pub enroll: bool,
 }
 
 impl Default for SwapConfig {
@@ -56,6 +70,7 @@ impl Default for SwapConfig {
             swapfile_enabled: false,
             swapfile_size_gb: None,
             swappiness: 20,
+            hibernation: false,
         }
     }
 }
@@ -63,44 +78,161 @@ impl Default for SwapConfig {
 #[derive(Debug, Clone)]
 pub struct InstallConfig {
     pub device: PathBuf,
-    pub passphrase: String,
-    pub root_password: String,
+    /// Put the root filesystem in a LUKS container; off formats the
+    /// partition directly
+    pub encryption: bool,
+    /// Further disks the btrfs root is mirrored across with RAID1; `device`
+    /// keeps the EFI partition
+    pub raid_devices: Vec<PathBuf>,
+    pub passphrase: Secret,
+    /// LUKS header file on other media; None keeps it on the partition
+    pub luks_header: Option<PathBuf>,
+    pub root_password: Secret,
     pub hostname: String,
     pub timezone: String,
     pub locale: String,
+    /// Generated besides `locale`, which stays the default
+    pub locales: Vec<String>,
     pub keymap: String,
+    /// Graphical layout override, when `keymap` doesn't map to the right one
+    pub keymap_x11: Option<String>,
+    /// Console font; the kernel's built-in font when None
+    pub console_font: Option<String>,
     pub distro: DistroKind,
+    /// Init system; None uses the distro's default
+    pub init: Option<InitKind>,
+    pub filesystem: FilesystemKind,
+    /// Btrfs subvolumes and compression (ignored for other filesystems)
+    pub btrfs_layout: BtrfsLayout,
+    /// EFI partition size and label, and whether to keep an existing one
+    pub partition_layout: PartitionLayout,
+    pub boot: BootKind,
+    /// UKIs or plain EFISTUB kernels (dracut-efistub only)
+    pub boot_mode: BootMode,
+    /// Extra kernel command line arguments
+    pub kernel_cmdline: Vec<String>,
+    /// Kernel to install; the distro's default when None
+    pub kernel: Option<Kernel>,
+    /// Kernels installed alongside `kernel`, each booted by its own UKI
+    pub extra_kernels: Vec<Kernel>,
+    /// Pass `quiet` on the kernel command line
+    pub quiet_boot: bool,
+    /// Show a plymouth boot splash
+    pub splash_boot: bool,
+    /// Add a rescue boot entry that drops to a root shell
+    pub recovery_entry: bool,
     pub enable_networking: bool,
     pub extra_packages: Vec<String>,
+    /// Enable the distro's 32-bit packages before `extra_packages` install
+    pub multilib: bool,
     pub desktop: DesktopConfig,
     pub swap: SwapConfig,
     pub audio: AudioConfig,
     pub network: NetworkConfig,
     pub firewall: FirewallConfig,
+    /// Scheduled snapshots on the installed system
+    pub snapshots: SnapshotsConfig,
+    /// sudo or doas for the wheel group
+    pub security: SecurityConfig,
     pub secureboot: SecureBootConfig,
     pub microcode: bool,
+    /// Manifest pre_install/post_install scripts
+    pub scripts: ScriptConfig,
+    /// Package repository overriding the distro's default
+    pub mirror: MirrorConfig,
+    /// Mirror chosen for pacman, written to the target's mirrorlist
+    pub selected_mirror: Option<Mirror>,
+    /// Image file `device` is a loop device for; images have no NVRAM to
+    /// add boot entries to
+    pub image: Option<PathBuf>,
 }
 
 impl Default for InstallConfig {
     fn default() -> Self {
         Self {
             device: PathBuf::new(),
-            passphrase: String::new(),
-            root_password: String::new(),
+            encryption: true,
+            raid_devices: Vec::new(),
+            passphrase: Secret::default(),
+            luks_header: None,
+            root_password: Secret::default(),
             hostname: "mkos".into(),
             timezone: "UTC".into(),
             locale: "en_US.UTF-8".into(),
+            locales: Vec::new(),
             keymap: "us".into(),
+            keymap_x11: None,
+            console_font: None,
             distro: DistroKind::Artix,
+            init: None,
+            filesystem: FilesystemKind::default(),
+            btrfs_layout: BtrfsLayout::default(),
+            partition_layout: PartitionLayout::default(),
+            boot: BootKind::default(),
+            boot_mode: BootMode::default(),
+            kernel_cmdline: Vec::new(),
+            kernel: None,
+            extra_kernels: Vec::new(),
+            quiet_boot: true,
+            splash_boot: false,
+            recovery_entry: true,
             enable_networking: true,
             extra_packages: Vec::new(),
+            multilib: false,
             desktop: DesktopConfig::default(),
             swap: SwapConfig::default(),
             audio: AudioConfig::default(),
             network: NetworkConfig::default(),
             firewall: FirewallConfig::default(),
+            snapshots: SnapshotsConfig::default(),
+            security: SecurityConfig::default(),
             secureboot: SecureBootConfig::default(),
             microcode: false,
+            scripts: ScriptConfig::default(),
+            mirror: MirrorConfig::default(),
+            selected_mirror: None,
+            image: None,
         }
     }
 }
+
+impl InstallConfig {
+    /// The distro backend with the chosen init system and repository
+    pub fn create_distro(&self) -> Result<Box<dyn Distro>> {
+        let mut distro = self.distro.create_with_init(self.init)?;
+        if let Some(repo) = Repo::from_config(&self.mirror)? {
+            distro.set_repo(&repo);
+        }
+        Ok(distro)
+    }
+
+    /// Kernels the boot images are built for; empty boots whatever is
+    /// installed
+    ///
+    /// The distro's default kernel stands in for `kernel` when extra kernels
+    /// need telling apart from it.
+    pub fn boot_kernels(&self) -> Vec<Kernel> {
+        if self.kernel.is_none() && self.extra_kernels.is_empty() {
+            return Vec::new();
+        }
+        let mut kernels = vec![self.kernel.unwrap_or(self.distro.default_kernel())];
+        for kernel in &self.extra_kernels {
+            if !kernels.contains(kernel) {
+                kernels.push(*kernel);
+            }
+        }
+        kernels
+    }
+
+    /// Every disk installed to, `device` first
+    pub fn disks(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.device.as_path()).chain(self.raid_devices.iter().map(PathBuf::as_path))
+    }
+
+    /// ParallelDownloads for pacman, which only Artix installs with
+    pub fn pacman_parallel_downloads(&self) -> Option<u8> {
+        self.mirror
+            .parallel_downloads
+            .filter(|_| self.distro == DistroKind::Artix)
+    }
+}
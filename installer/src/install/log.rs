@@ -0,0 +1,139 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Where the installed system keeps the log of its install
+pub const TARGET_LOG_PATH: &str = "var/log/mkos-install.log";
+
+/// Everything an install prints, kept in a file
+///
+/// Output written before [`InstallLog::open`] is held and written once the
+/// file exists, so the log on the target starts at the beginning of the
+/// install rather than when the target was mounted.
+#[derive(Clone, Default)]
+pub struct InstallLog(Arc<Mutex<Sink>>);
+
+#[derive(Default)]
+struct Sink {
+    file: Option<File>,
+    pending: Vec<u8>,
+    closed: bool,
+}
+
+impl InstallLog {
+    /// Append to `path` from now on, writing out what was held so far; a
+    /// log that's already open stays where it is
+    pub fn open(&self, path: &Path) -> Result<()> {
+        let mut sink = self.lock();
+        if sink.file.is_some() || sink.closed {
+            return Ok(());
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        file.write_all(&std::mem::take(&mut sink.pending))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        sink.file = Some(file);
+        Ok(())
+    }
+
+    pub fn line(&self, line: &str) {
+        let mut sink = self.lock();
+        let _ = sink.write_all(line.as_bytes());
+        let _ = sink.write_all(b"\n");
+    }
+
+    /// Flush and stop writing, so the filesystem holding the file can be
+    /// unmounted
+    pub fn close(&self) {
+        let mut sink = self.lock();
+        if let Some(mut file) = sink.file.take() {
+            let _ = file.flush();
+        }
+        sink.pending.clear();
+        sink.closed = true;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Sink> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Write for InstallLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.file {
+            Some(file) => file.write(buf),
+            None if self.closed => Ok(buf.len()),
+            None => {
+                self.pending.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_before_and_after_open_reach_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(TARGET_LOG_PATH);
+        let log = InstallLog::default();
+
+        log.line("==> Partition");
+        log.clone()
+            .write_all(b"$ sgdisk --zap-all /dev/sda\n")
+            .unwrap();
+        log.open(&path).unwrap();
+        log.line("==> Bootstrap");
+        log.close();
+        log.line("after close");
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "==> Partition\n$ sgdisk --zap-all /dev/sda\n==> Bootstrap\n"
+        );
+    }
+
+    #[test]
+    fn first_open_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = InstallLog::default();
+        log.open(&dir.path().join("explicit.log")).unwrap();
+        log.open(&dir.path().join("target.log")).unwrap();
+        log.line("hello");
+
+        assert!(!dir.path().join("target.log").exists());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("explicit.log")).unwrap(),
+            "hello\n"
+        );
+    }
+}
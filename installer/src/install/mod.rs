@@ -1,24 +1,46 @@
 mod config;
+mod log;
+mod observer;
+mod preflight;
+mod report;
+mod space;
+mod verify;
 
 pub use config::{DesktopConfig, InstallConfig, SecureBootConfig, SwapConfig};
+pub use log::{InstallLog, TARGET_LOG_PATH};
+pub use observer::{InstallObserver, InstallStep, StdoutObserver};
+pub use preflight::{
+    check_all, preflight, preflight_failures, preflight_network, preflight_offline, Host,
+    LiveSystem,
+};
+pub use report::{recorded_init, InstallReport, REPORT_PATH};
+pub use space::{format_gb, required_bytes, space_summary};
 
-use anyhow::Result;
-use std::path::PathBuf;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tracing::instrument;
 
-use crate::boot::{BootConfig, BootSystem, DracutEfistub};
+use crate::boot::{BootConfig, BootEntry, ResumeConfig, BOOT_MODE_PATH, BOOT_SYSTEM_PATH};
 use crate::chroot::{self, SystemConfig};
-use crate::crypt::{
-    create_subvolumes, format_btrfs, format_luks, get_uuid, mount_subvolumes, open_luks,
-    BtrfsLayout, LuksConfig,
-};
-use crate::disk::{self, PartitionLayout};
-use crate::manifest::GreetdConfig;
+use crate::crypt::snapshot::INSTALL_SNAPSHOT;
+use crate::crypt::{format_luks, get_uuid, open_luks, DetachedHeader, LuksConfig, MountOptions};
+use crate::disk;
+use crate::keyboard::{self, XkbLayout};
+use crate::manifest::{GreetdConfig, Manifest};
 use crate::paths;
+use crate::secret;
+use crate::state::{StateAction, SystemState};
 
 pub struct Installer {
     config: InstallConfig,
     target: PathBuf,
     luks_name: String,
+    observer: Box<dyn InstallObserver>,
+    manifest: Option<Manifest>,
+    install_log: InstallLog,
+    /// Log kept outside the target; None logs to [`TARGET_LOG_PATH`] once
+    /// it's mounted
+    log_file: Option<PathBuf>,
 }
 
 impl Installer {
@@ -27,69 +49,245 @@ impl Installer {
             config,
             target: PathBuf::from(paths::MOUNT_TARGET),
             luks_name: paths::LUKS_MAPPER_NAME.into(),
+            observer: Box::new(StdoutObserver),
+            manifest: None,
+            install_log: InstallLog::default(),
+            log_file: None,
+        }
+    }
+
+    /// Report progress to `observer` instead of stdout
+    pub fn with_observer(mut self, observer: Box<dyn InstallObserver>) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    /// Record `manifest` as the installed system's state
+    pub fn with_manifest(mut self, manifest: Manifest) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    /// Keep the install's log at `path` instead of on the target, so it
+    /// survives failures before the target is mounted
+    pub fn with_log_file(mut self, path: PathBuf) -> Self {
+        self.log_file = Some(path);
+        self
+    }
+
+    fn log(&self, line: impl AsRef<str>) {
+        self.observer.on_log(line.as_ref());
+        self.install_log.line(line.as_ref());
+    }
+
+    #[instrument(skip_all, fields(
+        device = %self.config.device.display(),
+        distro = self.config.distro.name(),
+        filesystem = self.config.filesystem.name(),
+    ))]
+    pub fn run(&self) -> Result<InstallReport> {
+        if let Some(path) = &self.log_file {
+            self.install_log.open(path)?;
+        }
+        crate::cmd::set_log(Some(Box::new(self.install_log.clone())));
+
+        let result = self.install().map_err(|e| self.redacted(e));
+        if let Err(e) = &result {
+            self.install_log.line(&format!("Install failed: {:#}", e));
+        }
+
+        // Closed before the target is unmounted, which an open file would block
+        crate::cmd::set_log(None);
+        self.install_log.close();
+        result
+    }
+
+    fn install(&self) -> Result<InstallReport> {
+        self.log("\nChecking system requirements...");
+        preflight(&self.config)?;
+
+        self.run_pre_install_scripts()?;
+
+        let mut boot_entry = None;
+        let mut report = None;
+        self.run_steps(|step| match step {
+            InstallStep::Partition => self.partition(),
+            InstallStep::Encrypt => self.encrypt(),
+            InstallStep::CreateFilesystems => self.create_filesystems(),
+            InstallStep::Mount => {
+                self.mount()?;
+                self.install_log.open(&self.target.join(TARGET_LOG_PATH))
+            }
+            InstallStep::Bootstrap => self.bootstrap(),
+            InstallStep::Configure => {
+                self.configure()?;
+                self.run_post_install_scripts()
+            }
+            InstallStep::Swap => self.setup_swap(),
+            InstallStep::Boot => {
+                boot_entry = Some(self.setup_boot()?);
+                Ok(())
+            }
+            InstallStep::Snapshot => {
+                let entry = boot_entry.as_ref().context("Boot was not set up")?;
+                // Written before the snapshot so the snapshot carries it too
+                let built = self.report(entry)?;
+                built.write(&self.target.join(REPORT_PATH))?;
+                if let Some(manifest) = &self.manifest {
+                    SystemState::new(StateAction::Install, manifest).write(&self.target)?;
+                }
+                self.create_snapshot()?;
+                report = Some(built);
+                Ok(())
+            }
+            InstallStep::Verify => {
+                let entry = boot_entry.as_ref().context("Boot was not set up")?;
+                self.verify_boot(entry)
+            }
+        })?;
+
+        report.context("Install finished without a report")
+    }
+
+    /// Unmount the target and lock the encrypted partition, so the disk
+    /// can be detached
+    pub fn release(&self) -> Result<()> {
+        crate::cmd::run("umount", ["--recursive", &self.target.to_string_lossy()])?;
+        if !self.config.encryption {
+            return Ok(());
         }
+        for i in 0..self.config.disks().count() {
+            crate::crypt::close_luks(&disk::member_name(&self.luks_name, i))?;
+        }
+        Ok(())
+    }
+
+    /// `error` without the passphrase or root password in it
+    fn redacted(&self, error: anyhow::Error) -> anyhow::Error {
+        secret::redact_error(
+            error,
+            &[&self.config.passphrase, &self.config.root_password],
+        )
     }
 
-    pub fn run(&self) -> Result<()> {
-        self.partition()?;
-        self.encrypt()?;
-        self.create_filesystems()?;
-        self.mount()?;
-        self.bootstrap()?;
-        self.configure()?;
-        self.setup_swap()?;
-        self.setup_boot()?;
-        self.create_snapshot()?;
+    /// Run every step in order through `run`, stopping at the first failure
+    fn run_steps(&self, mut run: impl FnMut(InstallStep) -> Result<()>) -> Result<()> {
+        for step in InstallStep::ALL {
+            self.observer.on_step_start(step);
+            self.install_log.line(&format!("\n==> {}", step));
+            // Observers show errors to people, so secrets come out first
+            let result = run(step).map_err(|e| self.redacted(e));
+            self.observer.on_step_end(step, &result);
+            if let Err(e) = &result {
+                self.install_log
+                    .line(&format!("==> {} failed: {:#}", step, e));
+            }
+            result?;
+        }
         Ok(())
     }
 
+    #[instrument(skip_all)]
     fn partition(&self) -> Result<()> {
-        println!("\n[1/9] Partitioning disk...");
+        let layout = &self.config.partition_layout;
+        if layout.reuse_esp {
+            // Keep the other systems on the disk and what's in their ESP
+            let existing = disk::probe_existing_partitions(&self.config.device)?;
+            return disk::check_reusable_esp(&existing);
+        }
 
-        disk::wipe_device(&self.config.device)?;
+        // RAID members get the same layout; only the first disk's EFI
+        // partition is formatted and mounted
+        for device in self.config.disks() {
+            if layout.secure_erase {
+                let method = disk::EraseMethod::for_device(device);
+                self.log(format!(
+                    "Erasing {} by {}; this can take hours on large disks...",
+                    device.display(),
+                    method.describe()
+                ));
+                disk::erase_device(device, method)?;
+            }
 
-        let layout = PartitionLayout::default();
-        disk::create_partitions(&self.config.device, &layout)?;
+            disk::wipe_device(device)?;
+            disk::create_partitions(device, layout)?;
+        }
 
         let parts = disk::detect_partitions(&self.config.device)?;
-        disk::format_efi(&parts.efi)?;
+        disk::format_efi(&parts.efi, &layout.efi_label)?;
 
         Ok(())
     }
 
+    #[instrument(skip_all)]
     fn encrypt(&self) -> Result<()> {
-        println!("\n[2/9] Setting up encryption...");
+        if !self.config.encryption {
+            self.log("  Encryption is off; the root filesystem goes on the partition itself");
+            return Ok(());
+        }
 
-        let parts = disk::detect_partitions(&self.config.device)?;
-        let luks_config = LuksConfig::default();
+        let luks_config = LuksConfig {
+            header: self.config.luks_header.clone(),
+            ..Default::default()
+        };
 
-        format_luks(&parts.luks, &self.config.passphrase, &luks_config)?;
-        open_luks(&parts.luks, &self.luks_name, &self.config.passphrase)?;
+        // Each disk of a RAID root is its own container, with the same
+        // passphrase
+        for (i, device) in self.config.disks().enumerate() {
+            let parts = disk::detect_partitions(device)?;
+            format_luks(&parts.luks, self.config.passphrase.expose(), &luks_config)?;
+            open_luks(
+                &parts.luks,
+                &disk::member_name(&self.luks_name, i),
+                self.config.passphrase.expose(),
+                self.config.luks_header.as_deref(),
+            )?;
+        }
 
         Ok(())
     }
 
+    #[instrument(skip_all)]
     fn create_filesystems(&self) -> Result<()> {
-        println!("\n[3/9] Creating filesystems...");
+        let root_devices = self.root_devices()?;
+        let filesystem = self.config.filesystem.create();
 
-        let mapper_device = PathBuf::from(format!("/dev/mapper/{}", self.luks_name));
-        let layout = BtrfsLayout::default();
+        filesystem.format_devices(&root_devices, "mkos")?;
 
-        format_btrfs(&mapper_device, "mkos")?;
-        create_subvolumes(&mapper_device, &layout)?;
+        if filesystem.supports_subvolumes() {
+            let layout = &self.config.btrfs_layout;
+            filesystem.create_subvolumes(&root_devices[0], &layout.subvolumes)?;
+            if layout.quota {
+                crate::crypt::enable_quota(&root_devices[0], layout.snapshots_limit)?;
+            }
+        }
 
         Ok(())
     }
 
+    #[instrument(skip_all)]
     fn mount(&self) -> Result<()> {
-        println!("\n[4/9] Mounting filesystems...");
-
-        let mapper_device = PathBuf::from(format!("/dev/mapper/{}", self.luks_name));
-        let layout = BtrfsLayout::default();
+        let root_device = self.root_devices()?.remove(0);
+        let filesystem = self.config.filesystem.create();
         let parts = disk::detect_partitions(&self.config.device)?;
 
         std::fs::create_dir_all(&self.target)?;
-        mount_subvolumes(&mapper_device, &layout, &self.target)?;
+
+        if filesystem.supports_subvolumes() {
+            let layout = &self.config.btrfs_layout;
+            let options = MountOptions {
+                compress: Some(layout.compress.clone()),
+                ..Default::default()
+            };
+            filesystem.mount_subvolumes(
+                &root_device,
+                &layout.subvolumes,
+                &self.target,
+                &options,
+            )?;
+        } else {
+            filesystem.mount(&root_device, &self.target, &MountOptions::default())?;
+        }
 
         // Mount EFI partition
         let boot_dir = self.target.join("boot");
@@ -101,11 +299,48 @@ impl Installer {
         Ok(())
     }
 
+    #[instrument(skip_all)]
     fn bootstrap(&self) -> Result<()> {
-        println!("\n[5/9] Installing base system...");
+        let distro = self.config.create_distro()?;
+        distro.bootstrap(
+            &self.target,
+            self.config.kernel,
+            self.config.enable_networking,
+        )?;
+
+        // Each extra kernel gets its own UKI once the initramfs is built
+        if !self.config.extra_kernels.is_empty() {
+            let packages: Vec<&str> = self
+                .config
+                .extra_kernels
+                .iter()
+                .map(|k| k.package())
+                .collect();
+            distro.install_packages(&self.target, &packages)?;
+        }
 
-        let distro = self.config.distro.create();
-        distro.bootstrap(&self.target, self.config.enable_networking)?;
+        // Not every bootstrap brings the root filesystem's tools
+        distro.install_packages(&self.target, self.config.filesystem.packages())?;
+
+        // The bootstrap only brings dracut and efibootmgr
+        let boot_packages = self.config.boot.packages();
+        if !boot_packages.is_empty() {
+            self.log(format!(
+                "Installing the {} boot system...",
+                self.config.boot.name()
+            ));
+            distro.install_packages(&self.target, boot_packages)?;
+        }
+
+        // A local repository is only there while the install medium is
+        if let Some(mirror) = &self.config.selected_mirror {
+            if !mirror.url.starts_with("file://") {
+                crate::mirror::install_mirrorlist(&self.target, mirror)?;
+            }
+        }
+        if let Some(downloads) = self.config.pacman_parallel_downloads() {
+            crate::mirror::install_parallel_downloads(&self.target, downloads)?;
+        }
 
         // Install desktop base packages if enabled (seat manager, polkit, etc.)
         if self.config.desktop.enabled {
@@ -115,12 +350,15 @@ impl Installer {
                 .seat_manager
                 .as_deref()
                 .unwrap_or("seatd");
-            println!("Installing desktop session support ({})...", seat_manager);
+            self.log(format!(
+                "Installing desktop session support ({})...",
+                seat_manager
+            ));
             distro.install_desktop_base(&self.target, seat_manager)?;
 
             // Install display manager if specified
             if let Some(dm) = &self.config.desktop.display_manager {
-                println!("Installing display manager: {}...", dm);
+                self.log(format!("Installing display manager: {}...", dm));
                 let needs_pam_rundir = seat_manager != "elogind";
                 distro.install_display_manager(
                     &self.target,
@@ -141,7 +379,7 @@ impl Installer {
 
             // Install XDG desktop portals if enabled
             if self.config.desktop.portals {
-                println!("Installing XDG desktop portals...");
+                self.log("Installing XDG desktop portals...");
                 let backends: Vec<&str> = self
                     .config
                     .desktop
@@ -155,41 +393,56 @@ impl Installer {
 
         // Set up user-level services if enabled
         if self.config.desktop.user_services {
-            println!("Setting up user-level services...");
+            self.log("Setting up user-level services...");
             distro.init_system().setup_user_services(&self.target)?;
         }
 
         // Install audio (PipeWire) if enabled
         if self.config.audio.enabled {
-            println!("Installing audio support (pipewire)...");
+            self.log("Installing audio support (pipewire)...");
             crate::audio::setup_audio(&self.target, &self.config.audio, distro.as_ref())?;
         }
 
         // Set up network services (mDNS, SSH, ET)
         if crate::network::has_network_services(&self.config.network) {
-            println!("Setting up network services...");
+            self.log("Setting up network services...");
             crate::network::setup_network(&self.target, &self.config.network, distro.as_ref())?;
         }
 
         // Set up firewall (nftables)
         if self.config.firewall.enabled {
-            println!("Setting up firewall (nftables)...");
+            self.log("Setting up firewall (nftables)...");
             crate::firewall::setup_firewall(&self.target, &self.config.firewall, distro.as_ref())?;
         }
 
+        // Schedule snapshots from cron
+        if let Some(schedule) = &self.config.snapshots.schedule {
+            self.log("Scheduling snapshots...");
+            crate::snapshot_schedule::setup_snapshot_schedule(
+                &self.target,
+                schedule,
+                distro.as_ref(),
+            )?;
+        }
+
         // Install CPU microcode if enabled
         if self.config.microcode {
             use crate::util::detect_cpu_vendor;
             let vendor = detect_cpu_vendor();
             if let Some(pkg) = vendor.microcode_package() {
-                println!("Installing {} microcode updates...", vendor.name());
+                self.log(format!("Installing {} microcode updates...", vendor.name()));
                 distro.install_packages(&self.target, &[pkg])?;
             }
         }
 
+        if self.config.multilib {
+            self.log("Enabling 32-bit packages...");
+            distro.enable_multilib(&self.target)?;
+        }
+
         // Install extra packages (e.g., GPU drivers)
         if !self.config.extra_packages.is_empty() {
-            println!("Installing additional packages...");
+            self.log("Installing additional packages...");
             let pkg_refs: Vec<&str> = self
                 .config
                 .extra_packages
@@ -200,16 +453,32 @@ impl Installer {
         }
 
         // Generate fstab using distro-specific tool
-        let fstab_content = distro.generate_fstab(&self.target)?;
+        let mut fstab_content = distro.generate_fstab(&self.target)?;
+        if !self.config.raid_devices.is_empty() {
+            let devices: Vec<String> = self
+                .root_devices()?
+                .iter()
+                .map(|d| d.to_string_lossy().into_owned())
+                .collect();
+            fstab_content = chroot::with_btrfs_devices(&fstab_content, &devices);
+        }
         chroot::generate_fstab(&self.target, &fstab_content)?;
 
         // Generate crypttab with LUKS UUID
-        let parts = disk::detect_partitions(&self.config.device)?;
-        let luks_uuid = get_uuid(&parts.luks)?;
-        chroot::generate_crypttab(&self.target, &luks_uuid)?;
+        if self.config.encryption {
+            let parts = disk::detect_partitions(&self.config.device)?;
+            let luks_uuid = get_uuid(&parts.luks, self.config.luks_header.as_deref())?;
+            let luks_header = self.detached_header(&parts.luks)?;
+            chroot::generate_crypttab(
+                &self.target,
+                &luks_uuid,
+                luks_header.as_ref(),
+                &self.member_luks_uuids()?,
+            )?;
+        }
 
         // Install kernel hooks for automatic UKI rebuild on kernel upgrade
-        println!("Installing kernel hooks...");
+        self.log("Installing kernel hooks...");
         distro.install_kernel_hook(&self.target)?;
 
         // Set up chroot environment for subsequent steps
@@ -218,21 +487,28 @@ impl Installer {
         Ok(())
     }
 
+    #[instrument(skip_all)]
     fn configure(&self) -> Result<()> {
-        println!("\n[6/9] Configuring system...");
-
         let sys_config = SystemConfig {
             hostname: self.config.hostname.clone(),
             timezone: self.config.timezone.clone(),
             locale: self.config.locale.clone(),
+            locales: self.config.locales.clone(),
             keymap: self.config.keymap.clone(),
+            console_font: self.config.console_font.clone(),
         };
 
         chroot::configure_system(&self.target, &sys_config)?;
-        chroot::set_root_password(&self.target, &self.config.root_password)?;
+        if self.config.desktop.enabled {
+            let layout =
+                XkbLayout::for_keymap(&self.config.keymap, self.config.keymap_x11.as_deref());
+            keyboard::write_graphical_layout(&self.target, &layout)?;
+        }
+        chroot::set_root_password(&self.target, self.config.root_password.expose())?;
 
-        // Configure sudoers for wheel group
-        chroot::configure_sudoers(&self.target)?;
+        // Let wheel in through sudo or doas
+        let distro = self.config.create_distro()?;
+        crate::privilege::setup_privilege(&self.target, &self.config.security, distro.as_ref())?;
 
         // Configure nsswitch (with mDNS if enabled)
         chroot::configure_nsswitch(&self.target, self.config.network.mdns)?;
@@ -240,72 +516,351 @@ impl Installer {
         Ok(())
     }
 
+    /// Run on the live system before the disk is touched, with TARGET set to
+    /// where the new system will be mounted
+    #[instrument(skip_all)]
+    fn run_pre_install_scripts(&self) -> Result<()> {
+        let scripts = &self.config.scripts.pre_install;
+        if scripts.is_empty() {
+            return Ok(());
+        }
+
+        self.log("\nRunning pre-install scripts...");
+        let target = self.target.to_string_lossy();
+        for script in scripts {
+            self.log(format!(
+                "  Executing: {}...",
+                script.lines().next().unwrap_or("(script)")
+            ));
+            crate::cmd::run_with_env("sh", ["-c", script], &[("TARGET", &target)])?;
+        }
+
+        Ok(())
+    }
+
+    /// Run inside the configured target, the same way post_apply scripts run
+    /// on an installed system
+    #[instrument(skip_all)]
+    fn run_post_install_scripts(&self) -> Result<()> {
+        let scripts = &self.config.scripts.post_install;
+        if scripts.is_empty() {
+            return Ok(());
+        }
+
+        self.log("\nRunning post-install scripts...");
+        for script in scripts {
+            self.log(format!(
+                "  Executing: {}...",
+                script.lines().next().unwrap_or("(script)")
+            ));
+            chroot::run_script(&self.target, script)?;
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
     fn setup_swap(&self) -> Result<()> {
         if !self.config.swap.zram_enabled && !self.config.swap.swapfile_enabled {
             return Ok(());
         }
 
-        println!("\n[7/9] Setting up swap...");
-        crate::swap::setup_swap(&self.target, &self.config.swap)
+        crate::swap::setup_swap(&self.target, &self.config.swap, self.config.filesystem)
     }
 
-    fn setup_boot(&self) -> Result<()> {
-        println!("\n[8/9] Setting up boot (UKI)...");
+    /// Returns the main boot entry
+    #[instrument(skip_all)]
+    fn setup_boot(&self) -> Result<BootEntry> {
+        self.log(format!("  Boot system: {}", self.config.boot.name()));
 
         let parts = disk::detect_partitions(&self.config.device)?;
-        let luks_uuid = get_uuid(&parts.luks)?;
+        let filesystem = self.config.filesystem.create();
+
+        // Only subvolume-capable filesystems get a rootflags=subvol= parameter
+        let subvol = if filesystem.supports_subvolumes() {
+            "@"
+        } else {
+            ""
+        };
 
         let boot_config = BootConfig {
-            luks_uuid: luks_uuid.clone(),
-            root_device: format!("/dev/mapper/{}", self.luks_name),
-            subvol: "@".into(),
+            luks_uuid: self.luks_uuid(&parts.luks)?,
+            extra_luks_uuids: self.member_luks_uuids()?,
+            root_device: self.root_device_arg()?,
+            subvol: subvol.into(),
+            quiet: self.config.quiet_boot,
+            splash: self.config.splash_boot,
+            kms_drivers: self.kms_drivers(),
+            console_font: self.config.console_font.is_some(),
+            microcode: self
+                .config
+                .microcode
+                .then(|| crate::util::detect_cpu_vendor().microcode_image())
+                .flatten()
+                .map(String::from),
+            resume: self.resume_config()?,
+            luks_header: self.detached_header(&parts.luks)?,
         };
 
-        let boot_system = DracutEfistub::new();
+        let boot_system = self.config.boot.create_with_cmdline(
+            self.config.kernel_cmdline.clone(),
+            self.config.boot_kernels(),
+            self.config.boot_mode,
+        );
+
+        // plymouth has to be installed for dracut or mkinitcpio to pick it up
+        if self.config.splash_boot {
+            self.config
+                .create_distro()?
+                .install_packages(&self.target, &["plymouth"])?;
+        }
+
+        // Persist the user's cmdline so kernel upgrade hooks rebuild with it
+        self.write_cmdline_extras(boot_config.resume.as_ref())?;
 
-        // Generate dracut config, build initramfs, and build boot image
+        // Generate initramfs config, build initramfs, and build boot image
         boot_system.generate_initramfs_config(&self.target, &boot_config)?;
 
-        // Unmount /run before dracut to prevent host runtime state from
-        // contaminating the initramfs (dracut --hostonly reads /run)
+        // Unmount /run before building the initramfs to prevent host runtime
+        // state from contaminating it (dracut --hostonly reads /run)
         chroot::unmount_run(&self.target)?;
         boot_system.build_initramfs(&self.target)?;
 
-        // Build all 3 UKIs: main, rescue, fallback
-        let entry = boot_system.build_boot_image(&self.target, &boot_config)?;
-        let rescue_entry = boot_system.build_rescue_image(&self.target, &boot_config)?;
-        let fallback_entry =
-            boot_system.build_fallback_image(&self.target, &boot_config, "@snapshots/install")?;
+        // Build all UKIs: one per kernel, rescue (unless turned off), and
+        // fallback (only when the filesystem can snapshot, since fallback
+        // boots the install snapshot)
+        let kernel_entries = boot_system.build_boot_images(&self.target, &boot_config)?;
+        let entry = kernel_entries[0].clone();
+        let rescue_entry = if self.config.recovery_entry {
+            Some(boot_system.build_rescue_image(&self.target, &boot_config)?)
+        } else {
+            None
+        };
+        let fallback_entry = if filesystem.supports_snapshots() {
+            Some(boot_system.build_fallback_image(
+                &self.target,
+                &boot_config,
+                &format!("@snapshots/{}", INSTALL_SNAPSHOT),
+            )?)
+        } else {
+            None
+        };
 
         // Sign all UKIs if secure boot is enabled
         if self.config.secureboot.enabled {
-            for uki_entry in [&entry, &rescue_entry, &fallback_entry] {
-                let uki_name = uki_entry
-                    .loader_path
-                    .rsplit('/')
-                    .next()
-                    .unwrap_or("mkos.efi");
-                self.setup_secureboot(uki_name)?;
-            }
+            let entries: Vec<_> = kernel_entries
+                .iter()
+                .chain(rescue_entry.as_ref())
+                .chain(fallback_entry.as_ref())
+                .collect();
+            self.setup_secureboot(&entries, boot_system.efi_binaries())?;
         }
 
         // Create UEFI fallback startup script
         boot_system.create_fallback_scripts(&self.target, &entry)?;
 
-        // Create all 3 EFI boot entries
-        println!("  Creating boot entries...");
-        boot_system.create_boot_entry(&self.config.device, 1, &entry)?;
-        boot_system.create_boot_entry(&self.config.device, 1, &fallback_entry)?;
-        boot_system.create_boot_entry(&self.config.device, 1, &rescue_entry)?;
+        // Create EFI boot entries
+        if self.config.image.is_some() {
+            self.log("  Skipping boot entries (disk images have no NVRAM)");
+            self.log("  Firmware without an entry boots /startup.nsh from the EFI shell");
+        } else {
+            self.log("  Creating boot entries...");
+            // Each new entry boots first, so the newest kernel's is created last
+            if let Some(rescue_entry) = &rescue_entry {
+                boot_system.create_boot_entry(&self.config.device, 1, rescue_entry)?;
+            }
+            if let Some(fallback_entry) = &fallback_entry {
+                boot_system.create_boot_entry(&self.config.device, 1, fallback_entry)?;
+            }
+            for entry in kernel_entries.iter().rev() {
+                boot_system.create_boot_entry(&self.config.device, 1, entry)?;
+            }
+        }
 
         // Tear down chroot environment
         chroot::teardown_chroot(&self.target)?;
 
+        Ok(entry)
+    }
+
+    fn report(&self, boot_entry: &BootEntry) -> Result<InstallReport> {
+        let parts = disk::detect_partitions(&self.config.device)?;
+        let distro = self.config.create_distro()?;
+
+        Ok(InstallReport {
+            device: self.config.device.clone(),
+            distro: self.config.distro.to_string(),
+            init: distro.init_system().name().into(),
+            hostname: self.config.hostname.clone(),
+            efi_uuid: disk::filesystem_uuid(&parts.efi)?,
+            luks_uuid: self.luks_uuid(&parts.luks)?,
+            efi_partition: parts.efi,
+            luks_partition: parts.luks,
+            package_count: distro.package_manager().installed_count(&self.target),
+            enabled_services: distro.init_system().enabled_services(&self.target),
+            boot_entry: boot_entry.label.clone(),
+            snapshot: self
+                .config
+                .filesystem
+                .create()
+                .supports_snapshots()
+                .then(|| INSTALL_SNAPSHOT.to_string()),
+        })
+    }
+
+    /// Device the root filesystem is on for each disk, `device` first: the
+    /// unlocked LUKS device, or the partition itself without encryption
+    fn root_devices(&self) -> Result<Vec<PathBuf>> {
+        if !self.config.encryption {
+            return self
+                .config
+                .disks()
+                .map(|device| Ok(disk::detect_partitions(device)?.luks))
+                .collect();
+        }
+        Ok((0..self.config.disks().count())
+            .map(|i| {
+                PathBuf::from(format!(
+                    "/dev/mapper/{}",
+                    disk::member_name(&self.luks_name, i)
+                ))
+            })
+            .collect())
+    }
+
+    /// The root device as the kernel command line names it; an unencrypted
+    /// partition goes by its filesystem UUID, which survives renumbering
+    fn root_device_arg(&self) -> Result<String> {
+        if self.config.encryption {
+            return Ok(format!("/dev/mapper/{}", self.luks_name));
+        }
+        let parts = disk::detect_partitions(&self.config.device)?;
+        Ok(format!("UUID={}", disk::filesystem_uuid(&parts.luks)?))
+    }
+
+    /// LUKS UUID of the root partition, empty without encryption
+    fn luks_uuid(&self, partition: &Path) -> Result<String> {
+        if !self.config.encryption {
+            return Ok(String::new());
+        }
+        get_uuid(partition, self.config.luks_header.as_deref())
+    }
+
+    /// LUKS UUIDs of the RAID members besides `device`
+    fn member_luks_uuids(&self) -> Result<Vec<String>> {
+        self.config
+            .raid_devices
+            .iter()
+            .map(|device| get_uuid(&disk::detect_partitions(device)?.luks, None))
+            .collect()
+    }
+
+    /// Where the initramfs finds the detached LUKS header, if there is one
+    fn detached_header(&self, luks_partition: &Path) -> Result<Option<DetachedHeader>> {
+        self.config
+            .luks_header
+            .as_deref()
+            .map(|header| DetachedHeader::locate(header, luks_partition))
+            .transpose()
+    }
+
+    /// Resume from the swapfile, which sits on the root device
+    fn resume_config(&self) -> Result<Option<ResumeConfig>> {
+        if !self.config.swap.hibernation {
+            return Ok(None);
+        }
+
+        let offset = crate::swap::resume_offset(&self.target, self.config.filesystem)?;
+        Ok(Some(ResumeConfig {
+            device: self.root_device_arg()?,
+            offset: Some(offset),
+        }))
+    }
+
+    /// Early KMS drivers for this machine's GPUs; none for disk images,
+    /// which boot on other hardware
+    fn kms_drivers(&self) -> Vec<String> {
+        if self.config.image.is_some() {
+            return Vec::new();
+        }
+        let proprietary_nvidia = self.config.extra_packages.iter().any(|p| p == "nvidia");
+        crate::cli::gpu::kms_drivers(&crate::cli::gpu::detect_gpus(), proprietary_nvidia)
+    }
+
+    /// Write /etc/mkos/cmdline with `quiet` and extra arguments, and the boot
+    /// system and mode, for mkos-rebuild-uki
+    fn write_cmdline_extras(&self, resume: Option<&ResumeConfig>) -> Result<()> {
+        let mut args = Vec::new();
+        if self.config.quiet_boot || self.config.splash_boot {
+            args.push("quiet".to_string());
+        }
+        if self.config.splash_boot {
+            args.push("splash".to_string());
+        }
+        // The swapfile offset only changes if it's recreated, so upgrades reuse it
+        args.extend(resume.iter().flat_map(|r| r.cmdline_args()));
+        args.extend(self.config.kernel_cmdline.iter().cloned());
+
+        let mkos_dir = self.target.join("etc/mkos");
+        std::fs::create_dir_all(&mkos_dir)?;
+        std::fs::write(mkos_dir.join("cmdline"), format!("{}\n", args.join(" ")))?;
+        // Upgrades rebuild the same kind of image with the same backend
+        std::fs::write(
+            self.target.join(BOOT_SYSTEM_PATH),
+            format!("{}\n", self.config.boot.name()),
+        )?;
+        std::fs::write(
+            self.target.join(BOOT_MODE_PATH),
+            format!("{}\n", self.config.boot_mode.name()),
+        )?;
+
         Ok(())
     }
 
+    /// Check the installed system can boot, so nobody reboots into one
+    /// that can't
+    #[instrument(skip_all)]
+    fn verify_boot(&self, entry: &BootEntry) -> Result<()> {
+        let parts = disk::detect_partitions(&self.config.device)?;
+        let label = self.config.boot.create().nvram_label(entry);
+
+        let mut problems = Vec::new();
+        problems.extend(verify::check_boot_image(&self.target, entry));
+        if self.config.encryption {
+            let luks_source = match self.detached_header(&parts.luks)? {
+                Some(header) => header.data_device,
+                None => format!("UUID={}", self.luks_uuid(&parts.luks)?),
+            };
+            problems.extend(verify::check_crypttab(&self.target, &luks_source));
+        }
+        problems.extend(verify::check_fstab(&self.target, &verify::known_uuids()));
+        if self.config.image.is_none() {
+            problems.extend(verify::check_nvram(&verify::efibootmgr()?, &label));
+        }
+
+        if problems.is_empty() {
+            self.log("  Boot image, crypttab, fstab and boot entry check out");
+            return Ok(());
+        }
+        anyhow::bail!(
+            "The installed system may not boot:\n{}",
+            problems
+                .iter()
+                .map(|p| format!("  - {}", p))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )
+    }
+
+    #[instrument(skip_all)]
     fn create_snapshot(&self) -> Result<()> {
-        println!("\n[9/9] Creating initial snapshot...");
+        if !self.config.filesystem.create().supports_snapshots() {
+            self.log(format!(
+                "  Skipping snapshot ({} does not support snapshots)",
+                self.config.filesystem.name()
+            ));
+            return Ok(());
+        }
 
         use crate::crypt::snapshot;
         snapshot::create_install_snapshot(&self.target)?;
@@ -313,66 +868,80 @@ impl Installer {
         Ok(())
     }
 
-    fn setup_secureboot(&self, uki_name: &str) -> Result<()> {
-        use crate::uki::{enroll_keys, generate_keys, sign_efi_binary, KeyPair, SecureBootKeys};
+    #[instrument(skip_all)]
+    fn setup_secureboot(&self, entries: &[&BootEntry], efi_binaries: &[&str]) -> Result<()> {
+        use crate::uki::{
+            enroll_efivars, enroll_keys, generate_keys, is_setup_mode, load_keys, sign_efi_binary,
+        };
 
-        println!("  Setting up Secure Boot...");
+        self.log("  Setting up Secure Boot...");
 
-        let keys_dir = if let Some(ref keys_path) = self.config.secureboot.keys_path {
-            // Use existing keys
-            println!("    Using existing keys from: {}", keys_path.display());
-            keys_path.clone()
+        // Keys are generated (or loaded) once and shared by every UKI
+        let (keys_dir, keys) = if let Some(ref keys_path) = self.config.secureboot.keys_path {
+            self.log(format!(
+                "    Using existing keys from: {}",
+                keys_path.display()
+            ));
+            (keys_path.clone(), load_keys(keys_path))
         } else {
-            // Generate new keys
-            println!("    Generating new Secure Boot keys...");
+            self.log("    Generating new Secure Boot keys...");
             let keys_dir = self.target.join("root/.secureboot-keys");
-            let _keys = generate_keys(&keys_dir)?;
-            println!("    ✓ Keys generated in: {}", keys_dir.display());
-            println!(
-                "    IMPORTANT: Back up these keys! They are stored in /root/.secureboot-keys"
+            let keys = generate_keys(&keys_dir)?;
+            self.log(format!("    ✓ Keys generated in: {}", keys_dir.display()));
+            self.log(
+                "    IMPORTANT: Back up these keys! They are stored in /root/.secureboot-keys",
             );
-            keys_dir
-        };
-
-        // Load keys
-        let keys = SecureBootKeys {
-            pk: KeyPair {
-                key: keys_dir.join("PK.key").to_string_lossy().into(),
-                cert: keys_dir.join("PK.crt").to_string_lossy().into(),
-            },
-            kek: KeyPair {
-                key: keys_dir.join("KEK.key").to_string_lossy().into(),
-                cert: keys_dir.join("KEK.crt").to_string_lossy().into(),
-            },
-            db: KeyPair {
-                key: keys_dir.join("db.key").to_string_lossy().into(),
-                cert: keys_dir.join("db.crt").to_string_lossy().into(),
-            },
+            (keys_dir, keys)
         };
 
-        // Sign the UKI
-        let uki_path = self.target.join("boot").join(uki_name);
-        println!("    Signing UKI: {}", uki_name);
-        sign_efi_binary(&uki_path, &keys)?;
-        println!("    ✓ UKI signed");
+        // Entries may share an image (systemd-boot entries all use one
+        // kernel), so sign each file once
+        let mut signed = std::collections::HashSet::new();
+        for entry in entries {
+            let image_name = entry.loader_path.rsplit('/').next().unwrap_or("mkos.efi");
+            if !signed.insert(image_name) {
+                continue;
+            }
+            let image_path = self.target.join("boot").join(image_name);
+            self.log(format!("    Signing boot image: {}", image_name));
+            sign_efi_binary(&image_path, &keys)?;
+        }
+        for binary in efi_binaries {
+            self.log(format!("    Signing EFI binary: /{}", binary));
+            sign_efi_binary(&self.target.join("boot").join(binary), &keys)?;
+        }
+        self.log("    ✓ Boot images signed");
 
         // Copy enrollment keys to EFI partition
-        println!("    Copying enrollment keys to EFI partition...");
+        self.log("    Copying enrollment keys to EFI partition...");
         let efi_mount = self.target.join("boot");
         enroll_keys(&efi_mount, &keys_dir)?;
-        println!("    ✓ Enrollment keys copied to /boot/keys/");
-
-        println!("  ✓ Secure Boot configured");
-        println!();
-        println!("  ==> Next Steps for Secure Boot:");
-        println!("      1. Reboot into UEFI/BIOS setup");
-        println!("      2. Enable Secure Boot and enter Setup Mode");
-        println!("      3. Enroll keys from /boot/keys/ in this order:");
-        println!("         - db.auth (Signature Database)");
-        println!("         - KEK.auth (Key Exchange Key)");
-        println!("         - PK.auth (Platform Key) - MUST BE LAST!");
-        println!("      4. Save and exit UEFI setup");
-        println!();
+        self.log("    ✓ Enrollment keys copied to /boot/keys/");
+
+        if self.config.secureboot.enroll && is_setup_mode() {
+            self.log("    Firmware is in Setup Mode, enrolling keys...");
+            enroll_efivars(&keys_dir)?;
+            self.log("  ✓ Secure Boot configured and keys enrolled");
+            self.log("      Enable Secure Boot in UEFI setup after rebooting.");
+            self.log("");
+            return Ok(());
+        }
+
+        if self.config.secureboot.enroll {
+            self.log("    Firmware is not in Setup Mode, skipping automatic enrollment");
+        }
+
+        self.log("  ✓ Secure Boot configured");
+        self.log("");
+        self.log("  ==> Next Steps for Secure Boot:");
+        self.log("      1. Reboot into UEFI/BIOS setup");
+        self.log("      2. Enable Secure Boot and enter Setup Mode");
+        self.log("      3. Enroll keys from /boot/keys/ in this order:");
+        self.log("         - db.auth (Signature Database)");
+        self.log("         - KEK.auth (Key Exchange Key)");
+        self.log("         - PK.auth (Platform Key) - MUST BE LAST!");
+        self.log("      4. Save and exit UEFI setup");
+        self.log("");
 
         Ok(())
     }
@@ -443,3 +1012,107 @@ fn build_greeter_command(greeter: Option<&str>, config: &GreetdConfig) -> String
         _ => "agreety --cmd /bin/sh".to_string(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Records every callback as a line
+    #[derive(Clone, Default)]
+    struct RecordingObserver(Arc<Mutex<Vec<String>>>);
+
+    impl InstallObserver for RecordingObserver {
+        fn on_step_start(&self, step: InstallStep) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("start {}", step.number()));
+        }
+
+        fn on_log(&self, line: &str) {
+            self.0.lock().unwrap().push(format!("log {}", line));
+        }
+
+        fn on_step_end(&self, step: InstallStep, result: &Result<()>) {
+            let outcome = match result {
+                Ok(()) => "ok".to_string(),
+                Err(e) => format!("failed: {:#}", e),
+            };
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("end {} {}", step.number(), outcome));
+        }
+    }
+
+    fn installer(observer: &RecordingObserver) -> Installer {
+        Installer::new(InstallConfig::default()).with_observer(Box::new(observer.clone()))
+    }
+
+    #[test]
+    fn ten_steps_fire_in_order() {
+        let observer = RecordingObserver::default();
+        let mut ran = Vec::new();
+        installer(&observer)
+            .run_steps(|step| {
+                ran.push(step);
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(ran, InstallStep::ALL);
+        let expected: Vec<String> = (1..=10)
+            .flat_map(|n| [format!("start {}", n), format!("end {} ok", n)])
+            .collect();
+        assert_eq!(*observer.0.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn a_failing_step_ends_the_install() {
+        let observer = RecordingObserver::default();
+        let installer = installer(&observer);
+        let result = installer.run_steps(|step| {
+            installer.log(step.description());
+            match step {
+                InstallStep::Encrypt => anyhow::bail!("cryptsetup failed"),
+                _ => Ok(()),
+            }
+        });
+
+        assert!(result.is_err());
+        assert_eq!(
+            *observer.0.lock().unwrap(),
+            [
+                "start 1",
+                "log Partitioning disk",
+                "end 1 ok",
+                "start 2",
+                "log Setting up encryption",
+                "end 2 failed: cryptsetup failed"
+            ]
+        );
+    }
+
+    #[test]
+    fn step_errors_never_show_secrets() {
+        let observer = RecordingObserver::default();
+        let config = InstallConfig {
+            passphrase: "correct horse battery".into(),
+            root_password: "hunter22".into(),
+            ..Default::default()
+        };
+        let installer = Installer::new(config).with_observer(Box::new(observer.clone()));
+        let result = installer
+            .run_steps(|_| anyhow::bail!("chpasswd rejected hunter22 after correct horse battery"));
+
+        let error = format!("{:#}", result.unwrap_err());
+        assert_eq!(error, "chpasswd rejected *** after ***");
+        let events = observer.0.lock().unwrap();
+        assert_eq!(
+            events.last().unwrap(),
+            "end 1 failed: chpasswd rejected *** after ***"
+        );
+        assert!(!format!("{:?}", installer.config).contains("hunter22"));
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::Result;
+use std::fmt;
+
+/// The steps of an install, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallStep {
+    Partition,
+    Encrypt,
+    CreateFilesystems,
+    Mount,
+    Bootstrap,
+    Configure,
+    Swap,
+    Boot,
+    Snapshot,
+    Verify,
+}
+
+impl InstallStep {
+    pub const ALL: [InstallStep; 10] = [
+        Self::Partition,
+        Self::Encrypt,
+        Self::CreateFilesystems,
+        Self::Mount,
+        Self::Bootstrap,
+        Self::Configure,
+        Self::Swap,
+        Self::Boot,
+        Self::Snapshot,
+        Self::Verify,
+    ];
+
+    /// Position of the step, counting from 1
+    pub fn number(self) -> usize {
+        Self::ALL.iter().position(|&s| s == self).unwrap_or(0) + 1
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Partition => "Partitioning disk",
+            Self::Encrypt => "Setting up encryption",
+            Self::CreateFilesystems => "Creating filesystems",
+            Self::Mount => "Mounting filesystems",
+            Self::Bootstrap => "Installing base system",
+            Self::Configure => "Configuring system",
+            Self::Swap => "Setting up swap",
+            Self::Boot => "Setting up boot",
+            Self::Snapshot => "Creating initial snapshot",
+            Self::Verify => "Verifying boot setup",
+        }
+    }
+}
+
+impl fmt::Display for InstallStep {
+    /// `[1/10] Partitioning disk`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}/{}] {}",
+            self.number(),
+            Self::ALL.len(),
+            self.description()
+        )
+    }
+}
+
+/// Receives an install's progress, so front-ends decide how to show it
+pub trait InstallObserver: Send {
+    fn on_step_start(&self, step: InstallStep);
+
+    /// A line of progress output
+    fn on_log(&self, line: &str);
+
+    fn on_step_end(&self, step: InstallStep, result: &Result<()>);
+}
+
+/// Prints progress to stdout, for the command-line installer
+pub struct StdoutObserver;
+
+impl InstallObserver for StdoutObserver {
+    fn on_step_start(&self, step: InstallStep) {
+        println!("\n{}...", step);
+    }
+
+    fn on_log(&self, line: &str) {
+        println!("{}", line);
+    }
+
+    fn on_step_end(&self, _step: InstallStep, _result: &Result<()>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_are_numbered_in_order() {
+        assert_eq!(
+            InstallStep::Partition.to_string(),
+            "[1/10] Partitioning disk"
+        );
+        assert_eq!(
+            InstallStep::Snapshot.to_string(),
+            "[9/10] Creating initial snapshot"
+        );
+        assert_eq!(
+            InstallStep::Verify.to_string(),
+            "[10/10] Verifying boot setup"
+        );
+    }
+}
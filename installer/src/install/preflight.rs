@@ -0,0 +1,598 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::space::{required_bytes, space_summary};
+use super::InstallConfig;
+use crate::boot::{BootKind, BootMode, Kernel};
+use crate::crypt::FilesystemKind;
+use crate::disk::{self, ExistingPartitions};
+use crate::distro::DistroKind;
+use crate::hooks::{MKOS, REBUILD_UKI};
+use crate::mirror::{url_host_port, Repo};
+
+/// Tools the installer runs on the live system, regardless of configuration
+const COMMON_BINARIES: &[&str] = &[
+    "wipefs",
+    "parted",
+    "mkfs.fat",
+    "cryptsetup",
+    "lsblk",
+    "blkid",
+    "findmnt",
+    "mount",
+    "umount",
+    "chroot",
+    "efibootmgr",
+];
+
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Deep enough for a mirror's `<repo>/os/<arch>` layout
+const LOCAL_REPO_DEPTH: usize = 4;
+
+/// The parts of the live system the offline checks look at
+pub trait LiveSystem {
+    /// Booted through UEFI firmware
+    fn uefi(&self) -> bool;
+
+    fn has_command(&self, name: &str) -> bool;
+
+    /// `name` ships next to the installer, to be copied into the target
+    fn has_companion(&self, name: &str) -> bool;
+
+    /// Size in bytes of `device`, once it's known to be a whole disk
+    fn disk_size(&self, device: &Path) -> Result<u64>;
+
+    /// What's on the partitions disk.reuse_esp keeps and takes
+    fn existing_partitions(&self, device: &Path) -> Result<ExistingPartitions>;
+}
+
+/// The system the installer is running on
+pub struct Host;
+
+impl LiveSystem for Host {
+    fn uefi(&self) -> bool {
+        Path::new("/sys/firmware/efi").exists()
+    }
+
+    fn has_command(&self, name: &str) -> bool {
+        which::which(name).is_ok()
+    }
+
+    fn has_companion(&self, name: &str) -> bool {
+        crate::hooks::companion_binary(name).is_some()
+    }
+
+    fn disk_size(&self, device: &Path) -> Result<u64> {
+        crate::disk::validate_device(device)?;
+
+        // The kernel counts 512-byte sectors whatever the disk's own sector size
+        let name = device
+            .canonicalize()
+            .ok()
+            .and_then(|path| path.file_name().map(PathBuf::from))
+            .with_context(|| format!("Can't resolve {}", device.display()))?;
+        let sectors = Path::new("/sys/class/block").join(name).join("size");
+        let sectors: u64 = fs::read_to_string(&sectors)
+            .with_context(|| format!("Failed to read {}", sectors.display()))?
+            .trim()
+            .parse()
+            .with_context(|| format!("Unexpected size in {}", sectors.display()))?;
+        Ok(sectors * 512)
+    }
+
+    fn existing_partitions(&self, device: &Path) -> Result<ExistingPartitions> {
+        disk::probe_existing_partitions(device)
+    }
+}
+
+/// Check the live environment before touching the disk
+///
+/// Every check runs so all problems are reported together.
+pub fn preflight(config: &InstallConfig) -> Result<()> {
+    check_all(preflight_failures(config))
+}
+
+/// Fail with every problem in `failures` listed, if there are any
+pub fn check_all(failures: Vec<String>) -> Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    let list: Vec<String> = failures.iter().map(|f| format!("  - {}", f)).collect();
+    anyhow::bail!("Preflight checks failed:\n{}", list.join("\n"))
+}
+
+/// Every failed preflight check, empty when the system is ready
+pub fn preflight_failures(config: &InstallConfig) -> Vec<String> {
+    let mut failures = preflight_offline(config, &Host);
+    failures.extend(preflight_network(config));
+    failures
+}
+
+/// Failed checks that need no network: firmware, commands, a local
+/// repository, and the disks being whole disks big enough for the install
+///
+/// Disks are only checked once one is chosen, so an empty `config.device`
+/// skips them.
+pub fn preflight_offline(config: &InstallConfig, system: &dyn LiveSystem) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    // Images boot elsewhere, so the host's firmware doesn't matter
+    if config.image.is_none() && !system.uefi() {
+        failures.push("Not booted in UEFI mode (/sys/firmware/efi is missing)".to_string());
+    }
+
+    let required = required_binaries(config);
+    for binary in missing_binaries(&required, |b| system.has_command(b)) {
+        failures.push(format!("Required command not found: {}", binary));
+    }
+
+    // The kernel hooks run it on the installed system
+    if !system.has_companion(REBUILD_UKI) {
+        failures.push(format!(
+            "{} not found next to the installer; download it with mkos-install",
+            REBUILD_UKI
+        ));
+    }
+    // Scheduled snapshots run it on the installed system
+    if !config
+        .snapshots
+        .schedule
+        .as_ref()
+        .is_none_or(|s| s.is_empty())
+        && !system.has_companion(MKOS)
+    {
+        failures.push(format!(
+            "{} not found next to the installer; snapshots.schedule needs it",
+            MKOS
+        ));
+    }
+
+    match Repo::from_config(&config.mirror) {
+        Ok(Some(Repo::Local(path))) => {
+            failures.extend(local_repo_failures(&path, config.distro, config.kernel))
+        }
+        Ok(_) => {}
+        Err(e) => failures.push(e.to_string()),
+    }
+
+    // An image's loop device is as big as was asked for
+    if config.image.is_none() && !config.device.as_os_str().is_empty() {
+        let needed = required_bytes(config);
+        for device in config.disks() {
+            match system.disk_size(device) {
+                Err(e) => failures.push(format!("{:#}", e)),
+                // Kept partitions take space that can't be counted here
+                Ok(_) if config.partition_layout.reuse_esp => {}
+                Ok(size) if size < needed => failures.push(format!(
+                    "{} is too small: {}",
+                    device.display(),
+                    space_summary(needed, size)
+                )),
+                Ok(_) => {}
+            }
+        }
+
+        // Refuse before anything is formatted, rather than once the ESP fills
+        if config.partition_layout.reuse_esp {
+            if let Err(e) = system
+                .existing_partitions(&config.device)
+                .and_then(|existing| disk::check_reusable_esp(&existing))
+            {
+                failures.push(format!("{:#}", e));
+            }
+        }
+    }
+
+    failures
+}
+
+/// Failed checks that need the network: reaching the package repository,
+/// unless it's a local one
+pub fn preflight_network(config: &InstallConfig) -> Vec<String> {
+    let repo = match Repo::from_config(&config.mirror) {
+        Ok(Some(Repo::Local(_))) | Err(_) => return Vec::new(),
+        Ok(Some(repo)) => repo.url(),
+        Ok(None) => config.distro.create().repo_url().to_string(),
+    };
+
+    match check_reachable(&repo) {
+        Ok(()) => Vec::new(),
+        Err(e) => vec![format!("Cannot reach {}: {}", repo, e)],
+    }
+}
+
+/// Host binaries needed for this configuration
+///
+/// dracut and the kernel tooling run inside the chroot once bootstrapped, so
+/// only what runs on the live system is listed.
+fn required_binaries(config: &InstallConfig) -> Vec<&'static str> {
+    let mut binaries = COMMON_BINARIES.to_vec();
+
+    if config.image.is_some() {
+        binaries.retain(|b| *b != "efibootmgr");
+        binaries.push("losetup");
+    }
+    if !config.encryption {
+        binaries.retain(|b| *b != "cryptsetup");
+    }
+    if config.partition_layout.secure_erase {
+        binaries.extend(["blockdev", "blkdiscard", "dd"]);
+    }
+
+    binaries.push(match config.filesystem {
+        FilesystemKind::Btrfs => "mkfs.btrfs",
+        FilesystemKind::Ext4 => "mkfs.ext4",
+        FilesystemKind::Xfs => "mkfs.xfs",
+    });
+
+    binaries.extend(match config.distro {
+        DistroKind::Artix => &["basestrap", "fstabgen"][..],
+        DistroKind::Void => &["xbps-install"],
+        DistroKind::Slackware => &["slapt-get"],
+        DistroKind::Alpine => &["apk"],
+        DistroKind::Gentoo => &["curl", "tar"],
+        DistroKind::Devuan => &["apt-get"],
+        DistroKind::OpenSuse => &["zypper"],
+    });
+
+    // ukify assembles the UKI on the host from the target's kernel and initramfs
+    if config.boot == BootKind::DracutEfistub && config.boot_mode == BootMode::Uki {
+        binaries.push("ukify");
+    }
+
+    if config.secureboot.enabled {
+        binaries.push("sbsign");
+        // Keys are enrolled from the live system while it's in Setup Mode
+        if config.secureboot.enroll {
+            binaries.push("efi-updatevar");
+        }
+    }
+
+    binaries
+}
+
+/// Problems with a local repository for an offline install
+fn local_repo_failures(path: &Path, distro: DistroKind, kernel: Option<Kernel>) -> Vec<String> {
+    if !path.is_dir() {
+        return vec![format!(
+            "Local repository {} is not a directory",
+            path.display()
+        )];
+    }
+
+    let mut files = Vec::new();
+    collect_file_names(path, LOCAL_REPO_DEPTH, &mut files);
+
+    let extensions = package_extensions(distro);
+    base_packages(distro, kernel)
+        .iter()
+        .filter(|pkg| !has_package(&files, pkg, extensions))
+        .map(|pkg| format!("Local repository {} has no {} package", path.display(), pkg))
+        .collect()
+}
+
+/// Packages the bootstrap can't do without
+fn base_packages(distro: DistroKind, kernel: Option<Kernel>) -> Vec<&'static str> {
+    match distro {
+        DistroKind::Artix => vec![
+            "base",
+            kernel.map_or("linux", Kernel::name),
+            "linux-firmware",
+        ],
+        DistroKind::Void => vec!["base-system"],
+        DistroKind::Slackware => vec!["aaa_base"],
+        DistroKind::Alpine => vec!["alpine-base"],
+        DistroKind::Gentoo => vec!["gentoo-kernel-bin"],
+        DistroKind::Devuan => vec!["linux-image-amd64"],
+        DistroKind::OpenSuse => vec!["patterns-base-base"],
+    }
+}
+
+fn package_extensions(distro: DistroKind) -> &'static [&'static str] {
+    match distro {
+        DistroKind::Artix => &[".pkg.tar.zst", ".pkg.tar.xz"],
+        DistroKind::Void => &[".xbps"],
+        DistroKind::Slackware => &[".txz", ".tgz"],
+        DistroKind::Alpine => &[".apk"],
+        DistroKind::Gentoo => &[".gpkg.tar", ".tbz2"],
+        DistroKind::Devuan => &[".deb"],
+        DistroKind::OpenSuse => &[".rpm"],
+    }
+}
+
+fn collect_file_names(dir: &Path, depth: usize, names: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if depth > 1 {
+                collect_file_names(&path, depth - 1, names);
+            }
+        } else {
+            names.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+}
+
+/// Whether `files` holds a build of `package`: its name, a `-` or `_`, then
+/// a version, so `linux` isn't satisfied by `linux-firmware`
+fn has_package(files: &[String], package: &str, extensions: &[&str]) -> bool {
+    files.iter().any(|file| {
+        extensions.iter().any(|ext| file.ends_with(ext))
+            && file
+                .strip_prefix(package)
+                .and_then(|rest| rest.strip_prefix(['-', '_']))
+                .is_some_and(|version| version.starts_with(|c: char| c.is_ascii_digit()))
+    })
+}
+
+fn missing_binaries<'a>(required: &[&'a str], exists: impl Fn(&str) -> bool) -> Vec<&'a str> {
+    required.iter().copied().filter(|b| !exists(b)).collect()
+}
+
+/// Open a TCP connection to the host of `url`
+fn check_reachable(url: &str) -> Result<()> {
+    let (host, port) =
+        url_host_port(url).ok_or_else(|| anyhow::anyhow!("could not parse host from URL"))?;
+
+    let addrs: Vec<_> = (host.as_str(), port).to_socket_addrs()?.collect();
+    let mut last_err = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, NETWORK_TIMEOUT) {
+            Ok(_) => return Ok(()),
+            Err(e) => last_err = Some(e),
+        }
+    }
+
+    match last_err {
+        Some(e) => Err(e.into()),
+        None => anyhow::bail!("{} did not resolve", host),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_binaries_reports_each_absent_tool() {
+        let required = ["cryptsetup", "parted", "ukify", "basestrap"];
+        let installed = ["cryptsetup", "basestrap"];
+
+        let missing = missing_binaries(&required, |b| installed.contains(&b));
+        assert_eq!(missing, ["parted", "ukify"]);
+        assert!(missing_binaries(&required, |_| true).is_empty());
+    }
+
+    #[test]
+    fn required_binaries_follow_config() {
+        let mut config = InstallConfig {
+            distro: DistroKind::Gentoo,
+            filesystem: FilesystemKind::Xfs,
+            boot: BootKind::MkinitcpioSystemdBoot,
+            ..Default::default()
+        };
+
+        let binaries = required_binaries(&config);
+        assert!(binaries.contains(&"cryptsetup"));
+        assert!(binaries.contains(&"mkfs.xfs"));
+        assert!(binaries.contains(&"curl"));
+        assert!(!binaries.contains(&"mkfs.btrfs"));
+        assert!(!binaries.contains(&"ukify"));
+        assert!(!binaries.contains(&"sbsign"));
+
+        config.distro = DistroKind::Artix;
+        config.boot = BootKind::DracutEfistub;
+        config.secureboot.enabled = true;
+        let binaries = required_binaries(&config);
+        assert!(binaries.contains(&"basestrap"));
+        assert!(binaries.contains(&"ukify"));
+        assert!(binaries.contains(&"sbsign"));
+        assert!(!binaries.contains(&"efi-updatevar"));
+
+        config.secureboot.enroll = true;
+        assert!(required_binaries(&config).contains(&"efi-updatevar"));
+
+        config.secureboot.enabled = false;
+        config.boot_mode = BootMode::Efistub;
+        assert!(!required_binaries(&config).contains(&"ukify"));
+    }
+
+    /// A live system with every command and companion binary but `missing`,
+    /// booted as `uefi` says, whose disks are the sizes given in GB
+    struct FakeSystem {
+        uefi: bool,
+        missing: Vec<&'static str>,
+        disks: Vec<(&'static str, u64)>,
+        existing: ExistingPartitions,
+    }
+
+    impl LiveSystem for FakeSystem {
+        fn uefi(&self) -> bool {
+            self.uefi
+        }
+
+        fn has_command(&self, name: &str) -> bool {
+            !self.missing.contains(&name)
+        }
+
+        fn has_companion(&self, name: &str) -> bool {
+            !self.missing.contains(&name)
+        }
+
+        fn disk_size(&self, device: &Path) -> Result<u64> {
+            self.disks
+                .iter()
+                .find(|(path, _)| Path::new(path) == device)
+                .map(|(_, gb)| gb * 1_000_000_000)
+                .ok_or_else(|| anyhow::anyhow!("Device {} does not exist", device.display()))
+        }
+
+        fn existing_partitions(&self, _device: &Path) -> Result<ExistingPartitions> {
+            Ok(self.existing.clone())
+        }
+    }
+
+    fn ready() -> FakeSystem {
+        FakeSystem {
+            uefi: true,
+            missing: Vec::new(),
+            disks: vec![("/dev/sda", 256), ("/dev/sdb", 4)],
+            existing: ExistingPartitions::default(),
+        }
+    }
+
+    fn on(device: &str) -> InstallConfig {
+        InstallConfig {
+            device: device.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn offline_checks_pass_on_a_ready_system() {
+        assert!(preflight_offline(&on("/dev/sda"), &ready()).is_empty());
+        // No disk chosen yet
+        assert!(preflight_offline(&on(""), &ready()).is_empty());
+    }
+
+    #[test]
+    fn offline_checks_report_every_problem() {
+        let system = FakeSystem {
+            uefi: false,
+            missing: vec!["parted", "basestrap", "mkos-rebuild-uki"],
+            ..ready()
+        };
+        let failures = preflight_offline(&on("/dev/nvme0n1"), &system);
+        assert_eq!(
+            failures,
+            [
+                "Not booted in UEFI mode (/sys/firmware/efi is missing)",
+                "Required command not found: parted",
+                "Required command not found: basestrap",
+                "mkos-rebuild-uki not found next to the installer; download it with mkos-install",
+                "Device /dev/nvme0n1 does not exist",
+            ]
+        );
+    }
+
+    #[test]
+    fn scheduled_snapshots_need_mkos() {
+        let system = FakeSystem {
+            missing: vec!["mkos"],
+            ..ready()
+        };
+        let mut config = on("/dev/sda");
+        assert!(preflight_offline(&config, &system).is_empty());
+
+        config.snapshots.schedule = Some(crate::manifest::SnapshotSchedule {
+            hourly: 24,
+            daily: 7,
+        });
+        assert_eq!(
+            preflight_offline(&config, &system),
+            ["mkos not found next to the installer; snapshots.schedule needs it"]
+        );
+    }
+
+    #[test]
+    fn offline_checks_estimate_disk_space() {
+        let failures = preflight_offline(&on("/dev/sdb"), &ready());
+        assert_eq!(
+            failures,
+            ["/dev/sdb is too small: ~5.0 GB required, 4.0 GB available"]
+        );
+
+        let mut config = on("/dev/sda");
+        config.raid_devices = vec!["/dev/sdb".into()];
+        assert_eq!(preflight_offline(&config, &ready()).len(), 1);
+
+        config.raid_devices.clear();
+        config.swap.swapfile_enabled = true;
+        assert!(preflight_offline(&config, &ready()).is_empty());
+
+        // Images are checked against the host's tools only
+        let mut image = on("/dev/sdb");
+        image.image = Some("/tmp/mkos.img".into());
+        let system = FakeSystem {
+            uefi: false,
+            ..ready()
+        };
+        assert!(preflight_offline(&image, &system).is_empty());
+    }
+
+    #[test]
+    fn reused_esp_is_checked_before_partitioning() {
+        let mut config = on("/dev/sda");
+        config.partition_layout.reuse_esp = true;
+        // A Windows disk with its 100 MB ESP and reserved partition
+        let system = FakeSystem {
+            existing: ExistingPartitions {
+                efi_fstype: Some("vfat".into()),
+                efi_size_mb: 100,
+                efi_free_mb: 70,
+                mkos_exists: true,
+                mkos_parttype: Some("E3C9E316-0B5C-4DB8-817D-F92DF00215AE".into()),
+                mkos_fstype: None,
+            },
+            ..ready()
+        };
+        let failures = preflight_offline(&config, &system);
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].contains("EFI partition is 100 MB"));
+    }
+
+    #[test]
+    fn package_files_match_name_and_version() {
+        let files = [
+            "linux-firmware-20240909.552ed9b-1-any.pkg.tar.zst".to_string(),
+            "base-3-2-any.pkg.tar.zst".to_string(),
+            "base-3-2-any.pkg.tar.zst.sig".to_string(),
+        ];
+        let ext = package_extensions(DistroKind::Artix);
+
+        assert!(has_package(&files, "base", ext));
+        assert!(has_package(&files, "linux-firmware", ext));
+        assert!(!has_package(&files, "linux", ext));
+        assert!(!has_package(&files, "base", &[".xbps"]));
+    }
+
+    #[test]
+    fn local_repo_reports_missing_base_packages() {
+        let repo = tempfile::tempdir().unwrap();
+        let os = repo.path().join("current");
+        fs::create_dir_all(&os).unwrap();
+
+        assert_eq!(
+            local_repo_failures(repo.path(), DistroKind::Void, None),
+            [format!(
+                "Local repository {} has no base-system package",
+                repo.path().display()
+            )]
+        );
+
+        fs::write(os.join("base-system-0.114_2.x86_64.xbps"), "").unwrap();
+        assert!(local_repo_failures(repo.path(), DistroKind::Void, None).is_empty());
+
+        let missing = repo.path().join("missing");
+        assert!(
+            local_repo_failures(&missing, DistroKind::Void, None)[0].contains("not a directory")
+        );
+    }
+
+    #[test]
+    fn local_repo_needs_the_chosen_kernel() {
+        assert!(base_packages(DistroKind::Artix, None).contains(&"linux"));
+        let packages = base_packages(DistroKind::Artix, Some(Kernel::Lts));
+        assert!(packages.contains(&"linux-lts"));
+        assert!(!packages.contains(&"linux"));
+    }
+}
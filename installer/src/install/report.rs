@@ -0,0 +1,127 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::init::InitKind;
+
+/// Where the installed system keeps the report of its install
+pub const REPORT_PATH: &str = "etc/mkos/install-report.json";
+
+/// What an install built, for provisioning systems to record
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct InstallReport {
+    pub device: PathBuf,
+    pub distro: String,
+    pub init: String,
+    pub hostname: String,
+    pub efi_partition: PathBuf,
+    pub efi_uuid: String,
+    pub luks_partition: PathBuf,
+    /// Empty when the install isn't encrypted
+    pub luks_uuid: String,
+    /// Unset when the package database couldn't be read
+    pub package_count: Option<usize>,
+    pub enabled_services: Vec<String>,
+    pub boot_entry: String,
+    /// Unset when the filesystem can't snapshot
+    pub snapshot: Option<String>,
+}
+
+impl InstallReport {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Write the report as JSON to `path`, or to stdout when it's `-`
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = self.to_json()?;
+        if path == Path::new("-") {
+            println!("{}", json);
+            return Ok(());
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, format!("{}\n", json))
+            .with_context(|| format!("Failed to write install report: {}", path.display()))
+    }
+}
+
+/// Init system recorded in the install report of the system at `root`;
+/// None when it has no report
+pub fn recorded_init(root: &Path) -> Result<Option<InitKind>> {
+    let Ok(json) = fs::read_to_string(root.join(REPORT_PATH)) else {
+        return Ok(None);
+    };
+    let report: serde_json::Value =
+        serde_json::from_str(&json).context("Failed to parse the install report")?;
+    report["init"].as_str().map(str::parse).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_serializes_every_field() {
+        let report = InstallReport {
+            device: "/dev/nvme0n1".into(),
+            distro: "artix".into(),
+            init: "s6".into(),
+            hostname: "workstation".into(),
+            efi_partition: "/dev/nvme0n1p1".into(),
+            efi_uuid: "1234-ABCD".into(),
+            luks_partition: "/dev/nvme0n1p2".into(),
+            luks_uuid: "0b7c2a9e-6f1d-4c1e-9a53-2f4f0f1c8d11".into(),
+            package_count: Some(412),
+            enabled_services: vec!["dbus".into(), "sshd".into()],
+            boot_entry: "mkOS".into(),
+            snapshot: Some("install".into()),
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "device": "/dev/nvme0n1",
+                "distro": "artix",
+                "init": "s6",
+                "hostname": "workstation",
+                "efi_partition": "/dev/nvme0n1p1",
+                "efi_uuid": "1234-ABCD",
+                "luks_partition": "/dev/nvme0n1p2",
+                "luks_uuid": "0b7c2a9e-6f1d-4c1e-9a53-2f4f0f1c8d11",
+                "package_count": 412,
+                "enabled_services": ["dbus", "sshd"],
+                "boot_entry": "mkOS",
+                "snapshot": "install"
+            })
+        );
+    }
+
+    #[test]
+    fn report_is_written_to_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("etc/mkos/install-report.json");
+        InstallReport::default().write(&path).unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["package_count"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn recorded_init_reads_the_report() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(recorded_init(dir.path()).unwrap(), None);
+
+        let report = InstallReport {
+            init: "dinit".into(),
+            ..Default::default()
+        };
+        report.write(&dir.path().join(REPORT_PATH)).unwrap();
+        assert_eq!(recorded_init(dir.path()).unwrap(), Some(InitKind::Dinit));
+    }
+}
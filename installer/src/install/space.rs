@@ -0,0 +1,78 @@
+//! Rough disk space an install takes, checked before the disk is wiped
+
+use super::InstallConfig;
+
+const GB: u64 = 1_000_000_000;
+const MB: u64 = 1_000_000;
+
+/// Floor for an installed base system, with room for updates
+const BASE_SYSTEM_BYTES: u64 = 4 * GB;
+
+/// What a graphical session adds on top
+const DESKTOP_BYTES: u64 = 2 * GB;
+
+/// Average installed size of each extra package, dependencies included
+const PACKAGE_BYTES: u64 = 50 * MB;
+
+/// Swapfile size when the config leaves it to the installer
+const DEFAULT_SWAPFILE_GB: u64 = 8;
+
+/// Estimated bytes the install takes on each disk: the EFI partition, the
+/// base system, packages, a desktop and the swapfile
+pub fn required_bytes(config: &InstallConfig) -> u64 {
+    let mut bytes = config.partition_layout.efi_size_mb * MB + BASE_SYSTEM_BYTES;
+    bytes += config.extra_packages.len() as u64 * PACKAGE_BYTES;
+    if config.desktop.enabled {
+        bytes += DESKTOP_BYTES;
+    }
+    if config.swap.swapfile_enabled {
+        let gb = config
+            .swap
+            .swapfile_size_gb
+            .map_or(DEFAULT_SWAPFILE_GB, u64::from);
+        bytes += gb * GB;
+    }
+    bytes
+}
+
+/// The estimate against what a disk holds, as in "~6.2 GB required, 4.0 GB
+/// available"
+pub fn space_summary(required: u64, available: u64) -> String {
+    format!(
+        "~{} required, {} available",
+        format_gb(required),
+        format_gb(available)
+    )
+}
+
+pub fn format_gb(bytes: u64) -> String {
+    format!("{:.1} GB", bytes as f64 / GB as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_adds_desktop_swap_and_packages() {
+        let mut config = InstallConfig::default();
+        let base = required_bytes(&config);
+        assert_eq!(base, 1024 * MB + BASE_SYSTEM_BYTES);
+
+        config.desktop.enabled = true;
+        config.swap.swapfile_enabled = true;
+        config.extra_packages = vec!["git".into(), "vim".into()];
+        assert_eq!(required_bytes(&config), base + 2 * GB + 8 * GB + 100 * MB);
+
+        config.swap.swapfile_size_gb = Some(16);
+        assert_eq!(format_gb(required_bytes(&config)), "23.1 GB");
+    }
+
+    #[test]
+    fn summary_shows_both_sides() {
+        assert_eq!(
+            space_summary(6_200_000_000, 4 * GB),
+            "~6.2 GB required, 4.0 GB available"
+        );
+    }
+}
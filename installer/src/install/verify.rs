@@ -0,0 +1,174 @@
+//! Checks that an install left something the firmware can boot
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::boot::{nvram_has_entry, BootEntry};
+
+/// Where udev links filesystems by UUID
+const BY_UUID_DIR: &str = "/dev/disk/by-uuid";
+
+/// The boot image `entry` loads, under the ESP mounted at `target/boot`
+pub fn check_boot_image(target: &Path, entry: &BootEntry) -> Option<String> {
+    let path = target
+        .join("boot")
+        .join(entry.loader_path.trim_start_matches('/'));
+    match fs::metadata(&path) {
+        Ok(meta) if meta.len() > 0 => None,
+        Ok(_) => Some(format!("{} is empty", path.display())),
+        Err(_) => Some(format!("{} is missing", path.display())),
+    }
+}
+
+/// The first crypttab entry under `target` unlocks `source`
+/// (e.g., UUID=...)
+pub fn check_crypttab(target: &Path, source: &str) -> Option<String> {
+    let Ok(content) = fs::read_to_string(target.join("etc/crypttab")) else {
+        return Some("/etc/crypttab is missing".into());
+    };
+    let device = content
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+        .and_then(|l| l.split_whitespace().nth(1));
+
+    match device {
+        Some(device) if device == source => None,
+        Some(device) => Some(format!(
+            "/etc/crypttab unlocks {}, but the encrypted partition is {}",
+            device, source
+        )),
+        None => Some("/etc/crypttab has no entries".into()),
+    }
+}
+
+/// Every `UUID=` in the fstab under `target` is one of `uuids`
+pub fn check_fstab(target: &Path, uuids: &[String]) -> Vec<String> {
+    let Ok(content) = fs::read_to_string(target.join("etc/fstab")) else {
+        return vec!["/etc/fstab is missing".into()];
+    };
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.starts_with('#'))
+        .filter_map(|l| l.split_whitespace().next()?.strip_prefix("UUID="))
+        .filter(|uuid| !uuids.iter().any(|u| u == uuid))
+        .map(|uuid| format!("/etc/fstab mounts UUID={}, which no filesystem has", uuid))
+        .collect()
+}
+
+/// `label` is one of the boot entries `efibootmgr` lists
+pub fn check_nvram(efibootmgr: &str, label: &str) -> Option<String> {
+    (!nvram_has_entry(efibootmgr, label)).then(|| format!("No '{}' boot entry in NVRAM", label))
+}
+
+/// UUIDs of the filesystems udev knows about
+pub fn known_uuids() -> Vec<String> {
+    fs::read_dir(BY_UUID_DIR)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Boot entries in NVRAM, as efibootmgr lists them
+pub fn efibootmgr() -> Result<String> {
+    let output = Command::new("efibootmgr")
+        .output()
+        .context("Failed to run efibootmgr")?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn entry(loader_path: &str) -> BootEntry {
+        BootEntry {
+            label: "mkOS".into(),
+            loader_path: loader_path.into(),
+            cmdline: None,
+        }
+    }
+
+    #[test]
+    fn boot_image_must_exist_and_have_content() {
+        let target = tempfile::tempdir().unwrap();
+        write(target.path(), "boot/mkos-6.1.0.efi", "MZ");
+        write(target.path(), "boot/empty.efi", "");
+
+        assert_eq!(
+            check_boot_image(target.path(), &entry("/mkos-6.1.0.efi")),
+            None
+        );
+        assert!(check_boot_image(target.path(), &entry("/empty.efi"))
+            .unwrap()
+            .ends_with("empty.efi is empty"));
+        assert!(check_boot_image(target.path(), &entry("/gone.efi"))
+            .unwrap()
+            .ends_with("gone.efi is missing"));
+    }
+
+    #[test]
+    fn crypttab_must_unlock_the_partition() {
+        let target = tempfile::tempdir().unwrap();
+        assert_eq!(
+            check_crypttab(target.path(), "UUID=abcd").as_deref(),
+            Some("/etc/crypttab is missing")
+        );
+
+        write(
+            target.path(),
+            "etc/crypttab",
+            "# <target name> <source device> <key file> <options>\nsystem UUID=abcd none luks,discard\n",
+        );
+        assert_eq!(check_crypttab(target.path(), "UUID=abcd"), None);
+        assert_eq!(
+            check_crypttab(target.path(), "UUID=ef01").as_deref(),
+            Some("/etc/crypttab unlocks UUID=abcd, but the encrypted partition is UUID=ef01")
+        );
+    }
+
+    #[test]
+    fn fstab_uuids_must_exist() {
+        let target = tempfile::tempdir().unwrap();
+        write(
+            target.path(),
+            "etc/fstab",
+            "# /dev/mapper/system UUID=1111\n\
+             UUID=1111 / btrfs subvol=@ 0 0\n\
+             UUID=2222 /boot vfat defaults 0 2\n\
+             tmpfs /tmp tmpfs defaults 0 0\n",
+        );
+
+        assert!(check_fstab(target.path(), &["1111".into(), "2222".into()]).is_empty());
+        assert_eq!(
+            check_fstab(target.path(), &["1111".into()]),
+            ["/etc/fstab mounts UUID=2222, which no filesystem has"]
+        );
+    }
+
+    #[test]
+    fn nvram_entry_matches_whole_label() {
+        let output = "BootCurrent: 0000\nBootOrder: 0001,0000\n\
+                      Boot0000* Linux Boot Manager\tHD(1,GPT,...)\n\
+                      Boot0001* mkOS (rescue)\tHD(1,GPT,...)\n";
+        assert_eq!(check_nvram(output, "Linux Boot Manager"), None);
+        assert_eq!(check_nvram(output, "mkOS (rescue)"), None);
+        assert_eq!(
+            check_nvram(output, "mkOS").as_deref(),
+            Some("No 'mkOS' boot entry in NVRAM")
+        );
+    }
+}
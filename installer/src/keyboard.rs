@@ -0,0 +1,255 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// X keyboard config, read by Xorg, relative to the root
+pub const XORG_KEYBOARD_CONF: &str = "etc/X11/xorg.conf.d/00-keyboard.conf";
+
+/// Login script exporting the layout for Wayland compositors that read
+/// `XKB_DEFAULT_*` (sway, river, labwc and other wlroots ones), relative to
+/// the root
+pub const XKB_PROFILE_SCRIPT: &str = "etc/profile.d/50-xkb-layout.sh";
+
+/// Where kbd keeps console keymaps, relative to the root, by distro
+const KEYMAP_DIRS: &[&str] = &["usr/share/kbd/keymaps", "usr/share/keymaps"];
+
+/// Console keymaps whose XKB layout is named differently: console keymap,
+/// layout, variant
+const CONSOLE_TO_XKB: &[(&str, &str, Option<&str>)] = &[
+    ("uk", "gb", None),
+    ("de-latin1", "de", None),
+    ("de-latin1-nodeadkeys", "de", Some("nodeadkeys")),
+    ("fr-latin1", "fr", None),
+    ("fr-latin9", "fr", Some("latin9")),
+    ("fr_CH", "ch", Some("fr")),
+    ("sg", "ch", None),
+    ("sv-latin1", "se", None),
+    ("se-latin1", "se", None),
+    ("dk-latin1", "dk", None),
+    ("no-latin1", "no", None),
+    ("fi-latin1", "fi", None),
+    ("la-latin1", "latam", None),
+    ("br-abnt2", "br", None),
+    ("jp106", "jp", None),
+    ("slovene", "si", None),
+    ("trq", "tr", None),
+    ("pl2", "pl", None),
+    ("cz-qwertz", "cz", None),
+    ("us-acentos", "us", Some("intl")),
+    ("dvorak", "us", Some("dvorak")),
+    ("colemak", "us", Some("colemak")),
+];
+
+/// An XKB layout and optional variant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XkbLayout {
+    pub layout: String,
+    pub variant: Option<String>,
+}
+
+impl XkbLayout {
+    /// The layout for console `keymap`, or `layout_override` when set
+    ///
+    /// The override is XKB notation: `de` or `de(nodeadkeys)`. Keymaps not in
+    /// the table keep their name up to the first `-`, which is the layout for
+    /// most (`it`, `es`, `hu`, `ru`).
+    pub fn for_keymap(keymap: &str, layout_override: Option<&str>) -> Self {
+        if let Some(layout) = layout_override {
+            return Self::parse(layout);
+        }
+
+        match CONSOLE_TO_XKB.iter().find(|(name, _, _)| *name == keymap) {
+            Some((_, layout, variant)) => Self {
+                layout: layout.to_string(),
+                variant: variant.map(String::from),
+            },
+            None => Self {
+                layout: keymap.split('-').next().unwrap_or(keymap).to_string(),
+                variant: None,
+            },
+        }
+    }
+
+    /// `layout` or `layout(variant)`
+    fn parse(spec: &str) -> Self {
+        match spec.split_once('(') {
+            Some((layout, variant)) => Self {
+                layout: layout.to_string(),
+                variant: Some(variant.trim_end_matches(')').to_string()),
+            },
+            None => Self {
+                layout: spec.to_string(),
+                variant: None,
+            },
+        }
+    }
+
+    fn xorg_conf(&self) -> String {
+        let variant = self
+            .variant
+            .as_ref()
+            .map(|v| format!("    Option \"XkbVariant\" \"{}\"\n", v))
+            .unwrap_or_default();
+        format!(
+            "# Written by mkOS from system.keymap\n\
+             Section \"InputClass\"\n    \
+                 Identifier \"system-keyboard\"\n    \
+                 MatchIsKeyboard \"on\"\n    \
+                 Option \"XkbLayout\" \"{}\"\n\
+             {}EndSection\n",
+            self.layout, variant
+        )
+    }
+
+    fn profile_script(&self) -> String {
+        let mut script = format!(
+            "# Written by mkOS from system.keymap\nexport XKB_DEFAULT_LAYOUT={}\n",
+            self.layout
+        );
+        if let Some(variant) = &self.variant {
+            script.push_str(&format!("export XKB_DEFAULT_VARIANT={}\n", variant));
+        }
+        script
+    }
+}
+
+/// Console keymaps available under `root`, sorted
+///
+/// Asks `localectl` for the running system where it exists, and otherwise
+/// lists the keymap files kbd ships.
+pub fn console_keymaps(root: &Path) -> Vec<String> {
+    if root == Path::new("/") {
+        if let Ok(output) = Command::new("localectl").arg("list-keymaps").output() {
+            if output.status.success() {
+                return String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .map(String::from)
+                    .collect();
+            }
+        }
+    }
+
+    let mut keymaps = Vec::new();
+    for dir in KEYMAP_DIRS {
+        collect_keymaps(&root.join(dir), &mut keymaps);
+    }
+    keymaps.sort();
+    keymaps.dedup();
+    keymaps
+}
+
+/// Names of `*.map` and `*.map.gz` files under `dir`, skipping the
+/// `include` directories of partial maps
+fn collect_keymaps(dir: &Path, keymaps: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if path.is_dir() {
+            if name != "include" {
+                collect_keymaps(&path, keymaps);
+            }
+        } else if let Some(keymap) = name
+            .strip_suffix(".map.gz")
+            .or_else(|| name.strip_suffix(".map"))
+        {
+            keymaps.push(keymap.to_string());
+        }
+    }
+}
+
+/// Set graphical sessions under `root` to `layout`, for Xorg and Wayland
+///
+/// Returns whether anything changed.
+pub fn write_graphical_layout(root: &Path, layout: &XkbLayout) -> Result<bool> {
+    let mut changed = false;
+    for (path, content) in [
+        (XORG_KEYBOARD_CONF, layout.xorg_conf()),
+        (XKB_PROFILE_SCRIPT, layout.profile_script()),
+    ] {
+        let path = root.join(path);
+        if fs::read_to_string(&path).ok().as_deref() == Some(content.as_str()) {
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+        changed = true;
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(layout: &str, variant: Option<&str>) -> XkbLayout {
+        XkbLayout {
+            layout: layout.into(),
+            variant: variant.map(String::from),
+        }
+    }
+
+    #[test]
+    fn console_keymaps_map_to_xkb_layouts() {
+        assert_eq!(XkbLayout::for_keymap("us", None), layout("us", None));
+        assert_eq!(XkbLayout::for_keymap("uk", None), layout("gb", None));
+        assert_eq!(
+            XkbLayout::for_keymap("de-latin1-nodeadkeys", None),
+            layout("de", Some("nodeadkeys"))
+        );
+        assert_eq!(
+            XkbLayout::for_keymap("dvorak", None),
+            layout("us", Some("dvorak"))
+        );
+        assert_eq!(XkbLayout::for_keymap("it", None), layout("it", None));
+        assert_eq!(XkbLayout::for_keymap("es-cp850", None), layout("es", None));
+    }
+
+    #[test]
+    fn override_wins() {
+        assert_eq!(
+            XkbLayout::for_keymap("us", Some("de(neo)")),
+            layout("de", Some("neo"))
+        );
+        assert_eq!(XkbLayout::for_keymap("uk", Some("gb")), layout("gb", None));
+    }
+
+    #[test]
+    fn keymaps_are_listed_from_kbd() {
+        let root = tempfile::tempdir().unwrap();
+        for file in [
+            "i386/qwerty/us.map.gz",
+            "i386/qwertz/de-latin1.map.gz",
+            "i386/dvorak/dvorak.map",
+            "i386/include/qwerty-layout.inc",
+            "include/compose.latin1",
+        ] {
+            let path = root.path().join(KEYMAP_DIRS[0]).join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        assert_eq!(console_keymaps(root.path()), ["de-latin1", "dvorak", "us"]);
+    }
+
+    #[test]
+    fn layout_is_written_for_xorg_and_wayland() {
+        let root = tempfile::tempdir().unwrap();
+        let de = layout("de", Some("nodeadkeys"));
+
+        assert!(write_graphical_layout(root.path(), &de).unwrap());
+        assert!(!write_graphical_layout(root.path(), &de).unwrap());
+
+        let xorg = fs::read_to_string(root.path().join(XORG_KEYBOARD_CONF)).unwrap();
+        assert!(xorg.contains("    Option \"XkbLayout\" \"de\"\n"));
+        assert!(xorg.contains("    Option \"XkbVariant\" \"nodeadkeys\"\n"));
+
+        let script = fs::read_to_string(root.path().join(XKB_PROFILE_SCRIPT)).unwrap();
+        assert!(script.contains("export XKB_DEFAULT_LAYOUT=de\n"));
+        assert!(script.contains("export XKB_DEFAULT_VARIANT=nodeadkeys\n"));
+    }
+}
@@ -12,14 +12,21 @@ pub mod firewall;
 pub mod hooks;
 pub mod init;
 pub mod install;
+pub mod keyboard;
+pub mod locale;
 pub mod manifest;
 pub mod mirror;
 pub mod network;
 pub mod paths;
 pub mod pkgmgr;
+pub mod privilege;
 pub mod prompt;
 pub mod rescue;
+pub mod secret;
+pub mod snapshot_schedule;
+pub mod state;
 pub mod swap;
+pub mod tree_diff;
 pub mod tui;
 pub mod uki;
 pub mod util;
@@ -0,0 +1,237 @@
+use anyhow::{bail, Result};
+use std::fs;
+use std::path::{Component, Path};
+
+use crate::util::closest_matches;
+
+/// Timezone database, relative to the root
+const ZONEINFO_DIR: &str = "usr/share/zoneinfo";
+
+/// Locales glibc can generate, one `<name> <charset>` per line, relative to
+/// the root
+const SUPPORTED_LOCALES: &str = "usr/share/i18n/SUPPORTED";
+
+/// Locales locale-gen can be told to build, relative to the root
+const LOCALE_GEN: &str = "etc/locale.gen";
+
+/// Check `timezone` names a zone in the zoneinfo tree under `root`
+///
+/// Passes when `root` has no zoneinfo tree to check against.
+pub fn check_timezone(root: &Path, timezone: &str) -> Result<()> {
+    let zoneinfo = root.join(ZONEINFO_DIR);
+    if !zoneinfo.is_dir() {
+        return Ok(());
+    }
+
+    let relative = Path::new(timezone);
+    let plain = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    if plain && zoneinfo.join(relative).is_file() {
+        return Ok(());
+    }
+
+    let mut zones = Vec::new();
+    collect_zones(&zoneinfo, "", &mut zones);
+    bail!(
+        "Unknown timezone {}{}",
+        timezone,
+        suggestion(timezone, zones.iter().map(String::as_str))
+    )
+}
+
+/// Check `locale` is one glibc on the system under `root` can generate
+///
+/// Passes when `root` lists no locales, as on musl systems.
+pub fn check_locale(root: &Path, locale: &str) -> Result<()> {
+    let locales = available_locales(root);
+    if locales.is_empty() || locales.iter().any(|l| l == locale) {
+        return Ok(());
+    }
+
+    bail!(
+        "Unknown locale {}{}",
+        locale,
+        suggestion(locale, locales.iter().map(String::as_str))
+    )
+}
+
+/// Every zone in the zoneinfo tree under `root`, sorted
+pub fn timezones(root: &Path) -> Vec<String> {
+    let mut zones = Vec::new();
+    collect_zones(&root.join(ZONEINFO_DIR), "", &mut zones);
+    zones.sort();
+    zones
+}
+
+/// Every locale glibc under `root` can generate, sorted
+pub fn locales(root: &Path) -> Vec<String> {
+    let mut locales = available_locales(root);
+    locales.sort();
+    locales.dedup();
+    locales
+}
+
+/// `locale.gen` content with every one of `locales` enabled: commented
+/// entries are uncommented, and missing ones appended
+pub fn enable_locales(locale_gen: &str, locales: &[&str]) -> String {
+    let mut missing: Vec<&str> = locales.to_vec();
+    let mut lines: Vec<String> = locale_gen
+        .lines()
+        .map(|line| {
+            let entry = line.trim_start_matches('#').trim();
+            let name = entry.split_whitespace().next().unwrap_or("");
+            if locales.contains(&name) && entry.split_whitespace().count() == 2 {
+                missing.retain(|l| *l != name);
+                entry.to_string()
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+
+    for locale in missing {
+        let charset = locale.split_once('.').map_or("UTF-8", |(_, c)| c);
+        lines.push(format!("{} {}", locale, charset));
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Locale names from glibc's SUPPORTED list and locale.gen, commented out
+/// or not
+fn available_locales(root: &Path) -> Vec<String> {
+    [SUPPORTED_LOCALES, LOCALE_GEN]
+        .iter()
+        .filter_map(|path| fs::read_to_string(root.join(path)).ok())
+        .flat_map(|content| {
+            content
+                .lines()
+                .filter_map(|line| {
+                    let mut fields = line.trim_start_matches('#').split_whitespace();
+                    // Entries are `<name> <charset>`; prose comments have
+                    // more words or none
+                    match (fields.next(), fields.next(), fields.next()) {
+                        (Some(name), Some(_charset), None) => Some(name.to_string()),
+                        _ => None,
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Zone names under `dir`, skipping the `posix` and `right` copies of the
+/// tree and the lowercase data files beside the zones
+fn collect_zones(dir: &Path, prefix: &str, zones: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if prefix.is_empty() && (name == "posix" || name == "right") {
+            continue;
+        }
+        if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+            continue;
+        }
+
+        let zone = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        let path = entry.path();
+        if path.is_dir() {
+            collect_zones(&path, &zone, zones);
+        } else {
+            zones.push(zone);
+        }
+    }
+}
+
+/// `; did you mean ...?` when something in `candidates` is close to `name`
+fn suggestion<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> String {
+    let matches = closest_matches(name, candidates);
+    if matches.is_empty() {
+        String::new()
+    } else {
+        format!("; did you mean {}?", matches.join(" or "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(root: &Path, path: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "").unwrap();
+    }
+
+    #[test]
+    fn timezones_are_checked_against_zoneinfo() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(check_timezone(root.path(), "America/Denvr").is_ok());
+
+        touch(root.path(), "usr/share/zoneinfo/America/Denver");
+        touch(root.path(), "usr/share/zoneinfo/Europe/Berlin");
+        touch(root.path(), "usr/share/zoneinfo/UTC");
+        touch(root.path(), "usr/share/zoneinfo/posix/America/Denver");
+        touch(root.path(), "usr/share/zoneinfo/zone.tab");
+
+        assert!(check_timezone(root.path(), "America/Denver").is_ok());
+        assert!(check_timezone(root.path(), "UTC").is_ok());
+
+        let err = check_timezone(root.path(), "America/Denvr").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown timezone America/Denvr; did you mean America/Denver?"
+        );
+        assert_eq!(
+            timezones(root.path()),
+            ["America/Denver", "Europe/Berlin", "UTC"]
+        );
+        assert!(check_timezone(root.path(), "America").is_err());
+        assert!(check_timezone(root.path(), "../../../etc/passwd").is_err());
+        assert_eq!(
+            check_timezone(root.path(), "Mars/Olympus")
+                .unwrap_err()
+                .to_string(),
+            "Unknown timezone Mars/Olympus"
+        );
+    }
+
+    #[test]
+    fn every_locale_is_uncommented() {
+        let locale_gen = "# en_US.UTF-8 UTF-8 is the default\n#de_DE.UTF-8 UTF-8\n#en_US ISO-8859-1\n#en_US.UTF-8 UTF-8\n#ja_JP.UTF-8 UTF-8\n";
+        assert_eq!(
+            enable_locales(locale_gen, &["en_US.UTF-8", "ja_JP.UTF-8", "fr_FR.UTF-8"]),
+            "# en_US.UTF-8 UTF-8 is the default\n#de_DE.UTF-8 UTF-8\n#en_US ISO-8859-1\nen_US.UTF-8 UTF-8\nja_JP.UTF-8 UTF-8\nfr_FR.UTF-8 UTF-8\n"
+        );
+        assert_eq!(enable_locales("", &["C.UTF-8"]), "C.UTF-8 UTF-8\n");
+    }
+
+    #[test]
+    fn locales_are_checked_against_locale_gen() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(check_locale(root.path(), "en_US.UTF8").is_ok());
+
+        fs::create_dir_all(root.path().join("etc")).unwrap();
+        fs::write(
+            root.path().join(LOCALE_GEN),
+            "# Configuration file for locale-gen\n#\n#de_DE.UTF-8 UTF-8\nen_US.UTF-8 UTF-8\n",
+        )
+        .unwrap();
+
+        assert!(check_locale(root.path(), "en_US.UTF-8").is_ok());
+        assert!(check_locale(root.path(), "de_DE.UTF-8").is_ok());
+
+        let err = check_locale(root.path(), "en_US.UTF8").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown locale en_US.UTF8; did you mean en_US.UTF-8?"
+        );
+        assert!(check_locale(root.path(), "Configuration").is_err());
+    }
+}
@@ -0,0 +1,69 @@
+use anyhow::{bail, Context, Result};
+use ring::digest::{Context as Digest, SHA256};
+use std::io::{self, Read};
+
+/// Hex SHA-256 of everything `reader` yields
+pub fn sha256_hex(mut reader: impl Read) -> io::Result<String> {
+    let mut digest = Digest::new(&SHA256);
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        digest.update(&buf[..n]);
+    }
+
+    Ok(digest
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Compare the content of `reader` against a hex SHA-256 digest
+pub fn verify_sha256(reader: impl Read, expected: &str) -> Result<()> {
+    let actual = sha256_hex(reader).context("Failed to read content for checksum")?;
+    let expected = expected.trim();
+
+    if !actual.eq_ignore_ascii_case(expected) {
+        bail!("SHA-256 mismatch: expected {}, got {}", expected, actual);
+    }
+    Ok(())
+}
+
+/// The digest from a `.sha256` file, as written by `sha256sum`
+/// (`<digest>  <filename>`) or holding the bare digest
+pub fn parse_checksum_file(content: &str) -> Result<String> {
+    let digest = content.split_whitespace().next().unwrap_or("");
+    if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("Not a SHA-256 checksum file");
+    }
+    Ok(digest.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn digest_matches_sha256sum() {
+        assert_eq!(sha256_hex(&b"hello"[..]).unwrap(), HELLO);
+        verify_sha256(&b"hello"[..], &HELLO.to_uppercase()).unwrap();
+
+        let err = verify_sha256(&b"hullo"[..], HELLO).unwrap_err();
+        assert!(err.to_string().contains("mismatch"));
+    }
+
+    #[test]
+    fn checksum_file_formats() {
+        let line = format!("{}  manifest.tar.gz\n", HELLO);
+        assert_eq!(parse_checksum_file(&line).unwrap(), HELLO);
+        assert_eq!(parse_checksum_file(HELLO).unwrap(), HELLO);
+        assert!(parse_checksum_file("").is_err());
+        assert!(parse_checksum_file("<html>not found</html>").is_err());
+    }
+}
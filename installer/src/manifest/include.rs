@@ -0,0 +1,286 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::Manifest;
+
+/// Top-level sections whose lists an including manifest adds to instead of
+/// replacing
+const APPENDED_SECTIONS: &[&str] = &["packages", "services", "files", "scripts"];
+
+/// `manifest` with the manifests listed under its `includes` merged
+/// underneath it
+///
+/// `path` is the file `manifest` was read from, which includes are resolved
+/// relative to. Manifests read from a URL or stdin have nothing to resolve
+/// them against.
+pub(super) fn resolve(manifest: Manifest, path: Option<&Path>) -> Result<Manifest> {
+    if manifest.includes.is_empty() {
+        return Ok(manifest);
+    }
+    let Some(path) = path else {
+        bail!("Manifest includes need a local manifest or tar bundle to resolve against");
+    };
+
+    let document = load_document(path, &mut Vec::new())?;
+    let mut manifest: Manifest = serde_json::from_value(document.clone())
+        .with_context(|| format!("Failed to merge includes of {}", path.display()))?;
+    super::record_unknown_fields(&mut manifest, &document);
+    Ok(manifest)
+}
+
+impl Manifest {
+    /// This manifest with the profile `name` merged over it, by the same
+    /// rules as includes
+    pub fn with_profile(mut self, name: &str) -> Result<Manifest> {
+        let Some(profile) = self.profiles.remove(name) else {
+            let mut names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            if names.is_empty() {
+                bail!(
+                    "Profile {} was requested, but the manifest has no profiles",
+                    name
+                );
+            }
+            names.sort_unstable();
+            bail!(
+                "Unknown profile {}. The manifest has: {}",
+                name,
+                names.join(", ")
+            );
+        };
+
+        let mut unknown_fields = std::mem::take(&mut self.unknown_fields);
+        self.profiles.clear();
+
+        let mut document = serde_json::to_value(&self)?;
+        merge(&mut document, profile.clone(), "");
+        let mut manifest: Manifest = serde_json::from_value(document)
+            .with_context(|| format!("Invalid profile {}", name))?;
+
+        let known = serde_json::to_value(&manifest)?;
+        unknown_fields.extend(
+            super::validate::unknown_fields(&profile, &known)
+                .into_iter()
+                .map(|field| format!("profiles.{}.{}", name, field)),
+        );
+        manifest.unknown_fields = unknown_fields;
+        Ok(manifest)
+    }
+}
+
+/// The document at `path` merged over everything it includes, in order
+///
+/// `chain` holds the manifests currently being included, to catch cycles.
+fn load_document(path: &Path, chain: &mut Vec<PathBuf>) -> Result<Value> {
+    let canonical = fs::canonicalize(path)
+        .with_context(|| format!("Failed to read manifest: {}", path.display()))?;
+    if chain.contains(&canonical) {
+        let cycle: Vec<String> = chain
+            .iter()
+            .chain([&canonical])
+            .map(|p| p.display().to_string())
+            .collect();
+        bail!("Manifest include cycle: {}", cycle.join(" -> "));
+    }
+
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read manifest: {}", path.display()))?;
+    let mut document = super::parse_document(path, &content)?;
+    let includes: Vec<String> = match document.as_object_mut().and_then(|d| d.remove("includes")) {
+        Some(includes) => serde_json::from_value(includes)
+            .with_context(|| format!("includes in {} must be a list of paths", path.display()))?,
+        None => Vec::new(),
+    };
+
+    chain.push(canonical);
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut merged = Value::Object(Map::new());
+    for include in &includes {
+        merge(&mut merged, load_document(&dir.join(include), chain)?, "");
+    }
+    chain.pop();
+
+    merge(&mut merged, document, "");
+    Ok(merged)
+}
+
+/// Merge `overlay` into `base`: tables merge key by key, lists in
+/// [`APPENDED_SECTIONS`] are appended to, and any other value in `overlay`
+/// replaces the one in `base`
+fn merge(base: &mut Value, overlay: Value, section: &str) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                let section = if section.is_empty() { &key } else { section };
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value, section),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(overlay)) if APPENDED_SECTIONS.contains(&section) => {
+            base.extend(overlay)
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::load_from_file;
+
+    fn write(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn main_manifest_wins_and_package_lists_append() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "base.yaml",
+            r#"
+system:
+  hostname: base
+  timezone: Europe/Berlin
+packages:
+  base: [git, vim]
+boot:
+  cmdline: [quiet]
+"#,
+        );
+        write(
+            dir.path(),
+            "desktop.toml",
+            r#"
+[system]
+hostname = "desktop"
+
+[packages]
+desktop = ["sway"]
+"#,
+        );
+        let main = write(
+            dir.path(),
+            "laptop.yaml",
+            r#"
+includes: [base.yaml, desktop.toml]
+system:
+  hostname: laptop
+packages:
+  base: [tlp]
+boot:
+  cmdline: [splash]
+"#,
+        );
+
+        let manifest = load_from_file(&main).unwrap().manifest;
+        assert_eq!(manifest.system.hostname, "laptop");
+        assert_eq!(manifest.system.timezone, "Europe/Berlin");
+        assert_eq!(manifest.packages.groups["base"], ["git", "vim", "tlp"]);
+        assert_eq!(manifest.packages.groups["desktop"], ["sway"]);
+        assert_eq!(manifest.boot.cmdline, ["splash"]);
+        assert!(manifest.includes.is_empty());
+        assert!(manifest.unknown_fields.is_empty());
+    }
+
+    #[test]
+    fn later_includes_win_over_earlier_ones() {
+        let mut base = serde_json::json!({"system": {"hostname": "a", "keymap": "us"}});
+        merge(
+            &mut base,
+            serde_json::json!({"system": {"hostname": "b"}}),
+            "",
+        );
+        assert_eq!(
+            base,
+            serde_json::json!({"system": {"hostname": "b", "keymap": "us"}})
+        );
+    }
+
+    #[test]
+    fn profile_overrides_the_base() {
+        let manifest = crate::manifest::parse_yaml(
+            r#"
+system:
+  hostname: base
+  timezone: Europe/Berlin
+packages:
+  base: [git]
+profiles:
+  laptop:
+    system:
+      hostname: laptop
+    packages:
+      base: [tlp]
+      desktop: [sway]
+    sytem:
+      keymap: de
+  server:
+    system:
+      hostname: server
+"#,
+        )
+        .unwrap();
+        assert!(manifest.unknown_fields.is_empty());
+
+        let laptop = manifest.with_profile("laptop").unwrap();
+        assert_eq!(laptop.system.hostname, "laptop");
+        assert_eq!(laptop.system.timezone, "Europe/Berlin");
+        assert_eq!(laptop.packages.groups["base"], ["git", "tlp"]);
+        assert_eq!(laptop.packages.groups["desktop"], ["sway"]);
+        assert!(laptop.profiles.is_empty());
+        assert_eq!(laptop.unknown_fields, ["profiles.laptop.sytem"]);
+    }
+
+    #[test]
+    fn missing_profile_lists_the_available_ones() {
+        let manifest =
+            crate::manifest::parse_yaml("profiles:\n  server: {}\n  laptop: {}\n").unwrap();
+        let err = manifest.with_profile("desktop").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown profile desktop. The manifest has: laptop, server"
+        );
+
+        assert!(Manifest::default().with_profile("laptop").is_err());
+    }
+
+    #[test]
+    fn include_cycles_are_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write(dir.path(), "a.yaml", "includes: [b.yaml]\n");
+        write(dir.path(), "b.yaml", "includes: [c.yaml]\n");
+        write(dir.path(), "c.yaml", "includes: [a.yaml]\n");
+
+        let err = load_from_file(&main).unwrap_err();
+        assert!(format!("{:#}", err).contains("Manifest include cycle"));
+    }
+
+    #[test]
+    fn shared_includes_are_not_a_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "common.yaml", "packages:\n  base: [git]\n");
+        write(dir.path(), "a.yaml", "includes: [common.yaml]\n");
+        write(dir.path(), "b.yaml", "includes: [common.yaml]\n");
+        let main = write(dir.path(), "main.yaml", "includes: [a.yaml, b.yaml]\n");
+
+        let manifest = load_from_file(&main).unwrap().manifest;
+        assert_eq!(manifest.packages.groups["base"], ["git", "git"]);
+    }
+
+    #[test]
+    fn includes_need_a_file_to_resolve_against() {
+        let manifest = Manifest {
+            includes: vec!["base.yaml".into()],
+            ..Default::default()
+        };
+        assert!(resolve(manifest, None).is_err());
+    }
+}
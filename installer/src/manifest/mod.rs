@@ -1,11 +1,39 @@
+mod checksum;
+mod include;
+mod preset;
 mod schema;
+mod validate;
 
+pub use checksum::verify_sha256;
+pub use preset::Preset;
 pub use schema::*;
 
+use checksum::parse_checksum_file;
+
 use anyhow::{bail, Context, Result};
 use std::fs::{self, File};
 use std::io::{self, Read};
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tracing::debug;
+
+const URL_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Per read, so large tar bundles still download as long as data keeps flowing
+const URL_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Attempts per mirror URL, overridable with `MKOS_MANIFEST_RETRIES`
+const FETCH_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry, doubled after each further failure
+const FETCH_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Largest file a manifest `source` URL may serve
+pub const MAX_REMOTE_FILE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// A `.sha256` file is one line; anything bigger is an error page
+const MAX_CHECKSUM_BYTES: u64 = 4096;
 
 /// Represents a loaded manifest bundle (manifest + optional files from tar)
 #[derive(Debug)]
@@ -18,30 +46,42 @@ pub struct ManifestBundle {
 #[derive(Debug, Clone)]
 pub enum ManifestSource {
     File(PathBuf),
-    Url(String),
+    /// Mirror URLs, tried in order
+    Url(Vec<String>),
     Stdin,
     Interactive,
 }
 
 impl ManifestSource {
     /// Parse from command line argument
+    ///
+    /// A URL argument may be a comma-separated list of mirrors.
     pub fn from_arg(arg: Option<&str>) -> Self {
         match arg {
             None => Self::Interactive,
             Some("-") => Self::Stdin,
-            Some(s) if s.starts_with("http://") || s.starts_with("https://") => {
-                Self::Url(s.to_string())
-            }
+            Some(s) if is_url(s) => Self::Url(
+                s.split(',')
+                    .map(str::trim)
+                    .filter(|u| !u.is_empty())
+                    .map(String::from)
+                    .collect(),
+            ),
             Some(s) => Self::File(PathBuf::from(s)),
         }
     }
 }
 
+/// Whether a manifest argument or file `source` refers to an http(s) URL
+pub fn is_url(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
 /// Load manifest from any supported source
 pub fn load(source: &ManifestSource) -> Result<ManifestBundle> {
     match source {
         ManifestSource::File(path) => load_from_file(path),
-        ManifestSource::Url(url) => load_from_url(url),
+        ManifestSource::Url(urls) => load_from_url(urls),
         ManifestSource::Stdin => load_from_stdin(),
         ManifestSource::Interactive => Ok(ManifestBundle {
             manifest: Manifest::default(),
@@ -50,51 +90,33 @@ pub fn load(source: &ManifestSource) -> Result<ManifestBundle> {
     }
 }
 
-/// Load manifest from a file (YAML, JSON, or tar)
+/// Load manifest from a file (YAML, JSON, TOML, or tar)
 fn load_from_file(path: &Path) -> Result<ManifestBundle> {
     let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
 
     match extension {
         "tar" | "tgz" | "tar.gz" => load_from_tar_file(path),
-        "yaml" | "yml" => {
-            let content = fs::read_to_string(path)
-                .with_context(|| format!("Failed to read manifest: {}", path.display()))?;
-            let manifest = parse_yaml(&content)?;
-            Ok(ManifestBundle {
-                manifest,
-                files_dir: path.parent().map(|p| p.to_path_buf()),
-            })
-        }
-        "json" => {
-            let content = fs::read_to_string(path)
-                .with_context(|| format!("Failed to read manifest: {}", path.display()))?;
-            let manifest = parse_json(&content)?;
-            Ok(ManifestBundle {
-                manifest,
-                files_dir: path.parent().map(|p| p.to_path_buf()),
-            })
-        }
         _ => {
-            // Try to detect format from content
             let content = fs::read_to_string(path)
                 .with_context(|| format!("Failed to read manifest: {}", path.display()))?;
-            let manifest = parse_auto(&content)?;
+            let manifest = match extension {
+                "yaml" | "yml" => parse_yaml(&content)?,
+                "json" => parse_json(&content)?,
+                "toml" => parse_toml(&content)?,
+                // Try to detect format from content
+                _ => parse_auto(&content)?,
+            };
             Ok(ManifestBundle {
-                manifest,
+                manifest: include::resolve(manifest, Some(path))?,
                 files_dir: path.parent().map(|p| p.to_path_buf()),
             })
         }
     }
 }
 
-/// Load manifest from a tar archive
+/// Load manifest from a tar archive, verifying it against a sibling
+/// `<archive>.sha256` file when one exists
 fn load_from_tar_file(path: &Path) -> Result<ManifestBundle> {
-    use flate2::read::GzDecoder;
-    use tar::Archive;
-
-    let file = File::open(path)
-        .with_context(|| format!("Failed to open tar archive: {}", path.display()))?;
-
     // Check if gzipped
     let is_gzip = path
         .extension()
@@ -102,21 +124,45 @@ fn load_from_tar_file(path: &Path) -> Result<ManifestBundle> {
         .map(|e| e == "tgz" || e == "gz")
         .unwrap_or(false);
 
+    let mut checksum_path = path.as_os_str().to_owned();
+    checksum_path.push(".sha256");
+    let checksum = match fs::read_to_string(&checksum_path) {
+        Ok(content) => Some(parse_checksum_file(&content).with_context(|| {
+            format!(
+                "Invalid checksum file: {}",
+                Path::new(&checksum_path).display()
+            )
+        })?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).context("Failed to read checksum file"),
+    };
+
+    load_tar(path, is_gzip, checksum.as_deref())
+}
+
+/// Verify and extract a tar archive, then load the manifest inside it
+fn load_tar(path: &Path, is_gzip: bool, checksum: Option<&str>) -> Result<ManifestBundle> {
+    use flate2::read::GzDecoder;
+
+    let open = || {
+        File::open(path).with_context(|| format!("Failed to open tar archive: {}", path.display()))
+    };
+
+    if let Some(expected) = checksum {
+        verify_sha256(open()?, expected)
+            .with_context(|| format!("Refusing to extract {}", path.display()))?;
+        debug!(path = %path.display(), "archive checksum verified");
+    }
+
     // Extract to temp directory
     let extract_dir = tempfile::tempdir().context("Failed to create temp directory")?;
     let extract_path = extract_dir.path().to_path_buf();
 
     if is_gzip {
-        let decoder = GzDecoder::new(file);
-        let mut archive = Archive::new(decoder);
-        archive
-            .unpack(&extract_path)
+        unpack_archive(GzDecoder::new(open()?), &extract_path)
             .context("Failed to extract tar.gz archive")?;
     } else {
-        let mut archive = Archive::new(file);
-        archive
-            .unpack(&extract_path)
-            .context("Failed to extract tar archive")?;
+        unpack_archive(open()?, &extract_path).context("Failed to extract tar archive")?;
     }
 
     // Find manifest file in extracted contents
@@ -128,7 +174,7 @@ fn load_from_tar_file(path: &Path) -> Result<ManifestBundle> {
         )
     })?;
 
-    let manifest = parse_auto(&content)?;
+    let manifest = include::resolve(parse_auto(&content)?, Some(&manifest_path))?;
 
     // Keep the temp dir alive (will be cleaned up on process exit)
     let files_dir = extract_dir.keep();
@@ -139,7 +185,62 @@ fn load_from_tar_file(path: &Path) -> Result<ManifestBundle> {
     })
 }
 
-/// Find manifest.yaml or manifest.json in a directory
+/// Unpack every entry of a tar stream into `dest`, refusing entries whose
+/// path or link target would land outside it
+fn unpack_archive(reader: impl Read, dest: &Path) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if escapes_root(&path) {
+            bail!(
+                "Archive entry escapes the extract directory: {}",
+                path.display()
+            );
+        }
+
+        if let Some(target) = entry.link_name()? {
+            let resolved = path.parent().unwrap_or(Path::new("")).join(&target);
+            if escapes_root(&resolved) {
+                bail!(
+                    "Archive link escapes the extract directory: {} -> {}",
+                    path.display(),
+                    target.display()
+                );
+            }
+        }
+
+        entry
+            .unpack_in(dest)
+            .with_context(|| format!("Failed to extract {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Whether a relative archive path is absolute or climbs above its root
+fn escapes_root(path: &Path) -> bool {
+    use std::path::Component;
+
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return true,
+            Component::ParentDir => {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+        }
+    }
+    false
+}
+
+/// Find manifest.yaml, manifest.json or manifest.toml in a directory
 fn find_manifest_in_dir(dir: &Path) -> Result<PathBuf> {
     let yaml_path = dir.join("manifest.yaml");
     if yaml_path.exists() {
@@ -156,17 +257,170 @@ fn find_manifest_in_dir(dir: &Path) -> Result<PathBuf> {
         return Ok(json_path);
     }
 
+    let toml_path = dir.join("manifest.toml");
+    if toml_path.exists() {
+        return Ok(toml_path);
+    }
+
     bail!(
-        "No manifest.yaml or manifest.json found in archive root. \
-         Expected one of: manifest.yaml, manifest.yml, manifest.json"
+        "No manifest.yaml, manifest.json or manifest.toml found in archive root. \
+         Expected one of: manifest.yaml, manifest.yml, manifest.json, manifest.toml"
     );
 }
 
-/// Load manifest from URL (YAML, JSON, or tar)
-fn load_from_url(url: &str) -> Result<ManifestBundle> {
-    let response = ureq::get(url)
-        .call()
-        .with_context(|| format!("Failed to fetch manifest from URL: {}", url))?;
+/// Load manifest from the first mirror URL that serves it (YAML, JSON, TOML,
+/// or tar)
+fn load_from_url(urls: &[String]) -> Result<ManifestBundle> {
+    let agent = http_agent();
+    let policy = RetryPolicy::from_env();
+    let (url, download) =
+        fetch_with_retry(urls, &policy, thread::sleep, |url| download(&agent, url))?;
+
+    match download {
+        Download::Text(content) => Ok(ManifestBundle {
+            manifest: include::resolve(parse_auto(&content)?, None)?,
+            files_dir: None,
+        }),
+        Download::Tar(temp_file) => load_tar_download(&agent, &url, temp_file),
+    }
+}
+
+/// Fetch a file referenced by URL, refusing anything larger than `max_bytes`
+pub fn fetch_file(url: &str, max_bytes: u64) -> Result<Vec<u8>> {
+    let agent = http_agent();
+    let urls = [url.to_string()];
+    let (_, body) = fetch_with_retry(&urls, &RetryPolicy::from_env(), thread::sleep, |url| {
+        let response = agent.get(url).call()?;
+        read_capped(response.into_reader(), max_bytes)
+            .map_err(|e| FetchError::Fatal(e.context(format!("Failed to download {}", url))))
+    })?;
+    Ok(body)
+}
+
+fn http_agent() -> ureq::Agent {
+    // ureq has no default timeouts, so a stalled server would hang forever
+    ureq::AgentBuilder::new()
+        .timeout_connect(URL_CONNECT_TIMEOUT)
+        .timeout_read(URL_READ_TIMEOUT)
+        .build()
+}
+
+/// Read all of `reader`, failing once more than `max_bytes` arrive
+fn read_capped(reader: impl Read, max_bytes: u64) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    reader.take(max_bytes + 1).read_to_end(&mut body)?;
+    if body.len() as u64 > max_bytes {
+        bail!("larger than the {} MiB limit", max_bytes / (1024 * 1024));
+    }
+    Ok(body)
+}
+
+/// A fully downloaded manifest response
+enum Download {
+    Text(String),
+    Tar(tempfile::NamedTempFile),
+}
+
+/// Why a fetch attempt failed
+#[derive(Debug)]
+enum FetchError {
+    /// Timeouts, connection failures and 5xx responses; worth trying again
+    Retryable(anyhow::Error),
+    /// Other error statuses such as 404; retrying the same URL won't help
+    Fatal(anyhow::Error),
+}
+
+impl From<ureq::Error> for FetchError {
+    fn from(err: ureq::Error) -> Self {
+        match err {
+            ureq::Error::Status(code, _) if code >= 500 || code == 429 => {
+                Self::Retryable(err.into())
+            }
+            ureq::Error::Status(..) => Self::Fatal(err.into()),
+            ureq::Error::Transport(_) => Self::Retryable(err.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RetryPolicy {
+    attempts: u32,
+    backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: FETCH_ATTEMPTS,
+            backoff: FETCH_BACKOFF,
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn from_env() -> Self {
+        let mut policy = Self::default();
+        if let Some(attempts) = std::env::var("MKOS_MANIFEST_RETRIES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            policy.attempts = attempts;
+        }
+        policy
+    }
+
+    /// Delay after failed attempt `attempt` (0-based)
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(attempt))
+    }
+}
+
+/// Try each URL in turn, retrying retryable failures with exponential backoff
+///
+/// Returns the URL that succeeded along with the fetched value.
+fn fetch_with_retry<T>(
+    urls: &[String],
+    policy: &RetryPolicy,
+    mut sleep: impl FnMut(Duration),
+    mut fetch: impl FnMut(&str) -> Result<T, FetchError>,
+) -> Result<(String, T)> {
+    if urls.is_empty() {
+        bail!("No manifest URL given");
+    }
+
+    let mut failures = Vec::new();
+    for url in urls {
+        for attempt in 0..policy.attempts.max(1) {
+            match fetch(url) {
+                Ok(value) => return Ok((url.clone(), value)),
+                Err(FetchError::Retryable(e)) => {
+                    failures.push(format!("{}: {:#}", url, e));
+                    if attempt + 1 < policy.attempts {
+                        let delay = policy.delay(attempt);
+                        eprintln!(
+                            "Fetching {} failed ({:#}), retrying in {}s",
+                            url,
+                            e,
+                            delay.as_secs()
+                        );
+                        sleep(delay);
+                    }
+                }
+                Err(FetchError::Fatal(e)) => {
+                    failures.push(format!("{}: {:#}", url, e));
+                    break;
+                }
+            }
+        }
+    }
+
+    let list: Vec<String> = failures.iter().map(|f| format!("  - {}", f)).collect();
+    bail!("Failed to fetch manifest:\n{}", list.join("\n"))
+}
+
+/// Download `url` completely, so a dropped connection mid-body can be retried
+fn download(agent: &ureq::Agent, url: &str) -> Result<Download, FetchError> {
+    let response = agent.get(url).call()?;
 
     let content_type = response.header("content-type").unwrap_or("").to_lowercase();
 
@@ -178,61 +432,51 @@ fn load_from_url(url: &str) -> Result<ManifestBundle> {
         || url.ends_with(".tar.gz");
 
     if is_tar {
-        load_tar_from_url(url, response)
+        let mut temp_file = tempfile::NamedTempFile::new()
+            .context("Failed to create temp file")
+            .map_err(FetchError::Fatal)?;
+        let mut reader = response.into_reader();
+        io::copy(&mut reader, &mut temp_file)
+            .context("Failed to download tar archive")
+            .map_err(FetchError::Retryable)?;
+        Ok(Download::Tar(temp_file))
     } else {
         let content = response
             .into_string()
-            .context("Failed to read response body")?;
-        let manifest = parse_auto(&content)?;
-        Ok(ManifestBundle {
-            manifest,
-            files_dir: None,
-        })
+            .context("Failed to read response body")
+            .map_err(FetchError::Retryable)?;
+        Ok(Download::Text(content))
     }
 }
 
-/// Load tar archive from URL
-fn load_tar_from_url(url: &str, response: ureq::Response) -> Result<ManifestBundle> {
-    use flate2::read::GzDecoder;
-    use tar::Archive;
-
+/// Verify a tar archive downloaded from `url` against `<url>.sha256` when
+/// the server has one, then extract it
+fn load_tar_download(
+    agent: &ureq::Agent,
+    url: &str,
+    temp_file: tempfile::NamedTempFile,
+) -> Result<ManifestBundle> {
     let is_gzip = url.ends_with(".tgz") || url.ends_with(".tar.gz");
+    let checksum = fetch_checksum(agent, url)?;
+    load_tar(temp_file.path(), is_gzip, checksum.as_deref())
+}
 
-    // Download to temp file
-    let mut temp_file = tempfile::NamedTempFile::new().context("Failed to create temp file")?;
-    let mut reader = response.into_reader();
-    io::copy(&mut reader, &mut temp_file).context("Failed to download tar archive")?;
-
-    // Reopen for reading
-    let file = File::open(temp_file.path()).context("Failed to open downloaded tar")?;
-
-    // Extract
-    let extract_dir = tempfile::tempdir().context("Failed to create temp directory")?;
-    let extract_path = extract_dir.path().to_path_buf();
-
-    if is_gzip {
-        let decoder = GzDecoder::new(file);
-        let mut archive = Archive::new(decoder);
-        archive
-            .unpack(&extract_path)
-            .context("Failed to extract tar.gz archive")?;
-    } else {
-        let mut archive = Archive::new(file);
-        archive
-            .unpack(&extract_path)
-            .context("Failed to extract tar archive")?;
-    }
-
-    let manifest_path = find_manifest_in_dir(&extract_path)?;
-    let content = fs::read_to_string(&manifest_path)?;
-    let manifest = parse_auto(&content)?;
-
-    let files_dir = extract_dir.keep();
+/// The digest published at `<url>.sha256`, or None if there isn't one
+fn fetch_checksum(agent: &ureq::Agent, url: &str) -> Result<Option<String>> {
+    let checksum_url = format!("{}.sha256", url);
+    let response = match agent.get(&checksum_url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(404, _)) => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to fetch checksum: {}", checksum_url))
+        }
+    };
 
-    Ok(ManifestBundle {
-        manifest,
-        files_dir: Some(files_dir),
-    })
+    let body = read_capped(response.into_reader(), MAX_CHECKSUM_BYTES)
+        .with_context(|| format!("Failed to read checksum: {}", checksum_url))?;
+    let checksum = parse_checksum_file(&String::from_utf8_lossy(&body))
+        .with_context(|| format!("Invalid checksum file: {}", checksum_url))?;
+    Ok(Some(checksum))
 }
 
 /// Load manifest from stdin
@@ -242,33 +486,115 @@ fn load_from_stdin() -> Result<ManifestBundle> {
         .read_to_string(&mut content)
         .context("Failed to read manifest from stdin")?;
 
-    let manifest = parse_auto(&content)?;
     Ok(ManifestBundle {
-        manifest,
+        manifest: parse(&content)?,
         files_dir: None,
     })
 }
 
+/// Parse a manifest in any supported format that didn't come from a file,
+/// so has no directory for includes to resolve against
+pub fn parse(content: &str) -> Result<Manifest> {
+    include::resolve(parse_auto(content)?, None)
+}
+
 /// Parse YAML content
 fn parse_yaml(content: &str) -> Result<Manifest> {
-    serde_yaml::from_str(content).context("Failed to parse YAML manifest")
+    let mut manifest: Manifest =
+        serde_yaml::from_str(content).context("Failed to parse YAML manifest")?;
+    if let Ok(raw) = serde_yaml::from_str(content) {
+        record_unknown_fields(&mut manifest, &raw);
+    }
+    Ok(manifest)
 }
 
 /// Parse JSON content
 fn parse_json(content: &str) -> Result<Manifest> {
-    serde_json::from_str(content).context("Failed to parse JSON manifest")
+    let mut manifest: Manifest =
+        serde_json::from_str(content).context("Failed to parse JSON manifest")?;
+    if let Ok(raw) = serde_json::from_str(content) {
+        record_unknown_fields(&mut manifest, &raw);
+    }
+    Ok(manifest)
+}
+
+/// Parse TOML content
+fn parse_toml(content: &str) -> Result<Manifest> {
+    let mut manifest: Manifest =
+        toml::from_str(content).context("Failed to parse TOML manifest")?;
+    if let Ok(raw) = toml::from_str(content) {
+        record_unknown_fields(&mut manifest, &raw);
+    }
+    Ok(manifest)
+}
+
+fn record_unknown_fields(manifest: &mut Manifest, raw: &serde_json::Value) {
+    if let Ok(known) = serde_json::to_value(&*manifest) {
+        manifest.unknown_fields = validate::unknown_fields(raw, &known);
+    }
 }
 
 /// Auto-detect format and parse
 fn parse_auto(content: &str) -> Result<Manifest> {
     let trimmed = content.trim();
 
-    // JSON starts with { or [
-    if trimmed.starts_with('{') || trimmed.starts_with('[') {
-        parse_json(content)
-    } else {
-        // Assume YAML (which is a superset of JSON anyway)
-        parse_yaml(content)
+    // JSON starts with { or [, but so does a TOML table header
+    if trimmed.starts_with('{') || (trimmed.starts_with('[') && !looks_like_toml(content)) {
+        return parse_json(content);
+    }
+
+    // Assume YAML (which is a superset of JSON anyway), unless it doesn't
+    // parse and the content reads as TOML
+    match parse_yaml(content) {
+        Err(_) if looks_like_toml(content) => parse_toml(content),
+        result => result,
+    }
+}
+
+/// The raw document in a manifest file, with the format picked the way
+/// [`load_from_file`] picks it
+fn parse_document(path: &Path, content: &str) -> Result<serde_json::Value> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+
+    let document = match extension {
+        "json" => serde_json::from_str(content).context("Failed to parse JSON manifest"),
+        "toml" => toml::from_str(content).context("Failed to parse TOML manifest"),
+        "yaml" | "yml" => serde_yaml::from_str(content).context("Failed to parse YAML manifest"),
+        _ if looks_like_toml(content) => {
+            toml::from_str(content).context("Failed to parse TOML manifest")
+        }
+        _ => serde_yaml::from_str(content).context("Failed to parse YAML manifest"),
+    };
+    document.with_context(|| format!("Invalid manifest: {}", path.display()))
+}
+
+/// Whether the first meaningful line is a TOML `[table]` header or a
+/// `key = value` pair
+fn looks_like_toml(content: &str) -> bool {
+    let Some(line) = content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+    else {
+        return false;
+    };
+
+    let is_key = |key: &str| {
+        let key = key.trim();
+        !key.is_empty()
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_-.\"' ".contains(c))
+    };
+
+    if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+        let header = header.trim_start_matches('[').trim_end_matches(']');
+        return is_key(header);
+    }
+
+    match line.split_once('=') {
+        Some((key, _)) => is_key(key),
+        None => false,
     }
 }
 
@@ -313,6 +639,8 @@ disk:
       mountpoint: /
     - name: "@home"
       mountpoint: /home
+    - name: "@snapshots"
+      mountpoint: /.snapshots
 
 packages:
   base:
@@ -344,11 +672,112 @@ distro: artix
         assert_eq!(manifest.system.hostname, "workstation");
         assert_eq!(manifest.disk.device, Some("/dev/sda".into()));
         assert!(manifest.disk.encryption);
-        assert_eq!(manifest.packages.get("base").unwrap().len(), 2);
+        assert_eq!(manifest.packages.groups.get("base").unwrap().len(), 2);
         assert!(manifest.users.contains_key("polar"));
         assert_eq!(manifest.files.len(), 1);
     }
 
+    #[test]
+    fn test_minimal_toml_manifest() {
+        let toml = r#"
+[system]
+hostname = "test"
+"#;
+        let manifest = parse_toml(toml).unwrap();
+        assert_eq!(manifest.system.hostname, "test");
+        assert_eq!(manifest.system.timezone, "UTC");
+        assert_eq!(manifest.distro, "artix");
+    }
+
+    #[test]
+    fn test_full_toml_manifest() {
+        let toml = r#"
+distro = "artix"
+
+[system]
+hostname = "workstation"
+timezone = "America/Denver"
+locale = "en_US.UTF-8"
+keymap = "us"
+
+[disk]
+device = "/dev/sda"
+encryption = true
+filesystem = "btrfs"
+subvolumes = [
+  { name = "@", mountpoint = "/" },
+  { name = "@home", mountpoint = "/home" },
+  { name = "@snapshots", mountpoint = "/.snapshots" },
+]
+
+[packages]
+base = ["s6-base", "linux"]
+desktop = ["dwl"]
+
+[services]
+enable = ["dhcpcd"]
+
+[users.polar]
+shell = "/bin/zsh"
+groups = ["wheel", "video"]
+
+[[files]]
+path = "/etc/motd"
+content = "Welcome to mkOS"
+mode = "0644"
+"#;
+
+        let manifest = parse_toml(toml).unwrap();
+        assert_eq!(manifest.system.hostname, "workstation");
+        assert_eq!(manifest.disk.device, Some("/dev/sda".into()));
+        assert!(manifest.disk.encryption);
+        assert_eq!(manifest.disk.subvolumes.len(), 3);
+        assert_eq!(manifest.packages.groups.get("base").unwrap().len(), 2);
+        assert!(manifest.users.contains_key("polar"));
+        assert_eq!(manifest.files.len(), 1);
+        assert!(manifest.unknown_fields.is_empty());
+    }
+
+    #[test]
+    fn all_packages_are_ordered_and_unique() {
+        let yaml = r#"
+packages:
+  desktop: [dbus, sway, foot]
+  base: [base-devel, dbus, git]
+  tools: [git, ripgrep]
+"#;
+
+        let manifest = parse_yaml(yaml).unwrap();
+        assert_eq!(
+            manifest.all_packages(),
+            ["base-devel", "dbus", "git", "sway", "foot", "ripgrep"]
+        );
+    }
+
+    #[test]
+    fn preset_sits_under_explicit_packages() {
+        let yaml = r#"
+preset: developer
+packages:
+  base: [vim, tmux]
+  remove: [htop]
+services:
+  enable: [crond]
+"#;
+
+        let manifest = parse_yaml(yaml).unwrap();
+        assert_eq!(manifest.preset, Some(Preset::Developer));
+        assert_eq!(
+            manifest.all_packages(),
+            ["vim", "tmux", "git", "base-devel"]
+        );
+        assert_eq!(manifest.enabled_services(), ["sshd", "crond"]);
+
+        let yaml = "preset: developer\nservices:\n  disable: [sshd]\n";
+        assert!(parse_yaml(yaml).unwrap().enabled_services().is_empty());
+        assert!(parse_yaml("preset: server\n").is_err());
+    }
+
     #[test]
     fn test_auto_detect_json() {
         let json = r#"{"system": {"hostname": "test"}}"#;
@@ -363,6 +792,19 @@ distro: artix
         assert_eq!(manifest.system.hostname, "test");
     }
 
+    #[test]
+    fn test_auto_detect_toml() {
+        let manifest = parse_auto("[system]\nhostname = \"test\"\n").unwrap();
+        assert_eq!(manifest.system.hostname, "test");
+
+        let manifest = parse_auto("# mkOS\ndistro = \"void\"\n").unwrap();
+        assert_eq!(manifest.distro, "void");
+
+        assert!(looks_like_toml("[[files]]\npath = \"/etc/motd\"\n"));
+        assert!(!looks_like_toml("[1, 2]"));
+        assert!(!looks_like_toml("system:\n  hostname: test\n"));
+    }
+
     #[test]
     fn test_default_subvolumes() {
         let yaml = "system:\n  hostname: test\n";
@@ -392,4 +834,188 @@ distro: artix
             ManifestSource::File(_)
         ));
     }
+
+    #[test]
+    fn url_arg_lists_mirrors() {
+        let ManifestSource::Url(urls) =
+            ManifestSource::from_arg(Some("https://a.example/m.yaml, https://b.example/m.yaml,"))
+        else {
+            panic!("expected a URL source");
+        };
+        assert_eq!(
+            urls,
+            ["https://a.example/m.yaml", "https://b.example/m.yaml"]
+        );
+    }
+
+    fn test_policy() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 3,
+            backoff: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn fetch_retries_then_succeeds() {
+        let urls = vec!["https://a.example/m.yaml".to_string()];
+        let mut calls = 0;
+        let mut delays = Vec::new();
+
+        let (url, value) = fetch_with_retry(
+            &urls,
+            &test_policy(),
+            |d| delays.push(d),
+            |_| {
+                calls += 1;
+                if calls < 3 {
+                    Err(FetchError::Retryable(anyhow::anyhow!("timed out")))
+                } else {
+                    Ok("manifest")
+                }
+            },
+        )
+        .unwrap();
+
+        assert_eq!(url, "https://a.example/m.yaml");
+        assert_eq!(value, "manifest");
+        assert_eq!(calls, 3);
+        assert_eq!(
+            delays,
+            [Duration::from_millis(10), Duration::from_millis(20)]
+        );
+    }
+
+    #[test]
+    fn fatal_error_moves_to_next_mirror() {
+        let urls = vec![
+            "https://a.example/m.yaml".to_string(),
+            "https://b.example/m.yaml".to_string(),
+        ];
+        let mut tried = Vec::new();
+
+        let (url, _) = fetch_with_retry(
+            &urls,
+            &test_policy(),
+            |_| {},
+            |url| {
+                tried.push(url.to_string());
+                if url.starts_with("https://a.") {
+                    Err(FetchError::Fatal(anyhow::anyhow!("status code 404")))
+                } else {
+                    Ok(())
+                }
+            },
+        )
+        .unwrap();
+
+        assert_eq!(url, "https://b.example/m.yaml");
+        assert_eq!(tried, urls);
+    }
+
+    #[test]
+    fn exhausted_mirrors_report_every_failure() {
+        let urls = vec![
+            "https://a.example/m.yaml".to_string(),
+            "https://b.example/m.yaml".to_string(),
+        ];
+        let mut calls = 0;
+
+        let err = fetch_with_retry::<()>(
+            &urls,
+            &test_policy(),
+            |_| {},
+            |_| {
+                calls += 1;
+                Err(FetchError::Retryable(anyhow::anyhow!("status code 503")))
+            },
+        )
+        .unwrap_err();
+
+        assert_eq!(calls, 6);
+        let message = err.to_string();
+        assert!(message.contains("https://a.example/m.yaml: status code 503"));
+        assert!(message.contains("https://b.example/m.yaml: status code 503"));
+    }
+
+    /// A tar holding one file, with the name written into the header raw so
+    /// names `tar::Builder` would refuse can be produced
+    fn tar_with_entry(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append(&header, data).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn traversal_entries_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("extract");
+        fs::create_dir(&dest).unwrap();
+
+        let tar = tar_with_entry("../evil.sh", b"#!/bin/sh\n");
+        let err = unpack_archive(io::Cursor::new(tar), &dest).unwrap_err();
+
+        assert!(err.to_string().contains("escapes the extract directory"));
+        assert!(!dir.path().join("evil.sh").exists());
+    }
+
+    #[test]
+    fn archive_paths_must_stay_inside_root() {
+        assert!(!escapes_root(Path::new("manifest.yaml")));
+        assert!(!escapes_root(Path::new("./etc/../etc/motd")));
+        assert!(escapes_root(Path::new("etc/../../motd")));
+        assert!(escapes_root(Path::new("/etc/passwd")));
+    }
+
+    #[test]
+    fn tar_checksum_mismatch_fails_before_extraction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bundle.tar");
+        fs::write(
+            &path,
+            tar_with_entry("manifest.yaml", b"system:\n  hostname: test\n"),
+        )
+        .unwrap();
+
+        fs::write(dir.path().join("bundle.tar.sha256"), "0".repeat(64)).unwrap();
+        let err = load_from_tar_file(&path).unwrap_err();
+        assert!(format!("{:#}", err).contains("SHA-256 mismatch"));
+
+        let digest = checksum::sha256_hex(File::open(&path).unwrap()).unwrap();
+        fs::write(
+            dir.path().join("bundle.tar.sha256"),
+            format!("{}  bundle.tar\n", digest),
+        )
+        .unwrap();
+        let bundle = load_from_tar_file(&path).unwrap();
+        assert_eq!(bundle.manifest.system.hostname, "test");
+    }
+
+    #[test]
+    fn read_capped_rejects_oversized_bodies() {
+        let body = read_capped(io::Cursor::new(vec![7u8; 1024]), 1024).unwrap();
+        assert_eq!(body.len(), 1024);
+
+        let err = read_capped(io::Cursor::new(vec![7u8; 1025]), 1024).unwrap_err();
+        assert!(err.to_string().contains("limit"));
+    }
+
+    #[test]
+    fn ureq_errors_are_classified() {
+        let status =
+            |code| ureq::Error::Status(code, ureq::Response::new(code, "status", "").unwrap());
+        assert!(matches!(
+            FetchError::from(status(503)),
+            FetchError::Retryable(_)
+        ));
+        assert!(matches!(
+            FetchError::from(status(404)),
+            FetchError::Fatal(_)
+        ));
+    }
 }
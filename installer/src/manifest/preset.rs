@@ -0,0 +1,136 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A package group: name, generic package names
+pub type PresetGroup = (&'static str, &'static [&'static str]);
+
+const BASE: PresetGroup = ("base", &["curl", "man-db", "openssh", "vim"]);
+
+const DESKTOP: PresetGroup = (
+    "desktop",
+    &[
+        "dbus",
+        "pipewire",
+        "pipewire-pulse",
+        "wireplumber",
+        "xdg-desktop-portal",
+        "xdg-desktop-portal-gtk",
+        "xdg-utils",
+        "font-noto",
+        "font-noto-emoji",
+        "firefox",
+    ],
+);
+
+const DEVELOPMENT: PresetGroup = ("development", &["git", "base-devel", "htop"]);
+
+/// A starting set of packages and services for people who don't want to
+/// write a `packages` section
+///
+/// Preset groups sit underneath the manifest's own: a `packages` group of
+/// the same name replaces the preset's, and `packages.remove` drops single
+/// packages from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    Minimal,
+    Desktop,
+    Developer,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 3] = [Preset::Minimal, Preset::Desktop, Preset::Developer];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Minimal => "minimal",
+            Preset::Desktop => "desktop",
+            Preset::Developer => "developer",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Preset::Minimal => "Command line essentials and SSH",
+            Preset::Desktop => "Essentials plus audio, portals, fonts and a browser",
+            Preset::Developer => "Essentials plus git and a compiler toolchain",
+        }
+    }
+
+    /// Package groups, by generic package name
+    pub fn groups(self) -> &'static [PresetGroup] {
+        match self {
+            Preset::Minimal => &[BASE],
+            Preset::Desktop => &[BASE, DESKTOP],
+            Preset::Developer => &[BASE, DEVELOPMENT],
+        }
+    }
+
+    /// Services to enable, by generic service name
+    pub fn services(self) -> &'static [&'static str] {
+        match self {
+            Preset::Minimal => &[],
+            Preset::Desktop => &["dbus"],
+            Preset::Developer => &["sshd"],
+        }
+    }
+}
+
+impl FromStr for Preset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match Preset::ALL.into_iter().find(|p| p.name() == s) {
+            Some(preset) => Ok(preset),
+            None => bail!(
+                "Unknown preset {}. Expected one of: minimal, desktop, developer",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distro::packages::PackageDatabase;
+    use crate::distro::DistroKind;
+
+    #[test]
+    fn presets_resolve_to_mapped_packages() {
+        let db = PackageDatabase::global();
+        for preset in Preset::ALL {
+            let packages: Vec<&str> = preset
+                .groups()
+                .iter()
+                .flat_map(|(_, packages)| packages.iter().copied())
+                .collect();
+            assert!(!packages.is_empty(), "{} has no packages", preset);
+
+            for package in packages {
+                assert!(
+                    db.map_for_distro(package, DistroKind::Artix).is_some(),
+                    "{} lists {}, which Artix doesn't map",
+                    preset,
+                    package
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn presets_parse_by_name() {
+        for preset in Preset::ALL {
+            assert_eq!(preset.name().parse::<Preset>().unwrap(), preset);
+        }
+        assert!("server".parse::<Preset>().is_err());
+    }
+}
@@ -1,8 +1,23 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+use super::preset::Preset;
+use crate::secret::REDACTED;
+
+/// The `packages` group listing packages to uninstall rather than install
+pub const REMOVE_GROUP: &str = "remove";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Manifest {
+    /// Manifests merged underneath this one, relative to its directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub includes: Vec<String>,
+
+    /// Named partial manifests, one of which can be merged over the rest of
+    /// this one with [`Manifest::with_profile`]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, serde_json::Value>,
+
     #[serde(default)]
     pub system: SystemConfig,
 
@@ -25,7 +40,24 @@ pub struct Manifest {
     pub firewall: FirewallConfig,
 
     #[serde(default)]
-    pub packages: HashMap<String, Vec<String>>,
+    pub snapshots: SnapshotsConfig,
+
+    #[serde(default)]
+    pub security: SecurityConfig,
+
+    #[serde(default)]
+    pub secureboot: SecureBootManifest,
+
+    #[serde(default)]
+    pub boot: BootManifest,
+
+    /// Built-in package and service set underneath `packages` and
+    /// `services`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<Preset>,
+
+    #[serde(default)]
+    pub packages: PackagesManifest,
 
     #[serde(default)]
     pub services: ServiceConfig,
@@ -39,8 +71,20 @@ pub struct Manifest {
     #[serde(default)]
     pub scripts: ScriptConfig,
 
+    #[serde(default)]
+    pub mirror: MirrorConfig,
+
     #[serde(default = "default_distro")]
     pub distro: String,
+
+    /// Init system (s6, runit, openrc, sysvinit); the distro's default when unset
+    #[serde(default)]
+    pub init: Option<String>,
+
+    /// Fields in the source document the schema doesn't know, which were
+    /// ignored
+    #[serde(skip)]
+    pub unknown_fields: Vec<String>,
 }
 
 fn default_distro() -> String {
@@ -50,6 +94,8 @@ fn default_distro() -> String {
 impl Default for Manifest {
     fn default() -> Self {
         Self {
+            includes: Vec::new(),
+            profiles: HashMap::new(),
             system: SystemConfig::default(),
             disk: DiskConfig::default(),
             desktop: DesktopManifest::default(),
@@ -57,16 +103,35 @@ impl Default for Manifest {
             audio: AudioConfig::default(),
             network: NetworkConfig::default(),
             firewall: FirewallConfig::default(),
-            packages: HashMap::new(),
+            snapshots: SnapshotsConfig::default(),
+            security: SecurityConfig::default(),
+            secureboot: SecureBootManifest::default(),
+            boot: BootManifest::default(),
+            preset: None,
+            packages: PackagesManifest::default(),
             services: ServiceConfig::default(),
             users: HashMap::new(),
             files: Vec::new(),
             scripts: ScriptConfig::default(),
+            mirror: MirrorConfig::default(),
             distro: default_distro(),
+            init: None,
+            unknown_fields: Vec::new(),
         }
     }
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PackagesManifest {
+    /// Remove packages an earlier apply installed once no group lists them
+    #[serde(default)]
+    pub managed: bool,
+
+    /// Package groups to install, plus [`REMOVE_GROUP`] to uninstall
+    #[serde(flatten)]
+    pub groups: HashMap<String, Vec<String>>,
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DesktopManifest {
     /// Enable graphical session support
@@ -159,6 +224,10 @@ pub struct SwapManifest {
     /// Swappiness (0-100)
     #[serde(default = "default_swappiness")]
     pub swappiness: u8,
+
+    /// Resume from the swapfile after hibernating
+    #[serde(default)]
+    pub hibernation: bool,
 }
 
 fn default_swappiness() -> u8 {
@@ -239,6 +308,148 @@ pub struct NetworkConfig {
     /// Eternal Terminal configuration
     #[serde(default)]
     pub eternalterminal: Option<EtConfig>,
+
+    /// Static interface addresses (interfaces not listed use DHCP)
+    #[serde(default)]
+    pub interfaces: Vec<InterfaceConfig>,
+
+    /// Wireless network to provision with iwd
+    #[serde(default)]
+    pub wifi: Option<WifiConfig>,
+
+    /// WireGuard tunnel brought up as wg0
+    #[serde(default)]
+    pub wireguard: Option<WireguardConfig>,
+
+    /// Tailscale node joined to a tailnet with an auth key
+    #[serde(default)]
+    pub tailscale: Option<TailscaleConfig>,
+}
+
+/// WireGuard interface and the peers it talks to
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WireguardConfig {
+    /// Tunnel address in CIDR notation (e.g., "10.100.0.2/24")
+    pub address: String,
+
+    /// Base64 private key, as printed by `wg genkey`
+    pub private_key: String,
+
+    /// UDP port to listen on (random when unset)
+    #[serde(default)]
+    pub listen_port: Option<u16>,
+
+    /// DNS servers to use while the tunnel is up
+    #[serde(default)]
+    pub dns: Vec<String>,
+
+    #[serde(default)]
+    pub peers: Vec<WireguardPeer>,
+}
+
+// Keep the private key out of debug output
+impl std::fmt::Debug for WireguardConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WireguardConfig")
+            .field("address", &self.address)
+            .field("private_key", &REDACTED)
+            .field("listen_port", &self.listen_port)
+            .field("dns", &self.dns)
+            .field("peers", &self.peers)
+            .finish()
+    }
+}
+
+/// A WireGuard peer
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WireguardPeer {
+    /// Base64 public key of the peer
+    pub public_key: String,
+
+    /// Optional base64 pre-shared key for this peer
+    #[serde(default)]
+    pub preshared_key: Option<String>,
+
+    /// Addresses routed to the peer, in CIDR notation
+    pub allowed_ips: Vec<String>,
+
+    /// host:port to reach the peer at
+    #[serde(default)]
+    pub endpoint: Option<String>,
+
+    /// Seconds between keepalives, for peers behind NAT
+    #[serde(default)]
+    pub persistent_keepalive: Option<u16>,
+}
+
+// Keep the pre-shared key out of debug output
+impl std::fmt::Debug for WireguardPeer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WireguardPeer")
+            .field("public_key", &self.public_key)
+            .field(
+                "preshared_key",
+                &self.preshared_key.as_ref().map(|_| REDACTED),
+            )
+            .field("allowed_ips", &self.allowed_ips)
+            .field("endpoint", &self.endpoint)
+            .field("persistent_keepalive", &self.persistent_keepalive)
+            .finish()
+    }
+}
+
+/// Tailscale enrollment
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TailscaleConfig {
+    /// Auth key from the Tailscale admin console (`tskey-...`)
+    pub authkey: String,
+}
+
+// Keep the auth key out of debug output
+impl std::fmt::Debug for TailscaleConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TailscaleConfig")
+            .field("authkey", &REDACTED)
+            .finish()
+    }
+}
+
+/// Wireless network credentials for iwd
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WifiConfig {
+    /// Network name
+    pub ssid: String,
+
+    /// WPA passphrase (8-63 characters) or 64-digit hex pre-shared key
+    pub psk: String,
+}
+
+// Keep the PSK out of debug output
+impl std::fmt::Debug for WifiConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WifiConfig")
+            .field("ssid", &self.ssid)
+            .field("psk", &REDACTED)
+            .finish()
+    }
+}
+
+/// Static address configuration for a single network interface
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterfaceConfig {
+    /// Interface name (e.g., "eth0", "enp3s0")
+    pub name: String,
+
+    /// Address in CIDR notation (e.g., "192.168.1.10/24")
+    pub address: String,
+
+    /// Default gateway, must be inside the address's subnet
+    #[serde(default)]
+    pub gateway: Option<String>,
+
+    /// DNS servers
+    #[serde(default)]
+    pub dns: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -246,14 +457,35 @@ pub struct SshConfig {
     /// Enable SSH server
     #[serde(default = "default_true")]
     pub enabled: bool,
+
+    /// Port sshd listens on
+    #[serde(default = "default_ssh_port")]
+    pub port: u16,
+
+    /// Allow root to log in over SSH
+    #[serde(default)]
+    pub permit_root_login: bool,
+
+    /// Allow password logins; unset means only when no user has `ssh_keys`
+    #[serde(default)]
+    pub password_auth: Option<bool>,
 }
 
 impl Default for SshConfig {
     fn default() -> Self {
-        Self { enabled: true }
+        Self {
+            enabled: true,
+            port: default_ssh_port(),
+            permit_root_login: false,
+            password_auth: None,
+        }
     }
 }
 
+fn default_ssh_port() -> u16 {
+    22
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EtConfig {
     /// Enable Eternal Terminal server
@@ -283,6 +515,51 @@ fn default_et_port() -> u16 {
     2022
 }
 
+/// How the wheel group gets root
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SecurityConfig {
+    /// "sudo" or "doas"; installs default to sudo, and applies leave the
+    /// system alone unless this or `wheel_nopasswd` is set
+    #[serde(default)]
+    pub privilege_escalation: Option<String>,
+
+    /// Let wheel run commands as root without a password
+    #[serde(default)]
+    pub wheel_nopasswd: bool,
+}
+
+impl SecurityConfig {
+    /// The selected tool, sudo when none is
+    pub fn tool(&self) -> &str {
+        self.privilege_escalation.as_deref().unwrap_or("sudo")
+    }
+}
+
+/// Automatic btrfs snapshots
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SnapshotsConfig {
+    /// Take snapshots from cron, pruning each kind to its count
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<SnapshotSchedule>,
+}
+
+/// How many scheduled snapshots of each kind to keep; 0 takes none
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotSchedule {
+    #[serde(default)]
+    pub hourly: usize,
+
+    #[serde(default)]
+    pub daily: usize,
+}
+
+impl SnapshotSchedule {
+    /// Whether any kind of snapshot is scheduled
+    pub fn is_empty(&self) -> bool {
+        self.hourly == 0 && self.daily == 0
+    }
+}
+
 /// Firewall configuration using nftables
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FirewallConfig {
@@ -351,22 +628,159 @@ pub struct FirewallRule {
     #[serde(default = "default_tcp")]
     pub protocol: String,
 
-    /// Source IP restriction (CIDR notation, e.g., "192.168.1.0/24")
+    /// Source IP restriction (CIDR notation, e.g., "192.168.1.0/24" or "fd00::/64")
     #[serde(default)]
     pub source: Option<String>,
+
+    /// Address family: "ip" (IPv4 only), "ip6" (IPv6 only), or unset for both
+    #[serde(default)]
+    pub family: Option<String>,
 }
 
 fn default_tcp() -> String {
     "tcp".into()
 }
 
+/// Boot configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BootManifest {
+    /// Boot system: "dracut-efistub" or "mkinitcpio-systemd-boot"
+    #[serde(default = "default_boot_system")]
+    pub system: String,
+
+    /// dracut-efistub mode: "uki" builds Unified Kernel Images, "efistub"
+    /// boots the kernel with the command line in the NVRAM entry
+    #[serde(default = "default_boot_mode")]
+    pub mode: String,
+
+    /// Extra kernel command line arguments, one per entry
+    #[serde(default)]
+    pub cmdline: Vec<String>,
+
+    /// Pass `quiet` on the kernel command line
+    #[serde(default = "default_true")]
+    pub quiet: bool,
+
+    /// Show a plymouth boot splash, which also asks for the LUKS passphrase
+    #[serde(default)]
+    pub splash: bool,
+
+    /// Add a "mkOS (rescue)" entry that boots to a root shell
+    #[serde(default = "default_true")]
+    pub recovery_entry: bool,
+
+    /// Kernel: "linux", "linux-lts", "linux-zen" or "linux-hardened"; the
+    /// distro's default when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<String>,
+
+    /// Further kernels installed alongside `kernel`, each with its own UKI
+    /// and boot entry (dracut-efistub only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_kernels: Vec<String>,
+}
+
+impl Default for BootManifest {
+    fn default() -> Self {
+        Self {
+            system: default_boot_system(),
+            mode: default_boot_mode(),
+            cmdline: Vec::new(),
+            quiet: true,
+            splash: false,
+            recovery_entry: true,
+            kernel: None,
+            extra_kernels: Vec::new(),
+        }
+    }
+}
+
+fn default_boot_system() -> String {
+    "dracut-efistub".into()
+}
+
+fn default_boot_mode() -> String {
+    "uki".into()
+}
+
+/// Secure Boot configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SecureBootManifest {
+    /// Generate keys and sign the UKIs
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Directory with existing PK/KEK/db keys (generated when unset)
+    #[serde(default)]
+    pub keys_path: Option<String>,
+
+    /// Enroll keys into efivars when the firmware is in Setup Mode
+    #[serde(default)]
+    pub enroll: bool,
+}
+
 impl Manifest {
+    /// Every package from every group but [`REMOVE_GROUP`], each once
+    ///
+    /// Groups are taken in name order and keep their own order, so the
+    /// package manager sees the same command line on every run. Groups of
+    /// the preset come in unless a group of the same name replaces them,
+    /// minus anything listed for removal.
     pub fn all_packages(&self) -> Vec<&str> {
-        self.packages
-            .values()
-            .flat_map(|pkgs| pkgs.iter().map(|s| s.as_str()))
+        let removed = self.removed_packages();
+        let mut groups: Vec<(&str, Vec<&str>)> = self
+            .packages
+            .groups
+            .iter()
+            .filter(|(name, _)| name.as_str() != REMOVE_GROUP)
+            .map(|(name, pkgs)| (name.as_str(), pkgs.iter().map(String::as_str).collect()))
+            .collect();
+        for (name, pkgs) in self.preset.map(Preset::groups).unwrap_or_default() {
+            if !self.packages.groups.contains_key(*name) {
+                let pkgs = pkgs.iter().copied().filter(|p| !removed.contains(p));
+                groups.push((name, pkgs.collect()));
+            }
+        }
+        groups.sort_by_key(|(name, _)| *name);
+
+        let mut seen = HashSet::new();
+        groups
+            .into_iter()
+            .flat_map(|(_, pkgs)| pkgs)
+            .filter(|pkg| seen.insert(*pkg))
             .collect()
     }
+
+    /// Services to enable: the preset's, unless disabled, then the
+    /// manifest's own
+    pub fn enabled_services(&self) -> Vec<&str> {
+        let preset = self.preset.map(Preset::services).unwrap_or_default();
+        let mut services: Vec<&str> = preset
+            .iter()
+            .copied()
+            .filter(|s| !self.services.disable.iter().any(|d| d == s))
+            .collect();
+        for service in &self.services.enable {
+            if !services.contains(&service.as_str()) {
+                services.push(service);
+            }
+        }
+        services
+    }
+
+    /// Packages listed under [`REMOVE_GROUP`]
+    pub fn removed_packages(&self) -> Vec<&str> {
+        self.packages
+            .groups
+            .get(REMOVE_GROUP)
+            .map(|pkgs| pkgs.iter().map(String::as_str).collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether any user logs in with SSH keys
+    pub fn has_ssh_keys(&self) -> bool {
+        self.users.values().any(|user| !user.ssh_keys.is_empty())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -377,11 +791,27 @@ pub struct SystemConfig {
     #[serde(default = "default_timezone")]
     pub timezone: String,
 
+    /// Default locale (`LANG`)
     #[serde(default = "default_locale")]
     pub locale: String,
 
+    /// More locales to generate besides `locale`
+    #[serde(default)]
+    pub locales: Vec<String>,
+
+    /// Console keymap, also mapped to the graphical sessions' layout
     #[serde(default = "default_keymap")]
     pub keymap: String,
+
+    /// XKB layout for graphical sessions, as `layout` or `layout(variant)`,
+    /// when the one mapped from `keymap` is wrong
+    #[serde(default)]
+    pub keymap_x11: Option<String>,
+
+    /// Console font (e.g., "ter-132n" for HiDPI screens), set from the
+    /// initramfs on; the kernel's built-in font when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub console_font: Option<String>,
 }
 
 impl Default for SystemConfig {
@@ -390,11 +820,27 @@ impl Default for SystemConfig {
             hostname: default_hostname(),
             timezone: default_timezone(),
             locale: default_locale(),
+            locales: Vec::new(),
             keymap: default_keymap(),
+            keymap_x11: None,
+            console_font: None,
         }
     }
 }
 
+impl SystemConfig {
+    /// `locale` and then `locales`, each once
+    pub fn all_locales(&self) -> Vec<&str> {
+        let mut all = vec![self.locale.as_str()];
+        for locale in &self.locales {
+            if !all.contains(&locale.as_str()) {
+                all.push(locale);
+            }
+        }
+        all
+    }
+}
+
 fn default_hostname() -> String {
     "mkos".into()
 }
@@ -416,27 +862,80 @@ pub struct DiskConfig {
     #[serde(default)]
     pub device: Option<String>,
 
+    /// Whole disks to mirror a btrfs root across with RAID1, instead of
+    /// `device`; the first holds the EFI partition
+    #[serde(default)]
+    pub devices: Vec<String>,
+
     #[serde(default = "default_true")]
     pub encryption: bool,
 
     #[serde(default = "default_encryption_type")]
     pub encryption_type: String,
 
+    /// What the LUKS passphrase must contain, checked wherever it's entered
+    #[serde(default)]
+    pub passphrase_policy: PassphrasePolicy,
+
+    /// Keep the LUKS header in this file, on other media than the disk
+    #[serde(default)]
+    pub detached_header: Option<String>,
+
+    /// EFI partition size in MB; None keeps the default
+    #[serde(default)]
+    pub efi_size_mb: Option<u64>,
+
+    /// EFI partition volume label; None keeps the default
+    #[serde(default)]
+    pub efi_label: Option<String>,
+
+    /// Keep the existing partitions and EFI partition, installing to
+    /// partition 2 (for dual-booting)
+    #[serde(default)]
+    pub reuse_esp: bool,
+
+    /// Discard (SSDs) or random-fill (spinning disks) the whole disk before
+    /// partitioning; slow on large disks
+    #[serde(default)]
+    pub secure_erase: bool,
+
     #[serde(default = "default_filesystem")]
     pub filesystem: String,
 
     #[serde(default)]
     pub subvolumes: Vec<SubvolumeConfig>,
+
+    /// Btrfs compression (e.g., "zstd:3", "lzo", "none"); None keeps the default
+    #[serde(default)]
+    pub compression: Option<String>,
+
+    /// Enable btrfs quotas, so snapshot sizes can be shown and limited
+    #[serde(default)]
+    pub btrfs_quota: bool,
+
+    /// Space all snapshots may take together (e.g., "50G"); needs btrfs_quota
+    #[serde(default)]
+    pub snapshots_limit: Option<String>,
 }
 
 impl Default for DiskConfig {
     fn default() -> Self {
         Self {
             device: None,
+            devices: Vec::new(),
             encryption: true,
             encryption_type: default_encryption_type(),
+            passphrase_policy: PassphrasePolicy::default(),
+            detached_header: None,
+            efi_size_mb: None,
+            efi_label: None,
+            reuse_esp: false,
+            secure_erase: false,
             filesystem: default_filesystem(),
             subvolumes: default_subvolumes(),
+            compression: None,
+            btrfs_quota: false,
+            snapshots_limit: None,
         }
     }
 }
@@ -445,6 +944,43 @@ fn default_encryption_type() -> String {
     "luks2".into()
 }
 
+/// Requirements for the disk encryption passphrase
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PassphrasePolicy {
+    /// Fewest characters accepted
+    #[serde(default = "default_min_passphrase_length")]
+    pub min_length: usize,
+
+    #[serde(default)]
+    pub require_uppercase: bool,
+
+    #[serde(default)]
+    pub require_lowercase: bool,
+
+    #[serde(default)]
+    pub require_digit: bool,
+
+    /// Anything that isn't a letter or digit, including spaces
+    #[serde(default)]
+    pub require_symbol: bool,
+}
+
+impl Default for PassphrasePolicy {
+    fn default() -> Self {
+        Self {
+            min_length: default_min_passphrase_length(),
+            require_uppercase: false,
+            require_lowercase: false,
+            require_digit: false,
+            require_symbol: false,
+        }
+    }
+}
+
+fn default_min_passphrase_length() -> usize {
+    8
+}
+
 fn default_filesystem() -> String {
     "btrfs".into()
 }
@@ -485,7 +1021,7 @@ pub struct ServiceConfig {
     pub disable: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct UserConfig {
     #[serde(default = "default_shell")]
     pub shell: String,
@@ -501,12 +1037,53 @@ pub struct UserConfig {
 
     #[serde(default)]
     pub home: Option<String>,
+
+    /// Git repository cloned into the home directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dotfiles: Option<DotfilesConfig>,
+}
+
+// Keep the password hash out of debug output, since hashes of weak
+// passwords crack
+impl std::fmt::Debug for UserConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserConfig")
+            .field("shell", &self.shell)
+            .field("groups", &self.groups)
+            .field(
+                "password_hash",
+                &self.password_hash.as_ref().map(|_| REDACTED),
+            )
+            .field("ssh_keys", &self.ssh_keys)
+            .field("home", &self.home)
+            .field("dotfiles", &self.dotfiles)
+            .finish()
+    }
 }
 
 fn default_shell() -> String {
     "/bin/bash".into()
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DotfilesConfig {
+    /// HTTPS or SSH URL, including `git@host:path`
+    pub repo: String,
+
+    /// Branch to check out instead of the repository's default
+    #[serde(default)]
+    pub branch: Option<String>,
+
+    /// Where to clone, relative to the home directory; `.dotfiles` when unset
+    #[serde(default)]
+    pub dest: Option<String>,
+
+    /// Shell command run as the user inside the clone after cloning, such
+    /// as `./install.sh`
+    #[serde(default)]
+    pub post_clone: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileConfig {
     pub path: String,
@@ -525,6 +1102,37 @@ pub struct FileConfig {
 
     #[serde(default)]
     pub group: Option<String>,
+
+    /// Expected SHA-256 of the content, as hex; checked before writing
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+/// Package repository to install from instead of the distro's default
+///
+/// Both forms point at the root of a mirror, laid out like the distro's
+/// official ones.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MirrorConfig {
+    /// Mirror URL
+    #[serde(default)]
+    pub url: Option<String>,
+
+    /// Local copy of a mirror, for offline installs
+    #[serde(default)]
+    pub local_path: Option<String>,
+
+    /// Only consider mirrorlist entries for this country
+    #[serde(default)]
+    pub country: Option<String>,
+
+    /// Pick the mirror that answers fastest instead of prompting
+    #[serde(default)]
+    pub benchmark: bool,
+
+    /// Packages pacman downloads at once; None keeps its default
+    #[serde(default)]
+    pub parallel_downloads: Option<u8>,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
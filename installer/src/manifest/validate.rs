@@ -0,0 +1,470 @@
+use anyhow::Result;
+use serde_json::Value;
+
+use super::{FileConfig, Manifest};
+use crate::boot::{validate_cmdline, BootKind, BootMode, Kernel};
+use crate::crypt::{validate_compression, BtrfsLayout, FilesystemKind, Subvolume};
+use crate::distro::DistroKind;
+use crate::init::InitKind;
+use crate::install::SwapConfig;
+
+impl Manifest {
+    /// Every problem that would stop an install or apply, empty when the
+    /// manifest is usable
+    ///
+    /// Only the manifest itself is checked; nothing on the running system is
+    /// looked at.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let mut check = |field: &str, result: Result<()>| {
+            if let Err(e) = result {
+                errors.push(format!("{}: {:#}", field, e));
+            }
+        };
+
+        check("distro", self.distro.parse::<DistroKind>().map(drop));
+
+        if let Some(init) = &self.init {
+            // Only checked against the distro when that parsed
+            let distro = self.distro.parse::<DistroKind>().ok();
+            check(
+                "init",
+                init.parse::<InitKind>().and_then(|init| match distro {
+                    Some(distro) => distro.check_init(init),
+                    None => Ok(()),
+                }),
+            );
+        }
+
+        check("disk.filesystem", self.check_filesystem());
+        check("disk.subvolumes", self.check_subvolumes());
+        check(
+            "disk.passphrase_policy",
+            crate::crypt::validate_passphrase_policy(&self.disk.passphrase_policy),
+        );
+        check(
+            "disk.btrfs_quota",
+            crate::crypt::check_quota(&self.disk).map(drop),
+        );
+        if let Some(compression) = &self.disk.compression {
+            check("disk.compression", validate_compression(compression));
+        }
+
+        check(
+            "boot.system",
+            self.boot.system.parse::<BootKind>().map(drop),
+        );
+        // Only checked against the boot system when that parsed
+        if let Ok(boot) = self.boot.system.parse::<BootKind>() {
+            if let Ok(distro) = self.distro.parse::<DistroKind>() {
+                check("boot.system", distro.check_boot(boot));
+            }
+            check(
+                "disk.devices",
+                crate::disk::check_raid_devices(&self.disk, boot, self.swap.swapfile),
+            );
+        }
+        check(
+            "boot.mode",
+            self.boot.mode.parse::<BootMode>().and_then(|mode| {
+                match self.boot.system.parse::<BootKind>() {
+                    Ok(boot) if mode != BootMode::Uki && boot != BootKind::DracutEfistub => Err(
+                        anyhow::anyhow!("{} needs the dracut-efistub boot system", mode.name()),
+                    ),
+                    _ if mode == BootMode::Efistub && self.secureboot.enabled => {
+                        Err(anyhow::anyhow!("efistub can't be used with Secure Boot"))
+                    }
+                    _ => Ok(()),
+                }
+            }),
+        );
+        check("boot.cmdline", validate_cmdline(&self.boot.cmdline));
+        if let Some(kernel) = &self.boot.kernel {
+            // Only checked against the distro when that parsed
+            let distro = self.distro.parse::<DistroKind>().ok();
+            check(
+                "boot.kernel",
+                kernel.parse::<Kernel>().and_then(|kernel| match distro {
+                    Some(distro) => distro.check_kernel(kernel),
+                    None => Ok(()),
+                }),
+            );
+        }
+        if self.boot.splash {
+            if let Ok(distro) = self.distro.parse::<DistroKind>() {
+                check("boot.splash", distro.check_splash());
+            }
+        }
+        if !self.boot.extra_kernels.is_empty() {
+            let distro = self.distro.parse::<DistroKind>().ok();
+            check(
+                "boot.extra_kernels",
+                self.boot.extra_kernels.iter().try_for_each(|kernel| {
+                    let kernel = kernel.parse::<Kernel>()?;
+                    match distro {
+                        Some(distro) => distro.check_kernel(kernel),
+                        None => Ok(()),
+                    }
+                }),
+            );
+            if self
+                .boot
+                .system
+                .parse::<BootKind>()
+                .is_ok_and(|boot| boot != BootKind::DracutEfistub)
+            {
+                check(
+                    "boot.extra_kernels",
+                    Err(anyhow::anyhow!(
+                        "extra kernels need the dracut-efistub boot system"
+                    )),
+                );
+            }
+        }
+
+        let swap = SwapConfig {
+            swapfile_enabled: self.swap.swapfile,
+            hibernation: self.swap.hibernation,
+            ..Default::default()
+        };
+        check("swap", crate::swap::validate_hibernation(&swap));
+
+        check(
+            "network.interfaces",
+            crate::network::validate_interfaces(&self.network.interfaces),
+        );
+        if let Some(wifi) = &self.network.wifi {
+            check("network.wifi", crate::network::validate_wifi(wifi));
+        }
+        if let Some(wireguard) = &self.network.wireguard {
+            check(
+                "network.wireguard",
+                crate::network::validate_wireguard(wireguard),
+            );
+        }
+        if let Some(tailscale) = &self.network.tailscale {
+            check(
+                "network.tailscale",
+                crate::network::validate_tailscale(tailscale),
+            );
+        }
+        if let Some(ssh) = &self.network.ssh {
+            check("network.ssh", crate::network::validate_ssh(ssh));
+        }
+        check(
+            "firewall",
+            crate::firewall::validate_firewall(&self.firewall),
+        );
+        if let Some(schedule) = &self.snapshots.schedule {
+            check(
+                "snapshots.schedule",
+                crate::snapshot_schedule::validate_schedule(schedule, &self.disk.filesystem),
+            );
+        }
+        check("mirror", crate::mirror::validate_mirror(&self.mirror));
+        if self.mirror.url.is_some() || self.mirror.local_path.is_some() {
+            if let Ok(distro) = self.distro.parse::<DistroKind>() {
+                check("mirror", distro.check_repo());
+            }
+        }
+        check(
+            "security.privilege_escalation",
+            crate::privilege::validate_security(&self.security).and_then(|()| {
+                match self.distro.parse::<DistroKind>() {
+                    Ok(distro) => crate::privilege::check_packaged(&self.security, distro),
+                    Err(_) => Ok(()),
+                }
+            }),
+        );
+
+        check("packages.remove", self.check_removed_packages());
+
+        for (i, file) in self.files.iter().enumerate() {
+            check(&format!("files[{}]", i), check_file(file));
+        }
+
+        errors
+    }
+
+    fn check_filesystem(&self) -> Result<()> {
+        let filesystem: FilesystemKind = self.disk.filesystem.parse()?;
+        if !self.disk.subvolumes.is_empty() && !filesystem.create().supports_subvolumes() {
+            anyhow::bail!("{} does not support subvolumes", filesystem.name());
+        }
+        Ok(())
+    }
+
+    fn check_removed_packages(&self) -> Result<()> {
+        let installed = self.all_packages();
+        let both: Vec<&str> = self
+            .removed_packages()
+            .into_iter()
+            .filter(|p| installed.contains(p))
+            .collect();
+        if !both.is_empty() {
+            anyhow::bail!("also listed for install: {}", both.join(", "));
+        }
+        Ok(())
+    }
+
+    fn check_subvolumes(&self) -> Result<()> {
+        if self.disk.subvolumes.is_empty() {
+            return Ok(());
+        }
+        let subvolumes = self
+            .disk
+            .subvolumes
+            .iter()
+            .map(|s| Subvolume {
+                name: s.name.clone(),
+                mountpoint: s.mountpoint.clone(),
+            })
+            .collect();
+        BtrfsLayout::from_subvolumes(subvolumes).map(drop)
+    }
+}
+
+fn check_file(file: &FileConfig) -> Result<()> {
+    if !file.path.starts_with('/') {
+        anyhow::bail!("path {} is not absolute", file.path);
+    }
+
+    match (&file.content, &file.source) {
+        (None, None) => anyhow::bail!("{} has no content or source", file.path),
+        (Some(_), Some(_)) => anyhow::bail!(
+            "{} has both content and source; only content would be used",
+            file.path
+        ),
+        _ => {}
+    }
+
+    if let Some(mode) = &file.mode {
+        let valid = !mode.is_empty() && mode.len() <= 4 && mode.chars().all(|c| c.is_digit(8));
+        if !valid {
+            anyhow::bail!("{} has invalid mode {}", file.path, mode);
+        }
+    }
+
+    if let Some(sha256) = &file.sha256 {
+        super::checksum::parse_checksum_file(sha256)
+            .map_err(|_| anyhow::anyhow!("{} has an invalid sha256", file.path))?;
+    }
+
+    Ok(())
+}
+
+impl Manifest {
+    /// A warning per field the schema doesn't know, usually a typo whose
+    /// setting would otherwise be lost without a word
+    pub fn unknown_field_warnings(&self) -> Vec<String> {
+        self.unknown_fields
+            .iter()
+            .map(|field| format!("Unknown manifest field {} was ignored", field))
+            .collect()
+    }
+}
+
+/// Paths of fields in `raw` that deserializing into a [`Manifest`] dropped
+///
+/// `known` is the parsed manifest serialized back, which carries every
+/// field the schema has.
+pub(super) fn unknown_fields(raw: &Value, known: &Value) -> Vec<String> {
+    let mut unknown = Vec::new();
+    collect_unknown(raw, known, "", &mut unknown);
+    unknown
+}
+
+fn collect_unknown(raw: &Value, known: &Value, path: &str, unknown: &mut Vec<String>) {
+    match (raw, known) {
+        (Value::Object(raw), Value::Object(known)) => {
+            for (key, value) in raw {
+                let child = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                match known.get(key) {
+                    Some(known) => collect_unknown(value, known, &child, unknown),
+                    None => unknown.push(child),
+                }
+            }
+        }
+        (Value::Array(raw), Value::Array(known)) => {
+            for (i, (raw, known)) in raw.iter().zip(known).enumerate() {
+                collect_unknown(raw, known, &format!("{}[{}]", path, i), unknown);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{parse_json, parse_yaml};
+
+    #[test]
+    fn default_manifest_is_valid() {
+        assert!(Manifest::default().validate().is_empty());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let manifest = parse_yaml(
+            r#"
+distro: arch
+init: upstart
+disk:
+  filesystem: zfs
+boot:
+  system: grub
+mirror:
+  url: https://mirror.example/artix
+  local_path: /mnt/repo
+files:
+  - path: etc/motd
+    content: hi
+  - path: /etc/issue
+    mode: "0999"
+    source: issue
+"#,
+        )
+        .unwrap();
+
+        let errors = manifest.validate();
+        let fields: Vec<&str> = errors
+            .iter()
+            .map(|e| e.split_once(':').unwrap().0)
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "distro",
+                "init",
+                "disk.filesystem",
+                "boot.system",
+                "mirror",
+                "files[0]",
+                "files[1]"
+            ]
+        );
+    }
+
+    #[test]
+    fn raid_devices_are_checked_against_the_boot_system() {
+        let manifest = parse_yaml(
+            "disk:\n  devices: [/dev/sda, /dev/sdb]\nboot:\n  system: mkinitcpio-systemd-boot\n",
+        )
+        .unwrap();
+        let errors = manifest.validate();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("disk.devices: "));
+
+        let manifest = parse_yaml("disk:\n  devices: [/dev/sda, /dev/sdb]\n").unwrap();
+        assert!(manifest.validate().is_empty());
+    }
+
+    #[test]
+    fn init_is_checked_against_the_distro() {
+        let manifest = parse_yaml("distro: void\ninit: openrc\n").unwrap();
+        let errors = manifest.validate();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("init: Void Linux does not support"));
+    }
+
+    #[test]
+    fn boot_system_is_checked_against_the_distro() {
+        let manifest = parse_yaml("distro: devuan\nboot:\n  system: systemd-boot\n").unwrap();
+        let errors = manifest.validate();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("boot.system: Devuan GNU+Linux does not package"));
+
+        let manifest = parse_yaml("boot:\n  system: systemd-boot\n").unwrap();
+        assert!(manifest.validate().is_empty());
+    }
+
+    #[test]
+    fn efistub_mode_refuses_secureboot() {
+        let manifest =
+            parse_yaml("boot:\n  mode: efistub\nsecureboot:\n  enabled: true\n").unwrap();
+        assert_eq!(
+            manifest.validate(),
+            ["boot.mode: efistub can't be used with Secure Boot"]
+        );
+
+        let manifest = parse_yaml("boot:\n  mode: uki\nsecureboot:\n  enabled: true\n").unwrap();
+        assert!(manifest.validate().is_empty());
+    }
+
+    #[test]
+    fn splash_needs_plymouth_from_the_distro() {
+        let manifest = parse_yaml("distro: slackware\nboot:\n  splash: true\n").unwrap();
+        assert_eq!(
+            manifest.validate(),
+            ["boot.splash: Slackware Linux does not package plymouth"]
+        );
+
+        let manifest = parse_yaml("boot:\n  splash: true\n").unwrap();
+        assert!(manifest.validate().is_empty());
+    }
+
+    #[test]
+    fn packages_cannot_be_installed_and_removed() {
+        let manifest =
+            parse_yaml("packages:\n  base: [git, vim]\n  remove: [nano, vim]\n").unwrap();
+        assert!(!manifest.packages.managed);
+        assert_eq!(manifest.all_packages(), ["git", "vim"]);
+        assert_eq!(manifest.removed_packages(), ["nano", "vim"]);
+        assert_eq!(
+            manifest.validate(),
+            ["packages.remove: also listed for install: vim"]
+        );
+    }
+
+    #[test]
+    fn managed_is_not_a_package_group() {
+        let manifest = parse_yaml("packages:\n  managed: true\n  base: [git]\n").unwrap();
+        assert!(manifest.packages.managed);
+        assert_eq!(manifest.all_packages(), ["git"]);
+        assert!(manifest.unknown_fields.is_empty());
+    }
+
+    #[test]
+    fn misspelled_top_level_field_is_warned_about() {
+        let manifest = parse_yaml("pakages:\n  base: [git]\n").unwrap();
+        assert!(manifest.packages.groups.is_empty());
+        assert_eq!(
+            manifest.unknown_field_warnings(),
+            ["Unknown manifest field pakages was ignored"]
+        );
+
+        let json = parse_json(r#"{"distro": "void", "hostnme": "x"}"#).unwrap();
+        assert_eq!(json.unknown_fields, ["hostnme"]);
+    }
+
+    #[test]
+    fn unknown_fields_are_found() {
+        let manifest = parse_yaml(
+            r#"
+sytem:
+  hostname: typo
+desktop:
+  enabled: true
+  greter: tuigreet
+files:
+  - path: /etc/motd
+    content: hi
+    owner: root
+    permissions: "0644"
+packages:
+  base: [git]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            manifest.unknown_fields,
+            ["desktop.greter", "files[0].permissions", "sytem"]
+        );
+    }
+}
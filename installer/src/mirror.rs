@@ -1,18 +1,82 @@
 use anyhow::{Context, Result};
 use std::fs;
 use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cmd;
+use crate::manifest::MirrorConfig;
+
+const MIRRORLIST_PATH: &str = "/etc/pacman.d/mirrorlist";
+
+const PACMAN_CONF_PATH: &str = "/etc/pacman.conf";
+
+/// Mirrors slower than this to accept a connection aren't worth ranking
+const BENCHMARK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// A working mirror syncs its databases in seconds; a dead one hangs
+const MIRROR_SYNC_TIMEOUT: Duration = Duration::from_secs(120);
 
 #[derive(Debug, Clone)]
 pub struct Mirror {
     pub name: String,
     pub url: String,
+    /// Comment heading the mirror's group in the mirrorlist, usually a country
+    pub section: Option<String>,
+}
+
+/// Package repository chosen in the manifest
+#[derive(Debug, Clone, PartialEq)]
+pub enum Repo {
+    Url(String),
+    /// A directory on the live system, so nothing is downloaded
+    Local(PathBuf),
+}
+
+impl Repo {
+    /// The manifest's repository, None to use the distro's default
+    pub fn from_config(config: &MirrorConfig) -> Result<Option<Self>> {
+        match (&config.url, &config.local_path) {
+            (Some(_), Some(_)) => anyhow::bail!("mirror.url and mirror.local_path are exclusive"),
+            (Some(url), None) => Ok(Some(Repo::Url(url.trim_end_matches('/').to_string()))),
+            (None, Some(path)) => Ok(Some(Repo::Local(PathBuf::from(path)))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// As a URL, with `file://` for a local repository
+    pub fn url(&self) -> String {
+        match self {
+            Repo::Url(url) => url.clone(),
+            Repo::Local(path) => format!("file://{}", path.display()),
+        }
+    }
+
+    /// As a URL, or the bare path for a local repository
+    pub fn location(&self) -> String {
+        match self {
+            Repo::Url(url) => url.clone(),
+            Repo::Local(path) => path.display().to_string(),
+        }
+    }
+
+    /// pacman `Server` line value for a mirror root
+    pub fn pacman_server(&self) -> String {
+        format!("{}/$repo/os/$arch", self.url())
+    }
 }
 
 pub fn parse_mirrorlist(path: &str) -> Result<Vec<Mirror>> {
     let content = fs::read_to_string(path).context("Failed to read mirrorlist")?;
+    Ok(parse_mirrorlist_content(&content))
+}
 
+fn parse_mirrorlist_content(content: &str) -> Vec<Mirror> {
     let mut mirrors = Vec::new();
     let mut current_name = String::new();
+    let mut section = None;
 
     for line in content.lines() {
         let line = line.trim();
@@ -22,6 +86,7 @@ pub fn parse_mirrorlist(path: &str) -> Result<Vec<Mirror>> {
             let comment = line.trim_start_matches('#').trim();
             if !comment.is_empty() && !comment.starts_with("Server") {
                 current_name = comment.to_string();
+                section = Some(comment.to_string());
             }
         } else if line.starts_with("Server") {
             // Server line: Server = https://...
@@ -38,13 +103,17 @@ pub fn parse_mirrorlist(path: &str) -> Result<Vec<Mirror>> {
                     current_name.clone()
                 };
 
-                mirrors.push(Mirror { name, url });
+                mirrors.push(Mirror {
+                    name,
+                    url,
+                    section: section.clone(),
+                });
                 current_name.clear();
             }
         }
     }
 
-    Ok(mirrors)
+    mirrors
 }
 
 pub fn select_mirror(mirrors: &[Mirror]) -> Result<&Mirror> {
@@ -110,20 +179,397 @@ pub fn write_mirrorlist(path: &str, mirror: &Mirror) -> Result<()> {
     Ok(())
 }
 
-pub fn setup_mirror() -> Result<()> {
-    let mirrorlist_path = "/etc/pacman.d/mirrorlist";
+/// Check the manifest's repository and download settings
+pub fn validate_mirror(config: &MirrorConfig) -> Result<()> {
+    Repo::from_config(config)?;
+    if config.parallel_downloads == Some(0) {
+        anyhow::bail!("mirror.parallel_downloads must be at least 1");
+    }
+    Ok(())
+}
+
+/// Whether the manifest says anything about mirrors, so failing to honor
+/// it should stop the install
+pub fn is_configured(config: &MirrorConfig) -> bool {
+    config.url.is_some()
+        || config.local_path.is_some()
+        || config.country.is_some()
+        || config.benchmark
+}
+
+/// Pick the mirror the manifest asks for: its explicit repository, the
+/// fastest or first mirror for `country`, or an interactive choice
+///
+/// None when the mirrorlist is empty and pacman's default should be kept.
+pub fn choose_mirror(config: &MirrorConfig) -> Result<Option<Mirror>> {
+    if let Some(repo) = Repo::from_config(config)? {
+        let name = match repo {
+            Repo::Url(_) => "manifest mirror",
+            Repo::Local(_) => "local repository",
+        };
+        return Ok(Some(Mirror {
+            name: name.into(),
+            url: repo.pacman_server(),
+            section: None,
+        }));
+    }
+
+    let mut mirrors = parse_mirrorlist(MIRRORLIST_PATH)?;
 
-    let mirrors = parse_mirrorlist(mirrorlist_path)?;
+    if let Some(country) = &config.country {
+        mirrors = filter_by_country(mirrors, country);
+        if mirrors.is_empty() {
+            anyhow::bail!("No mirrors in {} match {}", MIRRORLIST_PATH, country);
+        }
+    }
 
     if mirrors.is_empty() {
         println!("No mirrors found in mirrorlist, using default");
-        return Ok(());
+        return Ok(None);
+    }
+
+    if config.benchmark {
+        println!("Benchmarking {} mirrors...", mirrors.len());
+        let ranked = rank_mirrors(benchmark(mirrors));
+        let Some((fastest, latency)) = ranked.into_iter().next() else {
+            anyhow::bail!("No mirror answered within {}s", BENCHMARK_TIMEOUT.as_secs());
+        };
+        println!("Fastest: {} ({}ms)", fastest.name, latency.as_millis());
+        return Ok(Some(fastest));
+    }
+
+    if config.country.is_some() {
+        return Ok(mirrors.into_iter().next());
+    }
+
+    select_mirror(&mirrors).cloned().map(Some)
+}
+
+/// Mirrors whose mirrorlist heading or name mention `country`
+fn filter_by_country(mirrors: Vec<Mirror>, country: &str) -> Vec<Mirror> {
+    let country = country.to_lowercase();
+    mirrors
+        .into_iter()
+        .filter(|m| {
+            m.section
+                .as_deref()
+                .is_some_and(|s| s.to_lowercase().contains(&country))
+                || m.name.to_lowercase().contains(&country)
+        })
+        .collect()
+}
+
+/// Time a TCP connection to each mirror, all at once
+fn benchmark(mirrors: Vec<Mirror>) -> Vec<(Mirror, Option<Duration>)> {
+    thread::scope(|scope| {
+        let handles: Vec<_> = mirrors
+            .into_iter()
+            .map(|mirror| {
+                scope.spawn(move || {
+                    let latency = connect_time(&mirror.url);
+                    (mirror, latency)
+                })
+            })
+            .collect();
+
+        handles.into_iter().filter_map(|h| h.join().ok()).collect()
+    })
+}
+
+fn connect_time(url: &str) -> Option<Duration> {
+    let (host, port) = url_host_port(url)?;
+    let addr = (host.as_str(), port).to_socket_addrs().ok()?.next()?;
+
+    let start = Instant::now();
+    TcpStream::connect_timeout(&addr, BENCHMARK_TIMEOUT).ok()?;
+    Some(start.elapsed())
+}
+
+/// Fastest first, dropping mirrors that didn't answer
+pub fn rank_mirrors(measured: Vec<(Mirror, Option<Duration>)>) -> Vec<(Mirror, Duration)> {
+    let mut reachable: Vec<(Mirror, Duration)> = measured
+        .into_iter()
+        .filter_map(|(mirror, latency)| latency.map(|l| (mirror, l)))
+        .collect();
+    reachable.sort_by_key(|(_, latency)| *latency);
+    reachable
+}
+
+/// Host and port of an http(s) URL, defaulting the port from the scheme
+pub fn url_host_port(url: &str) -> Option<(String, u16)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let default_port = match scheme {
+        "https" => 443,
+        "http" => 80,
+        _ => return None,
+    };
+
+    let authority = rest.split('/').next()?;
+    if authority.is_empty() {
+        return None;
+    }
+
+    match authority.rsplit_once(':') {
+        Some((host, port)) => Some((host.to_string(), port.parse().ok()?)),
+        None => Some((authority.to_string(), default_port)),
+    }
+}
+
+/// Make `mirror` the live system's only pacman mirror and sync against it
+pub fn setup_mirror(mirror: &Mirror) -> Result<()> {
+    let original = fs::read_to_string(MIRRORLIST_PATH)?;
+    write_mirrorlist(MIRRORLIST_PATH, mirror)?;
+
+    // Sync now so a dead mirror fails here rather than hanging the install
+    if let Err(e) = cmd::run_with_timeout("pacman", ["-Sy"], MIRROR_SYNC_TIMEOUT) {
+        fs::write(MIRRORLIST_PATH, original).context("Failed to restore mirrorlist")?;
+        return Err(e.context(format!("Mirror {} is not usable", mirror.name)));
     }
 
-    let selected = select_mirror(&mirrors)?;
-    println!("\nSelected: {}", selected.name);
+    Ok(())
+}
+
+/// Let the live system's pacman, which basestrap runs, download `downloads`
+/// packages at once
+pub fn setup_parallel_downloads(downloads: u8) -> Result<()> {
+    write_parallel_downloads(Path::new(PACMAN_CONF_PATH), downloads)
+}
+
+/// Set ParallelDownloads in the target's pacman.conf, so the installed
+/// system keeps it
+pub fn install_parallel_downloads(target: &Path, downloads: u8) -> Result<()> {
+    write_parallel_downloads(
+        &target.join(PACMAN_CONF_PATH.trim_start_matches('/')),
+        downloads,
+    )
+}
+
+fn write_parallel_downloads(path: &Path, downloads: u8) -> Result<()> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    fs::write(path, with_parallel_downloads(&content, downloads))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
 
-    write_mirrorlist(mirrorlist_path, selected)?;
+/// `pacman.conf` with ParallelDownloads set, replacing the line (commented
+/// out or not) if there is one, or adding it to `[options]`
+fn with_parallel_downloads(content: &str, downloads: u8) -> String {
+    let setting = format!("ParallelDownloads = {}", downloads);
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
 
+    let existing = lines.iter().position(|line| {
+        line.trim_start_matches(|c: char| c == '#' || c.is_whitespace())
+            .starts_with("ParallelDownloads")
+    });
+    match existing {
+        Some(i) => lines[i] = setting,
+        None => match lines.iter().position(|line| line.trim() == "[options]") {
+            Some(i) => lines.insert(i + 1, setting),
+            None => {
+                lines.insert(0, "[options]".into());
+                lines.insert(1, setting);
+            }
+        },
+    }
+
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
+
+/// Enable Artix's [lib32] repository in the live system's pacman.conf,
+/// which installs into the target, and in the target's own
+pub fn enable_lib32(target: &Path) -> Result<()> {
+    let target_conf = target.join(PACMAN_CONF_PATH.trim_start_matches('/'));
+    for path in [Path::new(PACMAN_CONF_PATH), &target_conf] {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        fs::write(path, with_lib32_repo(&content))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
     Ok(())
 }
+
+/// `pacman.conf` with [lib32] enabled, uncommenting the section pacman
+/// ships if there is one
+fn with_lib32_repo(content: &str) -> String {
+    let uncommented = |line: &str| line.trim_start_matches(['#', ' ']).trim_end().to_string();
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+
+    match lines.iter().position(|line| uncommented(line) == "[lib32]") {
+        Some(i) => {
+            lines[i] = "[lib32]".into();
+            if let Some(include) = lines.get_mut(i + 1) {
+                if uncommented(include).starts_with("Include") {
+                    *include = uncommented(include);
+                }
+            }
+        }
+        None => lines.extend([
+            String::new(),
+            "[lib32]".into(),
+            format!("Include = {}", MIRRORLIST_PATH),
+        ]),
+    }
+
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
+
+/// Write `mirror` as the target's mirrorlist, so the installed system keeps
+/// using it
+pub fn install_mirrorlist(target: &Path, mirror: &Mirror) -> Result<()> {
+    let path = target.join(MIRRORLIST_PATH.trim_start_matches('/'));
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_mirrorlist(&path.to_string_lossy(), mirror)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repo_from_manifest() {
+        let mut config = MirrorConfig::default();
+        assert_eq!(Repo::from_config(&config).unwrap(), None);
+
+        config.local_path = Some("/run/media/repo".into());
+        let local = Repo::from_config(&config).unwrap().unwrap();
+        assert_eq!(local.location(), "/run/media/repo");
+        assert_eq!(
+            local.pacman_server(),
+            "file:///run/media/repo/$repo/os/$arch"
+        );
+
+        config.url = Some("https://mirror.example/artix/".into());
+        assert!(Repo::from_config(&config).is_err());
+
+        config.local_path = None;
+        let url = Repo::from_config(&config).unwrap().unwrap();
+        assert_eq!(url.url(), "https://mirror.example/artix");
+        assert_eq!(url.location(), url.url());
+    }
+
+    #[test]
+    fn lib32_is_uncommented_or_added() {
+        let shipped = "[options]\nArchitecture = auto\n\n\
+                       [world]\nInclude = /etc/pacman.d/mirrorlist\n\n\
+                       #[lib32]\n#Include = /etc/pacman.d/mirrorlist\n";
+        assert!(
+            with_lib32_repo(shipped).ends_with("\n[lib32]\nInclude = /etc/pacman.d/mirrorlist\n")
+        );
+
+        let without = "[options]\n\n[world]\nInclude = /etc/pacman.d/mirrorlist\n";
+        let enabled = with_lib32_repo(without);
+        assert!(enabled.ends_with("\n\n[lib32]\nInclude = /etc/pacman.d/mirrorlist\n"));
+        assert_eq!(with_lib32_repo(&enabled), enabled);
+    }
+
+    fn mirror(name: &str) -> Mirror {
+        Mirror {
+            name: name.into(),
+            url: format!("https://{}/artix-linux/repos/$repo/os/$arch", name),
+            section: None,
+        }
+    }
+
+    #[test]
+    fn ranking_orders_by_latency_and_drops_unreachable() {
+        let ms = Duration::from_millis;
+        let measured = vec![
+            (mirror("slow.example"), Some(ms(180))),
+            (mirror("dead.example"), None),
+            (mirror("fast.example"), Some(ms(12))),
+            (mirror("mid.example"), Some(ms(45))),
+        ];
+
+        let ranked: Vec<(String, Duration)> = rank_mirrors(measured)
+            .into_iter()
+            .map(|(m, latency)| (m.name, latency))
+            .collect();
+        assert_eq!(
+            ranked,
+            [
+                ("fast.example".to_string(), ms(12)),
+                ("mid.example".to_string(), ms(45)),
+                ("slow.example".to_string(), ms(180)),
+            ]
+        );
+        assert!(rank_mirrors(vec![(mirror("dead.example"), None)]).is_empty());
+    }
+
+    #[test]
+    fn mirrors_keep_their_section_for_country_filtering() {
+        let mirrors = parse_mirrorlist_content(
+            "# Default mirrors\n\
+             Server = https://mirrors.dotsrc.org/artix-linux/repos/$repo/os/$arch\n\
+             \n\
+             # Germany\n\
+             Server = https://ftp.halifax.rwth-aachen.de/artixlinux/$repo/os/$arch\n\
+             Server = https://mirror.netcologne.de/artix-linux/$repo/os/$arch\n\
+             # Server = https://disabled.example/$repo/os/$arch\n",
+        );
+        assert_eq!(mirrors.len(), 3);
+        assert_eq!(mirrors[2].section.as_deref(), Some("Germany"));
+        assert_eq!(mirrors[2].name, "mirror.netcologne.de");
+
+        let german = filter_by_country(mirrors.clone(), "germany");
+        assert_eq!(german.len(), 2);
+        assert!(filter_by_country(mirrors, "Japan").is_empty());
+    }
+
+    #[test]
+    fn parallel_downloads_replaces_commented_default() {
+        let conf = "[options]\nHoldPkg = pacman glibc\n#ParallelDownloads = 5\n\n[system]\nInclude = /etc/pacman.d/mirrorlist\n";
+        let conf = with_parallel_downloads(conf, 10);
+        assert!(conf.contains("\nParallelDownloads = 10\n"));
+        assert!(!conf.contains("#ParallelDownloads"));
+        assert!(conf.ends_with("Include = /etc/pacman.d/mirrorlist\n"));
+
+        // Setting it again replaces the line rather than adding another
+        let conf = with_parallel_downloads(&conf, 3);
+        assert_eq!(conf.matches("ParallelDownloads").count(), 1);
+        assert!(conf.contains("\nParallelDownloads = 3\n"));
+    }
+
+    #[test]
+    fn parallel_downloads_goes_under_options() {
+        let conf = with_parallel_downloads("[options]\nArchitecture = auto\n", 8);
+        assert_eq!(
+            conf,
+            "[options]\nParallelDownloads = 8\nArchitecture = auto\n"
+        );
+        assert_eq!(
+            with_parallel_downloads("", 8),
+            "[options]\nParallelDownloads = 8\n"
+        );
+    }
+
+    #[test]
+    fn zero_parallel_downloads_is_rejected() {
+        let mut config = MirrorConfig {
+            parallel_downloads: Some(0),
+            ..Default::default()
+        };
+        assert!(validate_mirror(&config).is_err());
+        config.parallel_downloads = Some(5);
+        assert!(validate_mirror(&config).is_ok());
+    }
+
+    #[test]
+    fn url_host_and_port() {
+        assert_eq!(
+            url_host_port("https://mirrors.dotsrc.org/artix-linux/repos"),
+            Some(("mirrors.dotsrc.org".into(), 443))
+        );
+        assert_eq!(
+            url_host_port("http://mirror.local:8080/"),
+            Some(("mirror.local".into(), 8080))
+        );
+        assert_eq!(url_host_port("ftp://example.org/"), None);
+        assert_eq!(url_host_port("https://"), None);
+    }
+}
@@ -1,13 +1,30 @@
-//! Network services setup (mDNS, SSH, Eternal Terminal)
+//! Network services setup (static addressing, WiFi, mDNS, SSH, Eternal Terminal,
+//! WireGuard, Tailscale)
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::net::IpAddr;
 use std::path::Path;
 
-use crate::distro::Distro;
-use crate::manifest::{EtConfig, NetworkConfig};
+use crate::distro::{Distro, DistroKind};
+use crate::init::ServiceSpec;
+use crate::manifest::{
+    EtConfig, InterfaceConfig, NetworkConfig, SshConfig, TailscaleConfig, WifiConfig,
+    WireguardConfig,
+};
 
 /// Set up network services based on configuration
 pub fn setup_network(root: &Path, config: &NetworkConfig, distro: &dyn Distro) -> Result<()> {
+    if !config.interfaces.is_empty() {
+        println!("  Configuring static interfaces...");
+        setup_interfaces(root, &config.interfaces, distro)?;
+    }
+
+    if let Some(wifi) = &config.wifi {
+        println!("  Setting up WiFi (iwd)...");
+        setup_wifi(root, wifi, distro)?;
+    }
+
     if config.mdns {
         println!("  Setting up mDNS (Avahi)...");
         setup_mdns(root, distro)?;
@@ -16,7 +33,7 @@ pub fn setup_network(root: &Path, config: &NetworkConfig, distro: &dyn Distro) -
     if let Some(ssh) = &config.ssh {
         if ssh.enabled {
             println!("  Setting up SSH server...");
-            setup_ssh(root, distro)?;
+            setup_ssh(root, ssh, distro)?;
         }
     }
 
@@ -27,39 +44,566 @@ pub fn setup_network(root: &Path, config: &NetworkConfig, distro: &dyn Distro) -
         }
     }
 
+    if let Some(wireguard) = &config.wireguard {
+        println!("  Setting up WireGuard (wg0)...");
+        setup_wireguard(root, wireguard, distro)?;
+    }
+
+    if let Some(tailscale) = &config.tailscale {
+        println!("  Setting up Tailscale...");
+        setup_tailscale(root, tailscale, distro)?;
+    }
+
+    Ok(())
+}
+
+/// Write static address configuration in the distro's network config format
+fn setup_interfaces(
+    root: &Path,
+    interfaces: &[InterfaceConfig],
+    distro: &dyn Distro,
+) -> Result<()> {
+    validate_interfaces(interfaces)?;
+
+    if distro.name() == DistroKind::Devuan.name() {
+        // Devuan uses /etc/network/interfaces (ifupdown)
+        let path = root.join("etc/network/interfaces");
+        fs::create_dir_all(root.join("etc/network"))?;
+        fs::write(&path, ifupdown_interfaces(interfaces))
+            .context("Failed to write /etc/network/interfaces")?;
+    } else {
+        // Everything else runs dhcpcd, which takes static profiles per interface
+        let path = root.join("etc/dhcpcd.conf");
+        let mut content = fs::read_to_string(&path).unwrap_or_default();
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(&dhcpcd_profiles(interfaces));
+        fs::write(&path, content).context("Failed to write /etc/dhcpcd.conf")?;
+    }
+
+    for iface in interfaces {
+        println!("    {}: {}", iface.name, iface.address);
+    }
+
+    Ok(())
+}
+
+/// Validate static interface configuration: CIDR addresses, gateways inside
+/// the subnet, and DNS servers as plain IP addresses
+pub fn validate_interfaces(interfaces: &[InterfaceConfig]) -> Result<()> {
+    let mut seen = std::collections::HashSet::new();
+
+    for iface in interfaces {
+        if iface.name.is_empty() || iface.name.contains(char::is_whitespace) {
+            bail!("Invalid interface name: '{}'", iface.name);
+        }
+        if !seen.insert(iface.name.as_str()) {
+            bail!("Interface '{}' is configured more than once", iface.name);
+        }
+
+        let (addr, prefix) = parse_cidr(&iface.address)
+            .with_context(|| format!("Invalid address for interface '{}'", iface.name))?;
+
+        if let Some(gateway) = &iface.gateway {
+            let gateway: IpAddr = gateway.parse().with_context(|| {
+                format!(
+                    "Invalid gateway for interface '{}': {}",
+                    iface.name, gateway
+                )
+            })?;
+            if !in_subnet(addr, prefix, gateway) {
+                bail!(
+                    "Gateway {} is not reachable from {} on interface '{}'",
+                    gateway,
+                    iface.address,
+                    iface.name
+                );
+            }
+        }
+
+        for dns in &iface.dns {
+            dns.parse::<IpAddr>().with_context(|| {
+                format!("Invalid DNS server for interface '{}': {}", iface.name, dns)
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse an address in CIDR notation (e.g., "192.168.1.10/24")
+pub fn parse_cidr(cidr: &str) -> Result<(IpAddr, u8)> {
+    let Some((addr, prefix)) = cidr.split_once('/') else {
+        bail!("Expected CIDR notation (e.g., 192.168.1.10/24): {}", cidr);
+    };
+
+    let addr: IpAddr = addr
+        .parse()
+        .with_context(|| format!("Invalid IP address: {}", addr))?;
+    let prefix: u8 = prefix
+        .parse()
+        .with_context(|| format!("Invalid prefix length: {}", prefix))?;
+
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    if prefix > max {
+        bail!("Prefix length /{} is out of range for {}", prefix, addr);
+    }
+
+    Ok((addr, prefix))
+}
+
+/// Check whether `other` is inside the subnet `addr/prefix`
+fn in_subnet(addr: IpAddr, prefix: u8, other: IpAddr) -> bool {
+    let (a, b, width) = match (addr, other) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => (u32::from(a) as u128, u32::from(b) as u128, 32),
+        (IpAddr::V6(a), IpAddr::V6(b)) => (u128::from(a), u128::from(b), 128),
+        _ => return false,
+    };
+
+    let host_bits = width - u32::from(prefix);
+    let mask = u128::MAX.checked_shl(host_bits).unwrap_or(0);
+    a & mask == b & mask
+}
+
+/// Render dhcpcd static profiles
+fn dhcpcd_profiles(interfaces: &[InterfaceConfig]) -> String {
+    let mut out = String::new();
+
+    for iface in interfaces {
+        let is_v6 = iface.address.contains(':');
+        let key = if is_v6 { "ip6_address" } else { "ip_address" };
+
+        out.push_str(&format!("\ninterface {}\n", iface.name));
+        out.push_str(&format!("static {}={}\n", key, iface.address));
+        if let Some(gateway) = &iface.gateway {
+            out.push_str(&format!("static routers={}\n", gateway));
+        }
+        if !iface.dns.is_empty() {
+            out.push_str(&format!(
+                "static domain_name_servers={}\n",
+                iface.dns.join(" ")
+            ));
+        }
+    }
+
+    out
+}
+
+/// Render /etc/network/interfaces with loopback and static stanzas
+fn ifupdown_interfaces(interfaces: &[InterfaceConfig]) -> String {
+    let mut out = String::from("auto lo\niface lo inet loopback\n");
+
+    for iface in interfaces {
+        let family = if iface.address.contains(':') {
+            "inet6"
+        } else {
+            "inet"
+        };
+
+        out.push_str(&format!(
+            "\nauto {name}\niface {name} {family} static\n    address {}\n",
+            iface.address,
+            name = iface.name,
+            family = family
+        ));
+        if let Some(gateway) = &iface.gateway {
+            out.push_str(&format!("    gateway {}\n", gateway));
+        }
+        if !iface.dns.is_empty() {
+            out.push_str(&format!("    dns-nameservers {}\n", iface.dns.join(" ")));
+        }
+    }
+
+    out
+}
+
+/// Install iwd, write the network profile, and enable the iwd service
+fn setup_wifi(root: &Path, wifi: &WifiConfig, distro: &dyn Distro) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    validate_wifi(wifi)?;
+
+    distro.install_packages(root, &["iwd"])?;
+
+    let iwd_dir = root.join("var/lib/iwd");
+    fs::create_dir_all(&iwd_dir)?;
+    fs::set_permissions(&iwd_dir, fs::Permissions::from_mode(0o700))?;
+
+    let profile_path = iwd_dir.join(iwd_profile_name(&wifi.ssid));
+    write_private(&profile_path, &iwd_profile(&wifi.psk))
+        .context("Failed to write iwd network profile")?;
+
+    println!("    Network: {}", wifi.ssid);
+
+    enable_service(root, distro, "iwd")?;
+
+    Ok(())
+}
+
+/// Validate WiFi credentials against iwd's limits
+pub fn validate_wifi(wifi: &WifiConfig) -> Result<()> {
+    if wifi.ssid.is_empty() || wifi.ssid.len() > 32 {
+        bail!("WiFi SSID must be 1-32 bytes");
+    }
+
+    let is_hex_key = wifi.psk.len() == 64 && wifi.psk.chars().all(|c| c.is_ascii_hexdigit());
+    if !is_hex_key && !(8..=63).contains(&wifi.psk.len()) {
+        bail!("WiFi passphrase must be 8-63 characters or a 64-digit hex key");
+    }
+
+    Ok(())
+}
+
+/// iwd profile file name: plain SSIDs are used as-is, anything else is
+/// hex-encoded with a leading '='
+fn iwd_profile_name(ssid: &str) -> String {
+    let plain = ssid
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == ' ' || c == '_' || c == '-');
+
+    if plain {
+        format!("{}.psk", ssid)
+    } else {
+        let hex: String = ssid.bytes().map(|b| format!("{:02x}", b)).collect();
+        format!("={}.psk", hex)
+    }
+}
+
+/// Render an iwd PSK profile
+fn iwd_profile(psk: &str) -> String {
+    if psk.len() == 64 && psk.chars().all(|c| c.is_ascii_hexdigit()) {
+        format!("[Security]\nPreSharedKey={}\n", psk)
+    } else {
+        format!("[Security]\nPassphrase={}\n", psk)
+    }
+}
+
+/// Write a file only root can read, creating it with 0600 before writing so
+/// the secret in it is never world-readable
+fn write_private(path: &Path, content: &str) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    file.write_all(content.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(())
+}
+
+/// Install wireguard-tools, write wg0.conf and bring the tunnel up at boot
+fn setup_wireguard(root: &Path, config: &WireguardConfig, distro: &dyn Distro) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    validate_wireguard(config)?;
+
+    distro.install_packages(root, &["wireguard-tools"])?;
+
+    let wg_dir = root.join("etc/wireguard");
+    fs::create_dir_all(&wg_dir)?;
+    fs::set_permissions(&wg_dir, fs::Permissions::from_mode(0o700))?;
+    write_private(&wg_dir.join("wg0.conf"), &wg_quick_config(config))?;
+
+    let service = ServiceSpec::oneshot("wireguard", "wg-quick up wg0");
+    let init = distro.init_system();
+    init.create_service(root, &service)?;
+    init.enable_service(root, "wireguard")?;
+
+    Ok(())
+}
+
+/// Check a WireGuard tunnel: keys, addresses and peer endpoints
+pub fn validate_wireguard(config: &WireguardConfig) -> Result<()> {
+    parse_cidr(&config.address).context("Invalid WireGuard address")?;
+    if !is_wireguard_key(&config.private_key) {
+        bail!("WireGuard private_key must be a base64 key from `wg genkey`");
+    }
+    for dns in &config.dns {
+        dns.parse::<IpAddr>()
+            .with_context(|| format!("Invalid WireGuard DNS server: {}", dns))?;
+    }
+    if config.peers.is_empty() {
+        bail!("WireGuard needs at least one peer");
+    }
+
+    for peer in &config.peers {
+        if !is_wireguard_key(&peer.public_key) {
+            bail!("Invalid WireGuard peer public_key: {}", peer.public_key);
+        }
+        if peer
+            .preshared_key
+            .as_deref()
+            .is_some_and(|key| !is_wireguard_key(key))
+        {
+            bail!(
+                "WireGuard peer {} has an invalid preshared_key",
+                peer.public_key
+            );
+        }
+        if peer.allowed_ips.is_empty() {
+            bail!("WireGuard peer {} has no allowed_ips", peer.public_key);
+        }
+        for ips in &peer.allowed_ips {
+            parse_cidr(ips).with_context(|| {
+                format!("Invalid allowed_ips for WireGuard peer {}", peer.public_key)
+            })?;
+        }
+        if let Some(endpoint) = &peer.endpoint {
+            let valid = endpoint
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+            if !valid {
+                bail!(
+                    "WireGuard peer endpoint must be host:port, not {}",
+                    endpoint
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check whichever VPNs are configured
+pub fn validate_vpn(config: &NetworkConfig) -> Result<()> {
+    if let Some(wireguard) = &config.wireguard {
+        validate_wireguard(wireguard)?;
+    }
+    if let Some(tailscale) = &config.tailscale {
+        validate_tailscale(tailscale)?;
+    }
+    Ok(())
+}
+
+/// WireGuard keys are 32 bytes, 44 characters of base64
+fn is_wireguard_key(key: &str) -> bool {
+    key.len() == 44
+        && key.ends_with('=')
+        && key[..43]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/')
+}
+
+/// Render wg0.conf for wg-quick
+fn wg_quick_config(config: &WireguardConfig) -> String {
+    let mut out = format!(
+        "[Interface]\nAddress = {}\nPrivateKey = {}\n",
+        config.address, config.private_key
+    );
+    if let Some(port) = config.listen_port {
+        out.push_str(&format!("ListenPort = {}\n", port));
+    }
+    if !config.dns.is_empty() {
+        out.push_str(&format!("DNS = {}\n", config.dns.join(", ")));
+    }
+
+    for peer in &config.peers {
+        out.push_str(&format!("\n[Peer]\nPublicKey = {}\n", peer.public_key));
+        if let Some(psk) = &peer.preshared_key {
+            out.push_str(&format!("PresharedKey = {}\n", psk));
+        }
+        out.push_str(&format!("AllowedIPs = {}\n", peer.allowed_ips.join(", ")));
+        if let Some(endpoint) = &peer.endpoint {
+            out.push_str(&format!("Endpoint = {}\n", endpoint));
+        }
+        if let Some(keepalive) = peer.persistent_keepalive {
+            out.push_str(&format!("PersistentKeepalive = {}\n", keepalive));
+        }
+    }
+
+    out
+}
+
+/// Where the Tailscale auth key waits for the first boot
+const TAILSCALE_AUTHKEY: &str = "etc/tailscale/authkey";
+
+/// Install tailscale and join the tailnet at boot
+///
+/// tailscaled can't run in the chroot, so `tailscale up` runs from a
+/// oneshot service once it's started. The key is read from a root-only file
+/// rather than passed on the command line, where `ps` and the install log
+/// would show it.
+fn setup_tailscale(root: &Path, config: &TailscaleConfig, distro: &dyn Distro) -> Result<()> {
+    validate_tailscale(config)?;
+
+    if distro.map_package("tailscale").is_none() {
+        bail!("Tailscale isn't packaged for {}", distro.name());
+    }
+    distro.install_packages(root, &["tailscale"])?;
+
+    let key_path = root.join(TAILSCALE_AUTHKEY);
+    if let Some(dir) = key_path.parent() {
+        fs::create_dir_all(dir).context("Failed to create /etc/tailscale")?;
+    }
+    write_private(&key_path, &format!("{}\n", config.authkey))?;
+
+    let daemon = distro.map_service("tailscaled");
+    let init = distro.init_system();
+    init.create_service(
+        root,
+        &ServiceSpec::longrun(
+            &daemon,
+            "tailscaled --state=/var/lib/tailscale/tailscaled.state",
+        ),
+    )?;
+    init.create_service(root, &tailscale_up_service(&daemon))?;
+    init.enable_service(root, &daemon)?;
+    init.enable_service(root, "tailscale-up")?;
+
     Ok(())
 }
 
+/// Oneshot that logs in with the stored auth key once tailscaled is up
+fn tailscale_up_service(daemon: &str) -> ServiceSpec {
+    ServiceSpec::oneshot(
+        "tailscale-up",
+        format!("tailscale up --auth-key=file:/{}", TAILSCALE_AUTHKEY),
+    )
+    .wait_for("/var/run/tailscale/tailscaled.sock")
+    .depends_on(daemon)
+}
+
+/// Check a Tailscale auth key looks like one
+pub fn validate_tailscale(config: &TailscaleConfig) -> Result<()> {
+    if !config.authkey.starts_with("tskey-") || config.authkey.contains(char::is_whitespace) {
+        bail!("Tailscale authkey must be a tskey-... key from the admin console");
+    }
+    Ok(())
+}
+
+/// Check for a non-wireless interface with carrier
+pub fn has_wired_link() -> bool {
+    let Ok(entries) = fs::read_dir("/sys/class/net") else {
+        return false;
+    };
+
+    entries.filter_map(|e| e.ok()).any(|entry| {
+        let path = entry.path();
+        entry.file_name() != "lo"
+            && !path.join("wireless").exists()
+            && fs::read_to_string(path.join("carrier")).is_ok_and(|c| c.trim() == "1")
+    })
+}
+
+/// Enable a service by its generic name, skipping it if the distro has no equivalent
+pub(crate) fn enable_service(root: &Path, distro: &dyn Distro, generic: &str) -> Result<()> {
+    let service = distro.map_service(generic);
+    if service.is_empty() {
+        println!(
+            "    {} is not available on {}, skipping",
+            generic,
+            distro.name()
+        );
+        return Ok(());
+    }
+
+    distro
+        .init_system()
+        .enable_service(root, &service)
+        .with_context(|| format!("Failed to enable {} service", service))
+}
+
 /// Install and enable Avahi for mDNS (.local hostname resolution)
 fn setup_mdns(root: &Path, distro: &dyn Distro) -> Result<()> {
     // Install packages
     distro.install_packages(root, &["avahi", "nss-mdns"])?;
 
     // Enable avahi service
-    let service = distro.map_service("avahi");
-    distro
-        .init_system()
-        .enable_service(root, &service)
-        .context("Failed to enable avahi service")?;
+    enable_service(root, distro, "avahi")?;
 
     Ok(())
 }
 
-/// Install and enable SSH server
-fn setup_ssh(root: &Path, distro: &dyn Distro) -> Result<()> {
+/// Drop-in holding the sshd settings mkOS chose
+const SSHD_DROPIN: &str = "etc/ssh/sshd_config.d/10-mkos.conf";
+
+/// Install SSH server, write its settings and enable it
+fn setup_ssh(root: &Path, config: &SshConfig, distro: &dyn Distro) -> Result<()> {
+    validate_ssh(config)?;
+
     // Install openssh package
     distro.install_packages(root, &["openssh"])?;
 
+    let dropin = root.join(SSHD_DROPIN);
+    if let Some(dir) = dropin.parent() {
+        fs::create_dir_all(dir).context("Failed to create /etc/ssh/sshd_config.d")?;
+    }
+    fs::write(&dropin, sshd_dropin(config))
+        .with_context(|| format!("Failed to write /{}", SSHD_DROPIN))?;
+
+    // Not every distro's sshd_config reads the drop-in directory
+    let main = root.join("etc/ssh/sshd_config");
+    let current = fs::read_to_string(&main).unwrap_or_default();
+    if let Some(updated) = include_dropins(&current) {
+        fs::write(&main, updated).context("Failed to write /etc/ssh/sshd_config")?;
+    }
+
     // Enable sshd service
-    let service = distro.map_service("sshd");
-    distro
-        .init_system()
-        .enable_service(root, &service)
-        .context("Failed to enable sshd service")?;
+    enable_service(root, distro, "sshd")?;
 
     Ok(())
 }
 
+/// Check the SSH server settings
+pub fn validate_ssh(config: &SshConfig) -> Result<()> {
+    if config.port == 0 {
+        bail!("SSH port must be between 1 and 65535");
+    }
+    Ok(())
+}
+
+/// Decide `ssh.password_auth` where the manifest left it unset: passwords
+/// stay on unless some user has SSH keys to log in with instead
+pub fn settle_ssh_auth(config: &mut NetworkConfig, users_have_keys: bool) {
+    if let Some(ssh) = &mut config.ssh {
+        ssh.password_auth.get_or_insert(!users_have_keys);
+    }
+}
+
+/// sshd settings for the mkOS drop-in
+fn sshd_dropin(config: &SshConfig) -> String {
+    let yes_no = |on: bool| if on { "yes" } else { "no" };
+    let password = config.password_auth.unwrap_or(true);
+
+    format!(
+        "# mkOS SSH server configuration\n\
+         # Generated by mkos-install\n\n\
+         Port {}\n\
+         PermitRootLogin {}\n\
+         PasswordAuthentication {}\n\
+         KbdInteractiveAuthentication {}\n\
+         PubkeyAuthentication yes\n",
+        config.port,
+        yes_no(config.permit_root_login),
+        yes_no(password),
+        yes_no(password),
+    )
+}
+
+/// `sshd_config` with the drop-in directory included ahead of its own
+/// settings (sshd keeps the first value it reads), or None when it already
+/// includes it
+fn include_dropins(config: &str) -> Option<String> {
+    let included = config.lines().any(|line| {
+        let line = line.trim_start();
+        line.starts_with("Include") && line.contains("sshd_config.d")
+    });
+    if included {
+        return None;
+    }
+    Some(format!(
+        "Include /etc/ssh/sshd_config.d/*.conf\n\n{}",
+        config
+    ))
+}
+
 /// Install and configure Eternal Terminal
 fn setup_eternalterminal(root: &Path, config: &EtConfig, distro: &dyn Distro) -> Result<()> {
     // Install eternalterminal package
@@ -75,22 +619,310 @@ fn setup_eternalterminal(root: &Path, config: &EtConfig, distro: &dyn Distro) ->
     std::fs::write(root.join("etc/et.cfg"), et_config).context("Failed to write /etc/et.cfg")?;
 
     // Enable etserver service
-    let service = distro.map_service("etserver");
-    distro
-        .init_system()
-        .enable_service(root, &service)
-        .context("Failed to enable etserver service")?;
+    enable_service(root, distro, "etserver")?;
 
     Ok(())
 }
 
 /// Check if any network services are enabled
 pub fn has_network_services(config: &NetworkConfig) -> bool {
-    config.mdns
+    !config.interfaces.is_empty()
+        || config.wifi.is_some()
+        || config.mdns
         || config.ssh.as_ref().map(|s| s.enabled).unwrap_or(false)
         || config
             .eternalterminal
             .as_ref()
             .map(|e| e.enabled)
             .unwrap_or(false)
+        || config.wireguard.is_some()
+        || config.tailscale.is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::WireguardPeer;
+
+    fn iface(address: &str, gateway: Option<&str>) -> InterfaceConfig {
+        InterfaceConfig {
+            name: "eth0".into(),
+            address: address.into(),
+            gateway: gateway.map(String::from),
+            dns: vec!["1.1.1.1".into()],
+        }
+    }
+
+    #[test]
+    fn parse_cidr_valid() {
+        let (addr, prefix) = parse_cidr("192.168.1.10/24").unwrap();
+        assert_eq!(addr, "192.168.1.10".parse::<IpAddr>().unwrap());
+        assert_eq!(prefix, 24);
+
+        assert!(parse_cidr("fd00::10/64").is_ok());
+    }
+
+    #[test]
+    fn parse_cidr_invalid() {
+        assert!(parse_cidr("192.168.1.10").is_err());
+        assert!(parse_cidr("192.168.1.300/24").is_err());
+        assert!(parse_cidr("192.168.1.10/33").is_err());
+        assert!(parse_cidr("192.168.1.10/abc").is_err());
+    }
+
+    #[test]
+    fn gateway_must_be_in_subnet() {
+        assert!(validate_interfaces(&[iface("192.168.1.10/24", Some("192.168.1.1"))]).is_ok());
+        assert!(validate_interfaces(&[iface("192.168.1.10/24", Some("192.168.2.1"))]).is_err());
+        assert!(validate_interfaces(&[iface("10.0.0.5/8", Some("10.255.0.1"))]).is_ok());
+        assert!(validate_interfaces(&[iface("192.168.1.10/24", Some("fd00::1"))]).is_err());
+        assert!(validate_interfaces(&[iface("192.168.1.10/24", None)]).is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_dns_and_duplicates() {
+        let mut bad_dns = iface("192.168.1.10/24", None);
+        bad_dns.dns = vec!["dns.example.com".into()];
+        assert!(validate_interfaces(&[bad_dns]).is_err());
+
+        let dup = iface("192.168.1.10/24", None);
+        assert!(validate_interfaces(&[dup.clone(), dup]).is_err());
+    }
+
+    #[test]
+    fn wifi_validation() {
+        let wifi = |ssid: &str, psk: &str| WifiConfig {
+            ssid: ssid.into(),
+            psk: psk.into(),
+        };
+
+        assert!(validate_wifi(&wifi("home", "correct horse")).is_ok());
+        assert!(validate_wifi(&wifi("home", &"a".repeat(64))).is_ok());
+        assert!(validate_wifi(&wifi("home", "short")).is_err());
+        assert!(validate_wifi(&wifi("home", &"z".repeat(64))).is_err());
+        assert!(validate_wifi(&wifi("", "correct horse")).is_err());
+        assert!(validate_wifi(&wifi(&"x".repeat(33), "correct horse")).is_err());
+    }
+
+    #[test]
+    fn wifi_psk_not_in_debug_output() {
+        let wifi = WifiConfig {
+            ssid: "home".into(),
+            psk: "hunter2hunter2".into(),
+        };
+        assert!(!format!("{:?}", wifi).contains("hunter2"));
+    }
+
+    #[test]
+    fn iwd_profile_names() {
+        assert_eq!(iwd_profile_name("Home Network"), "Home Network.psk");
+        assert_eq!(iwd_profile_name("cafe_5G-2"), "cafe_5G-2.psk");
+        assert_eq!(iwd_profile_name("Joe's"), "=4a6f652773.psk");
+    }
+
+    #[test]
+    fn iwd_profile_contents() {
+        assert_eq!(
+            iwd_profile("correct horse"),
+            "[Security]\nPassphrase=correct horse\n"
+        );
+        let key = "ab".repeat(32);
+        assert_eq!(
+            iwd_profile(&key),
+            format!("[Security]\nPreSharedKey={}\n", key)
+        );
+    }
+
+    #[test]
+    fn dhcpcd_static_profile() {
+        let profile = dhcpcd_profiles(&[iface("192.168.1.10/24", Some("192.168.1.1"))]);
+        assert_eq!(
+            profile,
+            "\ninterface eth0\n\
+             static ip_address=192.168.1.10/24\n\
+             static routers=192.168.1.1\n\
+             static domain_name_servers=1.1.1.1\n"
+        );
+    }
+
+    #[test]
+    fn ifupdown_static_stanza() {
+        let content = ifupdown_interfaces(&[iface("192.168.1.10/24", Some("192.168.1.1"))]);
+        assert!(content.starts_with("auto lo\niface lo inet loopback\n"));
+        assert!(content.contains("auto eth0\niface eth0 inet static\n"));
+        assert!(content.contains("    address 192.168.1.10/24\n"));
+        assert!(content.contains("    gateway 192.168.1.1\n"));
+        assert!(content.contains("    dns-nameservers 1.1.1.1\n"));
+    }
+
+    fn settled(ssh: SshConfig, users_have_keys: bool) -> SshConfig {
+        let mut network = NetworkConfig {
+            ssh: Some(ssh),
+            ..Default::default()
+        };
+        settle_ssh_auth(&mut network, users_have_keys);
+        network.ssh.unwrap()
+    }
+
+    #[test]
+    fn sshd_dropin_is_key_only_when_users_have_keys() {
+        let dropin = sshd_dropin(&settled(SshConfig::default(), true));
+        assert!(dropin.contains("Port 22\n"));
+        assert!(dropin.contains("PermitRootLogin no\n"));
+        assert!(dropin.contains("PasswordAuthentication no\n"));
+        assert!(dropin.contains("KbdInteractiveAuthentication no\n"));
+    }
+
+    #[test]
+    fn sshd_dropin_keeps_passwords_without_keys() {
+        let dropin = sshd_dropin(&settled(SshConfig::default(), false));
+        assert!(dropin.contains("PasswordAuthentication yes\n"));
+        assert!(dropin.contains("KbdInteractiveAuthentication yes\n"));
+    }
+
+    #[test]
+    fn sshd_dropin_follows_explicit_settings() {
+        let ssh = SshConfig {
+            port: 2222,
+            permit_root_login: true,
+            password_auth: Some(true),
+            ..Default::default()
+        };
+        // An explicit password_auth wins over the users' keys
+        let dropin = sshd_dropin(&settled(ssh, true));
+        assert!(dropin.contains("Port 2222\n"));
+        assert!(dropin.contains("PermitRootLogin yes\n"));
+        assert!(dropin.contains("PasswordAuthentication yes\n"));
+
+        assert!(validate_ssh(&SshConfig {
+            port: 0,
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn sshd_config_includes_dropins_first() {
+        let updated = include_dropins("Port 22\nUsePAM yes\n").unwrap();
+        assert_eq!(
+            updated,
+            "Include /etc/ssh/sshd_config.d/*.conf\n\nPort 22\nUsePAM yes\n"
+        );
+        assert!(include_dropins(&updated).is_none());
+        assert!(include_dropins("Include /etc/ssh/sshd_config.d/*.conf\n").is_none());
+    }
+
+    const KEY_A: &str = "yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=";
+    const KEY_B: &str = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=";
+
+    fn wireguard() -> WireguardConfig {
+        WireguardConfig {
+            address: "10.100.0.2/24".into(),
+            private_key: KEY_A.into(),
+            listen_port: None,
+            dns: Vec::new(),
+            peers: vec![WireguardPeer {
+                public_key: KEY_B.into(),
+                preshared_key: None,
+                allowed_ips: vec!["10.100.0.0/24".into()],
+                endpoint: Some("vpn.example.com:51820".into()),
+                persistent_keepalive: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn wg_quick_config_minimal() {
+        assert_eq!(
+            wg_quick_config(&wireguard()),
+            format!(
+                "[Interface]\n\
+                 Address = 10.100.0.2/24\n\
+                 PrivateKey = {}\n\
+                 \n\
+                 [Peer]\n\
+                 PublicKey = {}\n\
+                 AllowedIPs = 10.100.0.0/24\n\
+                 Endpoint = vpn.example.com:51820\n",
+                KEY_A, KEY_B
+            )
+        );
+    }
+
+    #[test]
+    fn wg_quick_config_optional_settings() {
+        let mut config = wireguard();
+        config.listen_port = Some(51820);
+        config.dns = vec!["10.100.0.1".into(), "1.1.1.1".into()];
+        config.peers[0].preshared_key = Some(KEY_A.into());
+        config.peers[0].allowed_ips.push("fd00::/64".into());
+        config.peers[0].persistent_keepalive = Some(25);
+        config.peers.push(WireguardPeer {
+            public_key: KEY_A.into(),
+            preshared_key: None,
+            allowed_ips: vec!["10.100.0.3/32".into()],
+            endpoint: None,
+            persistent_keepalive: None,
+        });
+
+        let content = wg_quick_config(&config);
+        assert!(content.contains("ListenPort = 51820\nDNS = 10.100.0.1, 1.1.1.1\n"));
+        assert!(content.contains(&format!("PresharedKey = {}\n", KEY_A)));
+        assert!(content.contains("AllowedIPs = 10.100.0.0/24, fd00::/64\n"));
+        assert!(content.contains("PersistentKeepalive = 25\n"));
+        assert_eq!(content.matches("[Peer]").count(), 2);
+        assert!(content.ends_with("AllowedIPs = 10.100.0.3/32\n"));
+        assert!(validate_wireguard(&config).is_ok());
+    }
+
+    #[test]
+    fn wireguard_validation() {
+        assert!(validate_wireguard(&wireguard()).is_ok());
+
+        let mut config = wireguard();
+        config.private_key = "not-a-key".into();
+        assert!(validate_wireguard(&config).is_err());
+
+        let mut config = wireguard();
+        config.peers[0].endpoint = Some("vpn.example.com".into());
+        assert!(validate_wireguard(&config).is_err());
+
+        let mut config = wireguard();
+        config.peers[0].allowed_ips.clear();
+        assert!(validate_wireguard(&config).is_err());
+
+        let mut config = wireguard();
+        config.peers.clear();
+        assert!(validate_wireguard(&config).is_err());
+    }
+
+    #[test]
+    fn wireguard_keys_stay_out_of_debug_output() {
+        let mut config = wireguard();
+        config.peers[0].preshared_key = Some(KEY_B.into());
+        let debug = format!("{:?}", config);
+        assert!(!debug.contains(KEY_A));
+        // Public keys aren't secret
+        assert_eq!(debug.matches(KEY_B).count(), 1);
+    }
+
+    #[test]
+    fn tailscale_key_is_read_from_a_file() {
+        let config = TailscaleConfig {
+            authkey: "tskey-auth-abc123".into(),
+        };
+        assert!(validate_tailscale(&config).is_ok());
+        assert!(!format!("{:?}", config).contains("abc123"));
+        assert!(validate_tailscale(&TailscaleConfig {
+            authkey: "hunter2".into()
+        })
+        .is_err());
+
+        let service = tailscale_up_service("tailscaled");
+        assert_eq!(
+            service.command,
+            "tailscale up --auth-key=file:/etc/tailscale/authkey"
+        );
+        assert_eq!(service.depends, ["tailscaled"]);
+    }
 }
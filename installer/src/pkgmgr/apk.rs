@@ -1,5 +1,6 @@
 use super::PackageManager;
 use anyhow::Result;
+use std::collections::HashSet;
 use std::path::Path;
 
 use crate::cmd;
@@ -42,6 +43,38 @@ impl PackageManager for Apk {
     }
 
     fn install_kernel_hooks(&self, root: &Path) -> Result<()> {
-        crate::hooks::install_uki_rebuild_script(root)
+        crate::hooks::install_uki_rebuild_binary(root)
+    }
+
+    fn installed_count(&self, root: &Path) -> Option<usize> {
+        super::count_in_file(&root.join("lib/apk/db/installed"), "\nP:")
+    }
+
+    fn installed_packages(&self, root: &Path) -> Result<HashSet<String>> {
+        let installed = super::read_database(&root.join("lib/apk/db/installed"))?;
+        Ok(parse_installed(&installed))
+    }
+}
+
+/// Package names from the `P:` line of each installed database record
+fn parse_installed(installed: &str) -> HashSet<String> {
+    installed
+        .lines()
+        .filter_map(|line| line.strip_prefix("P:"))
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn installed_database_names_packages() {
+        let installed = "C:Q1a\nP:musl\nV:1.2.5-r0\n\nC:Q1b\nP:busybox-binsh\nV:1.36.1-r29\n";
+        assert_eq!(
+            parse_installed(installed),
+            HashSet::from(["musl".into(), "busybox-binsh".into()])
+        );
     }
 }
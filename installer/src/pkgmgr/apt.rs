@@ -1,5 +1,6 @@
 use super::PackageManager;
 use anyhow::Result;
+use std::collections::HashSet;
 use std::path::Path;
 
 use crate::cmd;
@@ -42,6 +43,49 @@ impl PackageManager for Apt {
     }
 
     fn install_kernel_hooks(&self, root: &Path) -> Result<()> {
-        crate::hooks::install_uki_rebuild_script(root)
+        crate::hooks::install_uki_rebuild_binary(root)
+    }
+
+    fn installed_count(&self, root: &Path) -> Option<usize> {
+        super::count_in_file(
+            &root.join("var/lib/dpkg/status"),
+            "Status: install ok installed",
+        )
+    }
+
+    fn installed_packages(&self, root: &Path) -> Result<HashSet<String>> {
+        let status = super::read_database(&root.join("var/lib/dpkg/status"))?;
+        Ok(parse_status(&status))
+    }
+}
+
+/// Packages in the dpkg status file that are installed, not just removed
+/// with their configuration left behind
+fn parse_status(status: &str) -> HashSet<String> {
+    status
+        .split("\n\n")
+        .filter(|record| record.contains("Status: install ok installed"))
+        .filter_map(|record| {
+            record
+                .lines()
+                .find_map(|line| line.strip_prefix("Package: "))
+        })
+        .map(|name| name.trim().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_names_installed_packages() {
+        let status = "Package: bash\nStatus: install ok installed\nVersion: 5.2\n\n\
+                      Package: old\nStatus: deinstall ok config-files\n\n\
+                      Package: libc6\nStatus: install ok installed\n";
+        assert_eq!(
+            parse_status(status),
+            HashSet::from(["bash".into(), "libc6".into()])
+        );
     }
 }
@@ -1,5 +1,6 @@
 use super::PackageManager;
 use anyhow::Result;
+use std::collections::HashSet;
 use std::path::Path;
 
 use crate::cmd;
@@ -47,6 +48,70 @@ impl PackageManager for Emerge {
     }
 
     fn install_kernel_hooks(&self, root: &Path) -> Result<()> {
-        crate::hooks::install_uki_rebuild_script(root)
+        crate::hooks::install_uki_rebuild_binary(root)
+    }
+
+    fn installed_count(&self, root: &Path) -> Option<usize> {
+        // var/db/pkg/<category>/<package>
+        let categories = std::fs::read_dir(root.join("var/db/pkg")).ok()?;
+        Some(
+            categories
+                .flatten()
+                .filter_map(|category| super::count_dirs(&category.path()))
+                .sum(),
+        )
+    }
+
+    fn installed_packages(&self, root: &Path) -> Result<HashSet<String>> {
+        // var/db/pkg/<category>/<package>-<version>
+        let db = root.join("var/db/pkg");
+        let mut installed = HashSet::new();
+        for category in super::database_entries(&db)? {
+            for package in super::database_entries(&db.join(&category))? {
+                let name = package_name(&package);
+                installed.insert(format!("{}/{}", category, name));
+                installed.insert(name.to_string());
+            }
+        }
+        Ok(installed)
+    }
+}
+
+/// `gtk+-3.24.41-r1` to `gtk+`: the version starts at the first dash
+/// followed by a digit
+fn package_name(entry: &str) -> &str {
+    entry
+        .match_indices('-')
+        .find(|(i, _)| entry[i + 1..].starts_with(|c: char| c.is_ascii_digit()))
+        .map_or(entry, |(i, _)| &entry[..i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn database_entries_name_packages() {
+        assert_eq!(package_name("bash-5.2_p26-r1"), "bash");
+        assert_eq!(package_name("linux-firmware-20240610-r1"), "linux-firmware");
+        assert_eq!(package_name("gtk+-3.24.41-r1"), "gtk+");
+        assert_eq!(
+            package_name("gentoo-kernel-bin-6.6.32"),
+            "gentoo-kernel-bin"
+        );
+    }
+
+    #[test]
+    fn installed_by_name_and_category() {
+        let root = tempfile::tempdir().unwrap();
+        let db = root.path().join("var/db/pkg");
+        std::fs::create_dir_all(db.join("sys-fs/cryptsetup-2.7.2")).unwrap();
+        std::fs::create_dir_all(db.join("app-shells/bash-5.2_p26-r1")).unwrap();
+
+        let installed = Emerge::new().installed_packages(root.path()).unwrap();
+        assert!(installed.contains("sys-fs/cryptsetup"));
+        assert!(installed.contains("cryptsetup"));
+        assert!(installed.contains("bash"));
+        assert_eq!(installed.len(), 4);
     }
 }
@@ -4,6 +4,7 @@ mod emerge;
 mod pacman;
 mod slapt_get;
 mod xbps;
+mod zypper;
 
 pub use apk::Apk;
 pub use apt::Apt;
@@ -11,8 +12,11 @@ pub use emerge::Emerge;
 pub use pacman::Pacman;
 pub use slapt_get::SlaptGet;
 pub use xbps::Xbps;
+pub use zypper::Zypper;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::fs;
 use std::path::Path;
 
 /// Package manager trait for distro-agnostic package management
@@ -34,18 +38,221 @@ pub trait PackageManager: Send + Sync {
     /// Different package managers have different hook mechanisms:
     /// - pacman: /etc/pacman.d/hooks/
     /// - xbps: /etc/kernel.d/post-install/
-    /// - apt: /etc/kernel/postinst.d/
+    /// - apt, zypper: /etc/kernel/postinst.d/
     fn install_kernel_hooks(&self, root: &Path) -> Result<()>;
 
-    /// Remove a package
-    fn remove(&self, root: &Path, package: &str) -> Result<()> {
-        let _ = (root, package);
-        Ok(())
+    /// Number of packages installed in `root`, when its package database
+    /// can be read
+    fn installed_count(&self, root: &Path) -> Option<usize>;
+
+    /// Names of the packages installed in `root`, read from its package
+    /// database; empty when there is no database yet
+    fn installed_packages(&self, root: &Path) -> Result<HashSet<String>>;
+
+    /// Remove packages, and whatever they pulled in that nothing else
+    /// needs, from a target root directory
+    fn remove(&self, root: &Path, packages: &[&str]) -> Result<()> {
+        let _ = (root, packages);
+        bail!("{} does not support removing packages yet", self.name())
     }
 
-    /// Check if a package is installed
+    /// Check if a package is installed; use [`InstalledPackages`] to check
+    /// more than one
     fn is_installed(&self, root: &Path, package: &str) -> bool {
-        let _ = (root, package);
-        false
+        self.installed_packages(root)
+            .is_ok_and(|installed| installed.contains(package))
+    }
+}
+
+/// What is installed in a root, queried once and shared by everything
+/// that checks packages, and kept current as packages come and go
+#[derive(Debug, Clone, Default)]
+pub struct InstalledPackages(HashSet<String>);
+
+impl InstalledPackages {
+    pub fn query(package_manager: &dyn PackageManager, root: &Path) -> Result<Self> {
+        package_manager
+            .installed_packages(root)
+            .map(Self)
+            .with_context(|| {
+                format!(
+                    "Failed to read the {} package database",
+                    package_manager.name()
+                )
+            })
+    }
+
+    pub fn contains(&self, package: &str) -> bool {
+        self.0.contains(package)
+    }
+
+    /// Note packages that were just installed
+    pub fn add<S: Into<String>>(&mut self, packages: impl IntoIterator<Item = S>) {
+        self.0.extend(packages.into_iter().map(Into::into));
+    }
+
+    /// Note packages that were just removed
+    pub fn remove<S: AsRef<str>>(&mut self, packages: impl IntoIterator<Item = S>) {
+        for package in packages {
+            self.0.remove(package.as_ref());
+        }
+    }
+}
+
+impl<S: Into<String>> FromIterator<S> for InstalledPackages {
+    fn from_iter<I: IntoIterator<Item = S>>(packages: I) -> Self {
+        Self(packages.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Refuse removing any of `packages` that is in `essential`
+fn check_removable(packages: &[&str], essential: &[&str]) -> Result<()> {
+    let refused: Vec<&str> = packages
+        .iter()
+        .filter(|p| essential.contains(p))
+        .copied()
+        .collect();
+    if !refused.is_empty() {
+        bail!(
+            "Refusing to remove essential packages: {}",
+            refused.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Entries in `dir`, for databases keeping a file per installed package
+fn count_entries(dir: &Path) -> Option<usize> {
+    Some(fs::read_dir(dir).ok()?.count())
+}
+
+/// Subdirectories of `dir`, for databases keeping a directory per installed
+/// package
+fn count_dirs(dir: &Path) -> Option<usize> {
+    Some(
+        fs::read_dir(dir)
+            .ok()?
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .count(),
+    )
+}
+
+/// Contents of the database file at `path`, empty when it doesn't exist
+fn read_database(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        result => result.with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Names of the entries in the database directory `dir`, none when it
+/// doesn't exist
+fn database_entries(dir: &Path) -> Result<Vec<String>> {
+    let entries = match fs::read_dir(dir) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        result => result.with_context(|| format!("Failed to read {}", dir.display()))?,
+    };
+    Ok(entries
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Occurrences of `marker` in the database file at `path`, for databases
+/// keeping one record per installed package
+fn count_in_file(path: &Path, marker: &str) -> Option<usize> {
+    Some(fs::read_to_string(path).ok()?.matches(marker).count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pacman_counts_package_directories() {
+        let root = tempfile::tempdir().unwrap();
+        let local = root.path().join("var/lib/pacman/local");
+        fs::create_dir_all(local.join("bash-5.2-1")).unwrap();
+        fs::create_dir_all(local.join("linux-6.9-1")).unwrap();
+        fs::write(local.join("ALPM_DB_VERSION"), "9\n").unwrap();
+
+        assert_eq!(Pacman::new().installed_count(root.path()), Some(2));
+        assert_eq!(
+            Pacman::new().installed_count(Path::new("/nonexistent")),
+            None
+        );
+    }
+
+    #[test]
+    fn pacman_lists_installed_packages() {
+        let root = tempfile::tempdir().unwrap();
+        let local = root.path().join("var/lib/pacman/local");
+        fs::create_dir_all(local.join("bash-5.2-1")).unwrap();
+        fs::create_dir_all(local.join("linux-firmware-20240610.1-1")).unwrap();
+        fs::write(local.join("ALPM_DB_VERSION"), "9\n").unwrap();
+
+        let pacman = Pacman::new();
+        let installed = InstalledPackages::query(&pacman, root.path()).unwrap();
+        assert!(installed.contains("bash"));
+        assert!(installed.contains("linux-firmware"));
+        assert!(!installed.contains("linux"));
+        assert!(pacman.is_installed(root.path(), "bash"));
+        assert!(!pacman.is_installed(root.path(), "vim"));
+
+        // Nothing bootstrapped yet means nothing installed
+        let empty = tempfile::tempdir().unwrap();
+        assert!(!InstalledPackages::query(&pacman, empty.path())
+            .unwrap()
+            .contains("bash"));
+    }
+
+    #[test]
+    fn installed_packages_follow_changes() {
+        let mut installed: InstalledPackages = ["bash", "vim"].into_iter().collect();
+        installed.add(["htop"]);
+        installed.remove(["vim"]);
+        assert!(installed.contains("bash"));
+        assert!(installed.contains("htop"));
+        assert!(!installed.contains("vim"));
+    }
+
+    #[test]
+    fn essential_packages_are_not_removed() {
+        let root = Path::new("/nonexistent");
+        let err = Pacman::new()
+            .remove(root, &["firefox", "base", "linux"])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Refusing to remove essential packages: base, linux"
+        );
+
+        let err = Xbps::new("https://repo").remove(root, &["base-system"]);
+        assert!(err.is_err());
+        assert!(Apk::new().remove(root, &["vim"]).is_err());
+    }
+
+    #[test]
+    fn apk_and_dpkg_count_records() {
+        let root = tempfile::tempdir().unwrap();
+        let apk_db = root.path().join("lib/apk/db");
+        fs::create_dir_all(&apk_db).unwrap();
+        fs::write(
+            apk_db.join("installed"),
+            "C:Q1a\nP:musl\nV:1\n\nC:Q1b\nP:busybox\n",
+        )
+        .unwrap();
+        assert_eq!(Apk::new().installed_count(root.path()), Some(2));
+
+        let dpkg = root.path().join("var/lib/dpkg");
+        fs::create_dir_all(&dpkg).unwrap();
+        fs::write(
+            dpkg.join("status"),
+            "Package: bash\nStatus: install ok installed\n\n\
+             Package: old\nStatus: deinstall ok config-files\n",
+        )
+        .unwrap();
+        assert_eq!(Apt::new().installed_count(root.path()), Some(1));
     }
 }
@@ -1,9 +1,27 @@
 use super::PackageManager;
 use anyhow::Result;
+use std::collections::HashSet;
 use std::path::Path;
 
 use crate::cmd;
 
+/// Packages a bootstrapped system can't boot or manage packages without
+const ESSENTIAL_PACKAGES: &[&str] = &[
+    "base",
+    "linux",
+    "linux-firmware",
+    "glibc",
+    "pacman",
+    "cryptsetup",
+    "btrfs-progs",
+    "efibootmgr",
+    "dracut",
+    "openrc",
+    "runit",
+    "s6-base",
+    "dinit",
+];
+
 /// Pacman package manager (Arch Linux, Artix, Manjaro, etc.)
 #[derive(Debug, Clone, Default)]
 pub struct Pacman;
@@ -43,7 +61,69 @@ impl PackageManager for Pacman {
 
     fn install_kernel_hooks(&self, root: &Path) -> Result<()> {
         crate::hooks::install_pacman_hooks(root)?;
-        crate::hooks::install_uki_rebuild_script(root)?;
+        crate::hooks::install_uki_rebuild_binary(root)?;
         Ok(())
     }
+
+    fn installed_count(&self, root: &Path) -> Option<usize> {
+        super::count_dirs(&root.join("var/lib/pacman/local"))
+    }
+
+    fn installed_packages(&self, root: &Path) -> Result<HashSet<String>> {
+        let entries = super::database_entries(&root.join("var/lib/pacman/local"))?;
+        Ok(entries
+            .iter()
+            .filter_map(|entry| package_name(entry))
+            .map(String::from)
+            .collect())
+    }
+
+    fn remove(&self, root: &Path, packages: &[&str]) -> Result<()> {
+        if packages.is_empty() {
+            return Ok(());
+        }
+        super::check_removable(packages, ESSENTIAL_PACKAGES)?;
+
+        let root_str = root.to_string_lossy().to_string();
+        cmd::run("pacman", remove_args(&root_str, packages))
+    }
+}
+
+/// `bash-5.2.026-2` to `bash`: a local database directory ends in the
+/// version and release, and names may have dashes of their own
+fn package_name(entry: &str) -> Option<&str> {
+    let mut fields = entry.rsplitn(3, '-');
+    let (_release, _version) = (fields.next()?, fields.next()?);
+    fields.next()
+}
+
+/// `-Rns` also takes the packages' config backups and dependencies nothing
+/// else needs
+fn remove_args<'a>(root: &'a str, packages: &[&'a str]) -> Vec<&'a str> {
+    let mut args = vec!["-Rns", "--noconfirm", "-r", root];
+    args.extend(packages);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_database_entries_name_packages() {
+        assert_eq!(package_name("bash-5.2.026-2"), Some("bash"));
+        assert_eq!(
+            package_name("linux-firmware-20240610.1-1"),
+            Some("linux-firmware")
+        );
+        assert_eq!(package_name("ALPM_DB_VERSION"), None);
+    }
+
+    #[test]
+    fn remove_is_recursive_and_unattended() {
+        assert_eq!(
+            remove_args("/mnt", &["firefox", "vlc"]),
+            ["-Rns", "--noconfirm", "-r", "/mnt", "firefox", "vlc"]
+        );
+    }
 }
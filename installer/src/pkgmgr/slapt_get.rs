@@ -1,5 +1,6 @@
 use super::PackageManager;
 use anyhow::Result;
+use std::collections::HashSet;
 use std::path::Path;
 
 use crate::cmd;
@@ -42,6 +43,42 @@ impl PackageManager for SlaptGet {
     }
 
     fn install_kernel_hooks(&self, root: &Path) -> Result<()> {
-        crate::hooks::install_uki_rebuild_script(root)
+        crate::hooks::install_uki_rebuild_binary(root)
+    }
+
+    fn installed_count(&self, root: &Path) -> Option<usize> {
+        super::count_entries(&root.join("var/lib/pkgtools/packages"))
+    }
+
+    fn installed_packages(&self, root: &Path) -> Result<HashSet<String>> {
+        let entries = super::database_entries(&root.join("var/lib/pkgtools/packages"))?;
+        Ok(entries
+            .iter()
+            .filter_map(|entry| package_name(entry))
+            .map(String::from)
+            .collect())
+    }
+}
+
+/// `glibc-2.39-x86_64-2` to `glibc`: package records end in the version,
+/// architecture and build, and names may have dashes of their own
+fn package_name(entry: &str) -> Option<&str> {
+    let mut fields = entry.rsplitn(4, '-');
+    let (_build, _arch, _version) = (fields.next()?, fields.next()?, fields.next()?);
+    fields.next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_records_name_packages() {
+        assert_eq!(package_name("glibc-2.39-x86_64-2"), Some("glibc"));
+        assert_eq!(
+            package_name("kernel-firmware-20240610_1234abc-noarch-1"),
+            Some("kernel-firmware")
+        );
+        assert_eq!(package_name("README"), None);
     }
 }
@@ -1,9 +1,25 @@
 use super::PackageManager;
 use anyhow::Result;
+use std::collections::HashSet;
 use std::path::Path;
 
 use crate::cmd;
 
+/// Packages a bootstrapped system can't boot or manage packages without
+const ESSENTIAL_PACKAGES: &[&str] = &[
+    "base-system",
+    "linux",
+    "linux-firmware",
+    "glibc",
+    "musl",
+    "xbps",
+    "runit-void",
+    "cryptsetup",
+    "btrfs-progs",
+    "efibootmgr",
+    "dracut",
+];
+
 /// XBPS package manager (Void Linux)
 #[derive(Debug, Clone, Default)]
 pub struct Xbps {
@@ -54,7 +70,80 @@ impl PackageManager for Xbps {
 
     fn install_kernel_hooks(&self, root: &Path) -> Result<()> {
         crate::hooks::install_void_kernel_hooks(root)?;
-        crate::hooks::install_uki_rebuild_script(root)?;
+        crate::hooks::install_uki_rebuild_binary(root)?;
         Ok(())
     }
+
+    fn installed_count(&self, root: &Path) -> Option<usize> {
+        super::count_in_file(
+            &root.join("var/db/xbps/pkgdb-0.38.plist"),
+            "<key>pkgver</key>",
+        )
+    }
+
+    fn installed_packages(&self, root: &Path) -> Result<HashSet<String>> {
+        let pkgdb = super::read_database(&root.join("var/db/xbps/pkgdb-0.38.plist"))?;
+        Ok(parse_pkgdb(&pkgdb))
+    }
+
+    fn remove(&self, root: &Path, packages: &[&str]) -> Result<()> {
+        if packages.is_empty() {
+            return Ok(());
+        }
+        super::check_removable(packages, ESSENTIAL_PACKAGES)?;
+
+        let root_str = root.to_string_lossy().to_string();
+        cmd::run("xbps-remove", remove_args(&root_str, packages))
+    }
+}
+
+/// Package names from the pkgver (`name-version_revision`) of each record
+/// in the package database plist
+fn parse_pkgdb(plist: &str) -> HashSet<String> {
+    plist
+        .split("<key>pkgver</key>")
+        .skip(1)
+        .filter_map(|record| {
+            let pkgver = record
+                .trim_start()
+                .strip_prefix("<string>")?
+                .split("</string>")
+                .next()?;
+            pkgver.rsplit_once('-').map(|(name, _)| name.to_string())
+        })
+        .collect()
+}
+
+/// `-R` also takes dependencies nothing else needs
+fn remove_args<'a>(root: &'a str, packages: &[&'a str]) -> Vec<&'a str> {
+    let mut args = vec!["-R", "-r", root, "-y"];
+    args.extend(packages);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pkgdb_names_packages_by_pkgver() {
+        let plist = "<dict>\n\t<key>base-system</key>\n\t<dict>\n\
+                     \t\t<key>pkgver</key>\n\t\t<string>base-system-0.114_2</string>\n\
+                     \t</dict>\n\t<key>xbps</key>\n\t<dict>\n\
+                     \t\t<key>pkgver</key>\n\t\t<string>xbps-0.59.2_1</string>\n\
+                     \t</dict>\n</dict>\n";
+        assert_eq!(
+            parse_pkgdb(plist),
+            HashSet::from(["base-system".into(), "xbps".into()])
+        );
+        assert!(parse_pkgdb("").is_empty());
+    }
+
+    #[test]
+    fn remove_is_recursive_and_unattended() {
+        assert_eq!(
+            remove_args("/mnt", &["firefox"]),
+            ["-R", "-r", "/mnt", "-y", "firefox"]
+        );
+    }
 }
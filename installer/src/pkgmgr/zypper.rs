@@ -0,0 +1,97 @@
+use super::PackageManager;
+use anyhow::Result;
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::cmd;
+
+/// Zypper package manager (openSUSE)
+#[derive(Debug, Clone, Default)]
+pub struct Zypper;
+
+impl Zypper {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl PackageManager for Zypper {
+    fn name(&self) -> &str {
+        "zypper"
+    }
+
+    fn install(&self, root: &Path, packages: &[&str]) -> Result<()> {
+        if packages.is_empty() {
+            return Ok(());
+        }
+
+        let root_str = root.to_string_lossy().to_string();
+        let mut args: Vec<&str> = vec![
+            "--root",
+            &root_str,
+            "--gpg-auto-import-keys",
+            "install",
+            "-y",
+        ];
+        args.extend(packages);
+
+        cmd::run("zypper", args)
+    }
+
+    fn update(&self, root: &Path) -> Result<()> {
+        let root_str = root.to_string_lossy().to_string();
+        cmd::run(
+            "zypper",
+            ["--root", &root_str, "--gpg-auto-import-keys", "refresh"],
+        )
+    }
+
+    fn upgrade(&self, root: &Path) -> Result<()> {
+        // Tumbleweed is rolling, so upgrades are distribution upgrades
+        let root_str = root.to_string_lossy().to_string();
+        cmd::run("zypper", ["--root", &root_str, "dist-upgrade", "-y"])
+    }
+
+    fn install_kernel_hooks(&self, root: &Path) -> Result<()> {
+        crate::hooks::install_uki_rebuild_binary(root)
+    }
+
+    fn installed_count(&self, root: &Path) -> Option<usize> {
+        // The rpm database isn't plain files, so ask rpm
+        let root_str = root.to_string_lossy();
+        let output = cmd::run_output("rpm", ["--root", &root_str, "-qa"]).ok()?;
+        Some(output.lines().count())
+    }
+
+    fn installed_packages(&self, root: &Path) -> Result<HashSet<String>> {
+        let root_str = root.to_string_lossy();
+        let output = cmd::run_output(
+            "rpm",
+            ["--root", &root_str, "-qa", "--queryformat", "%{NAME}\n"],
+        )?;
+        Ok(parse_names(&output))
+    }
+}
+
+/// One package name per line, as the rpm query formats them
+fn parse_names(output: &str) -> HashSet<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rpm_query_names_packages() {
+        assert_eq!(
+            parse_names("bash\nglibc\n\nzypper\n"),
+            HashSet::from(["bash".into(), "glibc".into(), "zypper".into()])
+        );
+    }
+}
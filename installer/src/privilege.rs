@@ -0,0 +1,195 @@
+//! Administrator access for the wheel group, through sudo or doas
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::distro::packages::PackageDatabase;
+use crate::distro::{Distro, DistroKind};
+use crate::manifest::SecurityConfig;
+
+/// Drop-in granting wheel sudo, relative to the root
+pub const SUDOERS_WHEEL: &str = "etc/sudoers.d/wheel";
+
+/// doas reads a single file, relative to the root
+pub const DOAS_CONF: &str = "etc/doas.conf";
+
+/// First line of files mkOS writes, so a hand-written doas.conf is never
+/// removed
+const HEADER: &str = "# Written by mkOS from security.*";
+
+/// Tools wheel can get root through
+const TOOLS: &[&str] = &["sudo", "doas"];
+
+/// Install the configured tool and grant wheel access through it
+pub fn setup_privilege(root: &Path, config: &SecurityConfig, distro: &dyn Distro) -> Result<()> {
+    validate_security(config)?;
+    // Granting wheel through a tool that isn't installed locks admins out
+    if distro.map_package(config.tool()).is_none() {
+        bail!("{} does not package {}", distro.name(), config.tool());
+    }
+    distro.install_packages(root, &[config.tool()])?;
+    write_privilege_config(root, config)
+}
+
+/// Check exactly one supported tool is selected
+pub fn validate_security(config: &SecurityConfig) -> Result<()> {
+    let tool = config.tool();
+    if !TOOLS.contains(&tool) {
+        bail!(
+            "Unknown privilege escalation tool: {}. Supported: {}",
+            tool,
+            TOOLS.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Check `distro` packages the selected tool
+pub fn check_packaged(config: &SecurityConfig, distro: DistroKind) -> Result<()> {
+    if PackageDatabase::global()
+        .map_for_distro(config.tool(), distro)
+        .is_none()
+    {
+        bail!("{} does not package {}", distro.name(), config.tool());
+    }
+    Ok(())
+}
+
+/// Grant wheel root through the configured tool, and drop the grant mkOS
+/// wrote for the other one
+pub fn write_privilege_config(root: &Path, config: &SecurityConfig) -> Result<()> {
+    validate_security(config)?;
+
+    let (path, content, mode, other) = match config.tool() {
+        "doas" => (
+            DOAS_CONF,
+            doas_conf(config.wheel_nopasswd),
+            0o400,
+            SUDOERS_WHEEL,
+        ),
+        _ => (
+            SUDOERS_WHEEL,
+            sudoers_wheel(config.wheel_nopasswd),
+            0o440,
+            DOAS_CONF,
+        ),
+    };
+
+    let path = root.join(path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+    // Both tools refuse files others can write
+    fs::set_permissions(&path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions on {}", path.display()))?;
+
+    let other = root.join(other);
+    let ours = fs::read_to_string(&other)
+        .map(|content| content.starts_with(HEADER))
+        .unwrap_or(false);
+    if ours {
+        fs::remove_file(&other).with_context(|| format!("Failed to remove {}", other.display()))?;
+    }
+
+    Ok(())
+}
+
+fn sudoers_wheel(nopasswd: bool) -> String {
+    let tag = if nopasswd { "NOPASSWD: " } else { "" };
+    format!("{}\n%wheel ALL=(ALL:ALL) {}ALL\n", HEADER, tag)
+}
+
+fn doas_conf(nopasswd: bool) -> String {
+    let option = if nopasswd { "nopass" } else { "persist" };
+    // doas rejects a last rule without a trailing newline
+    format!("{}\npermit {} :wheel\n", HEADER, option)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn security(tool: &str, wheel_nopasswd: bool) -> SecurityConfig {
+        SecurityConfig {
+            privilege_escalation: Some(tool.into()),
+            wheel_nopasswd,
+        }
+    }
+
+    #[test]
+    fn sudoers_drop_in_honours_nopasswd() {
+        assert_eq!(
+            sudoers_wheel(false),
+            "# Written by mkOS from security.*\n%wheel ALL=(ALL:ALL) ALL\n"
+        );
+        assert_eq!(
+            sudoers_wheel(true),
+            "# Written by mkOS from security.*\n%wheel ALL=(ALL:ALL) NOPASSWD: ALL\n"
+        );
+    }
+
+    #[test]
+    fn doas_conf_honours_nopasswd() {
+        assert_eq!(
+            doas_conf(false),
+            "# Written by mkOS from security.*\npermit persist :wheel\n"
+        );
+        assert_eq!(
+            doas_conf(true),
+            "# Written by mkOS from security.*\npermit nopass :wheel\n"
+        );
+    }
+
+    #[test]
+    fn switching_tools_drops_the_other_grant() {
+        let root = tempfile::tempdir().unwrap();
+        write_privilege_config(root.path(), &SecurityConfig::default()).unwrap();
+        let sudoers = root.path().join(SUDOERS_WHEEL);
+        assert_eq!(
+            fs::metadata(&sudoers).unwrap().permissions().mode() & 0o777,
+            0o440
+        );
+
+        write_privilege_config(root.path(), &security("doas", true)).unwrap();
+        assert!(!sudoers.exists());
+        let doas = fs::read_to_string(root.path().join(DOAS_CONF)).unwrap();
+        assert!(doas.ends_with("permit nopass :wheel\n"));
+
+        write_privilege_config(root.path(), &security("sudo", false)).unwrap();
+        assert!(sudoers.exists());
+        assert!(!root.path().join(DOAS_CONF).exists());
+    }
+
+    #[test]
+    fn switching_tools_keeps_a_grant_mkos_did_not_write() {
+        let root = tempfile::tempdir().unwrap();
+        let sudoers = root.path().join(SUDOERS_WHEEL);
+        fs::create_dir_all(sudoers.parent().unwrap()).unwrap();
+        fs::write(&sudoers, "%wheel ALL=(ALL:ALL) ALL\n").unwrap();
+
+        write_privilege_config(root.path(), &security("doas", false)).unwrap();
+        assert!(sudoers.exists());
+    }
+
+    #[test]
+    fn tool_must_be_packaged() {
+        assert!(check_packaged(&security("doas", false), DistroKind::Artix).is_ok());
+        assert!(check_packaged(&security("sudo", false), DistroKind::Slackware).is_ok());
+        let err = check_packaged(&security("doas", false), DistroKind::Slackware).unwrap_err();
+        assert_eq!(err.to_string(), "Slackware Linux does not package doas");
+    }
+
+    #[test]
+    fn only_sudo_or_doas() {
+        assert!(validate_security(&SecurityConfig::default()).is_ok());
+        assert!(validate_security(&security("doas", false)).is_ok());
+        let err = validate_security(&security("sudo,doas", false)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown privilege escalation tool: sudo,doas. Supported: sudo, doas"
+        );
+    }
+}
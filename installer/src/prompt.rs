@@ -6,7 +6,10 @@
 //! user is prompted interactively.
 
 use anyhow::{bail, Result};
-use std::io::{self, Write};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{self, ClearType};
+use crossterm::{cursor, queue};
+use std::io::{self, IsTerminal, Write};
 
 /// Type of prompt to display for a field
 #[derive(Debug, Clone)]
@@ -33,6 +36,11 @@ pub enum PromptKind {
     },
     /// Number input with range
     Number { min: i64, max: i64, default: i64 },
+    /// Pick from a long list, narrowed as the user types
+    Search {
+        options: Vec<String>,
+        default: String,
+    },
 }
 
 /// An option in a select prompt
@@ -185,6 +193,23 @@ impl FieldSpec {
         }
     }
 
+    pub fn search(
+        key: impl Into<String>,
+        prompt: impl Into<String>,
+        options: Vec<String>,
+        default: impl Into<String>,
+    ) -> Self {
+        Self {
+            key: key.into(),
+            prompt: prompt.into(),
+            kind: PromptKind::Search {
+                options,
+                default: default.into(),
+            },
+            enabled: true,
+        }
+    }
+
     /// Disable this field (won't be prompted)
     pub fn disabled(mut self) -> Self {
         self.enabled = false;
@@ -275,9 +300,31 @@ pub fn prompt_field(spec: &FieldSpec) -> Result<FieldValue> {
             let value = prompt_number(&spec.prompt, *min, *max, *default)?;
             Ok(FieldValue::Number(value))
         }
+        PromptKind::Search { options, default } => {
+            let value = prompt_search(&spec.prompt, options, default)?;
+            Ok(FieldValue::Text(value))
+        }
     }
 }
 
+/// `options` containing `query`, ignoring case, with those where it starts
+/// a word (`Den` in `America/Denver`) first
+pub fn filter_options<'a>(options: &'a [String], query: &str) -> Vec<&'a str> {
+    let query = query.to_lowercase();
+    let (mut word_starts, rest): (Vec<&str>, Vec<&str>) = options
+        .iter()
+        .map(String::as_str)
+        .filter(|option| option.to_lowercase().contains(&query))
+        .partition(|option| {
+            option
+                .to_lowercase()
+                .split(['/', '_', '-', '.'])
+                .any(|word| word.starts_with(&query))
+        });
+    word_starts.extend(rest);
+    word_starts
+}
+
 // ============================================================================
 // Low-level prompt functions
 // ============================================================================
@@ -438,6 +485,96 @@ fn prompt_optional_select(
     }
 }
 
+/// Matches shown below a search prompt
+const SEARCH_RESULTS: usize = 8;
+
+fn prompt_search(prompt: &str, options: &[String], default: &str) -> Result<String> {
+    if options.is_empty() || !io::stdin().is_terminal() {
+        return prompt_text_default(prompt, default);
+    }
+
+    terminal::enable_raw_mode()?;
+    let picked = search_loop(prompt, options, default);
+    terminal::disable_raw_mode()?;
+    println!();
+    picked
+}
+
+/// Read keys until one of `options` is picked: typing narrows the list,
+/// arrows move through it, Enter takes the highlighted match (or `default`
+/// with nothing typed) and Esc takes `default`
+fn search_loop(prompt: &str, options: &[String], default: &str) -> Result<String> {
+    let mut query = String::new();
+    let mut selected = 0;
+    let mut out = io::stdout();
+
+    loop {
+        let matches = filter_options(options, &query);
+        selected = selected.min(matches.len().saturating_sub(1));
+
+        queue!(
+            out,
+            cursor::MoveToColumn(0),
+            terminal::Clear(ClearType::FromCursorDown)
+        )?;
+        write!(out, "{} [{}]: {}", prompt, default, query)?;
+        let shown = matches.len().min(SEARCH_RESULTS);
+        for (i, option) in matches.iter().take(shown).enumerate() {
+            let marker = if i == selected { ">" } else { " " };
+            write!(out, "\r\n  {} {}", marker, option)?;
+        }
+        if matches.len() > shown {
+            write!(out, "\r\n    ... {} more", matches.len() - shown)?;
+        } else if matches.is_empty() {
+            write!(out, "\r\n    no matches")?;
+        }
+        let below = shown.max(1) as u16 + u16::from(matches.len() > shown);
+        let column =
+            (prompt.chars().count() + default.chars().count() + query.chars().count() + 5) as u16;
+        queue!(out, cursor::MoveUp(below), cursor::MoveToColumn(column))?;
+        out.flush()?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Enter if query.is_empty() => return finish(default),
+            KeyCode::Enter => {
+                if let Some(option) = matches.get(selected) {
+                    return finish(option);
+                }
+            }
+            KeyCode::Esc => return finish(default),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                finish("")?;
+                bail!("Interrupted");
+            }
+            KeyCode::Up => selected = selected.saturating_sub(1),
+            KeyCode::Down if selected + 1 < shown => selected += 1,
+            KeyCode::Backspace => {
+                query.pop();
+                selected = 0;
+            }
+            KeyCode::Char(c) => {
+                query.push(c);
+                selected = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Clear the match list and leave `value` after the prompt
+fn finish(value: &str) -> Result<String> {
+    let mut out = io::stdout();
+    queue!(out, terminal::Clear(ClearType::FromCursorDown))?;
+    out.flush()?;
+    Ok(value.to_string())
+}
+
 fn prompt_number(prompt: &str, min: i64, max: i64, default: i64) -> Result<i64> {
     loop {
         print!("{} [{}-{}, default={}]: ", prompt, min, max, default);
@@ -511,4 +648,35 @@ mod tests {
         let conditional = FieldSpec::text("test", "Test").when(false);
         assert!(!conditional.enabled);
     }
+
+    #[test]
+    fn search_narrows_as_you_type() {
+        let zones: Vec<String> = [
+            "America/Denver",
+            "America/New_York",
+            "Europe/Berlin",
+            "Europe/Copenhagen",
+            "Pacific/Honolulu",
+        ]
+        .iter()
+        .map(|z| z.to_string())
+        .collect();
+
+        assert_eq!(filter_options(&zones, "").len(), zones.len());
+        assert_eq!(filter_options(&zones, "Den"), ["America/Denver"]);
+        assert_eq!(
+            filter_options(&zones, "en"),
+            ["America/Denver", "Europe/Copenhagen"]
+        );
+        assert_eq!(
+            filter_options(&zones, "h"),
+            ["Pacific/Honolulu", "Europe/Copenhagen"]
+        );
+        assert_eq!(filter_options(&zones, "york"), ["America/New_York"]);
+        assert_eq!(
+            filter_options(&zones, "europe/"),
+            ["Europe/Berlin", "Europe/Copenhagen"]
+        );
+        assert!(filter_options(&zones, "Mars").is_empty());
+    }
 }
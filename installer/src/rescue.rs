@@ -62,7 +62,7 @@ pub fn mount_system(efi_partition: &Path, luks_partition: &Path, passphrase: &st
 
     // Open LUKS
     println!("Opening LUKS partition...");
-    open_luks(luks_partition, LUKS_MAPPER, passphrase)?;
+    open_luks(luks_partition, LUKS_MAPPER, passphrase, None)?;
 
     let mapper_device = PathBuf::from(format!("/dev/mapper/{}", LUKS_MAPPER));
 
@@ -109,7 +109,12 @@ fn mount_btrfs_subvolumes(device: &Path, target: &Path) -> Result<()> {
             std::fs::create_dir_all(&mount_path)?;
         }
 
-        let opts = format!("subvol={},compress=zstd:1", subvol);
+        // Swapfiles can't live on a compressed mount
+        let opts = if subvol == "@swap" {
+            format!("subvol={}", subvol)
+        } else {
+            format!("subvol={},compress=zstd:1", subvol)
+        };
         let mount_str = mount_path.to_string_lossy().to_string();
         if let Err(e) = cmd::run("mount", ["-o", &opts, &device_str, &mount_str]) {
             println!("  Warning: could not mount {}: {}", subvol, e);
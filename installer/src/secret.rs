@@ -0,0 +1,109 @@
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// Shown in place of a secret
+pub const REDACTED: &str = "***";
+
+/// A passphrase or password, wiped from memory when dropped and never shown
+/// by `Debug`
+#[derive(Clone, Default)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    /// The secret itself, for the command that needs it
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for Secret {
+    fn from(secret: String) -> Self {
+        Self(Zeroizing::new(secret))
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(secret: &str) -> Self {
+        Self::from(secret.to_string())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Secret::from)
+    }
+}
+
+/// `text` with every occurrence of `secrets` replaced by [`REDACTED`]
+pub fn redact(text: &str, secrets: &[&Secret]) -> String {
+    secrets
+        .iter()
+        .filter(|secret| !secret.is_empty())
+        .fold(text.to_string(), |text, secret| {
+            text.replace(secret.expose(), REDACTED)
+        })
+}
+
+/// `error`, flattened to its message with `secrets` redacted when any of
+/// them shows up in it
+///
+/// Errors without secrets come back untouched, context chain and all.
+pub fn redact_error(error: anyhow::Error, secrets: &[&Secret]) -> anyhow::Error {
+    let message = format!("{:#}", error);
+    let redacted = redact(&message, secrets);
+    if redacted == message {
+        error
+    } else {
+        anyhow::anyhow!(redacted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::install::InstallConfig;
+
+    #[test]
+    fn debug_never_shows_the_secret() {
+        let config = InstallConfig {
+            passphrase: "correct horse battery".into(),
+            root_password: "hunter22".into(),
+            ..Default::default()
+        };
+
+        let debug = format!("{:?}", config);
+        assert!(!debug.contains("correct horse battery"));
+        assert!(!debug.contains("hunter22"));
+        assert!(debug.contains("passphrase: ***"));
+        assert_eq!(config.passphrase.expose(), "correct horse battery");
+    }
+
+    #[test]
+    fn secrets_are_redacted_from_errors() {
+        let passphrase = Secret::from("correct horse battery");
+        let empty = Secret::default();
+
+        let error =
+            anyhow::anyhow!("echoed correct horse battery").context("cryptsetup luksFormat failed");
+        let redacted = redact_error(error, &[&passphrase, &empty]);
+        assert_eq!(
+            format!("{:#}", redacted),
+            "cryptsetup luksFormat failed: echoed ***"
+        );
+
+        let error = anyhow::anyhow!("no such device").context("wipefs failed");
+        let untouched = redact_error(error, &[&passphrase, &empty]);
+        assert_eq!(untouched.chain().count(), 2);
+    }
+}
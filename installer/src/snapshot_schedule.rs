@@ -0,0 +1,239 @@
+//! Scheduled snapshots: cron entries that run `mkos snapshot create --auto`
+//! plus a boot oneshot that catches up on a run missed while powered off
+
+use anyhow::{bail, Context, Result};
+use chrono::{Duration, NaiveDateTime};
+use std::fs;
+use std::path::Path;
+
+use crate::crypt::FilesystemKind;
+use crate::distro::Distro;
+use crate::init::ServiceSpec;
+use crate::manifest::SnapshotSchedule;
+
+/// System crontab holding the snapshot schedule
+pub const CRONTAB: &str = "etc/cron.d/mkos-snapshots";
+
+/// Boot oneshot that takes a snapshot cron missed
+pub const BOOT_SERVICE: &str = "mkos-snapshot";
+
+/// cron's default PATH doesn't include /usr/local/bin
+const CRON_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// A kind of scheduled snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Scheduled {
+    Hourly,
+    Daily,
+}
+
+impl Scheduled {
+    pub fn name(self) -> &'static str {
+        match self {
+            Scheduled::Hourly => "hourly",
+            Scheduled::Daily => "daily",
+        }
+    }
+
+    /// Name prefix of this kind's snapshots
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Scheduled::Hourly => "hourly-",
+            Scheduled::Daily => "daily-",
+        }
+    }
+
+    fn period(self) -> Duration {
+        match self {
+            Scheduled::Hourly => Duration::hours(1),
+            Scheduled::Daily => Duration::days(1),
+        }
+    }
+
+    /// cron time fields; daily runs off the hour so it never races hourly
+    fn cron_time(self) -> &'static str {
+        match self {
+            Scheduled::Hourly => "0 * * * *",
+            Scheduled::Daily => "30 0 * * *",
+        }
+    }
+
+    /// How many snapshots of this kind `schedule` keeps
+    fn keep(self, schedule: &SnapshotSchedule) -> usize {
+        match self {
+            Scheduled::Hourly => schedule.hourly,
+            Scheduled::Daily => schedule.daily,
+        }
+    }
+
+    /// Whether a snapshot of this kind is due, given the newest one
+    ///
+    /// Five sixths of the period is enough, so a cron run that fires a few
+    /// seconds early isn't skipped.
+    pub fn is_due(self, newest: Option<NaiveDateTime>, now: NaiveDateTime) -> bool {
+        newest.is_none_or(|newest| now - newest >= self.period() * 5 / 6)
+    }
+}
+
+/// The kinds `schedule` takes, with how many of each to keep
+fn scheduled_kinds(schedule: &SnapshotSchedule) -> Vec<(Scheduled, usize)> {
+    [Scheduled::Hourly, Scheduled::Daily]
+        .into_iter()
+        .map(|kind| (kind, kind.keep(schedule)))
+        .filter(|(_, keep)| *keep > 0)
+        .collect()
+}
+
+fn create_command(kind: Scheduled, keep: usize) -> String {
+    format!(
+        "mkos snapshot create --auto {} --keep {}",
+        kind.name(),
+        keep
+    )
+}
+
+/// Check the schedule can be honoured on `filesystem`
+pub fn validate_schedule(schedule: &SnapshotSchedule, filesystem: &str) -> Result<()> {
+    if !schedule.is_empty() && filesystem.parse::<FilesystemKind>()? != FilesystemKind::Btrfs {
+        bail!("scheduled snapshots need a btrfs root, not {}", filesystem);
+    }
+    Ok(())
+}
+
+/// Install cron and mkos, write the snapshot crontab and enable the boot
+/// catch-up
+pub fn setup_snapshot_schedule(
+    root: &Path,
+    schedule: &SnapshotSchedule,
+    distro: &dyn Distro,
+) -> Result<()> {
+    if schedule.is_empty() {
+        return Ok(());
+    }
+
+    distro.install_packages(root, &["cronie"])?;
+    distro.install_service_scripts(root, "cronie")?;
+    // Both the crontab and the boot oneshot run it
+    crate::hooks::install_binary(root, crate::hooks::MKOS)?;
+
+    let path = root.join(CRONTAB);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("Failed to create /etc/cron.d")?;
+    }
+    fs::write(&path, crontab(schedule)).with_context(|| format!("Failed to write /{}", CRONTAB))?;
+
+    let init = distro.init_system();
+    if let Some(spec) = boot_service(schedule) {
+        init.create_service(root, &spec)?;
+        init.enable_service(root, BOOT_SERVICE)?;
+    }
+    crate::network::enable_service(root, distro, "crond")
+}
+
+/// System crontab taking and pruning each scheduled kind of snapshot
+pub fn crontab(schedule: &SnapshotSchedule) -> String {
+    let mut out = format!(
+        "# Scheduled snapshots, written by mkOS\nPATH={}\n",
+        CRON_PATH
+    );
+    for (kind, keep) in scheduled_kinds(schedule) {
+        out.push_str(&format!(
+            "{} root {}\n",
+            kind.cron_time(),
+            create_command(kind, keep)
+        ));
+    }
+    out
+}
+
+/// Oneshot run at boot for the longest scheduled period, since cron doesn't
+/// make up for runs missed while the machine was off
+///
+/// `--auto` only snapshots when one is due, so this is a no-op on machines
+/// that stay up.
+pub fn boot_service(schedule: &SnapshotSchedule) -> Option<ServiceSpec> {
+    let (kind, keep) = scheduled_kinds(schedule).pop()?;
+    Some(ServiceSpec::oneshot(
+        BOOT_SERVICE,
+        create_command(kind, keep),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::ServiceType;
+
+    fn at(ts: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(ts, "%Y-%m-%dT%H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn crontab_has_a_line_per_kind() {
+        let schedule = SnapshotSchedule {
+            hourly: 24,
+            daily: 7,
+        };
+        assert_eq!(
+            crontab(&schedule),
+            "# Scheduled snapshots, written by mkOS\n\
+             PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin\n\
+             0 * * * * root mkos snapshot create --auto hourly --keep 24\n\
+             30 0 * * * root mkos snapshot create --auto daily --keep 7\n"
+        );
+    }
+
+    #[test]
+    fn crontab_skips_kinds_kept_at_zero() {
+        let schedule = SnapshotSchedule {
+            hourly: 0,
+            daily: 14,
+        };
+        let crontab = crontab(&schedule);
+        assert!(!crontab.contains("hourly"));
+        assert!(crontab.contains("30 0 * * * root mkos snapshot create --auto daily --keep 14\n"));
+    }
+
+    #[test]
+    fn boot_service_catches_up_the_longest_period() {
+        let schedule = SnapshotSchedule {
+            hourly: 24,
+            daily: 7,
+        };
+        let spec = boot_service(&schedule).unwrap();
+        assert_eq!(spec.name, "mkos-snapshot");
+        assert_eq!(spec.command, "mkos snapshot create --auto daily --keep 7");
+        assert_eq!(spec.service_type, ServiceType::Oneshot);
+
+        let hourly_only = SnapshotSchedule {
+            hourly: 12,
+            daily: 0,
+        };
+        assert_eq!(
+            boot_service(&hourly_only).unwrap().command,
+            "mkos snapshot create --auto hourly --keep 12"
+        );
+        assert!(boot_service(&SnapshotSchedule::default()).is_none());
+    }
+
+    #[test]
+    fn due_after_most_of_the_period() {
+        let now = at("2026-10-16T11:00:00");
+        assert!(Scheduled::Hourly.is_due(None, now));
+        assert!(Scheduled::Hourly.is_due(Some(at("2026-10-16T10:00:02")), now));
+        assert!(!Scheduled::Hourly.is_due(Some(at("2026-10-16T10:30:00")), now));
+        assert!(!Scheduled::Daily.is_due(Some(at("2026-10-16T00:30:00")), now));
+        assert!(Scheduled::Daily.is_due(Some(at("2026-10-15T00:30:00")), now));
+    }
+
+    #[test]
+    fn schedule_needs_btrfs() {
+        let schedule = SnapshotSchedule {
+            hourly: 0,
+            daily: 7,
+        };
+        assert!(validate_schedule(&schedule, "btrfs").is_ok());
+        assert!(validate_schedule(&schedule, "ext4").is_err());
+        assert!(validate_schedule(&SnapshotSchedule::default(), "ext4").is_ok());
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::Manifest;
+
+/// Where the last install or apply is recorded, relative to the root
+pub const STATE_PATH: &str = "var/lib/mkos/state.json";
+
+/// Stands in for secrets in the recorded manifest
+const REDACTED: &str = "<redacted>";
+
+/// What brought the system to its recorded state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StateAction {
+    Install,
+    Apply,
+}
+
+/// The manifest mkOS last brought a system in line with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemState {
+    pub action: StateAction,
+    /// RFC 3339, in UTC
+    pub applied_at: String,
+    /// mkOS version that did it
+    pub version: String,
+    /// With includes and the profile merged in, and secrets redacted
+    pub manifest: Manifest,
+}
+
+impl SystemState {
+    pub fn new(action: StateAction, manifest: &Manifest) -> Self {
+        Self {
+            action,
+            applied_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            version: env!("CARGO_PKG_VERSION").into(),
+            manifest: redacted(manifest),
+        }
+    }
+
+    /// The state recorded under `root`, None when nothing was recorded yet
+    pub fn read(root: &Path) -> Result<Option<Self>> {
+        let path = state_path(root);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        serde_json::from_str(&content)
+            .map(Some)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn write(&self, root: &Path) -> Result<()> {
+        let path = state_path(root);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// The state file of the system at `root`
+pub fn state_path(root: &Path) -> PathBuf {
+    root.join(STATE_PATH)
+}
+
+/// `manifest` without the secrets it may carry: WiFi and VPN keys and
+/// password hashes
+fn redacted(manifest: &Manifest) -> Manifest {
+    let mut manifest = manifest.clone();
+    if let Some(wifi) = &mut manifest.network.wifi {
+        wifi.psk = REDACTED.into();
+    }
+    if let Some(wireguard) = &mut manifest.network.wireguard {
+        wireguard.private_key = REDACTED.into();
+        for peer in &mut wireguard.peers {
+            if peer.preshared_key.is_some() {
+                peer.preshared_key = Some(REDACTED.into());
+            }
+        }
+    }
+    if let Some(tailscale) = &mut manifest.network.tailscale {
+        tailscale.authkey = REDACTED.into();
+    }
+    for user in manifest.users.values_mut() {
+        if user.password_hash.is_some() {
+            user.password_hash = Some(REDACTED.into());
+        }
+    }
+    manifest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{TailscaleConfig, UserConfig, WifiConfig};
+
+    #[test]
+    fn state_round_trips_without_secrets() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(SystemState::read(root.path()).unwrap().is_none());
+
+        let mut manifest = Manifest::default();
+        manifest.system.hostname = "node1".into();
+        manifest.network.wifi = Some(WifiConfig {
+            ssid: "home".into(),
+            psk: "hunter22".into(),
+        });
+        manifest.network.tailscale = Some(TailscaleConfig {
+            authkey: "tskey-auth-secret".into(),
+        });
+        manifest.users.insert(
+            "alice".into(),
+            UserConfig {
+                shell: "/bin/zsh".into(),
+                groups: Vec::new(),
+                password_hash: Some("$6$salt$hash".into()),
+                ssh_keys: Vec::new(),
+                home: None,
+                dotfiles: None,
+            },
+        );
+
+        SystemState::new(StateAction::Apply, &manifest)
+            .write(root.path())
+            .unwrap();
+
+        let content = fs::read_to_string(root.path().join(STATE_PATH)).unwrap();
+        assert!(!content.contains("hunter22"));
+        assert!(!content.contains("tskey-auth-secret"));
+        assert!(!content.contains("$6$salt$hash"));
+
+        let state = SystemState::read(root.path()).unwrap().unwrap();
+        assert_eq!(state.action, StateAction::Apply);
+        assert_eq!(state.version, env!("CARGO_PKG_VERSION"));
+        assert!(chrono::DateTime::parse_from_rfc3339(&state.applied_at).is_ok());
+        assert_eq!(state.manifest.system.hostname, "node1");
+        assert_eq!(state.manifest.network.wifi.unwrap().psk, REDACTED);
+        assert_eq!(
+            state.manifest.users["alice"].password_hash.as_deref(),
+            Some(REDACTED)
+        );
+    }
+
+    #[test]
+    fn corrupt_state_is_an_error() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("var/lib/mkos")).unwrap();
+        fs::write(root.path().join(STATE_PATH), "{").unwrap();
+        assert!(SystemState::read(root.path()).is_err());
+    }
+}
@@ -3,10 +3,17 @@ use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 
 use crate::cmd;
+use crate::crypt::FilesystemKind;
 use crate::install::SwapConfig;
 
+/// Swapfile location inside the target, on the @swap subvolume for btrfs
+pub const SWAPFILE: &str = "/swap/swapfile";
+
+/// Disk swap sits below zram (pri=100) so memory compression is used first
+const SWAPFILE_PRIORITY: u32 = 10;
+
 /// Set up swap (zram and/or swapfile) based on configuration
-pub fn setup_swap(root: &Path, config: &SwapConfig) -> Result<()> {
+pub fn setup_swap(root: &Path, config: &SwapConfig, filesystem: FilesystemKind) -> Result<()> {
     if config.zram_enabled {
         let size_gb = config.zram_size_gb.unwrap_or(8);
         setup_zram(root, size_gb)?;
@@ -14,7 +21,7 @@ pub fn setup_swap(root: &Path, config: &SwapConfig) -> Result<()> {
 
     if config.swapfile_enabled {
         let size_gb = config.swapfile_size_gb.unwrap_or(8);
-        setup_swapfile(root, size_gb)?;
+        setup_swapfile(root, size_gb, filesystem)?;
     }
 
     if config.zram_enabled || config.swapfile_enabled {
@@ -24,6 +31,19 @@ pub fn setup_swap(root: &Path, config: &SwapConfig) -> Result<()> {
     Ok(())
 }
 
+/// Hibernation writes memory to the swapfile; zram lives in RAM and can't hold it
+pub fn validate_hibernation(config: &SwapConfig) -> Result<()> {
+    if config.hibernation && !config.swapfile_enabled {
+        anyhow::bail!("swap.hibernation requires swap.swapfile to be enabled");
+    }
+    Ok(())
+}
+
+/// Whether the swapfile may be too small to hold a hibernation image
+pub fn hibernation_swap_too_small(config: &SwapConfig, ram_gb: u32) -> bool {
+    config.hibernation && config.swapfile_size_gb.unwrap_or(8) < ram_gb
+}
+
 /// Create s6 service for zram swap
 fn setup_zram(root: &Path, size_gb: u32) -> Result<()> {
     let sv_dir = root.join("etc/s6/sv/zram");
@@ -63,51 +83,165 @@ swapon -p 100 /dev/zram0
     Ok(())
 }
 
-/// Create swapfile on btrfs (with COW disabled)
-fn setup_swapfile(root: &Path, size_gb: u32) -> Result<()> {
-    // Create /swap directory (for @swap subvolume mount)
-    let swap_dir = root.join("swap");
-    std::fs::create_dir_all(&swap_dir)?;
-
-    // Create swapfile in /swap directory (which will be @swap subvolume)
-    let swapfile = swap_dir.join("swapfile");
+/// Create the swapfile in /swap, which is the @swap subvolume on btrfs
+///
+/// btrfs can only swap to a file that is NOCOW, uncompressed and fully
+/// allocated. NOCOW only takes effect on an empty file, so it's set before
+/// any data is written. The swapfile lives inside the LUKS container, so it
+/// is encrypted along with the root filesystem.
+fn setup_swapfile(root: &Path, size_gb: u32, filesystem: FilesystemKind) -> Result<()> {
+    let swapfile = root.join(SWAPFILE.trim_start_matches('/'));
+    std::fs::create_dir_all(swapfile.parent().unwrap())?;
     let swapfile_str = swapfile.to_string_lossy().to_string();
+    let size = format!("{}G", size_gb);
+
+    if filesystem == FilesystemKind::Btrfs {
+        // btrfs-progs 6.1+ does NOCOW, preallocation and mkswap in one step
+        let mkswapfile = cmd::run(
+            "btrfs",
+            ["filesystem", "mkswapfile", "--size", &size, &swapfile_str],
+        );
+
+        if mkswapfile.is_err() {
+            let _ = std::fs::remove_file(&swapfile);
+            cmd::run("truncate", ["-s", "0", &swapfile_str])?;
+            cmd::run("chattr", ["+C", &swapfile_str])?;
+            cmd::run("fallocate", ["-l", &size, &swapfile_str])?;
+            cmd::run("mkswap", [&swapfile_str])?;
+        }
+    } else {
+        cmd::run("fallocate", ["-l", &size, &swapfile_str])?;
+        cmd::run("mkswap", [&swapfile_str])?;
+    }
 
-    // Create empty file first
-    cmd::run("truncate", ["-s", "0", &swapfile_str])?;
-
-    // Disable COW for btrfs (required for swap)
-    cmd::run("chattr", ["+C", &swapfile_str])?;
-
-    // Allocate space
-    cmd::run("fallocate", ["-l", &format!("{}G", size_gb), &swapfile_str])?;
-
-    // Set permissions (600)
     std::fs::set_permissions(&swapfile, std::fs::Permissions::from_mode(0o600))?;
 
-    // Format as swap
-    cmd::run("mkswap", [&swapfile_str])?;
-
-    // Add to fstab (low priority so zram is preferred)
     let fstab_path = root.join("etc/fstab");
-    let fstab_entry = "/swap/swapfile none swap defaults,pri=10 0 0\n";
-
     let existing = std::fs::read_to_string(&fstab_path).unwrap_or_default();
-    if !existing.contains("/swap/swapfile") {
-        let new_content = format!("{}\n{}", existing.trim_end(), fstab_entry);
+    if !existing.contains(SWAPFILE) {
+        let new_content = format!("{}\n{}", existing.trim_end(), swapfile_fstab_entry());
         std::fs::write(&fstab_path, new_content)?;
     }
 
     Ok(())
 }
 
+/// Page offset of the swapfile within its filesystem, for `resume_offset=`
+pub fn resume_offset(root: &Path, filesystem: FilesystemKind) -> Result<u64> {
+    let swapfile = root.join(SWAPFILE.trim_start_matches('/'));
+    let swapfile_str = swapfile.to_string_lossy().to_string();
+
+    if filesystem == FilesystemKind::Btrfs {
+        // filefrag reports btrfs logical addresses, which aren't what resume wants
+        let output = cmd::run_output(
+            "btrfs",
+            ["inspect-internal", "map-swapfile", "-r", &swapfile_str],
+        )?;
+        output
+            .trim()
+            .parse()
+            .with_context(|| format!("Unexpected map-swapfile output: {}", output.trim()))
+    } else {
+        let output = cmd::run_output("filefrag", ["-v", &swapfile_str])?;
+        parse_filefrag_offset(&output)
+            .with_context(|| format!("Could not find the first extent of {}", SWAPFILE))
+    }
+}
+
+/// Physical start of extent 0 from `filefrag -v` output
+///
+/// ```text
+///  ext:     logical_offset:        physical_offset: length:   expected: flags:
+///    0:        0..       0:      34816..     34816:      1:
+/// ```
+fn parse_filefrag_offset(output: &str) -> Option<u64> {
+    output.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.first() != Some(&"0:") {
+            return None;
+        }
+        fields.get(3)?.trim_end_matches("..").parse().ok()
+    })
+}
+
+fn swapfile_fstab_entry() -> String {
+    format!(
+        "{} none swap defaults,pri={} 0 0\n",
+        SWAPFILE, SWAPFILE_PRIORITY
+    )
+}
+
 /// Configure vm.swappiness via sysctl
 fn configure_swappiness(root: &Path, swappiness: u8) -> Result<()> {
     let sysctl_dir = root.join("etc/sysctl.d");
     std::fs::create_dir_all(&sysctl_dir)?;
 
-    let config = format!("vm.swappiness={}\n", swappiness);
-    std::fs::write(sysctl_dir.join("99-swap.conf"), config)?;
+    std::fs::write(
+        sysctl_dir.join("99-swap.conf"),
+        swappiness_sysctl(swappiness),
+    )?;
 
     Ok(())
 }
+
+fn swappiness_sysctl(swappiness: u8) -> String {
+    format!("vm.swappiness={}\n", swappiness)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hibernation_needs_swapfile() {
+        let zram_only = SwapConfig {
+            zram_enabled: true,
+            hibernation: true,
+            ..Default::default()
+        };
+        assert!(validate_hibernation(&zram_only).is_err());
+
+        let swapfile = SwapConfig {
+            swapfile_enabled: true,
+            swapfile_size_gb: Some(16),
+            hibernation: true,
+            ..Default::default()
+        };
+        assert!(validate_hibernation(&swapfile).is_ok());
+        assert!(!hibernation_swap_too_small(&swapfile, 16));
+        assert!(hibernation_swap_too_small(&swapfile, 32));
+    }
+
+    #[test]
+    fn fstab_entry_on_swap_subvolume() {
+        assert_eq!(
+            swapfile_fstab_entry(),
+            "/swap/swapfile none swap defaults,pri=10 0 0\n"
+        );
+    }
+
+    #[test]
+    fn filefrag_first_extent() {
+        let output = "Filesystem type is: ef53\n\
+                      File size of /swap/swapfile is 8589934592 (2097152 blocks of 4096 bytes)\n \
+                      ext:     logical_offset:        physical_offset: length:   expected: flags:\n   \
+                      0:        0..   32767:      34816..     67583:  32768:\n   \
+                      1:    32768..   63487:      67584..     98303:  30720:\n";
+        assert_eq!(parse_filefrag_offset(output), Some(34816));
+        assert_eq!(parse_filefrag_offset("no extents\n"), None);
+    }
+
+    #[test]
+    fn sysctl_drop_in() {
+        assert_eq!(swappiness_sysctl(20), "vm.swappiness=20\n");
+    }
+
+    #[test]
+    fn swappiness_written_to_drop_in() {
+        let root = tempfile::tempdir().unwrap();
+        configure_swappiness(root.path(), 60).unwrap();
+
+        let conf = std::fs::read_to_string(root.path().join("etc/sysctl.d/99-swap.conf")).unwrap();
+        assert_eq!(conf, "vm.swappiness=60\n");
+    }
+}
@@ -0,0 +1,267 @@
+//! Compare two directory trees by metadata, such as two snapshots of @
+//!
+//! Both trees are walked together in name order and changes are handed to a
+//! callback as they're found, so only the listings of the directories being
+//! walked are held in memory. Files count as modified when their type, size, mode, owner, mtime
+//! or symlink target differ; contents aren't read. The walk stays on each
+//! tree's own filesystem, which skips nested btrfs subvolumes and, on a live
+//! root, /proc, /sys and the like.
+
+use anyhow::{Context, Result};
+use std::cmp::Ordering;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// How a path differs between the old and the new tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+impl ChangeKind {
+    /// One-character marker for listings
+    pub fn marker(self) -> char {
+        match self {
+            ChangeKind::Added => '+',
+            ChangeKind::Removed => '-',
+            ChangeKind::Modified => 'M',
+        }
+    }
+}
+
+/// A changed path, relative to the tree roots
+///
+/// Added and removed directories are reported once, not file by file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+/// Counts of each kind of change
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiffSummary {
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+}
+
+impl DiffSummary {
+    pub fn record(&mut self, change: &Change) {
+        match change.kind {
+            ChangeKind::Added => self.added += 1,
+            ChangeKind::Removed => self.removed += 1,
+            ChangeKind::Modified => self.modified += 1,
+        }
+    }
+}
+
+/// Walk `old` and `new` together, calling `on_change` for each difference
+pub fn diff_trees(old: &Path, new: &Path, mut on_change: impl FnMut(Change)) -> Result<()> {
+    let devices = (device(old)?, device(new)?);
+    diff_dirs(old, new, Path::new(""), devices, &mut on_change)
+}
+
+fn device(path: &Path) -> Result<u64> {
+    Ok(fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .dev())
+}
+
+/// Entries of `dir` on `dev`, sorted by name
+fn entries(dir: &Path, dev: u64) -> Result<Vec<(std::ffi::OsString, Metadata)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.dev() == dev {
+            entries.push((entry.file_name(), metadata));
+        }
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(entries)
+}
+
+fn diff_dirs(
+    old: &Path,
+    new: &Path,
+    relative: &Path,
+    devices: (u64, u64),
+    on_change: &mut dyn FnMut(Change),
+) -> Result<()> {
+    let old_entries = entries(old, devices.0)?;
+    let new_entries = entries(new, devices.1)?;
+    let (mut i, mut j) = (0, 0);
+
+    while i < old_entries.len() || j < new_entries.len() {
+        let order = match (old_entries.get(i), new_entries.get(j)) {
+            (Some(a), Some(b)) => a.0.cmp(&b.0),
+            (Some(_), None) => Ordering::Less,
+            _ => Ordering::Greater,
+        };
+        match order {
+            Ordering::Less => {
+                let (name, metadata) = &old_entries[i];
+                on_change(Change {
+                    kind: ChangeKind::Removed,
+                    path: relative.join(name),
+                    is_dir: metadata.is_dir(),
+                });
+                i += 1;
+            }
+            Ordering::Greater => {
+                let (name, metadata) = &new_entries[j];
+                on_change(Change {
+                    kind: ChangeKind::Added,
+                    path: relative.join(name),
+                    is_dir: metadata.is_dir(),
+                });
+                j += 1;
+            }
+            Ordering::Equal => {
+                let (name, a) = &old_entries[i];
+                let b = &new_entries[j].1;
+                let path = relative.join(name);
+                if a.is_dir() && b.is_dir() {
+                    if differs(a, b) {
+                        on_change(Change {
+                            kind: ChangeKind::Modified,
+                            path: path.clone(),
+                            is_dir: true,
+                        });
+                    }
+                    diff_dirs(&old.join(name), &new.join(name), &path, devices, on_change)?;
+                } else if differs(a, b) || link_target_differs(&old.join(name), &new.join(name)) {
+                    on_change(Change {
+                        kind: ChangeKind::Modified,
+                        path,
+                        is_dir: false,
+                    });
+                }
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether two entries' metadata differ
+///
+/// Directory sizes and mtimes change whenever an entry is added or removed,
+/// which is reported on its own, so only their mode and owner are compared.
+fn differs(a: &Metadata, b: &Metadata) -> bool {
+    if a.mode() != b.mode() || a.uid() != b.uid() || a.gid() != b.gid() {
+        return true;
+    }
+    !a.is_dir()
+        && (a.size() != b.size() || a.mtime() != b.mtime() || a.mtime_nsec() != b.mtime_nsec())
+}
+
+fn link_target_differs(a: &Path, b: &Path) -> bool {
+    match (fs::read_link(a), fs::read_link(b)) {
+        (Ok(a), Ok(b)) => a != b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use std::time::{Duration, SystemTime};
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+        // Same mtime on both sides, so only real changes show
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    fn changes(old: &Path, new: &Path) -> Vec<(char, String)> {
+        let mut changes = Vec::new();
+        diff_trees(old, new, |change| {
+            changes.push((
+                change.kind.marker(),
+                change.path.to_string_lossy().into_owned(),
+            ))
+        })
+        .unwrap();
+        changes
+    }
+
+    #[test]
+    fn finds_added_removed_and_modified_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, new) = (dir.path().join("old"), dir.path().join("new"));
+        for root in [&old, &new] {
+            write(&root.join("etc/hostname"), "mkos\n");
+            write(&root.join("usr/bin/tool"), "v1");
+            symlink("tool", root.join("usr/bin/alias")).unwrap();
+        }
+        write(&old.join("etc/removed.conf"), "x");
+        write(&old.join("var/cache/pkg/a.pkg"), "a");
+        write(&new.join("etc/added.conf"), "y");
+        write(&new.join("usr/bin/tool"), "v2!");
+        write(&new.join("opt/app/bin/app"), "app");
+        fs::remove_file(new.join("usr/bin/alias")).unwrap();
+        symlink("other", new.join("usr/bin/alias")).unwrap();
+        fs::set_permissions(new.join("etc/hostname"), fs::Permissions::from_mode(0o600)).unwrap();
+
+        assert_eq!(
+            changes(&old, &new),
+            [
+                ('+', "etc/added.conf".to_string()),
+                ('M', "etc/hostname".to_string()),
+                ('-', "etc/removed.conf".to_string()),
+                ('+', "opt".to_string()),
+                ('M', "usr/bin/alias".to_string()),
+                ('M', "usr/bin/tool".to_string()),
+                ('-', "var".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn identical_trees_have_no_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, new) = (dir.path().join("old"), dir.path().join("new"));
+        for root in [&old, &new] {
+            write(&root.join("etc/fstab"), "# fstab\n");
+            fs::create_dir_all(root.join("home")).unwrap();
+        }
+        assert!(changes(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn summary_counts_each_kind() {
+        let mut summary = DiffSummary::default();
+        for kind in [ChangeKind::Added, ChangeKind::Added, ChangeKind::Modified] {
+            summary.record(&Change {
+                kind,
+                path: PathBuf::from("x"),
+                is_dir: false,
+            });
+        }
+        assert_eq!(
+            summary,
+            DiffSummary {
+                added: 2,
+                removed: 0,
+                modified: 1
+            }
+        );
+    }
+}
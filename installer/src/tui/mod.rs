@@ -9,10 +9,13 @@ use std::io::stdout;
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
+use zeroize::Zeroizing;
 
-use crate::disk::{self, BlockDevice};
+use crate::disk::{self, BlockDevice, DiskContents};
 use crate::distro::DistroKind;
-use crate::install::{InstallConfig, Installer};
+use crate::install::{self, InstallConfig, InstallObserver, InstallStep, Installer};
+use crate::manifest::PassphrasePolicy;
+use crate::secret::Secret;
 
 #[derive(Debug, Clone, PartialEq)]
 enum Screen {
@@ -29,14 +32,113 @@ enum Screen {
 struct InstallerState {
     devices: Vec<BlockDevice>,
     selected_device: usize,
-    passphrase: String,
-    root_password: String,
+    /// Disk the live system is running from, flagged in the list
+    boot_disk: Option<String>,
+    /// Extra keystroke given to erase the boot disk
+    boot_disk_confirmed: bool,
+    /// Existing data found on the selected disk
+    disk_contents: Option<DiskContents>,
+    /// Failed system requirement checks, shown on the welcome screen
+    preflight_failures: Vec<String>,
+    passphrase: Zeroizing<String>,
+    /// What the passphrase must meet; the TUI has no manifest, so the default
+    passphrase_policy: PassphrasePolicy,
+    root_password: Zeroizing<String>,
     install_log: Vec<String>,
     log_receiver: Option<mpsc::Receiver<String>>,
     install_complete: bool,
     install_error: Option<String>,
 }
 
+impl InstallerState {
+    /// Why the passphrase isn't acceptable yet, if it isn't
+    /// Estimated space the install needs on the selected disk, and whether
+    /// the disk holds it
+    fn space(&self) -> (u64, bool) {
+        let device = &self.devices[self.selected_device];
+        let required = install::required_bytes(&install_config(self, &device.path));
+        (required, required <= device.size_bytes)
+    }
+
+    fn passphrase_problem(&self) -> Option<String> {
+        crate::crypt::validate_passphrase(&self.passphrase, &self.passphrase_policy)
+            .err()
+            .map(|e| e.to_string())
+    }
+
+    fn selected_is_boot_disk(&self) -> bool {
+        let selected = self.devices.get(self.selected_device).map(|d| &d.path);
+        selected.is_some() && selected == self.boot_disk.as_ref()
+    }
+}
+
+/// Sends install progress to the install screen's log
+struct ChannelObserver(mpsc::Sender<String>);
+
+impl InstallObserver for ChannelObserver {
+    fn on_step_start(&self, step: InstallStep) {
+        let _ = self.0.send(step.to_string());
+    }
+
+    fn on_log(&self, line: &str) {
+        // The log is a list of lines; blank ones only spaced stdout output
+        let line = line.trim_start_matches('\n');
+        if !line.trim().is_empty() {
+            let _ = self.0.send(line.to_string());
+        }
+    }
+
+    fn on_step_end(&self, _step: InstallStep, _result: &Result<()>) {}
+}
+
+/// The TUI installs with fixed defaults; only the disk and secrets are asked
+fn install_config(state: &InstallerState, device: &str) -> InstallConfig {
+    InstallConfig {
+        device: PathBuf::from(device),
+        encryption: true,
+        raid_devices: Vec::new(),
+        passphrase: Secret::from(state.passphrase.to_string()),
+        luks_header: None,
+        root_password: Secret::from(state.root_password.to_string()),
+        hostname: "mkos".into(),
+        timezone: "UTC".into(),
+        locale: "en_US.UTF-8".into(),
+        locales: Vec::new(),
+        keymap: "us".into(),
+        keymap_x11: None,
+        console_font: None,
+        distro: DistroKind::Artix,
+        init: None,
+        filesystem: Default::default(),
+        btrfs_layout: Default::default(),
+        partition_layout: Default::default(),
+        boot: Default::default(),
+        boot_mode: Default::default(),
+        kernel_cmdline: Vec::new(),
+        kernel: None,
+        extra_kernels: Vec::new(),
+        quiet_boot: true,
+        splash_boot: false,
+        recovery_entry: true,
+        enable_networking: true,
+        extra_packages: Vec::new(),
+        multilib: false,
+        desktop: Default::default(),
+        swap: Default::default(),
+        audio: Default::default(),
+        network: Default::default(),
+        firewall: Default::default(),
+        snapshots: Default::default(),
+        security: Default::default(),
+        secureboot: Default::default(),
+        microcode: false,
+        scripts: Default::default(),
+        mirror: Default::default(),
+        selected_mirror: None,
+        image: None,
+    }
+}
+
 pub async fn run() -> Result<()> {
     enable_raw_mode()?;
     stdout().execute(EnterAlternateScreen)?;
@@ -57,6 +159,7 @@ pub async fn run() -> Result<()> {
 async fn run_app(terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>) -> Result<()> {
     let mut screen = Screen::Welcome;
     let mut state = InstallerState::default();
+    state.preflight_failures = install::preflight_failures(&install_config(&state, ""));
 
     loop {
         // Poll for install log messages
@@ -82,7 +185,15 @@ async fn run_app(terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>) ->
             continue;
         }
 
-        if let Event::Key(key) = event::read()? {
+        let event = event::read()?;
+
+        // Pick up the new size right away rather than on the next draw
+        if let Event::Resize(..) = event {
+            terminal.autoresize()?;
+            continue;
+        }
+
+        if let Event::Key(key) = event {
             if key.kind != KeyEventKind::Press {
                 continue;
             }
@@ -93,26 +204,25 @@ async fn run_app(terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>) ->
                 (_, KeyCode::Esc) if screen != Screen::Installing => break,
 
                 // Welcome screen
-                (Screen::Welcome, KeyCode::Enter) => {
+                (Screen::Welcome, KeyCode::Enter) if state.preflight_failures.is_empty() => {
                     state.devices = disk::list_block_devices()?;
+                    state.boot_disk = disk::current_boot_disk();
                     screen = Screen::DiskSelect;
                 }
 
                 // Disk selection
-                (Screen::DiskSelect, KeyCode::Up | KeyCode::Char('k')) => {
-                    if state.selected_device > 0 {
-                        state.selected_device -= 1;
-                    }
+                (Screen::DiskSelect, KeyCode::Up | KeyCode::Char('k'))
+                    if state.selected_device > 0 =>
+                {
+                    state.selected_device -= 1;
                 }
-                (Screen::DiskSelect, KeyCode::Down | KeyCode::Char('j')) => {
-                    if state.selected_device < state.devices.len().saturating_sub(1) {
-                        state.selected_device += 1;
-                    }
+                (Screen::DiskSelect, KeyCode::Down | KeyCode::Char('j'))
+                    if state.selected_device < state.devices.len().saturating_sub(1) =>
+                {
+                    state.selected_device += 1;
                 }
-                (Screen::DiskSelect, KeyCode::Enter) => {
-                    if !state.devices.is_empty() {
-                        screen = Screen::Passphrase;
-                    }
+                (Screen::DiskSelect, KeyCode::Enter) if !state.devices.is_empty() => {
+                    screen = Screen::Passphrase;
                 }
 
                 // Passphrase entry
@@ -122,13 +232,20 @@ async fn run_app(terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>) ->
                 (Screen::Passphrase, KeyCode::Backspace) => {
                     state.passphrase.pop();
                 }
-                (Screen::Passphrase, KeyCode::Enter) => {
-                    if state.passphrase.len() >= 8 {
-                        screen = Screen::Confirm;
-                    }
+                (Screen::Passphrase, KeyCode::Enter) if state.passphrase_problem().is_none() => {
+                    let device = &state.devices[state.selected_device];
+                    state.disk_contents = disk::probe(&device.path).ok();
+                    screen = Screen::Confirm;
                 }
 
-                // Confirmation
+                // Confirmation - the boot disk takes a second 'y'
+                // A disk too small for the install can't be confirmed
+                (Screen::Confirm, KeyCode::Char('y') | KeyCode::Char('Y')) if !state.space().1 => {}
+                (Screen::Confirm, KeyCode::Char('y') | KeyCode::Char('Y'))
+                    if state.selected_is_boot_disk() && !state.boot_disk_confirmed =>
+                {
+                    state.boot_disk_confirmed = true;
+                }
                 (Screen::Confirm, KeyCode::Char('y') | KeyCode::Char('Y')) => {
                     screen = Screen::Installing;
                     state.install_log.push("Starting installation...".into());
@@ -139,42 +256,27 @@ async fn run_app(terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>) ->
 
                     // Build install config
                     let device = &state.devices[state.selected_device];
-                    let config = InstallConfig {
-                        device: PathBuf::from(&device.path),
-                        passphrase: state.passphrase.clone(),
-                        root_password: state.root_password.clone(),
-                        hostname: "mkos".into(),
-                        timezone: "UTC".into(),
-                        locale: "en_US.UTF-8".into(),
-                        keymap: "us".into(),
-                        distro: DistroKind::Artix,
-                        enable_networking: true,
-                        extra_packages: Vec::new(),
-                        desktop: Default::default(),
-                        swap: Default::default(),
-                        audio: Default::default(),
-                        network: Default::default(),
-                        firewall: Default::default(),
-                        secureboot: Default::default(),
-                        microcode: false,
-                    };
+                    let config = install_config(&state, &device.path);
 
                     // Spawn install thread
                     thread::spawn(move || {
-                        let installer = Installer::new(config);
+                        let installer = Installer::new(config)
+                            .with_observer(Box::new(ChannelObserver(tx.clone())));
                         let result = installer.run();
 
                         match result {
-                            Ok(()) => {
+                            Ok(_) => {
                                 let _ = tx.send("__COMPLETE__".into());
                             }
                             Err(e) => {
-                                let _ = tx.send(format!("__ERROR__:{}", e));
+                                // Alternate form keeps the context chain and the failing command's stderr
+                                let _ = tx.send(format!("__ERROR__:{:#}", e));
                             }
                         }
                     });
                 }
                 (Screen::Confirm, KeyCode::Char('n') | KeyCode::Char('N')) => {
+                    state.boot_disk_confirmed = false;
                     screen = Screen::DiskSelect;
                 }
 
@@ -230,7 +332,7 @@ fn render(f: &mut Frame, screen: &Screen, state: &InstallerState) {
     let content_area = chunks[1];
 
     match screen {
-        Screen::Welcome => render_welcome(f, content_area),
+        Screen::Welcome => render_welcome(f, content_area, state),
         Screen::DiskSelect => render_disk_select(f, content_area, state),
         Screen::Passphrase => render_passphrase(f, content_area, state),
         Screen::Confirm => render_confirm(f, content_area, state),
@@ -240,8 +342,8 @@ fn render(f: &mut Frame, screen: &Screen, state: &InstallerState) {
     }
 }
 
-fn render_welcome(f: &mut Frame, area: Rect) {
-    let text = vec![
+fn render_welcome(f: &mut Frame, area: Rect, state: &InstallerState) {
+    let mut text = vec![
         Line::from(""),
         Line::from("Welcome to mkOS").style(Style::default().add_modifier(Modifier::BOLD)),
         Line::from(""),
@@ -253,9 +355,28 @@ fn render_welcome(f: &mut Frame, area: Rect) {
         Line::from("  • Artix Linux base installation (s6 init)"),
         Line::from("  • EFISTUB boot (no GRUB)"),
         Line::from(""),
-        Line::from("Press Enter to continue...").style(Style::default().fg(Color::Green)),
     ];
 
+    if state.preflight_failures.is_empty() {
+        text.push(Line::from("✓ System requirements met").style(Style::default().fg(Color::Green)));
+        text.push(Line::from(""));
+        text.push(
+            Line::from("Press Enter to continue...").style(Style::default().fg(Color::Green)),
+        );
+    } else {
+        let red = Style::default().fg(Color::Red);
+        text.push(
+            Line::from("System requirements not met:").style(red.add_modifier(Modifier::BOLD)),
+        );
+        for failure in &state.preflight_failures {
+            text.push(Line::from(format!("  • {}", failure)).style(red));
+        }
+        text.push(Line::from(""));
+        text.push(Line::from(
+            "Fix the problems above and restart the installer (q to quit)",
+        ));
+    }
+
     let paragraph = Paragraph::new(text)
         .alignment(Alignment::Center)
         .block(Block::default().borders(Borders::ALL));
@@ -263,15 +384,15 @@ fn render_welcome(f: &mut Frame, area: Rect) {
 }
 
 fn render_disk_select(f: &mut Frame, area: Rect, state: &InstallerState) {
+    // Inside the block borders
+    let width = area.width.saturating_sub(2) as usize;
+
     let items: Vec<ListItem> = state
         .devices
         .iter()
         .enumerate()
         .map(|(i, d)| {
-            let size_gb = d.size_bytes / 1_000_000_000;
-            let model = d.model.as_deref().unwrap_or("Unknown");
-            let removable = if d.removable { " [removable]" } else { "" };
-            let line = format!("{} - {} GB - {}{}", d.path, size_gb, model, removable);
+            let line = disk_line(d, width, state.boot_disk.as_ref() == Some(&d.path));
 
             let style = if i == state.selected_device {
                 Style::default().bg(Color::Blue).fg(Color::White)
@@ -294,15 +415,42 @@ fn render_disk_select(f: &mut Frame, area: Rect, state: &InstallerState) {
     f.render_widget(list, area);
 }
 
+/// Format a disk for the selection list, eliding the model to fit `width`
+fn disk_line(device: &BlockDevice, width: usize, boot_disk: bool) -> String {
+    let prefix = format!("{} - {} - ", device.path, device.size_label());
+    let mut tags = String::new();
+    if device.removable {
+        tags.push_str(" [removable]");
+    }
+    if boot_disk {
+        tags.push_str(" [boot medium]");
+    }
+    let model = device.model.as_deref().unwrap_or("Unknown");
+
+    let budget = width.saturating_sub(prefix.chars().count() + tags.chars().count());
+    format!("{}{}{}", prefix, elide(model, budget), tags)
+}
+
+/// Truncate `s` to at most `max` characters, ending in "…" when shortened
+fn elide(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s.to_string();
+    }
+    if max == 0 {
+        return String::new();
+    }
+
+    let mut out: String = s.chars().take(max - 1).collect();
+    out.push('…');
+    out
+}
+
 fn render_passphrase(f: &mut Frame, area: Rect, state: &InstallerState) {
-    let masked: String = "●".repeat(state.passphrase.len());
-    let min_note = if state.passphrase.len() < 8 {
-        format!(
-            " (minimum 8 characters, {} more needed)",
-            8 - state.passphrase.len()
-        )
-    } else {
-        " ✓".into()
+    let masked: String = "●".repeat(state.passphrase.chars().count());
+    let problem = state.passphrase_problem();
+    let note = match &problem {
+        Some(problem) => format!(" ({})", problem),
+        None => " ✓".into(),
     };
 
     let text = vec![
@@ -311,7 +459,7 @@ fn render_passphrase(f: &mut Frame, area: Rect, state: &InstallerState) {
         Line::from(""),
         Line::from(format!("  {}", masked)),
         Line::from(""),
-        Line::from(min_note).style(if state.passphrase.len() >= 8 {
+        Line::from(note).style(if problem.is_none() {
             Style::default().fg(Color::Green)
         } else {
             Style::default().fg(Color::Yellow)
@@ -325,20 +473,47 @@ fn render_passphrase(f: &mut Frame, area: Rect, state: &InstallerState) {
 
 fn render_confirm(f: &mut Frame, area: Rect, state: &InstallerState) {
     let device = &state.devices[state.selected_device];
-    let size_gb = device.size_bytes / 1_000_000_000;
 
-    let text = vec![
+    let mut text = vec![
         Line::from(""),
         Line::from("⚠ WARNING").style(Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
         Line::from(""),
         Line::from("This will DESTROY all data on:"),
         Line::from(""),
-        Line::from(format!("  {} ({} GB)", device.path, size_gb))
+        Line::from(format!("  {} ({})", device.path, device.size_label()))
             .style(Style::default().add_modifier(Modifier::BOLD)),
         Line::from(""),
-        Line::from("Are you sure you want to continue?"),
     ];
 
+    let (required, fits) = state.space();
+    text.push(Line::from(install::space_summary(
+        required,
+        device.size_bytes,
+    )));
+    text.push(Line::from(""));
+
+    if let Some(warning) = state.disk_contents.as_ref().and_then(|c| c.warning()) {
+        text.push(Line::from(warning).style(Style::default().fg(Color::Yellow)));
+        text.push(Line::from(""));
+    }
+
+    if !fits {
+        let danger = Style::default().fg(Color::Red).add_modifier(Modifier::BOLD);
+        text.push(Line::from("Not enough space for the install on this disk.").style(danger));
+        text.push(Line::from("Press n to pick another disk."));
+    } else if state.selected_is_boot_disk() {
+        let danger = Style::default().fg(Color::Red).add_modifier(Modifier::BOLD);
+        text.push(Line::from("This is the disk the running system booted from!").style(danger));
+        text.push(Line::from(""));
+        if state.boot_disk_confirmed {
+            text.push(Line::from("Press y again to erase it anyway, n to go back.").style(danger));
+        } else {
+            text.push(Line::from("Are you sure you want to continue? (y/n)"));
+        }
+    } else {
+        text.push(Line::from("Are you sure you want to continue?"));
+    }
+
     let paragraph = Paragraph::new(text).alignment(Alignment::Center).block(
         Block::default()
             .borders(Borders::ALL)
@@ -348,9 +523,14 @@ fn render_confirm(f: &mut Frame, area: Rect, state: &InstallerState) {
 }
 
 fn render_installing(f: &mut Frame, area: Rect, state: &InstallerState) {
+    // Only the newest lines that fit inside the borders, so the log follows output
+    let visible = area.height.saturating_sub(2) as usize;
+    let skip = state.install_log.len().saturating_sub(visible);
+
     let items: Vec<ListItem> = state
         .install_log
         .iter()
+        .skip(skip)
         .map(|s| ListItem::new(s.as_str()))
         .collect();
 
@@ -381,16 +561,84 @@ fn render_complete(f: &mut Frame, area: Rect) {
 }
 
 fn render_error(f: &mut Frame, area: Rect, msg: &str) {
-    let text = vec![
+    let mut text = vec![
         Line::from(""),
         Line::from("✗ Installation Failed")
             .style(Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
         Line::from(""),
-        Line::from(msg),
     ];
+    // CmdError puts the command's stderr on the following lines
+    text.extend(msg.lines().map(Line::from));
 
     let paragraph = Paragraph::new(text)
         .alignment(Alignment::Center)
         .block(Block::default().borders(Borders::ALL));
     f.render_widget(paragraph, area);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(model: Option<&str>, removable: bool) -> BlockDevice {
+        BlockDevice {
+            path: "/dev/sda".into(),
+            size_bytes: 512_000_000_000,
+            model: model.map(String::from),
+            removable,
+        }
+    }
+
+    #[test]
+    fn elide_shortens_with_ellipsis() {
+        assert_eq!(elide("Samsung SSD 980 PRO", 100), "Samsung SSD 980 PRO");
+        assert_eq!(elide("Samsung SSD 980 PRO", 8), "Samsung…");
+        assert_eq!(elide("Samsung", 0), "");
+    }
+
+    #[test]
+    fn disk_line_fits_width() {
+        let d = device(Some("Samsung SSD 980 PRO with Heatsink 2TB"), true);
+
+        let wide = disk_line(&d, 200, false);
+        assert_eq!(
+            wide,
+            "/dev/sda - 512 GB - Samsung SSD 980 PRO with Heatsink 2TB [removable]"
+        );
+
+        let narrow = disk_line(&d, 40, false);
+        assert_eq!(narrow.chars().count(), 40);
+        assert!(narrow.ends_with("… [removable]"));
+    }
+
+    #[test]
+    fn disk_line_unknown_model() {
+        assert_eq!(
+            disk_line(&device(None, false), 80, false),
+            "/dev/sda - 512 GB - Unknown"
+        );
+    }
+
+    #[test]
+    fn disk_line_flags_boot_medium() {
+        assert_eq!(
+            disk_line(&device(Some("Ventoy"), true), 80, true),
+            "/dev/sda - 512 GB - Ventoy [removable] [boot medium]"
+        );
+    }
+
+    #[test]
+    fn selected_boot_disk() {
+        let mut state = InstallerState {
+            devices: vec![device(None, false)],
+            ..Default::default()
+        };
+        assert!(!state.selected_is_boot_disk());
+
+        state.boot_disk = Some("/dev/sda".into());
+        assert!(state.selected_is_boot_disk());
+
+        state.boot_disk = Some("/dev/sdb".into());
+        assert!(!state.selected_is_boot_disk());
+    }
+}
@@ -1,3 +1,4 @@
+pub mod rebuild;
 mod secureboot;
 
 pub use secureboot::*;
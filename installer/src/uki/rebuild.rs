@@ -0,0 +1,773 @@
+//! Rebuild UKIs on the running system after a kernel upgrade.
+//!
+//! Maintains a boot entry per installed kernel (the newest is main), plus
+//! fallback (previous UKI) and rescue (init=/bin/sh).
+//! Kernel hooks exec this as /usr/local/bin/mkos-rebuild-uki.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::detect_tool;
+use crate::boot::{
+    kernel_versions, parse_efibootmgr, BootConfig, BootEntry, BootKind, BootMode, BootSystem,
+    DracutEfistub, MkinitcpioSystemdBoot, NvramEntry, BOOT_MODE_PATH, BOOT_SYSTEM_PATH,
+};
+use crate::crypt::{snapshot, DetachedHeader};
+use crate::disk::split_partition;
+
+const FALLBACK_UKI_NAME: &str = "mkos-fallback.efi";
+const RESCUE_UKI_NAME: &str = "mkos-rescue.efi";
+
+pub fn run() -> Result<()> {
+    if !nix::unistd::Uid::effective().is_root() {
+        eprintln!("Error: mkos-rebuild-uki must be run as root");
+        std::process::exit(1);
+    }
+
+    let root = Path::new("/");
+    let boot_dir = root.join("boot");
+
+    println!("==> Detecting kernel versions...");
+    let versions = kernel_versions(root, &[])?;
+    for kver in &versions {
+        println!("  Kernel: {}", kver);
+    }
+
+    let mode = recorded_boot_mode(root)?;
+
+    // Read boot configuration from crypttab (name-agnostic); an unencrypted
+    // install has no entries, and its root goes by filesystem UUID
+    let crypttab = fs::read_to_string("/etc/crypttab").unwrap_or_default();
+    let (luks_uuid, root_device, luks_header) = if has_entries(&crypttab) {
+        let (luks_name, device, luks_header) = parse_crypttab(&crypttab)?;
+        // A partition with a detached header has no UUID, and the header may
+        // be unplugged by now; the unlocked mapping still records it
+        let luks_uuid = match luks_header {
+            Some(_) => mapped_luks_uuid(&luks_name)?,
+            None => device,
+        };
+        (luks_uuid, format!("/dev/mapper/{}", luks_name), luks_header)
+    } else {
+        (String::new(), format!("UUID={}", root_uuid()?), None)
+    };
+
+    let (quiet, splash, extra_cmdline) = recorded_cmdline(root);
+
+    // Only btrfs roots boot from a subvolume; ext4/xfs roots take no rootflags
+    let subvol = if snapshot::is_btrfs_root() {
+        main_subvolume(&snapshot::current_root_subvolume()?)
+    } else {
+        String::new()
+    };
+
+    let microcode = microcode(&boot_dir);
+
+    let config = BootConfig {
+        luks_uuid,
+        extra_luks_uuids: crypttab_members(&crypttab)
+            .into_iter()
+            .map(|(_, uuid)| uuid)
+            .collect(),
+        root_device,
+        subvol,
+        quiet,
+        splash,
+        // The initramfs config written at install already has the KMS
+        // drivers and console font
+        kms_drivers: Vec::new(),
+        console_font: false,
+        microcode,
+        // resume= is recorded with the extras, and the dracut config already
+        // includes the resume module
+        resume: None,
+        luks_header,
+    };
+
+    if recorded_boot_system(root)? == BootKind::MkinitcpioSystemdBoot {
+        return rebuild_systemd_boot(root, &config, extra_cmdline);
+    }
+
+    let boot_system = DracutEfistub::new()
+        .with_extra_cmdline(extra_cmdline)
+        .with_mode(mode);
+
+    // Step 1: Preserve current main UKI as fallback before rebuilding
+    let mut fallback_kver = versions[0].clone();
+    if let Some(existing) = newest_main_uki(&boot_dir)? {
+        if let Some(kver) = main_uki_kver(&existing) {
+            fallback_kver = kver;
+        }
+        println!("==> Preserving current UKI as fallback...");
+        fs::copy(&existing, boot_dir.join(FALLBACK_UKI_NAME))?;
+        println!(
+            "  {} -> {}",
+            existing.file_name().unwrap_or_default().to_string_lossy(),
+            FALLBACK_UKI_NAME
+        );
+    }
+
+    // Step 2: Build new initramfs and UKIs
+    boot_system.build_initramfs(root)?;
+    let kernel_entries = boot_system.build_boot_images(root, &config)?;
+    // Only installs with boot.recovery_entry have a rescue UKI to rebuild
+    let rescue_entry = if boot_dir.join(RESCUE_UKI_NAME).exists() {
+        Some(boot_system.build_rescue_image(root, &config)?)
+    } else {
+        None
+    };
+
+    let fallback_entry = boot_dir
+        .join(FALLBACK_UKI_NAME)
+        .exists()
+        .then(|| BootEntry {
+            label: "mkOS (fallback)".into(),
+            loader_path: format!("/{}", FALLBACK_UKI_NAME),
+            // An EFISTUB kernel boots with the initramfs it was built with
+            cmdline: boot_system.entry_cmdline(
+                root,
+                &config,
+                &fallback_kver,
+                &boot_system.build_cmdline(&config),
+            ),
+        });
+
+    let entries: Vec<&BootEntry> = kernel_entries
+        .iter()
+        .chain(fallback_entry.as_ref())
+        .chain(rescue_entry.as_ref())
+        .collect();
+
+    // Step 3: Sign all UKIs if secure boot is configured
+    println!("==> Checking for Secure Boot setup...");
+    match detect_tool() {
+        Some(tool) => {
+            for entry in &entries {
+                let path = boot_dir.join(entry.loader_path.trim_start_matches('/'));
+                tool.sign_binary(&path)?;
+                println!("  Signed with {}: {}", tool.name(), entry.loader_path);
+            }
+        }
+        None => println!("  No Secure Boot setup found, skipping signing"),
+    }
+
+    // Step 4: Update EFI boot entries
+    if Path::new("/sys/firmware/efi").exists() {
+        update_boot_entries(
+            &boot_system,
+            &kernel_entries,
+            fallback_entry.as_ref(),
+            rescue_entry.as_ref(),
+        )?;
+    } else {
+        println!("Not in UEFI mode, skipping boot entry update");
+    }
+
+    // Step 5: Clean up old UKI files (keep only the current ones)
+    println!("==> Cleaning up old UKI files...");
+    let keep: Vec<&str> = entries
+        .iter()
+        .map(|e| e.loader_path.trim_start_matches('/'))
+        .collect();
+    for old_uki in stale_ukis(&boot_dir, &keep)? {
+        println!(
+            "  Removing: {}",
+            old_uki.file_name().unwrap_or_default().to_string_lossy()
+        );
+        let _ = Command::new("sbctl")
+            .arg("remove-file")
+            .arg(&old_uki)
+            .output();
+        fs::remove_file(&old_uki)?;
+    }
+
+    // The fallback entry boots its own kernel's initramfs in EFISTUB mode
+    let kvers: Vec<&str> = versions
+        .iter()
+        .map(String::as_str)
+        .chain([fallback_kver.as_str()])
+        .collect();
+    for old_initramfs in stale_initramfs(&boot_dir, &kvers)? {
+        println!(
+            "  Removing: {}",
+            old_initramfs
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+        );
+        fs::remove_file(&old_initramfs)?;
+    }
+
+    println!("Done");
+
+    Ok(())
+}
+
+/// Rebuild the initramfs and loader entries of a systemd-boot install;
+/// systemd-boot owns its NVRAM entry, so efibootmgr is left alone
+fn rebuild_systemd_boot(
+    root: &Path,
+    config: &BootConfig,
+    extra_cmdline: Vec<String>,
+) -> Result<()> {
+    let boot_system = MkinitcpioSystemdBoot::new()
+        .with_extra_cmdline(extra_cmdline)
+        .with_kernel(MkinitcpioSystemdBoot::installed_kernel(root));
+
+    println!("==> Rebuilding initramfs and loader entries...");
+    let fallback_subvol = format!("@snapshots/{}", snapshot::INSTALL_SNAPSHOT);
+    let entries = boot_system.rebuild_entries(root, config, &fallback_subvol)?;
+
+    // The upgraded kernel replaced the signed one; every entry boots it
+    println!("==> Checking for Secure Boot setup...");
+    match detect_tool() {
+        Some(tool) => {
+            let images = entries
+                .iter()
+                .take(1)
+                .map(|entry| entry.loader_path.trim_start_matches('/'))
+                .chain(boot_system.efi_binaries().iter().copied());
+            for image in images {
+                tool.sign_binary(&root.join("boot").join(image))?;
+                println!("  Signed with {}: /{}", tool.name(), image);
+            }
+        }
+        None => println!("  No Secure Boot setup found, skipping signing"),
+    }
+
+    println!("Done");
+
+    Ok(())
+}
+
+/// Recreate the mkOS NVRAM entries on the disk /boot is mounted from
+fn update_boot_entries(
+    boot_system: &dyn BootSystem,
+    kernel_entries: &[BootEntry],
+    fallback_entry: Option<&BootEntry>,
+    rescue_entry: Option<&BootEntry>,
+) -> Result<()> {
+    let source = Command::new("findmnt")
+        .args(["-n", "-o", "SOURCE", "/boot"])
+        .output()
+        .context("Failed to detect boot device")?;
+    let source = String::from_utf8_lossy(&source.stdout).trim().to_string();
+
+    let Some((device, part)) = split_partition(&source) else {
+        println!("WARNING: Could not detect boot device");
+        return Ok(());
+    };
+
+    recreate_boot_entries(
+        boot_system,
+        Path::new(&device),
+        part,
+        kernel_entries,
+        fallback_entry,
+        rescue_entry,
+    )
+}
+
+/// Replace the mkOS NVRAM entries with ones for the given images on
+/// partition `part` of `device`, and put them first in the boot order
+pub(crate) fn recreate_boot_entries(
+    boot_system: &dyn BootSystem,
+    device: &Path,
+    part: u32,
+    kernel_entries: &[BootEntry],
+    fallback_entry: Option<&BootEntry>,
+    rescue_entry: Option<&BootEntry>,
+) -> Result<()> {
+    println!("==> Updating EFI boot entries...");
+    let _ = Command::new("mount")
+        .args(["-o", "remount,rw", "/sys/firmware/efi/efivars"])
+        .status();
+
+    let result = (|| -> Result<()> {
+        // Delete old mkOS boot entries
+        for old in parse_efibootmgr(&efibootmgr_output()?) {
+            if old.label.starts_with("mkOS") {
+                let _ = Command::new("efibootmgr")
+                    .args(["-b", &old.bootnum, "-B"])
+                    .output();
+            }
+        }
+
+        // Create in reverse order so the boot order ends up correct
+        if let Some(rescue_entry) = rescue_entry {
+            boot_system.create_boot_entry(device, part, rescue_entry)?;
+        }
+        if let Some(fallback_entry) = fallback_entry {
+            boot_system.create_boot_entry(device, part, fallback_entry)?;
+        }
+        for entry in kernel_entries.iter().rev() {
+            boot_system.create_boot_entry(device, part, entry)?;
+        }
+
+        // Set boot order: main, other kernels, fallback, rescue, then others
+        let order = boot_order(&parse_efibootmgr(&efibootmgr_output()?));
+        if !order.is_empty() {
+            Command::new("efibootmgr")
+                .args(["-o", &order.join(",")])
+                .output()
+                .context("Failed to set boot order")?;
+        }
+
+        Ok(())
+    })();
+
+    let _ = Command::new("mount")
+        .args(["-o", "remount,ro", "/sys/firmware/efi/efivars"])
+        .status();
+
+    result
+}
+
+fn efibootmgr_output() -> Result<String> {
+    let output = Command::new("efibootmgr")
+        .output()
+        .context("Failed to run efibootmgr")?;
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Whether crypttab has anything to unlock
+pub(crate) fn has_entries(crypttab: &str) -> bool {
+    crypttab
+        .lines()
+        .map(str::trim)
+        .any(|l| !l.is_empty() && !l.starts_with('#'))
+}
+
+/// Filesystem UUID of the running root
+fn root_uuid() -> Result<String> {
+    let output = Command::new("findmnt")
+        .args(["-n", "-o", "UUID", "/"])
+        .output()
+        .context("Failed to run findmnt")?;
+    let uuid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if uuid.is_empty() {
+        bail!("Could not find the root filesystem's UUID");
+    }
+    Ok(uuid)
+}
+
+/// Parse the first crypttab entry into (mapper name, LUKS UUID or source
+/// device, detached header)
+pub(crate) fn parse_crypttab(content: &str) -> Result<(String, String, Option<DetachedHeader>)> {
+    let line = content
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with('#'));
+
+    let mut fields = line.map(str::split_whitespace).into_iter().flatten();
+    match (fields.next(), fields.next()) {
+        (Some(name), Some(device)) => {
+            let _key_file = fields.next();
+            let header = DetachedHeader::from_crypttab(device, fields.next().unwrap_or(""));
+            let uuid = device.strip_prefix("UUID=").unwrap_or(device);
+            Ok((name.to_string(), uuid.to_string(), header))
+        }
+        _ => bail!("Could not find LUKS UUID in /etc/crypttab"),
+    }
+}
+
+/// (mapper name, LUKS UUID) of the crypttab entries after the first: the
+/// other disks of a RAID root
+pub(crate) fn crypttab_members(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .skip(1)
+        .filter_map(|l| {
+            let mut fields = l.split_whitespace();
+            let name = fields.next()?;
+            let device = fields.next()?;
+            let uuid = device.strip_prefix("UUID=").unwrap_or(device);
+            Some((name.to_string(), uuid.to_string()))
+        })
+        .collect()
+}
+
+/// Boot system recorded by the installer; installs from before it was
+/// recorded all used dracut-efistub
+pub(crate) fn recorded_boot_system(root: &Path) -> Result<BootKind> {
+    match fs::read_to_string(root.join(BOOT_SYSTEM_PATH)) {
+        Ok(system) => system.trim().parse(),
+        Err(_) => Ok(BootKind::default()),
+    }
+}
+
+/// LUKS UUID of the open mapping `name`, from its device-mapper UUID
+pub(crate) fn mapped_luks_uuid(name: &str) -> Result<String> {
+    let output = Command::new("dmsetup")
+        .args(["info", "-c", "--noheadings", "-o", "uuid", name])
+        .output()
+        .context("Failed to run dmsetup")?;
+    let dm_uuid = String::from_utf8_lossy(&output.stdout);
+    luks_uuid_from_dm(dm_uuid.trim())
+        .with_context(|| format!("Could not find the LUKS UUID of {}", name))
+}
+
+/// `CRYPT-LUKS2-<hex>-<name>` to the dashed LUKS UUID
+fn luks_uuid_from_dm(dm_uuid: &str) -> Option<String> {
+    let hex = dm_uuid.split('-').nth(2)?;
+    if hex.len() != 32 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+/// Boot mode recorded by the installer; installs from before it was
+/// recorded all built UKIs
+pub(crate) fn recorded_boot_mode(root: &Path) -> Result<BootMode> {
+    match fs::read_to_string(root.join(BOOT_MODE_PATH)) {
+        Ok(mode) => mode.trim().parse(),
+        Err(_) => Ok(BootMode::default()),
+    }
+}
+
+/// quiet, splash and the other extra arguments (manifest boot.cmdline)
+/// the installer recorded; splash is kept apart since the rescue UKI
+/// leaves it off
+pub(crate) fn recorded_cmdline(root: &Path) -> (bool, bool, Vec<String>) {
+    let extras = fs::read_to_string(root.join("etc/mkos/cmdline")).ok();
+    let (quiet, mut extra_cmdline) = parse_cmdline_extras(extras.as_deref());
+    let splash = extra_cmdline.iter().any(|arg| arg == "splash");
+    extra_cmdline.retain(|arg| arg != "splash");
+    (quiet, splash, extra_cmdline)
+}
+
+/// CPU microcode image in /boot, loaded before the initramfs
+pub(crate) fn microcode(boot_dir: &Path) -> Option<String> {
+    ["intel-ucode.img", "amd-ucode.img"]
+        .into_iter()
+        .find(|name| boot_dir.join(name).exists())
+        .map(String::from)
+}
+
+/// Split /etc/mkos/cmdline into the quiet flag and extra arguments.
+/// Without the file, boot quietly with no extras (the installer default).
+fn parse_cmdline_extras(content: Option<&str>) -> (bool, Vec<String>) {
+    let Some(content) = content else {
+        return (true, Vec::new());
+    };
+
+    let mut quiet = false;
+    let mut extras = Vec::new();
+    for arg in content.split_whitespace() {
+        if arg == "quiet" {
+            quiet = true;
+        } else {
+            extras.push(arg.to_string());
+        }
+    }
+    (quiet, extras)
+}
+
+/// The main UKI always boots @, even when rebuilding from a fallback snapshot
+fn main_subvolume(current: &str) -> String {
+    if current.starts_with("@snapshots/") {
+        "@".into()
+    } else {
+        current.into()
+    }
+}
+
+/// Main UKIs are named mkos-<kver>.efi
+fn is_main_uki(name: &str) -> bool {
+    name.strip_prefix("mkos-")
+        .and_then(|rest| rest.chars().next())
+        .is_some_and(|c| c.is_ascii_digit())
+        && name.ends_with(".efi")
+}
+
+/// Kernel release a main UKI was built for
+fn main_uki_kver(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    is_main_uki(name).then(|| name["mkos-".len()..name.len() - ".efi".len()].to_string())
+}
+
+/// Most recently modified main UKI in /boot
+fn newest_main_uki(boot_dir: &Path) -> Result<Option<PathBuf>> {
+    let mut newest: Option<(std::time::SystemTime, PathBuf)> = None;
+
+    for entry in fs::read_dir(boot_dir)?.filter_map(|e| e.ok()) {
+        if !is_main_uki(&entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        let modified = entry.metadata()?.modified()?;
+        if newest.as_ref().is_none_or(|(time, _)| modified > *time) {
+            newest = Some((modified, entry.path()));
+        }
+    }
+
+    Ok(newest.map(|(_, path)| path))
+}
+
+/// mkos-*.efi files in /boot that aren't one of the current UKIs
+fn stale_ukis(boot_dir: &Path, keep: &[&str]) -> Result<Vec<PathBuf>> {
+    Ok(fs::read_dir(boot_dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            name.starts_with("mkos-") && name.ends_with(".efi") && !keep.contains(&name.as_str())
+        })
+        .map(|e| e.path())
+        .collect())
+}
+
+/// initramfs-<release>.img files in /boot of kernels that aren't `kvers`
+///
+/// Images named after a kernel package rather than a release, such as
+/// mkinitcpio's initramfs-linux.img, are left alone.
+fn stale_initramfs(boot_dir: &Path, kvers: &[&str]) -> Result<Vec<PathBuf>> {
+    Ok(fs::read_dir(boot_dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            name.strip_prefix("initramfs-")
+                .and_then(|rest| rest.strip_suffix(".img"))
+                .is_some_and(|kver| {
+                    kver.starts_with(|c: char| c.is_ascii_digit()) && !kvers.contains(&kver)
+                })
+        })
+        .map(|e| e.path())
+        .collect())
+}
+
+/// Boot order with main, the other kernels, fallback, rescue first, then
+/// everything else
+fn boot_order(entries: &[NvramEntry]) -> Vec<String> {
+    let find = |label: &str| {
+        entries
+            .iter()
+            .find(|e| e.label == label)
+            .map(|e| e.bootnum.clone())
+    };
+    let is_other_kernel = |label: &str| {
+        label.starts_with("mkOS (") && label != "mkOS (fallback)" && label != "mkOS (rescue)"
+    };
+
+    let mkos: Vec<String> = find("mkOS")
+        .into_iter()
+        .chain(
+            entries
+                .iter()
+                .filter(|e| is_other_kernel(&e.label))
+                .map(|e| e.bootnum.clone()),
+        )
+        .chain(
+            ["mkOS (fallback)", "mkOS (rescue)"]
+                .into_iter()
+                .filter_map(find),
+        )
+        .collect();
+
+    if mkos.is_empty() {
+        return Vec::new();
+    }
+
+    let others = entries
+        .iter()
+        .filter(|e| !e.label.starts_with("mkOS"))
+        .map(|e| e.bootnum.clone());
+
+    mkos.iter().cloned().chain(others).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crypttab_first_entry() {
+        let content = "# <name> <device> <password> <options>\n\nsystem UUID=abcd-1234 none luks\n";
+        let (name, uuid, header) = parse_crypttab(content).unwrap();
+        assert_eq!(name, "system");
+        assert_eq!(uuid, "abcd-1234");
+        assert_eq!(header, None);
+    }
+
+    #[test]
+    fn crypttab_detached_header() {
+        let content =
+            "system PARTUUID=0f3a-01 none luks,discard,header=/system.hdr:UUID=ABCD-1234\n";
+        let (_, device, header) = parse_crypttab(content).unwrap();
+        assert_eq!(device, "PARTUUID=0f3a-01");
+        assert_eq!(header.unwrap().header, "/system.hdr:UUID=ABCD-1234");
+        assert_eq!(
+            luks_uuid_from_dm("CRYPT-LUKS2-0123456789abcdef0123456789abcdef-system").as_deref(),
+            Some("01234567-89ab-cdef-0123-456789abcdef")
+        );
+        assert_eq!(luks_uuid_from_dm(""), None);
+    }
+
+    #[test]
+    fn crypttab_members_follow_the_root() {
+        let content = "# <name> <device> <password> <options>\n\
+                       system UUID=abcd-1234 none luks,discard\n\
+                       system1 UUID=ef01-2345 none luks,discard\n";
+        assert_eq!(
+            crypttab_members(content),
+            [("system1".to_string(), "ef01-2345".to_string())]
+        );
+        assert!(crypttab_members("system UUID=abcd-1234 none luks\n").is_empty());
+    }
+
+    #[test]
+    fn crypttab_empty_is_error() {
+        assert!(parse_crypttab("# nothing here\n").is_err());
+        assert!(!has_entries("# nothing here\n\n"));
+        assert!(has_entries("system UUID=abcd-1234 none luks\n"));
+    }
+
+    #[test]
+    fn cmdline_extras_default_quiet() {
+        assert_eq!(parse_cmdline_extras(None), (true, vec![]));
+    }
+
+    #[test]
+    fn cmdline_extras_split_quiet() {
+        let (quiet, extras) = parse_cmdline_extras(Some("quiet nvidia-drm.modeset=1\n"));
+        assert!(quiet);
+        assert_eq!(extras, vec!["nvidia-drm.modeset=1"]);
+
+        let (quiet, extras) = parse_cmdline_extras(Some("mitigations=off\n"));
+        assert!(!quiet);
+        assert_eq!(extras, vec!["mitigations=off"]);
+    }
+
+    #[test]
+    fn recorded_cmdline_keeps_splash_apart() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("etc/mkos")).unwrap();
+        fs::write(root.path().join("etc/mkos/cmdline"), "quiet splash debug\n").unwrap();
+        assert_eq!(
+            recorded_cmdline(root.path()),
+            (true, true, vec!["debug".to_string()])
+        );
+        assert_eq!(recorded_boot_mode(root.path()).unwrap(), BootMode::Uki);
+    }
+
+    #[test]
+    fn recorded_boot_system_defaults_to_dracut() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(
+            recorded_boot_system(root.path()).unwrap(),
+            BootKind::DracutEfistub
+        );
+
+        fs::create_dir_all(root.path().join("etc/mkos")).unwrap();
+        fs::write(
+            root.path().join(BOOT_SYSTEM_PATH),
+            "mkinitcpio-systemd-boot\n",
+        )
+        .unwrap();
+        assert_eq!(
+            recorded_boot_system(root.path()).unwrap(),
+            BootKind::MkinitcpioSystemdBoot
+        );
+    }
+
+    #[test]
+    fn main_subvolume_ignores_snapshots() {
+        assert_eq!(main_subvolume("@"), "@");
+        assert_eq!(main_subvolume("@snapshots/install"), "@");
+    }
+
+    #[test]
+    fn main_uki_names() {
+        assert!(is_main_uki("mkos-6.10.2-arch1-1.efi"));
+        assert!(!is_main_uki("mkos-fallback.efi"));
+        assert!(!is_main_uki("mkos-rescue.efi"));
+    }
+
+    #[test]
+    fn stale_ukis_skips_current() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "mkos-6.9.1.efi",
+            "mkos-6.10.2.efi",
+            "mkos-fallback.efi",
+            "mkos-rescue.efi",
+            "startup.nsh",
+        ] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+
+        let stale = stale_ukis(
+            dir.path(),
+            &["mkos-6.10.2.efi", "mkos-fallback.efi", "mkos-rescue.efi"],
+        )
+        .unwrap();
+        assert_eq!(stale, vec![dir.path().join("mkos-6.9.1.efi")]);
+    }
+
+    #[test]
+    fn stale_initramfs_of_removed_kernels() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "initramfs-6.9.1-arch1-1.img",
+            "initramfs-6.10.2-arch1-1.img",
+            "initramfs-6.6.40-1-lts.img",
+            "initramfs-linux.img",
+            "amd-ucode.img",
+        ] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+
+        let mut stale = stale_initramfs(dir.path(), &["6.10.2-arch1-1", "6.6.40-1-lts"]).unwrap();
+        stale.sort();
+        assert_eq!(stale, vec![dir.path().join("initramfs-6.9.1-arch1-1.img")]);
+    }
+
+    #[test]
+    fn main_uki_kver_from_name() {
+        assert_eq!(
+            main_uki_kver(Path::new("/boot/mkos-6.6.40-1-lts.efi")).as_deref(),
+            Some("6.6.40-1-lts")
+        );
+        assert_eq!(main_uki_kver(Path::new("/boot/mkos-rescue.efi")), None);
+    }
+
+    #[test]
+    fn efibootmgr_parse_and_order() {
+        let output = "BootCurrent: 0001\n\
+                      BootOrder: 0000,0001,0002,0003\n\
+                      Boot0000* Windows Boot Manager\tHD(1,GPT,...)\n\
+                      Boot0001* mkOS (rescue)\tHD(1,GPT,...)\n\
+                      Boot0002* mkOS (fallback)\tHD(1,GPT,...)\n\
+                      Boot0003* mkOS\tHD(1,GPT,...)\n";
+        let entries = parse_efibootmgr(output);
+
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[3].bootnum, "0003");
+        assert_eq!(entries[3].label, "mkOS");
+        assert_eq!(boot_order(&entries), vec!["0003", "0002", "0001", "0000"]);
+    }
+
+    #[test]
+    fn boot_order_puts_other_kernels_after_main() {
+        let output = "BootOrder: 0000,0001,0002,0003,0004\n\
+                      Boot0000* Windows Boot Manager\tHD(1,GPT,...)\n\
+                      Boot0001* mkOS (rescue)\tHD(1,GPT,...)\n\
+                      Boot0002* mkOS (fallback)\tHD(1,GPT,...)\n\
+                      Boot0003* mkOS (6.6.40-1-lts)\tHD(1,GPT,...)\n\
+                      Boot0004* mkOS\tHD(1,GPT,...)\n";
+        let entries = parse_efibootmgr(output);
+
+        assert_eq!(
+            boot_order(&entries),
+            vec!["0004", "0003", "0002", "0001", "0000"]
+        );
+    }
+}
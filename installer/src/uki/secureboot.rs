@@ -16,6 +16,12 @@ pub struct KeyPair {
     pub cert: String,
 }
 
+/// EFI global variable GUID (SetupMode, PK, KEK)
+const EFI_GLOBAL_GUID: &str = "8be4df61-93ca-11d2-aa0d-00e098032b8c";
+
+/// Image security database GUID (db)
+const EFI_IMAGE_SECURITY_DATABASE_GUID: &str = "d719b2cb-3d3a-4596-a3bc-dad00e67656f";
+
 pub fn generate_keys(output_dir: &Path) -> Result<SecureBootKeys> {
     fs::create_dir_all(output_dir)?;
 
@@ -26,7 +32,12 @@ pub fn generate_keys(output_dir: &Path) -> Result<SecureBootKeys> {
     generate_key_pair(output_dir, "KEK", "mkOS Key Exchange Key", &guid)?;
     generate_key_pair(output_dir, "db", "mkOS Signature Database Key", &guid)?;
 
-    Ok(SecureBootKeys {
+    Ok(load_keys(output_dir))
+}
+
+/// Key paths for an existing keys directory (PK/KEK/db .key and .crt)
+pub fn load_keys(output_dir: &Path) -> SecureBootKeys {
+    SecureBootKeys {
         pk: KeyPair {
             key: output_dir.join("PK.key").to_string_lossy().into(),
             cert: output_dir.join("PK.crt").to_string_lossy().into(),
@@ -39,7 +50,7 @@ pub fn generate_keys(output_dir: &Path) -> Result<SecureBootKeys> {
             key: output_dir.join("db.key").to_string_lossy().into(),
             cert: output_dir.join("db.crt").to_string_lossy().into(),
         },
-    })
+    }
 }
 
 fn generate_key_pair(dir: &Path, name: &str, cn: &str, guid: &str) -> Result<()> {
@@ -139,6 +150,47 @@ pub fn enroll_keys(efi_mount: &Path, keys_dir: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Check whether the firmware is in Secure Boot Setup Mode
+pub fn is_setup_mode() -> bool {
+    let path = format!("/sys/firmware/efi/efivars/SetupMode-{}", EFI_GLOBAL_GUID);
+    fs::read(path)
+        .map(|data| parse_setup_mode(&data))
+        .unwrap_or(false)
+}
+
+/// Parse an efivarfs SetupMode variable (4 attribute bytes, then the value)
+fn parse_setup_mode(data: &[u8]) -> bool {
+    data.get(4) == Some(&1)
+}
+
+/// Enroll keys into efivars with efi-updatevar (firmware must be in Setup Mode).
+/// PK is written last since enrolling it leaves Setup Mode.
+pub fn enroll_efivars(keys_dir: &Path) -> Result<()> {
+    for name in ["db", "KEK", "PK"] {
+        // efivarfs marks existing variables immutable
+        let var = efivar_path(name);
+        if Path::new(&var).exists() {
+            let _ = cmd::run("chattr", ["-i", &var]);
+        }
+
+        let auth = keys_dir.join(format!("{}.auth", name));
+        cmd::run("efi-updatevar", ["-f", &auth.to_string_lossy(), name])
+            .context(format!("Failed to enroll {} key", name))?;
+    }
+
+    Ok(())
+}
+
+/// efivarfs path of the Secure Boot key variable `name`
+fn efivar_path(name: &str) -> String {
+    let guid = if name == "db" {
+        EFI_IMAGE_SECURITY_DATABASE_GUID
+    } else {
+        EFI_GLOBAL_GUID
+    };
+    format!("/sys/firmware/efi/efivars/{}-{}", name, guid)
+}
+
 /// Trait for Secure Boot signing tools
 pub trait SecureBootTool {
     /// Check if this tool is available on the system
@@ -245,3 +297,43 @@ pub fn detect_tool() -> Option<Box<dyn SecureBootTool>> {
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn db_lives_under_the_image_security_database_guid() {
+        assert_eq!(
+            efivar_path("db"),
+            "/sys/firmware/efi/efivars/db-d719b2cb-3d3a-4596-a3bc-dad00e67656f"
+        );
+        assert_eq!(
+            efivar_path("KEK"),
+            "/sys/firmware/efi/efivars/KEK-8be4df61-93ca-11d2-aa0d-00e098032b8c"
+        );
+    }
+
+    #[test]
+    fn setup_mode_enabled() {
+        assert!(parse_setup_mode(&[0x06, 0x00, 0x00, 0x00, 0x01]));
+    }
+
+    #[test]
+    fn setup_mode_disabled() {
+        assert!(!parse_setup_mode(&[0x06, 0x00, 0x00, 0x00, 0x00]));
+    }
+
+    #[test]
+    fn setup_mode_truncated() {
+        assert!(!parse_setup_mode(&[0x06, 0x00]));
+    }
+
+    #[test]
+    fn load_keys_paths() {
+        let keys = load_keys(Path::new("/root/.secureboot-keys"));
+        assert_eq!(keys.db.key, "/root/.secureboot-keys/db.key");
+        assert_eq!(keys.pk.cert, "/root/.secureboot-keys/PK.crt");
+        assert_eq!(keys.kek.key, "/root/.secureboot-keys/KEK.key");
+    }
+}
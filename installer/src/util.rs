@@ -18,6 +18,15 @@ impl CpuVendor {
         }
     }
 
+    /// Get the early microcode image installed to /boot by the package
+    pub fn microcode_image(&self) -> Option<&'static str> {
+        match self {
+            CpuVendor::Intel => Some("intel-ucode.img"),
+            CpuVendor::Amd => Some("amd-ucode.img"),
+            CpuVendor::Unknown => None,
+        }
+    }
+
     /// Get a human-readable name for this vendor
     pub fn name(&self) -> &'static str {
         match self {
@@ -44,10 +53,56 @@ pub fn detect_cpu_vendor() -> CpuVendor {
     }
 }
 
+/// Up to three of `candidates` closest to `target`, for "did you mean"
+/// hints; typos a few edits away qualify, unrelated names don't
+pub fn closest_matches<'a>(
+    target: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Vec<&'a str> {
+    let target = target.to_lowercase();
+    let max_distance = (target.chars().count() / 4).max(2);
+
+    let mut scored: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .map(|c| (edit_distance(&target, &c.to_lowercase()), c))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .collect();
+    scored.sort();
+    scored.into_iter().take(3).map(|(_, c)| c).collect()
+}
+
+/// Levenshtein distance between `a` and `b`, in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn close_names_are_suggested() {
+        let zones = ["America/Denver", "America/Detroit", "Europe/Berlin"];
+        assert_eq!(closest_matches("America/Denvr", zones), ["America/Denver"]);
+        assert_eq!(
+            closest_matches("en_us.utf-8", ["en_US.UTF-8"]),
+            ["en_US.UTF-8"]
+        );
+        assert!(closest_matches("Mars/Olympus", zones).is_empty());
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
     #[test]
     fn intel_microcode_package() {
         assert_eq!(CpuVendor::Intel.microcode_package(), Some("intel-ucode"));
@@ -58,6 +113,13 @@ mod tests {
         assert_eq!(CpuVendor::Amd.microcode_package(), Some("amd-ucode"));
     }
 
+    #[test]
+    fn microcode_images() {
+        assert_eq!(CpuVendor::Intel.microcode_image(), Some("intel-ucode.img"));
+        assert_eq!(CpuVendor::Amd.microcode_image(), Some("amd-ucode.img"));
+        assert_eq!(CpuVendor::Unknown.microcode_image(), None);
+    }
+
     #[test]
     fn unknown_has_no_microcode_package() {
         assert_eq!(CpuVendor::Unknown.microcode_package(), None);
@@ -8,6 +8,8 @@ set -e
 
 MKOS_RELEASE_URL="${MKOS_RELEASE_URL:-https://github.com/monokrome/mkos/releases/latest/download}"
 INSTALLER_BIN="mkos-install"
+# Copied into the installed system, so they're fetched alongside it
+COMPANION_BINS="mkos-rebuild-uki mkos"
 TMP_DIR=""
 
 cleanup() {
@@ -55,15 +57,17 @@ check_deps() {
 
 download_installer() {
     local arch="$1"
-    local url="${MKOS_RELEASE_URL}/${INSTALLER_BIN}-${arch}"
+    local bin url
 
     info "Downloading mkos-install for ${arch}..."
 
-    if ! curl -fsSL "$url" -o "${TMP_DIR}/${INSTALLER_BIN}"; then
-        die "Failed to download installer from ${url}"
-    fi
-
-    chmod +x "${TMP_DIR}/${INSTALLER_BIN}"
+    for bin in "$INSTALLER_BIN" $COMPANION_BINS; do
+        url="${MKOS_RELEASE_URL}/${bin}-${arch}"
+        if ! curl -fsSL "$url" -o "${TMP_DIR}/${bin}"; then
+            die "Failed to download ${bin} from ${url}"
+        fi
+        chmod +x "${TMP_DIR}/${bin}"
+    done
 }
 
 run_installer() {
@@ -65,6 +65,9 @@ install -m 755 target/release/mkos-apply /usr/local/bin/
 echo "  Installing mkos-rescue to /usr/local/bin..."
 install -m 755 target/release/mkos-rescue /usr/local/bin/
 
+echo "  Installing mkos-rebuild-uki to /usr/local/bin..."
+install -m 755 target/release/mkos-rebuild-uki /usr/local/bin/
+
 echo ""
 echo "[2/5] Updating dracut configuration..."
 
@@ -90,9 +93,6 @@ force_add_dracutmodules+=" dm crypt btrfs "
 # Additional required modules
 add_dracutmodules+=" rootfs-block "
 
-# CPU microcode
-early_microcode=yes
-
 # Critical drivers for LUKS support
 add_drivers+=" dm_mod dm_crypt "
 
@@ -109,20 +109,21 @@ compress="zstd"
 install_items+=" /etc/crypttab "
 DRACUT_EOF
 
+# CPU microcode: a vendor image in /boot is loaded as the first UKI initrd,
+# otherwise dracut embeds the microcode itself
+if [ -e /boot/intel-ucode.img ] || [ -e /boot/amd-ucode.img ]; then
+    early_microcode=no
+else
+    early_microcode=yes
+fi
+printf '\n# CPU microcode\nearly_microcode=%s\n' "$early_microcode" >> /etc/dracut.conf.d/mkos.conf
+
 echo "  Updated /etc/dracut.conf.d/mkos.conf"
 
 echo ""
-echo "[3/5] Installing UKI rebuild script and pacman hook..."
-
-# Install the rebuild script from the repo template
-REBUILD_TEMPLATE="$PROJECT_ROOT/installer/src/templates/hooks/mkos-rebuild-uki.sh"
-if [ ! -f "$REBUILD_TEMPLATE" ]; then
-    echo "ERROR: Cannot find rebuild template at $REBUILD_TEMPLATE"
-    exit 1
-fi
+echo "[3/5] Installing pacman hook..."
 
-install -m 755 "$REBUILD_TEMPLATE" /usr/local/bin/mkos-rebuild-uki
-echo "  Installed /usr/local/bin/mkos-rebuild-uki"
+# mkos-rebuild-uki itself was installed with the binaries in step 1
 
 # Install the pacman hook
 mkdir -p /etc/pacman.d/hooks